@@ -10,6 +10,7 @@ use std::env;
 use criterion::{black_box, Criterion, BenchmarkId, Throughput};
 use jumprope::JumpRope;
 use crdt_testdata::{load_testing_data, TestData};
+use diamond_types::DbgBenchCursors;
 use diamond_types::list::{ListCRDT, ListOpLog};
 use diamond_types::list::encoding::*;
 use crate::utils::*;
@@ -167,6 +168,18 @@ fn encoding_nodecc_benchmarks(c: &mut Criterion) {
     }
 }
 
+fn content_tree_benchmarks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("content_tree");
+    for num_items in [1000, 100_000] {
+        let mut bench = DbgBenchCursors::new(num_items);
+        group.throughput(Throughput::Elements(1000));
+        group.bench_function(BenchmarkId::new("cursor_at_pos", num_items), |b| {
+            b.iter(|| black_box(bench.run()));
+        });
+    }
+    group.finish();
+}
+
 // criterion_group!(benches,
 //     local_benchmarks,
 //     encoding_nodecc_benchmarks,
@@ -250,6 +263,7 @@ fn main() {
     //     });
     // });
 
+    content_tree_benchmarks(&mut c);
     local_benchmarks(&mut c);
     encoding_nodecc_benchmarks(&mut c);
     // idxtrace_benchmarks(&mut c);
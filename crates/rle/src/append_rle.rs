@@ -1,7 +1,8 @@
+use alloc::vec::Vec;
 #[cfg(feature = "smallvec")]
 use smallvec::SmallVec;
 
-use crate::MergableSpan;
+use crate::{MergableSpan, MergableSpanCtx};
 
 pub trait AppendRle<T: MergableSpan> {
     /// Push a new item to this list-like object. If the passed item can be merged into the
@@ -24,6 +25,29 @@ pub trait AppendRle<T: MergableSpan> {
     }
 }
 
+/// Like [`AppendRle`], for spans which need a context to merge. See [`MergableSpanCtx`].
+pub trait AppendRleCtx<T: MergableSpanCtx> {
+    /// Push a new item to this list-like object, merging it into the last item if possible.
+    ///
+    /// Returns true if the item was merged into the previous last item, false if it was inserted.
+    fn push_rle_ctx(&mut self, item: T, ctx: &T::Ctx) -> bool;
+}
+
+impl<T: MergableSpanCtx> AppendRleCtx<T> for Vec<T> {
+    fn push_rle_ctx(&mut self, item: T, ctx: &T::Ctx) -> bool {
+        let item = match self.last_mut() {
+            Some(v) => match v.try_append_ctx(item, ctx) {
+                Ok(()) => return true,
+                Err((item, _)) => item,
+            },
+            None => item,
+        };
+
+        self.push(item);
+        false
+    }
+}
+
 // Apparently the cleanest way to do this DRY is using macros.
 impl<T: MergableSpan> AppendRle<T> for Vec<T> {
     fn push_rle(&mut self, item: T) -> bool {
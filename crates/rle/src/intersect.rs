@@ -1,4 +1,4 @@
-use crate::{HasRleKey, HasLength, SplitableSpan};
+use crate::{HasRleKey, HasLength, key_offset, RleKey, SplitableSpan};
 use crate::zip::Remainder;
 
 #[derive(Debug, Clone)]
@@ -41,21 +41,21 @@ impl<A, B> Iterator for RleIntersect<A, B, true> where A: Iterator, B: Iterator,
             let a_key = a.rle_key();
             let b_key = b.rle_key();
 
-            if a_key >= b_key + b.len() {
+            if a_key >= b_key + b.len() as RleKey {
                 // This could be further optimized, but its not a big deal here.
                 b = self.b.next()?;
                 continue;
             }
-            if b_key >= a_key + a.len() {
+            if b_key >= a_key + a.len() as RleKey {
                 a = self.a.next()?;
                 continue;
             }
 
             // Ok, they have some intersection.
             if a_key > b_key {
-                b.truncate_keeping_right(a_key - b_key);
+                b.truncate_keeping_right(key_offset(a_key, b_key));
             } else if b_key > a_key {
-                a.truncate_keeping_right(b_key - a_key);
+                a.truncate_keeping_right(key_offset(b_key, a_key));
             }
 
             if b.len() > a.len() {
@@ -87,21 +87,21 @@ impl<A, B> Iterator for RleIntersect<A, B, false> where A: Iterator, B: Iterator
             let a_key = a.rle_key();
             let b_key = b.rle_key();
 
-            if a_key >= b_key + b.len() {
+            if a_key >= b_key + b.len() as RleKey {
                 a = self.a.next()?;
                 continue;
             }
-            if b_key >= a_key + a.len() {
+            if b_key >= a_key + a.len() as RleKey {
                 b = self.b.next()?;
                 continue;
             }
 
             // Ok, they have some intersection.
             if a_key > b_key {
-                let rem = b.truncate_keeping_right(a_key - b_key);
+                let rem = b.truncate_keeping_right(key_offset(a_key, b_key));
                 self.rem = Remainder::SomeB(rem);
             } else if b_key > a_key {
-                let rem = a.truncate_keeping_right(b_key - a_key);
+                let rem = a.truncate_keeping_right(key_offset(b_key, a_key));
                 self.rem = Remainder::SomeA(rem);
             }
 
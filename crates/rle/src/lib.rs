@@ -1,9 +1,18 @@
-use std::fmt::Debug;
+//! Utilities for run-length encoded data.
+//!
+//! This crate is `no_std` compatible (it only needs `alloc`). Disable the default `std` feature to
+//! use it without the standard library.
 
-pub use append_rle::AppendRle;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use core::fmt::Debug;
+
+pub use append_rle::{AppendRle, AppendRleCtx};
 pub use splitable_span::*;
 pub use merge_iter::*;
-use std::ops::Range;
+use core::ops::Range;
 
 mod splitable_span;
 mod merge_iter;
@@ -11,7 +20,9 @@ mod append_rle;
 pub mod zip;
 pub mod take_max_iter;
 pub mod intersect;
+pub mod setops;
 pub mod rlerun;
+pub mod rle_vec;
 // mod gapbuffer;
 // pub mod iter_ctx;
 
@@ -32,24 +43,39 @@ pub trait Searchable {
     fn at_offset(&self, offset: usize) -> Self::Item;
 }
 
+/// The type of keys in keyed runs (like the entries in an [`RleVec`](rle_vec::RleVec)). This is
+/// usize by default. The `u64_keys` feature makes keys u64 on every platform, for keys which can
+/// outgrow a 32 bit usize.
+#[cfg(not(feature = "u64_keys"))]
+pub type RleKey = usize;
+#[cfg(feature = "u64_keys")]
+pub type RleKey = u64;
+
+/// The distance from `base` to `key`. Lengths and offsets within runs are always usizes.
+#[inline]
+#[allow(clippy::unnecessary_cast)] // RleKey is usize without the u64_keys feature.
+pub(crate) fn key_offset(key: RleKey, base: RleKey) -> usize {
+    (key - base) as usize
+}
+
 pub trait HasRleKey {
-    fn rle_key(&self) -> usize;
+    fn rle_key(&self) -> RleKey;
 }
 
 impl<T> HasRleKey for &T where T: HasRleKey {
-    fn rle_key(&self) -> usize {
+    fn rle_key(&self) -> RleKey {
         (*self).rle_key()
     }
 }
 
 impl HasRleKey for Range<usize> {
-    fn rle_key(&self) -> usize {
-        self.start
+    fn rle_key(&self) -> RleKey {
+        self.start as RleKey
     }
 }
 
 impl HasRleKey for Range<u32> {
-    fn rle_key(&self) -> usize {
-        self.start as _
+    fn rle_key(&self) -> RleKey {
+        self.start as RleKey
     }
 }
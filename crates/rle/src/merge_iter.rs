@@ -1,4 +1,4 @@
-use crate::MergableSpan;
+use crate::{MergableSpan, MergableSpanCtx};
 
 /// This is an iterator composer which wraps any iterator over a SplitableSpan to become an
 /// iterator over those same items in run-length order.
@@ -80,11 +80,76 @@ where I: Iterator<Item=X>, X: MergableSpan, Self: Sized
     }
 }
 
+/// Like [`MergeIter`], for spans which need a context to merge. See [`MergableSpanCtx`].
+#[derive(Debug, Clone)]
+pub struct MergeIterCtx<'a, I: Iterator, C: ?Sized> {
+    next: Option<I::Item>,
+    iter: I,
+    ctx: &'a C,
+}
+
+impl<'a, I: Iterator, C: ?Sized> MergeIterCtx<'a, I, C> {
+    pub fn new(iter: I, ctx: &'a C) -> Self {
+        Self {
+            next: None,
+            iter,
+            ctx,
+        }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I, X, C: ?Sized> Iterator for MergeIterCtx<'_, I, C>
+where
+    I: Iterator<Item = X>,
+    X: MergableSpanCtx<Ctx = C>
+{
+    type Item = X;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut this_val = match self.next.take() {
+            Some(val) => val,
+            None => {
+                self.iter.next()?
+            }
+        };
+
+        for val in &mut self.iter {
+            if let Err((val, _)) = this_val.try_append_ctx(val, self.ctx) {
+                self.next = Some(val);
+                break;
+            }
+        }
+
+        Some(this_val)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        (lower.min(1), upper)
+    }
+}
+
+pub trait MergeableIteratorCtx<X: MergableSpanCtx>: Iterator<Item = X> where Self: Sized {
+    fn merge_spans_ctx(self, ctx: &X::Ctx) -> MergeIterCtx<'_, Self, X::Ctx>;
+}
+
+impl<X, I> MergeableIteratorCtx<X> for I
+where I: Iterator<Item=X>, X: MergableSpanCtx, Self: Sized
+{
+    fn merge_spans_ctx(self, ctx: &X::Ctx) -> MergeIterCtx<'_, Self, X::Ctx> {
+        MergeIterCtx::new(self, ctx)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::ops::Range;
     use super::merge_items;
-    use crate::{merge_items_rev, RleRun};
+    use crate::{merge_items_rev, CannotAppend, MergableSpanCtx, MergeableIteratorCtx, RleRun};
 
     #[test]
     fn test_merge_iter() {
@@ -116,4 +181,43 @@ mod test {
         let two_merged = vec![5u32..10, 2..5];
         assert_eq!(merge_items_rev(two_merged.iter().cloned()).collect::<Vec<_>>(), vec![2..10]);
     }
-}
\ No newline at end of file
+
+    /// Runs of text which can only be merged if they're adjacent in a shared buffer.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct TextRun(Range<usize>);
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum TextMergeError { NotAdjacent, Newline }
+
+    impl MergableSpanCtx for TextRun {
+        type Ctx = str;
+        type MergeError = TextMergeError;
+
+        fn check_append_ctx(&self, other: &Self, ctx: &str) -> Result<(), TextMergeError> {
+            if self.0.end != other.0.start { Err(TextMergeError::NotAdjacent) }
+            else if ctx[self.0.clone()].ends_with('\n') { Err(TextMergeError::Newline) }
+            else { Ok(()) }
+        }
+
+        fn append_ctx(&mut self, other: Self, _ctx: &str) {
+            self.0.end = other.0.end;
+        }
+    }
+
+    #[test]
+    fn merge_iter_ctx() {
+        let text = "ab\ncd";
+        let runs = vec![TextRun(0..1), TextRun(1..3), TextRun(3..4), TextRun(4..5)];
+        assert_eq!(runs.into_iter().merge_spans_ctx(text).collect::<Vec<_>>(), vec![TextRun(0..3), TextRun(3..5)]);
+
+        let mut run = TextRun(0..1);
+        assert_eq!(run.try_append_ctx(TextRun(2..3), text), Err((TextRun(2..3), TextMergeError::NotAdjacent)));
+        assert_eq!(run.try_append_ctx(TextRun(1..3), text), Ok(()));
+        assert_eq!(run.check_append_ctx(&TextRun(3..4), text), Err(TextMergeError::Newline));
+
+        // Plain mergable spans work too.
+        let mut r = 0u32..5;
+        assert_eq!(r.try_append_ctx(6..7, &()), Err((6..7, CannotAppend)));
+        assert_eq!(vec![0u32..1, 1..2].into_iter().merge_spans_ctx(&()).collect::<Vec<_>>(), vec![0..2]);
+    }
+}
@@ -0,0 +1,614 @@
+//! A run-length encoded list of keyed runs.
+//!
+//! [`RleVec`] stores a sorted list of runs. Each run has a key (via [`HasRleKey`]) and a length,
+//! so it covers the range of keys `key..key + len`. Runs can be looked up by any key they contain
+//! using binary search.
+//!
+//! Pushing a run which continues on from the last run (see [`MergableSpan`]) extends the last run
+//! instead of adding a new entry. The list may have gaps between runs (its *sparse*), or runs may
+//! cover every key from the first run to the last (its *packed*). Some methods (like
+//! [`find_packed`](RleVec::find_packed)) are only valid on packed lists.
+//!
+//! ```
+//! use rle::RleDRun;
+//! use rle::rle_vec::RleVec;
+//!
+//! let mut list = RleVec::new();
+//! list.push(RleDRun::new(0..10, 'a'));
+//! list.push(RleDRun::new(10..15, 'a')); // Merged with the previous run.
+//! list.push(RleDRun::new(20..25, 'b'));
+//! assert_eq!(list.num_entries(), 2);
+//!
+//! assert_eq!(list.find_with_offset(12), Some((&RleDRun::new(0..15, 'a'), 12)));
+//! assert_eq!(list.find(17), None);
+//!
+//! let items: Vec<_> = list.iter_range(5..22).collect();
+//! assert_eq!(items, [RleDRun::new(5..15, 'a'), RleDRun::new(20..22, 'b')]);
+//! ```
+
+use alloc::vec::Vec;
+use core::cmp::Ordering::*;
+use core::iter::{Cloned, FromIterator};
+use core::ops::{Index, Range};
+use core::slice::SliceIndex;
+
+use crate::{AppendRle, HasLength, HasRleKey, key_offset, RleKey, MergableSpan, MergeableIterator, MergeIter, Searchable, SplitableSpan, SplitableSpanCtx};
+
+/// A run-length encoded list of keyed runs. See the [module documentation](self).
+///
+/// The entries are publicly accessible, but the entries must stay sorted by key and must not
+/// overlap.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct RleVec<V: HasLength + MergableSpan + Sized>(pub Vec<V>);
+
+#[inline]
+fn end_of<V: HasRleKey + HasLength>(v: &V) -> RleKey {
+    v.rle_key() + v.len() as RleKey
+}
+
+impl<V: HasLength + MergableSpan + Sized> RleVec<V> {
+    pub fn new() -> Self { Self(Vec::new()) }
+
+    /// Append a new value to the end of the RLE list. This method is fast - O(1) average time.
+    /// The new item will extend the last entry in the list if possible.
+    ///
+    /// Returns true if the item was merged into the previous item. False if it was appended new.
+    pub fn push(&mut self, val: V) -> bool {
+        self.0.push_rle(val)
+    }
+
+    /// Returns true if pushing `item` would extend the last entry.
+    pub fn push_will_merge(&self, item: &V) -> bool {
+        if let Some(v) = self.last_entry() {
+            v.can_append(item)
+        } else { false }
+    }
+
+    /// Returns past the end of the last key.
+    pub fn end(&self) -> RleKey where V: HasRleKey {
+        self.last_entry().map(end_of).unwrap_or(0)
+    }
+
+    pub fn last_entry(&self) -> Option<&V> { self.0.last() }
+
+    /// The number of (run-length encoded) entries in the list.
+    pub fn num_entries(&self) -> usize { self.0.len() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    #[inline]
+    pub fn iter(&self) -> core::slice::Iter<'_, V> { self.0.iter() }
+
+    pub fn iter_from_idx(&self, idx: usize) -> core::slice::Iter<'_, V> { self.0[idx..].iter() }
+
+    /// Iterate through the entries, merging any adjacent entries which can be merged.
+    pub fn iter_merged(&self) -> MergeIter<Cloned<core::slice::Iter<'_, V>>> { self.0.iter().cloned().merge_spans() }
+}
+
+impl<V: HasLength + MergableSpan + HasRleKey + Clone + Sized> RleVec<V> {
+    /// Find the index of the entry containing `needle` via binary search. If no entry contains
+    /// the needle, returns the index where an entry containing it would be inserted.
+    pub fn find_index(&self, needle: RleKey) -> Result<usize, usize> {
+        self.0.binary_search_by(|entry| {
+            let key = entry.rle_key();
+            if needle < key { Greater }
+            else if needle >= key + entry.len() as RleKey { Less }
+            else { Equal }
+        })
+    }
+
+    /// Find the index of the requested item. If the item doesn't exist in the map, return the
+    /// index of the next item (or self.0.len()).
+    pub fn find_next_index(&self, needle: RleKey) -> usize {
+        self.find_index(needle).unwrap_or_else(|i| i)
+    }
+
+    /// Find the entry containing `needle` using binary search.
+    pub fn find(&self, needle: RleKey) -> Option<&V> {
+        self.find_index(needle).ok().map(|idx| {
+            &self.0[idx]
+        })
+    }
+
+    /// Same as [`find`](Self::find), for lists where there are no gaps in the RLE list.
+    ///
+    /// Panics if the needle isn't in the list.
+    pub fn find_packed(&self, needle: RleKey) -> &V {
+        self.find(needle).unwrap()
+    }
+
+    /// Find the item at range, cloning and trimming it down to size. This is generally less
+    /// efficient than using find_with_offset and friends, but its much more convenient.
+    ///
+    /// Note the returned value might be smaller than the passed range.
+    pub fn find_packed_and_split<R: Into<Range<RleKey>>>(&self, range: R) -> V where V: SplitableSpan {
+        self.find_packed_and_split_ctx(range, &())
+    }
+
+    pub fn find_packed_and_split_ctx<R: Into<Range<RleKey>>>(&self, range: R, ctx: &V::Ctx) -> V where V: SplitableSpanCtx {
+        let range = range.into();
+        let (item, offset) = self.find_packed_with_offset(range.start);
+        let mut item = item.clone();
+        item.truncate_keeping_right_ctx(offset, ctx);
+        let len = key_offset(range.end, range.start);
+        if item.len() > len {
+            item.truncate_ctx(len, ctx);
+        }
+        item
+    }
+
+    /// Find the entry containing `needle` using binary search.
+    ///
+    /// If found returns Some((found value, offset of the needle within the entry)).
+    pub fn find_with_offset(&self, needle: RleKey) -> Option<(&V, usize)> {
+        self.find_index(needle).ok().map(|idx| {
+            let entry = &self.0[idx];
+            (entry, key_offset(needle, entry.rle_key()))
+        })
+    }
+
+    /// Same as [`find_with_offset`](Self::find_with_offset), for lists where there are no gaps in
+    /// the RLE list.
+    ///
+    /// Panics if the needle isn't in the list.
+    pub fn find_packed_with_offset(&self, needle: RleKey) -> (&V, usize) {
+        self.find_with_offset(needle).unwrap()
+    }
+
+    /// This method is similar to find, except instead of returning None when the value doesn't
+    /// exist in the RLE list, we return the position in the empty span.
+    ///
+    /// This method assumes the "base" of the RLE is 0.
+    ///
+    /// Returns (Ok(elem), offset) if item is found, otherwise (Err(void range), offset into void)
+    pub fn find_sparse(&self, needle: RleKey) -> (Result<&V, Range<RleKey>>, usize) {
+        match self.find_index(needle) {
+            Ok(idx) => {
+                let entry = &self.0[idx];
+                (Ok(entry), key_offset(needle, entry.rle_key()))
+            }
+            Err(idx) => {
+                let next_key = if let Some(entry) = self.0.get(idx) {
+                    entry.rle_key()
+                } else {
+                    RleKey::MAX
+                };
+
+                if idx == 0 {
+                    (Err(0..next_key), key_offset(needle, 0))
+                } else {
+                    let end = end_of(&self.0[idx - 1]);
+                    (Err(end..next_key), key_offset(needle, end))
+                }
+            }
+        }
+    }
+
+    /// Find the entry containing `needle` using binary search.
+    ///
+    /// If found, item is returned by mutable reference as Some((&mut item, offset)). The entry's
+    /// key and length must not be modified.
+    pub fn find_mut(&mut self, needle: RleKey) -> Option<(&mut V, usize)> {
+        self.find_index(needle).ok().map(move |idx| {
+            let entry = &mut self.0[idx];
+            let offset = key_offset(needle, entry.rle_key());
+            (entry, offset)
+        })
+    }
+
+    pub fn contains_needle(&self, needle: RleKey) -> bool {
+        !self.is_empty() && self.find_index(needle).is_ok()
+    }
+
+    /// Insert an item at this location in the RLE list. The item is merged with its neighbours if
+    /// possible. This method is O(n) as it needs to shift subsequent elements forward.
+    ///
+    /// Panics if the item overlaps with an existing entry.
+    pub fn insert(&mut self, val: V) {
+        // The way insert is usually used, data *usually* gets appended to the end. We'll check that
+        // case first since its a useful optimization.
+        if self.last_entry()
+            .map(|last| end_of(last) <= val.rle_key())
+            .unwrap_or(true)
+        {
+            self.push(val);
+            return;
+        }
+
+        let idx = self.find_index(val.rle_key()).expect_err("Item already exists");
+
+        // Extend the next / previous item if possible
+        if idx >= 1 {
+            let prev = &mut self.0[idx - 1];
+            if prev.can_append(&val) {
+                prev.append(val);
+                return;
+            }
+        }
+
+        if idx < self.0.len() {
+            let next = &mut self.0[idx];
+            debug_assert!(end_of(&val) <= next.rle_key(), "Items overlap");
+
+            if val.can_append(next) {
+                next.prepend(val);
+                return
+            }
+        }
+
+        self.0.insert(idx, val);
+    }
+
+    /// Remove a range of keys, splitting entries where needed. This may need to shuffle indexes
+    /// around. This method is O(n) with the number of items between this entry and the end of the
+    /// list.
+    ///
+    /// This method silently ignores requests to delete ranges we don't have.
+    pub fn remove_ctx<R: Into<Range<RleKey>>>(&mut self, deleted_range: R, ctx: &V::Ctx) where V: SplitableSpanCtx {
+        let mut deleted_range = deleted_range.into();
+
+        // Fast case - the requested entry is at the end.
+        loop {
+            if let Some(last) = self.0.last_mut() {
+                let last_start = last.rle_key();
+                let last_end = end_of(last);
+
+                // Range is past the end of the list. Nothing to do here!
+                if deleted_range.start >= last_end { return; }
+
+                // Need slow approach.
+                if deleted_range.end < last_end { break; }
+
+                if deleted_range.start <= last_start {
+                    // Remove entire last entry.
+                    self.0.pop();
+                    if deleted_range.start == last_start {
+                        // Easiest case. We're done.
+                        return;
+                    }
+                } else {
+                    // Truncate last entry and return.
+                    last.truncate_ctx(key_offset(deleted_range.start, last_start), ctx);
+                    return;
+                }
+            } else {
+                // The list is empty. Nothing more to do.
+                return;
+            }
+        }
+
+        // Slow case - the requested range is in the middle of the list somewhere. We need to carve
+        // it out.
+        let mut idx = match self.find_index(deleted_range.start) {
+            Ok(idx) => idx,
+            Err(idx) => {
+                match self.0.get(idx) {
+                    Some(entry) if entry.rle_key() < deleted_range.end => {
+                        deleted_range.start = entry.rle_key();
+                    }
+                    _ => return,
+                }
+                idx
+            }
+        };
+
+        loop {
+            if idx >= self.0.len() { break; }
+            let e = &mut self.0[idx];
+
+            debug_assert!(e.rle_key() <= deleted_range.start);
+
+            // There's 4 cases here.
+            let e_start = e.rle_key();
+            let e_end = end_of(e);
+
+            let keep_start = e_start < deleted_range.start;
+            let keep_end = e_end > deleted_range.end;
+            match (keep_start, keep_end) {
+                (false, false) => {
+                    // Remove the entry and iterate.
+                    self.0.remove(idx);
+                },
+
+                (false, true) => {
+                    // Trim the start, trim the end.
+                    e.truncate_keeping_right_ctx(key_offset(deleted_range.start, e_start), ctx);
+                    break;
+                },
+
+                (true, false) => {
+                    // Trim the end
+                    e.truncate_ctx(key_offset(deleted_range.start, e_start), ctx);
+                    idx += 1;
+                }
+
+                (true, true) => {
+                    // Trim in the middle.
+                    let mut remainder = e.truncate_ctx(key_offset(deleted_range.start, e_start), ctx);
+                    let r_start = remainder.rle_key();
+                    remainder.truncate_keeping_right_ctx(key_offset(deleted_range.end, r_start), ctx);
+                    self.insert(remainder);
+                    break;
+                }
+            }
+
+            if e_end == deleted_range.end { break; }
+        }
+    }
+
+    /// Search forward from idx until we find needle. idx is modified. Returns either the item if
+    /// successful, or the key of the subsequent item.
+    pub fn search_scanning_sparse(&self, needle: RleKey, idx: &mut usize) -> Result<&V, RleKey> {
+        while *idx < self.0.len() {
+            let e = &self.0[*idx];
+            if needle < end_of(e) {
+                return if needle >= e.rle_key() {
+                    Ok(e)
+                } else {
+                    Err(e.rle_key())
+                };
+            }
+
+            *idx += 1;
+        }
+        Err(RleKey::MAX)
+    }
+
+    pub fn search_scanning_packed(&self, needle: RleKey, idx: &mut usize) -> &V {
+        self.search_scanning_sparse(needle, idx).unwrap()
+    }
+
+    /// Search backwards from idx until we find needle. idx is modified. Returns either the item or
+    /// the end of the preceeding range. Note the end could be == needle. (But cannot be greater
+    /// than it).
+    pub fn search_scanning_backwards_sparse(&self, needle: RleKey, idx: &mut usize) -> Result<&V, RleKey> {
+        // This conditional looks inverted given we're looping backwards, but I'm using
+        // wrapping_sub - so when we reach the end the index wraps around and we'll hit usize::MAX.
+        while *idx < self.0.len() {
+            let e = &self.0[*idx];
+            if needle >= e.rle_key() {
+                return if needle < end_of(e) {
+                    Ok(e)
+                } else {
+                    Err(end_of(e))
+                };
+            }
+            *idx = idx.wrapping_sub(1);
+        }
+        Err(0)
+    }
+
+    /// Visit each item or gap in this (sparse) RLE list, ending at end with the passed visitor
+    /// method.
+    pub fn for_each_sparse<F>(&self, end: RleKey, mut visitor: F)
+    where F: FnMut(Result<&V, Range<RleKey>>) {
+        let mut key = 0;
+
+        for e in self.iter() {
+            let next_key = e.rle_key();
+            if key < next_key {
+                // Visit the empty range
+                visitor(Err(key..next_key));
+            }
+
+            // Ok now visit the entry we found.
+            visitor(Ok(e));
+            key = end_of(e);
+            debug_assert!(key <= end);
+        }
+        // And visit the remainder, if there is any.
+        if key < end {
+            visitor(Err(key..end));
+        }
+    }
+
+    /// Check that the RLE is contiguous and packed. Panic if not.
+    pub fn check_packed(&self) {
+        let mut expect_next = 0;
+        for (i, entry) in self.0.iter().enumerate() {
+            if i != 0 {
+                assert_eq!(entry.rle_key(), expect_next);
+            }
+            expect_next = end_of(entry);
+        }
+    }
+
+    /// Check that the RLE is contiguous and packed, starting at key 0. Panic if not.
+    pub fn check_packed_from_0(&self) {
+        let mut expect_next = 0;
+        for entry in self.0.iter() {
+            assert_eq!(entry.rle_key(), expect_next);
+            expect_next = end_of(entry);
+        }
+    }
+
+    /// Assert there's no possibility for items to be further compacted
+    pub fn check_fully_merged(&self) {
+        for i in 1..self.0.len() {
+            assert!(!self.0[i-1].can_append(&self.0[i]));
+        }
+    }
+}
+
+impl<V: HasLength + MergableSpan + Sized> FromIterator<V> for RleVec<V> {
+    fn from_iter<T: IntoIterator<Item=V>>(iter: T) -> Self {
+        let mut rle = Self::new();
+        for item in iter {
+            rle.push(item);
+        }
+        rle
+    }
+}
+
+impl<V: HasLength + MergableSpan + Sized> Extend<V> for RleVec<V> {
+    fn extend<T: IntoIterator<Item=V>>(&mut self, iter: T) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+impl<V: HasLength + MergableSpan + Sized> Default for RleVec<V> {
+    fn default() -> Self {
+        Self(Vec::default())
+    }
+}
+
+impl<V: HasLength + MergableSpan + Searchable + HasRleKey + Clone> RleVec<V> {
+    /// Get the item at key `idx` in a packed list.
+    pub fn get(&self, idx: RleKey) -> V::Item {
+        let (v, offset) = self.find_packed_with_offset(idx);
+        v.at_offset(offset)
+    }
+}
+
+impl<T: HasLength + MergableSpan, I: SliceIndex<[T]>> Index<I> for RleVec<T> {
+    type Output = I::Output;
+
+    #[inline]
+    fn index(&self, index: I) -> &Self::Output {
+        self.0.index(index)
+    }
+}
+
+fn id_clone<V: Clone>(v: &V) -> V {
+    v.clone()
+}
+
+/// An iterator over the entries in part of an [`RleVec`]. Entries are trimmed to fit inside the
+/// range. See [`RleVec::iter_range`].
+//
+// We could just use .iter().map() - and thats pretty sensible in most cases. But this inline
+// approach lets us avoid a .clone(). (Is this a good idea? Not sure!)
+#[derive(Debug, Clone)]
+pub struct RleVecRangeIter<'a, V: HasRleKey + HasLength, I: SplitableSpanCtx, F: Fn(&V) -> I> {
+    inner_iter: core::slice::Iter<'a, V>,
+    range: Range<RleKey>,
+    ctx: &'a I::Ctx, // This could have a different lifetime specifier.
+    map_fn: F,
+}
+
+impl<V: HasLength + HasRleKey + SplitableSpanCtx + MergableSpan + Clone> RleVec<V> {
+    /// Iterate through the entries which overlap with `range`, trimmed to fit inside it.
+    pub fn iter_range<R: Into<Range<RleKey>>>(&self, range: R) -> RleVecRangeIter<'_, V, V, impl Fn(&V) -> V> where V: SplitableSpan {
+        self.iter_range_ctx(range, &())
+    }
+
+    pub fn iter_range_ctx<'a, R: Into<Range<RleKey>>>(&'a self, range: R, ctx: &'a V::Ctx) -> RleVecRangeIter<'a, V, V, impl Fn(&V) -> V> {
+        self.iter_range_map_ctx(range, ctx, id_clone)
+    }
+}
+
+impl<V: HasLength + HasRleKey + MergableSpan + Clone> RleVec<V> {
+    /// Like [`iter_range`](Self::iter_range), but each entry is mapped before its trimmed.
+    pub fn iter_range_map<R: Into<Range<RleKey>>, I: SplitableSpan + HasLength, F: Fn(&V) -> I>(&self, range: R, map_fn: F) -> RleVecRangeIter<'_, V, I, F> {
+        self.iter_range_map_ctx(range, &(), map_fn)
+    }
+
+    pub fn iter_range_map_ctx<'a, R: Into<Range<RleKey>>, I: SplitableSpanCtx, F: Fn(&V) -> I>(&'a self, range: R, ctx: &'a I::Ctx, map_fn: F) -> RleVecRangeIter<'a, V, I, F> {
+        let range = range.into();
+        let start_idx = self.find_next_index(range.start);
+
+        RleVecRangeIter {
+            inner_iter: self.0[start_idx..].iter(),
+            range,
+            ctx,
+            map_fn
+        }
+    }
+}
+
+impl<V: HasRleKey + HasLength, I: HasLength + SplitableSpanCtx, F: Fn(&V) -> I> Iterator for RleVecRangeIter<'_, V, I, F> {
+    type Item = I;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner_iter.next()?;
+
+        // Should always be true given how we construct the iterator.
+        debug_assert!(end_of(item) >= self.range.start);
+
+        let item_start = item.rle_key();
+        let item_end = end_of(item);
+        if item_start >= self.range.end { return None; }
+
+        let mut item = (self.map_fn)(item);
+        if item_end > self.range.end {
+            // Trim the item down.
+            item.truncate_ctx(key_offset(self.range.end, item_start), self.ctx);
+        }
+        if item_start < self.range.start {
+            item.truncate_keeping_right_ctx(key_offset(self.range.start, item_start), self.ctx);
+        }
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::RleDRun;
+    use super::*;
+
+    fn run(range: Range<RleKey>) -> RleDRun<()> {
+        RleDRun::new(range, ())
+    }
+
+    #[test]
+    fn iter_range() {
+        let mut rle = RleVec::new();
+        rle.push(run(0..10));
+
+        let items = rle.iter_range(5..8).collect::<Vec<_>>();
+        assert_eq!(items, [run(5..8)]);
+    }
+
+    #[test]
+    fn iter_empty() {
+        let rle: RleVec<RleDRun<()>> = RleVec::new();
+        assert!(rle.iter().next().is_none());
+        assert!(rle.iter_range_map(0..0, |x| *x).next().is_none());
+        assert!(rle.iter_range(0..0).next().is_none());
+    }
+
+    #[test]
+    fn iter_range_sparse() {
+        let rle: RleVec<_> = vec![run(0..10), run(12..18), run(20..30)].into_iter().collect();
+        let items = rle.iter_range(5..25).collect::<Vec<_>>();
+        assert_eq!(items, [run(5..10), run(12..18), run(20..25)]);
+    }
+
+    #[test]
+    fn find_sparse() {
+        let mut rle = RleVec::new();
+        assert_eq!(rle.find_sparse(10), (Err(0..RleKey::MAX), 10));
+
+        rle.insert(run(15..17));
+        assert_eq!(rle.find_sparse(10), (Err(0..15), 10));
+        assert_eq!(rle.find_sparse(16), (Ok(&run(15..17)), 1));
+        assert_eq!(rle.find_sparse(20), (Err(17..RleKey::MAX), 3));
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut rle = RleVec::new();
+        rle.insert(run(5..7));
+        // Prepend
+        rle.insert(run(3..5));
+        // Append
+        rle.insert(run(7..12));
+        assert_eq!(rle.0, [run(3..12)]);
+
+        // Items which cannot be merged
+        rle.insert(run(0..1));
+        rle.insert(run(100..101));
+        assert_eq!(rle.num_entries(), 3);
+        rle.check_fully_merged();
+
+        rle.remove_ctx(5..8, &());
+        assert_eq!(rle.0, [run(0..1), run(3..5), run(8..12), run(100..101)]);
+        rle.remove_ctx(4..200, &());
+        assert_eq!(rle.0, [run(0..1), run(3..4)]);
+        assert_eq!(rle.end(), 4);
+    }
+}
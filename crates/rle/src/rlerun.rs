@@ -1,5 +1,5 @@
-use std::ops::Range;
-use crate::{HasLength, MergableSpan, SplitableSpanHelpers};
+use core::ops::Range;
+use crate::{HasLength, HasRleKey, key_offset, MergableSpan, RleKey, SplitableSpanHelpers};
 
 /// A splitablespan which contains a single element repeated N times. This is used in some examples.
 #[derive(Copy, Clone, Hash, Debug, PartialEq, Eq, Default)]
@@ -44,13 +44,13 @@ impl<T: Clone + Eq> MergableSpan for RleRun<T> {
 /// Distinct RLE run. Each distinct run expresses some value between each (start, end) pair.
 #[derive(Copy, Clone, Hash, Debug, PartialEq, Eq, Default)]
 pub struct RleDRun<T> {
-    pub start: usize,
-    pub end: usize,
+    pub start: RleKey,
+    pub end: RleKey,
     pub val: T,
 }
 
 impl<T: Clone> RleDRun<T> {
-    pub fn new(range: Range<usize>, val: T) -> Self {
+    pub fn new(range: Range<RleKey>, val: T) -> Self {
         Self {
             start: range.start,
             end: range.end,
@@ -59,13 +59,17 @@ impl<T: Clone> RleDRun<T> {
     }
 }
 
+impl<T> HasRleKey for RleDRun<T> {
+    fn rle_key(&self) -> RleKey { self.start }
+}
+
 impl<T: Clone> HasLength for RleDRun<T> {
-    fn len(&self) -> usize { self.end - self.start }
+    fn len(&self) -> usize { key_offset(self.end, self.start) }
 }
 
 impl<T: Clone> SplitableSpanHelpers for RleDRun<T> {
     fn truncate_h(&mut self, at: usize) -> Self {
-        let split_point = self.start + at;
+        let split_point = self.start + at as RleKey;
         debug_assert!(split_point < self.end);
         let remainder = Self { start: split_point, end: self.end, val: self.val.clone() };
         self.end = split_point;
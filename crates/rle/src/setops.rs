@@ -0,0 +1,185 @@
+//! Set operations (union, difference, complement) over iterators of sorted, non-overlapping spans.
+//! These are the friends of [`rle_intersect`](crate::intersect::rle_intersect).
+//!
+//! All of these iterators are lazy and don't allocate. Like intersect, the input iterators must
+//! yield items in ascending order (by rle_key), and each input must not overlap with itself.
+
+use core::iter::{once, Once};
+use crate::{HasLength, HasRleKey, key_offset, RleKey, SplitableSpan};
+
+fn end_of<T: HasLength + HasRleKey>(item: &T) -> RleKey {
+    item.rle_key() + item.len() as RleKey
+}
+
+/// Iterator returned by [`rle_union`].
+#[derive(Debug, Clone)]
+pub struct RleUnion<A, B> where A: Iterator {
+    a: A,
+    b: B,
+    a_head: Option<A::Item>,
+    b_head: Option<A::Item>,
+    /// Everything below this key has already been yielded.
+    covered_to: RleKey,
+}
+
+impl<A, B> Iterator for RleUnion<A, B>
+    where A: Iterator, B: Iterator<Item = A::Item>,
+          A::Item: SplitableSpan + HasLength + HasRleKey
+{
+    type Item = A::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.a_head.is_none() { self.a_head = self.a.next(); }
+            if self.b_head.is_none() { self.b_head = self.b.next(); }
+
+            let use_a = match (&self.a_head, &self.b_head) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(a), Some(b)) => a.rle_key() <= b.rle_key(),
+            };
+
+            let mut item = if use_a { self.a_head.take() } else { self.b_head.take() }.unwrap();
+
+            // Trim off anything we've already yielded.
+            if end_of(&item) <= self.covered_to { continue; }
+            if item.rle_key() < self.covered_to {
+                item.truncate_keeping_right(key_offset(self.covered_to, item.rle_key()));
+            }
+
+            self.covered_to = end_of(&item);
+            return Some(item);
+        }
+    }
+}
+
+/// Iterate through the union of the spans in a and b. Where a and b overlap, the item from
+/// whichever iterator starts earlier is used.
+///
+/// The resulting items are sorted and don't overlap, but adjacent items aren't merged. Use
+/// [`merge_spans`](crate::MergeableIterator::merge_spans) for that.
+pub fn rle_union<A, B>(a: A, b: B) -> RleUnion<A, B>
+    where A: Iterator, B: Iterator<Item = A::Item>,
+          A::Item: SplitableSpan + HasLength + HasRleKey
+{
+    RleUnion { a, b, a_head: None, b_head: None, covered_to: 0 }
+}
+
+/// Iterator returned by [`rle_difference`].
+#[derive(Debug, Clone)]
+pub struct RleDifference<A, B> where A: Iterator, B: Iterator {
+    a: A,
+    b: B,
+    a_head: Option<A::Item>,
+    b_head: Option<B::Item>,
+}
+
+impl<A, B> Iterator for RleDifference<A, B>
+    where A: Iterator, B: Iterator,
+          A::Item: SplitableSpan + HasLength + HasRleKey,
+          B::Item: HasLength + HasRleKey
+{
+    type Item = A::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut a = self.a_head.take().or_else(|| self.a.next())?;
+            let a_key = a.rle_key();
+
+            // Skip any b items which end before a starts.
+            let b = loop {
+                if self.b_head.is_none() { self.b_head = self.b.next(); }
+                match &self.b_head {
+                    None => return Some(a),
+                    Some(b) if end_of(b) <= a_key => { self.b_head = None; }
+                    Some(b) => break b,
+                }
+            };
+
+            let b_key = b.rle_key();
+            let b_end = end_of(b);
+
+            if b_key >= end_of(&a) {
+                // No overlap.
+                return Some(a);
+            } else if b_key > a_key {
+                // Yield the part of a before b.
+                let rest = a.truncate(key_offset(b_key, a_key));
+                self.a_head = Some(rest);
+                return Some(a);
+            } else if b_end < end_of(&a) {
+                // b covers the start of a.
+                a.truncate_keeping_right(key_offset(b_end, a_key));
+                self.a_head = Some(a);
+            } // Else b covers all of a. Discard it.
+        }
+    }
+}
+
+/// Iterate through all the spans in a which are not in b.
+pub fn rle_difference<A, B>(a: A, b: B) -> RleDifference<A, B>
+    where A: Iterator, B: Iterator,
+          A::Item: SplitableSpan + HasLength + HasRleKey,
+          B::Item: HasLength + HasRleKey
+{
+    RleDifference { a, b, a_head: None, b_head: None }
+}
+
+/// Iterate through the parts of `within` which are not named by any item in `iter`.
+pub fn rle_complement<I, T>(iter: I, within: T) -> RleDifference<Once<T>, I>
+    where I: Iterator,
+          T: SplitableSpan + HasLength + HasRleKey,
+          I::Item: HasLength + HasRleKey
+{
+    rle_difference(once(within), iter)
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod test {
+    use std::ops::Range;
+    use crate::MergeableIterator;
+    use super::*;
+
+    fn union(a: &[Range<u32>], b: &[Range<u32>]) -> Vec<Range<u32>> {
+        rle_union(a.iter().cloned(), b.iter().cloned()).merge_spans().collect()
+    }
+
+    fn difference(a: &[Range<u32>], b: &[Range<u32>]) -> Vec<Range<u32>> {
+        rle_difference(a.iter().cloned(), b.iter().cloned()).collect()
+    }
+
+    #[test]
+    fn union_smoke() {
+        assert_eq!(union(&[0..5, 10..20], &[3..15]), vec![0..20]);
+        assert_eq!(union(&[0..5], &[10..20]), vec![0..5, 10..20]);
+        assert_eq!(union(&[0..5], &[5..10]), vec![0..10]);
+        assert_eq!(union(&[0..20], &[5..10]), vec![0..20]);
+        assert_eq!(union(&[5..10, 12..13], &[0..20]), vec![0..20]);
+        assert_eq!(union(&[], &[1..2]), vec![1..2]);
+        assert_eq!(union(&[], &[]), vec![]);
+
+        // Without merging, the output is still sorted and non-overlapping.
+        let raw: Vec<_> = rle_union([0..5, 10..20].into_iter(), [3..15].into_iter()).collect();
+        assert_eq!(raw, vec![0..5, 5..15, 15..20]);
+    }
+
+    #[test]
+    fn difference_smoke() {
+        assert_eq!(difference(&[0..5, 10..20], &[3..15]), vec![0..3, 15..20]);
+        assert_eq!(difference(&[0..20], &[5..10]), vec![0..5, 10..20]);
+        assert_eq!(difference(&[0..20], &[0..5, 6..7, 19..30]), vec![5..6, 7..19]);
+        assert_eq!(difference(&[5..10], &[0..20]), vec![]);
+        assert_eq!(difference(&[0..5], &[]), vec![0..5]);
+        assert_eq!(difference(&[], &[0..5]), vec![]);
+    }
+
+    #[test]
+    fn complement_smoke() {
+        let c: Vec<_> = rle_complement([2..4, 6..8].into_iter(), 0u32..10).collect();
+        assert_eq!(c, vec![0..2, 4..6, 8..10]);
+        let c: Vec<_> = rle_complement(std::iter::empty::<Range<u32>>(), 0u32..10).collect();
+        assert_eq!(c, vec![0..10]);
+    }
+}
@@ -1,4 +1,4 @@
-use std::ops::{Deref, DerefMut, Range};
+use core::ops::{Deref, DerefMut, Range};
 
 pub trait HasLength {
     /// The number of child items in the entry. This is indexed with the size used in truncate.
@@ -251,6 +251,78 @@ pub trait MergableSpan: Clone {
     }
 }
 
+/// The reason a [`MergableSpan`] couldn't be merged. (Plain mergable spans don't say why).
+#[derive(Copy, Clone, Hash, Debug, PartialEq, Eq)]
+pub struct CannotAppend;
+
+/// A span whose merging depends on some external context, like [`SplitableSpanCtx`]. For example,
+/// a span which refers to content stored elsewhere might need to look at that content to decide
+/// if it can be merged.
+///
+/// Merging can also report *why* two spans couldn't be merged, via
+/// [`check_append_ctx`](MergableSpanCtx::check_append_ctx).
+///
+/// Every [`MergableSpan`] is a `MergableSpanCtx` with a context of `()`. Don't implement both.
+pub trait MergableSpanCtx: Clone {
+    type Ctx: ?Sized;
+
+    /// Why two spans couldn't be merged.
+    type MergeError;
+
+    /// Check if the other item can be appended to self. If it can't, returns why.
+    fn check_append_ctx(&self, other: &Self, ctx: &Self::Ctx) -> Result<(), Self::MergeError>;
+
+    /// Merge the passed item into self. The other item *must* be a valid target for merging (as
+    /// per check_append_ctx(), above).
+    fn append_ctx(&mut self, other: Self, ctx: &Self::Ctx);
+
+    /// Append an item at the start of this item. self = other + self.
+    ///
+    /// `other.check_append_ctx(self)` must succeed for this method to be called.
+    #[inline(always)]
+    fn prepend_ctx(&mut self, mut other: Self, ctx: &Self::Ctx) {
+        other.append_ctx(self.clone(), ctx);
+        *self = other;
+    }
+
+    #[inline(always)]
+    fn can_append_ctx(&self, other: &Self, ctx: &Self::Ctx) -> bool {
+        self.check_append_ctx(other, ctx).is_ok()
+    }
+
+    /// Append other to self if possible. Otherwise other is handed back, along with why it
+    /// couldn't be merged.
+    fn try_append_ctx(&mut self, other: Self, ctx: &Self::Ctx) -> Result<(), (Self, Self::MergeError)> {
+        match self.check_append_ctx(&other, ctx) {
+            Ok(()) => {
+                self.append_ctx(other, ctx);
+                Ok(())
+            }
+            Err(e) => Err((other, e)),
+        }
+    }
+}
+
+impl<T: MergableSpan> MergableSpanCtx for T {
+    type Ctx = ();
+    type MergeError = CannotAppend;
+
+    #[inline(always)]
+    fn check_append_ctx(&self, other: &Self, _ctx: &()) -> Result<(), CannotAppend> {
+        if self.can_append(other) { Ok(()) } else { Err(CannotAppend) }
+    }
+
+    #[inline(always)]
+    fn append_ctx(&mut self, other: Self, _ctx: &()) {
+        self.append(other);
+    }
+
+    #[inline(always)]
+    fn prepend_ctx(&mut self, other: Self, _ctx: &()) {
+        self.prepend(other);
+    }
+}
+
 /// An entry is expected to contain multiple items.
 ///
 /// A SplitableSpan is a range entry. That is, an entry which contains a compact run of many
@@ -448,11 +520,11 @@ impl MergableSpan for Range<usize> {
 ///
 /// Use this to test splitablespan implementations in tests.
 // #[cfg(test)]
-pub fn test_splitable_methods_valid<E: SplitAndJoinSpan + std::fmt::Debug + Clone + Eq>(entry: E) {
+pub fn test_splitable_methods_valid<E: SplitAndJoinSpan + core::fmt::Debug + Clone + Eq>(entry: E) {
     test_splitable_methods_valid_ctx(entry, &());
 }
 
-pub fn test_splitable_methods_valid_ctx<E: SplitAndJoinSpanCtx + std::fmt::Debug + Clone + Eq>(entry: E, ctx: &E::Ctx) {
+pub fn test_splitable_methods_valid_ctx<E: SplitAndJoinSpanCtx + core::fmt::Debug + Clone + Eq>(entry: E, ctx: &E::Ctx) {
     assert!(entry.len() >= 2, "Call this with a larger entry");
     // dbg!(&entry);
 
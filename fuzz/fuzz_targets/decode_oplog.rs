@@ -0,0 +1,24 @@
+//! Decode arbitrary bytes as an oplog. The decoder is used on untrusted network input, so any byte
+//! sequence must either load or return a ParseError - never panic or allocate unbounded memory.
+#![no_main]
+
+use diamond_types::list::encoding::DecodeOptions;
+use diamond_types::list::ListOpLog;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Most inputs won't have a valid checksum, which would hide everything after it.
+    let opts = DecodeOptions {
+        ignore_crc: true,
+        ..DecodeOptions::default().with_limits(100_000, 1 << 20, 1000)
+    };
+
+    if let Ok(oplog) = ListOpLog::load_from_opts(data, opts.clone()) {
+        oplog.dbg_check(true);
+
+        // Merging the same data again takes the overlapping path through the decoder.
+        let mut merged = oplog.clone();
+        merged.decode_and_add_opts(data, opts).unwrap();
+        assert_eq!(merged, oplog);
+    }
+});
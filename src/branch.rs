@@ -117,8 +117,9 @@ impl OpLog {
                             // I could use recursion here but this avoids stack-smashing attacks.
                             maps_to_copy.push(*child_map);
                         }
-                        RegisterValue::OwnedCRDT(CRDTKind::Register, _) => { todo!() }
                         RegisterValue::OwnedCRDT(CRDTKind::Collection, _) => { todo!() }
+                        // Custom CRDTs, counters and registers are read directly from the oplog.
+                        RegisterValue::OwnedCRDT(CRDTKind::Custom | CRDTKind::Counter | CRDTKind::Register, _) => {}
                         RegisterValue::OwnedCRDT(CRDTKind::Text, text_crdt) => {
                             // Eventually (rich) text items might contain more embedded CRDTs. But for
                             // now this is fine.
@@ -177,6 +178,7 @@ impl Branch {
             CRDTKind::Text => {
                 self.texts.remove(&crdt); // Easy peasy!
             }
+            CRDTKind::Custom | CRDTKind::Counter | CRDTKind::Register => {}
             _ => { todo!() }
         }
     }
@@ -301,6 +303,8 @@ impl Branch {
                         match kind {
                             CRDTKind::Map => &mut owned_map_crdts,
                             CRDTKind::Text => &mut owned_text_crdts,
+                            // Not stored in branches.
+                            CRDTKind::Custom | CRDTKind::Counter | CRDTKind::Register => { return; }
                             _ => { unimplemented!() }
                         }.insert(*key);
                     }
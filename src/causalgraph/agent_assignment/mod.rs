@@ -24,6 +24,51 @@ pub(crate) struct ClientData {
     /// of time spans must always obey the partial order of changes. But it will not necessarily
     /// agree with the order amongst time spans.
     pub(crate) lv_for_seq: RleVec<KVPair<DTRange>>,
+
+    /// Which sequence numbers local operations can use.
+    pub(crate) local_seqs: LocalSeqs,
+}
+
+/// Which sequence numbers a local agent can use. See
+/// [`SeqReservation`](crate::list::SeqReservation).
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub(crate) enum LocalSeqs {
+    /// Nothing has been reserved, so any sequence number can be used.
+    #[default]
+    Unlimited,
+    /// Only sequence numbers below this have been reserved.
+    ReservedUntil(LV),
+    /// The agent might have made operations we don't have. It can't make any more.
+    Collided,
+}
+
+/// How concurrent inserts at the same location in a list are ordered.
+///
+/// Every replica of a document must use the same policy, or they will not converge! Custom
+/// policies are stored in encoded files by name, and files can only be loaded into an oplog using
+/// the same policy.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TieBreak {
+    /// Order by agent name. This is the default.
+    #[default]
+    AgentName,
+
+    /// Compare agent names using a custom function. Agents the function considers equal are
+    /// ordered by name.
+    ///
+    /// The function can't be stored with the document, so the policy is identified by `name`.
+    /// Every replica must use the same function for each name.
+    Custom { name: &'static str, cmp: fn(&str, &str) -> Ordering },
+}
+
+impl TieBreak {
+    /// The name of a custom policy, or None for the default policy.
+    pub(crate) fn custom_name(&self) -> Option<&'static str> {
+        match self {
+            TieBreak::AgentName => None,
+            TieBreak::Custom { name, .. } => Some(name),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -43,12 +88,38 @@ pub struct AgentAssignment {
     /// This is used to map external CRDT locations -> Order numbers.
     pub(crate) client_data: Vec<ClientData>,
 
+    /// How concurrent inserts are ordered in lists.
+    pub(crate) insert_tie_break: TieBreak,
 }
 
 
 impl ClientData {
-    pub fn get_next_seq(&self) -> usize {
-        self.lv_for_seq.end()
+    pub fn get_next_seq(&self) -> LV {
+        self.lv_for_seq.end() as LV
+    }
+
+    /// If the agent can't make `len` more local operations without risking reusing a sequence
+    /// number, returns the first sequence number which might be reused.
+    pub(crate) fn first_unsafe_seq(&self, len: usize) -> Option<LV> {
+        match self.local_seqs {
+            LocalSeqs::Unlimited => None,
+            LocalSeqs::ReservedUntil(end) => (self.get_next_seq() + len as LV > end).then_some(end),
+            LocalSeqs::Collided => Some(self.get_next_seq()),
+        }
+    }
+
+    /// Panics if the agent can't make `len` more local operations without risking reusing a
+    /// sequence number.
+    pub(crate) fn check_local_seqs(&self, len: usize) {
+        match self.local_seqs {
+            LocalSeqs::Unlimited => {}
+            LocalSeqs::ReservedUntil(end) => {
+                assert!(self.get_next_seq() + len as LV <= end, "Agent {} has used all of its reserved sequence numbers", self.name);
+            }
+            LocalSeqs::Collided => {
+                panic!("Agent {} may reuse sequence numbers. Use a new agent instead", self.name);
+            }
+        }
     }
 
     pub fn is_empty(&self) -> bool {
@@ -56,12 +127,12 @@ impl ClientData {
     }
 
     #[inline]
-    pub(crate) fn try_seq_to_lv(&self, seq: usize) -> Option<LV> {
+    pub(crate) fn try_seq_to_lv(&self, seq: LV) -> Option<LV> {
         let (entry, offset) = self.lv_for_seq.find_with_offset(seq)?;
-        Some(entry.1.start + offset)
+        Some(entry.1.start + offset as LV)
     }
 
-    pub(crate) fn seq_to_lv(&self, seq: usize) -> LV {
+    pub(crate) fn seq_to_lv(&self, seq: LV) -> LV {
         self.try_seq_to_lv(seq).unwrap()
     }
 
@@ -69,8 +140,8 @@ impl ClientData {
     pub fn try_seq_to_lv_span(&self, seq_range: DTRange) -> Option<DTRange> {
         let (KVPair(_, entry), offset) = self.lv_for_seq.find_with_offset(seq_range.start)?;
 
-        let start = entry.start + offset;
-        let end = usize::min(entry.end, start + seq_range.len());
+        let start = entry.start + offset as LV;
+        let end = LV::min(entry.end, start + seq_range.len() as LV);
         Some(DTRange { start, end })
     }
 
@@ -90,8 +161,13 @@ impl AgentAssignment {
             .map(|id| id as AgentId)
     }
 
+    /// Agent names must be shorter than [`MAX_AGENT_NAME_LENGTH`] bytes, and "ROOT" is reserved.
+    pub fn is_valid_agent_name(name: &str) -> bool {
+        name != "ROOT" && name.len() < MAX_AGENT_NAME_LENGTH
+    }
+
     pub fn get_or_create_agent_id(&mut self, name: &str) -> AgentId {
-        // TODO: -> Result or something so this can be handled.
+        // Decoders check names with is_valid_agent_name first, so this only panics on local misuse.
         if name == "ROOT" { panic!("Agent ID 'ROOT' is reserved"); }
 
         assert!(name.len() < MAX_AGENT_NAME_LENGTH, "Agent name cannot exceed {MAX_AGENT_NAME_LENGTH} UTF8 bytes");
@@ -102,7 +178,8 @@ impl AgentAssignment {
             // Create a new id.
             self.client_data.push(ClientData {
                 name: SmartString::from(name),
-                lv_for_seq: RleVec::new()
+                lv_for_seq: RleVec::new(),
+                local_seqs: LocalSeqs::Unlimited,
             });
             (self.client_data.len() - 1) as AgentId
         }
@@ -119,13 +196,34 @@ impl AgentAssignment {
     /// So, seq_start..seq_start+len maps to lv_start..lv_start+len
     ///
     /// The items returned will always be in sequence order.
-    pub fn iter_lv_map_for_agent(&self, agent: AgentId) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+    pub fn iter_lv_map_for_agent(&self, agent: AgentId) -> impl Iterator<Item = (LV, LV, usize)> + '_ {
         self.client_data[agent as usize].lv_for_seq.iter()
             .map(|KVPair(seq, lv_range)| { (*seq, lv_range.start, lv_range.len()) })
     }
 
-    pub fn len(&self) -> usize {
-        self.client_with_lv.end()
+    /// Remove every agent which has no operations, and renumber the rest. Agents keep their
+    /// relative order. Returns a map from each old agent ID to its new ID (or None if the agent was
+    /// removed).
+    pub(crate) fn remove_unused_agents(&mut self) -> Vec<Option<AgentId>> {
+        let mut next_id = 0;
+        let map: Vec<Option<AgentId>> = self.client_data.iter().map(|c| {
+            if c.lv_for_seq.is_empty() { None } else {
+                next_id += 1;
+                Some(next_id - 1)
+            }
+        }).collect();
+
+        if next_id as usize != self.client_data.len() {
+            self.client_data.retain(|c| !c.lv_for_seq.is_empty());
+            for KVPair(_, span) in self.client_with_lv.0.iter_mut() {
+                span.agent = map[span.agent as usize].unwrap();
+            }
+        }
+        map
+    }
+
+    pub fn len(&self) -> LV {
+        self.client_with_lv.end() as LV
     }
 
     pub fn is_empty(&self) -> bool {
@@ -133,16 +231,16 @@ impl AgentAssignment {
     }
 
     pub fn local_to_agent_version(&self, version: LV) -> AgentVersion {
-        debug_assert_ne!(version, usize::MAX);
+        debug_assert_ne!(version, LV::MAX);
         self.client_with_lv.get(version)
     }
 
     pub(crate) fn local_span_to_agent_span(&self, version: DTRange) -> AgentSpan {
-        debug_assert_ne!(version.start, usize::MAX);
+        debug_assert_ne!(version.start, LV::MAX);
 
         let (loc, offset) = self.client_with_lv.find_packed_with_offset(version.start);
-        let start = loc.1.seq_range.start + offset;
-        let end = usize::min(loc.1.seq_range.end, start + version.len());
+        let start = loc.1.seq_range.start + offset as LV;
+        let end = LV::min(loc.1.seq_range.end, start + version.len() as LV);
         AgentSpan {
             agent: loc.1.agent,
             seq_range: DTRange { start, end }
@@ -162,16 +260,28 @@ impl AgentAssignment {
         debug_assert_eq!(span.start, self.len());
 
         let client_data = &mut self.client_data[agent as usize];
+        client_data.check_local_seqs(span.len());
 
         let next_seq = client_data.get_next_seq();
         client_data.lv_for_seq.push(KVPair(next_seq, span));
 
         self.client_with_lv.push(KVPair(span.start, AgentSpan {
             agent,
-            seq_range: DTRange { start: next_seq, end: next_seq + span.len() },
+            seq_range: DTRange { start: next_seq, end: next_seq + span.len() as LV },
         }));
     }
 
+    /// Compare two agents to order concurrent inserts at the same location, based on the
+    /// configured [`TieBreak`] policy. Inserts from the same agent are ordered by the caller.
+    pub(crate) fn insert_tie_break_agents(&self, a: AgentId, b: AgentId) -> Ordering {
+        let name_a = self.get_agent_name(a);
+        let name_b = self.get_agent_name(b);
+        match self.insert_tie_break {
+            TieBreak::AgentName => name_a.cmp(name_b),
+            TieBreak::Custom { cmp, .. } => cmp(name_a, name_b).then_with(|| name_a.cmp(name_b)),
+        }
+    }
+
     /// This is used to break ties.
     pub fn tie_break_agent_versions(&self, v1: AgentVersion, v2: AgentVersion) -> Ordering {
         if v1 == v2 { Ordering::Equal }
@@ -1,6 +1,9 @@
 /// This file contains utilities to convert remote IDs to local version and back.
 
 
+use std::error::Error;
+use std::fmt::{Display, Formatter, Write as _};
+use std::str::FromStr;
 use smartstring::alias::String as SmartString;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -14,11 +17,11 @@ use crate::causalgraph::agent_span::{AgentVersion, AgentSpan};
 /// Remote IDs are IDs you can pass to a remote peer.
 #[derive(Clone, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-pub struct RemoteVersionOwned(pub SmartString, pub usize);
+pub struct RemoteVersionOwned(pub SmartString, pub LV);
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-pub struct RemoteVersion<'a>(pub &'a str, pub usize);
+pub struct RemoteVersion<'a>(pub &'a str, pub LV);
 
 impl<'a> From<&'a RemoteVersionOwned> for RemoteVersion<'a> {
     fn from(rv: &'a RemoteVersionOwned) -> Self {
@@ -53,13 +56,13 @@ impl<'a> RemoteVersion<'a> {
 //     }
 // }
 
-impl<S> From<(S, usize)> for RemoteVersionOwned where S: Into<SmartString> {
-    fn from(r: (S, usize)) -> Self {
+impl<S> From<(S, LV)> for RemoteVersionOwned where S: Into<SmartString> {
+    fn from(r: (S, LV)) -> Self {
         Self(r.0.into(), r.1)
     }
 }
-impl<'a, S> From<(S, usize)> for RemoteVersion<'a> where S: Into<&'a str> {
-    fn from(r: (S, usize)) -> Self {
+impl<'a, S> From<(S, LV)> for RemoteVersion<'a> where S: Into<&'a str> {
+    fn from(r: (S, LV)) -> Self {
         Self(r.0.into(), r.1)
     }
 }
@@ -107,6 +110,134 @@ pub enum VersionConversionError {
     SeqInFuture,
 }
 
+impl Display for VersionConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VersionConversionError::UnknownAgent => f.write_str("Version names an unknown agent"),
+            VersionConversionError::SeqInFuture => f.write_str("Version names an operation which hasn't been seen yet"),
+        }
+    }
+}
+
+impl Error for VersionConversionError {}
+
+// *** String form ***
+//
+// Versions are written as `agent:seq`, and frontiers as a list of versions joined with `+` (eg
+// "seph:42+mike:17"). The root version is the empty string. Agent names are percent encoded, so
+// the result is safe to put in URLs.
+
+/// An error parsing a version from its string form.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum VersionParseError {
+    /// The string isn't in the `agent:seq+agent:seq` format.
+    InvalidFormat,
+    /// The version is well formed, but doesn't name an operation known to the document.
+    Conversion(VersionConversionError),
+}
+
+impl Display for VersionParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VersionParseError::InvalidFormat => f.write_str("Invalid version string"),
+            VersionParseError::Conversion(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for VersionParseError {}
+
+impl From<VersionConversionError> for VersionParseError {
+    fn from(e: VersionConversionError) -> Self {
+        VersionParseError::Conversion(e)
+    }
+}
+
+fn write_agent_escaped(f: &mut Formatter<'_>, agent: &str) -> std::fmt::Result {
+    for b in agent.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            f.write_char(b as char)?;
+        } else {
+            write!(f, "%{:02X}", b)?;
+        }
+    }
+    Ok(())
+}
+
+fn unescape_agent(s: &str) -> Result<SmartString, VersionParseError> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next(), iter.next()];
+            let hex = std::str::from_utf8(&[hex[0].ok_or(VersionParseError::InvalidFormat)?, hex[1].ok_or(VersionParseError::InvalidFormat)?])
+                .ok().and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or(VersionParseError::InvalidFormat)?;
+            bytes.push(hex);
+        } else {
+            bytes.push(b);
+        }
+    }
+    let agent = String::from_utf8(bytes).map_err(|_| VersionParseError::InvalidFormat)?;
+    if agent.is_empty() { return Err(VersionParseError::InvalidFormat); }
+    Ok(agent.into())
+}
+
+impl<'a> Display for RemoteVersion<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write_agent_escaped(f, self.0)?;
+        write!(f, ":{}", self.1)
+    }
+}
+
+impl Display for RemoteVersionOwned {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        RemoteVersion::from(self).fmt(f)
+    }
+}
+
+impl RemoteVersionOwned {
+    /// Parse a version from its string form (eg `seph:42`).
+    pub fn parse(s: &str) -> Result<Self, VersionParseError> {
+        let (agent, seq) = s.rsplit_once(':').ok_or(VersionParseError::InvalidFormat)?;
+        // Only plain digits. (usize::from_str also accepts a leading '+'.)
+        if seq.is_empty() || !seq.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(VersionParseError::InvalidFormat);
+        }
+        let seq = seq.parse().map_err(|_| VersionParseError::InvalidFormat)?;
+        Ok(RemoteVersionOwned(unescape_agent(agent)?, seq))
+    }
+}
+
+impl FromStr for RemoteVersionOwned {
+    type Err = VersionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Write a frontier in its string form (eg `seph:42+mike:17`). Versions are sorted, so the same
+/// frontier always has the same string form.
+pub fn remote_frontier_to_string<'a, I: IntoIterator<Item=RemoteVersion<'a>>>(frontier: I) -> String {
+    let mut versions: Vec<RemoteVersion> = frontier.into_iter().collect();
+    versions.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    let mut result = String::new();
+    for (i, v) in versions.iter().enumerate() {
+        if i > 0 { result.push('+'); }
+        write!(result, "{v}").unwrap();
+    }
+    result
+}
+
+/// Parse a frontier from its string form. The empty string is the root version.
+pub fn parse_remote_frontier(s: &str) -> Result<RemoteFrontierOwned, VersionParseError> {
+    if s.is_empty() { return Ok(RemoteFrontierOwned::new()); }
+    s.split('+').map(RemoteVersionOwned::parse).collect()
+}
+
 impl AgentAssignment {
     pub fn try_remote_to_local_version(&self, rv: RemoteVersion) -> Result<LV, VersionConversionError> {
         let agent = self.get_agent_id(rv.0)
@@ -141,9 +272,9 @@ impl AgentAssignment {
         let agent = self.get_or_create_agent_id(name);
         (agent, seq)
     }
-    pub(crate) fn remote_to_agent_version_known(&self, RemoteVersion(name, seq): RemoteVersion) -> AgentVersion {
-        let agent = self.get_agent_id(name).unwrap();
-        (agent, seq)
+    pub(crate) fn remote_to_agent_version_known(&self, RemoteVersion(name, seq): RemoteVersion) -> Result<AgentVersion, VersionConversionError> {
+        let agent = self.get_agent_id(name).ok_or(VersionConversionError::UnknownAgent)?;
+        Ok((agent, seq))
     }
 
     pub fn local_to_remote_version(&self, v: LV) -> RemoteVersion {
@@ -213,8 +344,9 @@ impl AgentAssignment {
 
 #[cfg(test)]
 mod test {
-    use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersion, RemoteVersionOwned};
+    use crate::causalgraph::agent_assignment::remote_ids::{parse_remote_frontier, remote_frontier_to_string, RemoteVersion, RemoteVersionOwned, VersionConversionError, VersionParseError};
     use crate::CausalGraph;
+    use crate::list::ListOpLog;
 
     #[test]
     fn id_smoke_test() {
@@ -246,6 +378,51 @@ mod test {
         // ]);
     }
 
+    #[test]
+    fn version_strings() {
+        let v = RemoteVersion("seph", 42);
+        assert_eq!(v.to_string(), "seph:42");
+        assert_eq!(RemoteVersionOwned::parse("seph:42"), Ok(v.to_owned()));
+
+        // Agent names are escaped.
+        let odd = RemoteVersionOwned("a:b+c%d 😃".into(), 0);
+        assert_eq!(odd.to_string(), "a%3Ab%2Bc%25d%20%F0%9F%98%83:0");
+        assert_eq!(odd.to_string().parse(), Ok(odd.clone()));
+
+        assert_eq!(remote_frontier_to_string([RemoteVersion("seph", 1), RemoteVersion("mike", 17)]), "mike:17+seph:1");
+        assert_eq!(remote_frontier_to_string([]), "");
+        assert_eq!(parse_remote_frontier("mike:17+seph:1").unwrap().as_slice(),
+            &[RemoteVersionOwned("mike".into(), 17), RemoteVersionOwned("seph".into(), 1)]);
+        assert!(parse_remote_frontier("").unwrap().is_empty());
+
+        for bad in ["seph", "seph:", ":1", "seph:-1", "seph:+1", "seph:1+", "se%2ph:1", "se%ph:1"] {
+            assert_eq!(parse_remote_frontier(bad), Err(VersionParseError::InvalidFormat), "{bad}");
+        }
+    }
+
+    #[test]
+    fn oplog_version_strings() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert_at(seph, &[], 0, "abc");
+        oplog.add_insert_at(mike, &[], 0, "xy");
+        assert_eq!(oplog.remote_frontier_string(), "mike:1+seph:2");
+        assert_eq!(oplog.frontier_to_remote_string(&[]), "");
+
+        for v in [&[][..], &[1], &[2, 4]] {
+            let s = oplog.frontier_to_remote_string(v);
+            assert_eq!(oplog.frontier_from_remote_string(&s).unwrap().as_ref(), v);
+        }
+        // Redundant versions are removed.
+        assert_eq!(oplog.frontier_from_remote_string("seph:0+seph:2").unwrap().as_ref(), &[2]);
+
+        assert_eq!(oplog.frontier_from_remote_string("fred:0"),
+            Err(VersionParseError::Conversion(VersionConversionError::UnknownAgent)));
+        assert_eq!(oplog.frontier_from_remote_string("seph:3"),
+            Err(VersionParseError::Conversion(VersionConversionError::SeqInFuture)));
+    }
+
     #[test]
     fn remote_versions_can_be_empty() {
         let cg = CausalGraph::new();
@@ -3,11 +3,12 @@
 use std::ops::Range;
 // use content_tree::ContentLength;
 use rle::{HasLength, MergableSpan, Searchable, SplitableSpan, SplitableSpanHelpers};
-use crate::AgentId;
+use crate::{AgentId, LV};
 use crate::dtrange::DTRange;
 
 /// (agent_id, seq) pair. The agent ID is an integer which maps to a local string via causal graph.
-pub type AgentVersion = (AgentId, usize);
+/// Sequence numbers are stored with the same type as local versions.
+pub type AgentVersion = (AgentId, LV);
 
 /// An AgentSpan represents a sequential span of (agent, seq) versions.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -22,8 +23,8 @@ impl From<(AgentId, DTRange)> for AgentSpan {
     }
 }
 
-impl From<(AgentId, Range<usize>)> for AgentSpan {
-    fn from((agent, seq_range): (AgentId, Range<usize>)) -> Self {
+impl From<(AgentId, Range<LV>)> for AgentSpan {
+    fn from((agent, seq_range): (AgentId, Range<LV>)) -> Self {
         AgentSpan { agent, seq_range: seq_range.into() }
     }
 }
@@ -47,7 +48,7 @@ impl Searchable for AgentSpan {
 
     fn at_offset(&self, offset: usize) -> AgentVersion {
         assert!(offset < self.len());
-        (self.agent, self.seq_range.start + offset)
+        (self.agent, self.seq_range.start + offset as LV)
     }
 }
 
@@ -3,7 +3,7 @@ use smallvec::SmallVec;
 use rle::{HasLength, MergableSpan, SplitableSpan};
 use rle::zip::rle_zip;
 
-use crate::{AgentId, CausalGraph, LV};
+use crate::{AgentId, CausalGraph, lv_to_usize, LV};
 use crate::causalgraph::*;
 use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontier, RemoteFrontierOwned};
 use crate::causalgraph::agent_span::AgentSpan;
@@ -26,11 +26,11 @@ impl CausalGraph {
         self.agent_assignment.client_data.len() as AgentId
     }
 
-    pub(crate) fn len_assignment(&self) -> usize {
+    pub(crate) fn len_assignment(&self) -> LV {
         self.agent_assignment.len()
     }
 
-    pub(crate) fn len_history(&self) -> usize {
+    pub(crate) fn len_history(&self) -> LV {
         self.graph.len()
     }
 
@@ -39,7 +39,7 @@ impl CausalGraph {
     ///
     /// TODO: Consider calling this something other than len(). We're essentially returning the next
     /// local version.
-    pub fn len(&self) -> usize {
+    pub fn len(&self) -> LV {
         let len = self.len_assignment();
         debug_assert_eq!(len, self.len_history());
         len
@@ -68,7 +68,7 @@ impl CausalGraph {
         if cfg!(debug_assertions) { self.check_flat(); }
 
         let start = self.len();
-        let span = (start .. start + num).into();
+        let span = (start .. start + num as LV).into();
 
         self.agent_assignment.assign_lv_to_client_next_seq(agent, span);
         self.graph.push(parents, span);
@@ -86,7 +86,7 @@ impl CausalGraph {
         if cfg!(debug_assertions) { self.check_flat(); }
 
         let start = self.len();
-        let span = (start .. start + num).into();
+        let span = (start .. start + num as LV).into();
 
         self.agent_assignment.assign_lv_to_client_next_seq(agent, span);
         self.graph.push(self.version.as_ref(), span);
@@ -111,7 +111,7 @@ impl CausalGraph {
             panic!("Time range already assigned");
         }
 
-        let time_span = (time_start .. time_start + span.len()).into();
+        let time_span = (time_start .. time_start + span.len() as LV).into();
 
         // Almost always appending to the end but its possible for the same agent ID to be used on
         // two concurrent branches, then transmitted in a different order.
@@ -192,7 +192,7 @@ impl CausalGraph {
                 }
 
                 // We know it can't combine with the previous element.
-                let time_span = (time_start..time_start + span.len()).into();
+                let time_span = (time_start..time_start + span.len() as LV).into();
                 client_data.lv_for_seq.0.insert(idx, KVPair(span.seq_range.start, time_span));
                 self.agent_assignment.client_with_lv.push(KVPair(time_start, span));
                 self.graph.push(parents, time_span);
@@ -214,7 +214,7 @@ impl CausalGraph {
         let mut av = self.agent_assignment.local_span_to_agent_span(v);
 
         // The entry needs to be the size of min(av, entry).
-        let usable_entry_len = entry.span.end - v.start;
+        let usable_entry_len = lv_to_usize(entry.span.end - v.start);
         if usable_entry_len < av.len() {
             av.truncate(usable_entry_len);
         }
@@ -254,7 +254,6 @@ impl CausalGraph {
         self.agent_assignment.local_to_remote_frontier_owned(self.version.as_ref())
     }
 
-    #[allow(unused)]
     pub fn iter(&self) -> impl Iterator<Item=CGEntry> + '_ {
         self.iter_range((0..self.len()).into())
     }
@@ -87,4 +87,40 @@ fn fuzz_cg_forever() {
         }
         fuzz_cg_flat(seed, false);
     })
-}
\ No newline at end of file
+}
+
+// Merging corrupt serialized changes should return an error rather than panicking.
+fn fuzz_cg_corrupt(seed: u64) {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut cg = CausalGraph::new();
+    let agents = [cg.get_or_create_agent_id("a"), cg.get_or_create_agent_id("b")];
+    for _i in 0..rng.gen_range(1..10) {
+        let agent = agents[rng.gen_range(0..2)];
+        let v = rng.gen_range(0..=cg.len());
+        let parents = if v == 0 { Frontier::root() } else { Frontier::new_1(v - 1) };
+        cg.assign_local_op_with_parents(parents.as_ref(), agent, rng.gen_range(1..5));
+    }
+
+    let mut msg = cg.serialize_changes_since(&[]);
+    for _ in 0..rng.gen_range(1..4) {
+        if msg.is_empty() { break; }
+        let i = rng.gen_range(0..msg.len());
+        match rng.gen_range(0..3) {
+            0 => msg.truncate(i),
+            1 => msg[i] = rng.gen(),
+            _ => msg[i] = msg[i].wrapping_add(1),
+        }
+    }
+
+    let mut dest = CausalGraph::new();
+    if dest.merge_serialized_changes(&msg).is_ok() {
+        dest.dbg_check(true);
+    }
+}
+
+#[test]
+fn fuzz_cg_corrupt_once() {
+    for seed in 0..1000 {
+        fuzz_cg_corrupt(seed);
+    }
+}
@@ -30,7 +30,7 @@ impl HasLength for CGEntry {
 
 impl MergableSpan for CGEntry {
     fn can_append(&self, other: &Self) -> bool {
-        let end = self.start + self.len();
+        let end = self.start + self.len() as LV;
         (end == other.start)
             && other.parents_are_trivial()
             && self.span.can_append(&other.span)
@@ -49,7 +49,7 @@ impl CGEntry {
     }
 
     pub fn time_span(&self) -> DTRange {
-        (self.start..self.start + self.len()).into()
+        (self.start..self.start + self.len() as LV).into()
     }
 
     pub fn clear(&mut self) {
@@ -63,8 +63,8 @@ impl SplitableSpanHelpers for CGEntry {
         let other_span = self.span.truncate(at);
 
         Self {
-            start: self.start + at,
-            parents: Frontier::new_1(self.start + at - 1),
+            start: self.start + at as LV,
+            parents: Frontier::new_1(self.start + at as LV - 1),
             span: other_span
         }
     }
@@ -136,7 +136,7 @@ impl PartialEq<Self> for CausalGraph {
                 };
 
                 let mapped_txn = GraphEntrySimple {
-                    span: (mapped_start..mapped_start + len_here).into(),
+                    span: (mapped_start..mapped_start + len_here as LV).into(),
                     // .unwrap() should be safe here because we've already walked past this item's
                     // parents.
                     parents: Frontier::from_unsorted_iter(txn.parents.iter().map(|t| map_lv_to_other(*t).unwrap()))
@@ -148,7 +148,7 @@ impl PartialEq<Self> for CausalGraph {
                     return false;
                 }
 
-                crdt_id.seq_range.start += len_here;
+                crdt_id.seq_range.start += len_here as LV;
                 txn.truncate_keeping_right(len_here);
                 if crdt_id.is_empty() { break; }
             }
@@ -9,13 +9,14 @@
 ///
 /// and it allows callers to add extra fields on each returned item.
 
+use std::cell::Cell;
 use std::cmp::{Ordering, Reverse};
 use std::collections::BinaryHeap;
 use std::fmt::Debug;
 
 use smallvec::{SmallVec, smallvec};
 
-use rle::AppendRle;
+use rle::{AppendRle, HasLength};
 
 use crate::{CausalGraph, DTRange, Frontier, LV};
 use crate::causalgraph::graph::Graph;
@@ -122,6 +123,12 @@ impl Graph {
     ///   are in the difference between parameter frontiers `a` and `b`.
     /// - (soon) subgraph.
     pub(crate) fn make_conflict_graph_between<S: Default>(&self, a: &[LV], b: &[LV]) -> ConflictSubgraph<S> {
+        self.make_conflict_graph_between_max(a, b, None).unwrap()
+    }
+
+    /// Make a conflict graph (see above). If `max_len` is set, this gives up and returns None once
+    /// the spans in the graph add up to more than `max_len` operations.
+    pub(crate) fn make_conflict_graph_between_max<S: Default>(&self, a: &[LV], b: &[LV], max_len: Option<usize>) -> Option<ConflictSubgraph<S>> {
         // TODO: Short circuits.
         if a == b {
             // Nothing to do here.
@@ -129,12 +136,12 @@ impl Graph {
             // This is a weird output for the conflict graph. It might make a lot more sense to
             // insert a single dummy entry which a_root and b_root both point to. Then other code
             // wouldn't need to special case this.
-            return ConflictSubgraph {
+            return Some(ConflictSubgraph {
                 entries: vec![],
                 base_version: a.into(),
                 a_root: usize::MAX,
                 b_root: usize::MAX,
-            };
+            });
         }
 
         // let mut result: Vec<ActionGraphEntry> = vec![];
@@ -146,6 +153,7 @@ impl Graph {
         let mut children: SmallVec<Child, 2> = smallvec![];
         let mut a_root = usize::MAX;
         let mut b_root = usize::MAX;
+        let len = Cell::new(0);
 
         // fn push_result<S: Default>(span: DTRange, flag: DiffFlag, children: &mut SmallVec<Child, 2>, result: &mut Vec<ConflictGraphEntry<S>>) -> usize {
         let mut push_result = |span: DTRange, flag: DiffFlag, children: &mut SmallVec<Child, 2>| -> usize {
@@ -182,6 +190,7 @@ impl Graph {
                 flag,
             });
 
+            len.set(len.get() + span.len());
             children.clear();
             new_index
         };
@@ -203,6 +212,7 @@ impl Graph {
 
         // Loop until we've collapsed the graph down to a single element.
         let frontier: Frontier = 'outer: loop {
+            if max_len.is_some_and(|max| len.get() > max) { return None; }
             let entry = queue.pop().unwrap();
 
             // println!("pop {:?} / {:?}", &entry, &queue);
@@ -346,6 +356,7 @@ impl Graph {
                 child: Child::Idx(new_index),
             });
         };
+        if max_len.is_some_and(|max| len.get() > max) { return None; }
 
         // dbg!(&children);
         // assert!(!root_children.is_empty());
@@ -364,9 +375,9 @@ impl Graph {
         // //     r.parents.reverse();
         // }
 
-        ConflictSubgraph {
+        Some(ConflictSubgraph {
             entries, base_version: frontier, a_root, b_root,
-        }
+        })
     }
 }
 
@@ -7,10 +7,12 @@ mod check;
 mod subgraph;
 mod simple;
 
-#[cfg(test)]
-pub mod random_graphs;
+#[cfg(any(test, feature = "testing"))]
+pub(crate) mod random_graphs;
 pub(crate) mod conflict_subgraph;
 
+pub use tools::DiffResult;
+
 use rle::{HasLength, HasRleKey, MergableSpan, SplitableSpan, SplitableSpanHelpers};
 use crate::{Frontier, LV};
 
@@ -76,8 +78,8 @@ impl Graph {
     }
 
     #[allow(unused)]
-    pub fn get_next_time(&self) -> usize {
-        self.entries.end()
+    pub fn get_next_time(&self) -> LV {
+        self.entries.end() as LV
     }
 
     /// Insert a new history entry for the specified range of versions, and the named parents.
@@ -136,7 +138,7 @@ impl GraphEntryInternal {
     //     } else { None } // look at .parents field.
     // }
 
-    pub fn parent_at_version(&self, v: LV) -> Option<usize> {
+    pub fn parent_at_version(&self, v: LV) -> Option<LV> {
         if v > self.span.start {
             Some(v - 1)
         } else { None } // look at .parents field.
@@ -179,15 +181,15 @@ impl GraphEntryInternal {
 
     // pub fn local_children_at_time(&self, time: usize) ->
 
-    pub fn contains(&self, localtime: usize) -> bool {
+    pub fn contains(&self, localtime: LV) -> bool {
         self.span.contains(localtime)
     }
 
-    pub fn last_time(&self) -> usize {
+    pub fn last_time(&self) -> LV {
         self.span.last()
     }
 
-    pub fn shadow_contains(&self, time: usize) -> bool {
+    pub fn shadow_contains(&self, time: LV) -> bool {
         debug_assert!(time <= self.last_time());
         time >= self.shadow
     }
@@ -221,7 +223,7 @@ impl MergableSpan for GraphEntryInternal {
 }
 
 impl HasRleKey for GraphEntryInternal {
-    fn rle_key(&self) -> usize {
+    fn rle_key(&self) -> LV {
         self.span.start
     }
 }
@@ -259,7 +261,7 @@ impl HasLength for GraphEntrySimple {
 }
 
 impl HasRleKey for GraphEntrySimple {
-    fn rle_key(&self) -> usize { self.span.start }
+    fn rle_key(&self) -> LV { self.span.start }
 }
 
 impl SplitableSpanHelpers for GraphEntrySimple {
@@ -268,7 +270,7 @@ impl SplitableSpanHelpers for GraphEntrySimple {
 
         GraphEntrySimple {
             span: self.span.truncate(at),
-            parents: Frontier::new_1(self.span.start + at - 1)
+            parents: Frontier::new_1(self.span.start + at as LV - 1)
         }
     }
 }
@@ -377,8 +379,8 @@ impl Graph {
         self.entries.iter().map(|e| e.into())
     }
 
-    pub(crate) fn len(&self) -> usize {
-        self.entries.end()
+    pub(crate) fn len(&self) -> LV {
+        self.entries.end() as LV
     }
 
     pub(crate) fn is_empty(&self) -> bool {
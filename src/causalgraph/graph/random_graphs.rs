@@ -8,7 +8,7 @@ use crate::causalgraph::graph::Graph;
 use crate::{AgentId, CausalGraph, DTRange, Frontier};
 use crate::list_fuzzer_tools::choose_2;
 
-pub(crate) fn with_random_cgs<F: FnMut((usize, usize), &CausalGraph, &[Frontier])>(seed: u64, iterations: (usize, usize), mut f: F) {
+pub fn with_random_cgs<F: FnMut((usize, usize), &CausalGraph, &[Frontier])>(seed: u64, iterations: (usize, usize), mut f: F) {
     for outer in 0..iterations.0 {
         let seed_here = seed + outer as u64;
         let mut rng = SmallRng::seed_from_u64(seed_here);
@@ -54,6 +54,6 @@ pub(crate) fn with_random_cgs<F: FnMut((usize, usize), &CausalGraph, &[Frontier]
 fn generate_some_graphs() {
     with_random_cgs(123, (1, 10), |(_, i), cg, _frontiers| {
         // dbg!(&cg.graph);
-        cg.generate_dot_svg(Path::new(&format!("graphs/{i}.svg")));
+        cg.generate_dot_svg(Path::new(&format!("graphs/{i}.svg")), None);
     });
 }
\ No newline at end of file
@@ -43,7 +43,7 @@ impl ScopedParents {
 }
 
 // TODO: Remove this.
-const OLD_INVALID_ROOT_TIME: usize = usize::MAX;
+const OLD_INVALID_ROOT_TIME: LV = LV::MAX;
 
 impl Graph {
     pub(crate) fn version_in_scope(&self, version: &[LV], info: &ScopedParents) -> Option<Frontier> {
@@ -137,7 +137,7 @@ impl Graph {
             } else {
                 // TODO: Reuse binary search from above.
                 let r = info.owned_times.find_sparse(time).0;
-                r.unwrap_err().start
+                r.unwrap_err().start as LV
             };
             let base = min_safe_base.max(containing_txn.span.start);
 
@@ -62,9 +62,9 @@ mod test {
     use crate::LV;
 
     fn check_simple_graph(g: &[GraphEntrySimple]) {
-        let mut last = usize::MAX;
+        let mut last = LV::MAX;
         for e in g {
-            assert!(last == usize::MAX || e.span.start > last);
+            assert!(last == LV::MAX || e.span.start > last);
             last = e.span.last();
 
             assert!(!e.span.is_empty());
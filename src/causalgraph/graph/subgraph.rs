@@ -36,6 +36,11 @@ impl<I: Iterator<Item = DTRange>> Filter<I> {
 }
 
 impl Graph {
+    /// Create a new graph containing only the operations named in `filter`, with the causal
+    /// relationships between them preserved. `filter` must be sorted and non-overlapping.
+    ///
+    /// The resulting graph has the same local versions as this graph (so it has holes). Returns
+    /// the subgraph along with the projection of `parents` onto it.
     pub fn subgraph(&self, filter: &[DTRange], parents: &[LV]) -> (Graph, Frontier) {
         let filter_iter = filter.iter().copied().rev();
         self.subgraph_raw(filter_iter, parents)
@@ -233,7 +238,10 @@ impl Graph {
         }, filtered_frontier)
     }
 
-    pub(crate) fn project_onto_subgraph(&self, filter: &[DTRange], frontier: &[LV]) -> Frontier {
+    /// Project `frontier` onto the subgraph made up of the operations in `filter`. The result is
+    /// the version in the subgraph containing all the filtered operations which `frontier`
+    /// contains. `filter` must be sorted and non-overlapping.
+    pub fn project_onto_subgraph(&self, filter: &[DTRange], frontier: &[LV]) -> Frontier {
         let filter_iter = filter.iter().copied().rev();
         self.project_onto_subgraph_raw(filter_iter, frontier)
     }
@@ -242,14 +250,14 @@ impl Graph {
     pub(crate) fn project_onto_subgraph_raw<I: Iterator<Item=DTRange>>(&self, rev_filter_iter: I, frontier: &[LV]) -> Frontier {
         if frontier.is_empty() { return Frontier::root(); }
 
-        let mut queue: BinaryHeap<usize> = BinaryHeap::new();
+        let mut queue: BinaryHeap<LV> = BinaryHeap::new();
         let mut result = Frontier::default();
 
-        fn dec(v_enc: usize) -> (bool, LV) {
+        fn dec(v_enc: LV) -> (bool, LV) {
             (v_enc % 2 == 1, v_enc >> 1)
         }
-        fn enc(active: bool, v: LV) -> usize {
-            (v << 1) + (active as usize)
+        fn enc(active: bool, v: LV) -> LV {
+            (v << 1) + (active as LV)
         }
 
         for v in frontier {
@@ -317,7 +325,7 @@ mod test {
     use crate::causalgraph::graph::tools::test::fancy_graph;
     use crate::rle::RleVec;
 
-    fn check_subgraph(g: &Graph, filter: &[Range<usize>], frontier: &[LV], expect_parents: &[&[LV]], expect_frontier: &[LV]) {
+    fn check_subgraph(g: &Graph, filter: &[Range<LV>], frontier: &[LV], expect_parents: &[&[LV]], expect_frontier: &[LV]) {
         let filter: Vec<DTRange> = filter.iter().map(|r| r.clone().into()).collect();
         let (subgraph, ff) = g.subgraph(&filter, frontier);
         // dbg!(&subgraph);
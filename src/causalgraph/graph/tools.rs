@@ -10,7 +10,7 @@ use smallvec::{smallvec, SmallVec};
 
 use rle::{AppendRle, SplitableSpan};
 
-use crate::{Frontier, LV};
+use crate::{Frontier, lv_to_usize, LV};
 use crate::causalgraph::graph::Graph;
 use crate::causalgraph::graph::tools::DiffFlag::*;
 use crate::dtrange::DTRange;
@@ -107,8 +107,23 @@ impl Graph {
         }
     }
 
+    /// Compare two versions (sets of operations), figuring out if one contains the other.
+    ///
+    /// Unlike [`frontier_cmp`](Graph::frontier_cmp), the versions don't need to be frontiers. They
+    /// can name any set of operations, in any order.
+    ///
+    /// Returns `None` if the versions are concurrent.
+    pub fn partial_cmp_versions(&self, a: &[LV], b: &[LV]) -> Option<Ordering> {
+        let dominators = |v: &[LV]| {
+            let mut v = v.to_vec();
+            v.sort_unstable();
+            self.find_dominators(&v)
+        };
+        self.frontier_cmp(dominators(a).as_ref(), dominators(b).as_ref())
+    }
+
     /// Calculates whether the specified version contains (dominates) the specified time.
-    pub(crate) fn frontier_contains_version(&self, frontier: &[LV], target: LV) -> bool {
+    pub fn frontier_contains_version(&self, frontier: &[LV], target: LV) -> bool {
         if frontier.contains(&target) { return true; }
 
         debug_assert_sorted(frontier);
@@ -184,7 +199,8 @@ impl Graph {
     }
 }
 
-pub(crate) type DiffResult = (SmallVec<DTRange, 4>, SmallVec<DTRange, 4>);
+/// The result of a diff between two versions: (spans only in a, spans only in b).
+pub type DiffResult = (SmallVec<DTRange, 4>, SmallVec<DTRange, 4>);
 
 impl Graph {
     /// Returns (spans only in a, spans only in b). Spans are in natural (ascending) order.
@@ -360,7 +376,7 @@ impl Graph {
         // Sorted highest to lowest (so we get the highest item first).
         #[derive(Debug, PartialEq, Eq, Clone)]
         struct TimePoint {
-            // For merges this is the highest time. usize::MAX for ROOT time. Ord implementation
+            // For merges this is the highest time. LV::MAX for ROOT time. Ord implementation
             // below makes sure ROOT gets sorted last.
             last: LV,
             // TODO: Compare performance here with actually using a vec.
@@ -395,7 +411,7 @@ impl Graph {
 
                 Self {
                     // Bleh.
-                    last: *version.last().unwrap_or(&usize::MAX),
+                    last: *version.last().unwrap_or(&LV::MAX),
                     merged_with: if version.len() > 1 {
                         SmallVec::from_slice(&version[..version.len() - 1])
                     } else {
@@ -416,7 +432,7 @@ impl Graph {
             let t = time.last;
             // dbg!((&time, flag));
 
-            if t == usize::MAX { break Frontier::root(); }
+            if t == LV::MAX { break Frontier::root(); }
 
             // Discard duplicate entries.
 
@@ -458,7 +474,7 @@ impl Graph {
                 if let Some((peek_time, _peek_flag)) = queue.peek() {
                     // println!("peek {:?}", &peek_time);
                     // Might be simpler to use containing_txn.contains(peek_time.last).
-                    if peek_time.last != usize::MAX && peek_time.last >= containing_txn.span.start {
+                    if peek_time.last != LV::MAX && peek_time.last >= containing_txn.span.start {
                         // The next item is within this txn. Consume it.
                         // dbg!((&peek_time, peek_flag));
                         let (time, next_flag) = queue.pop().unwrap();
@@ -466,7 +482,7 @@ impl Graph {
                         // Only emit inner items when they aren't duplicates.
                         if time.last + 1 < range.end {
                             // +1 because we don't want to include the actual merge point in the returned set.
-                            let offset = time.last + 1 - containing_txn.span.start;
+                            let offset = lv_to_usize(time.last + 1 - containing_txn.span.start);
                             debug_assert!(offset > 0);
                             let rem = range.truncate(offset);
 
@@ -595,6 +611,8 @@ impl Graph {
         result_rev
     }
 
+    /// Find the minimal frontier which contains all of `versions`. (Ie, remove any versions which
+    /// are dominated by other versions in the set). The input must be sorted.
     pub fn find_dominators(&self, versions: &[LV]) -> Frontier {
         let mut result = self.find_dominators_wide_rev(versions);
         result.reverse();
@@ -637,7 +655,7 @@ impl Graph {
         Frontier(result_rev)
     }
 
-    fn find_dominators_full_internal<F, I>(&self, versions_iter: I, stop_at_shadow: usize, mut visit: F)
+    fn find_dominators_full_internal<F, I>(&self, versions_iter: I, stop_at_shadow: LV, mut visit: F)
         where F: FnMut(LV, bool), I: Iterator<Item=LV>
     {
         if let Some(max_size) = versions_iter.size_hint().1 {
@@ -652,19 +670,19 @@ impl Graph {
 
         // Using the LSB in the data to encode whether this version was an input to the function.
         // We hit all the "normal" versions before the inputs.
-        fn enc_input(v: LV) -> usize { v << 1 }
-        fn enc_normal(v: LV) -> usize { (v << 1) + 1 }
-        fn dec(v_enc: usize) -> (bool, LV) {
+        fn enc_input(v: LV) -> LV { v << 1 }
+        fn enc_normal(v: LV) -> LV { (v << 1) + 1 }
+        fn dec(v_enc: LV) -> (bool, LV) {
             (v_enc % 2 == 0, v_enc >> 1)
         }
 
-        let mut queue: BinaryHeap<usize> = versions_iter.map(|v| {
-            if v >= usize::MAX / 2 { panic!("Cannot handle version beyond usize::MAX/2"); }
+        let mut queue: BinaryHeap<LV> = versions_iter.map(|v| {
+            if v >= LV::MAX / 2 { panic!("Cannot handle version beyond LV::MAX/2"); }
             enc_input(v)
         }).collect();
         let mut inputs_remaining = queue.len();
 
-        let mut last_emitted = usize::MAX;
+        let mut last_emitted = LV::MAX;
 
         while let Some(v_enc) = queue.pop() {
             // dbg!(&queue, v_enc);
@@ -678,7 +696,7 @@ impl Graph {
 
             let e = self.entries.find_packed(v);
 
-            if stop_at_shadow != usize::MAX && e.shadow <= stop_at_shadow {
+            if stop_at_shadow != LV::MAX && e.shadow <= stop_at_shadow {
                 break;
             }
 
@@ -710,6 +728,31 @@ impl Graph {
         }
     }
 
+    /// Find the version containing exactly the operations which are in both `a` and `b`. Both
+    /// arguments must be frontiers.
+    pub fn version_intersection(&self, a: &[LV], b: &[LV]) -> Frontier {
+        let (only_a, only_b) = self.diff(a, b);
+        let in_spans = |spans: &[DTRange], v: LV| {
+            let idx = spans.partition_point(|r| r.end <= v);
+            idx < spans.len() && spans[idx].start <= v
+        };
+
+        // Every operation at the frontier of the intersection is either named in a or b, or is a
+        // parent of an operation which is only in one of them.
+        let mut candidates: Vec<LV> = vec![];
+        for (v, only) in [(a, &only_a), (b, &only_b)] {
+            candidates.extend(v.iter().copied().filter(|v| !in_spans(only, *v)));
+            for range in only.iter() {
+                for e in self.iter_range(*range) {
+                    candidates.extend(e.parents.iter().copied().filter(|p| !in_spans(only, *p)));
+                }
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        self.find_dominators(&candidates)
+    }
+
     /// Given some disparate set of versions, figure out which versions are dominators - ie, the
     /// set of versions which "contains" the entire set of versions in their transitive dependency
     /// graph.
@@ -718,7 +761,7 @@ impl Graph {
     pub fn find_dominators_full<F, I>(&self, versions_iter: I, visit: F)
         where F: FnMut(LV, bool), I: Iterator<Item=LV>
     {
-        self.find_dominators_full_internal(versions_iter, usize::MAX, visit);
+        self.find_dominators_full_internal(versions_iter, LV::MAX, visit);
     }
 
     // /// Find dominators on an unsorted set of versions
@@ -771,7 +814,7 @@ impl Graph {
             while let Some(&next_lv) = queue.0.last() {
                 if next_lv >= entry.span.start {
                     count += lv - next_lv;
-                    sum += concurrency_here * (lv - next_lv);
+                    sum += concurrency_here * lv_to_usize(lv - next_lv);
                     lv = next_lv;
 
                     queue.0.pop();
@@ -779,7 +822,7 @@ impl Graph {
             }
 
             count += lv - entry.span.start + 1;
-            sum += concurrency_here * (lv - entry.span.start + 1);
+            sum += concurrency_here * lv_to_usize(lv - entry.span.start + 1);
 
             // This is slow.
             queue = self.find_dominators_2(queue.as_ref(), entry.parents.as_ref());
@@ -822,6 +865,40 @@ impl Graph {
 
         num_entries
     }
+
+    /// Find which operations are part of the linear history. An operation v is part of the linear
+    /// history if it comes after every operation before it (in local order), and every later
+    /// operation comes after it. So the history can be cleanly split on either side of v.
+    ///
+    /// Returns (span, linear_end) for each graph entry, where the operations in
+    /// `span.start..linear_end` are part of the linear history.
+    pub(crate) fn linear_history(&self) -> Vec<(DTRange, LV)> {
+        let entries: Vec<_> = self.iter().collect();
+
+        // The first condition holds when the frontier is [v]. The second holds if every later
+        // operation has a parent >= v. For each entry, this is the smallest "largest parent + 1" of
+        // any later entry. (Root is 0).
+        let mut min_later_parent = vec![LV::MAX; entries.len()];
+        for i in (0..entries.len().saturating_sub(1)).rev() {
+            let max_parent = entries[i + 1].parents.iter().max().map_or(0, |p| *p + 1);
+            min_later_parent[i] = min_later_parent[i + 1].min(max_parent);
+        }
+
+        let mut frontier = Frontier::root();
+        entries.iter().zip(min_later_parent).map(|(e, min_later)| {
+            frontier.advance(self, e.span);
+
+            // Within an entry, each operation's only parent is the previous operation. So the
+            // frontier is [v] for every operation in the entry if its [last]. But later entries
+            // might only come after some of the entry's operations.
+            let linear_end = if frontier.len() == 1 {
+                min_later.clamp(e.span.start, e.span.end)
+            } else {
+                e.span.start
+            };
+            (e.span, linear_end)
+        }).collect()
+    }
 }
 
 #[cfg(test)]
@@ -900,7 +977,7 @@ pub mod test {
         }
     }
 
-    fn assert_conflicting(graph: &Graph, a: &[LV], b: &[LV], expect_spans: &[(Range<usize>, DiffFlag)], expect_common: &[LV]) {
+    fn assert_conflicting(graph: &Graph, a: &[LV], b: &[LV], expect_spans: &[(Range<LV>, DiffFlag)], expect_common: &[LV]) {
         let expect: Vec<(DTRange, DiffFlag)> = expect_spans
             .iter()
             .rev()
@@ -1119,6 +1196,36 @@ pub mod test {
         assert_conflicting(&graph, &[9], &[2, 7], &[(0..5, Shared), (6..8, Shared), (8..10, OnlyA)], &[]);
     }
 
+    #[test]
+    fn version_intersection_tests() {
+        let graph = fancy_graph();
+        assert_eq!(graph.version_intersection(&[], &[5]).as_ref(), &[] as &[LV]);
+        assert_eq!(graph.version_intersection(&[2], &[5]).as_ref(), &[] as &[LV]);
+        assert_eq!(graph.version_intersection(&[2], &[6]).as_ref(), &[1]);
+        assert_eq!(graph.version_intersection(&[10], &[8]).as_ref(), &[8]);
+        assert_eq!(graph.version_intersection(&[9], &[10]).as_ref(), &[9]);
+        assert_eq!(graph.version_intersection(&[9], &[7]).as_ref(), &[7]);
+        assert_eq!(graph.version_intersection(&[2, 5], &[8]).as_ref(), &[1, 4]);
+
+        // Check every pair of versions against the set of operations they contain.
+        let history = |v: &[LV]| -> Vec<LV> {
+            graph.diff(&[], v).1.iter().flat_map(|r| r.start..r.end).collect()
+        };
+        let mut versions: Vec<Frontier> = vec![Frontier::root()];
+        for a in 0..11 {
+            for b in a..11 {
+                versions.push(graph.find_dominators(&[a, b]));
+            }
+        }
+        for a in versions.iter() {
+            for b in versions.iter() {
+                let (ha, hb) = (history(a.as_ref()), history(b.as_ref()));
+                let expect: Vec<LV> = ha.into_iter().filter(|v| hb.contains(v)).collect();
+                assert_eq!(history(graph.version_intersection(a.as_ref(), b.as_ref()).as_ref()), expect);
+            }
+        }
+    }
+
     #[test]
     fn version_contains_version_tests() {
         // let mut doc = ListCRDT::new();
@@ -1198,6 +1305,18 @@ pub mod test {
         }
     }
 
+    #[test]
+    fn partial_cmp_versions_smoke() {
+        let graph = fancy_graph();
+
+        // Versions don't need to be frontiers, or sorted.
+        assert_eq!(graph.partial_cmp_versions(&[10, 0], &[9]), Some(Ordering::Greater));
+        assert_eq!(graph.partial_cmp_versions(&[4, 1], &[8, 0]), Some(Ordering::Less));
+        assert_eq!(graph.partial_cmp_versions(&[8, 4], &[6, 8]), Some(Ordering::Equal));
+        assert_eq!(graph.partial_cmp_versions(&[5], &[2]), None);
+        assert_eq!(graph.partial_cmp_versions(&[], &[2]), Some(Ordering::Less));
+    }
+
     #[test]
     fn dominator_smoke_test() {
         let parents = fancy_graph();
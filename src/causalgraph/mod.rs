@@ -1,3 +1,33 @@
+//! The causal graph stores the history of a document - that is, the set of operations and the
+//! partial order between them. This is separate from the operations themselves, so it can be
+//! reused to build other CRDTs.
+//!
+//! The main types are:
+//!
+//! - [`CausalGraph`]: The full causal graph, including the mapping between local versions and
+//!   (agent, seq) pairs.
+//! - [`Graph`](graph::Graph): Just the parents information (the time DAG). This has most of the
+//!   useful tools for working with versions, like [`diff`](graph::Graph::diff),
+//!   [`find_dominators`](graph::Graph::find_dominators),
+//!   [`partial_cmp_versions`](graph::Graph::partial_cmp_versions) and
+//!   [`subgraph`](graph::Graph::subgraph).
+//! - [`Frontier`]: A version, expressed as the set of most recent operations.
+//!
+//! ```
+//! use std::cmp::Ordering;
+//! use diamond_types::CausalGraph;
+//!
+//! let mut cg = CausalGraph::new();
+//! let seph = cg.get_or_create_agent_id("seph");
+//! let mike = cg.get_or_create_agent_id("mike");
+//! let a = cg.assign_local_op_with_parents(&[], seph, 2); // 0..2
+//! let b = cg.assign_local_op_with_parents(&[], mike, 3); // 2..5
+//!
+//! assert_eq!(cg.graph.partial_cmp_versions(&[a.last()], &[b.last()]), None);
+//! assert_eq!(cg.graph.partial_cmp_versions(&[0], &[a.last()]), Some(Ordering::Less));
+//! assert_eq!(cg.version.as_ref(), &[1, 4]);
+//! ```
+
 // #![warn(unused)]
 
 use crate::{DTRange, Frontier, KVPair, Graph};
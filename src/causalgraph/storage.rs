@@ -278,7 +278,7 @@ impl CGStorage {
 
         // Length
         let (len, len_size) = decode_leb_usize(&buf[pos..]).map_err(|e| {
-            assert_eq!(e, ParseError::InvalidVarInt);
+            assert!(matches!(e, ParseError::InvalidVarInt | ParseError::ValueTooLarge));
             CGError::InvalidBlit
         })?;
         pos += len_size;
@@ -1,12 +1,12 @@
 use smallvec::{SmallVec, smallvec};
 use smartstring::alias::String as SmartString;
-use crate::{CausalGraph, DTRange, Frontier, LV};
+use crate::{CausalGraph, DTRange, Frontier, lv_to_usize, LV};
 use rle::{HasLength, MergeableIterator, SplitableSpanHelpers};
 
 #[cfg(feature = "serde")]
 use serde::{Serialize, Deserialize};
 use crate::causalgraph::agent_assignment::AgentAssignment;
-use crate::rle::RleSpanHelpers;
+use crate::rle::{KVPair, RleSpanHelpers};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -26,7 +26,20 @@ pub struct VersionSummary(Vec<VSEntry>);
 ///
 /// IF the same user agent can submit changes on multiple branches, this property does not hold.
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
-pub struct VersionSummaryFlat(Vec<(SmartString, usize)>);
+pub struct VersionSummaryFlat(Vec<(SmartString, LV)>);
+
+impl VersionSummary {
+    /// The entries in the summary. Each agent is named at most once.
+    pub fn entries(&self) -> &[VSEntry] {
+        &self.0
+    }
+}
+
+impl From<Vec<VSEntry>> for VersionSummary {
+    fn from(entries: Vec<VSEntry>) -> Self {
+        Self(entries)
+    }
+}
 
 // Serialize as {name1: [[start, end], [start, end], ..], name2: ...}.
 #[cfg(feature = "serde")]
@@ -37,7 +50,7 @@ mod serde_encoding {
     use serde::de::{MapAccess, Visitor};
     use smallvec::SmallVec;
     use crate::causalgraph::summary::{VersionSummary, VersionSummaryFlat, VSEntry};
-    use crate::DTRange;
+    use crate::{DTRange, LV};
     use smartstring::alias::String as SmartString;
 
     impl Serialize for VersionSummary {
@@ -100,7 +113,7 @@ mod serde_encoding {
         fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: MapAccess<'de> {
             let mut vs = VersionSummaryFlat(Vec::with_capacity(map.size_hint().unwrap_or(0)));
 
-            while let Some((k, v)) = map.next_entry::<SmartString, usize>()? {
+            while let Some((k, v)) = map.next_entry::<SmartString, LV>()? {
                 vs.0.push((k, v))
             }
             Ok(vs)
@@ -159,7 +172,7 @@ impl AgentAssignment {
 
                     let mut seq_range = e.range();
                     if entry_end_seq > *known_next_seq {
-                        seq_range.truncate_h(*known_next_seq - entry_start);
+                        seq_range.truncate_h(lv_to_usize(*known_next_seq - entry_start));
                     }
 
                     visitor(name, seq_range, Some(e.1.start));
@@ -209,6 +222,33 @@ impl AgentAssignment {
 }
 
 impl CausalGraph {
+    /// Summarize the operations in the history of `version` (rather than every operation we know
+    /// about, like [`AgentAssignment::summarize_versions`]).
+    pub fn summarize_version(&self, version: &[LV]) -> VersionSummary {
+        // Spans which are not in the version, in ascending order.
+        let (_, after) = self.graph.diff(version, self.version.as_ref());
+
+        let mut seq_ranges: Vec<SmallVec<DTRange, 2>> = vec![smallvec![]; self.agent_assignment.client_data.len()];
+        let mut start = 0;
+        for range in after.iter().copied().chain(std::iter::once((self.len()..self.len()).into())) {
+            if start < range.start {
+                for KVPair(_, span) in self.agent_assignment.client_with_lv.iter_range(DTRange::new(start, range.start)) {
+                    seq_ranges[span.agent as usize].push(span.seq_range);
+                }
+            }
+            start = range.end;
+        }
+
+        VersionSummary(seq_ranges.into_iter().enumerate().filter_map(|(agent, mut ranges)| {
+            if ranges.is_empty() { return None; }
+            ranges.sort_unstable_by_key(|r| r.start);
+            Some(VSEntry {
+                name: self.agent_assignment.client_data[agent].name.clone(),
+                seq_ranges: ranges.into_iter().merge_spans().collect(),
+            })
+        }).collect())
+    }
+
     pub fn intersect_with_flat_summary(&self, summary: &VersionSummaryFlat, frontier: &[LV]) -> (Frontier, Option<VersionSummaryFlat>) {
         let mut remainder: Option<VersionSummaryFlat> = None;
         // We'll just accumulate all the versions we see and check for dominators.
@@ -217,7 +257,7 @@ impl CausalGraph {
 
         self.agent_assignment.intersect_with_flat_summary_full(summary, |name, seq, v| {
             if let Some(v) = v {
-                let v_last = v + seq.len() - 1;
+                let v_last = v + seq.len() as LV - 1;
                 versions.push(v_last);
             } else {
                 let remainder = remainder.get_or_insert_with(Default::default);
@@ -239,7 +279,7 @@ impl CausalGraph {
 
         self.agent_assignment.intersect_with_summary_full(summary, |name, seq_range, v| {
             if let Some(v) = v {
-                let v_last = v + seq_range.len() - 1;
+                let v_last = v + seq_range.len() as LV - 1;
                 versions.push(v_last);
             } else {
                 let remainder = remainder.get_or_insert_with(Default::default);
@@ -0,0 +1,72 @@
+//! A simple counter CRDT. The value is the sum of every increment which has ever been applied, so
+//! concurrent increments and decrements commute.
+//!
+//! Counters are created by setting a map key to `CreateValue::NewCRDT(CRDTKind::Counter)`.
+
+use crate::{AgentId, LV, LVKey, OpLog};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CounterInfo {
+    /// (version, increment) pairs. Sorted by version.
+    pub(crate) ops: Vec<(LV, i64)>,
+
+    /// Cached sum of all the increments.
+    pub(crate) value: i64,
+}
+
+impl OpLog {
+    /// Add `n` to the counter `crdt`. Use a negative number to decrement it.
+    ///
+    /// Panics if the counter doesn't exist.
+    pub fn local_counter_add(&mut self, agent: AgentId, crdt: LVKey, n: i64) -> LV {
+        assert!(self.counters.contains_key(&crdt), "Missing counter");
+        let v = self.cg.assign_local_op(agent, 1).start;
+        self.push_counter_op(crdt, v, n);
+        v
+    }
+
+    /// This function requires that the lv has already been added to the causal graph.
+    pub(crate) fn push_counter_op(&mut self, crdt: LVKey, v: LV, n: i64) {
+        let info = self.counters.get_mut(&crdt).expect("Missing counter");
+        if let Err(idx) = info.ops.binary_search_by_key(&v, |(v, _)| *v) {
+            info.ops.insert(idx, (v, n));
+            // Wrapping so peers always agree on the value, even if it overflows.
+            info.value = info.value.wrapping_add(n);
+            self.counter_index.insert(v, crdt);
+        }
+    }
+
+    /// Get the current value of a counter. Returns None if the counter doesn't exist.
+    pub fn checkout_counter(&self, crdt: LVKey) -> Option<i64> {
+        self.counters.get(&crdt).map(|info| info.value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CRDTKind, CreateValue, DTValue, OpLog, Primitive, ROOT_CRDT_ID};
+
+    #[test]
+    fn concurrent_increments() {
+        let mut a = OpLog::new();
+        let seph = a.cg.get_or_create_agent_id("seph");
+        let likes = a.local_map_set(seph, ROOT_CRDT_ID, "likes", CreateValue::NewCRDT(CRDTKind::Counter));
+        a.local_counter_add(seph, likes, 3);
+
+        let mut b = OpLog::new();
+        b.merge_ops(a.ops_since(&[])).unwrap();
+        let mike = b.cg.get_or_create_agent_id("mike");
+        let likes_b = b.crdt_at_path(&["likes"]).1;
+
+        a.local_counter_add(seph, likes, 1);
+        b.local_counter_add(mike, likes_b, -10);
+
+        b.merge_ops(a.ops_since(&[])).unwrap();
+        a.merge_ops(b.ops_since(&[])).unwrap();
+        assert_eq!(a.checkout_counter(likes), Some(-6));
+        assert_eq!(b.checkout_counter(likes_b), Some(-6));
+        assert_eq!(*a.checkout()["likes"], DTValue::Primitive(Primitive::I64(-6)));
+        a.dbg_check(true);
+        b.dbg_check(true);
+    }
+}
@@ -0,0 +1,217 @@
+//! Support for plugging custom CRDT types into an [`OpLog`].
+//!
+//! Custom CRDTs are created like any other CRDT by setting a map key to
+//! `CreateValue::NewCRDT(CRDTKind::Custom)`. Their operations share the oplog's causal graph, and
+//! they're sent and merged along with everything else by [`ops_since`](OpLog::ops_since) and
+//! [`merge_ops`](OpLog::merge_ops).
+//!
+//! The oplog doesn't understand the operations themselves. They're stored as opaque bytes, tagged
+//! with the name of the type which created them. The type is only needed to create operations and
+//! to compute the CRDT's current value. When encoded, custom operations are stored in their own
+//! chunk, with each operation tagged with its type's name.
+
+use smartstring::alias::String as SmartString;
+use crate::{AgentId, CausalGraph, LV, LVKey, OpLog};
+use crate::encoding::parseerror::ParseError;
+
+/// A custom CRDT type which can be stored inside an [`OpLog`].
+///
+/// Operations are applied in the order they were added to the local oplog, which is always a
+/// valid causal order. But concurrent operations can be applied in a different order on different
+/// peers, so concurrent operations must commute: the result must be the same regardless of the
+/// order in which they're applied. Operations can commute naturally (like a counter or a grow-only
+/// set), or use the causal graph to pick a winner deterministically. Operations which need to be
+/// transformed against each other (like OT text operations) aren't supported.
+pub trait CustomCRDT {
+    /// The type name. This tags every operation, so it should never change.
+    const KIND: &'static str;
+
+    type Op;
+    type State: Default;
+
+    fn encode_op(op: &Self::Op) -> Vec<u8>;
+
+    /// Returns None if the operation is invalid.
+    fn decode_op(bytes: &[u8]) -> Option<Self::Op>;
+
+    /// Apply the operation at local version `v` to `state`.
+    fn apply(state: &mut Self::State, cg: &CausalGraph, v: LV, op: Self::Op);
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CustomInfo {
+    /// The name of the CRDT's type. This is empty until the first operation is added.
+    pub(crate) kind: SmartString,
+
+    /// (version, encoded op) pairs. Sorted by version.
+    pub(crate) ops: Vec<(LV, Vec<u8>)>,
+}
+
+impl OpLog {
+    /// Apply a local operation to the custom CRDT `crdt`.
+    ///
+    /// Panics if the CRDT doesn't exist, or if it already has operations from another type.
+    pub fn local_custom_op<C: CustomCRDT>(&mut self, agent: AgentId, crdt: LVKey, op: &C::Op) -> LV {
+        self.check_custom_kind(crdt, C::KIND).expect("Invalid custom CRDT");
+        let v = self.cg.assign_local_op(agent, 1).start;
+        self.push_custom_op(crdt, v, C::KIND, C::encode_op(op)).unwrap();
+        v
+    }
+
+    /// Check that `crdt` is a custom CRDT which can hold operations of the named kind.
+    fn check_custom_kind(&self, crdt: LVKey, kind: &str) -> Result<(), ParseError> {
+        match self.customs.get(&crdt) {
+            Some(info) if info.kind.is_empty() || info.kind == kind => Ok(()),
+            _ => Err(ParseError::GenericInvalidData),
+        }
+    }
+
+    /// This function requires that the lv has already been added to the causal graph. Operations
+    /// can come from remote peers, so this returns an error (rather than panicking) if the CRDT
+    /// doesn't exist or holds operations of another kind.
+    pub(crate) fn push_custom_op(&mut self, crdt: LVKey, v: LV, kind: &str, op: Vec<u8>) -> Result<(), ParseError> {
+        self.check_custom_kind(crdt, kind)?;
+        let info = self.customs.get_mut(&crdt).unwrap();
+        if info.kind.is_empty() {
+            info.kind = kind.into();
+        }
+
+        if let Err(idx) = info.ops.binary_search_by_key(&v, |(v, _)| *v) {
+            info.ops.insert(idx, (v, op));
+            self.custom_index.insert(v, crdt);
+        }
+        Ok(())
+    }
+
+    /// Look up the kind and encoded operation of the custom operation at `v`.
+    pub(crate) fn custom_op_at(&self, crdt: LVKey, v: LV) -> Option<(&str, &[u8])> {
+        let info = self.customs.get(&crdt)?;
+        let idx = info.ops.binary_search_by_key(&v, |(v, _)| *v).ok()?;
+        Some((info.kind.as_str(), &info.ops[idx].1))
+    }
+
+    /// Compute the current value of the custom CRDT `crdt`. Returns None if the CRDT doesn't
+    /// exist, it contains operations from some other type, or if any operation couldn't be decoded.
+    pub fn checkout_custom<C: CustomCRDT>(&self, crdt: LVKey) -> Option<C::State> {
+        let info = self.customs.get(&crdt)?;
+        if !info.kind.is_empty() && info.kind != C::KIND { return None; }
+
+        let mut state = C::State::default();
+        for (v, bytes) in &info.ops {
+            C::apply(&mut state, &self.cg, *v, C::decode_op(bytes)?);
+        }
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use crate::{CausalGraph, CRDTKind, CreateValue, LV, OpLog, ROOT_CRDT_ID, SerializedOps};
+    use crate::encoding::parseerror::ParseError;
+    use super::CustomCRDT;
+
+    struct Counter;
+
+    impl CustomCRDT for Counter {
+        const KIND: &'static str = "test_counter";
+        type Op = i64;
+        type State = i64;
+
+        fn encode_op(op: &i64) -> Vec<u8> { op.to_le_bytes().to_vec() }
+        fn decode_op(bytes: &[u8]) -> Option<i64> { Some(i64::from_le_bytes(bytes.try_into().ok()?)) }
+        fn apply(state: &mut i64, _cg: &CausalGraph, _v: LV, op: i64) { *state += op; }
+    }
+
+    #[test]
+    fn custom_counter_syncs() {
+        let mut a = OpLog::new();
+        let seph = a.cg.get_or_create_agent_id("seph");
+        let counter = a.local_map_set(seph, ROOT_CRDT_ID, "likes", CreateValue::NewCRDT(CRDTKind::Custom));
+        a.local_custom_op::<Counter>(seph, counter, &5);
+
+        let mut b = OpLog::new();
+        b.merge_ops(a.ops_since(&[])).unwrap();
+        let mike = b.cg.get_or_create_agent_id("mike");
+        let counter_b = b.crdt_at_path(&["likes"]).1;
+
+        // Concurrent changes.
+        a.local_custom_op::<Counter>(seph, counter, &-2);
+        b.local_custom_op::<Counter>(mike, counter_b, &10);
+
+        b.merge_ops(a.ops_since(&[])).unwrap();
+        a.merge_ops(b.ops_since(&[])).unwrap();
+        assert_eq!(a.checkout_custom::<Counter>(counter), Some(13));
+        assert_eq!(b.checkout_custom::<Counter>(counter_b), Some(13));
+        a.dbg_check(true);
+        b.dbg_check(true);
+
+        // Other custom types can't read it.
+        struct Other;
+        impl CustomCRDT for Other {
+            const KIND: &'static str = "other";
+            type Op = ();
+            type State = ();
+            fn encode_op(_op: &()) -> Vec<u8> { vec![] }
+            fn decode_op(_bytes: &[u8]) -> Option<()> { Some(()) }
+            fn apply(_state: &mut (), _cg: &CausalGraph, _v: LV, _op: ()) {}
+        }
+        assert_eq!(a.checkout_custom::<Other>(counter), None);
+    }
+
+    /// A grow-only set of characters. Adding items commutes, so every peer converges.
+    struct CharSet;
+
+    impl CustomCRDT for CharSet {
+        const KIND: &'static str = "test_char_set";
+        type Op = char;
+        type State = BTreeSet<char>;
+
+        fn encode_op(op: &char) -> Vec<u8> { op.to_string().into_bytes() }
+        fn decode_op(bytes: &[u8]) -> Option<char> { std::str::from_utf8(bytes).ok()?.chars().next() }
+        fn apply(state: &mut BTreeSet<char>, _cg: &CausalGraph, _v: LV, op: char) { state.insert(op); }
+    }
+
+    #[test]
+    fn concurrent_custom_ops_converge() {
+        let mut a = OpLog::new();
+        let seph = a.cg.get_or_create_agent_id("seph");
+        let s = a.local_map_set(seph, ROOT_CRDT_ID, "s", CreateValue::NewCRDT(CRDTKind::Custom));
+        a.local_custom_op::<CharSet>(seph, s, &'x');
+
+        let mut b = OpLog::new();
+        b.merge_ops(SerializedOps::decode(&a.ops_since(&[]).encode().unwrap()).unwrap()).unwrap();
+        let mike = b.cg.get_or_create_agent_id("mike");
+        let s_b = b.crdt_at_path(&["s"]).1;
+
+        a.local_custom_op::<CharSet>(seph, s, &'b');
+        b.local_custom_op::<CharSet>(mike, s_b, &'a');
+        b.local_custom_op::<CharSet>(mike, s_b, &'c');
+
+        // a and b apply the concurrent operations in different orders.
+        let from_a = a.ops_since(&[]).encode().unwrap();
+        let from_b = b.ops_since(&[]).encode().unwrap();
+        b.merge_ops(SerializedOps::decode(&from_a).unwrap()).unwrap();
+        a.merge_ops(SerializedOps::decode(&from_b).unwrap()).unwrap();
+
+        let expect: BTreeSet<char> = "abcx".chars().collect();
+        assert_eq!(a.checkout_custom::<CharSet>(s), Some(expect.clone()));
+        assert_eq!(b.checkout_custom::<CharSet>(s_b), Some(expect));
+    }
+
+    #[test]
+    fn mismatched_remote_kind_is_an_error() {
+        let mut a = OpLog::new();
+        let seph = a.cg.get_or_create_agent_id("seph");
+        let counter = a.local_map_set(seph, ROOT_CRDT_ID, "likes", CreateValue::NewCRDT(CRDTKind::Custom));
+        a.local_custom_op::<Counter>(seph, counter, &1);
+
+        let mut b = OpLog::new();
+        b.merge_ops(a.ops_since(&[])).unwrap();
+
+        a.local_custom_op::<Counter>(seph, counter, &1);
+        let mut ops = a.ops_since(&[]);
+        for op in ops.custom_ops.iter_mut() { op.2 = "evil"; }
+        assert_eq!(b.merge_ops(ops).unwrap_err(), ParseError::GenericInvalidData);
+    }
+}
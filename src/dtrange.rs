@@ -4,7 +4,7 @@ use std::fmt::{Debug, DebugStruct, Formatter};
 use rle::{HasLength, HasRleKey, MergableSpan, Searchable, SplitableSpanHelpers};
 
 use std::ops::{Range, RangeBounds};
-use crate::LV;
+use crate::{lv_to_usize, LV};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
@@ -17,35 +17,35 @@ use crate::serde_helpers::DTRangeTuple;
 #[derive(Copy, Clone, Eq, PartialEq, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(from = "DTRangeTuple", into = "DTRangeTuple"))]
 pub struct DTRange {
-    pub start: usize,
-    pub end: usize
+    pub start: LV,
+    pub end: LV
 }
 
 impl DTRange {
     #[inline]
-    pub fn new(start: usize, end: usize) -> DTRange {
+    pub fn new(start: LV, end: LV) -> DTRange {
         DTRange { start, end }
     }
 
     #[inline]
-    pub fn new_from_len(start: usize, len: usize) -> DTRange {
-        DTRange { start, end: start + len }
+    pub fn new_from_len(start: LV, len: usize) -> DTRange {
+        DTRange { start, end: start + len as LV }
     }
 
     pub fn consume_start(&mut self, amt: usize) {
-        self.start += amt;
+        self.start += amt as LV;
     }
 
-    pub fn end(&self) -> usize {
+    pub fn end(&self) -> LV {
         self.end
     }
 
-    pub fn last(&self) -> usize {
+    pub fn last(&self) -> LV {
         debug_assert!(self.end > self.start); // last is invalid for empty spans.
         self.end - 1
     }
 
-    pub fn contains(&self, item: usize) -> bool {
+    pub fn contains(&self, item: LV) -> bool {
         self.start <= item && item < self.end
     }
 
@@ -77,7 +77,7 @@ impl DTRange {
         else { Ordering::Equal }
     }
 
-    pub fn take_first(&mut self) -> Option<usize> {
+    pub fn take_first(&mut self) -> Option<LV> {
         if self.is_empty() {
             None
         } else {
@@ -87,8 +87,8 @@ impl DTRange {
         }
     }
 
-    pub fn iter(&self) -> impl Iterator<Item=usize> {
-        Range::<usize>::from(self)
+    pub fn iter(&self) -> impl Iterator<Item=LV> {
+        Range::<LV>::from(self)
     }
 
     pub fn clear(&mut self) {
@@ -96,14 +96,14 @@ impl DTRange {
     }
 }
 
-impl From<usize> for DTRange {
-    fn from(start: usize) -> Self {
+impl From<LV> for DTRange {
+    fn from(start: LV) -> Self {
         DTRange { start, end: start + 1 }
     }
 }
 
-impl From<Range<usize>> for DTRange {
-    fn from(range: Range<usize>) -> Self {
+impl From<Range<LV>> for DTRange {
+    fn from(range: Range<LV>) -> Self {
         DTRange {
             start: range.start,
             end: range.end,
@@ -111,8 +111,8 @@ impl From<Range<usize>> for DTRange {
     }
 }
 
-impl From<&Range<usize>> for DTRange {
-    fn from(range: &Range<usize>) -> Self {
+impl From<&Range<LV>> for DTRange {
+    fn from(range: &Range<LV>) -> Self {
         DTRange {
             start: range.start,
             end: range.end,
@@ -120,25 +120,33 @@ impl From<&Range<usize>> for DTRange {
     }
 }
 
-impl From<DTRange> for Range<usize> {
+impl From<DTRange> for Range<LV> {
     fn from(span: DTRange) -> Self {
         span.start..span.end
     }
 }
-impl From<&DTRange> for Range<usize> {
+impl From<&DTRange> for Range<LV> {
     fn from(span: &DTRange) -> Self {
         span.start..span.end
     }
 }
 
-impl RangeBounds<usize> for DTRange {
-    fn start_bound(&self) -> Bound<&usize> {
+// The rle crate's lists are keyed by usize.
+#[cfg(feature = "lv_u64")]
+impl From<DTRange> for Range<usize> {
+    fn from(span: DTRange) -> Self {
+        span.start as usize..span.end as usize
+    }
+}
+
+impl RangeBounds<LV> for DTRange {
+    fn start_bound(&self) -> Bound<&LV> {
         Bound::Included(&self.start)
     }
-    fn end_bound(&self) -> Bound<&usize> {
+    fn end_bound(&self) -> Bound<&LV> {
         Bound::Excluded(&self.end)
     }
-    fn contains<U>(&self, item: &U) -> bool where usize: PartialOrd<U>, U: ?Sized + PartialOrd<usize> {
+    fn contains<U>(&self, item: &U) -> bool where LV: PartialOrd<U>, U: ?Sized + PartialOrd<LV> {
         item >= &self.start && item < &self.end
     }
 }
@@ -146,13 +154,13 @@ impl RangeBounds<usize> for DTRange {
 impl HasLength for DTRange {
     #[inline]
     fn len(&self) -> usize {
-        self.end - self.start
+        lv_to_usize(self.end - self.start)
     }
 }
 
 impl SplitableSpanHelpers for DTRange {
     fn truncate_h(&mut self, at: usize) -> Self {
-        let split = self.start + at;
+        let split = self.start + at as LV;
         let other = DTRange {
             start: split,
             end: self.end,
@@ -164,7 +172,7 @@ impl SplitableSpanHelpers for DTRange {
 
     #[inline]
     fn truncate_keeping_right_h(&mut self, at: usize) -> Self {
-        let split = self.start + at;
+        let split = self.start + at as LV;
         let other = DTRange {
             start: self.start,
             end: split,
@@ -191,19 +199,19 @@ impl MergableSpan for DTRange {
 }
 
 impl Searchable for DTRange {
-    type Item = usize; // Time
+    type Item = LV;
 
     fn get_offset(&self, loc: Self::Item) -> Option<usize> {
         // debug_assert!(loc < self.len());
         if loc >= self.start && loc < self.end {
-            Some(loc - self.start)
+            Some(lv_to_usize(loc - self.start))
         } else {
             None
         }
     }
 
     fn at_offset(&self, offset: usize) -> Self::Item {
-        self.start + offset
+        self.start + offset as LV
     }
 }
 
@@ -216,7 +224,7 @@ impl Searchable for DTRange {
 // This is used for vector clocks. Note if you want order spans keyed by something else, use
 // KVPair<OrderSpan> instead.
 impl HasRleKey for DTRange {
-    fn rle_key(&self) -> usize {
+    fn rle_key(&self) -> LV {
         self.start
     }
 }
@@ -235,7 +243,8 @@ impl HasRleKey for DTRange {
 //     }
 // }
 
-pub(crate) const UNDERWATER_START: usize = usize::MAX / 4;
+// Underwater items are assigned LVs from here up, well above any real version.
+pub(crate) const UNDERWATER_START: LV = LV::MAX / 4;
 
 // pub(crate) fn is_underwater(lv: LV) -> bool {
 //     lv >= UNDERWATER_START
@@ -260,7 +269,7 @@ pub(crate) fn debug_lv(fmt: &mut DebugStruct, name: &str, val: LV) {
 }
 
 // #[derive(Debug)]
-struct Underwater(usize);
+struct Underwater(LV);
 
 impl Debug for Underwater {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
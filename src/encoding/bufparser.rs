@@ -1,6 +1,7 @@
 use std::mem::size_of;
 use crate::encoding::parseerror::ParseError;
 use crate::encoding::varint::*;
+use crate::LV;
 
 #[derive(Debug, Clone)]
 pub struct BufParser<'a>(pub(crate) &'a [u8]);
@@ -77,6 +78,12 @@ impl<'a> BufParser<'a> {
         Ok(val)
     }
 
+    /// Read a local version or sequence number. These are always encoded as u64s.
+    pub(crate) fn next_lv(&mut self) -> Result<LV, ParseError> {
+        let val = self.next_u64()?;
+        LV::try_from(val).map_err(|_| ParseError::ValueTooLarge)
+    }
+
     pub(crate) fn next_zigzag_isize(&mut self) -> Result<isize, ParseError> {
         let n = self.next_usize()?;
         Ok(num_decode_zigzag_isize(n))
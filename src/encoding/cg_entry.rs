@@ -109,6 +109,7 @@ fn read_cg_aa(reader: &mut BufParser, persist: bool, aa: &mut AgentAssignment, a
     let (agent, last_seq, idx) = if !is_known {
         if mapped_agent != 0 { return Err(ParseError::GenericInvalidData); }
         let agent_name = reader.next_str()?;
+        if !AgentAssignment::is_valid_agent_name(agent_name) { return Err(ParseError::GenericInvalidData); }
         let agent = aa.get_or_create_agent_id(agent_name);
         let idx = agent_map.len();
         if persist {
@@ -116,11 +117,12 @@ fn read_cg_aa(reader: &mut BufParser, persist: bool, aa: &mut AgentAssignment, a
         }
         (agent, 0, idx)
     } else {
-        let entry = agent_map[mapped_agent];
+        let entry = *agent_map.get(mapped_agent).ok_or(ParseError::GenericInvalidData)?;
         (entry.0, entry.1, mapped_agent)
     };
 
     let len = reader.next_usize()?;
+    if len == 0 { return Err(ParseError::GenericInvalidData); }
 
     let jump = if has_jump {
         reader.next_zigzag_isize()?
@@ -128,7 +130,7 @@ fn read_cg_aa(reader: &mut BufParser, persist: bool, aa: &mut AgentAssignment, a
 
     let start = isize_try_add(last_seq, jump)
         .ok_or(ParseError::GenericInvalidData)?;
-    let end = start + len;
+    let end = start.checked_add(len as LV).ok_or(ParseError::GenericInvalidData)?;
 
     if persist {
         agent_map[idx].1 = end;
@@ -140,11 +142,11 @@ fn read_cg_aa(reader: &mut BufParser, persist: bool, aa: &mut AgentAssignment, a
     }))
 }
 
-fn isize_try_add(x: usize, y: isize) -> Option<usize> {
+fn isize_try_add(x: LV, y: isize) -> Option<LV> {
     let result = (x as i128) + (y as i128);
 
-    if result < 0 || result > usize::MAX as i128 { None }
-    else { Some(result as usize) }
+    if result < 0 || result > LV::MAX as i128 { None }
+    else { Some(result as LV) }
 }
 
 /// NOTE: This does not put the returned data into the causal graph, or update read_map's txn_map.
@@ -162,6 +164,14 @@ fn read_raw(reader: &mut BufParser, persist: bool, aa: &mut AgentAssignment, nex
     Ok((parents, span))
 }
 
+/// Corrupt data could name parents which aren't a valid frontier (eg, duplicate items, or items
+/// which dominate one another). The causal graph can't store those.
+fn check_parents(cg: &CausalGraph, parents: &Frontier) -> Result<(), ParseError> {
+    if parents.len() > 1 && cg.graph.find_dominators(parents.as_ref()) != *parents {
+        Err(ParseError::GenericInvalidData)
+    } else { Ok(()) }
+}
+
 /// Read a CG entry and save it in the causal graph.
 ///
 /// On success, returns the new CG entry read. Note: the new entry's contents might not be
@@ -169,6 +179,7 @@ fn read_raw(reader: &mut BufParser, persist: bool, aa: &mut AgentAssignment, nex
 pub(crate) fn read_cg_entry_into_cg_nonoverlapping(reader: &mut BufParser, persist: bool, cg: &mut CausalGraph, read_map: &mut ReadMap) -> Result<CGEntry, ParseError> {
     let next_file_time = read_map.len();
     let (parents, span) = read_raw(reader, persist, &mut cg.agent_assignment, next_file_time, read_map)?;
+    check_parents(cg, &parents)?;
     let merged_span = cg.merge_and_assign_nonoverlapping(parents.as_ref(), span);
 
     if persist {
@@ -185,6 +196,7 @@ pub(crate) fn read_cg_entry_into_cg_nonoverlapping(reader: &mut BufParser, persi
 pub(crate) fn read_cg_entry_into_cg(reader: &mut BufParser, persist: bool, cg: &mut CausalGraph, read_map: &mut ReadMap) -> Result<DTRange, ParseError> {
     let mut next_file_lv = read_map.len();
     let (parents, span) = read_raw(reader, persist, &mut cg.agent_assignment, next_file_lv, read_map)?;
+    check_parents(cg, &parents)?;
     // dbg!((&parents, span));
 
     // Save it into the causal graph, and update
@@ -204,7 +216,7 @@ pub(crate) fn read_cg_entry_into_cg(reader: &mut BufParser, persist: bool, cg: &
             let client_data = &cg.agent_assignment.client_data[span.agent as usize];
             for KVPair(_, time) in client_data.lv_for_seq.iter_range(span.seq_range) {
                 read_map.txn_map.push(KVPair(next_file_lv, time));
-                next_file_lv += time.len();
+                next_file_lv += time.len() as LV;
             }
         }
     }
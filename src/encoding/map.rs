@@ -4,7 +4,7 @@ use crate::causalgraph::agent_assignment::ClientData;
 use crate::causalgraph::graph::Graph;
 use crate::rle::RleSpanHelpers;
 
-pub(crate) type ReadAgentMap = Vec<(AgentId, usize)>;
+pub(crate) type ReadAgentMap = Vec<(AgentId, LV)>;
 
 /// This struct stores the information we need while reading to map from relative agent info and
 /// edits to the equivalent local times.
@@ -57,7 +57,7 @@ impl ReadMap {
 pub struct WriteMap {
     /// Map from oplog's agent ID to the agent id in the file. Paired with the last assigned agent
     /// ID, to support agent IDs bouncing around.
-    agent_map: Vec<(Option<AgentId>, usize)>,
+    agent_map: Vec<(Option<AgentId>, LV)>,
     next_mapped_agent: AgentId,
 
     /// Map from local oplog versions -> file versions. Each entry is KVPair(local start, file range).
@@ -113,7 +113,7 @@ impl WriteMap {
     }
 
     pub(crate) fn insert_known(&mut self, local_range: DTRange, next_output_lv: LV) {
-        let output_range = (next_output_lv .. next_output_lv + local_range.len()).into();
+        let output_range = (next_output_lv .. next_output_lv + local_range.len() as LV).into();
 
         // NOTE: We have to use .insert instead of .push here so the txn_map stays in the expected
         // order! This will only be relevant if write() is called in a different order from the
@@ -195,7 +195,7 @@ impl WriteMap {
     }
 }
 
-pub fn isize_diff(x: usize, y: usize) -> isize {
+pub fn isize_diff(x: LV, y: LV) -> isize {
     // This looks awkward, but the optimizer reduces this to a simple `sub`:
     // https://rust.godbolt.org/z/Ta617dWsK
     let result = (x as i128) - (y as i128);
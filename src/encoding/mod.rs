@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 use std::mem::replace;
-use rle::MergableSpan;
+use rle::MergableSpanCtx;
 use num_enum::TryFromPrimitive;
 
 /// The encoding module converts the internal data structures to and from a lossless compact binary
@@ -20,6 +20,7 @@ pub(crate) mod cg_entry;
 pub(crate) mod op;
 pub(crate) mod chunk_reader;
 pub(crate) mod map;
+mod serialized_ops;
 // mod agent_assignment;
 
 
@@ -50,6 +51,14 @@ pub(crate) enum ChunkType {
     Operations = 20,
     // OpTypeAndPosition = 22,
 
+    /// Operations on custom CRDTs, tagged with the name of their type. See
+    /// [`SerializedOps::encode`](crate::SerializedOps::encode).
+    CustomOps = 30,
+    /// Operations on counter CRDTs.
+    CounterOps = 31,
+    /// Operations on register CRDTs.
+    RegisterOps = 32,
+
     // PatchContent = 24,
     // /// ContentKnown is a RLE expressing which ranges of patches have known content
     // ContentIsKnown = 25,
@@ -60,22 +69,21 @@ pub(crate) enum ChunkType {
 }
 
 #[derive(Clone)]
-pub(super) struct Merger<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx = ()> {
+pub(super) struct Merger<S: MergableSpanCtx, F: FnMut(S, &mut Ctx), Ctx = ()> {
     last: Option<S>,
     f: F,
     _ctx: PhantomData<Ctx> // Its pretty silly that this is needed.
 }
 
-impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Merger<S, F, Ctx> {
+impl<S: MergableSpanCtx, F: FnMut(S, &mut Ctx), Ctx> Merger<S, F, Ctx> {
     pub fn new(f: F) -> Self {
         Self { last: None, f, _ctx: PhantomData }
     }
 
-    pub fn push2(&mut self, span: S, ctx: &mut Ctx) {
+    /// Push a span whose merging depends on `merge_ctx`. See [`MergableSpanCtx`].
+    pub fn push_ctx(&mut self, span: S, merge_ctx: &S::Ctx, ctx: &mut Ctx) {
         if let Some(last) = self.last.as_mut() {
-            if last.can_append(&span) {
-                last.append(span);
-            } else {
+            if let Err((span, _)) = last.try_append_ctx(span, merge_ctx) {
                 let old = replace(last, span);
                 (self.f)(old, ctx);
             }
@@ -90,6 +98,13 @@ impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Merger<S, F, Ctx> {
         }
     }
 
+}
+
+impl<S: MergableSpanCtx<Ctx = ()>, F: FnMut(S, &mut Ctx), Ctx> Merger<S, F, Ctx> {
+    pub fn push2(&mut self, span: S, ctx: &mut Ctx) {
+        self.push_ctx(span, &(), ctx);
+    }
+
     pub fn flush_iter2<I: Iterator<Item = S>>(mut self, iter: I, ctx: &mut Ctx) {
         for span in iter {
             self.push2(span, ctx);
@@ -98,7 +113,7 @@ impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Merger<S, F, Ctx> {
     }
 }
 
-impl<S: MergableSpan, F: FnMut(S, &mut ())> Merger<S, F, ()> {
+impl<S: MergableSpanCtx<Ctx = ()>, F: FnMut(S, &mut ())> Merger<S, F, ()> {
     pub fn push(&mut self, span: S) {
         self.push2(span, &mut ());
     }
@@ -110,7 +125,7 @@ impl<S: MergableSpan, F: FnMut(S, &mut ())> Merger<S, F, ()> {
     }
 }
 
-impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Drop for Merger<S, F, Ctx> {
+impl<S: MergableSpanCtx, F: FnMut(S, &mut Ctx), Ctx> Drop for Merger<S, F, Ctx> {
     fn drop(&mut self) {
         if self.last.is_some() && !std::thread::panicking() {
             panic!("Merger dropped with unprocessed data");
@@ -129,5 +144,42 @@ impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Drop for Merger<S, F, Ctx> {
 
 #[cfg(test)]
 mod test {
+    use std::ops::Range;
+    use rle::MergableSpanCtx;
+    use super::Merger;
+
+    /// Runs of text which can only be merged if they're adjacent and don't cross a newline.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct TextRun(Range<usize>);
 
+    impl MergableSpanCtx for TextRun {
+        type Ctx = str;
+        type MergeError = ();
+
+        fn check_append_ctx(&self, other: &Self, ctx: &str) -> Result<(), ()> {
+            if self.0.end == other.0.start && !ctx[self.0.clone()].ends_with('\n') { Ok(()) } else { Err(()) }
+        }
+
+        fn append_ctx(&mut self, other: Self, _ctx: &str) {
+            self.0.end = other.0.end;
+        }
+    }
+
+    #[test]
+    fn merger_uses_merge_context() {
+        let text = "ab\ncd";
+        let mut out = vec![];
+        let mut m = Merger::new(|run: TextRun, out: &mut Vec<TextRun>| out.push(run));
+        for r in [0..1, 1..3, 3..4, 4..5] {
+            m.push_ctx(TextRun(r), text, &mut out);
+        }
+        m.flush2(&mut out);
+        assert_eq!(out, vec![TextRun(0..3), TextRun(3..5)]);
+
+        // Plain mergable spans still work.
+        let mut out = vec![];
+        Merger::new(|r: Range<u32>, out: &mut Vec<Range<u32>>| out.push(r))
+            .flush_iter2([0..1, 1..2, 5..6].into_iter(), &mut out);
+        assert_eq!(out, vec![0..2, 5..6]);
+    }
 }
@@ -3,14 +3,14 @@ use smallvec::SmallVec;
 
 use rle::Searchable;
 
-use crate::{Frontier, LV};
+use crate::{Frontier, lv_to_usize, LV};
 use crate::causalgraph::agent_assignment::AgentAssignment;
 use crate::encoding::bufparser::BufParser;
 use crate::encoding::map::{ReadMap, WriteMap};
 use crate::encoding::parseerror::ParseError;
 use crate::encoding::tools::{ExtendFromSlice, push_str};
 use crate::encoding::varint::*;
-use crate::frontier::sort_frontier;
+use crate::frontier::try_sort_frontier;
 
 pub(crate) fn write_parents_raw<R: ExtendFromSlice>(result: &mut R, parents: &[LV], next_output_time: LV, persist: bool, write_map: &mut WriteMap, aa: &AgentAssignment) {
     // println!("Write parents {:?} next_output_time {next_output_time}", parents);
@@ -51,7 +51,7 @@ pub(crate) fn write_parents_raw<R: ExtendFromSlice>(result: &mut R, parents: &[L
         let mut iter = parents.iter().peekable();
         // let mut first = true;
         while let Some(&p) = iter.next() {
-            debug_assert_ne!(p, usize::MAX);
+            debug_assert_ne!(p, LV::MAX);
 
             let has_more = iter.peek().is_some();
 
@@ -68,9 +68,9 @@ pub(crate) fn write_parents_raw<R: ExtendFromSlice>(result: &mut R, parents: &[L
                 // in the file, but this mapping doesn't guarantee that. Currently I'm
                 // re-sorting after reading - which is necessary for external parents anyway.
                 // But allowing unsorted local parents is vaguely upsetting.
-                let mapped_parent = map.1.start + offset;
+                let mapped_parent = map.1.start + offset as LV;
 
-                write_parent_diff(result, next_output_time - mapped_parent, has_more, false);
+                write_parent_diff(result, lv_to_usize(next_output_time - mapped_parent), has_more, false);
             } else {
                 // Foreign change
                 // println!("Region does not contain parent for {}", p);
@@ -96,7 +96,7 @@ pub(crate) fn write_parents_raw<R: ExtendFromSlice>(result: &mut R, parents: &[L
                     }
                 }
                 // And the sequence number.
-                push_usize(result, item.1);
+                push_lv(result, item.1);
             }
         }
     }
@@ -117,8 +117,9 @@ pub(crate) fn read_parents_raw(reader: &mut BufParser, persist: bool, aa: &mut A
             let diff = n;
             // Local parents (parents inside this chunk of data) are stored using their local (file)
             // time offset.
-            let file_time = next_time - diff;
-            let (entry, offset) = read_map.txn_map.find_with_offset(file_time).unwrap();
+            let file_time = next_time.checked_sub(diff as LV).ok_or(ParseError::GenericInvalidData)?;
+            let (entry, offset) = read_map.txn_map.find_with_offset(file_time)
+                .ok_or(ParseError::GenericInvalidData)?;
             entry.1.at_offset(offset)
         } else {
             let agent = match n {
@@ -130,6 +131,7 @@ pub(crate) fn read_parents_raw(reader: &mut BufParser, persist: bool, aa: &mut A
                 1 => {
                     // This is a foreign (unknown) item.
                     let agent_name = reader.next_str()?;
+                    if !AgentAssignment::is_valid_agent_name(agent_name) { return Err(ParseError::GenericInvalidData); }
                     let agent = aa.get_or_create_agent_id(agent_name);
                     if persist {
                         read_map.agent_map.push((agent, 0));
@@ -139,11 +141,11 @@ pub(crate) fn read_parents_raw(reader: &mut BufParser, persist: bool, aa: &mut A
                 n => {
                     // n references a mapped agent.
                     let mapped_agent = n - 2;
-                    read_map.agent_map[mapped_agent].0
+                    read_map.agent_map.get(mapped_agent).ok_or(ParseError::GenericInvalidData)?.0
                 }
             };
 
-            let seq = reader.next_usize()?;
+            let seq = reader.next_lv()?;
             aa.try_agent_version_to_lv((agent, seq))
                 .ok_or(ParseError::DataMissing)? // missing expected (agent, seq).
         };
@@ -158,7 +160,7 @@ pub(crate) fn read_parents_raw(reader: &mut BufParser, persist: bool, aa: &mut A
     // in a different order from the original local order.
     //
     // This is fine - we can just re-sort.
-    sort_frontier(&mut parents);
+    if !try_sort_frontier(&mut parents) { return Err(ParseError::GenericInvalidData); }
 
     Ok(Frontier(parents))
 }
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
+use crate::{AgentId, LV};
 use crate::causalgraph::agent_assignment::remote_ids::VersionConversionError;
 
 
@@ -17,11 +18,24 @@ pub enum ParseError {
     InvalidMagic,
     UnsupportedProtocolVersion,
     DocIdMismatch,
+    /// The data has no document ID, and the decoder was asked to require one.
+    DocIdMissing,
     BaseVersionUnknown,
     UnknownChunk,
     LZ4DecoderNeeded,
     LZ4DecompressionError, // I'd wrap it but lz4_flex errors don't implement any traits
     // LZ4DecompressionError(lz4_flex::block::DecompressError),
+    /// The data contains content compressed with a format we don't support (or which isn't
+    /// enabled in this build).
+    UnknownCompressionFormat,
+    ZstdDecompressionError,
+    /// The data is encrypted, but no cipher was passed to the decoder.
+    CipherNeeded,
+    /// The cipher couldn't decrypt the data. (Usually this means the key is wrong).
+    DecryptionFailed,
+    /// Signatures are being verified, but some operations aren't covered by a signature.
+    MissingSignature,
+    InvalidSignature,
     CompressedDataMissing,
     InvalidChunkHeader,
     MissingChunk(u32),
@@ -39,6 +53,9 @@ pub enum ParseError {
     InvalidUTF8,
     InvalidRemoteID(VersionConversionError),
     InvalidVarInt,
+    /// The data contains a number which is too big to fit in a usize on this platform. This can
+    /// happen when loading very large documents on 32 bit platforms (like wasm32).
+    ValueTooLarge,
     InvalidContent,
 
     GenericInvalidData,
@@ -49,6 +66,57 @@ pub enum ParseError {
     /// I'd like to explicitly support this case, and allow the oplog to contain a somewhat- sparse
     /// set of data, and load more as needed.
     DataMissing,
+
+    /// The data is bigger than one of the limits set in
+    /// [`DecodeOptions`](crate::list::encoding::DecodeOptions). `max` is the limit which was
+    /// exceeded.
+    LimitExceeded { limit: DecodeLimit, max: usize },
+
+    /// The data leaves a gap in an agent's sequence numbers. This is only checked when
+    /// [`DecodeOptions::require_contiguous_seqs`](crate::list::encoding::DecodeOptions::require_contiguous_seqs)
+    /// is set.
+    SeqGap,
+
+    /// The data's version hash doesn't match our history. This is only checked when
+    /// [`DecodeOptions::verify_version_hash`](crate::list::encoding::DecodeOptions::verify_version_hash)
+    /// is set.
+    VersionHashMismatch,
+
+    /// The data contains a different operation with the same ID (and sequence number) as an
+    /// operation we already have. This usually means an agent's sequence numbers were reused
+    /// after a restart. See [`ListOpLog::resume_agent`](crate::list::ListOpLog::resume_agent).
+    DuplicateId { agent: AgentId, seq: LV },
+
+    /// An operation inserts or deletes at a position past the end of the document (at the
+    /// operation's parent version). This is only checked when
+    /// [`DecodeOptions::check_positions`](crate::list::encoding::DecodeOptions::check_positions)
+    /// is set. `offset` is the index of the first invalid operation among the operations the data
+    /// would have added (so it's 0 for the first new operation).
+    InvalidPosition { offset: usize },
+
+    /// The data depends on operations which were pruned from this oplog when it was shallow
+    /// cloned. See [`ListOpLog::shallow_clone`](crate::list::ListOpLog::shallow_clone).
+    HistoryPruned,
+
+    /// The data was squashed at a different version than this oplog (or one side is squashed and
+    /// the other isn't). Their histories can't be merged, because the operations in each snapshot
+    /// would be added again. See
+    /// [`ListOpLog::squash_before`](crate::list::ListOpLog::squash_before).
+    SquashBaseMismatch,
+
+    /// The data was written with a different [`TieBreak`](crate::list::TieBreak) policy than the
+    /// oplog uses. Set the policy with
+    /// [`ListOpLog::set_insert_tie_break`](crate::list::ListOpLog::set_insert_tie_break) before
+    /// loading the data.
+    TieBreakMismatch,
+}
+
+/// The resource limits which can be set when decoding. See [`ParseError::LimitExceeded`].
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DecodeLimit {
+    Ops,
+    ContentBytes,
+    Agents,
 }
 
 impl Display for ParseError {
@@ -0,0 +1,340 @@
+//! A compact binary encoding for [`SerializedOps`]. This lets changes to an
+//! [`OpLog`](crate::OpLog) be saved and sent without needing serde.
+//!
+//! The format is just each field of [`SerializedOps`] in order. Lists are prefixed by their
+//! length, strings and byte arrays are length prefixed, and numbers are varints. Text operations
+//! store their content inline.
+//!
+//! Custom, counter and register operations are each stored in their own chunk
+//! ([`ChunkType::CustomOps`], [`ChunkType::CounterOps`] and [`ChunkType::RegisterOps`]). The
+//! custom chunk starts with a table of the custom type names used, and each operation names its
+//! type with an index into that table.
+
+use rle::HasLength;
+use crate::{CRDTKind, CreateValue, LV, Primitive, SerializedOps};
+use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
+use crate::encoding::bufparser::BufParser;
+use crate::encoding::ChunkType;
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::tools::{push_chunk, push_str};
+use crate::encoding::varint::{num_decode_zigzag_i64, num_encode_zigzag_i64, push_lv, push_u64, push_usize};
+use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
+use crate::list::operation::ListOpKind;
+use crate::rev_range::RangeRev;
+use crate::unicount::count_chars;
+
+fn push_bytes(into: &mut Vec<u8>, bytes: &[u8]) {
+    push_usize(into, bytes.len());
+    into.extend_from_slice(bytes);
+}
+
+fn push_rv(into: &mut Vec<u8>, rv: RemoteVersion) {
+    push_str(into, rv.0);
+    push_lv(into, rv.1);
+}
+
+fn push_kind(into: &mut Vec<u8>, kind: CRDTKind) {
+    push_usize(into, match kind {
+        CRDTKind::Map => 0,
+        CRDTKind::Register => 1,
+        CRDTKind::Collection => 2,
+        CRDTKind::Text => 3,
+        CRDTKind::Custom => 4,
+        CRDTKind::Counter => 5,
+    });
+}
+
+/// Returns an error if the value is [`Primitive::InvalidUninitialized`], which can't be encoded.
+fn push_primitive(into: &mut Vec<u8>, val: &Primitive) -> Result<(), ParseError> {
+    match val {
+        Primitive::Nil => push_usize(into, 0),
+        Primitive::Bool(b) => push_usize(into, if *b { 2 } else { 1 }),
+        Primitive::I64(n) => {
+            push_usize(into, 3);
+            push_u64(into, num_encode_zigzag_i64(*n));
+        }
+        Primitive::Str(s) => {
+            push_usize(into, 4);
+            push_str(into, s);
+        }
+        Primitive::InvalidUninitialized => return Err(ParseError::InvalidContent),
+    }
+    Ok(())
+}
+
+fn push_create_value(into: &mut Vec<u8>, val: &CreateValue) -> Result<(), ParseError> {
+    match val {
+        CreateValue::Primitive(p) => {
+            push_usize(into, 0);
+            push_primitive(into, p)?;
+        }
+        CreateValue::NewCRDT(kind) => {
+            push_usize(into, 1);
+            push_kind(into, *kind);
+        }
+    }
+    Ok(())
+}
+
+impl<'a> BufParser<'a> {
+    fn next_bytes(&mut self) -> Result<&'a [u8], ParseError> {
+        let len = self.next_usize()?;
+        self.next_n_bytes(len)
+    }
+
+    /// Read the next chunk, which must have the named type.
+    fn next_chunk_of(&mut self, chunk_type: ChunkType) -> Result<BufParser<'a>, ParseError> {
+        if self.next_u32()? != chunk_type as u32 { return Err(ParseError::UnknownChunk); }
+        Ok(BufParser(self.next_bytes()?))
+    }
+
+    fn next_rv(&mut self) -> Result<RemoteVersion<'a>, ParseError> {
+        Ok(RemoteVersion(self.next_str()?, self.next_lv()?))
+    }
+
+    /// Read a list length. Every item takes at least one byte, so lengths longer than the rest of
+    /// the data are invalid.
+    fn next_list_len(&mut self) -> Result<usize, ParseError> {
+        let len = self.next_usize()?;
+        if len > self.len() { Err(ParseError::InvalidLength) } else { Ok(len) }
+    }
+
+    fn next_kind(&mut self) -> Result<CRDTKind, ParseError> {
+        Ok(match self.next_usize()? {
+            0 => CRDTKind::Map,
+            1 => CRDTKind::Register,
+            2 => CRDTKind::Collection,
+            3 => CRDTKind::Text,
+            4 => CRDTKind::Custom,
+            5 => CRDTKind::Counter,
+            _ => return Err(ParseError::GenericInvalidData),
+        })
+    }
+
+    fn next_primitive(&mut self) -> Result<Primitive, ParseError> {
+        Ok(match self.next_usize()? {
+            0 => Primitive::Nil,
+            1 => Primitive::Bool(false),
+            2 => Primitive::Bool(true),
+            3 => Primitive::I64(num_decode_zigzag_i64(self.next_u64()?)),
+            4 => Primitive::Str(self.next_str()?.into()),
+            _ => return Err(ParseError::GenericInvalidData),
+        })
+    }
+
+    fn next_create_value(&mut self) -> Result<CreateValue, ParseError> {
+        Ok(match self.next_usize()? {
+            0 => CreateValue::Primitive(self.next_primitive()?),
+            1 => CreateValue::NewCRDT(self.next_kind()?),
+            _ => return Err(ParseError::GenericInvalidData),
+        })
+    }
+}
+
+impl<'a> SerializedOps<'a> {
+    /// Encode the operations in a compact binary format. Use [`decode`](SerializedOps::decode) to
+    /// read them back.
+    ///
+    /// Returns [`ParseError::InvalidContent`] if any value is
+    /// [`Primitive::InvalidUninitialized`].
+    pub fn encode(&self) -> Result<Vec<u8>, ParseError> {
+        let mut result = vec![];
+        push_bytes(&mut result, &self.cg_changes);
+
+        push_usize(&mut result, self.map_ops.len());
+        for (crdt, rv, key, val) in self.map_ops.iter() {
+            push_rv(&mut result, *crdt);
+            push_rv(&mut result, *rv);
+            push_str(&mut result, key);
+            push_create_value(&mut result, val)?;
+        }
+
+        push_usize(&mut result, self.text_ops.len());
+        for (crdt, rv, op) in self.text_ops.iter() {
+            push_rv(&mut result, *crdt);
+            push_rv(&mut result, *rv);
+            push_usize(&mut result, match (op.kind, op.loc.fwd) {
+                (ListOpKind::Ins, true) => 0,
+                (ListOpKind::Ins, false) => 1,
+                (ListOpKind::Del, true) => 2,
+                (ListOpKind::Del, false) => 3,
+            });
+            push_usize(&mut result, op.start());
+            push_usize(&mut result, op.loc.span.len());
+            match op.content_pos {
+                Some(pos) => {
+                    push_usize(&mut result, 1);
+                    push_str(&mut result, self.text_context.get_str(op.kind, pos));
+                }
+                None => push_usize(&mut result, 0),
+            }
+        }
+
+        let mut kinds: Vec<&str> = vec![];
+        let mut custom = vec![];
+        for (crdt, rv, kind, op) in self.custom_ops.iter() {
+            let kind_idx = kinds.iter().position(|k| k == kind).unwrap_or_else(|| {
+                kinds.push(kind);
+                kinds.len() - 1
+            });
+            push_rv(&mut custom, *crdt);
+            push_rv(&mut custom, *rv);
+            push_usize(&mut custom, kind_idx);
+            push_bytes(&mut custom, op);
+        }
+        let mut chunk = vec![];
+        push_usize(&mut chunk, kinds.len());
+        for kind in kinds { push_str(&mut chunk, kind); }
+        push_usize(&mut chunk, self.custom_ops.len());
+        chunk.extend_from_slice(&custom);
+        push_chunk(&mut result, ChunkType::CustomOps, &chunk).unwrap();
+
+        let mut chunk = vec![];
+        push_usize(&mut chunk, self.counter_ops.len());
+        for (crdt, rv, n) in self.counter_ops.iter() {
+            push_rv(&mut chunk, *crdt);
+            push_rv(&mut chunk, *rv);
+            push_u64(&mut chunk, num_encode_zigzag_i64(*n));
+        }
+        push_chunk(&mut result, ChunkType::CounterOps, &chunk).unwrap();
+
+        let mut chunk = vec![];
+        push_usize(&mut chunk, self.register_ops.len());
+        for (crdt, rv, val) in self.register_ops.iter() {
+            push_rv(&mut chunk, *crdt);
+            push_rv(&mut chunk, *rv);
+            push_primitive(&mut chunk, val)?;
+        }
+        push_chunk(&mut result, ChunkType::RegisterOps, &chunk).unwrap();
+        Ok(result)
+    }
+
+    /// Decode operations encoded with [`encode`](SerializedOps::encode). Agent names and keys are
+    /// borrowed from `data`.
+    pub fn decode(data: &'a [u8]) -> Result<Self, ParseError> {
+        let mut buf = BufParser(data);
+        let cg_changes = buf.next_bytes()?.to_vec();
+
+        let mut map_ops = vec![];
+        for _ in 0..buf.next_list_len()? {
+            map_ops.push((buf.next_rv()?, buf.next_rv()?, buf.next_str()?, buf.next_create_value()?));
+        }
+
+        let mut text_ops = vec![];
+        let mut text_context = ListOperationCtx::new();
+        for _ in 0..buf.next_list_len()? {
+            let crdt = buf.next_rv()?;
+            let rv = buf.next_rv()?;
+            let (kind, fwd) = match buf.next_usize()? {
+                0 => (ListOpKind::Ins, true),
+                1 => (ListOpKind::Ins, false),
+                2 => (ListOpKind::Del, true),
+                3 => (ListOpKind::Del, false),
+                _ => return Err(ParseError::GenericInvalidData),
+            };
+            let start = buf.next_usize()?;
+            let len = buf.next_usize()?;
+            let end = start.checked_add(len).ok_or(ParseError::InvalidLength)?;
+            if len == 0 { return Err(ParseError::InvalidLength); }
+            let content_pos = match buf.next_usize()? {
+                0 => None,
+                1 => {
+                    let content = buf.next_str()?;
+                    if count_chars(content) != len { return Err(ParseError::InvalidLength); }
+                    Some(text_context.push_str(kind, content))
+                }
+                _ => return Err(ParseError::GenericInvalidData),
+            };
+            text_ops.push((crdt, rv, ListOpMetrics {
+                loc: RangeRev { span: (start as LV..end as LV).into(), fwd },
+                kind,
+                content_pos,
+            }));
+        }
+
+        let mut chunk = buf.next_chunk_of(ChunkType::CustomOps)?;
+        let mut kinds = vec![];
+        for _ in 0..chunk.next_list_len()? {
+            kinds.push(chunk.next_str()?);
+        }
+        let mut custom_ops = vec![];
+        for _ in 0..chunk.next_list_len()? {
+            let crdt = chunk.next_rv()?;
+            let rv = chunk.next_rv()?;
+            let kind = *kinds.get(chunk.next_usize()?).ok_or(ParseError::GenericInvalidData)?;
+            custom_ops.push((crdt, rv, kind, chunk.next_bytes()?.to_vec()));
+        }
+        chunk.expect_empty()?;
+
+        let mut chunk = buf.next_chunk_of(ChunkType::CounterOps)?;
+        let mut counter_ops = vec![];
+        for _ in 0..chunk.next_list_len()? {
+            counter_ops.push((chunk.next_rv()?, chunk.next_rv()?, num_decode_zigzag_i64(chunk.next_u64()?)));
+        }
+        chunk.expect_empty()?;
+
+        let mut chunk = buf.next_chunk_of(ChunkType::RegisterOps)?;
+        let mut register_ops = vec![];
+        for _ in 0..chunk.next_list_len()? {
+            register_ops.push((chunk.next_rv()?, chunk.next_rv()?, chunk.next_primitive()?));
+        }
+        chunk.expect_empty()?;
+        buf.expect_empty()?;
+
+        Ok(Self { cg_changes, map_ops, text_ops, text_context, custom_ops, counter_ops, register_ops })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CRDTKind, CreateValue, OpLog, Primitive, ROOT_CRDT_ID, SerializedOps};
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::operation::TextOperation;
+
+    #[test]
+    fn round_trip() {
+        let mut oplog = OpLog::new();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+        oplog.local_map_set(seph, ROOT_CRDT_ID, "title", CreateValue::Primitive(Primitive::Str("hi".into())));
+        oplog.local_map_set(seph, ROOT_CRDT_ID, "n", CreateValue::Primitive(Primitive::I64(-5)));
+        let text = oplog.local_map_set(seph, ROOT_CRDT_ID, "body", CreateValue::NewCRDT(CRDTKind::Text));
+        oplog.local_text_op(seph, text, TextOperation::new_insert(0, "héllo"));
+        oplog.local_text_op(seph, text, TextOperation::new_delete(1..3));
+
+        let data = oplog.ops_since(&[]).encode().unwrap();
+        let mut copy = OpLog::new();
+        copy.merge_ops(SerializedOps::decode(&data).unwrap()).unwrap();
+        assert_eq!(copy.cg, oplog.cg);
+        assert_eq!(copy.checkout_text(text).to_string(), "hlo");
+        assert_eq!(copy.checkout(), oplog.checkout());
+
+        assert!(SerializedOps::decode(&data[..data.len() - 1]).is_err());
+        assert_eq!(SerializedOps::decode(&[]).unwrap_err(), ParseError::UnexpectedEOF);
+    }
+
+    #[test]
+    fn counter_and_register_round_trip() {
+        let mut oplog = OpLog::new();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+        let likes = oplog.local_map_set(seph, ROOT_CRDT_ID, "likes", CreateValue::NewCRDT(CRDTKind::Counter));
+        let title = oplog.local_map_set(seph, ROOT_CRDT_ID, "title", CreateValue::NewCRDT(CRDTKind::Register));
+        oplog.local_counter_add(seph, likes, 10);
+        oplog.local_counter_add(seph, likes, -3);
+        oplog.local_register_set(seph, title, Primitive::Str("Hello".into()));
+
+        let ops = oplog.ops_since(&[]);
+        assert_eq!(ops.counter_ops.len(), 2);
+        assert_eq!(ops.register_ops.len(), 1);
+        let data = ops.encode().unwrap();
+        let mut copy = OpLog::new();
+        copy.merge_ops(SerializedOps::decode(&data).unwrap()).unwrap();
+        assert_eq!(copy.cg, oplog.cg);
+        assert_eq!(copy.checkout_counter(likes), Some(7));
+        assert_eq!(copy.checkout_register(title), Some(Primitive::Str("Hello".into())));
+        assert_eq!(copy.checkout(), oplog.checkout());
+
+        // Uninitialized values can't be encoded.
+        oplog.local_register_set(seph, title, Primitive::InvalidUninitialized);
+        assert_eq!(oplog.ops_since(&[]).encode().unwrap_err(), ParseError::InvalidContent);
+    }
+}
@@ -18,6 +18,7 @@
 use std::mem::size_of;
 use crate::encoding::parseerror::ParseError;
 use crate::encoding::tools::{ExtendFromSlice, TryExtendFromSlice};
+use crate::LV;
 
 // const ENC_1_U64: u64 = 1u64 << 7;
 // const ENC_2_U64: u64 = (1u64 << 14) + (1u64 << 7);
@@ -400,8 +401,10 @@ pub(crate) fn decode_prefix_varint_u64_unroll_flat(buf: &U64VarintArr) -> (u64,
 
 pub fn decode_prefix_varint_usize(buf: &[u8]) -> Result<(usize, usize), ParseError> {
     if size_of::<usize>() <= size_of::<u32>() {
-        let (val, count) = decode_prefix_varint_u32(buf)?;
-        Ok((val as usize, count))
+        // Values written by 64 bit peers might not fit in a usize here.
+        let (val, count) = decode_prefix_varint_u64(buf)?;
+        let val = usize::try_from(val).map_err(|_| ParseError::ValueTooLarge)?;
+        Ok((val, count))
     } else if size_of::<usize>() == size_of::<u64>() {
         let (val, count) = decode_prefix_varint_u64(buf)?;
         Ok((val as usize, count))
@@ -476,6 +479,11 @@ pub(crate) fn push_usize<V: ExtendFromSlice>(into: &mut V, val: usize) {
     }
 }
 
+#[allow(clippy::unnecessary_cast)] // LV is u64 with the lv_u64 feature.
+pub(crate) fn push_lv<V: ExtendFromSlice>(into: &mut V, val: LV) {
+    push_u64(into, val as u64);
+}
+
 // pub(crate) fn push_str<V: ExtendFromSlice>(into: &mut V, val: &str) -> V::Result {
 //     let bytes = val.as_bytes();
 //     push_usize(into, bytes.len());
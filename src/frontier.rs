@@ -1,6 +1,7 @@
 use std::borrow::Borrow;
 use std::cmp::Ordering;
-use std::fmt::Debug;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
 use std::ops::{Index, IndexMut};
 
 #[cfg(feature = "serde")]
@@ -20,12 +21,43 @@ use crate::LV;
 ///
 /// A frontier must always remain sorted (in numerical order). Note: This is not checked when
 /// deserializing via serde!
+///
+/// Frontiers built directly (or with [`from_sorted`](Frontier::from_sorted)) aren't checked
+/// either, and the list APIs take frontiers as plain `&[LV]` slices which they assume are valid.
+/// Frontiers which come from outside the program should be checked with
+/// [`validate`](Frontier::validate) or built with [`try_from_versions`](Frontier::try_from_versions)
+/// first.
 #[derive(Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
 pub struct Frontier(pub SmallVec<LV, 2>);
 
 pub type FrontierRef<'a> = &'a [LV];
 
+/// The reason a list of versions isn't a valid frontier. See [`Frontier::validate`].
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum FrontierError {
+    /// The versions aren't in ascending order, or contain duplicates.
+    Unsorted,
+    /// The named version isn't in the graph.
+    UnknownVersion(LV),
+    /// The named version is in the history of another version in the frontier. Frontiers only
+    /// name the versions at the tip of the history.
+    Dominated(LV),
+}
+
+impl Display for FrontierError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrontierError::Unsorted => f.write_str("Frontier is not sorted"),
+            FrontierError::UnknownVersion(v) => write!(f, "Frontier names unknown version {v}"),
+            FrontierError::Dominated(v) => write!(f, "Frontier version {v} is dominated by another version"),
+        }
+    }
+}
+
+impl Error for FrontierError {}
+
 impl AsRef<[LV]> for Frontier {
     fn as_ref(&self) -> &[LV] {
         self.0.as_slice()
@@ -119,6 +151,12 @@ pub(crate) fn sort_frontier<const N: usize>(v: &mut SmallVec<LV, N>) {
     }
 }
 
+/// Sort a frontier read from untrusted data. Returns false if the frontier contains duplicates.
+pub(crate) fn try_sort_frontier<const N: usize>(v: &mut SmallVec<LV, N>) -> bool {
+    v.sort_unstable();
+    v.windows(2).all(|w| w[0] != w[1])
+}
+
 impl IntoIterator for Frontier {
     type Item = LV;
     type IntoIter = <SmallVec<LV, 2> as IntoIterator>::IntoIter;
@@ -167,6 +205,44 @@ impl Frontier {
         Self(data.into())
     }
 
+    /// Check that `versions` is a valid frontier in `graph`. The versions must be sorted, known
+    /// to the graph, and none of them can be in the history of another.
+    ///
+    /// Most methods which take a frontier as a `&[LV]` assume its valid, and misbehave (or panic)
+    /// if its not. Use this to check frontiers which come from outside the program.
+    pub fn validate(graph: &Graph, versions: &[LV]) -> Result<(), FrontierError> {
+        if !versions.windows(2).all(|w| w[0] < w[1]) { return Err(FrontierError::Unsorted); }
+        if let Some(&v) = versions.last().filter(|v| **v >= graph.len()) {
+            return Err(FrontierError::UnknownVersion(v));
+        }
+        if versions.len() >= 2 {
+            let dominators = graph.find_dominators(versions);
+            if let Some(&v) = versions.iter().find(|v| !dominators.0.contains(v)) {
+                return Err(FrontierError::Dominated(v));
+            }
+        }
+        Ok(())
+    }
+
+    /// Make a frontier from `versions`, checking that its valid. See [`validate`](Self::validate).
+    pub fn try_from_versions(graph: &Graph, versions: &[LV]) -> Result<Self, FrontierError> {
+        Self::validate(graph, versions)?;
+        Ok(Self::from_sorted(versions))
+    }
+
+    /// Make a frontier naming the smallest version which contains all of `versions`. Unlike
+    /// [`try_from_versions`](Self::try_from_versions), the versions can be in any order, and can
+    /// name operations which are in each other's history.
+    pub fn from_versions(graph: &Graph, versions: &[LV]) -> Result<Self, FrontierError> {
+        if let Some(&v) = versions.iter().find(|v| **v >= graph.len()) {
+            return Err(FrontierError::UnknownVersion(v));
+        }
+        let mut versions = versions.to_vec();
+        versions.sort_unstable();
+        versions.dedup();
+        Ok(graph.find_dominators(&versions))
+    }
+
     /// Frontiers should always be sorted smallest to largest.
     pub fn len(&self) -> usize {
         self.0.len()
@@ -179,7 +255,7 @@ impl Frontier {
         self.0.is_empty()
     }
 
-    pub fn iter(&self) -> std::slice::Iter<usize> {
+    pub fn iter(&self) -> std::slice::Iter<LV> {
         self.0.iter()
     }
 
@@ -508,6 +584,29 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn validate_frontiers() {
+        let graph = Graph::from_simple_items(&[
+            GraphEntrySimple { span: (0..3).into(), parents: Frontier::root() },
+            GraphEntrySimple { span: (3..6).into(), parents: Frontier::root() },
+            GraphEntrySimple { span: (6..9).into(), parents: Frontier::from_sorted(&[1, 4]) },
+        ]);
+
+        assert_eq!(Frontier::validate(&graph, &[]), Ok(()));
+        assert_eq!(Frontier::validate(&graph, &[2, 5]), Ok(()));
+        assert_eq!(Frontier::validate(&graph, &[2, 8]), Ok(()));
+        assert_eq!(Frontier::validate(&graph, &[5, 2]), Err(FrontierError::Unsorted));
+        assert_eq!(Frontier::validate(&graph, &[2, 2]), Err(FrontierError::Unsorted));
+        assert_eq!(Frontier::validate(&graph, &[2, 9]), Err(FrontierError::UnknownVersion(9)));
+        assert_eq!(Frontier::validate(&graph, &[1, 8]), Err(FrontierError::Dominated(1)));
+        assert_eq!(Frontier::validate(&graph, &[4, 5]), Err(FrontierError::Dominated(4)));
+
+        assert_eq!(Frontier::try_from_versions(&graph, &[2, 5]), Ok(Frontier::from_sorted(&[2, 5])));
+        assert_eq!(Frontier::try_from_versions(&graph, &[1, 8]), Err(FrontierError::Dominated(1)));
+        assert_eq!(Frontier::from_versions(&graph, &[8, 1, 8, 2]), Ok(Frontier::from_sorted(&[2, 8])));
+        assert_eq!(Frontier::from_versions(&graph, &[100]), Err(FrontierError::UnknownVersion(100)));
+    }
+
     #[test]
     fn frontier_movement_smoke_tests() {
         let mut branch: Frontier = Frontier::root();
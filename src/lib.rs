@@ -194,17 +194,23 @@ use smallvec::SmallVec;
 use smartstring::alias::String as SmartString;
 
 pub use ::rle::HasLength;
-use causalgraph::graph::Graph;
-pub use frontier::Frontier;
+pub use causalgraph::graph::Graph;
+pub use frontier::{Frontier, FrontierError};
 
 use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontierOwned, RemoteVersion, RemoteVersionOwned};
 use crate::causalgraph::agent_span::AgentVersion;
 pub use crate::causalgraph::CausalGraph;
 pub use crate::dtrange::DTRange;
+pub use crate::repo::{Repo, IMPORTED_DOC_NAME, IMPORT_AGENT_NAME};
+pub use crate::custom_crdt::CustomCRDT;
+#[cfg(feature = "expose_benchmarking")]
+pub use crate::ost::content_tree::DbgBenchCursors;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 
 use crate::rle::{KVPair, RleVec};
 use crate::textinfo::TextInfo;
+use crate::custom_crdt::CustomInfo;
+use crate::counter::CounterInfo;
 
 // use crate::list::internal_op::OperationInternal as TextOpInternal;
 
@@ -227,13 +233,21 @@ pub(crate) mod serde_helpers;
 
 mod listmerge;
 
-#[cfg(any(test, feature = "gen_test_data"))]
+#[cfg(any(test, feature = "testing"))]
 mod list_fuzzer_tools;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(feature = "replay")]
+pub mod replay;
 #[cfg(test)]
 mod fuzzer;
 mod branch;
 mod textinfo;
 mod oplog;
+mod custom_crdt;
+mod counter;
+mod register;
+mod repo;
 #[cfg(feature = "storage")]
 mod storage;
 mod simple_checkout;
@@ -242,13 +256,29 @@ mod stats;
 
 pub type AgentId = u32;
 
-// TODO: Consider changing this to u64 to add support for very long lived documents even on 32 bit
-// systems like wasm32
 /// An LV (LocalVersion) is used all over the place internally to identify a single operation.
 ///
 /// A local version (as the name implies) is local-only. Local versions generally need to be
 /// converted to RawVersions before being sent over the wire or saved to disk.
+///
+/// LVs are usizes by default. The `lv_u64` feature makes them u64s instead, so 32 bit platforms
+/// (like wasm32) use the same version type as everyone else. The encoded format doesn't depend on
+/// either. Without `lv_u64`, loading a document with more than about 4 billion operations on a 32
+/// bit platform fails with
+/// [`ParseError::ValueTooLarge`](crate::list::encoding::ParseError::ValueTooLarge).
+#[cfg(not(feature = "lv_u64"))]
 pub type LV = usize;
+#[cfg(feature = "lv_u64")]
+pub type LV = u64;
+
+/// Convert an LV to a usize. Lengths and offsets are always usizes, and so are document positions
+/// (which are sometimes stored in LV fields, like in a [`DTRange`]). This is a no-op without the
+/// `lv_u64` feature.
+#[inline]
+#[allow(clippy::unnecessary_cast)] // LV is usize without the lv_u64 feature.
+pub(crate) fn lv_to_usize(v: LV) -> usize {
+    v as usize
+}
 
 #[derive(Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(untagged))]
@@ -286,6 +316,10 @@ pub enum CRDTKind {
     Register,
     Collection, // SQL table / mongo collection
     Text,
+    /// A CRDT with a user supplied type. See [`CustomCRDT`].
+    Custom,
+    /// An integer which can be incremented and decremented.
+    Counter,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -334,7 +368,7 @@ pub enum CreateValue {
 //     list_ctx: ListOperationCtx,
 // }
 
-pub const ROOT_CRDT_ID: LV = usize::MAX;
+pub const ROOT_CRDT_ID: LV = LV::MAX;
 pub const ROOT_CRDT_ID_AV: AgentVersion = (AgentId::MAX, 0);
 
 
@@ -401,14 +435,26 @@ pub struct OpLog {
     map_keys: BTreeMap<(LVKey, SmartString), RegisterInfo>,
     /// CRDT ID -> Text CRDT.
     texts: BTreeMap<LVKey, TextInfo>,
+    /// CRDT ID -> Custom CRDT.
+    customs: BTreeMap<LVKey, CustomInfo>,
 
     // These are always inserted at the end, but items in the middle are removed. There's probably
     // a better data structure to accomplish this.
     map_index: BTreeMap<LV, (LVKey, SmartString)>,
     text_index: BTreeMap<LV, LVKey>,
+    /// Unlike the other indexes, this contains every custom operation.
+    custom_index: BTreeMap<LV, LVKey>,
+
+    /// CRDT ID -> Counter.
+    counters: BTreeMap<LVKey, CounterInfo>,
+    /// Contains every counter operation.
+    counter_index: BTreeMap<LV, LVKey>,
 
     // TODO: Vec -> SmallVec.
-    // registers: BTreeMap<LVKey, RegisterInfo>,
+    /// CRDT ID -> Standalone register. The values are always primitives.
+    registers: BTreeMap<LVKey, RegisterInfo>,
+    /// Contains every register operation.
+    register_index: BTreeMap<LV, LVKey>,
 
     // The set of CRDTs which have been deleted or superseded in the current version. This data is
     // pretty similar to the _index data, in that its mainly just useful for branches doing
@@ -447,6 +493,13 @@ pub struct SerializedOps<'a> {
     map_ops: Vec<(RemoteVersion<'a>, RemoteVersion<'a>, &'a str, CreateValue)>,
     text_ops: Vec<(RemoteVersion<'a>, RemoteVersion<'a>, ListOpMetrics)>,
     text_context: ListOperationCtx,
+    /// (CRDT, version, type name, encoded op).
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    custom_ops: Vec<(RemoteVersion<'a>, RemoteVersion<'a>, &'a str, Vec<u8>)>,
+    /// (CRDT, version, increment).
+    counter_ops: Vec<(RemoteVersion<'a>, RemoteVersion<'a>, i64)>,
+    /// (CRDT, version, new value).
+    register_ops: Vec<(RemoteVersion<'a>, RemoteVersion<'a>, Primitive)>,
 }
 
 impl<'a> From<SerializedOps<'a>> for SerializedOpsOwned {
@@ -460,6 +513,15 @@ impl<'a> From<SerializedOps<'a>> for SerializedOpsOwned {
                 (crdt_name.to_owned(), rv.to_owned(), metrics)
             }).collect(),
             text_context: ops.text_context,
+            custom_ops: ops.custom_ops.into_iter().map(|(crdt_name, rv, kind, op)| {
+                (crdt_name.to_owned(), rv.to_owned(), SmartString::from(kind), op)
+            }).collect(),
+            counter_ops: ops.counter_ops.into_iter().map(|(crdt_name, rv, n)| {
+                (crdt_name.to_owned(), rv.to_owned(), n)
+            }).collect(),
+            register_ops: ops.register_ops.into_iter().map(|(crdt_name, rv, val)| {
+                (crdt_name.to_owned(), rv.to_owned(), val)
+            }).collect(),
         }
     }
 }
@@ -479,6 +541,34 @@ pub struct SerializedOpsOwned {
     map_ops: Vec<(RemoteVersionOwned, RemoteVersionOwned, SmartString, CreateValue)>,
     text_ops: Vec<(RemoteVersionOwned, RemoteVersionOwned, ListOpMetrics)>,
     text_context: ListOperationCtx,
+    custom_ops: Vec<(RemoteVersionOwned, RemoteVersionOwned, SmartString, Vec<u8>)>,
+    counter_ops: Vec<(RemoteVersionOwned, RemoteVersionOwned, i64)>,
+    register_ops: Vec<(RemoteVersionOwned, RemoteVersionOwned, Primitive)>,
+}
+
+impl SerializedOpsOwned {
+    /// Borrow these operations as a [`SerializedOps`] object, which can be merged into an oplog.
+    pub fn as_ops(&self) -> SerializedOps<'_> {
+        SerializedOps {
+            cg_changes: self.cg_changes.clone(),
+            map_ops: self.map_ops.iter().map(|(crdt_name, rv, key, val)| {
+                (crdt_name.into(), rv.into(), key.as_str(), val.clone())
+            }).collect(),
+            text_ops: self.text_ops.iter().map(|(crdt_name, rv, metrics)| {
+                (crdt_name.into(), rv.into(), metrics.clone())
+            }).collect(),
+            text_context: self.text_context.clone(),
+            custom_ops: self.custom_ops.iter().map(|(crdt_name, rv, kind, op)| {
+                (crdt_name.into(), rv.into(), kind.as_str(), op.clone())
+            }).collect(),
+            counter_ops: self.counter_ops.iter().map(|(crdt_name, rv, n)| {
+                (crdt_name.into(), rv.into(), *n)
+            }).collect(),
+            register_ops: self.register_ops.iter().map(|(crdt_name, rv, val)| {
+                (crdt_name.into(), rv.into(), val.clone())
+            }).collect(),
+        }
+    }
 }
 
 /// This is used for checkouts. This is a value tree.
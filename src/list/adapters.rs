@@ -0,0 +1,241 @@
+//! Conversions from diamond types' operations into the wire formats used by other editing
+//! libraries. This lets the output of
+//! [`iter_xf_operations_from`](crate::list::ListOpLog::iter_xf_operations_from) be fed straight
+//! into existing editor pipelines.
+//!
+//! Supported formats:
+//!
+//! - [Quill deltas](https://quilljs.com/docs/delta/)
+//! - [ot-text-unicode](https://github.com/ottypes/text-unicode) operations
+//! - [CodeMirror 6](https://codemirror.net/docs/ref/#state.ChangeSet.toJSON) `ChangeSet` JSON
+//!
+//! Each operation converts to a separate change, and the changes must be applied in order. With
+//! the `serde` feature enabled, the changes serialize to the JSON format the library expects.
+//!
+//! ot-text-unicode counts positions in unicode characters, like diamond types does. Quill and
+//! CodeMirror count UTF-16 code units, so converting to those formats needs the document the
+//! operation applies to, to translate positions through any characters outside the basic
+//! multilingual plane (like emoji).
+
+use smartstring::alias::String as SmartString;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::ser::SerializeSeq;
+use crate::list::ListBranch;
+use crate::list::operation::{ListOpKind, TextOperation};
+use crate::listmerge::merge::reverse_str;
+use rle::HasLength;
+
+/// A single component of a Quill delta.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DeltaOp {
+    Retain(usize),
+    Insert(SmartString),
+    Delete(usize),
+}
+
+/// A single component of an ot-text-unicode operation. Serialized as a number (skip), a string
+/// (insert) or `{"d": n}` (delete).
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum TextUnicodeComponent {
+    Skip(usize),
+    Insert(SmartString),
+    Delete { d: usize },
+}
+
+/// A single section of a CodeMirror `ChangeSet`. Serialized as a number (for unchanged text) or
+/// as an array `[deleted length, ...inserted lines]`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CodeMirrorChange {
+    Retain(usize),
+    Replace { del_len: usize, lines: Vec<SmartString> },
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for CodeMirrorChange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            CodeMirrorChange::Retain(len) => serializer.serialize_u64(*len as u64),
+            CodeMirrorChange::Replace { del_len, lines } => {
+                let mut seq = serializer.serialize_seq(Some(lines.len() + 1))?;
+                seq.serialize_element(del_len)?;
+                for line in lines {
+                    seq.serialize_element(line.as_str())?;
+                }
+                seq.end()
+            }
+        }
+    }
+}
+
+impl TextOperation {
+    /// The inserted text, in document order. Returns None for deletes, or if the content isn't
+    /// known.
+    pub(crate) fn inserted_text(&self) -> Option<SmartString> {
+        if self.kind != ListOpKind::Ins { return None; }
+        let content = self.content_as_str()?;
+        Some(if self.loc.fwd { content.into() } else { reverse_str(content) })
+    }
+
+    fn expect_inserted_text(&self) -> SmartString {
+        self.inserted_text().expect("Cannot convert inserts with unknown content")
+    }
+
+    /// The start and end of this operation in `doc`, in UTF-16 code units. For inserts both are
+    /// the insert position.
+    fn utf16_range(&self, doc: &ListBranch) -> (usize, usize) {
+        let start = doc.pos_chars_to_utf16(self.start());
+        let end = match self.kind {
+            ListOpKind::Ins => start,
+            ListOpKind::Del => doc.pos_chars_to_utf16(self.end()),
+        };
+        (start, end)
+    }
+
+    /// Convert this operation into a Quill delta. Quill counts positions in UTF-16 code units, so
+    /// this needs `doc`, the document before the operation is applied.
+    ///
+    /// Panics if the operation is an insert with unknown content, or if the operation doesn't fit
+    /// in the document.
+    pub fn to_quill_delta(&self, doc: &ListBranch) -> Vec<DeltaOp> {
+        let mut result = vec![];
+        let (start, end) = self.utf16_range(doc);
+        if start > 0 { result.push(DeltaOp::Retain(start)); }
+        result.push(match self.kind {
+            ListOpKind::Ins => DeltaOp::Insert(self.expect_inserted_text()),
+            ListOpKind::Del => DeltaOp::Delete(end - start),
+        });
+        result
+    }
+
+    /// Convert this operation into an ot-text-unicode operation.
+    ///
+    /// Panics if the operation is an insert with unknown content.
+    pub fn to_text_unicode(&self) -> Vec<TextUnicodeComponent> {
+        let mut result = vec![];
+        let start = self.start();
+        if start > 0 { result.push(TextUnicodeComponent::Skip(start)); }
+        result.push(match self.kind {
+            ListOpKind::Ins => TextUnicodeComponent::Insert(self.expect_inserted_text()),
+            ListOpKind::Del => TextUnicodeComponent::Delete { d: self.len() },
+        });
+        result
+    }
+
+    /// Convert this operation into a CodeMirror `ChangeSet`. CodeMirror change sets describe the
+    /// whole document in UTF-16 code units, so this needs `doc`, the document before the
+    /// operation is applied.
+    ///
+    /// Panics if the operation is an insert with unknown content, or if the operation doesn't fit
+    /// in the document.
+    pub fn to_codemirror(&self, doc: &ListBranch) -> Vec<CodeMirrorChange> {
+        let mut result = vec![];
+        let (start, end) = self.utf16_range(doc);
+        if start > 0 { result.push(CodeMirrorChange::Retain(start)); }
+
+        let lines = match self.kind {
+            ListOpKind::Ins => self.expect_inserted_text().split('\n').map(|l| l.into()).collect(),
+            ListOpKind::Del => vec![],
+        };
+        result.push(CodeMirrorChange::Replace { del_len: end - start, lines });
+
+        let remaining = doc.len_utf16() - end;
+        if remaining > 0 { result.push(CodeMirrorChange::Retain(remaining)); }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListCRDT};
+    use crate::list::operation::TextOperation;
+    use super::*;
+
+    fn branch_with(content: &str) -> ListBranch {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, content);
+        doc.branch
+    }
+
+    #[test]
+    fn convert_ops() {
+        let doc = branch_with("abcde");
+        let ins = TextOperation::new_insert(3, "a\nb");
+        assert_eq!(ins.to_quill_delta(&doc), vec![DeltaOp::Retain(3), DeltaOp::Insert("a\nb".into())]);
+        assert_eq!(ins.to_text_unicode(), vec![
+            TextUnicodeComponent::Skip(3), TextUnicodeComponent::Insert("a\nb".into())
+        ]);
+        assert_eq!(ins.to_codemirror(&doc), vec![
+            CodeMirrorChange::Retain(3),
+            CodeMirrorChange::Replace { del_len: 0, lines: vec!["a".into(), "b".into()] },
+            CodeMirrorChange::Retain(2),
+        ]);
+
+        let doc = branch_with("ab");
+        let del = TextOperation::new_delete(0..2);
+        assert_eq!(del.to_quill_delta(&doc), vec![DeltaOp::Delete(2)]);
+        assert_eq!(del.to_text_unicode(), vec![TextUnicodeComponent::Delete { d: 2 }]);
+        assert_eq!(del.to_codemirror(&doc), vec![CodeMirrorChange::Replace { del_len: 2, lines: vec![] }]);
+    }
+
+    #[test]
+    fn convert_astral_chars() {
+        // Each emoji is 1 char but 2 UTF-16 code units.
+        let doc = branch_with("\u{1f600}a\u{1f600}b");
+        let del = TextOperation::new_delete(1..3);
+        assert_eq!(del.to_quill_delta(&doc), vec![DeltaOp::Retain(2), DeltaOp::Delete(3)]);
+        assert_eq!(del.to_text_unicode(), vec![
+            TextUnicodeComponent::Skip(1), TextUnicodeComponent::Delete { d: 2 }
+        ]);
+        assert_eq!(del.to_codemirror(&doc), vec![
+            CodeMirrorChange::Retain(2),
+            CodeMirrorChange::Replace { del_len: 3, lines: vec![] },
+            CodeMirrorChange::Retain(1),
+        ]);
+
+        let ins = TextOperation::new_insert(3, "\u{1f600}");
+        assert_eq!(ins.to_quill_delta(&doc), vec![
+            DeltaOp::Retain(5), DeltaOp::Insert("\u{1f600}".into())
+        ]);
+    }
+
+    #[test]
+    fn convert_xf_ops() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hi \u{1f600}there");
+        doc.delete_without_content(seph, 2..9);
+
+        // Each operation is converted against the document it applies to.
+        let mut branch = ListBranch::new();
+        let mut deltas = vec![];
+        for (_, op) in doc.oplog.iter_xf_operations() {
+            let Some(op) = op else { continue; };
+            deltas.push(op.to_quill_delta(&branch));
+            branch.apply(std::slice::from_ref(&op));
+        }
+        assert_eq!(deltas, vec![
+            vec![DeltaOp::Insert("hi \u{1f600}there".into())],
+            vec![DeltaOp::Retain(2), DeltaOp::Delete(8)],
+        ]);
+    }
+
+    #[cfg(all(feature = "serde", feature = "serde_json"))]
+    #[test]
+    fn serialize_formats() {
+        let doc = branch_with("abcd");
+        let ins = TextOperation::new_insert(3, "a\nb");
+        assert_eq!(serde_json::to_string(&ins.to_quill_delta(&doc)).unwrap(),
+                   r#"[{"retain":3},{"insert":"a\nb"}]"#);
+        assert_eq!(serde_json::to_string(&ins.to_codemirror(&doc)).unwrap(),
+                   r#"[3,[0,"a","b"],1]"#);
+        let del = TextOperation::new_delete(1..3);
+        assert_eq!(serde_json::to_string(&del.to_text_unicode()).unwrap(), r#"[1,{"d":2}]"#);
+    }
+}
@@ -0,0 +1,179 @@
+//! Agent metadata.
+//!
+//! Agents are identified by a name string, which is usually a random ID (like a UUID). Apps often
+//! want to attach more information to each agent - like who the user is and which device they were
+//! using. Rather than packing that into the agent's name (which is repeated in every file and
+//! every version sent over the network), it can be stored once as [`AgentInfo`].
+//!
+//! Agent info is saved with the document (alongside the agent's name), and sent to peers along
+//! with any operations (or versions) which name the agent. Each time an agent's info is set, its
+//! revision number goes up. When peers merge, the info with the highest revision wins. If two
+//! peers set an agent's info concurrently, the revisions can match - then the greater info (by
+//! comparing the fields in order) wins. So every peer ends up with the same info for each agent.
+
+use std::collections::BTreeMap;
+use smartstring::alias::String as SmartString;
+use crate::AgentId;
+use crate::list::ListOpLog;
+
+/// Metadata about an agent, like who the user is and which device they're using. Its stored once
+/// per agent rather than in every agent name. When peers set an agent's info differently, they
+/// converge on the most recently set info.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct AgentInfo {
+    /// A human readable name for the user (or process) behind the agent.
+    pub display_name: Option<SmartString>,
+    /// The device the agent is running on.
+    pub device: Option<SmartString>,
+    /// A public key for the agent, if it signs its operations. See
+    /// [`sign_operations`](ListOpLog::sign_operations).
+    pub public_key: Option<Vec<u8>>,
+}
+
+impl AgentInfo {
+    pub fn is_empty(&self) -> bool {
+        self.display_name.is_none() && self.device.is_none() && self.public_key.is_none()
+    }
+}
+
+/// Agent info by agent name, paired with its revision. Entries are merged by keeping the greatest
+/// (revision, info) pair. Cleared info is stored as an empty [`AgentInfo`], so clearing it also
+/// propagates to peers.
+pub(crate) type AgentInfoMap = BTreeMap<SmartString, (usize, AgentInfo)>;
+
+impl ListOpLog {
+    /// Get or create an agent with the given name, and set its info.
+    pub fn get_or_create_agent_with_info(&mut self, name: &str, info: AgentInfo) -> AgentId {
+        let agent = self.get_or_create_agent_id(name);
+        self.set_agent_info(agent, info);
+        agent
+    }
+
+    /// Set (or replace) the info stored for an agent. Setting empty info clears it.
+    ///
+    /// The new info replaces any info for the agent set by peers before it was merged here.
+    pub fn set_agent_info(&mut self, agent: AgentId, info: AgentInfo) {
+        let name = self.get_agent_name(agent);
+        let rev = self.agent_info.get(name).map_or(1, |(rev, _)| rev + 1);
+        if info.is_empty() && rev == 1 { return; }
+        self.agent_info.insert(name.into(), (rev, info));
+    }
+
+    /// Get the info stored for an agent, if any.
+    pub fn agent_info(&self, agent: AgentId) -> Option<&AgentInfo> {
+        self.agent_info.get(self.get_agent_name(agent))
+            .map(|(_, info)| info)
+            .filter(|info| !info.is_empty())
+    }
+
+    /// Iterate through every agent with stored info.
+    pub fn iter_agent_info(&self) -> impl Iterator<Item = (AgentId, &AgentInfo)> + '_ {
+        self.agent_info.iter().filter_map(|(name, (_, info))| {
+            if info.is_empty() { return None; }
+            self.get_agent_id(name).map(|agent| (agent, info))
+        })
+    }
+
+    /// Find every agent with the given display name. Display names aren't unique.
+    pub fn find_agents_by_display_name(&self, display_name: &str) -> Vec<AgentId> {
+        self.iter_agent_info()
+            .filter(|(_, info)| info.display_name.as_deref() == Some(display_name))
+            .map(|(agent, _)| agent)
+            .collect()
+    }
+
+    /// Find the agent with the given public key.
+    pub fn find_agent_by_public_key(&self, public_key: &[u8]) -> Option<AgentId> {
+        self.iter_agent_info()
+            .find(|(_, info)| info.public_key.as_deref() == Some(public_key))
+            .map(|(agent, _)| agent)
+    }
+
+    /// Merge agent info received from a remote peer. The info with the higher revision wins, and
+    /// ties are broken by comparing the info itself.
+    pub(crate) fn merge_agent_info(&mut self, agent: AgentId, rev: usize, info: AgentInfo) {
+        let name = self.get_agent_name(agent);
+        let entry = (rev, info);
+        if self.agent_info.get(name).is_none_or(|existing| *existing < entry) {
+            self.agent_info.insert(name.into(), entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+    use crate::list::encoding::EncodeOptions;
+    use super::AgentInfo;
+
+    fn info(name: &str, key: &[u8]) -> AgentInfo {
+        AgentInfo {
+            display_name: Some(name.into()),
+            device: Some("laptop".into()),
+            public_key: Some(key.to_vec()),
+        }
+    }
+
+    #[test]
+    fn agent_info_roundtrips() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_with_info("8b2d2e1c", info("Seph", &[1, 2, 3]));
+        let mike = oplog.get_or_create_agent_with_info("f00dcafe", info("Mike", &[4, 5]));
+        // Agents which don't appear in the file don't have their info saved.
+        let unused = oplog.get_or_create_agent_with_info("unused", info("Nobody", &[6]));
+        oplog.add_insert(seph, 0, "hi");
+        oplog.add_insert(mike, 2, "!!");
+
+        assert_eq!(oplog.find_agents_by_display_name("Mike"), vec![mike]);
+        assert_eq!(oplog.find_agent_by_public_key(&[1, 2, 3]), Some(seph));
+        assert_eq!(oplog.find_agent_by_public_key(&[6]), Some(unused));
+
+        let loaded = ListOpLog::load_from(&oplog.encode(&EncodeOptions::default())).unwrap();
+        let seph2 = loaded.get_agent_id("8b2d2e1c").unwrap();
+        assert_eq!(loaded.agent_info(seph2), Some(&info("Seph", &[1, 2, 3])));
+        assert_eq!(loaded.find_agents_by_display_name("Mike"), vec![loaded.get_agent_id("f00dcafe").unwrap()]);
+        assert_eq!(loaded.find_agent_by_public_key(&[6]), None);
+        assert_eq!(loaded.iter_agent_info().count(), 2);
+
+        // Version 0 files don't store agent info.
+        let v0 = ListOpLog::load_from(&oplog.encode(&EncodeOptions::default().protocol_version(0))).unwrap();
+        assert_eq!(v0.iter_agent_info().count(), 0);
+
+        // Merging oplogs directly copies every agent, so the unused agent's info comes too.
+        let mut merged = ListOpLog::new();
+        merged.add_missing_operations_from(&oplog);
+        assert_eq!(merged.iter_agent_info().count(), 3);
+
+        oplog.set_agent_info(mike, AgentInfo::default());
+        assert_eq!(oplog.agent_info(mike), None);
+    }
+
+    #[test]
+    fn agent_info_converges() {
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_with_info("seph", info("Joseph", &[1]));
+        a.add_insert(seph, 0, "hi");
+        let mut b = ListOpLog::new();
+        let seph_b = b.get_or_create_agent_with_info("seph", info("Seph", &[1]));
+
+        // The info was set concurrently, so the greater info wins on both peers.
+        let a_data = a.encode(&EncodeOptions::default());
+        b.decode_and_add(&a_data).unwrap();
+        let mut a2 = a.clone();
+        a2.add_missing_operations_from(&b);
+        assert_eq!(b.agent_info(seph_b).unwrap().display_name.as_deref(), Some("Seph"));
+        assert_eq!(a2.agent_info(seph).unwrap().display_name.as_deref(), Some("Seph"));
+
+        // Info set after merging replaces it everywhere.
+        a.add_missing_operations_from(&b);
+        a.set_agent_info(seph, info("Joseph", &[2]));
+        b.decode_and_add(&a.encode(&EncodeOptions::default())).unwrap();
+        assert_eq!(b.agent_info(seph_b), Some(&info("Joseph", &[2])));
+
+        // As does clearing it.
+        a.set_agent_info(seph, AgentInfo::default());
+        b.decode_and_add(&a.encode(&EncodeOptions::default())).unwrap();
+        assert_eq!(b.agent_info(seph_b), None);
+        assert_eq!(b.find_agent_by_public_key(&[2]), None);
+    }
+}
@@ -0,0 +1,284 @@
+//! Sequence number management for local agents.
+//!
+//! Every operation is identified by an `(agent, seq)` pair, and peers assume two operations with
+//! the same ID are the same operation. Local edits use the agent's next unused sequence number in
+//! the oplog - so if an app crashes after sending some edits to a peer but before saving the oplog,
+//! it will reuse those sequence numbers for different edits when it restarts. Peers can't tell the
+//! edits apart, and the document silently diverges.
+//!
+//! To avoid this, apps can reserve sequence numbers with [`ListOpLog::reserve_seqs`] and durably
+//! store the end of the reservation before making any edits. After a restart,
+//! [`ListOpLog::resume_agent`] checks the oplog against the stored value.
+//!
+//! Reservations are enforced. Once an agent has a reservation, it can't make local edits past the
+//! end of it - reserve more sequence numbers first. And if `resume_agent` finds the agent might
+//! have made edits we don't have, the agent can't make any more local edits. The checked edit
+//! methods ([`ListOpLog::try_add_operations`] and [`ListOpLog::try_add_operations_at`]) return a
+//! [`SeqCollision`] error for these edits. The other edit methods panic.
+//!
+//! When data from a peer reuses an ID we already have for a different operation, decoding fails
+//! with [`ParseError::DuplicateId`](crate::list::encoding::ParseError::DuplicateId).
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use rle::{HasLength, SplitableSpan};
+use crate::{AgentId, DTRange, lv_to_usize, LV};
+use crate::causalgraph::agent_assignment::LocalSeqs;
+use crate::list::ListOpLog;
+use crate::list::operation::{ListOpKind, TextOperation};
+
+/// A range of sequence numbers reserved for an agent's local edits. Once an agent has a
+/// reservation, its local edits must use reserved sequence numbers. Store the end of the range
+/// durably before editing, and pass it to [`ListOpLog::resume_agent`] after a restart.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SeqReservation {
+    pub agent: AgentId,
+    /// The reserved sequence numbers. The end of this range should be stored somewhere durable
+    /// before the agent makes any edits.
+    pub seqs: DTRange,
+}
+
+/// An agent's sequence numbers might be reused. `seq` is the first sequence number which might be
+/// assigned to two different operations.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SeqCollision {
+    pub agent: AgentId,
+    pub seq: LV,
+}
+
+impl Display for SeqCollision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sequence number {} of agent {} may be reused", self.seq, self.agent)
+    }
+}
+
+impl Error for SeqCollision {}
+
+impl SeqReservation {
+    /// The number of reserved sequence numbers which haven't been used yet.
+    pub fn remaining(&self, oplog: &ListOpLog) -> usize {
+        lv_to_usize(self.seqs.end.saturating_sub(oplog.next_seq_for(self.agent)))
+    }
+
+    /// Check that the agent hasn't made edits past the end of the reservation. Those edits aren't
+    /// protected - if the app crashes, their sequence numbers could be reused.
+    pub fn check(&self, oplog: &ListOpLog) -> Result<(), SeqCollision> {
+        if oplog.next_seq_for(self.agent) > self.seqs.end {
+            Err(SeqCollision { agent: self.agent, seq: self.seqs.end })
+        } else { Ok(()) }
+    }
+}
+
+impl ListOpLog {
+    /// The sequence number which will be used for the agent's next local operation.
+    pub fn next_seq_for(&self, agent: AgentId) -> LV {
+        self.cg.agent_assignment.client_data[agent as usize].get_next_seq()
+    }
+
+    /// Reserve the agent's next `len` sequence numbers. The caller should store the end of the
+    /// returned range somewhere durable, and pass it to [`resume_agent`](ListOpLog::resume_agent)
+    /// after restarting.
+    ///
+    /// From now on, the agent can only make local edits using reserved sequence numbers.
+    pub fn reserve_seqs(&mut self, agent: AgentId, len: usize) -> SeqReservation {
+        let start = self.next_seq_for(agent);
+        let end = start + len as LV;
+        let client_data = &mut self.cg.agent_assignment.client_data[agent as usize];
+        client_data.local_seqs = match client_data.local_seqs {
+            LocalSeqs::Collided => LocalSeqs::Collided,
+            LocalSeqs::ReservedUntil(old_end) => LocalSeqs::ReservedUntil(old_end.max(end)),
+            LocalSeqs::Unlimited => LocalSeqs::ReservedUntil(end),
+        };
+        SeqReservation { agent, seqs: (start..end).into() }
+    }
+
+    /// Get (or create) the named agent after a restart. `reserved_end` is the end of the last
+    /// reservation made for the agent (or 0 if it has never been used).
+    ///
+    /// Returns an error if the agent might have made edits which the oplog doesn't contain (eg,
+    /// because the app crashed before saving). Editing with the agent could reuse their sequence
+    /// numbers, so the app should use a new agent instead. Any more local edits made with the
+    /// agent will fail (or panic, for the unchecked edit methods).
+    pub fn resume_agent(&mut self, name: &str, reserved_end: LV) -> Result<AgentId, SeqCollision> {
+        let agent = self.get_or_create_agent_id(name);
+        let seq = self.next_seq_for(agent);
+        if seq < reserved_end {
+            self.cg.agent_assignment.client_data[agent as usize].local_seqs = LocalSeqs::Collided;
+            Err(SeqCollision { agent, seq })
+        } else { Ok(agent) }
+    }
+
+    /// Check that the agent can make `len` more local operations without risking reusing a
+    /// sequence number. This fails if the edits would go past the end of the agent's reservation,
+    /// or if [`resume_agent`](ListOpLog::resume_agent) found a possible collision.
+    pub fn check_local_edit(&self, agent: AgentId, len: usize) -> Result<(), SeqCollision> {
+        match self.cg.agent_assignment.client_data[agent as usize].first_unsafe_seq(len) {
+            Some(seq) => Err(SeqCollision { agent, seq }),
+            None => Ok(()),
+        }
+    }
+
+    /// Like [`add_operations`](ListOpLog::add_operations), but returns an error (and leaves the
+    /// oplog unchanged) instead of panicking if the edit could reuse sequence numbers. See
+    /// [`check_local_edit`](ListOpLog::check_local_edit).
+    pub fn try_add_operations(&mut self, agent: AgentId, ops: &[TextOperation]) -> Result<LV, SeqCollision> {
+        self.check_local_edit(agent, ops.iter().map(|op| op.len()).sum())?;
+        Ok(self.add_operations(agent, ops))
+    }
+
+    /// Like [`add_operations_at`](ListOpLog::add_operations_at), but returns an error (and leaves
+    /// the oplog unchanged) instead of panicking if the edit could reuse sequence numbers.
+    pub fn try_add_operations_at(&mut self, agent: AgentId, parents: &[LV], ops: &[TextOperation]) -> Result<LV, SeqCollision> {
+        self.check_local_edit(agent, ops.iter().map(|op| op.len()).sum())?;
+        Ok(self.add_operations_at(agent, parents, ops))
+    }
+
+    /// Compare an operation from a remote peer with the local operations starting at `start`,
+    /// which have the same IDs. Returns the first local version which doesn't match.
+    ///
+    /// Deleted content isn't compared, since its often not stored.
+    pub(crate) fn find_conflicting_op(&self, start: LV, mut op: TextOperation) -> Option<LV> {
+        let mut lv = start;
+        for local in self.iter_ops_range((start..start + op.len() as LV).into()) {
+            let rest = (op.len() > local.len()).then(|| op.truncate(local.len()));
+
+            if local.kind != op.kind || local.loc.span != op.loc.span
+                || (local.len() > 1 && local.loc.fwd != op.loc.fwd) {
+                return Some(lv);
+            }
+            if let (ListOpKind::Ins, Some(a), Some(b)) = (local.kind, &local.content, &op.content) {
+                if let Some(offset) = a.chars().zip(b.chars()).position(|(a, b)| a != b) {
+                    return Some(lv + offset as LV);
+                }
+            }
+
+            lv += local.len() as LV;
+            match rest {
+                Some(rest) => op = rest,
+                None => break,
+            }
+        }
+        None
+    }
+
+    /// Check that the known operations in `span` have the given parents. Returns the first local
+    /// version whose parents don't match.
+    pub(crate) fn find_conflicting_parents(&self, parents: &[LV], span: DTRange) -> Option<LV> {
+        self.cg.graph.iter_range(span).find(|e| {
+            if e.span.start == span.start {
+                e.parents.as_ref() != parents
+            } else {
+                e.parents.as_ref() != [e.span.start - 1]
+            }
+        }).map(|e| e.span.start)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+    use crate::list::encoding::{EncodeOptions, ParseError};
+    use crate::list::operation::TextOperation;
+    use super::{SeqCollision, SeqReservation};
+
+    #[test]
+    fn reservations() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.resume_agent("seph", 0).unwrap();
+        let reservation = oplog.reserve_seqs(seph, 5);
+        assert_eq!(reservation.seqs, (0..5).into());
+        oplog.add_insert(seph, 0, "hi");
+        assert_eq!(oplog.next_seq_for(seph), 2);
+        assert_eq!(reservation.remaining(&oplog), 3);
+        assert_eq!(reservation.check(&oplog), Ok(()));
+        oplog.add_insert(seph, 2, "!!!");
+        assert_eq!(reservation.remaining(&oplog), 0);
+
+        let reservation = oplog.reserve_seqs(seph, 6);
+        assert_eq!(reservation.seqs, (5..11).into());
+        oplog.add_insert(seph, 2, " there");
+        assert_eq!(reservation.check(&oplog), Ok(()));
+        assert_eq!(SeqReservation { agent: seph, seqs: (0..5).into() }.check(&oplog), Err(SeqCollision { agent: seph, seq: 5 }));
+
+        // After a crash, the saved oplog is missing some of seph's edits.
+        let mut restarted = ListOpLog::new();
+        let seph = restarted.get_or_create_agent_id("seph");
+        restarted.add_insert(seph, 0, "hi");
+        assert_eq!(restarted.resume_agent("seph", 5), Err(SeqCollision { agent: seph, seq: 2 }));
+        assert!(oplog.resume_agent("seph", 5).is_ok());
+    }
+
+    #[test]
+    fn edits_past_reservation_fail() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.reserve_seqs(seph, 2);
+        oplog.try_add_operations(seph, &[TextOperation::new_insert(0, "hi")]).unwrap();
+        let err = SeqCollision { agent: seph, seq: 2 };
+        assert_eq!(oplog.try_add_operations(seph, &[TextOperation::new_insert(0, "x")]), Err(err));
+        assert_eq!(oplog.try_add_operations_at(seph, &[], &[TextOperation::new_insert(0, "x")]), Err(err));
+        assert_eq!(oplog.len(), 2);
+
+        // Reserving more fixes it.
+        oplog.reserve_seqs(seph, 1);
+        oplog.try_add_operations(seph, &[TextOperation::new_insert(0, "x")]).unwrap();
+        assert_eq!(oplog.checkout_tip().content(), "xhi");
+    }
+
+    #[test]
+    fn collided_agents_cant_edit() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi");
+        assert!(oplog.resume_agent("seph", 5).is_err());
+        // Reserving more sequence numbers doesn't help, since seph might have used them.
+        oplog.reserve_seqs(seph, 10);
+        assert_eq!(oplog.check_local_edit(seph, 1), Err(SeqCollision { agent: seph, seq: 2 }));
+        assert_eq!(oplog.try_add_operations(seph, &[TextOperation::new_insert(0, "x")]), Err(SeqCollision { agent: seph, seq: 2 }));
+        assert_eq!(oplog.checkout_tip().content(), "hi");
+    }
+
+    #[test]
+    fn duplicate_ids_are_detected() {
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.add_insert(seph, 0, "abc");
+
+        // Same operations, so merging is fine.
+        let mut b = ListOpLog::new();
+        b.decode_and_add(&a.encode(&EncodeOptions::default())).unwrap();
+        let seph_b = b.get_agent_id("seph").unwrap();
+        b.add_insert(seph_b, 3, "d");
+        a.decode_and_add(&b.encode(&EncodeOptions::default())).unwrap();
+        assert_eq!(a.checkout_tip().content(), "abcd");
+
+        // Different content at the same IDs.
+        let mut c = ListOpLog::new();
+        let seph_c = c.get_or_create_agent_id("seph");
+        c.add_insert(seph_c, 0, "abX");
+        let mike = c.get_or_create_agent_id("mike");
+        c.add_insert(mike, 0, "!");
+        let data = c.encode(&EncodeOptions::default());
+        assert_eq!(a.decode_and_add(&data), Err(ParseError::DuplicateId { agent: seph, seq: 2 }));
+        assert_eq!(a.checkout_tip().content(), "abcd");
+
+        // Different positions.
+        let mut d = ListOpLog::new();
+        let seph_d = d.get_or_create_agent_id("seph");
+        d.add_insert(seph_d, 0, "ab");
+        d.add_insert(seph_d, 0, "c");
+        assert_eq!(a.decode_and_add(&d.encode(&EncodeOptions::default())), Err(ParseError::DuplicateId { agent: seph, seq: 2 }));
+
+        // Same operations with different parents.
+        let mut e = ListOpLog::new();
+        let seph_e = e.get_or_create_agent_id("seph");
+        e.add_insert(seph_e, 0, "ab");
+        e.add_insert_at(seph_e, &[], 0, "c");
+        let data = e.encode(&EncodeOptions::default());
+        let mut f = ListOpLog::new();
+        let seph_f = f.get_or_create_agent_id("seph");
+        f.add_insert(seph_f, 0, "ab");
+        f.add_insert(seph_f, 0, "c");
+        assert_eq!(f.decode_and_add(&data), Err(ParseError::DuplicateId { agent: seph_f, seq: 2 }));
+    }
+}
@@ -0,0 +1,258 @@
+//! Statistics about a document's history.
+//!
+//! These are intended for dashboards and for understanding how a document is being edited - who
+//! is editing it, how often users edit concurrently, and how the document has grown over time.
+//! Everything here is computed on demand from the oplog, so its O(n) with the size of the history.
+
+use smartstring::alias::String as SmartString;
+use rle::HasLength;
+use crate::{DTRange, lv_to_usize, LV};
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::listmerge::rewind::RewindTracker;
+use crate::rle::KVPair;
+
+/// The operations made by one agent. See [`HistoryStats`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AgentStats {
+    pub name: SmartString,
+    /// The number of characters inserted by the agent.
+    pub inserted: usize,
+    /// The number of characters deleted by the agent.
+    pub deleted: usize,
+}
+
+impl AgentStats {
+    /// The total number of operations made by the agent.
+    pub fn ops(&self) -> usize {
+        self.inserted + self.deleted
+    }
+}
+
+/// The result of [`ListOpLog::history_stats`].
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct HistoryStats {
+    /// Every agent which has made operations, in order of their names.
+    pub agents: Vec<AgentStats>,
+
+    /// The total number of characters inserted.
+    pub inserted: usize,
+    /// The total number of characters deleted.
+    pub deleted: usize,
+
+    /// The number of operations which merged concurrent branches of history (ie, which have more
+    /// than one parent).
+    pub merges: usize,
+
+    /// The number of operations which are concurrent with at least one other operation. The rest
+    /// of the operations happened in a single linear history, where everyone had seen every
+    /// previous change.
+    pub concurrent_ops: usize,
+
+    /// The ranges of operations (in local version order) which were made while history had
+    /// diverged, largest first. Each window ends when every branch has been merged back together.
+    pub conflict_windows: Vec<DTRange>,
+}
+
+impl HistoryStats {
+    /// The total number of operations.
+    pub fn ops(&self) -> usize {
+        self.inserted + self.deleted
+    }
+
+    /// The number of inserted characters for every deleted character. Returns 0 if nothing has
+    /// been inserted (including for empty documents), and infinity if characters have been
+    /// inserted but nothing has been deleted.
+    pub fn insert_delete_ratio(&self) -> f64 {
+        if self.inserted == 0 { 0.0 } else { self.inserted as f64 / self.deleted as f64 }
+    }
+
+    /// The fraction of operations which are concurrent with some other operation. This is 0 for
+    /// documents which have only ever been edited by one user at a time, and approaches 1 for
+    /// documents with lots of concurrent editing.
+    pub fn concurrency_factor(&self) -> f64 {
+        if self.ops() == 0 { 0.0 } else { self.concurrent_ops as f64 / self.ops() as f64 }
+    }
+}
+
+/// A sample of the document's size. See [`ListOpLog::growth_samples`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct GrowthSample {
+    /// The number of operations (in local version order) which have been applied.
+    pub ops: usize,
+    /// The length of the document (in characters) once those operations have been applied.
+    pub len: usize,
+    /// The timestamp of the last of those operations, if it has one. See
+    /// [`ListOpLog::set_op_metadata`].
+    pub timestamp: Option<u64>,
+}
+
+impl ListOpLog {
+    /// Calculate statistics about the document's history. See [`HistoryStats`].
+    pub fn history_stats(&self) -> HistoryStats {
+        let aa = &self.cg.agent_assignment;
+        let mut stats = HistoryStats::default();
+
+        let mut agents: Vec<AgentStats> = aa.client_data.iter().map(|c| AgentStats {
+            name: c.name.clone(),
+            inserted: 0,
+            deleted: 0,
+        }).collect();
+
+        for KVPair(lv, op) in self.operations.iter() {
+            for KVPair(_, span) in aa.client_with_lv.iter_range_ctx(*lv..*lv + op.len() as LV, &()) {
+                let agent = &mut agents[span.agent as usize];
+                match op.kind {
+                    ListOpKind::Ins => agent.inserted += span.len(),
+                    ListOpKind::Del => agent.deleted += span.len(),
+                }
+            }
+        }
+
+        agents.retain(|a| a.ops() > 0);
+        agents.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        stats.inserted = agents.iter().map(|a| a.inserted).sum();
+        stats.deleted = agents.iter().map(|a| a.deleted).sum();
+        stats.agents = agents;
+
+        stats.merges = self.cg.graph.iter().filter(|e| e.parents.len() > 1).count();
+
+        let mut window: Option<DTRange> = None;
+        for (span, linear_end) in self.cg.graph.linear_history() {
+            if linear_end > span.start {
+                stats.conflict_windows.extend(window.take());
+            }
+            if linear_end < span.end {
+                match window.as_mut() {
+                    Some(w) => w.end = span.end,
+                    None => window = Some((linear_end..span.end).into()),
+                }
+            }
+        }
+        stats.conflict_windows.extend(window);
+        stats.conflict_windows.sort_by_key(|r| std::cmp::Reverse(r.len()));
+        stats.concurrent_ops = stats.conflict_windows.iter().map(|r| r.len()).sum();
+
+        stats
+    }
+
+    /// Sample the length of the document after every `every` operations (in local version order),
+    /// and after the last operation. This is useful for charting how the document has grown over
+    /// time.
+    ///
+    /// When the history contains concurrent edits, some of the sampled versions might never have
+    /// existed on any peer - but the document's length at each version is still accurate.
+    ///
+    /// Panics if `every` is 0.
+    pub fn growth_samples(&self, every: usize) -> Vec<GrowthSample> {
+        assert!(every > 0);
+        let mut result = vec![];
+        let mut tracker = RewindTracker::new();
+        tracker.move_to(self, self.cg.version.as_ref());
+
+        // Concurrent deletes can delete the same item twice, so we need to track which items have
+        // actually been deleted.
+        let mut deleted = vec![false; lv_to_usize(self.len())];
+        let mut len = 0;
+
+        for KVPair(start, op) in self.operations.iter() {
+            for lv in *start..*start + op.len() as LV {
+                match op.kind {
+                    ListOpKind::Ins => len += 1,
+                    ListOpKind::Del => {
+                        let item = lv_to_usize(tracker.deleted_item(lv));
+                        if !deleted[item] {
+                            deleted[item] = true;
+                            len -= 1;
+                        }
+                    }
+                }
+
+                let ops = lv_to_usize(lv) + 1;
+                if ops % every == 0 || ops == lv_to_usize(self.len()) {
+                    let timestamp = self.op_metadata(lv).and_then(|m| m.timestamp);
+                    result.push(GrowthSample { ops, len, timestamp });
+                }
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::LV;
+    use crate::list::{ListOpLog, OpMetadata};
+    use crate::list::gen_random::gen_oplog;
+    use super::GrowthSample;
+
+    #[test]
+    fn history_stats() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hello"); // 0..5
+        // Two concurrent edits, then merged together.
+        oplog.add_insert_at(seph, &[4], 5, " world"); // 5..11
+        oplog.add_insert_at(mike, &[4], 0, "oh "); // 11..14
+        oplog.add_delete_at(seph, &[10, 13], 0..3); // 14..17
+        oplog.add_insert(mike, 0, "!"); // 17
+
+        let stats = oplog.history_stats();
+        assert_eq!(stats.agents.len(), 2);
+        assert_eq!(stats.agents[0].name, "mike");
+        assert_eq!((stats.agents[0].inserted, stats.agents[0].deleted), (4, 0));
+        assert_eq!((stats.agents[1].inserted, stats.agents[1].deleted), (11, 3));
+        assert_eq!((stats.inserted, stats.deleted), (15, 3));
+        assert_eq!(stats.merges, 1);
+        assert_eq!(stats.conflict_windows, vec![(5..14).into()]);
+        assert_eq!(stats.concurrent_ops, 9);
+        assert_eq!(stats.concurrency_factor(), 0.5);
+        assert_eq!(stats.insert_delete_ratio(), 5.0);
+
+        oplog.set_op_metadata((0..5).into(), OpMetadata::at_time(1000));
+        let samples = oplog.growth_samples(5);
+        assert_eq!(samples, vec![
+            GrowthSample { ops: 5, len: 5, timestamp: Some(1000) },
+            GrowthSample { ops: 10, len: 10, timestamp: None },
+            GrowthSample { ops: 15, len: 13, timestamp: None },
+            GrowthSample { ops: 18, len: 12, timestamp: None },
+        ]);
+        assert_eq!(samples.last().unwrap().len, oplog.checkout_tip().len());
+    }
+
+    #[test]
+    fn empty_history_stats() {
+        let stats = ListOpLog::new().history_stats();
+        assert_eq!(stats.ops(), 0);
+        assert_eq!(stats.insert_delete_ratio(), 0.0);
+        assert_eq!(stats.concurrency_factor(), 0.0);
+
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi");
+        assert_eq!(oplog.history_stats().insert_delete_ratio(), f64::INFINITY);
+    }
+
+    #[test]
+    fn concurrent_ops_match_brute_force() {
+        for seed in 0..10 {
+            let oplog = gen_oplog(seed, 8, false, true);
+            let graph = &oplog.cg.graph;
+            let is_concurrent = |v: LV| (0..oplog.len()).any(|w| {
+                !graph.frontier_contains_version(&[w], v) && !graph.frontier_contains_version(&[v], w)
+            });
+
+            let stats = oplog.history_stats();
+            let expected = (0..oplog.len()).filter(|v| is_concurrent(*v)).count();
+            assert_eq!(stats.concurrent_ops, expected);
+            for w in stats.conflict_windows.iter() {
+                assert!((w.start..w.end).all(is_concurrent));
+            }
+
+            let samples = oplog.growth_samples(7);
+            assert_eq!(samples.last().unwrap().len, oplog.checkout_tip().len());
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! Anchors are positions in a branch which move with the text around them.
+//!
+//! Comment threads, bookmarks and the like need to stay attached to the same text as the document
+//! changes. Plain positions go stale as soon as an edit is made before them. Anchors are stored in
+//! the branch, and updated whenever the branch's content changes - including local edits, merges
+//! and rewinds.
+//!
+//! Each anchor is attached to a character - either the one before it or the one after it,
+//! depending on its [`AnchorBias`]. The anchor stays with that character through concurrent
+//! edits, even after the character is deleted. (Its then placed where the character would have
+//! been.) Anchors are bound to their character the first time the branch is changed through an
+//! oplog after the anchor is added, which replays the document's history once.
+//!
+//! To store an anchor (or send it to another peer), use [`save_anchor`](ListBranch::save_anchor),
+//! which names the character by the ID of the operation which inserted it.
+//! [`restore_anchor`](ListBranch::restore_anchor) recreates the anchor on any replica of the
+//! same document, at any version.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use crate::LV;
+use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionOwned;
+use crate::list::{ListBranch, ListOpLog, PositionMap};
+
+/// A handle to an anchor in a branch. See [`ListBranch::add_anchor`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct AnchorId(usize);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AnchorBias {
+    /// The anchor is attached to the character before it. Text inserted at the anchor's position
+    /// goes after the anchor.
+    Left,
+    /// The anchor is attached to the character after it. Text inserted at the anchor's position
+    /// goes before the anchor.
+    Right,
+}
+
+/// An anchor named by the character its attached to. See [`ListBranch::save_anchor`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SavedAnchor {
+    /// The ID of the insert which created the character the anchor is attached to. None if the
+    /// anchor is at the start (for left biased anchors) or end (for right biased anchors) of the
+    /// document.
+    pub item: Option<RemoteVersionOwned>,
+    pub bias: AnchorBias,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum AnchorItem {
+    /// The anchor hasn't been bound to its character yet.
+    Unbound,
+    /// The anchor is at the start or end of the document.
+    Edge,
+    /// The LV of the character the anchor is attached to, and whether its visible in the branch.
+    Char(LV, bool),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Anchor {
+    pos: usize,
+    bias: AnchorBias,
+    item: AnchorItem,
+    /// Set when the position might not match the anchor's character anymore. This happens when
+    /// text is inserted right where a deleted character used to be, since the inserted text could
+    /// be on either side of the deleted character.
+    stale: bool,
+}
+
+/// The anchors in a branch, and their current positions.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct AnchorSet {
+    anchors: BTreeMap<AnchorId, Anchor>,
+    next_id: usize,
+    /// Set if any anchors are unbound.
+    unbound: bool,
+    /// Set if any anchors are stale.
+    stale: bool,
+}
+
+impl AnchorSet {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    pub(crate) fn transform_insert(&mut self, at: usize, len: usize) {
+        for a in self.anchors.values_mut() {
+            if at == a.pos && matches!(a.item, AnchorItem::Char(_, false)) {
+                a.stale = true;
+                self.stale = true;
+            }
+            if at < a.pos || (at == a.pos && a.bias == AnchorBias::Right) {
+                a.pos += len;
+            }
+        }
+    }
+
+    pub(crate) fn transform_remove(&mut self, range: Range<usize>) {
+        for a in self.anchors.values_mut() {
+            if let AnchorItem::Char(_, visible) = &mut a.item {
+                let attached = match a.bias {
+                    AnchorBias::Left => range.start < a.pos && a.pos <= range.end,
+                    AnchorBias::Right => range.start <= a.pos && a.pos < range.end,
+                };
+                if attached { *visible = false; }
+            }
+
+            if range.end <= a.pos { a.pos -= range.len(); }
+            else if range.start < a.pos { a.pos = range.start; }
+        }
+    }
+}
+
+impl ListBranch {
+    /// Add an anchor at `pos` in the branch's content. The anchor's position is updated as the
+    /// branch changes, including through local edits, merges and rewinds.
+    ///
+    /// The anchor is attached to the character before or after it (depending on `bias`), and
+    /// stays with that character through concurrent edits - even after its deleted. Use
+    /// [`save_anchor`](ListBranch::save_anchor) to store it or send it to another peer.
+    pub fn add_anchor(&mut self, pos: usize, bias: AnchorBias) -> AnchorId {
+        assert!(pos <= self.content.len_chars());
+        self.anchors.unbound = true;
+        self.insert_anchor(pos, bias, AnchorItem::Unbound)
+    }
+
+    fn insert_anchor(&mut self, pos: usize, bias: AnchorBias, item: AnchorItem) -> AnchorId {
+        let id = AnchorId(self.anchors.next_id);
+        self.anchors.next_id += 1;
+        self.anchors.anchors.insert(id, Anchor { pos, bias, item, stale: false });
+        id
+    }
+
+    /// Get the current position of an anchor. Returns None if the anchor has been removed.
+    pub fn anchor_position(&self, id: AnchorId) -> Option<usize> {
+        self.anchors.anchors.get(&id).map(|a| a.pos)
+    }
+
+    /// Remove an anchor. Returns false if the anchor didn't exist.
+    pub fn remove_anchor(&mut self, id: AnchorId) -> bool {
+        self.anchors.anchors.remove(&id).is_some()
+    }
+
+    /// Iterate through the anchors in the branch, and their current positions.
+    pub fn anchors(&self) -> impl Iterator<Item = (AnchorId, usize, AnchorBias)> + '_ {
+        self.anchors.anchors.iter().map(|(id, a)| (*id, a.pos, a.bias))
+    }
+
+    /// Bind any new anchors to the characters they're attached to. This must be called before
+    /// the branch's content is changed by operations from the oplog, while any composition is
+    /// lifted out.
+    pub(crate) fn bind_anchors(&mut self, oplog: &ListOpLog) {
+        if !self.anchors.unbound { return; }
+        self.anchors.unbound = false;
+        let map = PositionMap::new_at_version(oplog, self.version.as_ref());
+        for a in self.anchors.anchors.values_mut() {
+            if a.item != AnchorItem::Unbound { continue; }
+            let item = match a.bias {
+                AnchorBias::Left => a.pos.checked_sub(1).and_then(|p| map.id_at(p)),
+                AnchorBias::Right => map.id_at(a.pos),
+            };
+            a.item = match item {
+                Some(lv) => AnchorItem::Char(lv, true),
+                None => AnchorItem::Edge,
+            };
+        }
+    }
+
+    /// Move any stale anchors back next to their characters. This must be called after the
+    /// branch's content and version are updated, while any composition is lifted out.
+    pub(crate) fn fix_stale_anchors(&mut self, oplog: &ListOpLog) {
+        if !self.anchors.stale { return; }
+        self.anchors.stale = false;
+        for a in self.anchors.anchors.values_mut() {
+            if !a.stale { continue; }
+            a.stale = false;
+            let AnchorItem::Char(lv, _) = a.item else { continue; };
+            if let Some((pos, visible)) = oplog.item_boundary(lv, self.version.as_ref(), a.bias == AnchorBias::Left) {
+                a.pos = pos;
+                a.item = AnchorItem::Char(lv, visible);
+            }
+        }
+    }
+
+    /// Name an anchor by the character its attached to, so it can be stored or sent to other
+    /// peers. The oplog must be the one this branch was checked out from. Returns None if the
+    /// anchor has been removed.
+    ///
+    /// If the anchor hasn't been bound to its character yet, this needs to replay the document's
+    /// history, so its about as expensive as a checkout.
+    pub fn save_anchor(&self, oplog: &ListOpLog, id: AnchorId) -> Option<SavedAnchor> {
+        let a = self.anchors.anchors.get(&id)?;
+        let item = match a.item {
+            AnchorItem::Char(lv, _) => Some(lv),
+            AnchorItem::Edge => None,
+            AnchorItem::Unbound => {
+                let map = PositionMap::new_at_version(oplog, self.version.as_ref());
+                match a.bias {
+                    AnchorBias::Left => a.pos.checked_sub(1).and_then(|p| map.id_at(p)),
+                    AnchorBias::Right => map.id_at(a.pos),
+                }
+            }
+        };
+        let item = item.map(|lv| oplog.cg.agent_assignment.local_to_remote_version(lv).into());
+        Some(SavedAnchor { item, bias: a.bias })
+    }
+
+    /// Add an anchor from a [`SavedAnchor`]. If the anchor's character has been deleted, the
+    /// anchor is placed where the character would have been.
+    ///
+    /// The anchor's character doesn't need to be in the branch's version, but it must be known to
+    /// the oplog. Returns None if the character isn't known.
+    pub fn restore_anchor(&mut self, oplog: &ListOpLog, anchor: &SavedAnchor) -> Option<AnchorId> {
+        let (pos, item) = match (&anchor.item, anchor.bias) {
+            (None, AnchorBias::Left) => (0, AnchorItem::Edge),
+            (None, AnchorBias::Right) => (self.content.len_chars(), AnchorItem::Edge),
+            (Some(rv), bias) => {
+                let lv = oplog.cg.agent_assignment.try_remote_to_local_version(rv.into()).ok()?;
+                let (pos, visible) = oplog.item_boundary(lv, self.version.as_ref(), bias == AnchorBias::Left)?;
+                (pos, AnchorItem::Char(lv, visible))
+            }
+        };
+        Some(self.insert_anchor(pos, anchor.bias, item))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListCRDT};
+    use super::{AnchorBias, SavedAnchor};
+
+    #[test]
+    fn anchors_follow_edits() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hello world");
+
+        let mut remote = doc.oplog.clone();
+        let mike = remote.get_or_create_agent_id("mike");
+
+        let word = doc.branch.add_anchor(6, AnchorBias::Right);
+        let end = doc.branch.add_anchor(5, AnchorBias::Left);
+        let gone = doc.branch.add_anchor(4, AnchorBias::Right);
+
+        doc.insert(seph, 0, ">> ");
+        doc.insert(seph, 8, "!"); // At the left biased anchor.
+        assert_eq!(doc.branch.anchor_position(word), Some(10));
+        assert_eq!(doc.branch.anchor_position(end), Some(8));
+
+        // Concurrent changes are merged in.
+        remote.add_insert(mike, 6, "big ");
+        remote.add_delete_without_content(mike, 4..5);
+        doc.oplog.add_missing_operations_from(&remote);
+        doc.branch.merge(&doc.oplog, doc.oplog.cg.version.as_ref());
+        assert_eq!(doc.branch.content().to_string(), ">> hell! big world");
+        assert_eq!(doc.branch.anchor_position(word), Some(13));
+        assert_eq!(doc.branch.anchor_position(end), Some(7));
+        assert_eq!(doc.branch.anchor_position(gone), Some(7));
+
+        // Saved anchors can be restored on other branches.
+        let saved = doc.branch.save_anchor(&doc.oplog, word).unwrap();
+        let mut old = ListBranch::new_at_local_version(&doc.oplog, &[10]);
+        let id = old.restore_anchor(&doc.oplog, &saved).unwrap();
+        assert_eq!(old.anchor_position(id), Some(6));
+        let saved = doc.branch.save_anchor(&doc.oplog, gone).unwrap();
+        // The character the anchor was attached to was deleted, but the anchor still names it.
+        assert_eq!(saved, SavedAnchor { item: Some(("seph", 4).into()), bias: AnchorBias::Right });
+        let id = old.restore_anchor(&doc.oplog, &saved).unwrap();
+        assert_eq!(old.anchor_position(id), Some(4));
+        let id = old.restore_anchor(&doc.oplog, &SavedAnchor { item: Some(("seph", 4).into()), bias: AnchorBias::Left }).unwrap();
+        assert_eq!(old.anchor_position(id), Some(5));
+        assert_eq!(old.restore_anchor(&doc.oplog, &SavedAnchor { item: Some(("fred", 0).into()), bias: AnchorBias::Left }), None);
+        assert_eq!(old.restore_anchor(&doc.oplog, &SavedAnchor { item: Some(("seph", 100).into()), bias: AnchorBias::Left }), None);
+
+        // And on other replicas, which have their own local versions.
+        let mut other = ListCRDT::new();
+        other.get_or_create_agent_id("fred");
+        other.merge_data_and_ff(&doc.oplog.encode(&Default::default())).unwrap();
+        let saved = doc.branch.save_anchor(&doc.oplog, word).unwrap();
+        let id = other.branch.restore_anchor(&other.oplog, &saved).unwrap();
+        assert_eq!(other.branch.anchor_position(id), Some(13));
+
+        // Anchors move back with the branch when its rewound.
+        doc.branch.rewind_to(&doc.oplog, &[10]);
+        assert_eq!(doc.branch.anchor_position(word), Some(6));
+
+        assert!(doc.branch.remove_anchor(word));
+        assert!(!doc.branch.remove_anchor(word));
+        assert_eq!(doc.branch.anchors().count(), 2);
+    }
+
+    #[test]
+    fn anchors_stay_with_deleted_characters() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "abcd");
+        let mut remote = doc.oplog.clone();
+        let mike = remote.get_or_create_agent_id("mike");
+
+        // Attached to the 'c'.
+        let before_c = doc.branch.add_anchor(2, AnchorBias::Right);
+        let after_c = doc.branch.add_anchor(3, AnchorBias::Left);
+        doc.delete(seph, 2..3);
+        assert_eq!(doc.branch.anchor_position(before_c), Some(2));
+
+        // Concurrently, text is inserted after the 'c' and before the 'b'. The anchors stay on
+        // either side of where the 'c' was.
+        remote.add_insert(mike, 3, "X");
+        remote.add_insert(mike, 1, "Y");
+        doc.oplog.add_missing_operations_from(&remote);
+        doc.branch.merge(&doc.oplog, doc.oplog.cg.version.as_ref());
+        assert_eq!(doc.branch.content().to_string(), "aYbXd");
+        assert_eq!(doc.branch.anchor_position(before_c), Some(3));
+        assert_eq!(doc.branch.anchor_position(after_c), Some(3));
+
+        // Rewinding brings the 'c' back.
+        doc.branch.rewind_to(&doc.oplog, &[3]);
+        assert_eq!(doc.branch.content().to_string(), "abcd");
+        assert_eq!(doc.branch.anchor_position(before_c), Some(2));
+        assert_eq!(doc.branch.anchor_position(after_c), Some(3));
+    }
+}
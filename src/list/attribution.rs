@@ -0,0 +1,84 @@
+//! Blame / attribution support. This answers the question "who wrote this character?" for every
+//! character in a document at some version.
+
+use rle::HasLength;
+use crate::{AgentId, DTRange, LV};
+use crate::list::ListOpLog;
+use crate::listmerge::rewind::RewindTracker;
+
+impl ListOpLog {
+    /// Figure out which agent inserted each character in the document at the named version.
+    ///
+    /// The result is a list of `(char_range, agent, lv_range)` triples in document order, covering
+    /// the whole document. `char_range` is the range of (unicode character) positions in the
+    /// document at `version`, and `lv_range` is the range of local versions of the corresponding
+    /// insert operations. Both ranges always have the same length.
+    ///
+    /// This needs to replay the entire history of the document, so it's about as expensive as
+    /// calling [`checkout`](ListOpLog::checkout) from scratch.
+    pub fn attribution_at(&self, version: &[LV]) -> Vec<(DTRange, AgentId, DTRange)> {
+        let version = self.cg.graph.find_dominators(version);
+        let mut tracker = RewindTracker::new();
+        tracker.move_to(self, version.as_ref());
+
+        let mut result: Vec<(DTRange, AgentId, DTRange)> = vec![];
+        let mut pos = 0;
+        for lv_range in tracker.iter_visible() {
+            // The items in the tracker don't line up with agent spans, so each item might have
+            // been inserted by a few different agents.
+            for span in self.cg.agent_assignment.client_with_lv.iter_range(lv_range) {
+                let lv = DTRange::from(span.0..span.0 + span.1.len() as LV);
+                let chars: DTRange = (pos as LV..(pos + lv.len()) as LV).into();
+                pos += lv.len();
+
+                match result.last_mut() {
+                    Some((last_chars, last_agent, last_lv))
+                        if *last_agent == span.1.agent && last_lv.end == lv.start => {
+                        last_chars.end = chars.end;
+                        last_lv.end = lv.end;
+                    }
+                    _ => result.push((chars, span.1.agent, lv)),
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Get the attribution of every character in the document at the current version. See
+    /// [`attribution_at`](ListOpLog::attribution_at) for details.
+    pub fn attribution(&self) -> Vec<(DTRange, AgentId, DTRange)> {
+        self.attribution_at(self.cg.version.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn attribution_smoke() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+
+        oplog.add_insert(seph, 0, "hello"); // 0..5
+        let v = oplog.add_insert(mike, 5, " world"); // 5..11
+        oplog.add_delete_without_content(seph, 0..1); // 11
+        oplog.add_insert(seph, 0, "H"); // 12
+        // Concurrent with the changes above.
+        oplog.add_insert_at(mike, &[v], 11, "!"); // 13
+
+        assert_eq!(oplog.attribution(), vec![
+            ((0..1).into(), seph, (12..13).into()),
+            ((1..5).into(), seph, (1..5).into()),
+            ((5..11).into(), mike, (5..11).into()),
+            ((11..12).into(), mike, (13..14).into()),
+        ]);
+
+        assert_eq!(oplog.attribution_at(&[4]), vec![
+            ((0..5).into(), seph, (0..5).into()),
+        ]);
+        assert_eq!(oplog.attribution_at(&[]), vec![]);
+    }
+}
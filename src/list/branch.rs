@@ -1,13 +1,18 @@
+use std::fmt::{Display, Formatter};
 use std::ops::Range;
 use jumprope::{JumpRope, JumpRopeBuf};
+use rle::HasLength;
 use crate::list::{ListBranch, ListOpLog};
+use crate::list::history_hash::content_hash_chars;
 use smartstring::SmartString;
 use crate::list::list::{apply_local_operations};
 use crate::list::operation::ListOpKind::*;
 use crate::list::operation::{TextOperation, ListOpKind};
 use crate::dtrange::DTRange;
-use crate::{AgentId, Frontier, LV};
+use crate::{AgentId, Frontier, lv_to_usize, LV};
 use crate::causalgraph::agent_assignment::remote_ids::RemoteFrontier;
+use crate::listmerge::rewind::{RewindCache, RewindTracker};
+use crate::unicount::{chars_to_graphemes, count_chars, count_graphemes, graphemes_to_chars};
 
 impl ListBranch {
     /// Create a new (empty) branch at the start of history. The branch will be an empty list.
@@ -15,6 +20,12 @@ impl ListBranch {
         Self {
             version: Frontier::root(),
             content: JumpRopeBuf::new(),
+            rewind: RewindCache::default(),
+            merge_context: Default::default(),
+            composition: None,
+            lines: Default::default(),
+            events: Default::default(),
+            anchors: Default::default(),
         }
     }
 
@@ -62,15 +73,90 @@ impl ListBranch {
         self.content.is_empty()
     }
 
+    /// Returns the document's length in unicode characters. This is the same as
+    /// [`len`](ListBranch::len).
+    pub fn len_chars(&self) -> usize {
+        self.content.len_chars()
+    }
+
+    /// Returns the document's length in bytes, when encoded as UTF-8.
+    pub fn len_bytes(&self) -> usize {
+        self.content.len_bytes()
+    }
+
+    /// Iterate over the characters in the document.
+    ///
+    /// Any buffered edits are flushed into the rope first. The iterator copies the rope's chunks
+    /// out one at a time, so the rope isn't borrowed between calls to `next`.
+    pub fn chars(&self) -> BranchChars<'_> {
+        BranchChars { content: &self.content, next_pos: 0, len: self.len(), chunk: String::new(), chunk_offset: 0 }
+    }
+
+    /// Hash the document's content with [`content_hash`](crate::list::content_hash). Replicas
+    /// which have converged have the same hash, so this is a cheap way to compare documents on
+    /// different machines. This is not a cryptographic hash.
+    pub fn content_hash(&self) -> u64 {
+        content_hash_chars(self.content.borrow().chars())
+    }
+
+    /// Get the content in the specified range of characters. Any buffered edits are flushed into
+    /// the rope first, and the content is copied out.
+    pub fn slice(&self, range: Range<usize>) -> String {
+        assert!(range.start <= range.end && range.end <= self.len(), "Slice range out of bounds");
+        self.content.borrow().slice_substrings(range).collect()
+    }
+
+    /// Insert into the branch's content. All changes to the content go through this method (and
+    /// content_remove) so the line index and events stay up to date. `len` is the length of
+    /// `content` in characters, which callers always know. `version` names the operation which
+    /// made the change, if any.
+    pub(crate) fn content_insert(&mut self, pos: usize, content: &str, len: usize, version: Option<LV>) {
+        debug_assert_eq!(len, count_chars(content));
+        self.content.insert(pos, content);
+        if self.has_observers() {
+            self.after_insert(pos, content, len, version);
+        }
+    }
+
+    pub(crate) fn content_remove(&mut self, range: Range<usize>, version: Option<LV>) {
+        self.content.remove(range.clone());
+        if self.has_observers() {
+            self.after_remove(range, version);
+        }
+    }
+
+    /// Returns true if the line index, events or anchors need to hear about content changes.
+    /// Usually nothing does, and merging can skip the hooks.
+    #[inline]
+    fn has_observers(&self) -> bool {
+        self.lines.0.is_some() || self.events.0.is_some() || !self.anchors.is_empty()
+    }
+
+    fn after_insert(&mut self, pos: usize, content: &str, len: usize, version: Option<LV>) {
+        if let Some(lines) = self.lines.0.as_mut() {
+            lines.insert(pos, content);
+        }
+        self.anchors.transform_insert(pos, len);
+        self.events.push_insert(pos, len, content, version);
+    }
+
+    fn after_remove(&mut self, range: Range<usize>, version: Option<LV>) {
+        if let Some(lines) = self.lines.0.as_mut() {
+            lines.remove(range.clone());
+        }
+        self.anchors.transform_remove(range.clone());
+        self.events.push_delete(range, version);
+    }
+
     /// Apply a single operation. This method does not update the version.
-    fn apply_internal(&mut self, kind: ListOpKind, pos: DTRange, content: Option<&str>) {
+    fn apply_internal(&mut self, kind: ListOpKind, pos: DTRange, content: Option<&str>, version: Option<LV>) {
         match kind {
             Ins => {
-                self.content.insert(pos.start, content.unwrap());
+                self.content_insert(lv_to_usize(pos.start), content.unwrap(), pos.len(), version);
             }
 
             Del => {
-                self.content.remove(pos.into());
+                self.content_remove(pos.into(), version);
             }
         }
     }
@@ -81,14 +167,15 @@ impl ListBranch {
         for op in ops {
             self.apply_internal(op.kind, op.loc.span, op.content
                 .as_ref()
-                .map(|s| s.as_str())
+                .map(|s| s.as_str()),
+                None
             );
         }
     }
 
     pub(crate) fn apply_range_from(&mut self, ops: &ListOpLog, range: DTRange) {
         for (op, content) in ops.iter_range_simple(range) {
-            self.apply_internal(op.1.kind, op.1.loc.span, content);
+            self.apply_internal(op.1.kind, op.1.loc.span, content, Some(op.0));
         }
     }
 
@@ -120,6 +207,47 @@ impl ListBranch {
         apply_local_operations(oplog, self, agent, &[self.make_delete_op(del_span)])
     }
 
+    /// Apply a set of replacements to the document as a single local change from `agent`. Each
+    /// edit replaces the characters in `range` with the replacement text. The changes are grouped
+    /// into an atomic transaction.
+    ///
+    /// All ranges are positions in the document *before* the patch is applied, so callers don't
+    /// need to adjust positions to account for earlier edits. The edits can be passed in any order,
+    /// but they must not overlap.
+    ///
+    /// Returns the version of the last operation, or None if the patch didn't change anything.
+    pub fn apply_local_patch(&mut self, oplog: &mut ListOpLog, agent: AgentId, patch: &[(Range<usize>, &str)]) -> Option<LV> {
+        let mut edits: Vec<&(Range<usize>, &str)> = patch.iter().collect();
+        edits.sort_unstable_by_key(|(range, _)| (range.start, range.end));
+
+        let mut ops = vec![];
+        // The number of characters added (or removed, when negative) by the edits so far.
+        let mut offset: isize = 0;
+        let mut last_end = 0;
+        for (range, text) in edits {
+            assert!(range.start >= last_end, "Patch edits overlap");
+            assert!(range.start <= range.end && range.end <= self.len(), "Patch edit out of bounds");
+            last_end = range.end;
+
+            let pos = (range.start as isize + offset) as usize;
+            if !range.is_empty() {
+                let mut op = self.make_delete_op(range.clone());
+                op.loc.span = (pos as LV..(pos + range.len()) as LV).into();
+                ops.push(op);
+            }
+            if !text.is_empty() {
+                ops.push(TextOperation::new_insert(pos, text));
+            }
+            offset += count_chars(text) as isize - range.len() as isize;
+        }
+
+        if ops.is_empty() { return None; }
+        let start = oplog.len();
+        let v = apply_local_operations(oplog, self, agent, &ops);
+        oplog.add_transaction((start..v + 1).into());
+        Some(v)
+    }
+
     #[cfg(feature = "wchar_conversion")]
     pub fn insert_at_wchar(&mut self, oplog: &mut ListOpLog, agent: AgentId, wchar_pos: usize, ins_content: &str) -> LV {
         let char_pos = self.content.borrow().wchars_to_chars(wchar_pos);
@@ -135,6 +263,128 @@ impl ListBranch {
         apply_local_operations(oplog, self, agent, &[self.make_delete_op(start_pos .. end_pos)])
     }
 
+    /// Returns the document's length in UTF-16 code units. This is the length of the document in
+    /// javascript.
+    ///
+    /// With the `wchar_conversion` feature this is O(1). Otherwise it scans the document.
+    pub fn len_utf16(&self) -> usize {
+        #[cfg(feature = "wchar_conversion")] {
+            self.content.borrow().len_wchars()
+        }
+        #[cfg(not(feature = "wchar_conversion"))] {
+            self.pos_chars_to_utf16(self.len())
+        }
+    }
+
+    /// Convert a position in UTF-16 code units (as used by javascript and most editors) into a
+    /// char position in the document. With the `wchar_conversion` feature this is O(log n), using
+    /// the index maintained by the rope. Otherwise it scans the document up to the position.
+    pub fn pos_utf16_to_chars(&self, utf16_pos: usize) -> usize {
+        #[cfg(feature = "wchar_conversion")] {
+            self.content.borrow().wchars_to_chars(utf16_pos)
+        }
+        #[cfg(not(feature = "wchar_conversion"))] {
+            let content = self.content.borrow();
+            let mut utf16 = 0;
+            for (i, c) in content.chars().enumerate() {
+                if utf16 >= utf16_pos { return i; }
+                utf16 += c.len_utf16();
+            }
+            content.len_chars()
+        }
+    }
+
+    /// Convert a char position in the document into a position in UTF-16 code units. With the
+    /// `wchar_conversion` feature this is O(log n), using the index maintained by the rope.
+    /// Otherwise it scans the document up to the position.
+    pub fn pos_chars_to_utf16(&self, char_pos: usize) -> usize {
+        #[cfg(feature = "wchar_conversion")] {
+            self.content.borrow().chars_to_wchars(char_pos)
+        }
+        #[cfg(not(feature = "wchar_conversion"))] {
+            self.content.borrow().slice_chars(0..char_pos).map(char::len_utf16).sum()
+        }
+    }
+
+    /// Returns the number of grapheme clusters (user-perceived characters) in the document.
+    ///
+    /// Grapheme clusters aren't indexed by the rope, so this (and the other grapheme methods) scan
+    /// the document content. Grapheme boundaries are found using a simplified version of the
+    /// unicode segmentation rules (UAX #29), which handles combining marks, emoji sequences and
+    /// flags but not Hangul syllables or spacing marks.
+    pub fn len_graphemes(&self) -> usize {
+        count_graphemes(self.content.borrow().chars())
+    }
+
+    /// Convert a position in grapheme clusters into a char position in the document. This is O(n)
+    /// in the position.
+    pub fn pos_graphemes_to_chars(&self, grapheme_pos: usize) -> usize {
+        graphemes_to_chars(self.content.borrow().chars(), grapheme_pos)
+    }
+
+    /// Convert a char position in the document into a position in grapheme clusters. Positions
+    /// inside a grapheme cluster are rounded down to the start of the cluster. This is O(n) in the
+    /// position.
+    pub fn pos_chars_to_graphemes(&self, char_pos: usize) -> usize {
+        assert!(char_pos <= self.len());
+        chars_to_graphemes(self.content.borrow().chars(), char_pos)
+    }
+
+    /// Move the branch to any version in the oplog - including versions in the past.
+    ///
+    /// Moving forward in time is just a [`merge`](ListBranch::merge). But the first time a branch
+    /// is moved backwards (or sideways) in time, the branch builds a tracker spanning the
+    /// document's entire history. This is about as expensive as a fresh checkout, but after that
+    /// moving the branch to any nearby version is very cheap - since the tracker can be retreated
+    /// and advanced directly. This makes it suitable for driving a history slider.
+    ///
+    /// The tracker is kept alongside the branch until
+    /// [`clear_rewind_cache`](ListBranch::clear_rewind_cache) is called. While it exists, the
+    /// branch must only be used with the same oplog. (New operations may still be added to it).
+    ///
+    /// Rewinding requires that the content of all the involved inserts is known. Like merging,
+    /// versions which only contain part of a transaction are expanded to include the whole
+    /// transaction.
+    pub fn rewind_to(&mut self, oplog: &ListOpLog, version: &[LV]) {
+        let graph = &oplog.cg.graph;
+        let version = oplog.expand_to_transactions(version);
+        let version = version.as_ref();
+
+        if self.rewind.0.is_none() && graph.frontier_contains_frontier(version, self.version.as_ref()) {
+            // Merging lifts out (and restores) any provisional composition text itself.
+            self.merge(oplog, version);
+            return;
+        }
+
+        self.lift_composition();
+        self.bind_anchors(oplog);
+        // The line index, events, anchors and composition need to know what changed.
+        let observed = self.has_observers() || self.composition.is_some();
+        let tracker = self.rewind.0.get_or_insert_with(|| Box::new(RewindTracker::new()));
+        let version = graph.find_dominators(version);
+        let mut ops = vec![];
+        let out = if observed { Some(&mut ops) } else { None };
+        tracker.move_content(oplog, &mut self.content, self.version.as_ref(), version.as_ref(), out);
+        for op in ops {
+            if let Some(c) = self.composition.as_mut() {
+                c.transform(op.kind, op.loc.span);
+            }
+            match op.kind {
+                Ins => self.after_insert(op.start(), op.content_as_str().unwrap(), op.len(), None),
+                Del => self.after_remove(op.loc.span.into(), None),
+            }
+        }
+        self.version = version;
+        self.fix_stale_anchors(oplog);
+        self.restore_composition();
+    }
+
+    /// Discard the history tracker created by [`rewind_to`](ListBranch::rewind_to), freeing its
+    /// memory.
+    pub fn clear_rewind_cache(&mut self) {
+        self.rewind = RewindCache::default();
+    }
+
     /// Consume the Branch and return the contained rope content.
     pub fn into_inner(self) -> JumpRope {
         self.content.into_inner()
@@ -147,6 +397,57 @@ impl Default for ListBranch {
     }
 }
 
+/// An iterator over the characters in a branch. See [`ListBranch::chars`].
+pub struct BranchChars<'a> {
+    content: &'a JumpRopeBuf,
+    /// The char position of the start of the next chunk.
+    next_pos: usize,
+    len: usize,
+    /// The chunk currently being iterated, copied out of the rope. (Chunks are small.)
+    chunk: String,
+    /// The byte offset of the next char in `chunk`.
+    chunk_offset: usize,
+}
+
+impl Iterator for BranchChars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        loop {
+            if let Some(c) = self.chunk[self.chunk_offset..].chars().next() {
+                self.chunk_offset += c.len_utf8();
+                return Some(c);
+            }
+            if self.next_pos >= self.len { return None; }
+
+            let rope = self.content.borrow();
+            let s = rope.slice_substrings(self.next_pos..self.len).next()?;
+            self.chunk.clear();
+            self.chunk.push_str(s);
+            self.chunk_offset = 0;
+            self.next_pos += count_chars(s);
+        }
+    }
+}
+
+impl Display for ListBranch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.content, f)
+    }
+}
+
+impl PartialEq<str> for ListBranch {
+    fn eq(&self, other: &str) -> bool {
+        self.content == other
+    }
+}
+
+impl PartialEq<&str> for ListBranch {
+    fn eq(&self, other: &&str) -> bool {
+        self.content == *other
+    }
+}
+
 impl From<ListBranch> for JumpRope {
     fn from(branch: ListBranch) -> Self {
         branch.into_inner()
@@ -177,6 +478,40 @@ mod test {
         assert_eq!(b2.content, "hi");
     }
 
+    #[test]
+    fn read_api() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        // Long enough that the rope is split into multiple chunks.
+        let content = "héllo wörld 🙂 ".repeat(200);
+        oplog.add_insert(seph, 0, &content);
+        let mut branch = oplog.checkout_tip();
+
+        assert_eq!(branch.len_chars(), content.chars().count());
+        assert_eq!(branch.len_bytes(), content.len());
+        assert!(branch.chars().eq(content.chars()));
+        assert_eq!(branch.slice(1..5), "éllo");
+        assert_eq!(branch.slice(0..branch.len()), content);
+        assert_eq!(branch.slice(3..3), "");
+        assert_eq!(branch.to_string(), content);
+        assert_eq!(branch, content.as_str());
+        assert_ne!(branch, "nope");
+        assert_eq!(ListBranch::new(), "");
+        assert_eq!(ListBranch::new().chars().next(), None);
+
+        // Buffered edits are flushed before the content is borrowed.
+        branch.insert(&mut oplog, seph, 0, "abc");
+        assert_eq!(branch.slice(0..4), "abch");
+        assert!(branch.chars().take(4).eq("abch".chars()));
+
+        // The content can be read through a shared reference, even while iterating over it.
+        let shared = &branch;
+        let pairs: Vec<String> = shared.chars().take(2).enumerate()
+            .map(|(i, _)| shared.slice(i..i + 2))
+            .collect();
+        assert_eq!(pairs, ["ab", "bc"]);
+    }
+
     #[test]
     fn branch_at_early_version_applies_cleanly() {
         // Regression.
@@ -191,4 +526,139 @@ mod test {
 
         oplog.dbg_check(true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rewind_matches_checkout() {
+        use rand::prelude::*;
+        use crate::list::ListCRDT;
+        use crate::list::old_fuzzer_tools::old_make_random_change;
+        use crate::list_fuzzer_tools::choose_2;
+
+        for seed in 0..20 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut docs = [ListCRDT::new(), ListCRDT::new(), ListCRDT::new()];
+            for doc in docs.iter_mut() {
+                for a in 0..3 {
+                    doc.get_or_create_agent_id(format!("agent {a}").as_str());
+                }
+            }
+
+            for _ in 0..20 {
+                let idx = rng.gen_range(0..docs.len());
+                old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, false);
+
+                let (_a_idx, a, _b_idx, b) = choose_2(&mut docs, &mut rng);
+                a.oplog.add_missing_operations_from(&b.oplog);
+                a.branch.merge(&a.oplog, a.oplog.local_frontier_ref());
+            }
+
+            let oplog = &docs[0].oplog;
+            let mut branch = oplog.checkout_at(oplog.local_frontier_ref());
+            for _ in 0..30 {
+                let v = rng.gen_range(0..oplog.len());
+                let target = if rng.gen_bool(0.5) {
+                    Frontier::new_1(v)
+                } else {
+                    oplog.cg.graph.parents_at_version(v)
+                };
+
+                branch.rewind_to(oplog, target.as_ref());
+                let expect = oplog.checkout(target.as_ref());
+                assert_eq!(branch.version, expect.version);
+                assert_eq!(branch.content, expect.content);
+            }
+
+            branch.rewind_to(oplog, oplog.local_frontier_ref());
+            assert_eq!(branch.content, docs[0].branch.content);
+        }
+    }
+
+    #[test]
+    fn rewind_with_composition() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hello world");
+        let mut branch = oplog.checkout_tip();
+        oplog.add_insert(mike, 0, "ab");
+
+        branch.begin_composition(5);
+        branch.update_composition("XYZ");
+        // Moving forwards merges.
+        branch.rewind_to(&oplog, oplog.local_frontier_ref());
+        assert_eq!(branch.content, "abhelloXYZ world");
+        assert_eq!(branch.composition_range(), Some((7..10).into()));
+
+        branch.rewind_to(&oplog, &[10]);
+        assert_eq!(branch.content, "helloXYZ world");
+        assert_eq!(branch.composition_range(), Some((5..8).into()));
+
+        branch.rewind_to(&oplog, oplog.local_frontier_ref());
+        assert_eq!(branch.content, "abhelloXYZ world");
+        branch.commit_composition(&mut oplog, seph, "XYZ");
+        assert_eq!(oplog.checkout_tip().content, "abhelloXYZ world");
+    }
+
+    #[test]
+    fn grapheme_positions() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut branch = ListBranch::new();
+        branch.insert(&mut oplog, seph, 0, "ne\u{301}e \u{1f1f3}\u{1f1ff}!");
+        assert_eq!(branch.len(), 8);
+        assert_eq!(branch.len_graphemes(), 6);
+        assert_eq!(branch.pos_graphemes_to_chars(2), 3);
+        assert_eq!(branch.pos_graphemes_to_chars(6), 8);
+        assert_eq!(branch.pos_chars_to_graphemes(7), 5);
+        assert_eq!(branch.pos_chars_to_graphemes(2), 1);
+    }
+
+    #[test]
+    fn utf16_positions() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut branch = ListBranch::new();
+        branch.insert(&mut oplog, seph, 0, "a\u{1f600}b");
+        assert_eq!(branch.len_utf16(), 4);
+        assert_eq!(branch.pos_utf16_to_chars(3), 2);
+        assert_eq!(branch.pos_chars_to_utf16(2), 3);
+        assert_eq!(branch.pos_chars_to_utf16(3), 4);
+    }
+
+    #[test]
+    fn apply_local_patch() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut branch = ListBranch::new();
+        branch.insert(&mut oplog, seph, 0, "hello world");
+        let before = branch.local_frontier();
+
+        // Edits are named with positions in the original document, in any order.
+        let v = branch.apply_local_patch(&mut oplog, seph, &[
+            (6..11, "everyone"),
+            (0..5, "hi"),
+            (5..5, ","),
+        ]);
+        assert_eq!(branch.content, "hi, everyone");
+        assert_eq!(v, Some(oplog.len() - 1));
+
+        // The whole patch is a single run of operations on top of the previous version.
+        let ops = oplog.diff_versions(before.as_ref(), oplog.local_frontier_ref());
+        let mut b2 = oplog.checkout(before.as_ref());
+        b2.apply(&ops);
+        assert_eq!(b2.content, "hi, everyone");
+        assert_eq!(oplog.checkout_tip().content, "hi, everyone");
+
+        assert_eq!(branch.apply_local_patch(&mut oplog, seph, &[(1..1, "")]), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn apply_local_patch_overlapping() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut branch = ListBranch::new();
+        branch.insert(&mut oplog, seph, 0, "hello world");
+        branch.apply_local_patch(&mut oplog, seph, &[(0..5, "a"), (3..6, "b")]);
+    }
+}
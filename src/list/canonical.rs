@@ -0,0 +1,199 @@
+//! Canonical operation order.
+//!
+//! Each peer numbers operations in the order it happened to see them, so two peers with exactly
+//! the same history usually store it differently - and encode it to different bytes. Content
+//! addressed storage needs equal documents to produce equal files.
+//!
+//! The canonical order is a topological sort of the operations which only depends on the history
+//! itself. Operations are grouped into *chains*: runs of operations from the same agent with
+//! consecutive sequence numbers, where each operation's only parent is the one before it. Then
+//! whenever there's a choice, the chain whose first operation has the smallest (agent name, seq)
+//! goes next.
+//!
+//! This is used by [`EncodeOptions::canonical`](crate::list::encoding::EncodeOptions::canonical).
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use rle::HasLength;
+use smallvec::SmallVec;
+use crate::{DTRange, Frontier, LV};
+use crate::list::ListOpLog;
+
+struct Chain {
+    agent: u32,
+    seq_start: LV,
+    parents: Frontier,
+    /// The local versions in the chain, in order.
+    spans: SmallVec<DTRange, 1>,
+}
+
+impl Chain {
+    fn last(&self) -> LV { self.spans.last().unwrap().last() }
+    fn next_seq(&self) -> LV { self.seq_start + self.spans.iter().map(|s| s.len()).sum::<usize>() as LV }
+}
+
+impl ListOpLog {
+    /// The oplog's chains of operations, in canonical order.
+    fn canonical_chains(&self) -> Vec<Chain> {
+        let mut chains: Vec<Chain> = vec![];
+        // Map from the last LV of each chain to its index.
+        let mut chain_ends: HashMap<LV, usize> = HashMap::new();
+        // (LV range, chain index), sorted by LV.
+        let mut chain_of: Vec<(DTRange, usize)> = vec![];
+
+        for e in self.cg.iter_range((0..self.len()).into()) {
+            let span: DTRange = (e.start..e.start + e.span.len() as LV).into();
+            let continues = e.parents.try_get_single_entry()
+                .and_then(|p| chain_ends.get(&p).copied())
+                .filter(|idx| chains[*idx].agent == e.span.agent && chains[*idx].next_seq() == e.span.seq_range.start);
+
+            let idx = if let Some(idx) = continues {
+                chain_ends.remove(&chains[idx].last());
+                chains[idx].spans.push(span);
+                idx
+            } else {
+                chains.push(Chain {
+                    agent: e.span.agent,
+                    seq_start: e.span.seq_range.start,
+                    parents: e.parents,
+                    spans: smallvec::smallvec![span],
+                });
+                chains.len() - 1
+            };
+            chain_ends.insert(span.last(), idx);
+            chain_of.push((span, idx));
+        }
+
+        let find_chain = |lv: LV| -> usize {
+            let i = chain_of.partition_point(|(r, _)| r.end <= lv);
+            chain_of[i].1
+        };
+
+        let mut children: Vec<SmallVec<usize, 2>> = vec![smallvec::smallvec![]; chains.len()];
+        let mut waiting_for: Vec<usize> = vec![0; chains.len()];
+        for (idx, chain) in chains.iter().enumerate() {
+            let mut parent_chains: SmallVec<usize, 2> = chain.parents.iter().map(|p| find_chain(*p)).collect();
+            parent_chains.sort_unstable();
+            parent_chains.dedup();
+            waiting_for[idx] = parent_chains.len();
+            for p in parent_chains { children[p].push(idx); }
+        }
+
+        let aa = &self.cg.agent_assignment;
+        let key = |idx: usize| Reverse((aa.get_agent_name(chains[idx].agent), chains[idx].seq_start, idx));
+        let mut ready: BinaryHeap<_> = (0..chains.len()).filter(|i| waiting_for[*i] == 0).map(key).collect();
+
+        let mut order = Vec::with_capacity(chains.len());
+        while let Some(Reverse((_, _, idx))) = ready.pop() {
+            order.push(idx);
+            for c in children[idx].iter() {
+                waiting_for[*c] -= 1;
+                if waiting_for[*c] == 0 { ready.push(key(*c)); }
+            }
+        }
+        debug_assert_eq!(order.len(), chains.len());
+
+        let mut chains: Vec<Option<Chain>> = chains.into_iter().map(Some).collect();
+        order.into_iter().map(|idx| chains[idx].take().unwrap()).collect()
+    }
+
+    /// The oplog's operations in canonical order, as runs of local versions. See the
+    /// [module documentation](self).
+    pub(crate) fn canonical_spans(&self) -> impl Iterator<Item = DTRange> {
+        self.canonical_chains().into_iter().flat_map(|chain| chain.spans)
+    }
+
+    /// The entries in a version, sorted by their remote IDs rather than their local versions.
+    pub(crate) fn canonical_version_order(&self, version: &[LV]) -> SmallVec<LV, 2> {
+        let aa = &self.cg.agent_assignment;
+        let mut result: SmallVec<LV, 2> = version.into();
+        result.sort_by_cached_key(|v| {
+            let (agent, seq) = aa.local_to_agent_version(*v);
+            (aa.get_agent_name(agent), seq)
+        });
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+    use rle::HasLength;
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::encoding::EncodeOptions;
+    use crate::{DTRange, LV};
+    use crate::list::operation::ListOpKind;
+    use crate::rle::KVPair;
+
+    #[test]
+    fn canonical_encoding_is_stable() {
+        let mut any_differ = false;
+        for seed in 0..20 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut docs = [ListCRDT::new(), ListCRDT::new(), ListCRDT::new()];
+            for (doc, name) in docs.iter_mut().zip(["a", "b", "c"]) {
+                doc.get_or_create_agent_id(name);
+            }
+
+            // Each peer makes changes, and syncs with the others in a random order. So they all
+            // see the operations in a different order.
+            for _ in 0..30 {
+                let doc = &mut docs[rng.gen_range(0..3)];
+                let len = doc.len();
+                if len > 0 && rng.gen_bool(0.3) {
+                    let start = rng.gen_range(0..len);
+                    let end = rng.gen_range(start + 1..=len.min(start + 3));
+                    doc.delete(0, start..end);
+                } else {
+                    doc.insert(0, rng.gen_range(0..=len), ["x", "yz", "abc"][rng.gen_range(0..3)]);
+                }
+
+                if rng.gen_bool(0.3) {
+                    let from = rng.gen_range(0..3);
+                    let data = docs[from].oplog.encode(&EncodeOptions::patch());
+                    docs[rng.gen_range(0..3)].merge_data_and_ff(&data).unwrap();
+                }
+            }
+            for from in 0..3 {
+                let data = docs[from].oplog.encode(&EncodeOptions::patch());
+                for doc in docs.iter_mut() { doc.merge_data_and_ff(&data).unwrap(); }
+            }
+
+            let data = docs[0].oplog.encode(&EncodeOptions::canonical());
+            for doc in &docs[1..] {
+                any_differ |= doc.oplog.encode(&EncodeOptions::full()) != docs[0].oplog.encode(&EncodeOptions::full());
+                assert_eq!(doc.oplog.encode(&EncodeOptions::canonical()), data);
+            }
+
+            let loaded = ListOpLog::load_from(&data).unwrap();
+            loaded.dbg_check(true);
+            assert!(docs[0].oplog.eq_ignoring_agent_order(&loaded));
+            assert_eq!(loaded.encode(&EncodeOptions::canonical()), data);
+
+            // Patches from a version are canonical too.
+            let v = docs[0].oplog.cg.graph.find_dominators(&[docs[0].oplog.len() / 2]);
+            let remote_v = docs[0].oplog.cg.agent_assignment.local_to_remote_frontier_owned(v.as_ref());
+            let v1 = docs[1].oplog.cg.agent_assignment.remote_to_local_frontier(remote_v.iter());
+            let patch = docs[0].oplog.encode_from(&EncodeOptions::canonical(), v.as_ref());
+            assert_eq!(patch, docs[1].oplog.encode_from(&EncodeOptions::canonical(), v1.as_ref()));
+            let mut partial = docs[0].oplog.extract_subhistory(v.as_ref());
+            partial.decode_and_add(&patch).unwrap();
+            assert!(docs[0].oplog.eq_ignoring_agent_order(&partial));
+
+            // Content to omit is named with each peer's own local versions.
+            let lv = docs[0].oplog.operations.iter().find(|e| e.1.kind == ListOpKind::Ins).unwrap().0;
+            let rv = docs[0].oplog.cg.agent_assignment.local_to_remote_version(lv);
+            let lv1 = docs[1].oplog.cg.agent_assignment.remote_to_local_version(rv);
+            let omit = [DTRange::from(lv..lv + 1)];
+            let omit1 = [DTRange::from(lv1..lv1 + 1)];
+            let data = docs[0].oplog.encode(&EncodeOptions::canonical().omit_content_for(&omit));
+            assert_eq!(data, docs[1].oplog.encode(&EncodeOptions::canonical().omit_content_for(&omit1)));
+            let loaded = ListOpLog::load_from(&data).unwrap();
+            let loaded_lv = loaded.cg.agent_assignment.remote_to_local_version(rv);
+            assert!(loaded.operations.iter().any(|KVPair(v, op)| {
+                *v <= loaded_lv && loaded_lv < v + op.len() as LV && op.content_pos.is_none()
+            }));
+        }
+        assert!(any_differ);
+    }
+}
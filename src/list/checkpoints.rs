@@ -0,0 +1,161 @@
+//! Named checkpoints in a document's history.
+//!
+//! A checkpoint is a label (and an optional longer message) attached to a version of the document,
+//! like a tag or a commit message in git. Checkpoints are stored with the oplog, and they're synced
+//! to other peers along with the document's operations.
+//!
+//! A checkpoint's message can be changed by adding the checkpoint again. If peers change the
+//! message concurrently, the change made after the most other changes wins (like named branches).
+//! Ties are broken by picking the larger message, so every peer ends up with the same message.
+
+use smartstring::alias::String as SmartString;
+use crate::{Frontier, LV};
+use crate::list::ListOpLog;
+
+/// A named version of the document. See [`ListOpLog::add_checkpoint`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Checkpoint {
+    pub version: Frontier,
+    pub name: SmartString,
+    pub message: String,
+    /// The number of times the message has been changed. See the module documentation.
+    pub edit: usize,
+}
+
+impl ListOpLog {
+    /// Tag `version` with a name and message. The version is usually the document's current
+    /// version (see [`local_frontier`](ListOpLog::local_frontier)).
+    ///
+    /// Names don't need to be unique. Adding the same checkpoint (name and version) twice does
+    /// nothing, but the message is replaced.
+    ///
+    /// Panics if the version contains unknown operations.
+    pub fn add_checkpoint(&mut self, version: &[LV], name: &str, message: &str) {
+        assert!(version.iter().all(|v| *v < self.len()), "Cannot add checkpoint at unknown version");
+        let version = self.cg.graph.find_dominators(version);
+        if let Some(existing) = self.checkpoints.iter_mut()
+            .find(|c| c.name == name && c.version == version)
+        {
+            if existing.message != message {
+                existing.message = message.into();
+                existing.edit += 1;
+            }
+        } else {
+            self.checkpoints.push(Checkpoint {
+                version,
+                name: name.into(),
+                message: message.into(),
+                edit: 0,
+            });
+        }
+    }
+
+    /// Add a checkpoint received from a remote peer. If we already have it, the most recent
+    /// message wins. See the module documentation.
+    pub(crate) fn add_checkpoint_internal(&mut self, checkpoint: Checkpoint) {
+        if let Some(existing) = self.checkpoints.iter_mut()
+            .find(|c| c.name == checkpoint.name && c.version == checkpoint.version)
+        {
+            if (checkpoint.edit, &checkpoint.message) > (existing.edit, &existing.message) {
+                existing.message = checkpoint.message;
+                existing.edit = checkpoint.edit;
+            }
+        } else {
+            self.checkpoints.push(checkpoint);
+        }
+    }
+
+    /// All checkpoints in the document, in the order they were added (or received). This order
+    /// is local to this oplog - peers may list checkpoints in a different order.
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Find the checkpoint with the passed name at the latest version. Versions are compared by
+    /// the number of operations in their history, so a checkpoint wins over any checkpoint at a
+    /// version it contains. If that's tied, the version whose (sorted) agent names and sequence
+    /// numbers compare greater wins. So if there are several checkpoints at concurrent versions,
+    /// the same one is picked on every peer.
+    pub fn checkpoint_named(&self, name: &str) -> Option<&Checkpoint> {
+        self.checkpoints.iter()
+            .filter(|c| c.name == name)
+            .max_by_key(|c| self.version_sort_key(c.version.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn checkpoints_round_trip() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi");
+        oplog.add_checkpoint(&[1], "draft", "First draft");
+        let v1 = oplog.local_frontier();
+        oplog.add_insert(seph, 2, " there");
+        oplog.add_checkpoint(oplog.local_frontier().as_ref(), "v1.0", "");
+        oplog.add_checkpoint(&[1], "draft", "First draft!");
+
+        assert_eq!(oplog.checkpoints().len(), 2);
+        let draft = oplog.checkpoint_named("draft").unwrap();
+        assert_eq!(draft.version, v1);
+        assert_eq!(draft.message, "First draft!");
+        assert_eq!(oplog.checkout(draft.version.as_ref()).content(), "hi");
+
+        let data = oplog.encode(&EncodeOptions::default());
+        let loaded = ListOpLog::load_from(&data).unwrap();
+        assert_eq!(loaded.checkpoints(), oplog.checkpoints());
+
+        // Checkpoints are sent with patches.
+        let mut remote = ListOpLog::load_from(&oplog.encode_from(&EncodeOptions::patch(), &[])).unwrap();
+        assert_eq!(remote.checkpoints(), oplog.checkpoints());
+        let mike = remote.get_or_create_agent_id("mike");
+        remote.add_insert(mike, 0, ">");
+        remote.add_checkpoint(remote.local_frontier().as_ref(), "mike", "");
+        let patch = remote.encode_from(&EncodeOptions::patch(), oplog.local_frontier_ref());
+        let mut merged = oplog.clone();
+        oplog.decode_and_add(&patch).unwrap();
+        assert_eq!(oplog.checkpoints(), remote.checkpoints());
+
+        merged.add_missing_operations_from(&remote);
+        assert_eq!(merged.checkpoints(), remote.checkpoints());
+    }
+
+    #[test]
+    fn concurrent_checkpoint_edits_converge() {
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.add_insert(seph, 0, "hi");
+        a.add_checkpoint(&[1], "draft", "First draft");
+        let mut b = ListOpLog::load_from(&a.encode(&EncodeOptions::default())).unwrap();
+
+        // Both peers change the message, and add checkpoints with the same name at concurrent
+        // versions.
+        a.add_checkpoint(&[1], "draft", "aaa");
+        b.add_checkpoint(&[1], "draft", "bbb");
+        let mike = b.get_or_create_agent_id("mike");
+        a.add_insert(seph, 0, "a");
+        b.add_insert(mike, 0, "b");
+        a.add_checkpoint(&[2], "latest", "");
+        b.add_checkpoint(&[2], "latest", "");
+
+        let patch_a = a.encode_from(&EncodeOptions::patch(), &[1]);
+        let patch_b = b.encode_from(&EncodeOptions::patch(), &[1]);
+        a.decode_and_add(&patch_b).unwrap();
+        b.decode_and_add(&patch_a).unwrap();
+
+        for oplog in [&a, &b] {
+            assert_eq!(oplog.checkpoint_named("draft").unwrap().message, "bbb");
+            let latest = oplog.checkpoint_named("latest").unwrap();
+            assert_eq!(oplog.frontier_to_remote_string(latest.version.as_ref()), "seph:2");
+        }
+
+        // Later edits still win.
+        a.add_checkpoint(&[1], "draft", "aaa");
+        b.decode_and_add(&a.encode_from(&EncodeOptions::patch(), a.local_frontier_ref())).unwrap();
+        assert_eq!(b.checkpoint_named("draft").unwrap().message, "aaa");
+    }
+}
@@ -0,0 +1,111 @@
+//! Some input devices (styluses, IMEs, etc) emit a separate event for every keystroke at a very
+//! high rate. Each event added to the oplog individually costs a little bit of bookkeeping - so
+//! this module provides a small buffer which merges bursts of contiguous single character edits
+//! from the same agent before they're appended to the oplog.
+
+use rle::MergableSpan;
+use crate::{AgentId, LV};
+use crate::list::ListOpLog;
+use crate::list::operation::TextOperation;
+
+#[derive(Debug, Clone)]
+struct PendingOp {
+    agent: AgentId,
+    op: TextOperation,
+    last_at: u64,
+}
+
+/// Buffers local operations and merges them together when they're made by the same agent at
+/// contiguous positions within `window_ms` of each other.
+///
+/// Buffered operations are *not* in the oplog yet. All local edits must go through the coalescer
+/// (or [`flush`](OpCoalescer::flush) must be called first), and you should flush before reading
+/// from or syncing the oplog.
+#[derive(Debug, Clone)]
+pub struct OpCoalescer {
+    window_ms: u64,
+    pending: Option<PendingOp>,
+}
+
+impl OpCoalescer {
+    pub fn new(window_ms: u64) -> Self {
+        Self { window_ms, pending: None }
+    }
+
+    pub fn window_ms(&self) -> u64 { self.window_ms }
+
+    /// Returns true if there's an operation waiting to be flushed.
+    pub fn has_pending(&self) -> bool { self.pending.is_some() }
+
+    /// Add a local operation, made by `agent` at time `now_ms`. The timestamp only needs to be
+    /// monotonic - its never stored.
+    ///
+    /// If this causes a previously buffered operation to be written to the oplog, the local
+    /// version of the flushed operation is returned.
+    pub fn push(&mut self, oplog: &mut ListOpLog, agent: AgentId, op: TextOperation, now_ms: u64) -> Option<LV> {
+        if let Some(pending) = &mut self.pending {
+            if pending.agent == agent
+                && now_ms.saturating_sub(pending.last_at) <= self.window_ms
+                && pending.op.can_append(&op)
+            {
+                pending.op.append(op);
+                pending.last_at = now_ms;
+                return None;
+            }
+        }
+
+        let flushed = self.flush(oplog);
+        self.pending = Some(PendingOp { agent, op, last_at: now_ms });
+        flushed
+    }
+
+    /// Flush the pending operation if the window has expired by `now_ms`. Call this periodically
+    /// (eg from a timer) to make sure edits make it into the oplog.
+    pub fn flush_expired(&mut self, oplog: &mut ListOpLog, now_ms: u64) -> Option<LV> {
+        match &self.pending {
+            Some(pending) if now_ms.saturating_sub(pending.last_at) > self.window_ms => self.flush(oplog),
+            _ => None,
+        }
+    }
+
+    /// Write any buffered operation to the oplog. Returns the local version of the last flushed
+    /// operation, if there was anything to flush.
+    pub fn flush(&mut self, oplog: &mut ListOpLog) -> Option<LV> {
+        self.pending.take().map(|PendingOp { agent, op, .. }| {
+            oplog.add_operations_local(agent, &[op])
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+    use crate::list::operation::TextOperation;
+    use super::OpCoalescer;
+
+    #[test]
+    fn coalesces_typing() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut c = OpCoalescer::new(50);
+
+        assert_eq!(c.push(&mut oplog, seph, TextOperation::new_insert(0, "a"), 0), None);
+        assert_eq!(c.push(&mut oplog, seph, TextOperation::new_insert(1, "b"), 10), None);
+        assert_eq!(c.push(&mut oplog, seph, TextOperation::new_insert(2, "c"), 20), None);
+        assert!(oplog.is_empty());
+
+        // Not expired yet.
+        assert_eq!(c.flush_expired(&mut oplog, 60), None);
+        assert_eq!(c.flush_expired(&mut oplog, 71), Some(2));
+        assert_eq!(oplog.operations.num_entries(), 1);
+        assert!(!c.has_pending());
+
+        // Non-contiguous edits are flushed separately.
+        c.push(&mut oplog, seph, TextOperation::new_insert(0, "x"), 100);
+        assert_eq!(c.push(&mut oplog, seph, TextOperation::new_delete(2..3), 101), Some(3));
+        assert_eq!(c.flush(&mut oplog), Some(4));
+        assert_eq!(c.flush(&mut oplog), None);
+
+        assert_eq!(oplog.checkout_tip().content().to_string(), "xac");
+    }
+}
@@ -0,0 +1,225 @@
+//! A collaborative string with an API like `std::String`.
+//!
+//! [`CollabString`] bundles a [`ListCRDT`] with the agent making local edits, so applications
+//! which just want a shared text field don't need to think about agents, branches or oplogs.
+//!
+//! Unlike `String`, positions are measured in characters (unicode scalar values), not bytes.
+
+use std::fmt;
+use std::ops::{Bound, Range, RangeBounds};
+use crate::{AgentId, Frontier, LV};
+use crate::encoding::parseerror::ParseError;
+use crate::list::{BranchChars, ListCRDT};
+use crate::list::encoding::EncodeOptions;
+
+/// A collaborative string with an API like `std::String`. It bundles a [`ListCRDT`] with the
+/// agent making local edits, so applications which just want a shared text field don't need to
+/// think about agents, branches or oplogs.
+///
+/// Unlike `String`, positions are measured in characters (unicode scalar values), not bytes.
+#[derive(Debug, Clone)]
+pub struct CollabString {
+    doc: ListCRDT,
+    agent: AgentId,
+}
+
+impl CollabString {
+    /// Create an empty string, edited locally by the named agent.
+    pub fn new(agent_name: &str) -> Self {
+        Self::from_doc(ListCRDT::new(), agent_name)
+    }
+
+    /// Create a string containing `content`, inserted by the named agent.
+    pub fn from_str(agent_name: &str, content: &str) -> Self {
+        Self::from_doc(ListCRDT::from_str(agent_name, content), agent_name)
+    }
+
+    /// Wrap an existing document. Local edits will be made by the named agent.
+    pub fn from_doc(mut doc: ListCRDT, agent_name: &str) -> Self {
+        let agent = doc.get_or_create_agent_id(agent_name);
+        Self { doc, agent }
+    }
+
+    /// Load a document from encoded bytes. Local edits will be made by the named agent.
+    pub fn load_from(bytes: &[u8], agent_name: &str) -> Result<Self, ParseError> {
+        Ok(Self::from_doc(ListCRDT::load_from(bytes)?, agent_name))
+    }
+
+    /// The underlying document.
+    pub fn doc(&self) -> &ListCRDT { &self.doc }
+
+    /// The underlying document. The branch should be kept at the oplog's current version.
+    pub fn doc_mut(&mut self) -> &mut ListCRDT { &mut self.doc }
+
+    pub fn into_inner(self) -> ListCRDT { self.doc }
+
+    /// The agent making local edits.
+    pub fn agent(&self) -> AgentId { self.agent }
+
+    /// The length of the string in characters.
+    pub fn len(&self) -> usize { self.doc.len() }
+
+    pub fn is_empty(&self) -> bool { self.doc.is_empty() }
+
+    pub fn chars(&self) -> BranchChars<'_> { self.doc.branch.chars() }
+
+    /// Get the characters in `range`. See [`ListBranch::slice`](crate::list::ListBranch::slice).
+    pub fn slice(&self, range: Range<usize>) -> String { self.doc.branch.slice(range) }
+
+    /// The document's current version.
+    pub fn version(&self) -> Frontier { self.doc.oplog.cg.version.clone() }
+
+    fn char_range<R: RangeBounds<usize>>(&self, range: R) -> Range<usize> {
+        let start = match range.start_bound() {
+            Bound::Included(s) => *s,
+            Bound::Excluded(s) => *s + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(e) => *e + 1,
+            Bound::Excluded(e) => *e,
+            Bound::Unbounded => self.len(),
+        };
+        assert!(start <= end && end <= self.len(), "Range out of bounds");
+        start..end
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]));
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        self.insert_str(self.len(), s);
+    }
+
+    /// Insert a character at character position `pos`. Panics if `pos` is past the end.
+    pub fn insert(&mut self, pos: usize, c: char) {
+        self.insert_str(pos, c.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Insert a string at character position `pos`. Panics if `pos` is past the end.
+    pub fn insert_str(&mut self, pos: usize, s: &str) {
+        if !s.is_empty() {
+            self.doc.insert(self.agent, pos, s);
+        }
+    }
+
+    /// Remove the characters in `range`. Panics if the range is out of bounds.
+    pub fn remove_range<R: RangeBounds<usize>>(&mut self, range: R) {
+        let range = self.char_range(range);
+        if !range.is_empty() {
+            self.doc.delete(self.agent, range);
+        }
+    }
+
+    /// Replace the characters in `range` with `s`, as a single transaction. Panics if the range
+    /// is out of bounds.
+    pub fn replace_range<R: RangeBounds<usize>>(&mut self, range: R, s: &str) {
+        let range = self.char_range(range);
+        self.doc.apply_local_patch(self.agent, &[(range, s)]);
+    }
+
+    /// Shorten the string to `new_len` characters. Does nothing if its already shorter.
+    pub fn truncate(&mut self, new_len: usize) {
+        if new_len < self.len() {
+            self.remove_range(new_len..);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.remove_range(..);
+    }
+
+    /// Change the content to `text`, using a diff to find the edits. See
+    /// [`ListBranch::sync_text`](crate::list::ListBranch::sync_text).
+    pub fn set_text(&mut self, text: &str) -> Option<LV> {
+        self.doc.sync_text(self.agent, text)
+    }
+
+    /// Encode the whole document.
+    pub fn encode(&self) -> Vec<u8> {
+        self.doc.oplog.encode(&EncodeOptions::full())
+    }
+
+    /// Encode the changes made since `version`, to send to a peer which has already seen it.
+    pub fn encode_from(&self, version: &[LV]) -> Vec<u8> {
+        self.doc.oplog.encode_from(&EncodeOptions::patch(), version)
+    }
+
+    /// Merge in changes encoded by a peer.
+    pub fn merge_bytes(&mut self, bytes: &[u8]) -> Result<Frontier, ParseError> {
+        self.doc.merge_data_and_ff(bytes)
+    }
+
+    /// Merge in the changes made to another copy of this string.
+    pub fn merge_from(&mut self, other: &CollabString) {
+        self.doc.oplog.add_missing_operations_from(&other.doc.oplog);
+        self.doc.branch.merge(&self.doc.oplog, self.doc.oplog.cg.version.as_ref());
+    }
+}
+
+impl From<CollabString> for ListCRDT {
+    fn from(s: CollabString) -> Self { s.doc }
+}
+
+impl fmt::Display for CollabString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.doc.branch, f)
+    }
+}
+
+impl PartialEq<str> for CollabString {
+    fn eq(&self, other: &str) -> bool {
+        self.doc.branch == *other
+    }
+}
+
+impl PartialEq<&str> for CollabString {
+    fn eq(&self, other: &&str) -> bool {
+        self.doc.branch == *other
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CollabString;
+
+    #[test]
+    fn string_api() {
+        let mut s = CollabString::new("seph");
+        s.push_str("hello");
+        s.push('!');
+        s.insert_str(5, " wörld");
+        assert_eq!(s, "hello wörld!");
+        assert_eq!(s.len(), 12);
+        assert_eq!(s.slice(6..11), "wörld");
+
+        s.replace_range(6..11, "there");
+        assert_eq!(s.to_string(), "hello there!");
+        s.remove_range(..6);
+        s.insert(0, 'T');
+        s.remove_range(1..=1);
+        assert_eq!(s, "There!");
+        s.truncate(5);
+        assert_eq!(s.chars().collect::<String>(), "There");
+        s.set_text("Where");
+        assert_eq!(s, "Where");
+        s.clear();
+        assert!(s.is_empty());
+        s.doc().dbg_check(true);
+    }
+
+    #[test]
+    fn concurrent_edits() {
+        let mut a = CollabString::from_str("seph", "hello");
+        let mut b = CollabString::load_from(&a.encode(), "mike").unwrap();
+        let v = b.version();
+        a.push_str(" world");
+        b.insert_str(0, "oh ");
+
+        a.merge_bytes(&b.encode_from(v.as_ref())).unwrap();
+        b.merge_from(&a);
+        assert_eq!(a, "oh hello world");
+        assert_eq!(a.to_string(), b.to_string());
+    }
+}
@@ -0,0 +1,329 @@
+//! A compact representation for small documents.
+//!
+//! A [`ListOpLog`] keeps its operations, causal graph and agent assignments in separate indexed
+//! structures, and each of them has some fixed overhead. That adds up for applications which
+//! store thousands of tiny collaborative fields (eg form inputs), most of which are only ever
+//! edited by one person at a time.
+//!
+//! [`CompactListOpLog`] stores a small document as a single byte array (the document's encoded
+//! operations), along with the document's agents and their
+//! [sequence number reservations](ListOpLog::reserve_seqs), which aren't encoded. The full oplog
+//! is only decoded when the document is read or edited. Once the document grows past
+//! [`DEFAULT_MAX_COMPACT_BYTES`] or its history contains concurrent edits, it upgrades to a
+//! regular [`ListOpLog`] and stays that way.
+
+use std::cell::OnceCell;
+use smartstring::alias::String as SmartString;
+use crate::causalgraph::agent_assignment::LocalSeqs;
+use crate::encoding::parseerror::ParseError;
+use crate::Frontier;
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::encoding::EncodeOptions;
+
+/// Documents which take up more than this many bytes when encoded are upgraded to a full
+/// [`ListOpLog`].
+pub const DEFAULT_MAX_COMPACT_BYTES: usize = 4096;
+
+/// An oplog which is stored compactly while it is small and has a linear history. Reading a
+/// compact document decodes it, and the decoded oplog is kept until the next edit or call to
+/// [`compact`](CompactListOpLog::compact). Once a document has been upgraded it stays upgraded.
+#[derive(Debug, Clone)]
+pub struct CompactListOpLog {
+    repr: Repr,
+    max_compact_bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Repr {
+    Compact(Compact),
+    Full(Box<ListOpLog>),
+}
+
+#[derive(Debug, Clone)]
+struct Compact {
+    /// The oplog, encoded with [`compact_options`].
+    bytes: Box<[u8]>,
+    /// Every agent in the oplog, in order. Agents without operations aren't encoded, and neither
+    /// are sequence number reservations.
+    agents: Box<[(SmartString, LocalSeqs)]>,
+    /// The decoded oplog, once its been read.
+    decoded: OnceCell<Box<ListOpLog>>,
+}
+
+impl Compact {
+    fn decode(&self) -> Result<ListOpLog, ParseError> {
+        let mut oplog = ListOpLog::new();
+        for (name, _) in self.agents.iter() {
+            oplog.get_or_create_agent_id(name);
+        }
+        oplog.decode_and_add(&self.bytes)?;
+        for (client, (_, local_seqs)) in oplog.cg.agent_assignment.client_data.iter_mut().zip(self.agents.iter()) {
+            client.local_seqs = *local_seqs;
+        }
+        Ok(oplog)
+    }
+
+    fn get(&self) -> Result<&ListOpLog, ParseError> {
+        if let Some(oplog) = self.decoded.get() { return Ok(oplog); }
+        let oplog = self.decode()?;
+        Ok(self.decoded.get_or_init(|| Box::new(oplog)))
+    }
+
+    fn into_oplog(self) -> Result<ListOpLog, ParseError> {
+        match self.decoded.into_inner() {
+            Some(oplog) => Ok(*oplog),
+            None => Compact { decoded: OnceCell::new(), ..self }.decode(),
+        }
+    }
+}
+
+fn compact_options() -> EncodeOptions<'static> {
+    // Deleted content is kept so nothing is lost going to and from the compact form.
+    EncodeOptions::full().store_deleted_content(true)
+}
+
+/// Linear histories are the only ones which are cheap to merge into without the indexed
+/// structures. Queued patches aren't encoded, so oplogs with pending patches aren't compacted
+/// either.
+fn can_compact(oplog: &ListOpLog) -> bool {
+    oplog.pending_patches().next().is_none()
+        && oplog.cg.graph.iter().all(|e| {
+            e.parents.len() <= 1 && e.parents.iter().next().copied() == e.span.start.checked_sub(1)
+        })
+}
+
+impl Default for CompactListOpLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<ListOpLog> for CompactListOpLog {
+    fn from(oplog: ListOpLog) -> Self {
+        Self::from_oplog(oplog)
+    }
+}
+
+impl CompactListOpLog {
+    pub fn new() -> Self {
+        Self::from_oplog(ListOpLog::new())
+    }
+
+    /// Wrap an oplog. The oplog is stored compactly if its small enough.
+    pub fn from_oplog(oplog: ListOpLog) -> Self {
+        Self::from_oplog_with_limit(oplog, DEFAULT_MAX_COMPACT_BYTES)
+    }
+
+    /// Wrap an oplog, which is only stored compactly while its encoded size is at most
+    /// `max_compact_bytes`.
+    pub fn from_oplog_with_limit(oplog: ListOpLog, max_compact_bytes: usize) -> Self {
+        let mut result = Self { repr: Repr::Full(Box::new(oplog)), max_compact_bytes };
+        result.compact();
+        result
+    }
+
+    pub fn load_from(bytes: &[u8]) -> Result<Self, ParseError> {
+        Ok(Self::from_oplog(ListOpLog::load_from(bytes)?))
+    }
+
+    /// Returns true while the document is stored in compact form.
+    pub fn is_compact(&self) -> bool {
+        matches!(self.repr, Repr::Compact(_))
+    }
+
+    /// Try to store the document in compact form. Returns true if the document is now compact.
+    /// This also drops the decoded copy of a compact document kept by
+    /// [`read`](CompactListOpLog::read).
+    ///
+    /// This is done automatically after each edit to a compact document. Call this to move a
+    /// document back into compact form after its history has been made linear again (eg by
+    /// replacing it with the result of [`squash_before`](ListOpLog::squash_before) or
+    /// [`shallow_clone`](ListOpLog::shallow_clone)).
+    pub fn compact(&mut self) -> bool {
+        match &mut self.repr {
+            Repr::Compact(compact) => { compact.decoded.take(); }
+            Repr::Full(oplog) => {
+                if !can_compact(oplog) { return false; }
+                let bytes = oplog.encode(&compact_options());
+                if bytes.len() > self.max_compact_bytes { return false; }
+                let agents = oplog.cg.agent_assignment.client_data.iter()
+                    .map(|c| (c.name.clone(), c.local_seqs))
+                    .collect();
+                self.repr = Repr::Compact(Compact {
+                    bytes: bytes.into_boxed_slice(),
+                    agents,
+                    decoded: OnceCell::new(),
+                });
+            }
+        }
+        true
+    }
+
+    /// Upgrade the document to a full oplog, and keep it that way. Returns an error if the
+    /// compact data couldn't be decoded.
+    pub fn upgrade(&mut self) -> Result<&mut ListOpLog, ParseError> {
+        if let Repr::Compact(compact) = &mut self.repr {
+            let compact = std::mem::replace(compact, Compact {
+                bytes: Box::new([]),
+                agents: Box::new([]),
+                decoded: OnceCell::new(),
+            });
+            self.repr = Repr::Full(Box::new(compact.into_oplog()?));
+        }
+        match &mut self.repr {
+            Repr::Full(oplog) => Ok(oplog),
+            Repr::Compact(_) => unreachable!(),
+        }
+    }
+
+    /// Modify the document's oplog. Afterwards the document is stored compactly again if it still
+    /// fits. Otherwise it stays upgraded.
+    pub fn edit<R>(&mut self, f: impl FnOnce(&mut ListOpLog) -> R) -> Result<R, ParseError> {
+        let was_compact = self.is_compact();
+        let result = f(self.upgrade()?);
+        if was_compact { self.compact(); }
+        Ok(result)
+    }
+
+    /// Run `f` on the document's oplog. Compact documents are decoded the first time they're read.
+    pub fn read<R>(&self, f: impl FnOnce(&ListOpLog) -> R) -> Result<R, ParseError> {
+        match &self.repr {
+            Repr::Compact(compact) => Ok(f(compact.get()?)),
+            Repr::Full(oplog) => Ok(f(oplog)),
+        }
+    }
+
+    /// Convert into a regular oplog.
+    pub fn into_oplog(self) -> Result<ListOpLog, ParseError> {
+        match self.repr {
+            Repr::Compact(compact) => compact.into_oplog(),
+            Repr::Full(oplog) => Ok(*oplog),
+        }
+    }
+
+    pub fn local_frontier(&self) -> Result<Frontier, ParseError> {
+        self.read(|oplog| oplog.local_frontier())
+    }
+
+    pub fn checkout_tip(&self) -> Result<ListBranch, ParseError> {
+        self.read(|oplog| oplog.checkout_tip())
+    }
+
+    pub fn encode(&self, opts: &EncodeOptions) -> Result<Vec<u8>, ParseError> {
+        self.read(|oplog| oplog.encode(opts))
+    }
+
+    /// Merge encoded operations (eg a patch from a remote peer) into the document.
+    pub fn decode_and_add(&mut self, bytes: &[u8]) -> Result<Frontier, ParseError> {
+        self.edit(|oplog| oplog.decode_and_add(bytes))?
+    }
+
+    /// Roughly how many bytes of memory the document uses.
+    pub fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>() + match &self.repr {
+            Repr::Compact(compact) => {
+                compact.bytes.len()
+                    + compact.agents.iter().map(|(name, _)| std::mem::size_of::<(SmartString, LocalSeqs)>() + name.len()).sum::<usize>()
+                    + compact.decoded.get().map_or(0, |oplog| oplog.mem_usage_breakdown().total())
+            }
+            Repr::Full(oplog) => oplog.mem_usage_breakdown().total(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::causalgraph::agent_assignment::LocalSeqs;
+    use crate::list::{CompactListOpLog, ListOpLog};
+    use crate::list::encoding::EncodeOptions;
+
+    #[test]
+    fn round_trip() {
+        let mut doc = CompactListOpLog::new();
+        assert!(doc.is_compact());
+
+        doc.edit(|oplog| {
+            let seph = oplog.get_or_create_agent_id("seph");
+            oplog.add_insert(seph, 0, "hello world");
+            oplog.add_delete_without_content(seph, 0..6);
+        }).unwrap();
+        assert!(doc.is_compact());
+        assert_eq!(doc.checkout_tip().unwrap().content().to_string(), "world");
+
+        let mut expected = ListOpLog::new();
+        let seph = expected.get_or_create_agent_id("seph");
+        expected.add_insert(seph, 0, "hello world");
+        expected.add_delete_without_content(seph, 0..6);
+        // Reading keeps the decoded oplog until the document is compacted again.
+        assert!(doc.mem_size() > expected.mem_usage_breakdown().total());
+        assert!(doc.compact());
+        assert!(doc.mem_size() < expected.mem_usage_breakdown().total());
+        assert_eq!(doc.into_oplog().unwrap(), expected);
+    }
+
+    #[test]
+    fn upgrades_on_concurrent_edits() {
+        let mut doc = CompactListOpLog::new();
+        doc.edit(|oplog| {
+            let seph = oplog.get_or_create_agent_id("seph");
+            oplog.add_insert(seph, 0, "hi");
+        }).unwrap();
+
+        let mut remote = ListOpLog::new();
+        let mike = remote.get_or_create_agent_id("mike");
+        remote.add_insert(mike, 0, "yo");
+        doc.decode_and_add(&remote.encode(&EncodeOptions::full())).unwrap();
+        assert!(!doc.is_compact());
+        assert_eq!(doc.local_frontier().unwrap().len(), 2);
+
+        // Further edits keep the document upgraded.
+        doc.edit(|oplog| {
+            let seph = oplog.get_or_create_agent_id("seph");
+            oplog.add_insert(seph, 0, "x");
+        }).unwrap();
+        assert!(!doc.is_compact());
+        assert_eq!(doc.checkout_tip().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn upgrades_when_large() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi");
+        let mut doc = CompactListOpLog::from_oplog_with_limit(oplog, 100);
+        assert!(doc.is_compact());
+
+        // Something LZ4 can't compress away.
+        let mut seed = 1u32;
+        let content: String = (0..200).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            char::from(b'a' + (seed >> 16) as u8 % 26)
+        }).collect();
+        doc.edit(|oplog| oplog.add_insert(seph, 2, &content)).unwrap();
+        assert!(!doc.is_compact());
+        assert!(!doc.compact());
+
+        doc.edit(|oplog| oplog.add_delete_without_content(seph, 0..202)).unwrap();
+        assert_eq!(doc.checkout_tip().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn keeps_seq_reservations() {
+        let mut doc = CompactListOpLog::new();
+        let (seph, mike) = doc.edit(|oplog| {
+            let seph = oplog.get_or_create_agent_id("seph");
+            let mike = oplog.get_or_create_agent_id("mike");
+            oplog.reserve_seqs(mike, 3);
+            oplog.add_insert(mike, 0, "hi");
+            (seph, mike)
+        }).unwrap();
+        assert!(doc.is_compact());
+
+        // Agents keep their IDs, even if they haven't made any edits.
+        let oplog = doc.into_oplog().unwrap();
+        assert_eq!(oplog.get_agent_id("seph"), Some(seph));
+        assert_eq!(oplog.get_agent_id("mike"), Some(mike));
+        let reservation = oplog.cg.agent_assignment.client_data[mike as usize].local_seqs;
+        assert_eq!(reservation, LocalSeqs::ReservedUntil(3));
+    }
+}
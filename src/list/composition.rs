@@ -0,0 +1,148 @@
+//! Support for IME composition sessions.
+//!
+//! While the user is composing text with an IME, the editor shows provisional text which changes
+//! with every keystroke. We don't want any of that to end up in the oplog - only the final
+//! committed text. So the branch holds the provisional text in its content, and carefully lifts it
+//! out whenever remote changes are merged in.
+
+use rle::HasLength;
+use crate::{AgentId, lv_to_usize, LV};
+use crate::dtrange::DTRange;
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::ListOpKind;
+use crate::unicount::count_chars;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Composition {
+    /// The position of the provisional text in the document, in unicode characters.
+    pos: usize,
+    text: String,
+    /// Set while the provisional text has been temporarily removed from the branch's content.
+    lifted: bool,
+}
+
+impl Composition {
+    fn len(&self) -> usize {
+        count_chars(&self.text)
+    }
+
+    /// Update the composition position after an operation is applied to the document (with the
+    /// provisional text lifted out).
+    pub(crate) fn transform(&mut self, kind: ListOpKind, span: DTRange) {
+        let (start, end) = (lv_to_usize(span.start), lv_to_usize(span.end));
+        match kind {
+            ListOpKind::Ins => {
+                // Concurrent inserts at the same location end up before the provisional text.
+                if start <= self.pos { self.pos += span.len(); }
+            }
+            ListOpKind::Del => {
+                if end <= self.pos { self.pos -= span.len(); }
+                else if start < self.pos { self.pos = start; }
+            }
+        }
+    }
+}
+
+impl ListBranch {
+    /// Start an IME composition session at `pos`.
+    ///
+    /// While composing, the branch's content contains the provisional text, but none of it is
+    /// added to the oplog until [`commit_composition`](ListBranch::commit_composition) is called.
+    /// Remote changes can still be merged in, and the composition will move accordingly. No other
+    /// local edits should be made to the branch until the composition is committed or cancelled.
+    ///
+    /// Panics if a composition is already active.
+    pub fn begin_composition(&mut self, pos: usize) {
+        assert!(self.composition.is_none(), "Composition already active");
+        assert!(pos <= self.content.len_chars());
+        self.composition = Some(Box::new(Composition { pos, text: String::new(), lifted: false }));
+    }
+
+    /// Replace the provisional text of the current composition.
+    ///
+    /// Panics if there is no active composition.
+    pub fn update_composition(&mut self, text: &str) {
+        let c = self.composition.as_mut().expect("No active composition");
+        let (pos, len) = (c.pos, c.len());
+        c.text.clear();
+        c.text.push_str(text);
+        self.content_remove(pos..pos + len, None);
+        self.content_insert(pos, text, count_chars(text), None);
+    }
+
+    /// Get the range of the document (in unicode characters) currently holding provisional text.
+    pub fn composition_range(&self) -> Option<DTRange> {
+        self.composition.as_ref().map(|c| (c.pos as LV..(c.pos + c.len()) as LV).into())
+    }
+
+    /// Finish the composition, replacing the provisional text with `text`. The committed text is
+    /// added to the oplog as a normal insert from `agent`. Returns the version of the insert, or
+    /// None if the committed text is empty.
+    pub fn commit_composition(&mut self, oplog: &mut ListOpLog, agent: AgentId, text: &str) -> Option<LV> {
+        let pos = self.cancel_composition()?;
+        if text.is_empty() { None } else { Some(self.insert(oplog, agent, pos, text)) }
+    }
+
+    /// Discard the current composition and its provisional text. Returns the position the
+    /// composition was at.
+    pub fn cancel_composition(&mut self) -> Option<usize> {
+        let c = self.composition.take()?;
+        self.content_remove(c.pos..c.pos + c.len(), None);
+        Some(c.pos)
+    }
+
+    /// Temporarily remove any provisional text from the content, so the content matches the
+    /// branch's version.
+    pub(crate) fn lift_composition(&mut self) {
+        if let Some(c) = self.composition.as_mut() {
+            debug_assert!(!c.lifted);
+            c.lifted = true;
+            let range = c.pos..c.pos + count_chars(&c.text);
+            self.content_remove(range, None);
+        }
+    }
+
+    pub(crate) fn restore_composition(&mut self) {
+        if let Some(mut c) = self.composition.take() {
+            debug_assert!(c.lifted);
+            // The composition might be past the end of the document if we've moved back in time.
+            c.pos = c.pos.min(self.content.len_chars());
+            self.content_insert(c.pos, &c.text, c.len(), None);
+            c.lifted = false;
+            self.composition = Some(c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListCRDT;
+
+    #[test]
+    fn composition_with_remote_merge() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let mike = doc.get_or_create_agent_id("mike");
+        doc.insert(seph, 0, "hello world");
+        let v = doc.oplog.local_frontier();
+
+        doc.branch.begin_composition(5);
+        doc.branch.update_composition("に");
+        doc.branch.update_composition("にほ");
+        assert_eq!(doc.branch.content().to_string(), "helloにほ world");
+        assert_eq!(doc.branch.composition_range(), Some((5..7).into()));
+        assert_eq!(doc.oplog.len(), 11);
+
+        // Remote edits arrive mid-composition.
+        doc.oplog.add_insert_at(mike, v.as_ref(), 0, ">> ");
+        doc.oplog.add_delete_at(mike, &[13], 7..8);
+        doc.branch.merge(&doc.oplog, doc.oplog.local_frontier_ref());
+        assert_eq!(doc.branch.content().to_string(), ">> hellにほ world");
+        assert_eq!(doc.branch.composition_range(), Some((7..9).into()));
+
+        doc.branch.commit_composition(&mut doc.oplog, seph, "日本");
+        assert_eq!(doc.branch.composition_range(), None);
+        assert_eq!(doc.branch.content().to_string(), ">> hell日本 world");
+        assert_eq!(doc.oplog.checkout_tip().content(), doc.branch.content());
+    }
+}
@@ -0,0 +1,193 @@
+//! A cheap heuristic for guessing whether merging a patch will interleave awkwardly with local
+//! changes. This doesn't transform anything - it just compares the raw (untransformed) positions of
+//! the incoming operations with the positions of concurrent local operations. Because those
+//! positions are relative to different versions of the document, the result is only an estimate.
+//! But its good enough to tell users "hey, someone else was editing right here".
+
+use rle::{AppendRle, HasLength, SplitableSpanCtx};
+use crate::{AgentId, DTRange, lv_to_usize, LV};
+use crate::encoding::parseerror::ParseError;
+use crate::list::encoding::read_patch_ops;
+use crate::list::ListOpLog;
+use crate::list::op_metrics::ListOperationCtx;
+use crate::rle::KVPair;
+
+/// The result of [`ListOpLog::estimate_conflicts`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct ConflictEstimate {
+    /// The number of operations in the patch which aren't already known locally.
+    pub new_ops: usize,
+
+    /// The number of local operations which are concurrent with the patch (ie, the patch's author
+    /// hadn't seen them when they made their changes).
+    pub concurrent_ops: usize,
+
+    /// The number of new operations in the patch which touch a region of the document which was
+    /// also edited by a concurrent local operation.
+    pub overlapping_ops: usize,
+}
+
+impl ConflictEstimate {
+    /// Returns true if any incoming operation overlaps a concurrent local edit.
+    pub fn likely_conflict(&self) -> bool {
+        self.overlapping_ops > 0
+    }
+}
+
+/// Add the range touched by the op. Inserts and deletes both touch their span. I'm being generous
+/// here and treating adjacent edits as overlapping too.
+fn touched(start: LV, end: LV) -> DTRange {
+    (start.saturating_sub(1)..end + 1).into()
+}
+
+impl ListOpLog {
+    fn touched_ranges(&self, ranges: &[DTRange]) -> Vec<DTRange> {
+        ranges.iter().flat_map(|r| {
+            self.operations.iter_range_ctx(*r, &self.operation_ctx)
+                .map(|KVPair(_, op)| touched(op.loc.span.start, op.loc.span.end))
+        }).collect()
+    }
+
+    /// Estimate how badly the operations in the passed (encoded) patch will conflict with local
+    /// changes, without actually merging or transforming anything.
+    ///
+    /// This is intended for UIs which want to warn users before a risky merge. The patch is only
+    /// read - the oplog isn't modified or copied. Returns an error if the patch depends on
+    /// operations which aren't known locally.
+    pub fn estimate_conflicts(&self, patch: &[u8]) -> Result<ConflictEstimate, ParseError> {
+        let patch = read_patch_ops(self, patch)?;
+        let aa = &self.cg.agent_assignment;
+        let agents: Vec<Option<AgentId>> = patch.names.iter().map(|name| aa.get_agent_id(name)).collect();
+
+        // The patch's base version and the operations in it we already have tell us how much of
+        // our history the patch's author had seen. The rest are new, and are listed as offsets
+        // into the patch.
+        let mut known = patch.base_version.as_ref().to_vec();
+        let mut new_ranges: Vec<DTRange> = vec![];
+        let mut t = 0;
+        for span in patch.ids.iter() {
+            let start = span.seq_range.start;
+            let mut seq = start;
+            if let Some(agent) = agents[span.agent as usize] {
+                for KVPair(s, lvs) in aa.client_data[agent as usize].lv_for_seq.iter_range(span.seq_range) {
+                    if s > seq { new_ranges.push_rle((t + seq - start..t + s - start).into()); }
+                    // An agent's operations can be on different branches, so each graph entry
+                    // in the run might be a separate head.
+                    known.extend(self.cg.graph.iter_range(lvs).map(|e| e.span.last()));
+                    seq = s + lvs.len() as LV;
+                }
+            }
+            if seq < span.seq_range.end {
+                new_ranges.push_rle((t + seq - start..t + span.len() as LV).into());
+            }
+            t += span.len() as LV;
+        }
+        if patch.ops.iter().map(|op| op.len()).sum::<usize>() as LV != t {
+            return Err(ParseError::InvalidLength);
+        }
+
+        // Parents outside the patch are part of its base version, which we need to know.
+        for &(agent, seq) in patch.foreign_parents.iter() {
+            let name = patch.names[agent].as_str();
+            match agents[agent].and_then(|agent| aa.try_agent_version_to_lv((agent, seq))) {
+                Some(lv) => known.push(lv),
+                None if self.is_pruned(name, seq) => return Err(ParseError::HistoryPruned),
+                None => return Err(ParseError::BaseVersionUnknown),
+            }
+        }
+
+        let new_ops: usize = new_ranges.iter().map(|r| r.len()).sum();
+        if new_ops == 0 { return Ok(ConflictEstimate::default()); }
+
+        // Local operations the patch doesn't know about.
+        let patch_version = self.cg.graph.find_dominators(&known);
+        let (concurrent, _) = self.cg.graph.diff_rev(self.cg.version.as_ref(), patch_version.as_ref());
+        let concurrent_ops: usize = concurrent.iter().map(|r| r.len()).sum();
+
+        let mut local = self.touched_ranges(&concurrent);
+        local.sort_unstable_by_key(|r| r.start);
+
+        // The patch's operations don't have content, so this is never accessed.
+        let ctx = ListOperationCtx::new();
+        let mut overlapping_ops = 0;
+        let mut t = 0;
+        for op in patch.ops.iter() {
+            let op_range: DTRange = (t..t + op.len() as LV).into();
+            let first = new_ranges.partition_point(|r| r.end <= op_range.start);
+            for r in new_ranges[first..].iter().take_while(|r| r.start < op_range.end) {
+                // Cut out the part of the operation which is new.
+                let mut piece = op.clone();
+                let start = lv_to_usize(r.start.max(op_range.start) - t);
+                let end = lv_to_usize(r.end.min(op_range.end) - t);
+                if start > 0 { piece = piece.truncate_ctx(start, &ctx); }
+                piece.truncate_ctx(end - start, &ctx);
+
+                let r = touched(piece.loc.span.start, piece.loc.span.end);
+                // Any local range starting before our end might overlap.
+                let end_idx = local.partition_point(|l| l.start < r.end);
+                if local[..end_idx].iter().any(|l| l.end > r.start) {
+                    overlapping_ops += piece.len();
+                }
+            }
+            t = op_range.end;
+        }
+
+        Ok(ConflictEstimate {
+            new_ops,
+            concurrent_ops,
+            overlapping_ops,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::{ENCODE_FULL, ParseError};
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn estimate_overlapping_edits() {
+        let mut base = ListOpLog::new();
+        let seph = base.get_or_create_agent_id("seph");
+        base.add_insert(seph, 0, "aaaaaaaaaa bbbbbbbbbb");
+
+        // Remote edit at the start of the document.
+        let mut remote = base.clone();
+        let mike = remote.get_or_create_agent_id("mike");
+        remote.add_insert(mike, 2, "xx");
+        let patch = remote.encode_from(&ENCODE_FULL, base.local_frontier_ref());
+
+        // No local changes: nothing to conflict with.
+        let est = base.estimate_conflicts(&patch).unwrap();
+        assert_eq!(est.new_ops, 2);
+        assert_eq!(est.concurrent_ops, 0);
+        assert!(!est.likely_conflict());
+
+        // A concurrent local edit at the end of the document doesn't overlap.
+        let mut local = base.clone();
+        local.add_delete_without_content(seph, 15..18);
+        let est = local.estimate_conflicts(&patch).unwrap();
+        assert_eq!(est.concurrent_ops, 3);
+        assert!(!est.likely_conflict());
+
+        // But one right next to the remote edit does.
+        local.add_insert(seph, 3, "y");
+        let est = local.estimate_conflicts(&patch).unwrap();
+        assert_eq!(est.concurrent_ops, 4);
+        assert_eq!(est.overlapping_ops, 2);
+        assert!(est.likely_conflict());
+
+        // The estimate doesn't modify the oplog.
+        assert_eq!(local.len(), 25);
+
+        // Operations in the patch we already have are skipped.
+        let whole = remote.encode(&ENCODE_FULL);
+        assert_eq!(local.estimate_conflicts(&whole).unwrap(), est);
+
+        // Patches on top of versions we don't have can't be estimated.
+        let mut ahead = remote.clone();
+        ahead.add_insert(mike, 0, "z");
+        let patch = ahead.encode_from(&ENCODE_FULL, &[ahead.len() - 1]);
+        assert_eq!(local.estimate_conflicts(&patch), Err(ParseError::BaseVersionUnknown));
+    }
+}
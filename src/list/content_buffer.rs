@@ -0,0 +1,580 @@
+//! Storage for the content of inserts and deletes in an oplog.
+//!
+//! This used to just be a big `Vec<u8>`. But for large documents, every time the vec grows it needs
+//! to be copied - which means moving megabytes of bytes around when merging big chunks of history.
+//! Instead, content is stored in a list of chunks. Each chunk starts out small and grows (by
+//! doubling) up to a fixed size, so small documents don't pay for a whole chunk. Once a chunk
+//! is full it never moves.
+//!
+//! Content is still referenced by byte offset (operations store a `content_pos` range). Each chunk
+//! starts at an offset 1 past the end of the previous chunk. That gap means a content range can
+//! never span two chunks - because ranges in different chunks are never adjacent, operations will
+//! never be merged across a chunk boundary.
+//!
+//! Because full chunks never change, they're frozen into reference counted slices. Cloning a
+//! buffer (eg when a [`CowOpLog`](crate::list::CowOpLog) is edited while snapshots are alive) only
+//! copies the chunk being appended to, and the full chunks are shared. Full chunks can also be
+//! spilled out to a file to save memory. Spilled chunks are read back (and cached) when they're
+//! accessed. If that fails, the chunk reads as placeholder characters and the error is kept for
+//! [`ListOpLog::take_content_spill_error`](crate::list::ListOpLog::take_content_spill_error).
+//!
+//! Chunks can also reference content in a shared buffer (like the file an oplog was loaded from),
+//! which saves copying the content when the oplog is decoded. Lazy chunks go one step further, and
+//! don't index the shared content until its needed. Offsets in a lazy chunk count characters
+//! instead of bytes, since we can't find byte offsets without indexing the content.
+
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::ops::Range;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex, OnceLock};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::dtrange::DTRange;
+use crate::{lv_to_usize, LV};
+use crate::list::PLACEHOLDER_CHAR;
+use crate::unicount::chars_to_bytes;
+
+/// The normal chunk size. Strings larger than this get a chunk to themselves.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Lazy chunks store the byte offset of every nth character, so reads don't need to scan the chunk.
+const LAZY_INDEX_STRIDE: usize = 64;
+
+/// A shared, immutable buffer of bytes. See
+/// [`ListOpLog::load_from_shared`](crate::list::ListOpLog::load_from_shared).
+pub type SharedBytes = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+/// The file content is spilled to. Content is read back from the file in places which can't return
+/// errors (like merging), so read errors are kept here instead.
+pub(crate) struct SpillFile {
+    file: Mutex<File>,
+    error: Mutex<Option<io::Error>>,
+}
+
+impl SpillFile {
+    pub(crate) fn new(file: File) -> Self {
+        Self { file: Mutex::new(file), error: Mutex::new(None) }
+    }
+}
+
+/// Characters used in place of content which couldn't be read back, by their length in bytes.
+const SPILL_PLACEHOLDERS: [char; 4] = ['?', '\u{BF}', PLACEHOLDER_CHAR, '\u{1F5CB}'];
+
+/// A chunk of content which has been written out to a file.
+struct SpilledChunk {
+    file: Arc<SpillFile>,
+    offset: u64,
+    len: usize,
+    /// The length in bytes of each character in the chunk, run-length encoded as (length, count).
+    /// If the chunk can't be read back, placeholder characters with the same lengths are used
+    /// instead so operations still line up with their content.
+    char_lens: Arc<[(u8, usize)]>,
+    cache: OnceLock<Box<[u8]>>,
+}
+
+impl SpilledChunk {
+    fn new(file: Arc<SpillFile>, offset: u64, len: usize, char_lens: Arc<[(u8, usize)]>) -> Self {
+        Self { file, offset, len, char_lens, cache: OnceLock::new() }
+    }
+
+    fn read(&self) -> io::Result<Box<[u8]>> {
+        let mut data = vec![0; self.len];
+        let mut file = self.file.file.lock().unwrap();
+        file.seek(SeekFrom::Start(self.offset))?;
+        file.read_exact(&mut data)?;
+        Ok(data.into_boxed_slice())
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.cache.get_or_init(|| {
+            self.read().unwrap_or_else(|err| {
+                *self.file.error.lock().unwrap() = Some(err);
+                let mut placeholders = String::with_capacity(self.len);
+                for &(len, count) in self.char_lens.iter() {
+                    let c = SPILL_PLACEHOLDERS[len as usize - 1];
+                    for _ in 0..count { placeholders.push(c); }
+                }
+                placeholders.into_bytes().into_boxed_slice()
+            })
+        })
+    }
+}
+
+/// Content offsets are passed around in DTRanges.
+fn offsets(range: Range<usize>) -> DTRange {
+    (range.start as LV..range.end as LV).into()
+}
+
+/// The length in bytes of each character in some UTF-8 content. See [`SpilledChunk::char_lens`].
+fn char_lens(data: &[u8]) -> Arc<[(u8, usize)]> {
+    let mut result: Vec<(u8, usize)> = vec![];
+    // Continuation bytes are skipped, and the length of each character is read from its first byte.
+    for b in data.iter().filter(|b| *b & 0xc0 != 0x80) {
+        let len = match b {
+            0..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => 4,
+        };
+        match result.last_mut() {
+            Some((l, count)) if *l == len => *count += 1,
+            _ => result.push((len, 1)),
+        }
+    }
+    result.into()
+}
+
+/// Content in a shared buffer which hasn't been read yet. See
+/// [`ContentBuffer::push_lazy`].
+struct LazyChunk {
+    data: SharedBytes,
+    range: Range<usize>,
+    /// The number of characters in the chunk.
+    chars: usize,
+    /// The byte offset of every [`LAZY_INDEX_STRIDE`]th character. Built on first read.
+    index: OnceLock<Vec<usize>>,
+}
+
+impl LazyChunk {
+    fn new(data: SharedBytes, range: Range<usize>, chars: usize) -> Self {
+        Self { data, range, chars, index: OnceLock::new() }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &(*self.data).as_ref()[self.range.clone()]
+    }
+
+    fn index(&self) -> &[usize] {
+        self.index.get_or_init(|| {
+            let content = std::str::from_utf8(self.bytes())
+                .expect("Lazy content was checked when it was decoded");
+            let mut index = Vec::with_capacity(self.chars / LAZY_INDEX_STRIDE + 1);
+            let mut chars = 0;
+            for (i, _) in content.char_indices() {
+                if chars % LAZY_INDEX_STRIDE == 0 { index.push(i); }
+                chars += 1;
+            }
+            debug_assert_eq!(chars, self.chars);
+            index
+        })
+    }
+
+    /// The byte offset of the character at `offset`.
+    fn byte_offset(&self, offset: usize) -> usize {
+        let index = self.index();
+        if offset == self.chars { return self.range.len(); }
+        let base = index[offset / LAZY_INDEX_STRIDE];
+        // Safe because the whole chunk was checked when the index was built.
+        let rest = unsafe { std::str::from_utf8_unchecked(&self.bytes()[base..]) };
+        base + chars_to_bytes(rest, offset % LAZY_INDEX_STRIDE)
+    }
+}
+
+#[derive(Clone)]
+enum Chunk {
+    /// The chunk being appended to.
+    Mem(Vec<u8>),
+    /// A full chunk. These are shared when the buffer is cloned.
+    Frozen(Arc<[u8]>),
+    Spilled(Arc<SpilledChunk>),
+    /// A range of bytes in a shared buffer.
+    Shared(SharedBytes, Range<usize>),
+    /// A range of bytes in a shared buffer which hasn't been read yet. Offsets count characters.
+    Lazy(Arc<LazyChunk>),
+}
+
+impl Chunk {
+    fn len(&self) -> usize {
+        match self {
+            Chunk::Mem(c) => c.len(),
+            Chunk::Frozen(c) => c.len(),
+            Chunk::Spilled(c) => c.len,
+            Chunk::Shared(_, range) => range.len(),
+            Chunk::Lazy(c) => c.chars,
+        }
+    }
+
+    fn num_bytes(&self) -> usize {
+        match self {
+            Chunk::Lazy(c) => c.range.len(),
+            c => c.len(),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Chunk::Mem(c) => c.as_slice(),
+            Chunk::Frozen(c) => c,
+            Chunk::Spilled(c) => c.bytes(),
+            Chunk::Shared(data, range) => &(**data).as_ref()[range.clone()],
+            Chunk::Lazy(c) => {
+                c.index();
+                c.bytes()
+            }
+        }
+    }
+
+    /// Get the bytes at the range of offsets in the chunk.
+    fn get(&self, range: Range<usize>) -> &[u8] {
+        match self {
+            Chunk::Lazy(c) => &c.bytes()[c.byte_offset(range.start)..c.byte_offset(range.end)],
+            c => &c.bytes()[range],
+        }
+    }
+
+    /// The number of bytes of memory used by the chunk's content.
+    fn mem_size(&self) -> usize {
+        match self {
+            Chunk::Mem(c) => c.capacity(),
+            Chunk::Frozen(c) => c.len(),
+            Chunk::Spilled(c) => c.cache.get().map_or(0, |c| c.len()),
+            // The memory is owned by whoever created the shared buffer.
+            Chunk::Shared(..) => 0,
+            Chunk::Lazy(c) => c.index.get().map_or(0, |i| i.capacity() * size_of::<usize>()),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "ContentBufferData", into = "ContentBufferData"))]
+pub(crate) struct ContentBuffer {
+    /// List of (start offset, content).
+    chunks: Vec<(usize, Chunk)>,
+}
+
+/// The serialized form of a content buffer. All content is read back into memory.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "ContentBuffer")]
+struct ContentBufferData {
+    chunks: Vec<(usize, Vec<u8>)>,
+}
+
+#[cfg(feature = "serde")]
+impl From<ContentBufferData> for ContentBuffer {
+    fn from(data: ContentBufferData) -> Self {
+        Self { chunks: data.chunks.into_iter().map(|(start, c)| (start, Chunk::Mem(c))).collect() }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ContentBuffer> for ContentBufferData {
+    fn from(buf: ContentBuffer) -> Self {
+        Self { chunks: buf.chunks.iter().map(|(start, c)| (*start, c.bytes().to_vec())).collect() }
+    }
+}
+
+impl PartialEq for ContentBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.chunks.len() == other.chunks.len()
+            && self.chunks.iter().zip(other.chunks.iter())
+                .all(|((s1, c1), (s2, c2))| s1 == s2 && c1.bytes() == c2.bytes())
+    }
+}
+
+impl Eq for ContentBuffer {}
+
+impl ContentBuffer {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// The offset 1 past the end of the stored content. Note this is *not* the number of bytes
+    /// stored. Use [`num_bytes`](ContentBuffer::num_bytes) for that.
+    pub(crate) fn end(&self) -> usize {
+        self.chunks.last().map_or(0, |(start, c)| start + c.len())
+    }
+
+    /// The number of bytes of content stored.
+    pub(crate) fn num_bytes(&self) -> usize {
+        self.chunks.iter().map(|(_, c)| c.num_bytes()).sum()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.end() == 0
+    }
+
+    /// Append the string to the buffer, returning the range of offsets it was stored at.
+    pub(crate) fn push_str(&mut self, s: &str) -> DTRange {
+        let bytes = s.as_bytes();
+        let fits = self.chunks.last().is_some_and(|(_, c)| {
+            matches!(c, Chunk::Mem(c) if c.len() + bytes.len() <= c.capacity().max(CHUNK_SIZE))
+        });
+
+        if !fits && !bytes.is_empty() {
+            // The last chunk is full. Freeze it so clones can share it.
+            if let Some((_, c)) = self.chunks.last_mut() {
+                if let Chunk::Mem(data) = c {
+                    *c = Chunk::Frozen(std::mem::take(data).into());
+                }
+            }
+            let start = if self.chunks.is_empty() { 0 } else { self.end() + 1 };
+            self.chunks.push((start, Chunk::Mem(Vec::with_capacity(bytes.len()))));
+        }
+
+        let start = self.end();
+        if let Some((_, Chunk::Mem(c))) = self.chunks.last_mut() {
+            let needed = c.len() + bytes.len();
+            if needed > c.capacity() {
+                // Grow the chunk, but never past the chunk size.
+                let new_cap = (c.capacity() * 2).max(needed).min(CHUNK_SIZE);
+                c.reserve_exact(new_cap - c.len());
+            }
+            c.extend_from_slice(bytes);
+        }
+        offsets(start..start + bytes.len())
+    }
+
+    /// Append bytes from a shared buffer without copying them. The bytes must be valid UTF-8.
+    /// Returns the range of offsets they were stored at.
+    pub(crate) fn push_shared(&mut self, data: &SharedBytes, range: Range<usize>) -> DTRange {
+        let start = self.end();
+        let len = range.len();
+
+        // Content is usually read from consecutive bytes in the buffer, so we can extend the last
+        // chunk.
+        if let Some((_, Chunk::Shared(last_data, last_range))) = self.chunks.last_mut() {
+            if Arc::ptr_eq(last_data, data) && last_range.end == range.start {
+                last_range.end = range.end;
+                return offsets(start..start + len);
+            }
+        }
+
+        if len == 0 { return offsets(start..start); }
+        let start = if self.chunks.is_empty() { 0 } else { start + 1 };
+        self.chunks.push((start, Chunk::Shared(data.clone(), range)));
+        offsets(start..start + len)
+    }
+
+    /// Append content from a shared buffer without indexing it. The bytes must be valid UTF-8 and
+    /// contain exactly `chars` characters (the decoder checks this). Returns the range of offsets
+    /// it was stored at, which counts characters.
+    pub(crate) fn push_lazy(&mut self, data: &SharedBytes, range: Range<usize>, chars: usize) -> DTRange {
+        let start = self.end();
+        if chars == 0 && range.is_empty() { return offsets(start..start); }
+        let start = if self.chunks.is_empty() { 0 } else { start + 1 };
+        self.chunks.push((start, Chunk::Lazy(Arc::new(LazyChunk::new(data.clone(), range, chars)))));
+        offsets(start..start + chars)
+    }
+
+    fn chunk_idx(&self, offset: usize) -> usize {
+        // Almost all reads are from the last chunk.
+        let last = self.chunks.len() - 1;
+        if offset >= self.chunks[last].0 { last }
+        else { self.chunks.partition_point(|(start, _)| *start <= offset) - 1 }
+    }
+
+    /// Get the bytes at the named range. The range must have been returned by push_str (or be a
+    /// subset of such a range).
+    pub(crate) fn get(&self, range: DTRange) -> &[u8] {
+        if range.start == range.end { return &[]; }
+        let (start, c) = &self.chunks[self.chunk_idx(lv_to_usize(range.start))];
+        c.get(lv_to_usize(range.start) - start..lv_to_usize(range.end) - start)
+    }
+
+    /// Remove all content from offset `end` onwards.
+    pub(crate) fn truncate(&mut self, end: usize) {
+        while let Some((start, c)) = self.chunks.last_mut() {
+            if end <= *start {
+                self.chunks.pop();
+            } else {
+                let len = end - *start;
+                match c {
+                    Chunk::Mem(c) => c.truncate(len),
+                    Chunk::Frozen(f) => *c = Chunk::Mem(f[..len].to_vec()),
+                    Chunk::Spilled(s) => *c = Chunk::Mem(s.bytes()[..len].to_vec()),
+                    Chunk::Shared(_, range) => range.end = range.start + len,
+                    Chunk::Lazy(l) => {
+                        let end = l.range.start + l.byte_offset(len);
+                        *c = Chunk::Lazy(Arc::new(LazyChunk::new(l.data.clone(), l.range.start..end, len)));
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    /// Iterate through the stored content, chunk by chunk.
+    pub(crate) fn iter_chunks(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.chunks.iter().map(|(_, c)| c.bytes())
+    }
+
+    pub(crate) fn num_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The number of bytes of memory used to store content. This doesn't include spilled chunks,
+    /// unless they've been read back into memory.
+    pub(crate) fn mem_size(&self) -> usize {
+        self.chunks.iter().map(|(_, c)| c.mem_size()).sum()
+    }
+
+    /// Free any unused space in chunks which are still in memory.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        for (_, c) in self.chunks.iter_mut() {
+            if let Chunk::Mem(data) = c { data.shrink_to_fit(); }
+        }
+        self.chunks.shrink_to_fit();
+    }
+
+    /// The number of bytes of content which have been spilled to disk.
+    pub(crate) fn spilled_bytes(&self) -> usize {
+        self.chunks.iter()
+            .map(|(_, c)| if let Chunk::Spilled(c) = c { c.len } else { 0 })
+            .sum()
+    }
+
+    /// Write full chunks out to the file (oldest first) until at most `max_mem_size` bytes are
+    /// kept in memory. Spilled chunks which have been read back into memory are dropped from
+    /// memory again. The chunk being appended to is never spilled.
+    pub(crate) fn spill(&mut self, file: &Arc<SpillFile>, max_mem_size: usize) -> io::Result<()> {
+        for (_, c) in self.chunks.iter_mut() {
+            if let Chunk::Spilled(s) = c {
+                if s.cache.get().is_some() {
+                    *c = Chunk::Spilled(Arc::new(SpilledChunk::new(s.file.clone(), s.offset, s.len, s.char_lens.clone())));
+                }
+            }
+        }
+
+        let mut mem_size = self.mem_size();
+        let Some((_, full_chunks)) = self.chunks.split_last_mut() else { return Ok(()); };
+        for (_, c) in full_chunks {
+            if mem_size <= max_mem_size { break; }
+            if let Chunk::Mem(_) | Chunk::Frozen(_) = c {
+                let data = c.bytes();
+                let offset = {
+                    let mut f = file.file.lock().unwrap();
+                    let offset = f.seek(SeekFrom::End(0))?;
+                    f.write_all(data)?;
+                    offset
+                };
+                mem_size -= c.mem_size();
+                *c = Chunk::Spilled(Arc::new(SpilledChunk::new(file.clone(), offset, c.len(), char_lens(data))));
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the error from the last failed read of spilled content, if any.
+    pub(crate) fn take_spill_error(&self) -> Option<io::Error> {
+        self.chunks.iter().find_map(|(_, c)| match c {
+            Chunk::Spilled(s) => s.file.error.lock().unwrap().take(),
+            _ => None,
+        })
+    }
+}
+
+/// Where (and when) to spill content to disk. This is configuration rather than content, so its
+/// ignored when comparing.
+#[derive(Clone, Default)]
+pub(crate) struct ContentSpill(pub(crate) Option<(Arc<SpillFile>, usize)>);
+
+impl PartialEq for ContentSpill {
+    fn eq(&self, _other: &Self) -> bool { true }
+}
+
+impl Eq for ContentSpill {}
+
+impl Debug for ContentSpill {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ContentSpill")
+            .field(&self.0.as_ref().map(|(_, limit)| limit))
+            .finish()
+    }
+}
+
+// Not using the derived Debug so we can from_utf8 the internal content.
+impl Debug for ContentBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let content: String = self.iter_chunks()
+            .map(|c| std::str::from_utf8(c).unwrap())
+            .collect();
+        content.fmt(f)
+    }
+}
+
+impl From<&str> for ContentBuffer {
+    fn from(s: &str) -> Self {
+        let mut result = Self::new();
+        result.push_str(s);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chunks_are_not_adjacent() {
+        let mut buf = ContentBuffer::new();
+        assert_eq!(buf.push_str("hi"), (0..2).into());
+        assert_eq!(buf.push_str("there"), (2..7).into());
+
+        // Fill the rest of the chunk.
+        let filler = "x".repeat(CHUNK_SIZE - 7);
+        let r = buf.push_str(&filler);
+        assert_eq!(lv_to_usize(r.end), CHUNK_SIZE);
+
+        // The next string is in a new chunk, with a gap.
+        let r2 = buf.push_str("yo");
+        assert_eq!(r2, offsets(CHUNK_SIZE + 1..CHUNK_SIZE + 3));
+        assert_eq!(buf.get(r2), b"yo");
+        assert_eq!(buf.get((2..7).into()), b"there");
+        assert_eq!(buf.get((1..3).into()), b"it");
+        assert_eq!(buf.num_bytes(), CHUNK_SIZE + 2);
+
+        // Big strings get their own chunk.
+        let big = "z".repeat(CHUNK_SIZE * 2);
+        let r3 = buf.push_str(&big);
+        assert_eq!(r3.start, r2.end + 1);
+        assert_eq!(buf.get(r3), big.as_bytes());
+
+        buf.truncate(lv_to_usize(r2.end));
+        assert_eq!(buf.end(), lv_to_usize(r2.end));
+        buf.truncate(lv_to_usize(r2.start) - 1);
+        assert_eq!(buf.end(), CHUNK_SIZE);
+        buf.truncate(5);
+        assert_eq!(buf.end(), 5);
+        assert_eq!(buf.get((0..5).into()), b"hithe");
+        buf.truncate(0);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn clones_share_full_chunks() {
+        let mut buf = ContentBuffer::new();
+        buf.push_str(&"x".repeat(CHUNK_SIZE));
+        let r = buf.push_str("hi");
+
+        let mut copy = buf.clone();
+        let (Chunk::Frozen(a), Chunk::Frozen(b)) = (&buf.chunks[0].1, &copy.chunks[0].1) else {
+            panic!("Full chunks should be frozen");
+        };
+        assert!(Arc::ptr_eq(a, b));
+
+        // Editing the copy doesn't change the original.
+        copy.truncate(5);
+        copy.push_str("yo");
+        assert_eq!(copy.get((0..7).into()), b"xxxxxyo");
+        assert_eq!(buf.get(r), b"hi");
+        assert_eq!(buf.num_bytes(), CHUNK_SIZE + 2);
+    }
+
+    #[test]
+    fn small_chunks_grow() {
+        let mut buf = ContentBuffer::new();
+        buf.push_str("hi");
+        buf.push_str("there");
+        assert!(buf.mem_size() < 100);
+
+        buf.shrink_to_fit();
+        assert_eq!(buf.mem_size(), 7);
+        let r = buf.push_str(&"x".repeat(CHUNK_SIZE - 7));
+        assert_eq!(r, offsets(7..CHUNK_SIZE));
+        assert_eq!(buf.mem_size(), CHUNK_SIZE);
+        assert_eq!(buf.get((0..7).into()), b"hithere");
+    }
+}
@@ -0,0 +1,362 @@
+//! Version hashes.
+//!
+//! Peers normally trust each other's agent / sequence number bookkeeping - if two peers both have
+//! `(seph, 100)`, they assume they have the same operation. Version hashes let peers check that
+//! instead. Like git commits, the hash of each operation covers the operation itself and the hashes
+//! of its parents - so the hash of a version covers the document's entire history up to that point.
+//! Two peers with the same version hash have identical histories.
+//!
+//! Operations are hashed in runs - sequences of operations by the same agent where each operation
+//! directly follows the previous one (like a run-length encoded operation). Runs are found one
+//! operation at a time, so they don't depend on how operations were run-length encoded locally.
+//! The hash of an operation is the hash of its run up to and including that operation. Deleted
+//! content isn't hashed, since its usually not stored. Inserts with unknown content (eg, redacted
+//! inserts) are hashed without any content, so they won't match the hash of the original operation.
+//!
+//! Runs and the hashes computed from them are cached in the oplog, so hashing a version only needs
+//! to look at operations added since the last hash.
+//!
+//! This needs the `dag_hash` feature.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use rle::{AppendRle, HasLength};
+use sha2::{Digest, Sha256};
+use smartstring::alias::String as SmartString;
+use crate::dtrange::DTRange;
+use crate::encoding::tools::push_str;
+use crate::encoding::varint::{push_lv, push_usize};
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::{lv_to_usize, LV};
+use crate::rev_range::RangeRev;
+
+/// Prefix for everything hashed, so version hashes can't be confused with hashes of anything else.
+const HASH_DOMAIN: &[u8] = b"diamond-types list version hash v1";
+
+/// A SHA-256 hash identifying a version of a document and its entire history.
+pub type VersionHash = [u8; 32];
+
+/// Hash a set of operation hashes, in sorted order. This is used for the parents of each run, and
+/// for versions.
+fn push_hash_set(into: &mut Vec<u8>, mut hashes: Vec<VersionHash>) {
+    hashes.sort_unstable();
+    push_usize(into, hashes.len());
+    for h in hashes {
+        into.extend_from_slice(&h);
+    }
+}
+
+fn finish_hash(data: &[u8]) -> VersionHash {
+    let mut hasher = Sha256::new();
+    hasher.update(HASH_DOMAIN);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// A run of operations by one agent. Each operation after the first has the previous operation as
+/// its only parent, and continues its position like an RLE merged operation.
+#[derive(Debug, Clone)]
+struct HashRun {
+    agent: SmartString,
+    seq: LV,
+    kind: ListOpKind,
+    loc: RangeRev,
+    /// Is the content of the run known? Always true for deletes, since their content isn't hashed.
+    content_known: bool,
+    /// The local versions of the operations in the run, in order.
+    lvs: Vec<DTRange>,
+    /// The hash of the parents of the first operation.
+    parents_hash: VersionHash,
+}
+
+impl HashRun {
+    fn len(&self) -> usize {
+        self.loc.len()
+    }
+
+    fn last_lv(&self) -> LV {
+        self.lvs.last().unwrap().last()
+    }
+
+    /// Can an operation with the given parent and properties continue this run?
+    fn can_continue(&self, parent: LV, agent: &str, seq: LV, kind: ListOpKind, content_known: bool) -> bool {
+        self.last_lv() == parent
+            && self.agent == agent
+            && self.seq + self.len() as LV == seq
+            && self.kind == kind
+            && self.content_known == content_known
+    }
+
+    /// Hash the first `len` operations in the run.
+    fn hash_prefix(&self, len: usize, oplog: &ListOpLog) -> VersionHash {
+        let mut loc = self.loc;
+        if len < loc.len() { loc.truncate_tagged_span(self.kind, len); }
+
+        let mut buf = vec![];
+        push_str(&mut buf, &self.agent);
+        push_lv(&mut buf, self.seq);
+        push_usize(&mut buf, self.kind as usize);
+        push_usize(&mut buf, (loc.fwd || len == 1) as usize);
+        push_usize(&mut buf, lv_to_usize(loc.span.start));
+        push_usize(&mut buf, len);
+        if self.kind == ListOpKind::Ins {
+            if self.content_known {
+                push_usize(&mut buf, 1);
+                let mut content = String::new();
+                let mut remaining = len;
+                for r in self.lvs.iter() {
+                    if remaining == 0 { break; }
+                    let r: DTRange = (r.start..r.start + remaining.min(r.len()) as LV).into();
+                    remaining -= r.len();
+                    for (_, s) in oplog.iter_range_simple(r) {
+                        content.push_str(s.unwrap());
+                    }
+                }
+                push_str(&mut buf, &content);
+            } else {
+                push_usize(&mut buf, 0);
+            }
+        }
+        buf.extend_from_slice(&self.parents_hash);
+        finish_hash(&buf)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct HashRuns {
+    /// Operations before this have been added to runs.
+    end: LV,
+    runs: Vec<HashRun>,
+    /// The start of each span of local versions in a run, with its run and offset in the run.
+    spans: BTreeMap<LV, (usize, usize)>,
+    /// The hashes of operations calculated so far.
+    hashes: BTreeMap<LV, VersionHash>,
+}
+
+impl HashRuns {
+    fn push_span(&mut self, idx: usize, lv: LV, loc: RangeRev) {
+        let run = &mut self.runs[idx];
+        self.spans.insert(lv, (idx, run.len()));
+        run.lvs.push_rle((lv..lv + loc.len() as LV).into());
+        run.loc.append_ops(run.kind, loc);
+    }
+
+    /// Add the operations up to `end` to runs.
+    fn add_ops(&mut self, oplog: &ListOpLog, end: LV) {
+        if end <= self.end { return; }
+
+        for (op, entry, rv) in oplog.iter_full_range((self.end..end).into()) {
+            let kind = op.kind;
+            let content_known = kind == ListOpKind::Del || op.content.is_some();
+            let mut lv = entry.span.start;
+            let mut loc = op.loc;
+
+            // Continue the run of our parent if we can, one operation at a time if the operation
+            // as a whole doesn't fit.
+            let parent_run = match entry.parents.as_ref() {
+                [p] => Some(self.spans.range(..=*p).next_back().unwrap().1.0)
+                    .filter(|idx| self.runs[*idx].can_continue(*p, rv.0, rv.1.start, kind, content_known)),
+                _ => None,
+            };
+            if let Some(idx) = parent_run {
+                while loc.len() > 0 {
+                    if RangeRev::can_append_ops(kind, &self.runs[idx].loc, &loc) {
+                        self.push_span(idx, lv, loc);
+                        lv += loc.len() as LV;
+                        loc.span.end = loc.span.start;
+                    } else {
+                        let mut first = loc;
+                        let rest = first.truncate_tagged_span(kind, 1);
+                        if loc.len() == 1 || !RangeRev::can_append_ops(kind, &self.runs[idx].loc, &first) { break; }
+                        self.push_span(idx, lv, first);
+                        lv += 1;
+                        loc = rest;
+                    }
+                }
+                if loc.len() == 0 { continue; }
+            }
+
+            // Start a new run with the rest of the operation.
+            let parents = if lv == entry.span.start { entry.parents.as_ref().to_vec() } else { vec![lv - 1] };
+            let parent_hashes = parents.iter().map(|p| self.op_hash(*p, oplog)).collect();
+            let mut buf = vec![];
+            push_hash_set(&mut buf, parent_hashes);
+            self.runs.push(HashRun {
+                agent: rv.0.into(),
+                seq: rv.1.start + (lv - entry.span.start),
+                kind,
+                loc,
+                content_known,
+                lvs: vec![(lv..lv + loc.len() as LV).into()],
+                parents_hash: finish_hash(&buf),
+            });
+            self.spans.insert(lv, (self.runs.len() - 1, 0));
+        }
+        self.end = end;
+    }
+
+    /// Get the hash of an operation which has been added to a run.
+    fn op_hash(&mut self, lv: LV, oplog: &ListOpLog) -> VersionHash {
+        if let Some(hash) = self.hashes.get(&lv) { return *hash; }
+
+        let (start, (idx, offset)) = self.spans.range(..=lv).next_back().unwrap();
+        let hash = self.runs[*idx].hash_prefix(offset + lv_to_usize(lv - start) + 1, oplog);
+        self.hashes.insert(lv, hash);
+        hash
+    }
+
+    /// Forget everything from `len` onwards.
+    fn truncate(&mut self, len: LV) {
+        if len >= self.end { return; }
+
+        self.hashes.split_off(&len);
+        let removed = self.spans.split_off(&len);
+        let num_runs = self.runs.partition_point(|r| r.lvs[0].start < len);
+        let affected = removed.values().map(|(idx, _)| *idx)
+            .chain(self.spans.last_key_value().map(|(_, (idx, _))| *idx));
+        for idx in affected {
+            if idx >= num_runs { continue; }
+            let run = &mut self.runs[idx];
+            run.lvs.retain(|r| r.start < len);
+            let last = run.lvs.last_mut().unwrap();
+            last.end = last.end.min(len);
+            let run_len = run.lvs.iter().map(|r| r.len()).sum();
+            if run_len < run.len() { run.loc.truncate_tagged_span(run.kind, run_len); }
+        }
+        self.runs.truncate(num_runs);
+        self.end = len;
+    }
+}
+
+/// Cached version hash data for an oplog: the runs of operations found so far, and their hashes.
+#[derive(Debug, Default)]
+pub(crate) struct HashCache(Mutex<HashRuns>);
+
+impl Clone for HashCache {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.0.lock().unwrap().clone()))
+    }
+}
+
+impl HashCache {
+    /// Forget hashes for operations from `len` onwards. This must be called when operations are
+    /// removed from the oplog.
+    pub(crate) fn truncate(&mut self, len: LV) {
+        self.0.get_mut().unwrap().truncate(len);
+    }
+
+    /// Forget all cached hashes. This must be called when the content of existing operations
+    /// changes.
+    pub(crate) fn clear(&mut self) {
+        *self.0.get_mut().unwrap() = Default::default();
+    }
+}
+
+impl ListOpLog {
+    /// Get the hash of the named version. The hash covers every operation in the version's
+    /// history (like a git commit hash), so replicas with the same hash for a version have
+    /// identical histories. Deleted content isn't hashed, and inserts with unknown content are
+    /// hashed without their content.
+    ///
+    /// Hashes are cached, so this is only slow the first time its called, and when lots of
+    /// operations have been added since the last call.
+    ///
+    /// Panics if the version contains unknown operations.
+    pub fn hash_for_version(&self, version: &[LV]) -> VersionHash {
+        let end = version.iter().max().map_or(0, |v| *v + 1);
+        assert!(end <= self.len(), "Cannot hash unknown version");
+
+        let mut cache = self.hash_cache.0.lock().unwrap();
+        cache.add_ops(self, end);
+        let hashes = version.iter().map(|v| cache.op_hash(*v, self)).collect();
+
+        let mut buf = vec![];
+        push_hash_set(&mut buf, hashes);
+        finish_hash(&buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::encoding::{DecodeOptions, EncodeOptions, ParseError};
+    use crate::list::gen_random::gen_oplog;
+
+    #[test]
+    fn version_hashes_match_between_replicas() {
+        for seed in 0..10 {
+            let oplog = gen_oplog(seed, 40, false, true);
+            // Loading the oplog from a sorted file changes the local order of the operations.
+            let data = oplog.encode(&EncodeOptions::default().sort_operations(true));
+            let oplog2 = ListOpLog::load_from(&data).unwrap();
+
+            let rf = oplog.cg.remote_frontier_owned();
+            let v2 = oplog2.cg.agent_assignment.remote_to_local_frontier(rf.iter());
+            assert_eq!(oplog.hash_for_version(oplog.local_frontier_ref()), oplog2.hash_for_version(v2.as_ref()));
+            assert_ne!(oplog.hash_for_version(oplog.local_frontier_ref()), oplog.hash_for_version(&[]));
+        }
+
+        // Replicas which disagree about the content of an operation have different hashes.
+        let mut a = ListCRDT::new();
+        a.get_or_create_agent_id("seph");
+        a.insert(0, 0, "hi");
+        let mut b = ListCRDT::new();
+        b.get_or_create_agent_id("seph");
+        b.insert(0, 0, "yo");
+        assert_ne!(a.oplog.hash_for_version(&[1]), b.oplog.hash_for_version(&[1]));
+        assert_eq!(a.oplog.hash_for_version(&[]), b.oplog.hash_for_version(&[]));
+    }
+
+    #[test]
+    fn cached_hashes_match_uncached_hashes() {
+        let oplog = gen_oplog(3, 40, false, true);
+        let data = oplog.encode(&EncodeOptions::default());
+        let cached = ListOpLog::load_from(&data).unwrap();
+        for v in 0..oplog.len() {
+            // The cache is extended a little at a time.
+            let uncached = ListOpLog::load_from(&data).unwrap();
+            assert_eq!(cached.hash_for_version(&[v]), uncached.hash_for_version(&[v]));
+        }
+
+        // Hashes of rolled back operations are forgotten.
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.add_insert(seph, 0, "hi");
+        let checkpoint = a.checkpoint();
+        a.add_insert(seph, 2, "yo");
+        let yo_hash = a.hash_for_version(&[3]);
+        a.roll_back(checkpoint);
+        a.add_insert(seph, 2, "ho");
+        assert_ne!(a.hash_for_version(&[3]), yo_hash);
+
+        let mut b = ListOpLog::new();
+        let seph = b.get_or_create_agent_id("seph");
+        b.add_insert(seph, 0, "hiho");
+        assert_eq!(a.hash_for_version(&[3]), b.hash_for_version(&[3]));
+        assert_eq!(a.hash_for_version(&[1]), b.hash_for_version(&[1]));
+    }
+
+    #[test]
+    fn encode_version_hash() {
+        let oplog = gen_oplog(1, 30, false, true);
+        let data = oplog.encode(&EncodeOptions::default().store_version_hash(true));
+        let opts = DecodeOptions { verify_version_hash: true, ..Default::default() };
+        let loaded = ListOpLog::load_from_opts(&data, opts.clone()).unwrap();
+        assert_eq!(loaded.checkout_tip().content(), oplog.checkout_tip().content());
+
+        let plain = oplog.encode(&EncodeOptions::default());
+        assert_eq!(ListOpLog::load_from_opts(&plain, opts.clone()).unwrap_err(), ParseError::MissingChunk(34));
+        ListOpLog::load_from(&plain).unwrap();
+
+        // A peer with a different operation at the same version rejects the file.
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.add_insert(seph, 0, "hi");
+        let mut b = ListOpLog::new();
+        let seph = b.get_or_create_agent_id("seph");
+        b.add_insert(seph, 0, "yo");
+        let patch = b.encode(&EncodeOptions::patch().store_version_hash(true));
+        assert_eq!(a.decode_and_add_opts(&patch, opts).unwrap_err(), ParseError::VersionHashMismatch);
+    }
+}
@@ -0,0 +1,163 @@
+//! Document IDs and forks.
+//!
+//! Documents can be named with a document ID (usually a random GUID). Data from a document with a
+//! different ID can't be merged in, which stops histories from unrelated documents from being
+//! accidentally mixed together.
+//!
+//! When a document is copied to make a new document (a fork), the new document is given a new ID
+//! and remembers the ID of the document it was forked from (and the version at the time). Changes
+//! can still be merged between a document and its forks.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use smartstring::alias::String as SmartString;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::causalgraph::agent_assignment::remote_ids::RemoteFrontierOwned;
+use crate::list::ListOpLog;
+
+/// A record that a document was forked from another document.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ForkPoint {
+    /// The ID of the document this document was forked from.
+    pub doc_id: SmartString,
+    /// The version of the parent document when it was forked.
+    pub version: RemoteFrontierOwned,
+}
+
+/// Generate a random document ID (a 128 bit GUID, in hex).
+///
+/// The ID is generated from the standard library's randomly seeded hasher mixed with the current
+/// time and a counter. It's unique enough to tell documents apart, but it isn't suitable for
+/// anything security related.
+pub fn random_doc_id() -> SmartString {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos()).unwrap_or(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    let mut id = String::with_capacity(32);
+    for _ in 0..2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(time);
+        hasher.write_u64(count);
+        id.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    id.into()
+}
+
+impl ListOpLog {
+    /// Create a new, empty oplog named with a random document ID. See [`random_doc_id`].
+    pub fn new_with_random_doc_id() -> Self {
+        let mut oplog = Self::new();
+        oplog.doc_id = Some(random_doc_id());
+        oplog
+    }
+
+    pub fn doc_id(&self) -> Option<&str> {
+        self.doc_id.as_deref()
+    }
+
+    pub fn set_doc_id(&mut self, doc_id: Option<&str>) {
+        self.doc_id = doc_id.map(|id| id.into());
+    }
+
+    /// The documents this document was forked from, oldest first.
+    pub fn fork_points(&self) -> &[ForkPoint] {
+        &self.fork_points
+    }
+
+    /// Copy this document into a new document with its own (random) document ID. The new document
+    /// records that it was forked from this one.
+    pub fn fork_document(&self) -> Self {
+        let mut fork = self.clone();
+        if let Some(doc_id) = fork.doc_id.take() {
+            fork.fork_points.push(ForkPoint {
+                doc_id,
+                version: self.cg.agent_assignment.local_to_remote_frontier_owned(self.cg.version.as_ref()),
+            });
+        }
+        fork.doc_id = Some(random_doc_id());
+        fork
+    }
+
+    /// Returns true if data from a document with the passed ID (and fork history) can be merged
+    /// into this document. That's the case when the documents have the same ID, or one was forked
+    /// from the other (directly or indirectly).
+    pub(crate) fn is_related_document(&self, doc_id: &str, fork_points: &[ForkPoint]) -> bool {
+        let Some(local_id) = self.doc_id.as_deref() else { return true; };
+        local_id == doc_id
+            || self.fork_points.iter().any(|f| f.doc_id == doc_id)
+            || fork_points.iter().any(|f| f.doc_id == local_id)
+    }
+
+    pub(crate) fn add_fork_points(&mut self, fork_points: Vec<ForkPoint>) {
+        for f in fork_points {
+            if !self.fork_points.contains(&f) && self.doc_id.as_deref() != Some(f.doc_id.as_str()) {
+                self.fork_points.push(f);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::encoding::{DecodeOptions, EncodeOptions};
+    use crate::list::ListOpLog;
+    use super::random_doc_id;
+
+    #[test]
+    fn random_ids_are_unique() {
+        let a = random_doc_id();
+        assert_eq!(a.len(), 32);
+        assert_ne!(a, random_doc_id());
+        assert_ne!(ListOpLog::new_with_random_doc_id().doc_id(), ListOpLog::new_with_random_doc_id().doc_id());
+    }
+
+    #[test]
+    fn forks_can_merge() {
+        let mut doc = ListOpLog::new_with_random_doc_id();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.add_insert(seph, 0, "hi");
+
+        let mut fork = doc.fork_document();
+        assert_ne!(fork.doc_id(), doc.doc_id());
+        assert_eq!(fork.fork_points().len(), 1);
+        assert_eq!(fork.fork_points()[0].doc_id, doc.doc_id().unwrap());
+
+        let mike = fork.get_or_create_agent_id("mike");
+        fork.add_insert(mike, 2, " there");
+
+        // The fork history is saved with the document.
+        let data = fork.encode(&EncodeOptions::default());
+        let loaded = ListOpLog::load_from(&data).unwrap();
+        assert_eq!(loaded.fork_points(), fork.fork_points());
+
+        // Changes can be merged both ways between a document and its fork.
+        doc.decode_and_add(&data).unwrap();
+        assert_eq!(doc.checkout_tip().content(), fork.checkout_tip().content());
+        let before = doc.doc_id().unwrap().to_string();
+        doc.add_insert(seph, 0, "oh ");
+        fork.decode_and_add(&doc.encode(&EncodeOptions::default())).unwrap();
+        assert_eq!(doc.doc_id(), Some(before.as_str()));
+
+        // But not with unrelated documents.
+        let mut other = ListOpLog::new_with_random_doc_id();
+        let mike = other.get_or_create_agent_id("mike");
+        other.add_insert(mike, 0, "yo");
+        assert_eq!(doc.decode_and_add(&other.encode(&EncodeOptions::default())), Err(ParseError::DocIdMismatch));
+
+        // Files without a document ID can be rejected too.
+        let mut anon = ListOpLog::new();
+        let agent = anon.get_or_create_agent_id("anon");
+        anon.add_insert(agent, 0, "yo");
+        let opts = DecodeOptions { require_doc_id: true, ..Default::default() };
+        assert_eq!(doc.decode_and_add_opts(&anon.encode(&EncodeOptions::default()), opts), Err(ParseError::DocIdMissing));
+        doc.decode_and_add(&anon.encode(&EncodeOptions::default())).unwrap();
+    }
+}
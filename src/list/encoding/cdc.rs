@@ -0,0 +1,194 @@
+//! Content-defined chunking.
+//!
+//! When a growing document is saved over and over, most of each new file is the same as the last
+//! one. But the bytes don't line up - inserting a few bytes near the start of a file shifts
+//! everything after it - so backup systems and sync protocols which split files into fixed size
+//! blocks can't tell.
+//!
+//! With [`EncodeOptions::content_defined_chunks`](super::EncodeOptions::content_defined_chunks),
+//! the encoder splits the patches in the file at boundaries picked by a rolling hash over the bytes
+//! themselves. Unchanged regions produce the same chunks in every save, no matter where they end
+//! up in the file. The chunks (and a hash of each) are listed in a manifest chunk at the end of
+//! the file, which can be read with [`read_content_manifest`].
+
+use std::mem::replace;
+use std::ops::Range;
+use crc::{Crc, Digest, CRC_64_XZ};
+use crate::encoding::parseerror::ParseError;
+use crate::list::encoding::{ListChunkType, format};
+use crate::list::encoding::decode_tools::BufReader;
+use crate::list::encoding::encode_tools::{push_leb_usize, push_u64_le};
+
+/// Chunks are never smaller than this, except at the end of the data.
+const MIN_CHUNK_SIZE: usize = 1 << 10;
+/// Chunks are cut when the top bits of the rolling hash are all zero. This makes chunks
+/// 1 + 4kb on average.
+const BOUNDARY_MASK: u64 = !(u64::MAX >> 12);
+const MAX_CHUNK_SIZE: usize = 1 << 14;
+
+/// The hash of each chunk listed in the manifest. This is for finding unchanged regions, not for
+/// security - its easy to make chunks with matching hashes on purpose.
+pub(super) const CHUNK_HASH: Crc<u64> = Crc::<u64>::new(&CRC_64_XZ);
+
+/// Random values for each byte, used by the rolling (gear) hash. These are generated with
+/// splitmix64, and must never change - otherwise chunk boundaries will move between versions.
+static GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Splits a stream of bytes into content-defined chunks. The bytes can be passed in any number of
+/// pieces - the boundaries only depend on the bytes themselves.
+pub(super) struct Chunker {
+    gear: u64,
+    len: usize,
+    digest: Digest<'static, u64>,
+    /// (length, hash) of each finished chunk.
+    chunks: Vec<(usize, u64)>,
+}
+
+impl Chunker {
+    pub(super) fn new() -> Self {
+        Self { gear: 0, len: 0, digest: CHUNK_HASH.digest(), chunks: vec![] }
+    }
+
+    pub(super) fn push(&mut self, mut data: &[u8]) {
+        while let Some(cut) = self.find_boundary(data) {
+            self.digest.update(&data[..cut]);
+            self.end_chunk();
+            data = &data[cut..];
+        }
+        self.digest.update(data);
+    }
+
+    /// Scan through data, returning the number of bytes to consume before the current chunk ends.
+    fn find_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        for (i, b) in data.iter().enumerate() {
+            self.gear = (self.gear << 1).wrapping_add(GEAR[*b as usize]);
+            self.len += 1;
+            if self.len >= MAX_CHUNK_SIZE || (self.len >= MIN_CHUNK_SIZE && self.gear & BOUNDARY_MASK == 0) {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+
+    fn end_chunk(&mut self) {
+        let digest = replace(&mut self.digest, CHUNK_HASH.digest());
+        self.chunks.push((self.len, digest.finalize()));
+        self.len = 0;
+        self.gear = 0;
+    }
+
+    /// Write out the manifest chunk body, for chunks starting at `offset` in the file.
+    pub(super) fn write_manifest(mut self, into: &mut Vec<u8>, offset: usize) {
+        if self.len > 0 { self.end_chunk(); }
+        push_leb_usize(into, offset);
+        push_leb_usize(into, self.chunks.len());
+        for (len, hash) in self.chunks {
+            push_leb_usize(into, len);
+            push_u64_le(into, hash);
+        }
+    }
+}
+
+/// A content-defined chunk of an encoded file, as listed in the file's manifest. Chunk boundaries
+/// are picked by a rolling hash over the file's bytes, so unchanged regions of a document produce
+/// the same chunks each time its saved - even if they've moved within the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestChunk {
+    /// The bytes in the file which make up this chunk.
+    pub range: Range<usize>,
+    /// The CRC-64/XZ checksum of the chunk's bytes.
+    pub hash: u64,
+}
+
+pub(super) fn read_manifest_chunk(mut chunk: BufReader) -> Result<Vec<ManifestChunk>, ParseError> {
+    let mut pos = chunk.next_usize()?;
+    let num_chunks = chunk.next_usize()?;
+    let mut result = Vec::with_capacity(num_chunks.min(chunk.len()));
+    for _ in 0..num_chunks {
+        let len = chunk.next_usize()?;
+        let hash = chunk.next_u64_le()?;
+        let end = pos.checked_add(len).ok_or(ParseError::InvalidLength)?;
+        result.push(ManifestChunk { range: pos..end, hash });
+        pos = end;
+    }
+    chunk.expect_empty()?;
+    Ok(result)
+}
+
+/// Read the content manifest from an encoded file. Returns None if the file was encoded without
+/// [`content_defined_chunks`](super::EncodeOptions::content_defined_chunks).
+///
+/// This only reads the file's chunk headers. The operations in the file aren't parsed, and the
+/// file's checksum isn't checked.
+pub fn read_content_manifest(data: &[u8]) -> Result<Option<Vec<ManifestChunk>>, ParseError> {
+    let mut reader = BufReader(data);
+    reader.read_magic()?;
+    if !format::is_supported_version(reader.next_usize()?) {
+        return Err(ParseError::UnsupportedProtocolVersion);
+    }
+
+    let mut reader = reader.chunks();
+    while !reader.is_empty() {
+        let (chunk_type, chunk) = reader.next_chunk_untyped()?;
+        if chunk_type == ListChunkType::ContentManifest as u32 {
+            let chunks = read_manifest_chunk(chunk)?;
+            if chunks.last().is_some_and(|c| c.range.end > data.len()) {
+                return Err(ParseError::InvalidLength);
+            }
+            return Ok(Some(chunks));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+    use super::{Chunker, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE};
+
+    fn chunks_of(pieces: &[&[u8]]) -> Vec<(usize, u64)> {
+        let mut chunker = Chunker::new();
+        for piece in pieces { chunker.push(piece); }
+        if chunker.len > 0 { chunker.end_chunk(); }
+        chunker.chunks
+    }
+
+    #[test]
+    fn boundaries_are_stable_across_edits() {
+        let mut rng = SmallRng::seed_from_u64(10);
+        let mut data = vec![0u8; 100_000];
+        rng.fill_bytes(&mut data);
+        let before = chunks_of(&[&data]);
+        assert!(before.len() > 10);
+        for (len, _) in &before[..before.len() - 1] {
+            assert!((MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(len));
+        }
+
+        // The boundaries only depend on the bytes, not how they're passed in.
+        let (a, b) = data.split_at(12345);
+        assert_eq!(chunks_of(&[a, &b[..1], &b[1..]]), before);
+
+        // Insert a few bytes near the start. Only the chunk containing the edit should change.
+        let mut edited = data.clone();
+        edited.splice(2000..2000, [1, 2, 3, 4, 5]);
+        let after = chunks_of(&[&edited]);
+        let changed = after.iter().filter(|c| !before.contains(c)).count();
+        assert!(changed <= 2, "{changed} of {} chunks changed", after.len());
+        assert_eq!(after.last(), before.last());
+    }
+}
@@ -1,24 +1,39 @@
+use smartstring::alias::String as SmartString;
 use smallvec::{smallvec, SmallVec};
 use crate::list::encoding::*;
-use crate::list::{ListOpLog, switch};
+use crate::list::{is_squash_agent, ListOpLog, switch};
 use crate::frontier::*;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::list::operation::ListOpKind::{Del, Ins};
 use crate::rev_range::RangeRev;
-use crate::{AgentId, Frontier, LV};
+use crate::{AgentId, Frontier, lv_to_usize, LV};
 use crate::unicount::*;
 use rle::*;
 use crate::list::buffered_iter::Buffered;
 use crate::list::encoding::ListChunkType::*;
 use crate::causalgraph::graph::GraphEntrySimple;
-use crate::list::operation::ListOpKind;
+use crate::list::operation::{ListOpKind, TextOperation};
 use crate::dtrange::{DTRange, UNDERWATER_START};
 use crate::list::encoding::decode_tools::{BufReader, ChunkReader};
+use crate::causalgraph::agent_assignment::AgentAssignment;
 use crate::causalgraph::agent_span::AgentSpan;
 use crate::rle::{KVPair, RleKeyedAndSplitable, RleSpanHelpers, RleVec};
 use crate::encoding::parseerror::ParseError;
 use crate::encoding::tools::calc_checksum;
 use crate::list::encoding::leb::num_decode_zigzag_isize_old;
+use crate::list::content_buffer::SharedBytes;
+use std::ops::Range;
+use std::sync::Arc;
+use crate::list::encoding::cipher::decrypt_file;
+use crate::list::encoding::cdc::read_manifest_chunk;
+use crate::list::encoding::version_summary::{read_summary, summary_to_version};
+use crate::list::transformed_positions::TransformedPositions;
+use crate::list::shallow::PrunedHistory;
+use crate::list::named_branches::NamedBranch;
+use crate::listmerge::rewind::RewindTracker;
+use crate::list::{AgentInfo, Checkpoint, ForkPoint, OpMetadata, MAX_OP_METADATA_LEN, SignatureVerifier, SignedRange};
+use crate::encoding::varint::{num_decode_zigzag_i64, num_decode_zigzag_isize};
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontierOwned, RemoteVersionOwned};
 
 // If this is set to false, the compiler can optimize out the verbose printing code. This makes the
 // compiled output slightly smaller.
@@ -26,7 +41,7 @@ const ALLOW_VERBOSE: bool = false;
 // const ALLOW_VERBOSE: bool = true;
 
 impl<'a> BufReader<'a> {
-    fn read_next_agent_assignment(&mut self, map: &mut [(AgentId, usize)]) -> Result<Option<AgentSpan>, ParseError> {
+    pub(super) fn read_next_agent_assignment(&mut self, map: &mut [(AgentId, LV)]) -> Result<Option<AgentSpan>, ParseError> {
         // Agent assignments are almost always (but not always) linear. They can have gaps, and
         // they can be reordered if the same agent ID is used to contribute to multiple branches.
         //
@@ -37,9 +52,10 @@ impl<'a> BufReader<'a> {
         let mut n = self.next_usize()?;
         let has_jump = strip_bit_usize_2(&mut n);
         let len = self.next_usize()?;
+        if len == 0 { return Err(ParseError::InvalidLength); }
 
         let jump = if has_jump {
-            self.next_zigzag_isize()?
+            self.next_zigzag_i64()?
         } else { 0 };
 
         // The agent mapping uses 0 to refer to ROOT, but no actual operations can be assigned to
@@ -56,9 +72,10 @@ impl<'a> BufReader<'a> {
         let entry = &mut map[inner_agent];
         let agent = entry.0;
 
-        // TODO: Error if this overflows.
-        let start = (entry.1 as isize + jump) as usize;
-        let end = start + len;
+        let start = (entry.1 as i64).checked_add(jump)
+            .and_then(|s| LV::try_from(s).ok())
+            .ok_or(ParseError::InvalidLength)?;
+        let end = start.checked_add(len as LV).ok_or(ParseError::InvalidLength)?;
         entry.1 = end;
 
         Ok(Some(AgentSpan {
@@ -67,33 +84,44 @@ impl<'a> BufReader<'a> {
         }))
     }
 
-    fn read_version(mut self, oplog: &ListOpLog, agent_map: &[(AgentId, usize)]) -> Result<Frontier, ParseError> {
+    fn read_version(mut self, oplog: &ListOpLog, agent_map: &[(AgentId, LV)], base_hint: Option<&[LV]>) -> Result<Frontier, ParseError> {
         let mut result = smallvec![];
+        let mut used_hint = false;
         // All frontiers contain at least one item.
         loop {
             // let agent = reader.next_str()?;
             let (mapped_agent, has_more) = strip_bit_usize(self.next_usize()?);
-            let seq = self.next_usize()?; // Bleh. Skip me when root!
+            let seq = self.next_lv()?; // Bleh. Skip me when root!
             if mapped_agent == 0 { break; } // Root.
 
-            let agent = agent_map[mapped_agent - 1].0;
+            let agent = agent_map.get(mapped_agent - 1).ok_or(ParseError::InvalidLength)?.0;
 
-            let time = oplog.try_crdt_id_to_time((agent, seq))
-                .ok_or(ParseError::BaseVersionUnknown)?;
-            result.push(time);
+            match (oplog.try_crdt_id_to_time((agent, seq)), base_hint) {
+                (Some(time), _) => result.push(time),
+                (None, Some(hint)) if is_squash_agent(oplog.get_agent_name(agent)) => {
+                    result.extend_from_slice(hint);
+                    used_hint = true;
+                }
+                (None, _) => return Err(oplog.unknown_id_error(agent, seq, ParseError::BaseVersionUnknown)),
+            }
 
             if !has_more { break; }
         }
 
-        sort_frontier(&mut result);
-
         self.expect_empty()?;
 
+        if used_hint {
+            return Ok(oplog.cg.graph.find_dominators(&result));
+        }
+
+        if !try_sort_frontier(&mut result) { return Err(ParseError::GenericInvalidData); }
+
         Ok(Frontier(result))
     }
 
-    fn read_parents(&mut self, oplog: &ListOpLog, next_time: LV, agent_map: &[(AgentId, usize)]) -> Result<Frontier, ParseError> {
-        let mut parents = SmallVec::<usize, 2>::new();
+    fn read_parents(&mut self, oplog: &ListOpLog, next_time: LV, agent_map: &[(AgentId, LV)], base_hint: Option<&[LV]>) -> Result<Frontier, ParseError> {
+        let mut parents = SmallVec::<LV, 2>::new();
+        let mut used_hint = false;
         loop {
             let mut n = self.next_usize()?;
             let is_foreign = strip_bit_usize_2(&mut n);
@@ -104,21 +132,30 @@ impl<'a> BufReader<'a> {
                     // The parents list is empty (ie, our parent is ROOT).
                     break;
                 } else {
-                    let agent = agent_map[n - 1].0;
-                    let seq = self.next_usize()?;
+                    let agent = agent_map.get(n - 1).ok_or(ParseError::InvalidLength)?.0;
+                    let seq = self.next_lv()?;
                     // dbg!((agent, seq));
-                    if let Some(c) = oplog.cg.agent_assignment.client_data.get(agent as usize) {
-                        // Adding UNDERWATER_START for foreign parents in a horrible hack.
-                        // I'm so sorry. This gets pulled back out in history_entry_map_and_truncate
-                        c.try_seq_to_lv(seq).ok_or(ParseError::InvalidLength)?
-                    } else {
+                    let Some(c) = oplog.cg.agent_assignment.client_data.get(agent as usize) else {
                         return Err(ParseError::InvalidLength);
+                    };
+                    // Adding UNDERWATER_START for foreign parents in a horrible hack.
+                    // I'm so sorry. This gets pulled back out in history_entry_map_and_truncate
+                    match (c.try_seq_to_lv(seq), base_hint) {
+                        (Some(lv), _) => lv,
+                        (None, Some(hint)) if is_squash_agent(oplog.get_agent_name(agent)) => {
+                            // The parent is a snapshot we don't have, so we assume its the hinted
+                            // version.
+                            parents.extend_from_slice(hint);
+                            used_hint = true;
+                            if !has_more { break; } else { continue; }
+                        }
+                        (None, _) => return Err(oplog.unknown_id_error(agent, seq, ParseError::InvalidLength)),
                     }
                 }
             } else {
                 // Local parents (parents inside this chunk of data) are stored using their
                 // local time offset.
-                next_time - n
+                next_time.checked_sub(n as LV).ok_or(ParseError::InvalidLength)?
             };
 
             parents.push(parent);
@@ -127,22 +164,29 @@ impl<'a> BufReader<'a> {
             if !has_more { break; }
         }
 
+        if used_hint {
+            // Hinted versions can overlap with the other parents.
+            return Ok(oplog.cg.graph.find_dominators(&parents));
+        }
+
         // So this is awkward. There's two reasons parents could end up unsorted:
         // 1. The file is invalid. All local (non-foreign) changes should be in order).
         // or 2. We have foreign items - and they're not sorted based on the local versions.
         // This is fine and we should just re-sort.
-        sort_frontier(&mut parents);
+        if !try_sort_frontier(&mut parents) { return Err(ParseError::GenericInvalidData); }
 
         Ok(Frontier(parents))
     }
 
-    fn next_history_entry(&mut self, oplog: &ListOpLog, next_time: LV, agent_map: &[(AgentId, usize)]) -> Result<GraphEntrySimple, ParseError> {
+    fn next_history_entry(&mut self, oplog: &ListOpLog, next_time: LV, agent_map: &[(AgentId, LV)], base_hint: Option<&[LV]>) -> Result<GraphEntrySimple, ParseError> {
         let len = self.next_usize()?;
-        let parents = self.read_parents(oplog, next_time, agent_map)?;
+        if len == 0 { return Err(ParseError::InvalidLength); }
+        let end = next_time.checked_add(len as LV).ok_or(ParseError::InvalidLength)?;
+        let parents = self.read_parents(oplog, next_time, agent_map, base_hint)?;
 
         // Bleh its gross passing a &[Time] into here when we have a Frontier already.
         Ok(GraphEntrySimple {
-            span: (next_time..next_time + len).into(),
+            span: (next_time..end).into(),
             parents,
         })
     }
@@ -150,10 +194,10 @@ impl<'a> BufReader<'a> {
 }
 
 impl<'a> ChunkReader<'a> {
-    fn read_version(&mut self, oplog: &ListOpLog, agent_map: &[(AgentId, usize)]) -> Result<Frontier, ParseError> {
+    fn read_version(&mut self, oplog: &ListOpLog, agent_map: &[(AgentId, LV)], base_hint: Option<&[LV]>) -> Result<Frontier, ParseError> {
         let chunk = self.read_chunk_if_eq(ListChunkType::Version)?;
         if let Some(chunk) = chunk {
-            chunk.read_version(oplog, agent_map).map_err(|e| {
+            chunk.read_version(oplog, agent_map, base_hint).map_err(|e| {
                 // We can't read a frontier if it names agents or sequence numbers we haven't seen
                 // before. If this happens, its because we're trying to load a data set from the
                 // future.
@@ -194,17 +238,38 @@ impl<'a> ChunkReader<'a> {
         }
     }
 
-    fn read_fileinfo(&mut self, oplog: &mut ListOpLog) -> Result<FileInfoData, ParseError> {
+    fn read_fileinfo(&mut self, oplog: &mut ListOpLog, protocol_version: usize, max_agents: Option<usize>) -> Result<FileInfoData, ParseError> {
         let mut fileinfo = self.expect_chunk(ListChunkType::FileInfo)?.chunks();
 
         let doc_id = fileinfo.read_chunk_if_eq(ListChunkType::DocId)?;
-        let mut agent_names_chunk = fileinfo.expect_chunk(ListChunkType::AgentNames)?;
+        let fork_points = fileinfo.read_chunk_if_eq(ListChunkType::ForkPoints)?;
+        let agent_names_chunk = fileinfo.expect_chunk(ListChunkType::AgentNames)?;
+        let insert_tie_break = fileinfo.read_chunk_if_eq(ListChunkType::InsertTieBreak)?
+            .map(|chunk| chunk.into_content_str())
+            .transpose()?;
         let userdata = fileinfo.read_chunk_if_eq(ListChunkType::UserData)?;
 
         let doc_id = if let Some(doc_id) = doc_id {
             Some(doc_id.into_content_str()?)
         } else { None };
 
+        let mut fork_points_data = Vec::new();
+        if let Some(mut chunk) = fork_points {
+            let num_forks = chunk.next_usize()?;
+            for _ in 0..num_forks {
+                let doc_id = chunk.next_str()?.into();
+                let num_versions = chunk.next_usize()?;
+                let mut version = RemoteFrontierOwned::new();
+                for _ in 0..num_versions {
+                    let name = chunk.next_str()?;
+                    let seq = chunk.next_lv()?;
+                    version.push((name, seq).into());
+                }
+                fork_points_data.push(ForkPoint { doc_id, version });
+            }
+            chunk.expect_empty()?;
+        }
+
         // Map from agent IDs in the file (idx) to agent IDs in self, and the seq cursors.
         //
         // This will usually just be 0,1,2,3,4...
@@ -212,34 +277,77 @@ impl<'a> ChunkReader<'a> {
         // 0 implicitly maps to ROOT.
         // let mut file_to_self_agent_map = vec![(ROOT_AGENT, 0)];
         let mut agent_map = Vec::new();
-        while !agent_names_chunk.0.is_empty() {
-            let name = agent_names_chunk.next_str()?;
+        let mut agent_info = Vec::new();
+        for (name, info) in read_agent_names(agent_names_chunk, protocol_version)? {
+            if !AgentAssignment::is_valid_agent_name(name) { return Err(ParseError::GenericInvalidData); }
+            check_limit(max_agents, DecodeLimit::Agents, agent_map.len() + 1)?;
             let id = oplog.get_or_create_agent_id(name);
             agent_map.push((id, 0));
+            if let Some((rev, info)) = info { agent_info.push((id, rev, info)); }
         }
 
         Ok(FileInfoData {
             userdata,
             doc_id,
+            fork_points: fork_points_data,
             agent_map,
+            agent_info,
+            insert_tie_break,
         })
     }
 }
 
 
+/// An agent's name, and its info (with the info's revision) if the file has any.
+pub(crate) type AgentNameEntry<'a> = (&'a str, Option<(usize, AgentInfo)>);
+
+/// Read the names in an AgentNames chunk, along with each agent's info. See
+/// `AgentMapping::consume` for the format.
+pub(crate) fn read_agent_names(mut chunk: BufReader<'_>, protocol_version: usize) -> Result<Vec<AgentNameEntry<'_>>, ParseError> {
+    let with_info = format::agent_names_have_info(protocol_version);
+    let mut result = Vec::new();
+    while !chunk.is_empty() {
+        let name = chunk.next_str()?;
+        let rev = if with_info { chunk.next_usize()? } else { 0 };
+        if rev == 0 {
+            result.push((name, None));
+            continue;
+        }
+
+        let flags = chunk.next_usize()?;
+        let mut info = AgentInfo::default();
+        if flags & 1 != 0 { info.display_name = Some(chunk.next_str()?.into()); }
+        if flags & 2 != 0 { info.device = Some(chunk.next_str()?.into()); }
+        if flags & 4 != 0 {
+            let len = chunk.next_usize()?;
+            info.public_key = Some(chunk.next_n_bytes(len)?.to_vec());
+        }
+        result.push((name, Some((rev, info))));
+    }
+    Ok(result)
+}
+
 // Returning a tuple was getting too unwieldy.
 #[derive(Debug)]
 struct FileInfoData<'a> {
     userdata: Option<BufReader<'a>>,
     doc_id: Option<&'a str>,
-    agent_map: Vec<(AgentId, usize)>,
+    fork_points: Vec<ForkPoint>,
+    agent_map: Vec<(AgentId, LV)>,
+    /// Agent info (and its revision) for agents in the file, which is added once the file has
+    /// been read.
+    agent_info: Vec<(AgentId, usize, AgentInfo)>,
+    /// The name of the file's custom insert tie break policy, if it has one.
+    insert_tie_break: Option<&'a str>,
 }
 
 
 /// Returns (mapped span, remainder).
 /// The returned remainder is *NOT MAPPED*. This allows this method to be called in a loop.
-fn history_entry_map_and_truncate(mut hist_entry: GraphEntrySimple, version_map: &RleVec<KVPair<DTRange>>) -> (GraphEntrySimple, Option<GraphEntrySimple>) {
-    let (map_entry, offset) = version_map.find_packed_with_offset(hist_entry.span.start);
+fn history_entry_map_and_truncate(mut hist_entry: GraphEntrySimple, version_map: &RleVec<KVPair<DTRange>>) -> Result<(GraphEntrySimple, Option<GraphEntrySimple>), ParseError> {
+    // Corrupt files can name history which isn't in the version map.
+    let (map_entry, offset) = version_map.find_with_offset(hist_entry.span.start)
+        .ok_or(ParseError::InvalidLength)?;
 
     let mut map_entry = map_entry.1;
     map_entry.truncate_keeping_right(offset);
@@ -249,7 +357,7 @@ fn history_entry_map_and_truncate(mut hist_entry: GraphEntrySimple, version_map:
     // Keep entire history entry. Just map it.
     let len = hist_entry.len(); // hist_entry <= map_entry here.
     hist_entry.span.start = map_entry.start;
-    hist_entry.span.end = hist_entry.span.start + len;
+    hist_entry.span.end = hist_entry.span.start + len as LV;
 
     // dbg!(&hist_entry.parents);
 
@@ -257,27 +365,32 @@ fn history_entry_map_and_truncate(mut hist_entry: GraphEntrySimple, version_map:
     // const UNDERWATER_LAST: usize = ROOT_TIME - 1;
     for p in hist_entry.parents.0.iter_mut() {
         if *p >= UNDERWATER_START {
-            let (span, offset) = version_map.find_packed_with_offset(*p);
-            *p = span.1.start + offset;
+            let (span, offset) = version_map.find_with_offset(*p)
+                .ok_or(ParseError::InvalidLength)?;
+            *p = span.1.start + offset as LV;
         }
     }
 
     // Parents can become unsorted here because they might not map cleanly. Thanks, fuzzer.
-    sort_frontier(&mut hist_entry.parents.0);
+    if !try_sort_frontier(&mut hist_entry.parents.0) { return Err(ParseError::GenericInvalidData); }
+    // And parents must come before the entry itself.
+    if hist_entry.parents.0.last().is_some_and(|&p| p >= hist_entry.span.start) {
+        return Err(ParseError::GenericInvalidData);
+    }
 
-    (hist_entry, remainder)
+    Ok((hist_entry, remainder))
 }
 
 // I could just pass &mut last_cursor_pos to a flat read() function. Eh. Once again, generators
 // would make this way cleaner.
 #[derive(Debug)]
-struct ReadPatchesIter<'a> {
+pub(super) struct ReadPatchesIter<'a> {
     buf: BufReader<'a>,
     last_cursor_pos: usize,
 }
 
 impl<'a> ReadPatchesIter<'a> {
-    fn new(buf: BufReader<'a>) -> Self {
+    pub(super) fn new(buf: BufReader<'a>) -> Self {
         Self {
             buf,
             last_cursor_pos: 0,
@@ -313,14 +426,22 @@ impl<'a> ReadPatchesIter<'a> {
         // dbg!(self.last_cursor_pos, diff);
         let raw_start = isize::wrapping_add(self.last_cursor_pos as isize, diff) as usize;
 
+        // Zero length operations are invalid, and would stall the decoder.
+        if len == 0 { return Err(ParseError::InvalidLength); }
+
         let (start, raw_end) = match (tag, fwd) {
-            (Ins, true) => (raw_start, raw_start + len),
+            (Ins, true) => (raw_start, raw_start.checked_add(len).ok_or(ParseError::InvalidLength)?),
             (Ins, false) | (Del, true) => (raw_start, raw_start), // Weird symmetry!
-            (Del, false) => (raw_start - len, raw_start - len),
+            (Del, false) => {
+                let s = raw_start.checked_sub(len).ok_or(ParseError::InvalidLength)?;
+                (s, s)
+            },
         };
         // dbg!((raw_start, tag, fwd, len, start, raw_end));
 
-        let end = start + len;
+        let end = start.checked_add(len).ok_or(ParseError::InvalidLength)?;
+        // Positions are isize offsets from each other. Larger values can only come from corrupt data.
+        if end > isize::MAX as usize { return Err(ParseError::InvalidLength); }
 
         // dbg!(pos);
         self.last_cursor_pos = raw_end;
@@ -328,7 +449,7 @@ impl<'a> ReadPatchesIter<'a> {
 
         Ok(ListOpMetrics {
             loc: RangeRev { // TODO: Probably a nicer way to construct this.
-                span: (start..end).into(),
+                span: (start as LV..end as LV).into(),
                 fwd,
             },
             kind: tag,
@@ -349,21 +470,35 @@ impl<'a> Iterator for ReadPatchesIter<'a> {
 struct ReadPatchContentIter<'a> {
     run_chunk: BufReader<'a>,
     content: &'a str,
+    /// When the content is loaded lazily, this is the offset of the next item's content in the
+    /// oplog's lazy content chunk.
+    lazy_pos: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ItemContent<'a> {
+    Str(&'a str),
+    /// The item's content is in a lazy content chunk, at this (character) offset.
+    Lazy(usize),
 }
 
 #[derive(Debug, Clone)]
 struct ContentItem<'a> {
     len: usize,
-    content: Option<&'a str>,
+    content: Option<ItemContent<'a>>,
 }
 
 impl<'a> SplitableSpanHelpers for ContentItem<'a> {
     fn truncate_h(&mut self, at: usize) -> Self {
-        let content_remainder = if let Some(str) = self.content.as_mut() {
-            let (here, remainder) = split_at_char(str, at);
-            *str = here;
-            Some(remainder)
-        } else { None };
+        let content_remainder = match self.content.as_mut() {
+            Some(ItemContent::Str(str)) => {
+                let (here, remainder) = split_at_char(str, at);
+                *str = here;
+                Some(ItemContent::Str(remainder))
+            }
+            Some(ItemContent::Lazy(pos)) => Some(ItemContent::Lazy(*pos + at)),
+            None => None,
+        };
 
         let remainder = ContentItem {
             len: self.len - at,
@@ -381,7 +516,11 @@ impl<'a> HasLength for ContentItem<'a> {
 }
 
 impl<'a> ReadPatchContentIter<'a> {
-    fn new(mut chunk: BufReader<'a>, compressed: Option<&mut BufReader<'a>>) -> Result<(ListOpKind, Self), ParseError> {
+    /// If `lazy` is set and the content isn't compressed, the content is checked but not read
+    /// into the iterator. The raw content and the number of characters it contains are returned,
+    /// and the caller needs to add them to the oplog and set `lazy_pos`.
+    #[allow(clippy::type_complexity)]
+    fn new(mut chunk: BufReader<'a>, compressed: Option<&mut BufReader<'a>>, lazy: bool) -> Result<(ListOpKind, Self, Option<(&'a [u8], usize)>), ParseError> {
         let tag = match chunk.next_u32()? {
             0 => Ins,
             1 => Del,
@@ -389,24 +528,50 @@ impl<'a> ReadPatchContentIter<'a> {
         };
 
         let mut chunk = chunk.chunks();
+        if lazy && chunk.0.peek_u32()? == Some(Content as u32) {
+            let mut content = chunk.expect_chunk(Content)?;
+            if content.next_u32()? != DataType::PlainText as u32 {
+                return Err(ParseError::UnknownChunk);
+            }
+            let run_chunk = chunk.expect_chunk(ContentIsKnown)?;
+
+            let mut runs = run_chunk.clone();
+            let mut chars = 0usize;
+            while !runs.is_empty() {
+                let (len, known) = strip_bit_usize(runs.next_usize()?);
+                if known { chars = chars.checked_add(len).ok_or(ParseError::InvalidLength)?; }
+            }
+
+            // The content isn't copied, but its checked here so reading it later can't fail.
+            let s = std::str::from_utf8(content.0).map_err(|_| ParseError::InvalidUTF8)?;
+            if count_chars(s) != chars { return Err(ParseError::InvalidLength); }
+
+            return Ok((tag, Self { run_chunk, content: "", lazy_pos: Some(0) }, Some((content.0, chars))));
+        }
+
         let content = chunk.expect_content_str(compressed)?;
 
         let run_chunk = chunk.expect_chunk(ContentIsKnown)?;
 
-        Ok((tag, Self { run_chunk, content }))
+        Ok((tag, Self { run_chunk, content, lazy_pos: None }, None))
     }
 
     fn next_internal(&mut self) -> Result<ContentItem<'a>, ParseError> {
         let n = self.run_chunk.next_usize()?;
         let (len, known) = strip_bit_usize(n);
-        let content = if known {
+        let content = if !known { None } else if let Some(pos) = self.lazy_pos.as_mut() {
+            // Lazy content was checked in new().
+            let content = ItemContent::Lazy(*pos);
+            *pos += len;
+            Some(content)
+        } else {
             let content = consume_chars(&mut self.content, len);
             if count_chars(content) != len { // Having a duplicate strlen here is gross.
                 // We couldn't pull as many chars as requested from self.content.
                 return Err(ParseError::UnexpectedEOF);
             }
-            Some(content)
-        } else { None };
+            Some(ItemContent::Str(content))
+        };
 
         Ok(ContentItem { len, content })
     }
@@ -428,9 +593,92 @@ impl<'a> Iterator for ReadPatchContentIter<'a> {
 #[derive(Debug, Clone)]
 pub struct DecodeOptions {
     /// Ignore CRC check failures. This is mostly used for debugging.
+    ///
+    /// This only applies to the CRC at the end of the file. Chunk CRCs (see
+    /// [`EncodeOptions::chunk_crcs`](crate::list::encoding::EncodeOptions::chunk_crcs)) are always
+    /// checked.
     pub ignore_crc: bool,
 
     pub verbose: bool,
+
+    /// If the inserted or deleted content is compressed in a format this build can't decompress,
+    /// load the operations without their content instead of returning an error.
+    pub allow_missing_content: bool,
+
+    /// Used to decrypt files encoded with [`EncodeOptions::with_cipher`].
+    pub cipher: Option<Arc<dyn ChunkCipher>>,
+
+    /// If set, every operation in the file must be covered by a valid signature. Signatures are
+    /// checked with the passed verifier. See [`ListOpLog::sign_operations`].
+    pub verify_signatures: Option<Arc<dyn SignatureVerifier>>,
+
+    /// Reject data without a document ID when merging into a (non-empty) document which has one.
+    /// Data from documents with a different ID is always rejected.
+    pub require_doc_id: bool,
+
+    /// The maximum number of operations in the data. Servers loading data from untrusted peers
+    /// should set limits, so pathological files are rejected before they use lots of memory.
+    pub max_ops: Option<usize>,
+
+    /// The maximum number of bytes of inserted and deleted content (after decompression).
+    pub max_content_bytes: Option<usize>,
+
+    /// The maximum number of agents named in the data.
+    pub max_agents: Option<usize>,
+
+    /// Reject data which doesn't have a checksum.
+    pub require_crc: bool,
+
+    /// Reject data which leaves a gap in the sequence numbers of any agent. Peers always send all
+    /// the operations we're missing, so gaps only show up in malformed data.
+    pub require_contiguous_seqs: bool,
+
+    /// Check that every new operation only inserts and deletes at positions which exist in the
+    /// document at the operation's parent version, and reject the data with
+    /// [`ParseError::InvalidPosition`] if not. A rejected patch leaves the oplog unchanged.
+    /// Without this check, malformed operations are accepted and the content they check out to
+    /// is unspecified.
+    ///
+    /// This replays the document's whole history, so its about as expensive as a checkout.
+    pub check_positions: bool,
+
+    /// Keep the transformed positions stored in the data (see
+    /// [`EncodeOptions::store_xf`](crate::list::encoding::EncodeOptions::store_xf)), and use them
+    /// to speed up [`checkout_tip`](ListOpLog::checkout_tip).
+    ///
+    /// Stored positions can't be checked without redoing the work they save, so this should only
+    /// be set for data from trusted sources - like files this process wrote itself. A file with
+    /// incorrect positions will check out to the wrong content. When this isn't set, stored
+    /// positions are ignored and the positions are recomputed by merging.
+    pub trust_transformed_positions: bool,
+
+    /// Reject data which doesn't have a version hash, or where the version hash doesn't match the
+    /// version's hash in the merged document. See [`ListOpLog::hash_for_version`]. Checking the
+    /// hash is O(n) with the size of the document's history.
+    #[cfg(feature = "dag_hash")]
+    pub verify_version_hash: bool,
+
+    /// When loading from a shared buffer (see [`ListOpLog::load_from_shared_opts`]), don't copy
+    /// inserted and deleted content while decoding. Instead its read from the buffer the first
+    /// time its needed. This makes loading large documents much faster.
+    ///
+    /// Only uncompressed content can be loaded lazily, and only when loading into an empty oplog.
+    /// Lazy content is still checked while decoding, and invalid content is a [`ParseError`].
+    pub lazy_content: bool,
+}
+
+/// The result of [`ListOpLog::ingest_and_validate`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IngestSummary {
+    /// The version of the ingested data. (This might be different from the oplog's version.)
+    pub version: Frontier,
+
+    /// The new operations, which were appended to the oplog. This is empty if we already had
+    /// everything in the data.
+    pub new_ops: DTRange,
+
+    /// The names of the agents which made the new operations. Sorted.
+    pub agents: Vec<SmartString>,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -439,23 +687,148 @@ impl Default for DecodeOptions {
         Self {
             ignore_crc: false,
             verbose: false,
+            allow_missing_content: false,
+            cipher: None,
+            verify_signatures: None,
+            require_doc_id: false,
+            max_ops: None,
+            max_content_bytes: None,
+            max_agents: None,
+            require_crc: false,
+            require_contiguous_seqs: false,
+            check_positions: false,
+            trust_transformed_positions: false,
+            #[cfg(feature = "dag_hash")]
+            verify_version_hash: false,
+            lazy_content: false,
+        }
+    }
+}
+
+impl DecodeOptions {
+    pub fn with_cipher<C: ChunkCipher + 'static>(mut self, cipher: C) -> Self {
+        self.cipher = Some(Arc::new(cipher));
+        self
+    }
+
+    /// Set all the resource limits at once.
+    pub fn with_limits(mut self, max_ops: usize, max_content_bytes: usize, max_agents: usize) -> Self {
+        self.max_ops = Some(max_ops);
+        self.max_content_bytes = Some(max_content_bytes);
+        self.max_agents = Some(max_agents);
+        self
+    }
+}
+
+fn check_limit(max: Option<usize>, limit: DecodeLimit, amount: usize) -> Result<(), ParseError> {
+    match max {
+        Some(max) if amount > max => Err(ParseError::LimitExceeded { limit, max }),
+        _ => Ok(()),
+    }
+}
+
+/// Decompress the contents of a CompressedFields chunk.
+#[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables, unreachable_code))]
+pub(super) fn decompress_chunk(format: CompressionFormat, mut c: BufReader, max_len: Option<usize>) -> Result<Vec<u8>, ParseError> {
+    let uncompressed_len = c.next_usize()?;
+    check_limit(max_len, DecodeLimit::ContentBytes, uncompressed_len)?;
+
+    match format {
+        #[cfg(feature = "lz4")]
+        CompressionFormat::LZ4 => {
+            // LZ4 can't compress data by more than a factor of 255. Check that here so corrupt
+            // lengths don't make us try to allocate huge buffers.
+            if uncompressed_len > c.0.len().saturating_mul(255).saturating_add(16) {
+                return Err(ParseError::LZ4DecompressionError);
+            }
+
+            // The rest of the bytes contain lz4 compressed data.
+            lz4_flex::decompress(c.0, uncompressed_len)
+                .map_err(|_e| ParseError::LZ4DecompressionError)
         }
+        #[cfg(feature = "zstd")]
+        CompressionFormat::Zstd => {
+            // Zstd doesn't have a useful bound on the compression ratio. But zstd frames store
+            // their decompressed size, so check it matches before allocating anything.
+            match zstd::zstd_safe::get_frame_content_size(c.0) {
+                Ok(Some(len)) if len == uncompressed_len as u64 => {}
+                _ => return Err(ParseError::ZstdDecompressionError),
+            }
+
+            // The output buffer is sized from the stored length. Data which decompresses to more
+            // than that is an error, rather than being truncated.
+            let result = zstd::bulk::decompress(c.0, uncompressed_len)
+                .map_err(|_e| ParseError::ZstdDecompressionError)?;
+            if result.len() != uncompressed_len {
+                return Err(ParseError::ZstdDecompressionError);
+            }
+            Ok(result)
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(ParseError::UnknownCompressionFormat),
     }
 }
 
+/// If `inner` is a slice of `outer`, returns its range within `outer`.
+fn subslice_range(outer: &[u8], inner: &[u8]) -> Option<Range<usize>> {
+    let start = (inner.as_ptr() as usize).checked_sub(outer.as_ptr() as usize)?;
+    if start + inner.len() <= outer.len() { Some(start..start + inner.len()) } else { None }
+}
+
+/// The size of an oplog at some point in time. See [`ListOpLog::checkpoint`].
+#[derive(Debug, Clone)]
+pub(crate) struct OpLogCheckpoint {
+    /// Total (unmerged) number of operations.
+    len: LV,
+    doc_id: Option<SmartString>,
+    version: Frontier,
+    num_known_agents: usize,
+    ins_content_length: usize,
+    del_content_length: usize,
+    num_signatures: usize,
+    num_fork_points: usize,
+    had_transformed_positions: bool,
+}
+
 impl ListOpLog {
     pub fn load_from(data: &[u8]) -> Result<Self, ParseError> {
         let mut oplog = Self::new();
-        oplog.decode_internal(data, DecodeOptions::default())?;
+        oplog.decode_internal(data, DecodeOptions::default(), None, None)?;
         Ok(oplog)
     }
 
     pub fn load_from_opts(data: &[u8], opts: DecodeOptions) -> Result<Self, ParseError> {
         let mut oplog = Self::new();
-        oplog.decode_internal(data, opts)?;
+        oplog.decode_internal(data, opts, None, None)?;
         Ok(oplog)
     }
 
+    /// Load an oplog from a shared buffer. Unlike [`load_from`](ListOpLog::load_from), inserted
+    /// and deleted content isn't copied out of the buffer. The oplog keeps a reference to the
+    /// buffer instead. This makes opening large documents faster, and saves memory when the buffer
+    /// is a memory mapped file.
+    ///
+    /// Content can only be borrowed if it was stored uncompressed (see
+    /// [`EncodeOptions::compress_content`](crate::list::encoding::EncodeOptions::compress_content)).
+    /// Compressed content is decompressed and copied as usual.
+    pub fn load_from_shared(data: SharedBytes) -> Result<Self, ParseError> {
+        Self::load_from_shared_opts(data, DecodeOptions::default())
+    }
+
+    /// Load an oplog from a shared buffer with the given options. Set
+    /// [`DecodeOptions::lazy_content`] to avoid reading the content while loading.
+    pub fn load_from_shared_opts(data: SharedBytes, opts: DecodeOptions) -> Result<Self, ParseError> {
+        let mut oplog = Self::new();
+        oplog.decode_internal((*data).as_ref(), opts, Some(&data), None)?;
+        Ok(oplog)
+    }
+
+    /// Add all operations from a shared buffer into this document, referencing content in the
+    /// buffer rather than copying it. See [`load_from_shared`](ListOpLog::load_from_shared).
+    pub fn decode_and_add_shared(&mut self, data: &SharedBytes) -> Result<Frontier, ParseError> {
+        self.decode_and_add_internal((**data).as_ref(), DecodeOptions::default(), Some(data), None)
+    }
+
     /// Add all operations from a binary chunk into this document.
     ///
     /// Any duplicate operations are ignored.
@@ -474,112 +847,233 @@ impl ListOpLog {
     /// This method takes an options object, which for now doesn't do much. Most users should just
     /// call [`OpLog::decode_and_add`](OpLog::decode_and_add)
     pub fn decode_and_add_opts(&mut self, data: &[u8], opts: DecodeOptions) -> Result<Frontier, ParseError> {
-        // In order to merge data safely, when an error happens we need to unwind all the merged
-        // operations before returning. Otherwise self is in an invalid state.
-        //
-        // The merge_data method is append-only, so really we just need to trim back all the data
-        // that has been (partially) added.
-
-        // Total (unmerged) number of operations before this data is merged in.
-        let len = self.len();
+        self.decode_and_add_internal(data, opts, None, None)
+    }
 
-        // We could regenerate the frontier, but this is much lazier.
-        let doc_id = self.doc_id.clone();
-        let old_frontier = self.cg.version.clone();
-        let num_known_agents = self.cg.agent_assignment.client_data.len();
-        let ins_content_length = self.operation_ctx.ins_content.len();
-        let del_content_length = self.operation_ctx.del_content.len();
+    /// Validate data received from a remote peer, and add its operations to the oplog. This is
+    /// designed for relay servers, which need to check and store incoming patches before
+    /// forwarding them, but never need the document's content.
+    ///
+    /// This does everything [`decode_and_add`](ListOpLog::decode_and_add) does - the causal
+    /// parents of all operations must be known and the checksum must match - but it also requires
+    /// that the data has a checksum, and that it doesn't leave gaps in any agent's sequence
+    /// numbers. Nothing is ever checked out. If the data is invalid, the oplog is left unchanged.
+    ///
+    /// Data from untrusted peers should also be checked against resource limits. See
+    /// [`ingest_and_validate_opts`](ListOpLog::ingest_and_validate_opts).
+    pub fn ingest_and_validate(&mut self, data: &[u8]) -> Result<IngestSummary, ParseError> {
+        self.ingest_and_validate_opts(data, DecodeOptions::default())
+    }
 
-        let result = self.decode_internal(data, opts);
+    /// Like [`ingest_and_validate`](ListOpLog::ingest_and_validate), with options. The checksum
+    /// and sequence number checks are always turned on.
+    pub fn ingest_and_validate_opts(&mut self, data: &[u8], mut opts: DecodeOptions) -> Result<IngestSummary, ParseError> {
+        opts.ignore_crc = false;
+        opts.require_crc = true;
+        opts.require_contiguous_seqs = true;
+
+        let start = self.len();
+        let version = self.decode_and_add_internal(data, opts, None, None)?;
+        let new_ops: DTRange = (start..self.len()).into();
+
+        let aa = &self.cg.agent_assignment;
+        let mut agents: Vec<SmartString> = aa.client_with_lv.iter_range(new_ops)
+            .map(|KVPair(_, span)| aa.get_agent_name(span.agent).into())
+            .collect();
+        agents.sort_unstable();
+        agents.dedup();
+
+        Ok(IngestSummary { version, new_ops, agents })
+    }
 
-        if result.is_err() {
-            // Unwind changes back to len.
-            // This would be nicer with an RleVec iterator, but the iter implementation doesn't
-            // support iterating backwards.
-            self.doc_id = doc_id;
-
-            while let Some(last) = self.cg.agent_assignment.client_with_lv.0.last_mut() {
-                debug_assert!(len <= last.end());
-                if len == last.end() { break; }
-                else {
-                    // Truncate!
-                    let KVPair(_, removed) = if len <= last.0 {
-                        // Drop entire entry
-                        self.cg.agent_assignment.client_with_lv.0.pop().unwrap()
-                    } else {
-                        last.truncate(len - last.0)
-                    };
+    /// Check that the operations from `from` onwards don't leave gaps in their agents' sequence
+    /// numbers. Only the new spans (and the spans either side of them) are checked, so gaps which
+    /// were already there are ignored unless a new span is next to them.
+    fn seqs_are_contiguous(&self, from: LV) -> bool {
+        let aa = &self.cg.agent_assignment;
+        aa.client_with_lv.iter_range(from..self.len()).all(|KVPair(_, span)| {
+            let seqs = &aa.client_data[span.agent as usize].lv_for_seq;
+            // The new span is always in lv_for_seq.
+            let first = seqs.find_index(span.seq_range.start).unwrap();
+            let last = seqs.find_index(span.seq_range.last()).unwrap();
+            let entries = &seqs.0[first.saturating_sub(1)..(last + 2).min(seqs.0.len())];
+
+            (first > 0 || entries[0].0 == 0)
+                && entries.windows(2).all(|w| w[0].end() == w[1].0)
+        })
+    }
 
-                    let client_data = &mut self.cg.agent_assignment.client_data[removed.agent as usize];
-                    client_data.lv_for_seq.remove_ctx(removed.seq_range, &());
+    /// Check that every operation from `from` onwards only inserts and deletes at positions which
+    /// exist in the document at the operation's parent version. Operations before `from` are
+    /// assumed to be valid.
+    fn check_positions(&self, from: LV) -> Result<(), ParseError> {
+        // The tracker is only ever moved to versions which have already been checked.
+        let mut tracker = RewindTracker::new();
+        let mut version = Frontier::root();
+        let mut doc_len = 0;
+
+        for e in self.cg.iter_range((from..self.len()).into()) {
+            for op in tracker.diff(self, version.as_ref(), e.parents.as_ref()) {
+                match op.kind {
+                    Ins => doc_len += op.len(),
+                    Del => doc_len -= op.len(),
                 }
             }
 
-            let num_operations = self.operations.end();
-            if num_operations > len {
-                self.operations.remove_ctx((len..num_operations).into(), &self.operation_ctx);
+            let span: DTRange = (e.start..e.start + e.span.len() as LV).into();
+            for (KVPair(lv, op), _) in self.iter_range_simple(span) {
+                let valid = match op.kind {
+                    Ins => op.start() <= doc_len,
+                    Del => op.end() <= doc_len,
+                };
+                if !valid {
+                    return Err(ParseError::InvalidPosition { offset: lv_to_usize(lv - from) });
+                }
+                match op.kind {
+                    Ins => doc_len += op.len(),
+                    Del => doc_len -= op.len(),
+                }
             }
+            version = Frontier::new_1(span.last());
+        }
+        Ok(())
+    }
+
+    /// If `base_hint` is passed, data which depends on unknown snapshot operations (made by
+    /// [`squash_before`](ListOpLog::squash_before)) is treated as if it depended on the hinted
+    /// version instead. See [`ListOpLog::rebase_foreign_patch`].
+    pub(crate) fn decode_and_add_internal(&mut self, data: &[u8], opts: DecodeOptions, shared: Option<&SharedBytes>, base_hint: Option<&[LV]>) -> Result<Frontier, ParseError> {
+        // In order to merge data safely, when an error happens we need to unwind all the merged
+        // operations before returning. Otherwise self is in an invalid state.
+        let checkpoint = self.checkpoint();
 
-            // Trim history
-            let hist_entries = &mut self.cg.graph.entries;
-            let history_length = hist_entries.end();
-            if history_length > len {
-                // We can't use entries.remove because HistoryEntry doesn't support SplitableSpan.
-                // And also because we need to update child_indexes.
-                let del_span_start = len;
+        let result = self.decode_internal(data, opts, shared, base_hint);
 
-                let first_idx = hist_entries.find_index(len).unwrap();
+        if result.is_err() {
+            self.roll_back(checkpoint);
+        }
 
-                let e = &mut hist_entries.0[first_idx];
-                let first_truncated_idx = if del_span_start > e.span.start {
-                    // The first entry just needs to be trimmed down.
-                    e.span.truncate_from(del_span_start);
-                    first_idx + 1
+        result
+    }
+
+    /// Remember the size of the oplog, so anything added after this point can be removed again
+    /// with [`roll_back`](ListOpLog::roll_back).
+    pub(crate) fn checkpoint(&self) -> OpLogCheckpoint {
+        OpLogCheckpoint {
+            len: self.len(),
+            doc_id: self.doc_id.clone(),
+            // We could regenerate the frontier, but this is much lazier.
+            version: self.cg.version.clone(),
+            num_known_agents: self.cg.agent_assignment.client_data.len(),
+            ins_content_length: self.operation_ctx.ins_content.end(),
+            del_content_length: self.operation_ctx.del_content.end(),
+            num_signatures: self.signatures.len(),
+            num_fork_points: self.fork_points.len(),
+            had_transformed_positions: self.transformed_positions.is_some(),
+        }
+    }
+
+    /// Remove everything added to the oplog since the checkpoint was taken.
+    ///
+    /// The oplog is append-only, so really we just need to trim back all the data that has been
+    /// (partially) added.
+    pub(crate) fn roll_back(&mut self, checkpoint: OpLogCheckpoint) {
+        let len = checkpoint.len;
+
+        // Unwind changes back to len.
+        // This would be nicer with an RleVec iterator, but the iter implementation doesn't
+        // support iterating backwards.
+        self.doc_id = checkpoint.doc_id;
+
+        while let Some(last) = self.cg.agent_assignment.client_with_lv.0.last_mut() {
+            debug_assert!(len <= last.end());
+            if len == last.end() { break; }
+            else {
+                // Truncate!
+                let KVPair(_, removed) = if len <= last.0 {
+                    // Drop entire entry
+                    self.cg.agent_assignment.client_with_lv.0.pop().unwrap()
                 } else {
-                    first_idx
+                    last.truncate(lv_to_usize(len - last.0))
                 };
 
-                let mut idx = first_truncated_idx;
-
-                // Go through and unwind from idx.
-                while idx < hist_entries.num_entries() {
-                    // Cloning here is an ugly and kinda slow hack to work around the borrow
-                    // checker. But this whole case is rare anyway, so idk.
-                    let parents = hist_entries.0[idx].parents.clone();
-
-                    for p in parents {
-                        if p < len { // If p >= len, the target will be discarded anyway.
-                            let parent_entry = hist_entries.find_mut(p).unwrap().0;
-                            while let Some(&c_idx) = parent_entry.child_indexes.last() {
-                                if c_idx >= first_truncated_idx {
-                                    parent_entry.child_indexes.pop();
-                                } else { break; }
-                            }
-                        }
-                    }
+                let client_data = &mut self.cg.agent_assignment.client_data[removed.agent as usize];
+                client_data.lv_for_seq.remove_ctx(removed.seq_range, &());
+            }
+        }
 
-                    idx += 1;
-                }
+        let num_operations = self.operations.end();
+        if num_operations > len {
+            self.operations.remove_ctx(len..num_operations, &self.operation_ctx);
+        }
 
-                self.cg.graph.entries.0.truncate(first_truncated_idx);
+        // Trim history
+        let hist_entries = &mut self.cg.graph.entries;
+        let history_length = hist_entries.end();
+        if history_length > len {
+            // We can't use entries.remove because HistoryEntry doesn't support SplitableSpan.
+            // And also because we need to update child_indexes.
+            let del_span_start = len;
+
+            let first_idx = hist_entries.find_index(len).unwrap();
+
+            let e = &mut hist_entries.0[first_idx];
+            let first_truncated_idx = if del_span_start > e.span.start {
+                // The first entry just needs to be trimmed down.
+                e.span.truncate_from(del_span_start);
+                first_idx + 1
+            } else {
+                first_idx
+            };
 
-                while let Some(&last_idx) = self.cg.graph.root_child_indexes.last() {
-                    if last_idx >= self.cg.graph.entries.num_entries() {
-                        self.cg.graph.root_child_indexes.pop();
-                    } else { break; }
+            let mut idx = first_truncated_idx;
+
+            // Go through and unwind from idx.
+            while idx < hist_entries.num_entries() {
+                // Cloning here is an ugly and kinda slow hack to work around the borrow
+                // checker. But this whole case is rare anyway, so idk.
+                let parents = hist_entries.0[idx].parents.clone();
+
+                for p in parents {
+                    if p < len { // If p >= len, the target will be discarded anyway.
+                        let parent_entry = hist_entries.find_mut(p).unwrap().0;
+                        while let Some(&c_idx) = parent_entry.child_indexes.last() {
+                            if c_idx >= first_truncated_idx {
+                                parent_entry.child_indexes.pop();
+                            } else { break; }
+                        }
+                    }
                 }
-            }
 
-            // Remove excess agents
-            self.cg.agent_assignment.client_data.truncate(num_known_agents);
+                idx += 1;
+            }
 
-            self.operation_ctx.ins_content.truncate(ins_content_length);
-            self.operation_ctx.del_content.truncate(del_content_length);
+            self.cg.graph.entries.0.truncate(first_truncated_idx);
 
-            self.cg.version = old_frontier;
+            while let Some(&last_idx) = self.cg.graph.root_child_indexes.last() {
+                if last_idx >= self.cg.graph.entries.num_entries() {
+                    self.cg.graph.root_child_indexes.pop();
+                } else { break; }
+            }
         }
 
-        result
+        self.cg.agent_assignment.client_data.truncate(checkpoint.num_known_agents);
+
+        self.operation_ctx.ins_content.truncate(checkpoint.ins_content_length);
+        self.operation_ctx.del_content.truncate(checkpoint.del_content_length);
+
+        self.cg.version = checkpoint.version;
+        self.signatures.truncate(checkpoint.num_signatures);
+        self.fork_points.truncate(checkpoint.num_fork_points);
+        // Metadata set on the new operations may have been joined onto an earlier run.
+        let num_metadata = self.op_metadata.partition_point(|(r, _)| r.start < len);
+        self.op_metadata.truncate(num_metadata);
+        if let Some((r, _)) = self.op_metadata.last_mut() { r.end = r.end.min(len); }
+        let num_transactions = self.transactions.partition_point(|t| t.end <= len);
+        self.transactions.truncate(num_transactions);
+        if !checkpoint.had_transformed_positions { self.transformed_positions = None; }
+        #[cfg(feature = "dag_hash")]
+        self.hash_cache.truncate(len);
     }
 
     /// Merge data from the remote source into our local document state.
@@ -587,7 +1081,19 @@ impl ListOpLog {
     /// NOTE: This code is quite new.
     /// TODO: Currently if this method returns an error, the local state is undefined & invalid.
     /// Until this is fixed, the signature of the method will stay kinda weird to prevent misuse.
-    fn decode_internal(&mut self, data: &[u8], opts: DecodeOptions) -> Result<Frontier, ParseError> {
+    ///
+    /// If `shared` is passed, it must contain the same bytes as `data`. Content is referenced from
+    /// the shared buffer where possible.
+    fn decode_internal(&mut self, data: &[u8], opts: DecodeOptions, shared: Option<&SharedBytes>, base_hint: Option<&[LV]>) -> Result<Frontier, ParseError> {
+        // Encrypted chunks are decrypted up front, and then we parse the decrypted file instead.
+        if let Some(plaintext) = decrypt_file(data, &opts)? {
+            return self.decode_internal(&plaintext, opts, None, base_hint);
+        }
+
+        let len_before = self.len();
+        // Everything in a squashed oplog comes after its snapshot. (See squash_before).
+        let squashed = self.squash_base().is_some();
+
         // Written to be symmetric with encode functions.
         let mut reader = BufReader(data);
 
@@ -598,7 +1104,7 @@ impl ListOpLog {
 
         reader.read_magic()?;
         let protocol_version = reader.next_usize()?;
-        if protocol_version != PROTOCOL_VERSION {
+        if !format::is_supported_version(protocol_version) {
             return Err(ParseError::UnsupportedProtocolVersion);
         }
 
@@ -608,57 +1114,89 @@ impl ListOpLog {
         // *** Compressed data ***
         // If there is a compressed chunk, it can contain data for other fields, all mushed
         // together.
-        let mut compressed_chunk;
-
-        #[cfg(not(feature = "lz4"))] {
-            compressed_chunk = None;
-            if reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)?.is_some() {
-                return Err(ParseError::LZ4DecoderNeeded);
+        //
+        // If the compressed chunk uses a format we can't decompress, we skip it. Compressed
+        // content in the start branch is optional, so we can carry on without it. Compressed patch
+        // content is an error unless DecodeOptions::allow_missing_content is set.
+        let mut compressed_unavailable = None;
+        let _compressed_chunk_raw: Option<Vec<u8>> = match reader.0.peek_u32()?.and_then(CompressionFormat::from_chunk_type) {
+            None => None,
+            Some(format) => {
+                let (_, c) = reader.next_chunk_checked()?;
+                match format {
+                    Some(format) if format.is_supported() => Some(decompress_chunk(format, c, opts.max_content_bytes)?),
+                    Some(CompressionFormat::LZ4) => {
+                        compressed_unavailable = Some(ParseError::LZ4DecoderNeeded);
+                        None
+                    }
+                    _ => {
+                        compressed_unavailable = Some(ParseError::UnknownCompressionFormat);
+                        None
+                    }
+                }
             }
-        }
-
-        let _compressed_chunk_raw: Option<Vec<u8>>; // Pulled out so its lifetime escapes the block.
-        #[cfg(feature = "lz4")] {
-            _compressed_chunk_raw = if let Some(mut c) = reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)? {
-                let uncompressed_len = c.next_usize()?;
+        };
 
-                // The rest of the bytes contain lz4 compressed data.
-                let data = lz4_flex::decompress(c.0, uncompressed_len)
-                    .map_err(|_e| ParseError::LZ4DecompressionError)?;
-                Some(data)
-            } else { None };
-
-            // To consume from compressed_chunk_raw, we'll make a slice that we can iterate through.
-            compressed_chunk = _compressed_chunk_raw.as_ref().map(|b| BufReader(b));
-        }
+        // To consume from compressed_chunk_raw, we'll make a slice that we can iterate through.
+        let mut compressed_chunk = _compressed_chunk_raw.as_ref().map(|b| BufReader(b));
 
         // *** FileInfo ***
         // fileinfo has DocID, UserData and AgentNames.
         // The agent_map is a map from agent_id in the file to agent_id in self.
         let FileInfoData {
-            userdata: _userdata, doc_id, mut agent_map,
-        } = reader.read_fileinfo(self)?;
+            userdata: _userdata, doc_id, fork_points, mut agent_map, agent_info, insert_tie_break,
+        } = reader.read_fileinfo(self, protocol_version, opts.max_agents)?;
+
+        // Merging with a different tie break policy would order concurrent inserts differently
+        // from the file's other replicas.
+        if insert_tie_break != self.insert_tie_break().custom_name() {
+            return Err(ParseError::TieBreakMismatch);
+        }
 
-        // If we already have a doc_id, make sure they match before merging.
+        // If we already have a doc_id, make sure they match before merging. Data from documents
+        // forked from this document (or vice versa) can be merged, but we keep our doc_id.
         if let Some(file_doc_id) = doc_id {
-            if let Some(local_doc_id) = self.doc_id.as_ref() {
-                if file_doc_id != local_doc_id && !self.is_empty() {
-                    return Err(ParseError::DocIdMismatch);
+            if self.is_empty() || self.doc_id.is_none() {
+                self.doc_id = Some(file_doc_id.into());
+            } else if !self.is_related_document(file_doc_id, &fork_points) {
+                return Err(ParseError::DocIdMismatch);
+            }
+        } else if opts.require_doc_id && self.doc_id.is_some() && !self.is_empty() {
+            return Err(ParseError::DocIdMissing);
+        }
+        self.add_fork_points(fork_points);
+
+        // If we already have the file's version, check its hash before merging anything. A
+        // mismatch means the file has different history for operations we already know about.
+        #[cfg(feature = "dag_hash")]
+        if opts.verify_version_hash {
+            if let Some((version, hash)) = find_version_hash(reader.clone())? {
+                if let Ok(version) = self.cg.agent_assignment.try_remote_to_local_frontier(version.iter()) {
+                    if self.hash_for_version(version.as_ref()) != hash {
+                        return Err(ParseError::VersionHashMismatch);
+                    }
                 }
             }
-            self.doc_id = Some(file_doc_id.into());
         }
 
         // *** StartBranch ***
         let mut start_branch = reader.expect_chunk(ListChunkType::StartBranch)?.chunks();
 
-        // Start version - which if missing defaults to ROOT ([]).
-        let start_version = start_branch.read_version(self, &agent_map)?;
+        // Start version - which if missing defaults to ROOT ([]). Its stored either as a frontier
+        // or as a summary.
+        let mut start_version = start_branch.read_version(self, &agent_map, base_hint)?;
+        if let Some(chunk) = start_branch.read_chunk_if_eq(ListChunkType::VersionSummary)? {
+            if !start_version.is_root() { return Err(ParseError::GenericInvalidData); }
+            start_version = summary_to_version(self, &read_summary(chunk)?, base_hint)?;
+        }
 
         // The start branch also optionally contains the document content at this version. We can't
         // use it yet (NYI) but it needs to be parsed because it because it might be compressed.
         if !start_branch.is_empty() {
-            let _start_content = start_branch.expect_content_str(compressed_chunk.as_mut())?;
+            let _start_content = match start_branch.expect_content_str(compressed_chunk.as_mut()) {
+                Err(ParseError::CompressedDataMissing) if compressed_unavailable.is_some() => None,
+                r => Some(r?),
+            };
             // dbg!(start_content);
             // TODO! Attach start_content if we're empty and start_version != ROOT.
         }
@@ -676,11 +1214,29 @@ impl ListOpLog {
             let mut patch_chunk = reader.expect_chunk(ListChunkType::Patches)?
                 .chunks();
 
+            // Content can only be loaded lazily if every operation in the patches is new, since
+            // content for operations we already have is compared with the local content.
+            let lazy_content = opts.lazy_content && shared.is_some() && !patches_overlap;
             let mut ins_content = None;
             let mut del_content = None;
+            let mut content_bytes = 0usize;
 
             while let Some(chunk) = patch_chunk.read_chunk_if_eq(ListChunkType::PatchContent)? {
-                let (tag, content_chunk) = ReadPatchContentIter::new(chunk, compressed_chunk.as_mut())?;
+                let (tag, mut content_chunk, lazy) = match (ReadPatchContentIter::new(chunk, compressed_chunk.as_mut(), lazy_content), &compressed_unavailable) {
+                    // The content is compressed in a format we can't read.
+                    (Err(ParseError::CompressedDataMissing), Some(_)) if opts.allow_missing_content => continue,
+                    (Err(ParseError::CompressedDataMissing), Some(err)) => return Err(*err),
+                    (r, _) => r?,
+                };
+                if let (Some((bytes, chars)), Some(shared)) = (lazy, shared) {
+                    content_bytes += bytes.len();
+                    let range = subslice_range(data, bytes).ok_or(ParseError::InvalidContent)?;
+                    let pos = self.operation_ctx.push_lazy(tag, shared, range, chars);
+                    content_chunk.lazy_pos = Some(lv_to_usize(pos.start));
+                } else {
+                    content_bytes += content_chunk.content.len();
+                }
+                check_limit(opts.max_content_bytes, DecodeLimit::ContentBytes, content_bytes)?;
                 // let iter = content_chunk.take_max();
                 let iter = content_chunk.buffered();
                 match tag {
@@ -728,7 +1284,10 @@ impl ListOpLog {
             let mut version_map = RleVec::new();
 
             // Take and merge the next exactly n patches
-            let mut parse_next_patches = |oplog: &mut ListOpLog, mut n: usize, keep: bool| -> Result<(), ParseError> {
+            //
+            // If dup_of is set, the patches are already in the oplog (starting at that version), and
+            // they're checked against the local operations instead of being merged.
+            let mut parse_next_patches = |oplog: &mut ListOpLog, mut n: usize, mut dup_of: Option<LV>| -> Result<(), ParseError> {
                 while n > 0 {
                     let mut max_len = n;
 
@@ -753,17 +1312,40 @@ impl ListOpLog {
                             }
                         } else { None };
 
-                        assert!(max_len > 0);
+                        if max_len == 0 { return Err(ParseError::InvalidLength); }
                         n -= max_len;
 
                         let remainder = op.trim_ctx(max_len, &dummy_ctx);
 
-                        // dbg!(keep, (next_patch_time, &op, content_here));
+                        // dbg!(dup_of, (next_patch_time, &op, content_here));
 
                         // self.operations.push(KVPair(next_time, op));
-                        if keep {
-                            oplog.push_op_internal(next_patch_time, op.loc, op.kind, content_here);
-                            next_patch_time += max_len;
+                        if let Some(lv) = dup_of {
+                            let content = content_here.map(|c| match c {
+                                ItemContent::Str(c) => c.into(),
+                                ItemContent::Lazy(_) => unreachable!("Content is never lazy when patches overlap"),
+                            });
+                            let remote_op = TextOperation { loc: op.loc, kind: op.kind, content };
+                            if let Some(v) = oplog.find_conflicting_op(lv, remote_op) {
+                                let (agent, seq) = oplog.cg.agent_assignment.local_to_agent_version(v);
+                                return Err(ParseError::DuplicateId { agent, seq });
+                            }
+                            dup_of = Some(lv + max_len as LV);
+                        } else {
+                            match content_here {
+                                Some(ItemContent::Lazy(pos)) => {
+                                    oplog.push_op_internal_lazy(next_patch_time, op.loc, op.kind, (pos as LV..(pos + max_len) as LV).into());
+                                }
+                                Some(ItemContent::Str(c)) => {
+                                    if let Some((s, range)) = shared.and_then(|s| subslice_range(data, c.as_bytes()).map(|r| (s, r))) {
+                                        oplog.push_op_internal_shared(next_patch_time, op.loc, op.kind, s, range);
+                                    } else {
+                                        oplog.push_op_internal(next_patch_time, op.loc, op.kind, Some(c));
+                                    }
+                                }
+                                None => oplog.push_op_internal(next_patch_time, op.loc, op.kind, None),
+                            }
+                            next_patch_time += max_len as LV;
                         }
 
                         if let Some(r) = remainder {
@@ -778,6 +1360,8 @@ impl ListOpLog {
             };
 
             while let Some(mut crdt_span) = agent_assignment_chunk.read_next_agent_assignment(&mut agent_map)? {
+                check_limit(opts.max_ops, DecodeLimit::Ops,
+                            lv_to_usize(next_file_time - new_op_start).saturating_add(crdt_span.len()))?;
                 // let mut crdt_span = crdt_span; // TODO: Remove me. Blerp clion.
                 // dbg!(crdt_span);
                 if crdt_span.agent as usize >= self.cg.agent_assignment.client_data.len() {
@@ -794,22 +1378,26 @@ impl ListOpLog {
                         // dbg!((crdt_span.seq_range, span, offset));
                         let (span_end, overlap_start) = match span {
                             // Skip the entry.
-                            Ok(entry) => (entry.end(), Some(entry.1.start + offset)),
+                            Ok(entry) => (entry.end(), Some(entry.1.start + offset as LV)),
                             // Consume the entry
-                            Err(empty_span) => (empty_span.end, None),
+                            Err(empty_span) => (empty_span.end as LV, None),
                         };
 
                         let end = crdt_span.seq_range.end.min(span_end);
                         let consume_here = crdt_span.seq_range.truncate_keeping_right_from(end);
-                        let len = consume_here.len();
+                        let len = consume_here.len() as LV;
 
-                        let keep = if let Some(overlap_start) = overlap_start {
+                        let dup_of = if let Some(overlap_start) = overlap_start {
                             let overlap = (overlap_start .. overlap_start + len).into();
                             // There's overlap. We'll filter out this item.
-                            version_map.push_rle(KVPair(next_file_time, overlap));
+                            version_map.push(KVPair(next_file_time, overlap));
                             // println!("push overlap {:?}", KVPair(next_file_time, overlap));
-                            false
+                            Some(overlap_start)
                         } else {
+                            // New snapshot operations would duplicate our own snapshot's content.
+                            if len_before > 0 && is_squash_agent(self.get_agent_name(crdt_span.agent)) {
+                                return Err(ParseError::SquashBaseMismatch);
+                            }
                             self.assign_time_to_crdt_span(next_assignment_time, AgentSpan {
                                 agent: crdt_span.agent,
                                 seq_range: consume_here,
@@ -819,18 +1407,18 @@ impl ListOpLog {
                             //     next_file_time,
                             //     TimeSpan::from(next_assignment_time..next_assignment_time + len),
                             // ));
-                            version_map.push_rle(KVPair(
+                            version_map.push(KVPair(
                                 next_file_time,
                                 (next_assignment_time..next_assignment_time + len).into(),
                             ));
                             next_assignment_time += len;
-                            true
+                            None
                         };
                         next_file_time += len;
 
                         // dbg!(&file_to_local_version_map);
 
-                        parse_next_patches(self, len, keep)?;
+                        parse_next_patches(self, len as usize, dup_of)?;
 
                         // And deal with history.
                         // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, keep)?;
@@ -840,12 +1428,23 @@ impl ListOpLog {
                     // Optimization - don't bother with the filtering code above if loaded changes
                     // follow local changes. Most calls to this function load into an empty
                     // document, and this is the case.
+
+                    // But corrupt files could still try to assign the same sequence numbers twice.
+                    let client = &self.cg.agent_assignment.client_data[crdt_span.agent as usize];
+                    match client.lv_for_seq.find_sparse(crdt_span.seq_range.start).0 {
+                        Err(gap) if gap.end as LV >= crdt_span.seq_range.end => {}
+                        _ => return Err(ParseError::InvalidLength),
+                    }
+                    if len_before > 0 && is_squash_agent(self.get_agent_name(crdt_span.agent)) {
+                        return Err(ParseError::SquashBaseMismatch);
+                    }
+
                     self.assign_time_to_crdt_span(next_assignment_time, crdt_span);
-                    let len = crdt_span.len();
+                    let len = crdt_span.len() as LV;
                     let timespan = (next_assignment_time..next_assignment_time+len).into();
                     // file_to_local_version_map.push_rle((next_assignment_time..next_assignment_time + len).into());
-                    version_map.push_rle(KVPair(next_file_time, timespan));
-                    parse_next_patches(self, len, true)?;
+                    version_map.push(KVPair(next_file_time, timespan));
+                    parse_next_patches(self, len as usize, None)?;
                     // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, true)?;
 
                     next_assignment_time += len;
@@ -857,16 +1456,16 @@ impl ListOpLog {
             // dbg!(&version_map);
             let mut next_history_time = first_new_time;
 
-            let mut file_frontier = start_version;
+            let mut file_frontier = start_version.clone();
 
             while !history_chunk.is_empty() {
-                let mut entry = history_chunk.next_history_entry(self, next_file_time, &agent_map)?;
+                let mut entry = history_chunk.next_history_entry(self, next_file_time, &agent_map, base_hint)?;
                 // So at this point the entry has underwater entry spans, and parents are underwater
                 // when they're local to the file (and non-underwater when they refer to our items).
                 // This makes the entry safe to truncate(), but we need to map it before we can use
                 // it.
 
-                next_file_time += entry.len();
+                next_file_time += entry.len() as LV;
                 // dbg!(&entry);
 
                 // If patches don't overlap, this code can be simplified to this:
@@ -878,16 +1477,31 @@ impl ListOpLog {
 
                 loop {
                     let (mut mapped, remainder)
-                        = history_entry_map_and_truncate(entry, &version_map);
+                        = history_entry_map_and_truncate(entry, &version_map)?;
                     // dbg!(&mapped);
                     mapped.parents.debug_check_sorted();
-                    assert!(mapped.span.start <= next_history_time);
+                    if mapped.span.start > next_history_time {
+                        return Err(ParseError::InvalidLength);
+                    }
 
-                    // We'll update merge parents even if nothing is merged.
+                    // We'll update merge parents even if nothing is merged. (Corrupt files can
+                    // contain the same operations twice).
+                    if file_frontier.0.contains(&mapped.span.start) || file_frontier.0.contains(&mapped.span.last()) {
+                        return Err(ParseError::GenericInvalidData);
+                    }
                     // dbg!((&file_frontier, &mapped));
                     file_frontier.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
                     // dbg!(&file_frontier);
 
+                    // Operations we already have must have the same parents as our copy.
+                    if mapped.span.start < next_history_time {
+                        let known = (mapped.span.start..mapped.span.end.min(next_history_time)).into();
+                        if let Some(v) = self.find_conflicting_parents(mapped.parents.as_ref(), known) {
+                            let (agent, seq) = self.cg.agent_assignment.local_to_agent_version(v);
+                            return Err(ParseError::DuplicateId { agent, seq });
+                        }
+                    }
+
                     if mapped.span.end > next_history_time {
                         // We'll merge items from mapped.
 
@@ -895,13 +1509,18 @@ impl ListOpLog {
                         // separated in version_map. Its kinda ugly though - I'd like a better way
                         // to deal with this case.
                         if mapped.span.start < next_history_time {
-                            mapped.truncate_keeping_right(next_history_time - mapped.span.start);
+                            mapped.truncate_keeping_right(lv_to_usize(next_history_time - mapped.span.start));
+                        }
+
+                        // So new operations at the root must come from a different history.
+                        if squashed && mapped.parents.is_empty() {
+                            return Err(ParseError::SquashBaseMismatch);
                         }
 
                         self.cg.graph.push(mapped.parents.as_ref(), mapped.span);
                         self.cg.version.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
 
-                        next_history_time += mapped.len();
+                        next_history_time += mapped.len() as LV;
                     } // else we already have these entries. Filter them out.
 
                     if let Some(remainder) = remainder {
@@ -916,6 +1535,78 @@ impl ListOpLog {
             if next_patch_time != next_assignment_time { return Err(ParseError::InvalidLength); }
             if next_patch_time != next_history_time { return Err(ParseError::InvalidLength); }
 
+            if let Some(mut txn_chunk) = patch_chunk.read_chunk_if_eq(ListChunkType::Transactions)? {
+                let mut last_end: LV = 0;
+                while !txn_chunk.is_empty() {
+                    let start = last_end.checked_add(txn_chunk.next_lv()?).ok_or(ParseError::InvalidLength)?;
+                    let end = start.checked_add(txn_chunk.next_lv()?).ok_or(ParseError::InvalidLength)?;
+                    last_end = end;
+
+                    let file_end = new_op_start.checked_add(end).ok_or(ParseError::InvalidLength)?;
+                    let file_range: DTRange = (new_op_start + start..file_end).into();
+                    let range = map_contiguous_range(&version_map, file_range)
+                        .ok_or(ParseError::GenericInvalidData)?;
+                    if !self.try_add_transaction(range) {
+                        return Err(ParseError::GenericInvalidData);
+                    }
+                }
+            }
+
+            if let Some(mut chunk) = patch_chunk.read_chunk_if_eq(ListChunkType::OpMetadata)? {
+                let mut last_end: LV = 0;
+                let mut last_timestamp = 0u64;
+                while !chunk.is_empty() {
+                    let start = last_end.checked_add(chunk.next_lv()?).ok_or(ParseError::InvalidLength)?;
+                    let end = start.checked_add(chunk.next_lv()?).ok_or(ParseError::InvalidLength)?;
+                    last_end = end;
+
+                    let flags = chunk.next_usize()?;
+                    if flags > 3 { return Err(ParseError::GenericInvalidData); }
+                    let timestamp = if flags & 1 != 0 {
+                        last_timestamp = last_timestamp.wrapping_add(num_decode_zigzag_i64(chunk.next_u64()?) as u64);
+                        Some(last_timestamp)
+                    } else { None };
+                    let data = if flags & 2 != 0 {
+                        let len = chunk.next_usize()?;
+                        if len > MAX_OP_METADATA_LEN { return Err(ParseError::InvalidLength); }
+                        chunk.next_n_bytes(len)?.to_vec()
+                    } else { vec![] };
+                    let meta = OpMetadata { timestamp, data };
+
+                    // Metadata is only added for new operations. The file range might map to
+                    // several runs of local versions.
+                    let mut v = new_op_start.checked_add(start).ok_or(ParseError::InvalidLength)?;
+                    let file_end = new_op_start.checked_add(end).ok_or(ParseError::InvalidLength)?;
+                    while v < file_end {
+                        let (KVPair(_, mapped), offset) = version_map.find_with_offset(v)
+                            .ok_or(ParseError::GenericInvalidData)?;
+                        let len = (mapped.len() - offset) as LV;
+                        let len = len.min(file_end - v);
+                        let lv = mapped.start + offset as LV;
+                        if lv >= first_new_time {
+                            self.set_op_metadata((lv..lv + len).into(), meta.clone());
+                        }
+                        v += len;
+                    }
+                }
+            }
+
+            let xf_cancelled_chunk = patch_chunk.read_chunk_if_eq(ListChunkType::TransformedCancelsOps)?;
+            if let Some(xf_chunk) = patch_chunk.read_chunk_if_eq(ListChunkType::TransformedPositions)? {
+                let runs = read_transformed_positions(xf_cancelled_chunk, xf_chunk, lv_to_usize(next_file_time - new_op_start))?;
+                // The positions are relative to the file's start version, and they're only useful
+                // if the file's operations keep the same order locally. So we only keep them when
+                // loading into an empty oplog.
+                if opts.trust_transformed_positions && first_new_time == 0 && start_version.is_root() && !runs.is_empty() {
+                    self.transformed_positions = Some(TransformedPositions {
+                        version: self.cg.version.clone(),
+                        runs,
+                    });
+                }
+            } else if xf_cancelled_chunk.is_some() {
+                return Err(ParseError::MissingChunk(ListChunkType::TransformedPositions as _));
+            }
+
             // dbg!(&patch_chunk);
             patch_chunk.expect_empty()?;
             history_chunk.expect_empty()?;
@@ -936,7 +1627,100 @@ impl ListOpLog {
             file_frontier
         }; // End of patches
 
+        // *** Signatures ***
+        let mut signatures = vec![];
+        if let Some(mut chunk) = reader.read_chunk_if_eq(ListChunkType::Signatures)? {
+            let num_signatures = chunk.next_usize()?;
+            for _ in 0..num_signatures {
+                let agent = chunk.next_str()?.into();
+                let start = chunk.next_lv()?;
+                let end = start.checked_add(chunk.next_lv()?).ok_or(ParseError::InvalidLength)?;
+                let key_len = chunk.next_usize()?;
+                let public_key = chunk.next_n_bytes(key_len)?.to_vec();
+                let sig_len = chunk.next_usize()?;
+                let signature = chunk.next_n_bytes(sig_len)?.to_vec();
+                signatures.push(SignedRange { agent, seq_range: (start..end).into(), public_key, signature });
+            }
+            chunk.expect_empty()?;
+        }
+
+        // *** Checkpoints ***
+        let mut checkpoints = vec![];
+        if let Some(mut chunk) = reader.read_chunk_if_eq(ListChunkType::Checkpoints)? {
+            let num_checkpoints = chunk.next_usize()?;
+            for _ in 0..num_checkpoints {
+                let name = chunk.next_str()?.into();
+                let message = chunk.next_str()?.into();
+                let edit = chunk.next_usize()?;
+                let version = read_remote_frontier(&mut chunk)?;
+
+                // Checkpoints at versions we don't know about can't be stored. (This shouldn't
+                // happen unless the file is missing operations).
+                if let Ok(version) = self.cg.agent_assignment.try_remote_to_local_frontier(version.iter()) {
+                    checkpoints.push(Checkpoint { version, name, message, edit });
+                }
+            }
+            chunk.expect_empty()?;
+        }
+
+        // *** Named branches ***
+        let mut named_branches = vec![];
+        if let Some(mut chunk) = reader.read_chunk_if_eq(ListChunkType::NamedBranches)? {
+            let num_branches = chunk.next_usize()?;
+            for _ in 0..num_branches {
+                let name: SmartString = chunk.next_str()?.into();
+                let (edit, deleted) = strip_bit_usize(chunk.next_usize()?);
+                if deleted {
+                    named_branches.push((name, NamedBranch { edit, version: None }));
+                    continue;
+                }
+                let version = read_remote_frontier(&mut chunk)?;
+                if let Ok(version) = self.cg.agent_assignment.try_remote_to_local_frontier(version.iter()) {
+                    named_branches.push((name, NamedBranch { edit, version: Some(version) }));
+                }
+            }
+            chunk.expect_empty()?;
+        }
+
+        // *** Version hash ***
+        // This has to be checked once the operations have been merged in, below.
+        let version_hash = if let Some(mut chunk) = reader.read_chunk_if_eq(ListChunkType::VersionHash)? {
+            let version = read_remote_frontier(&mut chunk)?;
+            let hash = chunk.next_n_bytes(32)?;
+            chunk.expect_empty()?;
+            Some((version, hash))
+        } else { None };
+
+        // *** Pruned history ***
+        // This is only kept when loading into an empty oplog. See the shallow module.
+        let mut pruned = PrunedHistory::new();
+        if let Some(mut chunk) = reader.read_chunk_if_eq(ListChunkType::PrunedHistory)? {
+            let num_agents = chunk.next_usize()?;
+            for _ in 0..num_agents {
+                let name: SmartString = chunk.next_str()?.into();
+                let num_ranges = chunk.next_usize()?;
+                let mut ranges = Vec::with_capacity(num_ranges.min(chunk.len()));
+                for _ in 0..num_ranges {
+                    let start = chunk.next_lv()?;
+                    let len = chunk.next_lv()?;
+                    let end = start.checked_add(len).ok_or(ParseError::InvalidLength)?;
+                    ranges.push((start..end).into());
+                }
+                pruned.insert(name, ranges);
+            }
+            chunk.expect_empty()?;
+        }
+
+        // *** Content manifest ***
+        // This only describes how the file is laid out, so there's nothing to load. But we'll make
+        // sure its valid.
+        if let Some(chunk) = reader.read_chunk_if_eq(ListChunkType::ContentManifest)? {
+            read_manifest_chunk(chunk)?;
+        }
+
         // TODO: Move checksum check to the start, so if it fails we don't modify the document.
+        // The CRC covers any unknown chunks before it, so they need to be skipped first.
+        reader.skip_unknown_chunks()?;
         let reader_len = reader.0.len();
         if let Some(mut crc_reader) = reader.read_chunk_if_eq(ListChunkType::Crc)? {
             // So this is a bit dirty. The bytes which have been checksummed is everything up to
@@ -952,6 +1736,59 @@ impl ListOpLog {
                     return Err(ParseError::ChecksumFailed);
                 }
             }
+        } else if opts.require_crc {
+            return Err(ParseError::MissingChunk(ListChunkType::Crc as u32));
+        }
+
+        if opts.require_contiguous_seqs && !self.seqs_are_contiguous(len_before) {
+            return Err(ParseError::SeqGap);
+        }
+
+        if opts.check_positions {
+            self.check_positions(len_before)?;
+        }
+
+        #[cfg(feature = "dag_hash")]
+        if opts.verify_version_hash {
+            let (version, hash) = version_hash.ok_or(ParseError::MissingChunk(ListChunkType::VersionHash as u32))?;
+            let version = self.cg.agent_assignment.try_remote_to_local_frontier(version.iter())
+                .map_err(ParseError::InvalidRemoteID)?;
+            if self.hash_for_version(version.as_ref()) != hash {
+                return Err(ParseError::VersionHashMismatch);
+            }
+        }
+        #[cfg(not(feature = "dag_hash"))]
+        let _ = version_hash;
+
+        // If verification fails, the added signatures are rolled back with everything else.
+        let file_signatures: Vec<usize> = signatures.into_iter()
+            .map(|s| self.add_signature(s))
+            .collect();
+        if let Some(verifier) = opts.verify_signatures.as_deref() {
+            let mut verified = vec![];
+            if !file_signatures.iter().all(|&i| self.signature_is_valid(i, verifier, &mut verified)) {
+                return Err(ParseError::InvalidSignature);
+            }
+
+            // Every operation in the file needs to be signed. (Though the signatures could have
+            // been received earlier, in which case they're checked again here).
+            let (_, file_ranges) = self.cg.graph.diff(start_version.as_ref(), file_frontier.as_ref());
+            if !file_ranges.iter().all(|r| self.range_is_signed(*r, verifier, &mut verified)) {
+                return Err(ParseError::MissingSignature);
+            }
+        }
+
+        for c in checkpoints {
+            self.add_checkpoint_internal(c);
+        }
+        for (name, branch) in named_branches {
+            self.merge_named_branch(name, branch);
+        }
+        for (agent, rev, info) in agent_info {
+            self.merge_agent_info(agent, rev, info);
+        }
+        if len_before == 0 && !pruned.is_empty() {
+            self.set_pruned_history(pruned);
         }
 
         // self.frontier = end_frontier_chunk.read_full_frontier(&self)?;
@@ -960,6 +1797,70 @@ impl ListOpLog {
     }
 }
 
+/// Read the transformed positions of the `len` operations in the file. The cancelled chunk is
+/// only stored if some operations were cancelled.
+fn read_transformed_positions(mut cancelled: Option<BufReader>, mut positions: BufReader, len: usize) -> Result<Vec<(DTRange, Option<isize>)>, ParseError> {
+    let mut runs = vec![];
+    let mut pos = 0;
+    // The current run in the positions chunk.
+    let (mut moved_by, mut moved_len) = (0isize, 0usize);
+
+    while pos < len {
+        let (run_len, is_cancelled) = match cancelled.as_mut() {
+            Some(c) => strip_bit_usize(c.next_usize()?),
+            None => (len - pos, false),
+        };
+        if run_len == 0 || run_len > len - pos { return Err(ParseError::InvalidLength); }
+
+        if is_cancelled {
+            runs.push(((pos as LV..(pos + run_len) as LV).into(), None));
+            pos += run_len;
+        } else {
+            let end = pos + run_len;
+            while pos < end {
+                if moved_len == 0 {
+                    moved_by = num_decode_zigzag_isize(positions.next_usize()?);
+                    moved_len = positions.next_usize()?;
+                    if moved_len == 0 { return Err(ParseError::InvalidLength); }
+                }
+                let l = moved_len.min(end - pos);
+                runs.push(((pos as LV..(pos + l) as LV).into(), Some(moved_by)));
+                pos += l;
+                moved_len -= l;
+            }
+        }
+    }
+
+    if moved_len != 0 { return Err(ParseError::InvalidLength); }
+    if let Some(c) = cancelled { c.expect_empty()?; }
+    positions.expect_empty()?;
+    Ok(runs)
+}
+
+/// Find the version hash chunk in the rest of the file (if there is one).
+#[cfg(feature = "dag_hash")]
+fn find_version_hash<'a>(mut reader: ChunkReader<'a>) -> Result<Option<(RemoteFrontierOwned, &'a [u8])>, ParseError> {
+    while !reader.is_empty() {
+        let (chunk_type, mut chunk) = reader.next_chunk_untyped()?;
+        if chunk_type == ListChunkType::VersionHash as u32 {
+            let version = read_remote_frontier(&mut chunk)?;
+            return Ok(Some((version, chunk.next_n_bytes(32)?)));
+        }
+    }
+    Ok(None)
+}
+
+fn read_remote_frontier(chunk: &mut BufReader) -> Result<RemoteFrontierOwned, ParseError> {
+    let num_versions = chunk.next_usize()?;
+    let mut version = RemoteFrontierOwned::new();
+    for _ in 0..num_versions {
+        let agent = chunk.next_str()?;
+        let seq = chunk.next_lv()?;
+        version.push((agent, seq).into());
+    }
+    Ok(version)
+}
+
 #[allow(unused)]
 pub(super) fn dbg_print_chunks_in(bytes: &[u8]) {
     BufReader(bytes).dbg_print_chunk_tree();
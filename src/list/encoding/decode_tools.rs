@@ -1,8 +1,10 @@
 use std::mem::size_of;
 use crate::encoding::parseerror::ParseError;
-use crate::list::encoding::leb::num_decode_zigzag_isize_old;
-use crate::list::encoding::{DataType, ListChunkType, MAGIC_BYTES};
+use crate::list::encoding::leb::{num_decode_zigzag_i64_old, num_decode_zigzag_isize_old};
+use crate::encoding::tools::calc_checksum;
+use crate::list::encoding::{CRITICAL_CHUNK_BIT, DataType, ListChunkType, MAGIC_BYTES};
 use crate::list::encoding::leb::{decode_leb_u32, decode_leb_u64, decode_leb_usize};
+use crate::LV;
 
 #[derive(Debug, Clone)]
 pub struct BufReader<'a>(pub(super) &'a [u8]);
@@ -62,12 +64,19 @@ impl<'a> BufReader<'a> {
     }
 
     pub(super) fn next_u32_le(&mut self) -> Result<u32, ParseError> {
-        // self.check_has_bytes(size_of::<u32>())?;
-        let val = u32::from_le_bytes(self.0[0..4].try_into().map_err(|_| ParseError::UnexpectedEOF)?);
+        self.check_has_bytes(size_of::<u32>())?;
+        let val = u32::from_le_bytes(self.0[0..4].try_into().unwrap());
         self.consume(size_of::<u32>());
         Ok(val)
     }
 
+    pub(super) fn next_u64_le(&mut self) -> Result<u64, ParseError> {
+        self.check_has_bytes(size_of::<u64>())?;
+        let val = u64::from_le_bytes(self.0[0..8].try_into().unwrap());
+        self.consume(size_of::<u64>());
+        Ok(val)
+    }
+
     #[allow(unused)]
     pub(super) fn next_u64(&mut self) -> Result<u64, ParseError> {
         self.check_not_empty()?;
@@ -83,11 +92,22 @@ impl<'a> BufReader<'a> {
         Ok(val)
     }
 
+    /// Read a local version or sequence number. These are always encoded as u64s.
+    pub(super) fn next_lv(&mut self) -> Result<LV, ParseError> {
+        let val = self.next_u64()?;
+        LV::try_from(val).map_err(|_| ParseError::ValueTooLarge)
+    }
+
     pub(super) fn next_zigzag_isize(&mut self) -> Result<isize, ParseError> {
         let n = self.next_usize()?;
         Ok(num_decode_zigzag_isize_old(n))
     }
 
+    pub(super) fn next_zigzag_i64(&mut self) -> Result<i64, ParseError> {
+        let n = self.next_u64()?;
+        Ok(num_decode_zigzag_i64_old(n))
+    }
+
     pub(super) fn next_n_bytes(&mut self, num_bytes: usize) -> Result<&'a [u8], ParseError> {
         if num_bytes > self.0.len() { return Err(ParseError::UnexpectedEOF); }
 
@@ -205,9 +225,9 @@ impl<'a> ChunkReader<'a> {
         self.0.expect_empty()
     }
 
-    fn next_chunk_raw(&mut self) -> Result<(ListChunkType, BufReader<'a>), ParseError> {
-        let chunk_type = ListChunkType::try_from(self.0.next_u32()?)
-            .map_err(|_| ParseError::UnknownChunk);
+    /// Read the next chunk without interpreting its type.
+    pub(super) fn next_chunk_untyped(&mut self) -> Result<(u32, BufReader<'a>), ParseError> {
+        let chunk_type = self.0.next_u32()?;
 
         // This in no way guarantees we're good.
         let len = self.0.next_usize()?;
@@ -215,27 +235,49 @@ impl<'a> ChunkReader<'a> {
             return Err(ParseError::InvalidLength);
         }
 
-        let reader = BufReader(self.0.next_n_bytes(len)?);
+        Ok((chunk_type, BufReader(self.0.next_n_bytes(len)?)))
+    }
+
+    /// Read the next chunk without interpreting its type. If the chunk is followed by a ChunkCrc
+    /// chunk, the CRC is checked and consumed too.
+    pub(super) fn next_chunk_checked(&mut self) -> Result<(u32, BufReader<'a>), ParseError> {
+        let start = self.0.0;
+        let result = self.next_chunk_untyped()?;
+        let raw = &start[..start.len() - self.0.len()];
+
+        if self.0.peek_u32()? == Some(ListChunkType::ChunkCrc as u32) {
+            let (_, mut crc) = self.next_chunk_untyped()?;
+            if calc_checksum(raw) != crc.next_u32_le()? {
+                return Err(ParseError::ChecksumFailed);
+            }
+            crc.expect_empty()?;
+        }
+        Ok(result)
+    }
 
-        // Note we're try-ing chunk_type here so we still read all the bytes if we can, even if
-        // the chunk type is unknown.
-        Ok((chunk_type?, reader))
+    /// Skip past any unknown chunks. Returns an error if we hit an unknown chunk which is marked as
+    /// critical. See [`CRITICAL_CHUNK_BIT`].
+    pub(super) fn skip_unknown_chunks(&mut self) -> Result<(), ParseError> {
+        while let Some(chunk_type) = self.0.peek_u32()? {
+            if ListChunkType::try_from(chunk_type).is_ok() { break; }
+            if chunk_type & CRITICAL_CHUNK_BIT != 0 { return Err(ParseError::UnknownChunk); }
+            self.next_chunk_checked()?;
+        }
+        Ok(())
     }
 
     /// Read the next chunk, skipping unknown chunks for forwards compatibility.
     pub(super) fn next_chunk(&mut self) -> Result<(ListChunkType, BufReader<'a>), ParseError> {
-        loop {
-            let c = self.next_chunk_raw();
-            match c {
-                Err(ParseError::UnknownChunk) => {}, // Keep scanning.
-                _ => { return c; }
-            }
-        }
+        self.skip_unknown_chunks()?;
+        let (chunk_type, reader) = self.next_chunk_checked()?;
+        // The chunk type is known, since we just skipped past the unknown ones.
+        Ok((ListChunkType::try_from(chunk_type).unwrap(), reader))
     }
 
     /// Read a chunk with the named type. Returns None if the next chunk isn't the specified type,
     /// or we hit EOF.
     pub(super) fn read_chunk_if_eq(&mut self, expect_chunk_type: ListChunkType) -> Result<Option<BufReader<'a>>, ParseError> {
+        self.skip_unknown_chunks()?;
         if let Some(actual_chunk_type) = self.0.peek_u32()? {
             if actual_chunk_type != (expect_chunk_type as u32) {
                 // Chunk doesn't match requested type.
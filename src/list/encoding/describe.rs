@@ -0,0 +1,382 @@
+//! Summaries of encoded patches, for showing to users before the patch is applied.
+
+use std::collections::BTreeSet;
+use rle::HasLength;
+use smartstring::alias::String as SmartString;
+use crate::{AgentId, Frontier, LV};
+use crate::causalgraph::agent_span::AgentSpan;
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontierOwned, RemoteVersionOwned, RemoteVersionSpanOwned};
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::tools::calc_checksum;
+use crate::encoding::varint::{strip_bit_usize, strip_bit_usize_2};
+use crate::list::ListOpLog;
+use crate::list::encoding::{DataType, DecodeOptions, format, ListChunkType};
+use crate::list::encoding::cipher::decrypt_file;
+use crate::list::encoding::decode_oplog::{read_agent_names, ReadPatchesIter};
+use crate::list::encoding::decode_tools::{BufReader, ChunkReader};
+use crate::list::encoding::version_summary::{read_summary, summary_to_version};
+use crate::list::op_metrics::ListOpMetrics;
+use crate::list::operation::ListOpKind;
+
+/// A description of the contents of an encoded patch. See [`ListOpLog::describe_patch`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PatchSummary {
+    pub doc_id: Option<SmartString>,
+
+    /// The version the patch applies on top of. If the patch stores a summary of this version
+    /// instead (see [`EncodeOptions::store_start_summary`](crate::list::encoding::EncodeOptions::store_start_summary)),
+    /// this names the last operation of each run of operations in the summary.
+    pub base_version: RemoteFrontierOwned,
+
+    /// The last operations in the patch. This is empty if the patch doesn't contain any
+    /// operations.
+    pub heads: RemoteFrontierOwned,
+
+    /// The agents which made operations in the patch, and the number of operations each of them
+    /// made. Sorted by name.
+    pub agents: Vec<(SmartString, usize)>,
+
+    /// The number of inserted characters.
+    pub inserted: usize,
+    /// The number of deleted characters.
+    pub deleted: usize,
+
+    /// The size of the inserted and deleted content stored in the patch, in bytes.
+    pub content_bytes: usize,
+}
+
+impl PatchSummary {
+    /// The total number of operations in the patch.
+    pub fn num_ops(&self) -> usize {
+        self.inserted + self.deleted
+    }
+}
+
+/// The IDs in a patch, read without decoding its operations. See [`read_patch_header`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct PatchHeader {
+    /// The version the patch applies on top of.
+    pub base_version: RemoteFrontierOwned,
+    /// The runs of operations in the patch, in file order.
+    pub ids: Vec<RemoteVersionSpanOwned>,
+}
+
+/// Skip a file's header, returning its protocol version and a reader for its chunks.
+fn read_file_chunks(data: &[u8]) -> Result<(usize, ChunkReader<'_>), ParseError> {
+    let mut reader = BufReader(data);
+    reader.read_magic()?;
+    let protocol_version = reader.next_usize()?;
+    if !format::is_supported_version(protocol_version) {
+        return Err(ParseError::UnsupportedProtocolVersion);
+    }
+    Ok((protocol_version, reader.chunks()))
+}
+
+/// Read the doc ID and agent names from the file info chunk.
+fn read_file_info<'a>(chunks: &mut ChunkReader<'a>, protocol_version: usize) -> Result<(Option<&'a str>, Vec<&'a str>), ParseError> {
+    let mut fileinfo = loop {
+        let (chunk_type, chunk) = chunks.next_chunk_untyped()?;
+        if chunk_type == ListChunkType::FileInfo as u32 { break chunk.chunks(); }
+    };
+    let doc_id = fileinfo.read_chunk_if_eq(ListChunkType::DocId)?
+        .map(|chunk| chunk.into_content_str())
+        .transpose()?;
+    let agent_names = loop {
+        let (chunk_type, chunk) = fileinfo.next_chunk_untyped()?;
+        if chunk_type == ListChunkType::AgentNames as u32 { break chunk; }
+    };
+    let names = read_agent_names(agent_names, protocol_version)?.into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    Ok((doc_id, names))
+}
+
+/// Read the (agent name, seq) pairs in a version chunk.
+fn read_version_ids<'a>(mut chunk: BufReader, names: &[&'a str]) -> Result<Vec<(&'a str, LV)>, ParseError> {
+    let mut result = vec![];
+    loop {
+        let (mapped_agent, has_more) = strip_bit_usize(chunk.next_usize()?);
+        let seq = chunk.next_lv()?;
+        if mapped_agent == 0 { break; } // Root.
+
+        let name = names.get(mapped_agent - 1).ok_or(ParseError::InvalidLength)?;
+        result.push((*name, seq));
+
+        if !has_more { break; }
+    }
+    Ok(result)
+}
+
+/// Read the version the file's operations apply on top of.
+fn read_base_version(chunks: &mut ChunkReader, names: &[&str]) -> Result<RemoteFrontierOwned, ParseError> {
+    let mut result = RemoteFrontierOwned::new();
+    let mut start_branch = chunks.expect_chunk(ListChunkType::StartBranch)?.chunks();
+    if let Some(chunk) = start_branch.read_chunk_if_eq(ListChunkType::Version)? {
+        result.extend(read_version_ids(chunk, names)?.into_iter()
+            .map(|(name, seq)| RemoteVersionOwned(name.into(), seq)));
+    } else if let Some(chunk) = start_branch.read_chunk_if_eq(ListChunkType::VersionSummary)? {
+        // Without the causal graph, the best we can do is name the last operation in each run.
+        for entry in read_summary(chunk)?.entries() {
+            result.extend(entry.seq_ranges.iter()
+                .map(|r| RemoteVersionOwned(entry.name.clone(), r.last())));
+        }
+    }
+    Ok(result)
+}
+
+/// Read the version the file's operations apply on top of, as a local version in `oplog`.
+fn read_local_base_version(chunks: &mut ChunkReader, names: &[&str], oplog: &ListOpLog) -> Result<Frontier, ParseError> {
+    let mut start_branch = chunks.expect_chunk(ListChunkType::StartBranch)?.chunks();
+    if let Some(chunk) = start_branch.read_chunk_if_eq(ListChunkType::Version)? {
+        let aa = &oplog.cg.agent_assignment;
+        let versions = read_version_ids(chunk, names)?.into_iter().map(|(name, seq)| {
+            match aa.get_agent_id(name).and_then(|agent| aa.try_agent_version_to_lv((agent, seq))) {
+                Some(lv) => Ok(lv),
+                None if oplog.is_pruned(name, seq) => Err(ParseError::HistoryPruned),
+                None => Err(ParseError::BaseVersionUnknown),
+            }
+        }).collect::<Result<Vec<_>, _>>()?;
+        Ok(oplog.cg.graph.find_dominators(&versions))
+    } else if let Some(chunk) = start_branch.read_chunk_if_eq(ListChunkType::VersionSummary)? {
+        summary_to_version(oplog, &read_summary(chunk)?, None)
+    } else {
+        Ok(Frontier::root())
+    }
+}
+
+/// Read the runs of operation IDs in the file, in file order. Agent IDs are indexes into the
+/// file's agent names.
+fn read_op_versions(patches: &mut ChunkReader, num_agents: usize) -> Result<Vec<AgentSpan>, ParseError> {
+    let mut op_versions = loop {
+        let (chunk_type, chunk) = patches.next_chunk_untyped()?;
+        if chunk_type == ListChunkType::OpVersions as u32 { break chunk; }
+    };
+    let mut agent_map: Vec<_> = (0..num_agents).map(|i| (i as AgentId, 0)).collect();
+    let mut result = vec![];
+    while let Some(span) = op_versions.read_next_agent_assignment(&mut agent_map)? {
+        result.push(span);
+    }
+    Ok(result)
+}
+
+/// The number of bytes of content stored in a patch content chunk.
+fn read_content_len(mut chunk: BufReader) -> Result<usize, ParseError> {
+    chunk.next_u32()?; // Inserts or deletes.
+    let (chunk_type, mut content) = chunk.chunks().expect_chunk_pred(
+        |c| c == ListChunkType::Content || c == ListChunkType::ContentCompressed,
+        ListChunkType::Content
+    )?;
+    if content.next_u32()? != DataType::PlainText as u32 {
+        return Err(ParseError::UnknownChunk);
+    }
+    // Compressed content stores its uncompressed length.
+    if chunk_type == ListChunkType::Content { Ok(content.len()) } else { content.next_usize() }
+}
+
+/// A parent of an operation in a file which isn't in the file itself, as (agent index, seq).
+type ForeignParent = (usize, LV);
+
+/// Read the file's parents information. Returns the operations in the file which no other
+/// operation in the file depends on (as offsets in file order), and the parents of the file's
+/// operations which aren't in the file.
+fn read_parents(mut chunk: BufReader) -> Result<(BTreeSet<usize>, Vec<ForeignParent>), ParseError> {
+    let mut heads = BTreeSet::new();
+    let mut foreign = vec![];
+    let mut next_time = 0usize;
+    while !chunk.is_empty() {
+        let len = chunk.next_usize()?;
+        if len == 0 { return Err(ParseError::InvalidLength); }
+        loop {
+            let mut n = chunk.next_usize()?;
+            let is_foreign = strip_bit_usize_2(&mut n);
+            let has_more = strip_bit_usize_2(&mut n);
+            if is_foreign {
+                if n == 0 { break; } // Root.
+                // Parents from outside the file can't be heads.
+                foreign.push((n - 1, chunk.next_lv()?));
+            } else {
+                heads.remove(&next_time.checked_sub(n).ok_or(ParseError::InvalidLength)?);
+            }
+            if !has_more { break; }
+        }
+        next_time = next_time.checked_add(len).ok_or(ParseError::InvalidLength)?;
+        heads.insert(next_time - 1);
+    }
+    Ok((heads, foreign))
+}
+
+/// Check the file's CRC, if it has one. `chunks` is every chunk after the last one read.
+fn check_crc(data: &[u8], mut chunks: ChunkReader) -> Result<(), ParseError> {
+    while !chunks.is_empty() {
+        let remaining = chunks.0.len();
+        let (chunk_type, mut chunk) = chunks.next_chunk_untyped()?;
+        if chunk_type == ListChunkType::Crc as u32
+            && calc_checksum(&data[..data.len() - remaining]) != chunk.next_u32_le()? {
+            return Err(ParseError::ChecksumFailed);
+        }
+    }
+    Ok(())
+}
+
+/// Read the base version and operation IDs from a patch. This only walks the patch's chunks - the
+/// operations themselves aren't parsed or checked.
+pub(crate) fn read_patch_header(data: &[u8]) -> Result<PatchHeader, ParseError> {
+    let plaintext = decrypt_file(data, &DecodeOptions::default())?;
+    let data = plaintext.as_deref().unwrap_or(data);
+
+    let (protocol_version, mut chunks) = read_file_chunks(data)?;
+    let (_, names) = read_file_info(&mut chunks, protocol_version)?;
+    let base_version = read_base_version(&mut chunks, &names)?;
+    let mut patches = chunks.expect_chunk(ListChunkType::Patches)?.chunks();
+    let ids = read_op_versions(&mut patches, names.len())?.into_iter()
+        .map(|span| RemoteVersionSpanOwned(names[span.agent as usize].into(), span.seq_range))
+        .collect();
+
+    Ok(PatchHeader { base_version, ids })
+}
+
+/// The operations in a patch, read without merging them into an oplog. See [`read_patch_ops`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PatchOps {
+    /// The version the patch applies on top of, in the oplog the patch was read against.
+    pub base_version: Frontier,
+    /// The names of the agents in the patch.
+    pub names: Vec<SmartString>,
+    /// The runs of operation IDs in the patch, in file order. Agent IDs are indexes into `names`.
+    pub ids: Vec<AgentSpan>,
+    /// The type and (untransformed) position of each operation, in file order. Content isn't read.
+    pub ops: Vec<ListOpMetrics>,
+    /// The parents of the patch's operations which aren't in the patch.
+    pub foreign_parents: Vec<ForeignParent>,
+}
+
+/// Read the operations in a patch without merging them into `oplog`. The patch's base version must
+/// be known by the oplog, but the operations themselves aren't checked against it.
+pub(crate) fn read_patch_ops(oplog: &ListOpLog, data: &[u8]) -> Result<PatchOps, ParseError> {
+    let plaintext = decrypt_file(data, &DecodeOptions::default())?;
+    let data = plaintext.as_deref().unwrap_or(data);
+
+    let (protocol_version, mut chunks) = read_file_chunks(data)?;
+    let (_, names) = read_file_info(&mut chunks, protocol_version)?;
+    let base_version = read_local_base_version(&mut chunks, &names, oplog)?;
+    let mut patches = chunks.expect_chunk(ListChunkType::Patches)?.chunks();
+    let ids = read_op_versions(&mut patches, names.len())?;
+    let ops = ReadPatchesIter::new(patches.expect_chunk(ListChunkType::OpTypeAndPosition)?)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (_, foreign_parents) = read_parents(patches.expect_chunk(ListChunkType::OpParents)?)?;
+    if foreign_parents.iter().any(|(agent, _)| *agent >= names.len()) {
+        return Err(ParseError::InvalidLength);
+    }
+
+    check_crc(data, chunks)?;
+    Ok(PatchOps {
+        base_version,
+        names: names.into_iter().map(|name| name.into()).collect(),
+        ids,
+        ops,
+        foreign_parents,
+    })
+}
+
+impl ListOpLog {
+    /// Describe the contents of a patch (or any encoded file) without merging it into an oplog.
+    /// The patch doesn't need to apply to any particular document - its base version can contain
+    /// operations nobody here has seen.
+    ///
+    /// This only walks the patch's chunks, so its much cheaper than loading the patch. The
+    /// operations aren't checked, so a patch which can be described might still fail to load.
+    /// Returns [`ParseError::CipherNeeded`] for encrypted patches.
+    pub fn describe_patch(data: &[u8]) -> Result<PatchSummary, ParseError> {
+        let plaintext = decrypt_file(data, &DecodeOptions::default())?;
+        let data = plaintext.as_deref().unwrap_or(data);
+
+        let (protocol_version, mut chunks) = read_file_chunks(data)?;
+        let (doc_id, names) = read_file_info(&mut chunks, protocol_version)?;
+        let mut summary = PatchSummary {
+            doc_id: doc_id.map(|id| id.into()),
+            base_version: read_base_version(&mut chunks, &names)?,
+            ..Default::default()
+        };
+
+        let mut patches = chunks.expect_chunk(ListChunkType::Patches)?.chunks();
+        while let Some(chunk) = patches.read_chunk_if_eq(ListChunkType::PatchContent)? {
+            summary.content_bytes += read_content_len(chunk)?;
+        }
+
+        let ids = read_op_versions(&mut patches, names.len())?;
+        let mut num_ops = vec![0; names.len()];
+        for span in ids.iter() {
+            num_ops[span.agent as usize] += span.len();
+        }
+        summary.agents = names.iter().zip(num_ops)
+            .filter(|(_, n)| *n > 0)
+            .map(|(name, n)| ((*name).into(), n))
+            .collect();
+        summary.agents.sort_unstable();
+
+        for op in ReadPatchesIter::new(patches.expect_chunk(ListChunkType::OpTypeAndPosition)?) {
+            let op = op?;
+            match op.kind {
+                ListOpKind::Ins => summary.inserted += op.len(),
+                ListOpKind::Del => summary.deleted += op.len(),
+            }
+        }
+
+        let (heads, _) = read_parents(patches.expect_chunk(ListChunkType::OpParents)?)?;
+        let mut heads = heads.into_iter().peekable();
+        let mut t = 0;
+        for span in ids {
+            while let Some(h) = heads.next_if(|h| *h < t + span.len()) {
+                summary.heads.push(RemoteVersionOwned(names[span.agent as usize].into(), span.seq_range.start + (h - t) as LV));
+            }
+            t += span.len();
+        }
+        if heads.next().is_some() { return Err(ParseError::InvalidLength); }
+
+        check_crc(data, chunks)?;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rle::HasLength;
+    use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::gen_random::gen_oplog;
+    use crate::list::ListOpLog;
+    use crate::{Frontier, LV};
+    use super::{read_patch_header, read_patch_ops};
+
+    #[test]
+    fn describe_round_trips() {
+        for seed in 0..10 {
+            let oplog = gen_oplog(seed, 30, true, true);
+            let aa = &oplog.cg.agent_assignment;
+            let from = Frontier::new_1(oplog.len() / 2);
+            let patch = oplog.encode_from(&EncodeOptions::patch(), from.as_ref());
+            let (_, new_spans) = oplog.cg.graph.diff(from.as_ref(), oplog.local_frontier_ref());
+            let num_new: usize = new_spans.iter().map(|r| r.len()).sum();
+
+            let summary = ListOpLog::describe_patch(&patch).unwrap();
+            let heads = aa.remote_to_local_frontier(summary.heads.iter());
+            assert_eq!(heads.as_ref(), oplog.local_frontier_ref());
+            assert_eq!(summary.base_version, aa.local_to_remote_frontier_owned(from.as_ref()));
+            assert_eq!(summary.num_ops(), num_new);
+            assert_eq!(summary.agents.iter().map(|(_, n)| n).sum::<usize>(), num_new);
+
+            // The IDs in the header name exactly the new operations.
+            let header = read_patch_header(&patch).unwrap();
+            let mut lvs: Vec<LV> = header.ids.iter().flat_map(|span| {
+                span.1.iter().map(|seq| aa.remote_to_local_version(RemoteVersion(&span.0, seq)))
+            }).collect();
+            lvs.sort_unstable();
+            let expect_lvs: Vec<LV> = new_spans.iter().flat_map(|r| r.iter()).collect();
+            assert_eq!(lvs, expect_lvs);
+
+            let ops = read_patch_ops(&oplog, &patch).unwrap();
+            assert_eq!(ops.base_version, from);
+            assert_eq!(ops.ops.iter().map(|op| op.len()).sum::<usize>(), num_new);
+        }
+    }
+}
@@ -1,19 +1,28 @@
+use std::fmt::{Display, Formatter};
 use jumprope::JumpRope;
+use smallvec::SmallVec;
 use rle::{HasLength, RleRun};
 use crate::list::encoding::*;
 use crate::causalgraph::graph::GraphEntrySimple;
 use crate::list::operation::ListOpKind::{Del, Ins};
-use crate::list::{ListBranch, ListOpLog, switch};
+use crate::list::{ListBranch, ListOpLog, OpMetadata, switch};
+use crate::encoding::varint::{mix_bit_usize, num_encode_zigzag_i64};
 use crate::rle::{KVPair, RleVec};
-use crate::{AgentId, LV};
+use crate::{AgentId, Frontier, lv_to_usize, LV};
 use crate::frontier::local_frontier_is_root;
 use crate::list::op_metrics::ListOpMetrics;
 use crate::list::operation::ListOpKind;
 use crate::dtrange::DTRange;
-use crate::encoding::tools::calc_checksum;
-use crate::list::encoding::encode_tools::{Merger, push_leb_chunk, push_leb_str, push_leb_u32, push_leb_usize, push_u32_le, write_leb_bit_run};
-use crate::list::encoding::leb::{encode_leb_u32, encode_leb_usize, num_encode_zigzag_isize_old};
+use std::io::{self, Write};
+use crc::Digest;
+use crate::encoding::tools::CHECKSUM;
+use crate::list::encoding::encode_tools::{Merger, push_leb_chunk, push_leb_str, push_leb_u32, push_leb_lv, push_leb_u64, push_leb_usize, push_u32_le, write_leb_bit_run};
+use crate::list::encoding::leb::{encode_leb_u32, encode_leb_u64, encode_leb_usize, num_encode_zigzag_i64_old, num_encode_zigzag_isize_old};
 use crate::listmerge::merge::TransformedResultRaw;
+use crate::list::encoding::cipher::push_chunk_maybe_encrypted;
+use crate::list::encoding::cdc::Chunker;
+use crate::list::encoding::version_summary::write_summary;
+use crate::unicount::chars_to_bytes;
 const ALLOW_VERBOSE: bool = true;
 
 /// Write an operation to the passed writer.
@@ -95,10 +104,28 @@ fn write_op(dest: &mut Vec<u8>, op: &ListOpMetrics, cursor: &mut usize) {
 // }
 
 
+/// The error returned by [`ListOpLog::encode_range`]. This lists the operations the receiving peer
+/// would need to have, but which it doesn't have and which aren't being sent.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MissingDependencies(pub Vec<DTRange>);
+
+impl Display for MissingDependencies {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Missing dependencies:")?;
+        for r in &self.0 {
+            write!(f, " {}..{}", r.start, r.end)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MissingDependencies {}
+
 #[derive(Debug, Copy, Clone)]
 struct AgentAssignmentRun {
     agent: AgentId,
-    delta: isize,
+    // Sequence numbers are LVs, so this is an i64 even on 32 bit platforms.
+    delta: i64,
     len: usize,
 }
 
@@ -118,6 +145,43 @@ impl HasLength for AgentAssignmentRun {
     }
 }
 
+/// A run of operations in the file, where each operation's only parent is the operation before it
+/// in the file. Unlike a [`GraphEntrySimple`], the operations don't need to be contiguous locally,
+/// since canonical files reorder the operations.
+#[derive(Debug, Clone)]
+struct FileGraphEntry {
+    /// The local versions of the operations, in file order.
+    spans: SmallVec<DTRange, 1>,
+    parents: Frontier,
+}
+
+impl From<GraphEntrySimple> for FileGraphEntry {
+    fn from(e: GraphEntrySimple) -> Self {
+        Self { spans: smallvec::smallvec![e.span], parents: e.parents }
+    }
+}
+
+impl MergableSpan for FileGraphEntry {
+    fn can_append(&self, other: &Self) -> bool {
+        other.parents.len() == 1
+            && other.parents[0] == self.spans.last().unwrap().last()
+    }
+
+    fn append(&mut self, other: Self) {
+        for span in other.spans {
+            let last = self.spans.last_mut().unwrap();
+            if last.can_append(&span) { last.append(span); }
+            else { self.spans.push(span); }
+        }
+    }
+}
+
+impl HasLength for FileGraphEntry {
+    fn len(&self) -> usize {
+        self.spans.iter().map(|s| s.len()).sum()
+    }
+}
+
 fn write_assignment_run(dest: &mut Vec<u8>, run: AgentAssignmentRun) {
     // Its rare, but possible for the agent assignment sequence to jump around a little.
     // This can happen when:
@@ -138,7 +202,7 @@ fn write_assignment_run(dest: &mut Vec<u8>, run: AgentAssignmentRun) {
     pos += encode_leb_usize(run.len, &mut buf[pos..]);
 
     if has_jump {
-        pos += encode_leb_usize(num_encode_zigzag_isize_old(run.delta), &mut buf[pos..]);
+        pos += encode_leb_u64(num_encode_zigzag_i64_old(run.delta), &mut buf[pos..]);
     }
 
     dest.extend_from_slice(&buf[..pos]);
@@ -148,9 +212,10 @@ fn write_assignment_run(dest: &mut Vec<u8>, run: AgentAssignmentRun) {
 struct AgentMapping {
     /// Map from oplog's agent ID to the agent id in the file. Paired with the last assigned agent
     /// ID, to support agent IDs bouncing around.
-    map: Vec<Option<(AgentId, usize)>>,
+    map: Vec<Option<(AgentId, LV)>>,
     next_mapped_agent: AgentId,
-    output: Vec<u8>,
+    /// The mapped agents, in the order they appear in the file.
+    agents: Vec<AgentId>,
 }
 
 impl AgentMapping {
@@ -160,14 +225,13 @@ impl AgentMapping {
         let mut result = Self {
             map: Vec::with_capacity(client_len),
             next_mapped_agent: 1, // 0 is implicitly assigned to ROOT.
-            output: Vec::new()
+            agents: Vec::new()
         };
         result.map.resize(client_len, None);
         result
     }
 
-    // TODO: Narrow arguments to &AgentAssignment
-    fn map(&mut self, oplog: &ListOpLog, agent: AgentId) -> AgentId {
+    fn map(&mut self, agent: AgentId) -> AgentId {
         // 0 is implicitly ROOT.
         assert_ne!(agent, AgentId::MAX);
 
@@ -176,22 +240,47 @@ impl AgentMapping {
         self.map[agent].map_or_else(|| {
             let mapped = self.next_mapped_agent;
             self.map[agent] = Some((mapped, 0));
-            push_leb_str(&mut self.output, oplog.cg.agent_assignment.client_data[agent].name.as_str());
+            self.agents.push(agent as AgentId);
             // println!("Mapped agent {} -> {}", oplog.cg.client_data[agent].name, mapped);
             self.next_mapped_agent += 1;
             mapped
         }, |v| v.0)
     }
 
-    fn seq_delta(&mut self, agent: AgentId, span: DTRange) -> isize {
+    fn seq_delta(&mut self, agent: AgentId, span: DTRange) -> i64 {
         let item = self.map[agent as usize].as_mut().unwrap();
         let old_seq = item.1;
         item.1 = span.end;
-        (span.start as isize) - (old_seq as isize)
+        (span.start as i64) - (old_seq as i64)
     }
 
-    fn consume(self) -> Vec<u8> {
-        self.output
+    /// Write the contents of the AgentNames chunk. If `with_info` is set, each agent's name is
+    /// followed by its info: the info's revision (or 0 if there's none), then a bitfield of the
+    /// fields which are set, and the fields themselves.
+    fn consume(self, oplog: &ListOpLog, with_info: bool) -> Vec<u8> {
+        let mut result = Vec::new();
+        for agent in self.agents {
+            let name = oplog.get_agent_name(agent);
+            push_leb_str(&mut result, name);
+            if !with_info { continue; }
+
+            let Some((rev, info)) = oplog.agent_info.get(name) else {
+                push_leb_usize(&mut result, 0);
+                continue;
+            };
+            push_leb_usize(&mut result, *rev);
+            let flags = info.display_name.is_some() as usize
+                | (info.device.is_some() as usize) << 1
+                | (info.public_key.is_some() as usize) << 2;
+            push_leb_usize(&mut result, flags);
+            if let Some(name) = info.display_name.as_ref() { push_leb_str(&mut result, name); }
+            if let Some(device) = info.device.as_ref() { push_leb_str(&mut result, device); }
+            if let Some(key) = info.public_key.as_ref() {
+                push_leb_usize(&mut result, key.len());
+                result.extend_from_slice(key);
+            }
+        }
+        result
     }
 }
 
@@ -209,10 +298,10 @@ fn write_local_version(dest: &mut Vec<u8>, version: &[LV], map: &mut AgentMappin
         let (agent, seq) = oplog.lv_to_agent_version(*t);
 
         // (Mapped agent ID, seq) pairs. Agent id has mixed in bit for has_more.
-        let mapped = map.map(oplog, agent);
+        let mapped = map.map(agent);
         let n = mix_bit_usize(mapped as _, has_more);
         push_leb_usize(&mut buf, n);
-        push_leb_usize(&mut buf, seq);
+        push_leb_lv(&mut buf, seq);
     }
     push_leb_chunk(dest, ListChunkType::Version, &buf, false);
     // buf.clear();
@@ -236,7 +325,6 @@ fn write_content<'a, I: Iterator<Item = &'a [u8]>>(dest: &mut Vec<u8>, kind: Dat
     const MIN_COMPRESSED_LEN: usize = 20;
 
     let (b, chunk_type) = match (compressed, len >= MIN_COMPRESSED_LEN) {
-        #[cfg(feature = "lz4")]
         (Some(b), true) => {
             // Store the compressed length in the origin chunk.
             push_leb_usize(&mut buf, len);
@@ -275,7 +363,7 @@ fn write_chunk_str(dest: &mut Vec<u8>, s: &str, chunk_type: ListChunkType) {
 
 /// Returns compressed chunk size
 #[cfg(feature = "lz4")]
-fn write_compressed_chunk(dest: &mut Vec<u8>, data: &[u8]) -> usize {
+fn compress_lz4(data: &[u8]) -> Vec<u8> {
     // dbg!(&compress_bytes);
     let max_compressed_size = lz4_flex::block::get_maximum_output_size(data.len());
 
@@ -294,10 +382,73 @@ fn write_compressed_chunk(dest: &mut Vec<u8>, data: &[u8]) -> usize {
     // TooSmall, and that should probably be a panic anyway.
     pos += lz4_flex::compress_into(data, &mut compressed[pos..]).unwrap();
     compressed.truncate(pos);
-    // write_chunk(ChunkType::CompressedFields, &mut compressed);
-    push_leb_chunk(dest, ListChunkType::CompressedFieldsLZ4, &compressed[..pos], false);
+    compressed
+}
 
-    pos
+#[cfg(feature = "zstd")]
+fn compress_zstd(data: &[u8]) -> Vec<u8> {
+    // Same layout as the LZ4 chunk: The uncompressed length, then the compressed data.
+    let mut compressed = Vec::new();
+    push_leb_usize(&mut compressed, data.len());
+    // Compressing into a vec can only fail on allocation failure.
+    compressed.extend_from_slice(&zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL).unwrap());
+    compressed
+}
+
+/// Returns compressed chunk size
+#[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables, unreachable_code))]
+fn write_compressed_chunk(dest: &mut Vec<u8>, format: CompressionFormat, data: &[u8], cipher: Option<&dyn ChunkCipher>) -> usize {
+    let compressed: Vec<u8> = match format {
+        #[cfg(feature = "lz4")]
+        CompressionFormat::LZ4 => compress_lz4(data),
+        #[cfg(feature = "zstd")]
+        CompressionFormat::Zstd => compress_zstd(data),
+        #[allow(unreachable_patterns)]
+        _ => unreachable!("Compression format {:?} is not supported", format),
+    };
+    push_chunk_maybe_encrypted(dest, format.chunk_type(), &compressed, cipher, false);
+    compressed.len()
+}
+
+/// Wraps the writer for an encoded file, keeping a running checksum of everything written.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    digest: Digest<'static, u32>,
+    /// The checksum of everything written since the last chunk CRC.
+    chunk_digest: Digest<'static, u32>,
+    len: usize,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, digest: CHECKSUM.digest(), chunk_digest: CHECKSUM.digest(), len: 0 }
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.digest.update(data);
+        self.chunk_digest.update(data);
+        self.len += data.len();
+        self.inner.write_all(data)
+    }
+
+    /// Start checksumming a new chunk.
+    fn reset_chunk_digest(&mut self) {
+        self.chunk_digest = CHECKSUM.digest();
+    }
+
+    /// Write a ChunkCrc chunk, containing the checksum of the chunk we just wrote.
+    fn write_chunk_crc(&mut self) -> io::Result<()> {
+        let crc = std::mem::replace(&mut self.chunk_digest, CHECKSUM.digest()).finalize();
+        let mut buf = Vec::with_capacity(6);
+        push_leb_chunk(&mut buf, ListChunkType::ChunkCrc, &crc.to_le_bytes(), false);
+        self.write_all(&buf)?;
+        self.reset_chunk_digest();
+        Ok(())
+    }
+
+    fn checksum(&self) -> u32 {
+        self.digest.clone().finalize()
+    }
 }
 
 /// Simple helper struct for content (ins / del) chunks. These have two parts:
@@ -335,6 +486,41 @@ impl<F: FnMut(RleRun<bool>, &mut Vec<u8>)> ContentChunk<F> {
         self.bit_writer.push2(RleRun::new(known, len), &mut self.known_out);
     }
 
+    /// Push the content of the operations in `span`, leaving out the content of any operations in
+    /// the `omit` ranges.
+    fn push_omitting(&mut self, content: Option<&str>, span: DTRange, omit: &[DTRange]) {
+        let mut omitted: SmallVec<DTRange, 2> = omit.iter()
+            .filter_map(|r| r.intersect(&span))
+            .filter(|r| !r.is_empty())
+            .collect();
+        let Some(content) = content.filter(|_| !omitted.is_empty()) else {
+            self.push(content, span.len());
+            return;
+        };
+        omitted.sort_unstable_by_key(|r| r.start);
+
+        // The content is stored in the order the characters were inserted (or deleted).
+        let mut pos = span.start;
+        let mut rest = content;
+        for r in omitted {
+            if r.end <= pos { continue; }
+            let start = r.start.max(pos);
+            if start > pos {
+                let len = lv_to_usize(start - pos);
+                let bytes = chars_to_bytes(rest, len);
+                self.push(Some(&rest[..bytes]), len);
+                rest = &rest[bytes..];
+            }
+            let len = lv_to_usize(r.end - start);
+            rest = &rest[chars_to_bytes(rest, len)..];
+            self.push(None, len);
+            pos = r.end;
+        }
+        if pos < span.end {
+            self.push(Some(rest), lv_to_usize(span.end - pos));
+        }
+    }
+
     fn flush(mut self, compressed_out: Option<&mut Vec<u8>>) -> Option<Vec<u8>> {
         self.bit_writer.flush2(&mut self.known_out);
 
@@ -358,11 +544,60 @@ impl ListOpLog {
     /// Encode the data stored in the OpLog into a (custom) compact binary form suitable for saving
     /// to disk, or sending over the network.
     pub fn encode_from(&self, opts: &EncodeOptions, from_version: &[LV]) -> Vec<u8> {
+        self.encode_internal(opts, from_version, None)
+    }
+
+    /// Encode the oplog like [`encode`](ListOpLog::encode), writing the file to `w` as its
+    /// generated rather than building it in memory. The file is written one chunk at a time, and
+    /// the checksum is calculated as the data is written.
+    ///
+    /// Writes are small, so `w` should usually be buffered.
+    pub fn encode_to<W: Write>(&self, w: W, opts: &EncodeOptions) -> io::Result<()> {
+        self.encode_from_to(w, opts, &[])
+    }
+
+    /// Like [`encode_to`](ListOpLog::encode_to), but only encoding the changes since
+    /// `from_version`. See [`encode_from`](ListOpLog::encode_from).
+    pub fn encode_from_to<W: Write>(&self, w: W, opts: &EncodeOptions, from_version: &[LV]) -> io::Result<()> {
+        self.encode_internal_to(w, opts, from_version, None)
+    }
+
+    pub(crate) fn encode_internal(&self, opts: &EncodeOptions, from_version: &[LV], ranges: Option<&[DTRange]>) -> Vec<u8> {
+        let mut result = Vec::new();
+        self.encode_internal_to(&mut result, opts, from_version, ranges)
+            .expect("Writing to a Vec cannot fail");
+        result
+    }
+
+    /// Encode the operations in `ranges` (or everything after `from_version` if ranges is None).
+    /// The ranges must be sorted, and every parent of an operation in the ranges must be either
+    /// in the ranges or in from_version.
+    pub(crate) fn encode_internal_to<W: Write>(&self, w: W, opts: &EncodeOptions, from_version: &[LV], ranges: Option<&[DTRange]>) -> io::Result<()> {
         // if !frontier_is_root(from_frontier) {
         //     unimplemented!("Encoding from a non-root frontier is not implemented");
         // }
         let verbose = ALLOW_VERBOSE && opts.verbose;
 
+        let adjusted_opts;
+        let opts = if opts.canonical || opts.protocol_version < format::CURRENT_VERSION {
+            let mut o = opts.clone();
+            if opts.canonical {
+                assert!(ranges.is_none(), "Canonical output is only supported when encoding from a version");
+                // The operations are written in canonical order instead of being sorted. And
+                // anything which depends on how the operations were received locally is left out.
+                o.sort = false;
+                o.store_xf = false;
+                o.store_op_metadata = false;
+            }
+            o.restrict_to_protocol_version();
+            adjusted_opts = o;
+            &adjusted_opts
+        } else { opts };
+        // Versions are written in a canonical order too.
+        let version_order = |version: &[LV]| -> SmallVec<LV, 2> {
+            if opts.canonical { self.canonical_version_order(version) } else { version.into() }
+        };
+
         // Before anything else, we'll scan the oplog and assemble all the data in memory that we
         // need to write.
 
@@ -393,9 +628,12 @@ impl ListOpLog {
         // - Interleaved it would compress much less well with snappy / lz4.
 
         // Only used when compression is enabled.
-        let mut compress_bytes = if opts.compress_content && cfg!(feature = "lz4") {
+        let mut compress_bytes = if (opts.compress_branch_content || opts.compress_patch_content)
+            && opts.compression.is_supported() {
             Some(Vec::new())
         } else { None };
+        let compress_branch = opts.compress_branch_content;
+        let compress_patch = opts.compress_patch_content && !opts.content_defined_chunks;
 
         let mut inserted_content = if opts.store_inserted_content {
             Some(ContentChunk::new(write_leb_bit_run, Ins))
@@ -427,16 +665,16 @@ impl ListOpLog {
         // Each entry's key is the internal local time, and the value (entry.1) is the range in the
         // output.
         let mut txn_map = RleVec::<KVPair<DTRange>>::new();
-        let mut next_output_time = 0;
+        let mut next_output_time: LV = 0;
         let mut txns_chunk = Vec::new();
-        let mut txns_writer = Merger::new(|txn: GraphEntrySimple, agent_mapping: &mut AgentMapping| {
-            // println!("Upstream {}-{}", txn.span.start, txn.span.end);
+        let mut txns_writer = Merger::new(|txn: FileGraphEntry, agent_mapping: &mut AgentMapping| {
             // First add this entry to the txn map.
-            let len = txn.span.len();
-            let output_range = (next_output_time .. next_output_time + len).into();
-            // txn_map.push(KVPair(txn.span.start, output_range));
-            txn_map.insert(KVPair(txn.span.start, output_range));
-            next_output_time = output_range.end;
+            let len = txn.len();
+            let output_range: DTRange = (next_output_time .. next_output_time + len as LV).into();
+            for span in txn.spans.iter() {
+                txn_map.insert(KVPair(span.start, (next_output_time..next_output_time + span.len() as LV).into()));
+                next_output_time += span.len() as LV;
+            }
 
             push_leb_usize(&mut txns_chunk, len);
 
@@ -450,7 +688,8 @@ impl ListOpLog {
                 // let n = 0, has_more = false, is_foreign = true. -> val = 1.
                 push_leb_usize(&mut txns_chunk, 1);
             } else {
-                let mut iter = txn.parents.iter().peekable();
+                let parents = version_order(txn.parents.as_ref());
+                let mut iter = parents.iter().peekable();
                 while let Some(&p) = iter.next() {
                     // let p = p; // intellij bug
                     let has_more = iter.peek().is_some();
@@ -472,15 +711,15 @@ impl ListOpLog {
                         // in the file, but this mapping doesn't guarantee that. Currently I'm
                         // re-sorting after reading - which is necessary for external parents anyway.
                         // But allowing unsorted local parents is vaguely upsetting.
-                        let mapped_parent = map.1.start + offset;
+                        let mapped_parent = map.1.start + offset as LV;
 
-                        write_parent_diff(output_range.start - mapped_parent, false);
+                        write_parent_diff((output_range.start - mapped_parent) as usize, false);
                     } else {
                         // Foreign change
                         // println!("Region does not contain parent for {}", p);
 
                         let (local_agent, seq) = self.lv_to_agent_version(p);
-                        let mapped_agent = agent_mapping.map(self, local_agent);
+                        let mapped_agent = agent_mapping.map(local_agent);
                         debug_assert!(mapped_agent >= 1);
 
                         // There are probably more compact ways to do this, but the txn data set is
@@ -490,7 +729,7 @@ impl ListOpLog {
                         //
                         // I'm adding 1 to the mapped agent to make room for ROOT. This is quite dirty!
                         write_parent_diff(mapped_agent as usize, true);
-                        push_leb_usize(&mut txns_chunk, seq);
+                        push_leb_lv(&mut txns_chunk, seq);
                     }
                 }
             }
@@ -504,7 +743,7 @@ impl ListOpLog {
             // 1. Agent names and agent assignment
             for KVPair(_, span) in self.cg.agent_assignment.client_with_lv.iter_range_ctx(graph_entry.span, &()) {
                 // Mark the agent as in-use (if we haven't already)
-                let mapped_agent = agent_mapping.map(self, span.agent);
+                let mapped_agent = agent_mapping.map(span.agent);
 
                 // agent_assignment is a list of (agent, len) pairs.
                 agent_assignment_writer.push(AgentAssignmentRun {
@@ -515,32 +754,28 @@ impl ListOpLog {
             }
 
             // 2. Operations!
-            for (op, content) in self.iter_range_simple(graph_entry.span) {
-                let op = op.1;
+            for (KVPair(lv, op), content) in self.iter_range_simple(graph_entry.span) {
 
                 // DANGER!! Its super important we pull out the content here rather than in
                 // ops_writer somehow. The reason is that the content_pos field on the merged
                 // OperationInternal objects will be invalid! Total foot gun there :p
 
-                if op.kind == Ins && opts.store_inserted_content {
-                    // For now at least, we can't skip inserted content for inserts.
-                    // TODO: Reconsider this at some point.
-                    assert!(content.is_some());
-                }
-
+                // Inserts can have unknown content (eg, if they've been redacted). That's recorded
+                // in the ContentIsKnown chunk.
                 let content_chunk = switch(op.kind,
                                            &mut inserted_content,
                                            &mut deleted_content
                 );
                 if let Some(content_chunk) = content_chunk {
-                    content_chunk.push(content, op.len());
+                    content_chunk.push_omitting(content, (lv..lv + op.len() as LV).into(), opts.omit_content_for);
                 }
 
-                ops_writer.push(op);
+                // The content is written separately, so where its stored locally doesn't matter.
+                ops_writer.push(ListOpMetrics { content_pos: None, ..op });
             }
 
             // 3. Parents!
-            txns_writer.push2(graph_entry, &mut agent_mapping);
+            txns_writer.push2(graph_entry.into(), &mut agent_mapping);
         };
 
         let mut any_cancelled = false;
@@ -549,15 +784,43 @@ impl ListOpLog {
 
         // If we just iterate in the current order, this code would be way simpler :p
         // let iter = self.cg.history.optimized_txns_between(from_frontier, &self.frontier);
-        if !opts.sort {
+        // The operations in the file.
+        let new_ranges: SmallVec<DTRange, 4> = match ranges {
+            Some(ranges) => ranges.into(),
+            None => self.cg.graph.diff(from_version, self.cg.version.as_ref()).1,
+        };
+
+        // The version a reader will have once they've merged the file.
+        let end_version = match ranges {
+            Some(ranges) => {
+                let mut versions: Vec<LV> = from_version.to_vec();
+                versions.extend(ranges.iter().map(|r| r.last()));
+                self.cg.graph.find_dominators(&versions)
+            }
+            None => self.cg.version.clone(),
+        };
+
+        if opts.canonical {
+            let mut sorted_ranges = new_ranges.clone();
+            sorted_ranges.sort_unstable_by_key(|r| r.start);
+            for span in self.canonical_spans() {
+                for r in sorted_ranges.iter() {
+                    let (start, end) = (span.start.max(r.start), span.end.min(r.end));
+                    if start >= end { continue; }
+                    for ge in self.cg.graph.iter_range((start..end).into()) {
+                        process_ops(ge);
+                    }
+                }
+            }
+        } else if !opts.sort {
             assert_eq!(opts.store_xf, false);
-            let (_, new_ranges) = self.cg.graph.diff(from_version, self.cg.version.as_ref());
             // for walk in self.cg.graph.iter_range() {
             // for walk in self.cg.graph.optimized_txns_between(from_version, self.cg.version.as_ref()) {
             for ge in new_ranges.iter().flat_map(|r| self.cg.graph.iter_range(*r)) {
                 process_ops(ge);
             }
         } else {
+            assert!(ranges.is_none(), "Sorted output is only supported when encoding from a version");
             // This is split into two writers because the cancelled flags take up much less room.
             let mut xf_cancelled_writer = Merger::new(write_leb_bit_run);
             let mut xf_moveby_writer = Merger::new(|e: RleRun<isize>, buf: &mut Vec<u8>| {
@@ -621,31 +884,94 @@ impl ListOpLog {
         ops_writer.flush();
         txns_writer.flush2(&mut agent_mapping);
 
+        // Transactions are written in file order. Transactions which are only partially included
+        // in the output are skipped.
+        let mut transactions_chunk = Vec::new();
+        let mut mapped_txns: Vec<DTRange> = self.transactions.iter()
+            .filter(|_| !opts.canonical && opts.writes(ListChunkType::Transactions))
+            .filter_map(|txn| map_contiguous_range(&txn_map, *txn))
+            .collect();
+        mapped_txns.sort_unstable_by_key(|r| r.start);
+        let mut last_end = 0;
+        for txn in mapped_txns {
+            push_leb_lv(&mut transactions_chunk, txn.start - last_end);
+            push_leb_usize(&mut transactions_chunk, txn.len());
+            last_end = txn.end;
+        }
+
+        // Operation metadata is also written in file order. Each entry is (gap since the last
+        // entry, length, flags, timestamp delta?, data?).
+        let mut op_metadata_chunk = Vec::new();
+        if opts.store_op_metadata && !self.op_metadata.is_empty() {
+            let mut entries: Vec<(DTRange, &OpMetadata)> = vec![];
+            for KVPair(lv_start, file_range) in txn_map.iter() {
+                let lv_end = lv_start + file_range.len() as LV;
+                let first = self.op_metadata.partition_point(|(r, _)| r.end <= *lv_start);
+                for (r, meta) in self.op_metadata[first..].iter().take_while(|(r, _)| r.start < lv_end) {
+                    let start = file_range.start + r.start.max(*lv_start) - lv_start;
+                    let end = file_range.start + r.end.min(lv_end) - lv_start;
+                    entries.push(((start..end).into(), meta));
+                }
+            }
+            entries.sort_unstable_by_key(|(r, _)| r.start);
+
+            let mut last_end = 0;
+            let mut last_timestamp = 0u64;
+            for (r, meta) in entries {
+                push_leb_lv(&mut op_metadata_chunk, r.start - last_end);
+                push_leb_usize(&mut op_metadata_chunk, r.len());
+                let flags = meta.timestamp.is_some() as usize | ((!meta.data.is_empty() as usize) << 1);
+                push_leb_usize(&mut op_metadata_chunk, flags);
+                if let Some(t) = meta.timestamp {
+                    push_leb_u64(&mut op_metadata_chunk, num_encode_zigzag_i64(t.wrapping_sub(last_timestamp) as i64));
+                    last_timestamp = t;
+                }
+                if !meta.data.is_empty() {
+                    push_leb_usize(&mut op_metadata_chunk, meta.data.len());
+                    op_metadata_chunk.extend_from_slice(&meta.data);
+                }
+                last_end = r.end;
+            }
+        }
+
         // This nominally needs to happen before we write out agent_mapping.
         // TODO: Support partial data sets. (from_frontier)
         let mut start_branch = Vec::new();
 
         // If the local version is root, start_branch is just an empty chunk.
         if !local_frontier_is_root(from_version) {
-            // This will skip writing the version if from_version is ROOT.
-            write_local_version(&mut start_branch, from_version, &mut agent_mapping, self);
+            if opts.store_start_summary {
+                // The summary replaces the frontier.
+                let mut buf = Vec::new();
+                let mut summary = self.cg.summarize_version(from_version);
+                if opts.canonical {
+                    let mut entries = summary.entries().to_vec();
+                    entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+                    summary = entries.into();
+                }
+                write_summary(&mut buf, &summary);
+                push_leb_chunk(&mut start_branch, ListChunkType::VersionSummary, &buf, verbose);
+            } else {
+                // This will skip writing the version if from_version is ROOT.
+                write_local_version(&mut start_branch, &version_order(from_version), &mut agent_mapping, self);
+            }
 
             if opts.store_start_branch_content {
                 let branch_here = ListBranch::new_at_local_version(self, from_version);
                 // dbg!(&branch_here);
-                write_content_rope(&mut start_branch, &branch_here.content.borrow(), compress_bytes.as_mut());
+                write_content_rope(&mut start_branch, &branch_here.content.borrow(), compress_bytes.as_mut().filter(|_| compress_branch));
             }
         }
 
         let end_branch = if opts.store_end_branch_content {
             let mut end_branch = Vec::new();
-            write_local_version(&mut end_branch, self.cg.version.as_ref(), &mut agent_mapping, self);
+            write_local_version(&mut end_branch, &version_order(end_version.as_ref()), &mut agent_mapping, self);
 
-            let branch_here = ListBranch::new_at_tip(self);
+            let branch_here = ListBranch::new_at_local_version(self, end_version.as_ref());
             if verbose {
                 println!("End content length (uncompressed) {}", branch_here.content.len_bytes());
             }
-            write_content_rope(&mut end_branch, &branch_here.content.borrow(), compress_bytes.as_mut());
+            write_content_rope(&mut end_branch, &branch_here.content.borrow(), compress_bytes.as_mut().filter(|_| compress_branch));
 
             Some(end_branch)
         } else { None };
@@ -664,8 +990,30 @@ impl ListOpLog {
             write_chunk_str(&mut fileinfo_buf, name.as_str(), ListChunkType::DocId);
         }
 
-        // agent names
-        push_leb_chunk(&mut fileinfo_buf, ListChunkType::AgentNames, &agent_mapping.consume(), verbose);
+        if !self.fork_points.is_empty() && opts.writes(ListChunkType::ForkPoints) {
+            let mut buf = Vec::new();
+            push_leb_usize(&mut buf, self.fork_points.len());
+            for f in self.fork_points.iter() {
+                push_leb_str(&mut buf, &f.doc_id);
+                push_leb_usize(&mut buf, f.version.len());
+                for rv in f.version.iter() {
+                    push_leb_str(&mut buf, &rv.0);
+                    push_leb_lv(&mut buf, rv.1);
+                }
+            }
+            push_leb_chunk(&mut fileinfo_buf, ListChunkType::ForkPoints, &buf, verbose);
+        }
+
+        // agent names (and info, for agents named in the file)
+        let agent_names = agent_mapping.consume(self, format::agent_names_have_info(opts.protocol_version));
+        push_leb_chunk(&mut fileinfo_buf, ListChunkType::AgentNames, &agent_names, verbose);
+
+        // Custom tie break policies.
+        if let Some(name) = self.insert_tie_break().custom_name() {
+            assert!(opts.writes(ListChunkType::InsertTieBreak),
+                "Custom tie break policies need protocol version 1");
+            write_chunk_str(&mut fileinfo_buf, name, ListChunkType::InsertTieBreak);
+        }
 
         // User data
         if let Some(data) = opts.user_data {
@@ -679,103 +1027,318 @@ impl ListOpLog {
                 println!("Inserted text length (uncompressed) {}", inserted_content.content.len());
             }
 
-            inserted_content.flush(compress_bytes.as_mut())
+            inserted_content.flush(compress_bytes.as_mut().filter(|_| compress_patch))
         });
         let deleted_content = deleted_content.and_then(|deleted_content| {
             if verbose {
                 println!("Deleted text length {}", deleted_content.content.len());
             }
 
-            deleted_content.flush(compress_bytes.as_mut())
+            deleted_content.flush(compress_bytes.as_mut().filter(|_| compress_patch))
         });
 
 
-        // *** Actually start writing to Result!! YAAAAYYY ***
-        let mut result = Vec::new();
+        // *** Actually start writing the file!! YAAAAYYY ***
+        // Everything we write goes through `out`, which keeps a running checksum. Each chunk is
+        // assembled in buf and then written out.
+        let mut out = ChecksumWriter::new(w);
+        let mut buf = Vec::new();
         // The file starts with MAGIC_BYTES
-        result.extend_from_slice(&MAGIC_BYTES);
-        push_leb_usize(&mut result, PROTOCOL_VERSION);
+        buf.extend_from_slice(&MAGIC_BYTES);
+        push_leb_usize(&mut buf, opts.protocol_version);
+        out.write_all(&buf)?;
+        out.reset_chunk_digest();
+        buf.clear();
 
         // We'll write a series of chunks. Each chunk has a chunk header (chunk type, length).
         // The first chunk is CompressedFields, in case we need compressed content later.
 
-        #[cfg(not(feature = "lz4"))] {
-            debug_assert!(compress_bytes.is_none());
-        }
-
-        #[cfg(feature = "lz4")] {
-            if let Some(compress_bytes) = compress_bytes {
-                if !compress_bytes.is_empty() {
-                    let compressed_len = write_compressed_chunk(&mut result, &compress_bytes);
-                    if verbose {
-                        println!("Compressed {} bytes in the file to {}", compress_bytes.len(), compressed_len);
-                    }
+        if let Some(compress_bytes) = compress_bytes {
+            if !compress_bytes.is_empty() {
+                let compressed_len = write_compressed_chunk(&mut buf, opts.compression, &compress_bytes, opts.cipher.as_deref());
+                if verbose {
+                    println!("Compressed {} bytes in the file to {}", compress_bytes.len(), compressed_len);
                 }
+                out.write_all(&buf)?;
+                buf.clear();
+                if opts.chunk_crcs { out.write_chunk_crc()?; }
             }
         }
 
-        let mut write_chunk = |c: ListChunkType, data: &mut Vec<u8>| {
+        let mut write_chunk = |out: &mut ChecksumWriter<W>, c: ListChunkType, data: &mut Vec<u8>| -> io::Result<()> {
             // if verbose {
             //     println!("{:?} length {}", c, data.len());
             // }
             // dbg!(&data);
-            push_leb_chunk(&mut result, c, data.as_slice(), verbose);
+            push_chunk_maybe_encrypted(&mut buf, c, data.as_slice(), opts.cipher.as_deref(), verbose);
             data.clear();
+            out.write_all(&buf)?;
+            buf.clear();
+            if opts.chunk_crcs { out.write_chunk_crc()?; }
+            Ok(())
         };
 
-        write_chunk(ListChunkType::FileInfo, &mut fileinfo_buf);
+        write_chunk(&mut out, ListChunkType::FileInfo, &mut fileinfo_buf)?;
 
         // *** Start Branch - which was filled in above. ***
-        write_chunk(ListChunkType::StartBranch, &mut start_branch);
+        write_chunk(&mut out, ListChunkType::StartBranch, &mut start_branch)?;
 
         if let Some(mut bytes) = end_branch {
-            write_chunk(ListChunkType::ExperimentalEndBranch, &mut bytes);
+            write_chunk(&mut out, ListChunkType::ExperimentalEndBranch, &mut bytes)?;
         }
 
         // *** Patches ***
-        // I'll just assemble it in buf. There's a lot of sloppy use of vec<u8>'s in here.
-        let mut patches_buf = fileinfo_buf;
+        // This is usually most of the file, so (unless its being encrypted) the child chunks are
+        // written out directly rather than being copied into a buffer first.
+        let mut patches: Vec<(ListChunkType, &[u8])> = vec![];
 
-        if let Some(bytes) = inserted_content {
-            push_leb_chunk(&mut patches_buf, ListChunkType::PatchContent, &bytes, verbose);
+        if let Some(bytes) = inserted_content.as_deref() {
+            patches.push((ListChunkType::PatchContent, bytes));
         }
-        if let Some(bytes) = deleted_content {
-            push_leb_chunk(&mut patches_buf, ListChunkType::PatchContent, &bytes, verbose);
+        if let Some(bytes) = deleted_content.as_deref() {
+            patches.push((ListChunkType::PatchContent, bytes));
         }
 
-        push_leb_chunk(&mut patches_buf, ListChunkType::OpVersions, &agent_assignment_chunk, verbose);
-        push_leb_chunk(&mut patches_buf, ListChunkType::OpTypeAndPosition, &ops_chunk, verbose);
-        push_leb_chunk(&mut patches_buf, ListChunkType::OpParents, &txns_chunk, verbose);
+        patches.push((ListChunkType::OpVersions, &agent_assignment_chunk));
+        patches.push((ListChunkType::OpTypeAndPosition, &ops_chunk));
+        patches.push((ListChunkType::OpParents, &txns_chunk));
+        if !transactions_chunk.is_empty() {
+            patches.push((ListChunkType::Transactions, &transactions_chunk));
+        }
+        if !op_metadata_chunk.is_empty() {
+            patches.push((ListChunkType::OpMetadata, &op_metadata_chunk));
+        }
 
         if opts.store_xf {
             if !xf_cancelled_chunk.is_empty() {
-                push_leb_chunk(&mut patches_buf, ListChunkType::TransformedCancelsOps, &xf_cancelled_chunk, verbose);
+                patches.push((ListChunkType::TransformedCancelsOps, &xf_cancelled_chunk));
+            }
+            patches.push((ListChunkType::TransformedPositions, &xf_ops_chunk));
+        }
+
+        // The content manifest is written at the end of the file, since its only needed by tools
+        // which don't care about the rest of the file.
+        let mut manifest = Vec::new();
+        let mut patches_buf = fileinfo_buf;
+        if opts.cipher.is_some() {
+            for (chunk_type, data) in patches {
+                push_leb_chunk(&mut patches_buf, chunk_type, data, verbose);
+            }
+            write_chunk(&mut out, ListChunkType::Patches, &mut patches_buf)?;
+        } else {
+            let headers: Vec<Vec<u8>> = patches.iter().map(|(chunk_type, data)| {
+                if verbose {
+                    println!("Chunk {:?} - size {}", chunk_type, data.len());
+                }
+                let mut header = Vec::new();
+                push_leb_u32(&mut header, *chunk_type as u32);
+                push_leb_usize(&mut header, data.len());
+                header
+            }).collect();
+            let total_len: usize = patches.iter().zip(headers.iter())
+                .map(|((_, data), header)| header.len() + data.len())
+                .sum();
+
+            push_leb_u32(&mut patches_buf, ListChunkType::Patches as u32);
+            push_leb_usize(&mut patches_buf, total_len);
+            out.write_all(&patches_buf)?;
+            patches_buf.clear();
+
+            let patches_start = out.len;
+            let mut chunker = opts.content_defined_chunks.then(Chunker::new);
+            for ((_, data), header) in patches.iter().zip(headers.iter()) {
+                out.write_all(header)?;
+                out.write_all(data)?;
+                if let Some(chunker) = chunker.as_mut() {
+                    chunker.push(header);
+                    chunker.push(data);
+                }
+            }
+            if let Some(chunker) = chunker {
+                chunker.write_manifest(&mut manifest, patches_start);
+            }
+            if opts.chunk_crcs { out.write_chunk_crc()?; }
+        }
+
+        // *** Signatures ***
+        // Any signatures which cover operations in the file. Signatures can extend back before
+        // from_version - the receiver will already have those operations.
+        if !self.signatures.is_empty() && !opts.canonical && opts.writes(ListChunkType::Signatures) {
+            let file_spans: Vec<_> = new_ranges.iter()
+                .flat_map(|r| self.iter_agent_mappings_range(*r))
+                .collect();
+
+            let signatures: Vec<_> = self.signatures.iter().filter(|s| {
+                file_spans.iter().any(|span| {
+                    self.get_agent_name(span.agent) == s.agent
+                        && span.seq_range.start < s.seq_range.end && s.seq_range.start < span.seq_range.end
+                })
+            }).collect();
+
+            if !signatures.is_empty() {
+                push_leb_usize(&mut patches_buf, signatures.len());
+                for s in signatures {
+                    push_leb_str(&mut patches_buf, &s.agent);
+                    push_leb_lv(&mut patches_buf, s.seq_range.start);
+                    push_leb_usize(&mut patches_buf, s.seq_range.len());
+                    push_leb_usize(&mut patches_buf, s.public_key.len());
+                    patches_buf.extend_from_slice(&s.public_key);
+                    push_leb_usize(&mut patches_buf, s.signature.len());
+                    patches_buf.extend_from_slice(&s.signature);
+                }
+                write_chunk(&mut out, ListChunkType::Signatures, &mut patches_buf)?;
+            }
+        }
+
+        // *** Checkpoints ***
+        // These are always included. There aren't usually many of them, and the receiver might not
+        // have them even if it has the operations.
+        if !self.checkpoints.is_empty() && opts.writes(ListChunkType::Checkpoints) {
+            push_leb_usize(&mut patches_buf, self.checkpoints.len());
+            for c in self.checkpoints.iter() {
+                push_leb_str(&mut patches_buf, &c.name);
+                push_leb_str(&mut patches_buf, &c.message);
+                push_leb_usize(&mut patches_buf, c.edit);
+                push_leb_usize(&mut patches_buf, c.version.len());
+                for rv in self.cg.agent_assignment.local_to_remote_frontier(&version_order(c.version.as_ref())) {
+                    push_leb_str(&mut patches_buf, rv.0);
+                    push_leb_lv(&mut patches_buf, rv.1);
+                }
+            }
+            write_chunk(&mut out, ListChunkType::Checkpoints, &mut patches_buf)?;
+        }
+
+        // *** Named branches ***
+        if !self.named_branches.is_empty() && opts.writes(ListChunkType::NamedBranches) {
+            push_leb_usize(&mut patches_buf, self.named_branches.len());
+            for (name, branch) in self.named_branches.iter() {
+                push_leb_str(&mut patches_buf, name);
+                // The low bit marks deleted branches.
+                push_leb_usize(&mut patches_buf, mix_bit_usize(branch.edit, branch.version.is_none()));
+                if let Some(version) = &branch.version {
+                    push_leb_usize(&mut patches_buf, version.len());
+                    for rv in self.cg.agent_assignment.local_to_remote_frontier(&version_order(version.as_ref())) {
+                        push_leb_str(&mut patches_buf, rv.0);
+                        push_leb_lv(&mut patches_buf, rv.1);
+                    }
+                }
+            }
+            write_chunk(&mut out, ListChunkType::NamedBranches, &mut patches_buf)?;
+        }
+
+        // *** Version hash ***
+        #[cfg(feature = "dag_hash")]
+        if opts.store_version_hash {
+            let version = end_version.as_ref();
+            push_leb_usize(&mut patches_buf, version.len());
+            for rv in self.cg.agent_assignment.local_to_remote_frontier(&version_order(version)) {
+                push_leb_str(&mut patches_buf, rv.0);
+                push_leb_lv(&mut patches_buf, rv.1);
+            }
+            patches_buf.extend_from_slice(&self.hash_for_version(version));
+            write_chunk(&mut out, ListChunkType::VersionHash, &mut patches_buf)?;
+        }
+
+        // *** Pruned history ***
+        if self.is_shallow() {
+            assert!(opts.writes(ListChunkType::PrunedHistory), "Shallow oplogs need protocol version 1");
+            let pruned: Vec<_> = self.iter_pruned().collect();
+            push_leb_usize(&mut patches_buf, pruned.len());
+            for (name, ranges) in pruned {
+                push_leb_str(&mut patches_buf, name);
+                push_leb_usize(&mut patches_buf, ranges.len());
+                for r in ranges {
+                    push_leb_lv(&mut patches_buf, r.start);
+                    push_leb_usize(&mut patches_buf, r.len());
+                }
             }
-            push_leb_chunk(&mut patches_buf, ListChunkType::TransformedPositions, &xf_ops_chunk, verbose);
+            write_chunk(&mut out, ListChunkType::PrunedHistory, &mut patches_buf)?;
         }
 
-        write_chunk(ListChunkType::Patches, &mut patches_buf);
+        // *** Content manifest ***
+        if !manifest.is_empty() {
+            write_chunk(&mut out, ListChunkType::ContentManifest, &mut manifest)?;
+        }
 
         // TODO (later): Final branch content.
 
         // println!("checksum {checksum}");
-        let checksum = calc_checksum(&result);
+        let checksum = out.checksum();
         push_u32_le(&mut patches_buf, checksum);
-        push_leb_chunk(&mut result, ListChunkType::Crc, &patches_buf, verbose);
-        // write_chunk(Chunk::CRC, &mut buf);
-        // push_u32(&mut result, checksum);
+        push_leb_chunk(&mut buf, ListChunkType::Crc, &patches_buf, verbose);
+        out.write_all(&buf)?;
 
         if verbose {
-            println!("== Total length {}", result.len());
+            println!("== Total length {}", out.len);
         }
 
-        result
+        Ok(())
     }
 
     pub fn encode(&self, opts: &EncodeOptions) -> Vec<u8> {
         self.encode_from(opts, &[])
     }
 
+    /// Encode only the operations in `ranges`, for a peer which already has the operations in
+    /// `remote_version`.
+    ///
+    /// The file names the versions the operations depend on (their parents outside the ranges), so
+    /// the peer can check it has everything it needs before merging them. If the peer would be
+    /// missing any operations (because they aren't in the ranges or in `remote_version`), this
+    /// returns an error listing them instead.
+    ///
+    /// Operations are always written in local order - [`EncodeOptions::sort_operations`],
+    /// [`EncodeOptions::store_xf`] and [canonical ordering](EncodeOptions::canonical) are ignored.
+    ///
+    /// Panics if the ranges contain unknown operations.
+    pub fn encode_range(&self, ranges: &[DTRange], remote_version: &[LV], opts: &EncodeOptions) -> Result<Vec<u8>, MissingDependencies> {
+        let mut sorted: Vec<DTRange> = ranges.iter().copied().filter(|r| !r.is_empty()).collect();
+        assert!(sorted.iter().all(|r| r.end <= self.len()), "Cannot encode unknown operations");
+        sorted.sort_unstable_by_key(|r| r.start);
+        let mut ranges: Vec<DTRange> = vec![];
+        for r in sorted {
+            match ranges.last_mut() {
+                Some(last) if r.start <= last.end => { last.end = last.end.max(r.end); }
+                _ => { ranges.push(r); }
+            }
+        }
+
+        let contains = |ranges: &[DTRange], v: LV| {
+            let idx = ranges.partition_point(|r| r.end <= v);
+            ranges.get(idx).is_some_and(|r| r.start <= v)
+        };
+
+        // The parents of the operations we're sending, which we aren't sending.
+        let mut deps = vec![];
+        for e in ranges.iter().flat_map(|r| self.cg.graph.iter_range(*r)) {
+            deps.extend(e.parents.iter().copied().filter(|p| !contains(&ranges, *p)));
+        }
+        let deps = self.cg.graph.find_dominators(&deps);
+
+        // Everything the peer needs to have before merging the file.
+        let (_, needed) = self.cg.graph.diff(remote_version, deps.as_ref());
+        let mut missing = vec![];
+        for r in needed {
+            // Remove the parts of r we're sending anyway.
+            let mut start = r.start;
+            let first = ranges.partition_point(|x| x.end <= r.start);
+            for x in ranges[first..].iter().take_while(|x| x.start < r.end) {
+                if x.start > start { missing.push((start..x.start).into()); }
+                start = x.end;
+            }
+            if start < r.end { missing.push((start..r.end).into()); }
+        }
+        if !missing.is_empty() {
+            return Err(MissingDependencies(missing));
+        }
+
+        let mut opts = opts.clone();
+        opts.sort = false;
+        opts.store_xf = false;
+        opts.canonical = false;
+        Ok(self.encode_internal(&opts, deps.as_ref(), Some(&ranges)))
+    }
+
     /// Encode the data stored in the OpLog into a (custom) compact binary form suitable for saving
     /// to disk, or sending over the network.
     pub fn encode_simple(&self, _opts: EncodeOptions) -> Vec<u8> {
@@ -939,7 +1502,7 @@ impl ListOpLog {
 
 #[cfg(test)]
 mod tests {
-    use crate::list::encoding::EncodeOptions;
+    use crate::list::encoding::{format, EncodeOptions, ListChunkType, MissingDependencies};
     use crate::list::{ListCRDT, ListOpLog};
 
     #[test]
@@ -976,6 +1539,50 @@ mod tests {
         // dbg!(data.len(), data);
     }
 
+    #[test]
+    fn encode_range() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "abc"); // 0..3
+        oplog.add_insert_at(mike, &[2], 3, "d"); // 3
+        oplog.add_insert_at(seph, &[2], 0, "x"); // 4
+        oplog.add_insert_at(seph, &[3, 4], 0, "y"); // 5
+
+        let data = oplog.encode_range(&[(0..3).into()], &[], &EncodeOptions::patch()).unwrap();
+        let mut remote = ListOpLog::load_from(&data).unwrap();
+        assert_eq!(remote.len(), 3);
+
+        // Send just seph's change. It only depends on what the remote already has.
+        let data = oplog.encode_range(&[(4..5).into()], &[2], &EncodeOptions::patch()).unwrap();
+        remote.decode_and_add(&data).unwrap();
+        assert_eq!(remote.checkout_tip().content(), "xabc");
+
+        // End branch content and version hashes describe the end of the range, not our tip.
+        let opts = EncodeOptions::patch().experimentally_store_end_branch_content(true);
+        #[cfg(feature = "dag_hash")]
+        let opts = opts.store_version_hash(true);
+        let data = oplog.encode_range(&[(4..5).into()], &[2], &opts).unwrap();
+        let layout = format::read_layout(&data).unwrap();
+        let payload = |id: ListChunkType| -> &[u8] {
+            &data[layout.chunks.iter().find(|c| c.id == id as u32).unwrap().payload.clone()]
+        };
+        let end_branch = payload(ListChunkType::ExperimentalEndBranch);
+        assert!(end_branch.windows(4).any(|w| w == b"xabc"));
+        assert!(!end_branch.windows(6).any(|w| w == b"yxabcd"));
+        #[cfg(feature = "dag_hash")]
+        assert!(payload(ListChunkType::VersionHash).ends_with(&oplog.hash_for_version(&[4])));
+
+        // The last change needs mike's change too. (Versions are local to the sending oplog.)
+        let err = oplog.encode_range(&[(5..6).into()], &[4], &EncodeOptions::patch());
+        assert_eq!(err, Err(MissingDependencies(vec![(3..4).into()])));
+        assert_eq!(err.unwrap_err().to_string(), "Missing dependencies: 3..4");
+
+        let data = oplog.encode_range(&[(5..6).into(), (3..4).into()], &[4], &EncodeOptions::patch()).unwrap();
+        remote.decode_and_add(&data).unwrap();
+        assert_eq!(remote.checkout_tip().content(), oplog.checkout_tip().content());
+    }
+
     #[test]
     fn encode_simple() {
         let mut oplog = ListOpLog::new();
@@ -1,5 +1,7 @@
+use std::sync::Arc;
+use crate::list::encoding::{ChunkCipher, CompressionFormat, format, ListChunkType};
 use crate::list::ListOpLog;
-use crate::LV;
+use crate::{DTRange, LV};
 
 // TODO: Make a builder API for this
 #[derive(Debug, Clone)]
@@ -11,44 +13,99 @@ pub struct EncodeOptions<'a> {
 
     pub(crate) store_start_branch_content: bool,
 
+    /// Store a summary of the known sequence numbers from each agent in the start branch.
+    pub(crate) store_start_summary: bool,
+
     /// Experimental.
     pub(crate) store_end_branch_content: bool,
 
     pub(crate) store_inserted_content: bool,
     pub(crate) store_deleted_content: bool,
 
-    pub(crate) compress_content: bool,
+    /// Store operation timestamps and metadata.
+    pub(crate) store_op_metadata: bool,
+
+    /// Compress the content stored in the start (and end) branch.
+    pub(crate) compress_branch_content: bool,
+    /// Compress inserted and deleted content.
+    pub(crate) compress_patch_content: bool,
+    pub(crate) compression: CompressionFormat,
+
+    pub(crate) cipher: Option<Arc<dyn ChunkCipher>>,
 
     pub(crate) verbose: bool,
 
     pub(crate) store_xf: bool,
     pub(crate) sort: bool,
+
+    /// Don't store the content of operations in these ranges.
+    pub(crate) omit_content_for: &'a [DTRange],
+
+    /// Split the patches into content-defined chunks, listed in a manifest.
+    pub(crate) content_defined_chunks: bool,
+
+    /// Store the hash of the file's end version.
+    #[cfg(feature = "dag_hash")]
+    pub(crate) store_version_hash: bool,
+
+    /// The version of the file format to write. See [`format`].
+    pub(crate) protocol_version: usize,
+
+    /// Write operations and agents in canonical order. See [`EncodeOptions::canonical`].
+    pub(crate) canonical: bool,
+
+    /// Follow each top level chunk with a CRC of that chunk.
+    pub(crate) chunk_crcs: bool,
 }
 
 
 pub const ENCODE_PATCH: EncodeOptions = EncodeOptions {
     user_data: None,
     store_start_branch_content: false,
+    store_start_summary: false,
     store_end_branch_content: false,
     store_inserted_content: true,
     store_deleted_content: false,
-    compress_content: true,
+    store_op_metadata: true,
+    compress_branch_content: true,
+    compress_patch_content: true,
+    compression: CompressionFormat::LZ4,
+    cipher: None,
     verbose: false,
     // sort_events:
     store_xf: false,
     sort: false,
+    omit_content_for: &[],
+    content_defined_chunks: false,
+    #[cfg(feature = "dag_hash")]
+    store_version_hash: false,
+    protocol_version: format::CURRENT_VERSION,
+    canonical: false,
+    chunk_crcs: false,
 };
 
 pub const ENCODE_FULL: EncodeOptions = EncodeOptions {
     user_data: None,
     store_start_branch_content: true,
+    store_start_summary: false,
     store_end_branch_content: false,
     store_inserted_content: true,
     store_deleted_content: false, // ?? Not sure about this one!
-    compress_content: true,
+    store_op_metadata: true,
+    compress_branch_content: true,
+    compress_patch_content: true,
+    compression: CompressionFormat::LZ4,
+    cipher: None,
     verbose: false,
     store_xf: false,
     sort: false,
+    omit_content_for: &[],
+    content_defined_chunks: false,
+    #[cfg(feature = "dag_hash")]
+    store_version_hash: false,
+    protocol_version: format::CURRENT_VERSION,
+    canonical: false,
+    chunk_crcs: false,
 };
 
 impl<'a> Default for EncodeOptions<'a> {
@@ -67,6 +124,26 @@ impl<'a> EncodeOptions<'a> {
         ENCODE_FULL.clone()
     }
 
+    /// Options for encoding the full document deterministically. Oplogs with the same history
+    /// (and the same document ID, agent info, checkpoints and named branches) always encode to
+    /// the same bytes, no matter what order each peer received the operations in. This is useful
+    /// for content addressed storage.
+    ///
+    /// Operations and agents are written in a canonical order (which takes O(n log n) time to
+    /// compute). Operation timestamps and metadata, transactions and signatures aren't stored.
+    /// Deleted content isn't stored either, since peers usually only know the content of their
+    /// own deletes. [`sort_operations`](Self::sort_operations) and [`store_xf`](Self::store_xf)
+    /// are ignored.
+    /// Files written with a cipher aren't deterministic, because each chunk is encrypted with a
+    /// fresh nonce.
+    pub fn canonical() -> Self {
+        EncodeOptions {
+            store_op_metadata: false,
+            canonical: true,
+            ..ENCODE_FULL.clone()
+        }
+    }
+
     pub fn encode_from(&self, oplog: &ListOpLog, from_version: &[LV]) -> Vec<u8> {
         oplog.encode_from(self, from_version)
     }
@@ -81,6 +158,16 @@ impl<'a> EncodeOptions<'a> {
         self
     }
 
+    /// Store a [`VersionSummary`](crate::causalgraph::summary::VersionSummary) of the start
+    /// version instead of its frontier. Peers can read it with
+    /// [`read_start_summary`](crate::list::encoding::read_start_summary) to find out which
+    /// operations the sender has, even if they don't know all of those operations. The summary is
+    /// usually larger than the frontier. Defaults to false.
+    pub fn store_start_summary(mut self, store_start_summary: bool) -> Self {
+        self.store_start_summary = store_start_summary;
+        self
+    }
+
     pub fn experimentally_store_end_branch_content(mut self, store: bool) -> Self {
         self.store_end_branch_content = store;
         self
@@ -96,8 +183,44 @@ impl<'a> EncodeOptions<'a> {
         self
     }
 
+    /// Store the timestamps and metadata attached to operations (see
+    /// [`ListOpLog::set_op_metadata`]). Defaults to true.
+    pub fn store_op_metadata(mut self, store_op_metadata: bool) -> Self {
+        self.store_op_metadata = store_op_metadata;
+        self
+    }
+
+    /// Compress all stored content. This is the same as setting both
+    /// [`compress_branch_content`](EncodeOptions::compress_branch_content) and
+    /// [`compress_patch_content`](EncodeOptions::compress_patch_content).
     pub fn compress_content(mut self, compress_content: bool) -> Self {
-        self.compress_content = compress_content;
+        self.compress_branch_content = compress_content;
+        self.compress_patch_content = compress_content;
+        self
+    }
+
+    pub fn compress_branch_content(mut self, compress: bool) -> Self {
+        self.compress_branch_content = compress;
+        self
+    }
+
+    pub fn compress_patch_content(mut self, compress: bool) -> Self {
+        self.compress_patch_content = compress;
+        self
+    }
+
+    /// Set the compression format used for compressed content. Defaults to LZ4. If support for
+    /// the format isn't compiled in, content is stored uncompressed.
+    pub fn compression_format(mut self, format: CompressionFormat) -> Self {
+        self.compression = format;
+        self
+    }
+
+    /// Encrypt the document's content and patches with the passed cipher. The file's structure
+    /// (chunk headers and the CRC) is left unencrypted. Files encoded like this can only be
+    /// loaded by passing the same cipher to [`DecodeOptions::with_cipher`](crate::list::encoding::DecodeOptions::with_cipher).
+    pub fn with_cipher<C: ChunkCipher + 'static>(mut self, cipher: C) -> Self {
+        self.cipher = Some(Arc::new(cipher));
         self
     }
 
@@ -111,6 +234,11 @@ impl<'a> EncodeOptions<'a> {
         self
     }
     
+    /// Store the transformed position of each operation, so documents loaded from the file can be
+    /// checked out without merging. This makes the file a little bigger. The positions are only
+    /// used when the file is loaded with
+    /// [`DecodeOptions::trust_transformed_positions`](crate::list::encoding::DecodeOptions::trust_transformed_positions).
+    ///
     /// Implies sorting
     pub fn store_xf(mut self, store_xf: bool) -> Self {
         self.store_xf = store_xf;
@@ -118,9 +246,82 @@ impl<'a> EncodeOptions<'a> {
         self
     }
 
+    /// Don't store the content of any operations in `ranges`. The operations are still encoded,
+    /// but they're marked as having unknown content. When the file is loaded, checking out the
+    /// document inserts [`PLACEHOLDER_CHAR`](crate::list::PLACEHOLDER_CHAR)s in place of the
+    /// missing content. See [`ListOpLog::ranges_missing_content`].
+    pub fn omit_content_for(mut self, ranges: &'a [DTRange]) -> Self {
+        self.omit_content_for = ranges;
+        self
+    }
+
+    /// Split the file's patches into content-defined chunks, and list them (with a hash of each)
+    /// in a manifest at the end of the file. Successive saves of a growing document will share
+    /// most of their chunks, so backup systems and sync protocols can skip the unchanged regions.
+    /// The manifest can be read with [`read_content_manifest`](crate::list::encoding::read_content_manifest).
+    ///
+    /// Compression shuffles bytes around the whole file, so patch content is stored uncompressed
+    /// when this is enabled. No manifest is written for encrypted files.
+    pub fn content_defined_chunks(mut self, content_defined_chunks: bool) -> Self {
+        self.content_defined_chunks = content_defined_chunks;
+        self
+    }
+
+    /// Store the hash of the document's version (see [`ListOpLog::hash_for_version`]), so the
+    /// receiver can check it ends up with an identical history. See
+    /// [`DecodeOptions::verify_version_hash`](crate::list::encoding::DecodeOptions::verify_version_hash).
+    #[cfg(feature = "dag_hash")]
+    pub fn store_version_hash(mut self, store_version_hash: bool) -> Self {
+        self.store_version_hash = store_version_hash;
+        self
+    }
+
+    /// Write an older version of the file format, for peers which don't understand the current
+    /// version. See [`format::negotiate_version`].
+    ///
+    /// Chunks added after the named version are left out of the file, along with the data they
+    /// store. Zstd compression falls back to LZ4. Encrypted files and shallow oplogs can't be
+    /// written in older versions - encoding them panics.
+    ///
+    /// Panics if the version isn't supported.
+    pub fn protocol_version(mut self, version: usize) -> Self {
+        assert!(format::is_supported_version(version), "Unsupported protocol version {version}");
+        self.protocol_version = version;
+        self
+    }
+
+    /// Follow each top level chunk with a CRC of that chunk's bytes. The CRC at the end of the
+    /// file only says whether the file is corrupt somewhere - chunk CRCs say which chunk has been
+    /// damaged, and are checked as each chunk is read. This makes files a few bytes bigger per
+    /// chunk. Defaults to false.
+    pub fn chunk_crcs(mut self, chunk_crcs: bool) -> Self {
+        self.chunk_crcs = chunk_crcs;
+        self
+    }
+
     pub fn build(self) -> EncodeOptions<'a> {
         self
     }
+
+    /// Can chunks of this type be written in the file's protocol version?
+    pub(super) fn writes(&self, chunk: ListChunkType) -> bool {
+        format::chunk_spec(chunk as u32).map_or(true, |s| s.since_version <= self.protocol_version)
+    }
+
+    /// Turn off anything which needs chunks that the file's protocol version doesn't have.
+    pub(super) fn restrict_to_protocol_version(&mut self) {
+        assert!(self.cipher.is_none() || self.writes(ListChunkType::Encrypted),
+            "Encrypted files need protocol version 1");
+        if !self.writes(ListChunkType::CompressedFieldsZstd) && self.compression == CompressionFormat::Zstd {
+            self.compression = CompressionFormat::LZ4;
+        }
+        self.store_start_summary &= self.writes(ListChunkType::VersionSummary);
+        self.store_op_metadata &= self.writes(ListChunkType::OpMetadata);
+        self.content_defined_chunks &= self.writes(ListChunkType::ContentManifest);
+        self.chunk_crcs &= self.writes(ListChunkType::ChunkCrc);
+        #[cfg(feature = "dag_hash")]
+        { self.store_version_hash &= self.writes(ListChunkType::VersionHash); }
+    }
 }
 
 pub type EncodeOptionsBuilder<'a> = EncodeOptions<'a>;
\ No newline at end of file
@@ -1,8 +1,9 @@
 use std::mem::{replace, size_of};
-use rle::{MergableSpan, RleRun};
+use rle::{MergableSpanCtx, RleRun};
 use std::marker::PhantomData;
 use crate::list::encoding::ListChunkType;
 use crate::encoding::varint::mix_bit_usize;
+use crate::LV;
 
 #[cfg(feature = "serde")]
 use serde::Serialize;
@@ -30,6 +31,11 @@ pub(super) fn push_leb_usize(into: &mut Vec<u8>, val: usize) {
     }
 }
 
+#[allow(clippy::unnecessary_cast)] // LV is u64 with the lv_u64 feature.
+pub(super) fn push_leb_lv(into: &mut Vec<u8>, val: LV) {
+    push_leb_u64(into, val as u64);
+}
+
 pub(super) fn push_leb_str(into: &mut Vec<u8>, val: &str) {
     let bytes = val.as_bytes();
     push_leb_usize(into, bytes.len());
@@ -42,6 +48,10 @@ pub(super) fn push_u32_le(into: &mut Vec<u8>, val: u32) {
     into.extend_from_slice(&bytes);
 }
 
+pub(super) fn push_u64_le(into: &mut Vec<u8>, val: u64) {
+    into.extend_from_slice(&val.to_le_bytes());
+}
+
 fn push_leb_chunk_header(into: &mut Vec<u8>, chunk_type: ListChunkType, len: usize) {
     push_leb_u32(into, chunk_type as u32);
     push_leb_usize(into, len);
@@ -63,22 +73,21 @@ pub(super) fn write_leb_bit_run(run: RleRun<bool>, into: &mut Vec<u8>) {
 }
 
 #[derive(Clone)]
-pub(super) struct Merger<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx = ()> {
+pub(super) struct Merger<S: MergableSpanCtx, F: FnMut(S, &mut Ctx), Ctx = ()> {
     pub(super) last: Option<S>,
     f: F,
     _ctx: PhantomData<Ctx> // This is awful.
 }
 
-impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Merger<S, F, Ctx> {
+impl<S: MergableSpanCtx, F: FnMut(S, &mut Ctx), Ctx> Merger<S, F, Ctx> {
     pub fn new(f: F) -> Self {
         Self { last: None, f, _ctx: PhantomData }
     }
 
-    pub fn push2(&mut self, span: S, ctx: &mut Ctx) {
+    /// Push a span whose merging depends on `merge_ctx`. See [`MergableSpanCtx`].
+    pub fn push_ctx(&mut self, span: S, merge_ctx: &S::Ctx, ctx: &mut Ctx) {
         if let Some(last) = self.last.as_mut() {
-            if last.can_append(&span) {
-                last.append(span);
-            } else {
+            if let Err((span, _)) = last.try_append_ctx(span, merge_ctx) {
                 let old = replace(last, span);
                 (self.f)(old, ctx);
             }
@@ -94,8 +103,14 @@ impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Merger<S, F, Ctx> {
     }
 }
 
+impl<S: MergableSpanCtx<Ctx = ()>, F: FnMut(S, &mut Ctx), Ctx> Merger<S, F, Ctx> {
+    pub fn push2(&mut self, span: S, ctx: &mut Ctx) {
+        self.push_ctx(span, &(), ctx);
+    }
+}
+
 // I hate this.
-impl<S: MergableSpan, F: FnMut(S, &mut ())> Merger<S, F, ()> {
+impl<S: MergableSpanCtx<Ctx = ()>, F: FnMut(S, &mut ())> Merger<S, F, ()> {
     pub fn push(&mut self, span: S) {
         self.push2(span, &mut ());
     }
@@ -104,7 +119,7 @@ impl<S: MergableSpan, F: FnMut(S, &mut ())> Merger<S, F, ()> {
     }
 }
 
-impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Drop for Merger<S, F, Ctx> {
+impl<S: MergableSpanCtx, F: FnMut(S, &mut Ctx), Ctx> Drop for Merger<S, F, Ctx> {
     fn drop(&mut self) {
         if self.last.is_some() && !std::thread::panicking() {
             panic!("Merger dropped with unprocessed data");
@@ -0,0 +1,382 @@
+//! A description of the binary format, as data.
+//!
+//! Encoded files start with the magic bytes `DMNDTYPS` and a protocol version, followed by a
+//! sequence of chunks. Each chunk has a type, a length and a payload. Some chunks (like the file
+//! info) contain more chunks.
+//!
+//! The [`CHUNKS`] table lists every chunk type the encoder can write, where each one can appear,
+//! which ones are required and the protocol version each was added in. The table is in file
+//! order - within any parent, chunks must appear in the order they're listed here. The exception
+//! is ChunkCrc, which can follow any top level chunk.
+//!
+//! Since protocol version 1, readers skip chunk types they don't know, unless the chunk type has
+//! the critical bit (`0x80`) set. Critical chunks change the meaning of the file, so readers which
+//! don't understand them must reject the file instead. Version 0 readers reject every chunk they
+//! don't know. So files written for version 0 leave out every chunk added since then, along with
+//! the data stored in those chunks (like signatures and checkpoints).
+//!
+//! Since protocol version 1, each name in the AgentNames chunk is also followed by that agent's
+//! [info](crate::list::AgentInfo). Version 0 files only store the names.
+//!
+//! [`read_layout`] reads the chunk tree of an encoded file, and [`validate_layout`] checks it
+//! against the table. Peers which support different versions of the format can agree on a
+//! version to use with [`negotiate_version`], then pass it to
+//! [`EncodeOptions::protocol_version`](super::EncodeOptions::protocol_version). The decoder
+//! accepts any supported version.
+
+use std::ops::{Range, RangeInclusive};
+use crate::encoding::parseerror::ParseError;
+use crate::list::encoding::decode_tools::{BufReader, ChunkReader};
+use crate::list::encoding::{ListChunkType, PROTOCOL_VERSION};
+use ListChunkType::*;
+
+/// The protocol version written by default.
+pub const CURRENT_VERSION: usize = PROTOCOL_VERSION;
+
+/// The oldest protocol version we can read and write.
+pub const OLDEST_SUPPORTED_VERSION: usize = 0;
+
+/// Every protocol version we can read and write.
+pub const SUPPORTED_VERSIONS: RangeInclusive<usize> = OLDEST_SUPPORTED_VERSION..=CURRENT_VERSION;
+
+pub fn is_supported_version(version: usize) -> bool {
+    SUPPORTED_VERSIONS.contains(&version)
+}
+
+/// Does the AgentNames chunk store agent info in this protocol version?
+pub(crate) fn agent_names_have_info(version: usize) -> bool {
+    version >= 1
+}
+
+/// Pick the newest protocol version supported by both us and a peer which supports
+/// `peer_versions`. Returns None if there's no version in common.
+pub fn negotiate_version(peer_versions: RangeInclusive<usize>) -> Option<usize> {
+    SUPPORTED_VERSIONS.rev().find(|v| peer_versions.contains(v))
+}
+
+/// How a chunk's payload is structured.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ChunkBody {
+    /// Chunk specific data.
+    Data,
+    /// A sequence of child chunks.
+    Chunks,
+    /// A varint tag, then a sequence of child chunks.
+    TaggedChunks,
+}
+
+/// Somewhere a chunk can appear in a file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ChunkPlacement {
+    /// The type of the chunk this chunk appears inside. None for top level chunks.
+    pub parent: Option<u32>,
+    /// Must the chunk appear (at least once) in every instance of its parent?
+    pub required: bool,
+}
+
+/// Everything we know about a chunk type. See [`CHUNKS`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ChunkSpec {
+    /// The chunk type, as written in the file.
+    pub id: u32,
+    pub name: &'static str,
+    pub body: ChunkBody,
+    /// The protocol version this chunk type was added in.
+    pub since_version: usize,
+    pub placements: &'static [ChunkPlacement],
+}
+
+const fn top(required: bool) -> ChunkPlacement {
+    ChunkPlacement { parent: None, required }
+}
+
+const fn inside(parent: ListChunkType, required: bool) -> ChunkPlacement {
+    ChunkPlacement { parent: Some(parent as u32), required }
+}
+
+const fn spec(chunk: ListChunkType, name: &'static str, body: ChunkBody, placements: &'static [ChunkPlacement]) -> ChunkSpec {
+    ChunkSpec { id: chunk as u32, name, body, since_version: 0, placements }
+}
+
+impl ChunkSpec {
+    const fn since(mut self, version: usize) -> Self {
+        self.since_version = version;
+        self
+    }
+}
+
+/// Every chunk type, in file order.
+pub const CHUNKS: &[ChunkSpec] = &[
+    spec(CompressedFieldsLZ4, "CompressedFieldsLZ4", ChunkBody::Data, &[top(false)]),
+    spec(CompressedFieldsZstd, "CompressedFieldsZstd", ChunkBody::Data, &[top(false)]).since(1),
+
+    spec(FileInfo, "FileInfo", ChunkBody::Chunks, &[top(true)]),
+    spec(DocId, "DocId", ChunkBody::Data, &[inside(FileInfo, false)]),
+    spec(ForkPoints, "ForkPoints", ChunkBody::Data, &[inside(FileInfo, false)]).since(1),
+    spec(AgentNames, "AgentNames", ChunkBody::Data, &[inside(FileInfo, true)]),
+    spec(InsertTieBreak, "InsertTieBreak", ChunkBody::Data, &[inside(FileInfo, false)]).since(1),
+    spec(UserData, "UserData", ChunkBody::Data, &[inside(FileInfo, false)]),
+
+    spec(StartBranch, "StartBranch", ChunkBody::Chunks, &[top(true)]),
+    spec(ExperimentalEndBranch, "ExperimentalEndBranch", ChunkBody::Chunks, &[top(false)]),
+    spec(Patches, "Patches", ChunkBody::Chunks, &[top(true)]),
+    spec(Version, "Version", ChunkBody::Data, &[
+        inside(StartBranch, false), inside(ExperimentalEndBranch, false),
+    ]),
+    spec(VersionSummary, "VersionSummary", ChunkBody::Data, &[inside(StartBranch, false)]).since(1),
+    spec(PatchContent, "PatchContent", ChunkBody::TaggedChunks, &[inside(Patches, false)]),
+    // Content chunks appear in branches and in patch content. Only one of them is written.
+    spec(Content, "Content", ChunkBody::Data, &[
+        inside(StartBranch, false), inside(ExperimentalEndBranch, false), inside(PatchContent, false),
+    ]),
+    spec(ContentCompressed, "ContentCompressed", ChunkBody::Data, &[
+        inside(StartBranch, false), inside(ExperimentalEndBranch, false), inside(PatchContent, false),
+    ]),
+    spec(ContentIsKnown, "ContentIsKnown", ChunkBody::Data, &[inside(PatchContent, true)]),
+    spec(OpVersions, "OpVersions", ChunkBody::Data, &[inside(Patches, true)]),
+    spec(OpTypeAndPosition, "OpTypeAndPosition", ChunkBody::Data, &[inside(Patches, true)]),
+    spec(OpParents, "OpParents", ChunkBody::Data, &[inside(Patches, true)]),
+    spec(Transactions, "Transactions", ChunkBody::Data, &[inside(Patches, false)]).since(1),
+    spec(OpMetadata, "OpMetadata", ChunkBody::Data, &[inside(Patches, false)]).since(1),
+    spec(TransformedCancelsOps, "TransformedCancelsOps", ChunkBody::Data, &[inside(Patches, false)]),
+    spec(TransformedPositions, "TransformedPositions", ChunkBody::Data, &[inside(Patches, false)]),
+
+    spec(Signatures, "Signatures", ChunkBody::Data, &[top(false)]).since(1),
+    spec(Checkpoints, "Checkpoints", ChunkBody::Data, &[top(false)]).since(1),
+    spec(NamedBranches, "NamedBranches", ChunkBody::Data, &[top(false)]).since(1),
+    spec(VersionHash, "VersionHash", ChunkBody::Data, &[top(false)]).since(1),
+    spec(PrunedHistory, "PrunedHistory", ChunkBody::Data, &[top(false)]).since(1),
+    spec(ContentManifest, "ContentManifest", ChunkBody::Data, &[top(false)]).since(1),
+    // Chunk CRCs can appear after any top level chunk. They're exempt from the ordering rules.
+    spec(ChunkCrc, "ChunkCrc", ChunkBody::Data, &[top(false)]).since(1),
+    // Encrypted chunks wrap other top level chunks. They're listed as the chunk inside.
+    spec(Encrypted, "Encrypted", ChunkBody::Data, &[top(false)]).since(1),
+    spec(Crc, "Crc", ChunkBody::Data, &[top(false)]),
+];
+
+/// Look up a chunk type in [`CHUNKS`].
+pub fn chunk_spec(id: u32) -> Option<&'static ChunkSpec> {
+    CHUNKS.iter().find(|s| s.id == id)
+}
+
+/// A chunk in an encoded file. See [`read_layout`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LayoutChunk {
+    /// The chunk type. For encrypted chunks, this is the type of the chunk inside.
+    pub id: u32,
+    /// The index of the parent chunk in [`FileLayout::chunks`]. None for top level chunks.
+    pub parent: Option<usize>,
+    /// Is the chunk's payload encrypted? The children of encrypted chunks aren't listed.
+    pub encrypted: bool,
+    /// The position of the chunk's payload in the file.
+    pub payload: Range<usize>,
+}
+
+/// The chunk tree of an encoded file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileLayout {
+    pub version: usize,
+    /// Every chunk in the file, parents before their children.
+    pub chunks: Vec<LayoutChunk>,
+}
+
+fn read_layout_chunks(data: &[u8], mut reader: ChunkReader, parent: Option<usize>, result: &mut Vec<LayoutChunk>) -> Result<(), ParseError> {
+    while !reader.is_empty() {
+        let (mut id, mut chunk) = reader.next_chunk_untyped()?;
+        let end = data.len() - reader.0.len();
+        let payload = end - chunk.len()..end;
+
+        let encrypted = id == Encrypted as u32;
+        if encrypted {
+            id = chunk.next_u32()?;
+        }
+        result.push(LayoutChunk { id, parent, encrypted, payload });
+        if encrypted { continue; }
+
+        let idx = result.len() - 1;
+        match chunk_spec(id).map(|s| s.body) {
+            Some(ChunkBody::Chunks) => read_layout_chunks(data, chunk.chunks(), Some(idx), result)?,
+            Some(ChunkBody::TaggedChunks) => {
+                chunk.next_u32()?;
+                read_layout_chunks(data, chunk.chunks(), Some(idx), result)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Read the chunk tree of an encoded file. Only the chunk headers are read - the file isn't
+/// otherwise parsed or validated.
+pub fn read_layout(data: &[u8]) -> Result<FileLayout, ParseError> {
+    let mut reader = BufReader(data);
+    reader.read_magic()?;
+    let version = reader.next_usize()?;
+    if !is_supported_version(version) {
+        return Err(ParseError::UnsupportedProtocolVersion);
+    }
+
+    let mut chunks = vec![];
+    read_layout_chunks(data, reader.chunks(), None, &mut chunks)?;
+    Ok(FileLayout { version, chunks })
+}
+
+/// Strictly check a file's chunk tree against [`CHUNKS`]. Every chunk must be known, in an allowed
+/// place and in order, and every required chunk must be present.
+///
+/// The decoder is more lenient than this. It skips unknown chunks, so files from newer versions
+/// of diamond types can still be read.
+pub fn validate_layout(layout: &FileLayout) -> Result<(), ParseError> {
+    // The index in CHUNKS of the last chunk seen in each parent.
+    let mut last_seen: Vec<(Option<usize>, usize)> = vec![];
+
+    for c in layout.chunks.iter() {
+        let idx = CHUNKS.iter().position(|s| s.id == c.id).ok_or(ParseError::UnknownChunk)?;
+        let spec = &CHUNKS[idx];
+        if spec.since_version > layout.version {
+            return Err(ParseError::UnknownChunk);
+        }
+
+        let parent_id = c.parent.map(|p| layout.chunks[p].id);
+        if !spec.placements.iter().any(|p| p.parent == parent_id) {
+            return Err(ParseError::InvalidChunkHeader);
+        }
+
+        if c.id == ChunkCrc as u32 { continue; }
+        match last_seen.iter_mut().find(|(p, _)| *p == c.parent) {
+            Some((_, last)) if *last > idx => { return Err(ParseError::InvalidChunkHeader); }
+            Some((_, last)) => { *last = idx; }
+            None => { last_seen.push((c.parent, idx)); }
+        }
+    }
+
+    // Check required chunks are present. The children of encrypted chunks are unknown.
+    let parents = std::iter::once(None)
+        .chain(layout.chunks.iter().enumerate().filter(|(_, c)| !c.encrypted).map(|(i, _)| Some(i)));
+    for parent in parents {
+        let parent_id = parent.map(|p| layout.chunks[p].id);
+        for spec in CHUNKS {
+            let required = spec.placements.iter().any(|p| p.parent == parent_id && p.required);
+            if required && !layout.chunks.iter().any(|c| c.parent == parent && c.id == spec.id) {
+                return Err(ParseError::MissingChunk(spec.id));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+    use crate::list::gen_random::gen_oplog;
+    use crate::list::ListOpLog;
+    use crate::list::encoding::{EncodeOptions, ParseError};
+    use super::*;
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(negotiate_version(0..=0), Some(0));
+        assert_eq!(negotiate_version(0..=100), Some(CURRENT_VERSION));
+        assert_eq!(negotiate_version(CURRENT_VERSION + 1..=100), None);
+        assert!(is_supported_version(CURRENT_VERSION));
+        assert!(!is_supported_version(CURRENT_VERSION + 1));
+    }
+
+    #[test]
+    fn table_is_consistent() {
+        for (i, s) in CHUNKS.iter().enumerate() {
+            assert_eq!(ListChunkType::try_from(s.id).map(|c| c as u32), Ok(s.id));
+            assert_eq!(chunk_spec(s.id), Some(s), "Duplicate chunk {}", s.name);
+            assert!(is_supported_version(s.since_version));
+            for p in s.placements {
+                // Parents must come before their children.
+                if let Some(parent) = p.parent {
+                    assert!(CHUNKS[..i].iter().any(|c| c.id == parent && c.body != ChunkBody::Data));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encoded_files_match_spec() {
+        for seed in 0..40 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let oplog = gen_oplog(seed, 30, rng.gen_bool(0.5), true);
+            let from = if rng.gen_bool(0.3) { oplog.len() / 2 } else { 0 };
+            let from_version = oplog.cg.graph.find_dominators(&(0..from).collect::<Vec<_>>());
+
+            let opts = if rng.gen_bool(0.5) { EncodeOptions::full() } else { EncodeOptions::patch() }
+                .store_deleted_content(rng.gen_bool(0.5))
+                .compress_content(rng.gen_bool(0.5))
+                .store_op_metadata(rng.gen_bool(0.5))
+                .content_defined_chunks(rng.gen_bool(0.2))
+                .store_start_summary(rng.gen_bool(0.3))
+                .chunk_crcs(rng.gen_bool(0.3));
+            let opts = if rng.gen_bool(0.3) { opts.store_xf(true) } else { opts };
+
+            for version in SUPPORTED_VERSIONS {
+                let data = oplog.encode_from(&opts.clone().protocol_version(version), from_version.as_ref());
+                let layout = read_layout(&data).unwrap();
+                assert_eq!(layout.version, version);
+                validate_layout(&layout).unwrap();
+
+                let mut result = ListOpLog::new();
+                if from > 0 {
+                    let base = oplog.encode_range(&[(0..from).into()], &[], &EncodeOptions::patch()).unwrap();
+                    result.decode_and_add(&base).unwrap();
+                }
+                result.decode_and_add(&data).unwrap();
+                assert!(oplog.eq_ignoring_agent_order(&result));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_layouts() {
+        let oplog = gen_oplog(1, 10, false, true);
+        let data = oplog.encode(&EncodeOptions::default());
+        let layout = read_layout(&data).unwrap();
+
+        let mut missing = layout.clone();
+        let idx = missing.chunks.iter().position(|c| c.id == OpParents as u32).unwrap();
+        missing.chunks.remove(idx);
+        assert_eq!(validate_layout(&missing), Err(ParseError::MissingChunk(OpParents as u32)));
+
+        let mut misplaced = layout.clone();
+        misplaced.chunks[idx].parent = None;
+        assert_eq!(validate_layout(&misplaced), Err(ParseError::InvalidChunkHeader));
+
+        let mut reordered = layout.clone();
+        let first = reordered.chunks.iter().position(|c| c.parent.is_none()).unwrap();
+        let last = reordered.chunks.iter().rposition(|c| c.parent.is_none()).unwrap();
+        reordered.chunks.swap(first, last);
+        assert!(validate_layout(&reordered).is_err());
+
+        let mut future = data.clone();
+        future[8] = CURRENT_VERSION as u8 + 1;
+        assert_eq!(read_layout(&future), Err(ParseError::UnsupportedProtocolVersion));
+        assert_eq!(ListOpLog::load_from(&future).unwrap_err(), ParseError::UnsupportedProtocolVersion);
+    }
+
+    #[test]
+    fn old_versions_leave_out_new_chunks() {
+        let mut oplog = gen_oplog(2, 20, false, true);
+        let version = oplog.local_frontier();
+        oplog.add_checkpoint(version.as_ref(), "v1", "First version");
+        let opts = EncodeOptions::full()
+            .store_start_summary(true)
+            .chunk_crcs(true)
+            .protocol_version(0);
+        let data = oplog.encode(&opts);
+
+        let layout = read_layout(&data).unwrap();
+        assert_eq!(layout.version, 0);
+        validate_layout(&layout).unwrap();
+        assert!(layout.chunks.iter().all(|c| chunk_spec(c.id).unwrap().since_version == 0));
+
+        let result = ListOpLog::load_from(&data).unwrap();
+        assert!(oplog.eq_ignoring_agent_order(&result));
+        assert!(result.checkpoints().is_empty());
+    }
+}
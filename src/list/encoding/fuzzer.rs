@@ -1,9 +1,13 @@
 use rand::prelude::*;
 use crate::list::{ListCRDT, ListOpLog};
 use crate::list::encoding::EncodeOptions;
+use crate::list::encoding::decode_oplog::DecodeOptions;
 use crate::list::old_fuzzer_tools::old_make_random_change;
 use crate::list_fuzzer_tools::{choose_2, make_random_change};
 use crate::listmerge::simple_oplog::{SimpleBranch, SimpleOpLog};
+use crate::list::OpMetadata;
+use crate::list::encoding::{DataType, MAGIC_BYTES, PROTOCOL_VERSION};
+use crate::list::encoding::encode_tools::{push_leb_u32, push_leb_u64, push_leb_usize};
 
 // This fuzzer will make an oplog, spam it with random changes from a single peer. Then save & load
 // it back to make sure the result doesn't change.
@@ -115,3 +119,164 @@ fn encode_decode_multi_fuzz_forever() {
         fuzz_encode_decode_multi(seed, false);
     }
 }
+
+/// Corrupt an encoded document in some random way.
+fn corrupt(bytes: &mut Vec<u8>, rng: &mut SmallRng) {
+    for _ in 0..rng.gen_range(1..4) {
+        if bytes.is_empty() { return; }
+        let i = rng.gen_range(0..bytes.len());
+        match rng.gen_range(0..5) {
+            0 => bytes.truncate(i),
+            1 => bytes.insert(i, rng.gen()),
+            2 => { bytes.remove(i); },
+            3 => bytes[i] = rng.gen(),
+            // Small changes are more likely to produce data which still parses.
+            _ => bytes[i] = bytes[i].wrapping_add(rng.gen_range(1..3)),
+        }
+    }
+}
+
+// Decoding corrupt data should always return an error (or some valid oplog), and never panic.
+fn fuzz_decode_corrupt_once(seed: u64) {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut doc = ListCRDT::new();
+    doc.get_or_create_agent_id("a");
+    doc.get_or_create_agent_id("b");
+    for _ in 0..rng.gen_range(1..20) {
+        let agent = rng.gen_range(0..2);
+        old_make_random_change(&mut doc, None, agent, &mut rng, true);
+    }
+
+    let mut bytes = doc.oplog.encode(&EncodeOptions::full().store_deleted_content(true));
+    corrupt(&mut bytes, &mut rng);
+    let opts = DecodeOptions { ignore_crc: true, verbose: false, allow_missing_content: false, cipher: None, verify_signatures: None, require_doc_id: false, max_ops: None, max_content_bytes: None, max_agents: None, require_crc: false, require_contiguous_seqs: false, check_positions: false, trust_transformed_positions: false,
+        #[cfg(feature = "dag_hash")] verify_version_hash: false, lazy_content: false };
+
+    if let Ok(oplog) = ListOpLog::load_from_opts(&bytes, opts.clone()) {
+        oplog.dbg_check(true);
+    }
+
+    // Merging into a document with overlapping history takes a different path.
+    let mut oplog = doc.oplog.clone();
+    if oplog.decode_and_add_opts(&bytes, opts).is_err() {
+        // Failed merges are rolled back.
+        assert_eq!(oplog, doc.oplog);
+    }
+    oplog.dbg_check(true);
+}
+
+#[test]
+fn decode_corrupt_data_fuzz_once() {
+    for seed in 0..1000 {
+        fuzz_decode_corrupt_once(seed);
+    }
+}
+
+#[test]
+#[ignore]
+fn decode_corrupt_data_fuzz_forever() {
+    for seed in 0.. {
+        if seed % 1000 == 0 { println!("seed {seed}"); }
+        fuzz_decode_corrupt_once(seed);
+    }
+}
+
+const CHUNK_TYPES: &[u32] = &[1, 2, 3, 4, 5, 6, 10, 11, 12, 13, 14, 15, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 90, 100];
+
+/// Append a random (probably invalid) chunk tree. The chunk structure is usually valid, so the
+/// fields inside chunks get exercised too.
+fn push_random_chunks(out: &mut Vec<u8>, rng: &mut SmallRng, depth: usize) {
+    for _ in 0..rng.gen_range(0..4) {
+        let mut body = vec![];
+        match rng.gen_range(0..4) {
+            0 if depth < 3 => push_random_chunks(&mut body, rng, depth + 1),
+            1 => {
+                for _ in 0..rng.gen_range(0..8) {
+                    // Mostly small numbers, but sometimes huge ones.
+                    let n = if rng.gen_bool(0.8) { rng.gen_range(0..40) } else { rng.gen() };
+                    push_leb_u64(&mut body, n);
+                }
+            }
+            2 => {
+                push_leb_u32(&mut body, DataType::PlainText as u32);
+                body.extend_from_slice("hi there ツ".as_bytes());
+            }
+            _ => body.extend((0..rng.gen_range(0..16)).map(|_| rng.gen::<u8>())),
+        }
+        push_leb_u32(out, CHUNK_TYPES[rng.gen_range(0..CHUNK_TYPES.len())]);
+        let len = if rng.gen_bool(0.95) { body.len() } else { rng.gen() };
+        push_leb_usize(out, len);
+        out.extend_from_slice(&body);
+    }
+}
+
+/// Check that decoding `bytes` either succeeds or fails cleanly. When merging into an existing
+/// document fails, the document must be unchanged.
+fn check_decode_is_safe(bytes: &[u8], existing: &ListOpLog) {
+    let opts = DecodeOptions { ignore_crc: true, trust_transformed_positions: true, ..Default::default() };
+    if let Ok(oplog) = ListOpLog::load_from_opts(bytes, opts.clone()) {
+        oplog.dbg_check(true);
+        // Stored transformed positions are trusted, but bad positions mustn't panic.
+        oplog.checkout_transformed_positions();
+        // Loading the same data again should be a no-op.
+        let mut merged = oplog.clone();
+        merged.decode_and_add_opts(bytes, opts.clone()).unwrap();
+        assert_eq!(merged, oplog);
+    }
+
+    let mut oplog = existing.clone();
+    if oplog.decode_and_add_opts(bytes, opts).is_err() {
+        assert_eq!(&oplog, existing);
+    }
+    oplog.dbg_check(true);
+}
+
+// Decode adversarial data: random chunk trees, and heavily mangled encodings with every optional
+// chunk present.
+fn fuzz_decode_adversarial_once(seed: u64) {
+    let mut rng = SmallRng::seed_from_u64(seed);
+
+    let mut bytes = MAGIC_BYTES.to_vec();
+    push_leb_usize(&mut bytes, PROTOCOL_VERSION);
+    push_random_chunks(&mut bytes, &mut rng, 0);
+    check_decode_is_safe(&bytes, &ListOpLog::new());
+
+    let mut doc = ListCRDT::new();
+    doc.get_or_create_agent_id("a");
+    doc.get_or_create_agent_id("b");
+    for _ in 0..rng.gen_range(1..10) {
+        let agent = rng.gen_range(0..2);
+        old_make_random_change(&mut doc, None, agent, &mut rng, true);
+    }
+    let mut oplog = doc.oplog;
+    oplog.set_op_metadata((0..1).into(), OpMetadata { timestamp: Some(rng.gen()), data: vec![1, 2] });
+    oplog.add_checkpoint(oplog.local_frontier().as_ref(), "c", "msg");
+    oplog.create_branch("main", oplog.local_frontier().as_ref());
+
+    let opts = EncodeOptions::full()
+        .store_deleted_content(true)
+        .compress_content(rng.gen())
+        .store_xf(rng.gen_bool(0.3));
+    let mut bytes = oplog.encode(&opts);
+    for _ in 0..rng.gen_range(1..4) {
+        corrupt(&mut bytes, &mut rng);
+    }
+    check_decode_is_safe(&bytes, &ListOpLog::new());
+    check_decode_is_safe(&bytes, &oplog);
+}
+
+#[test]
+fn decode_adversarial_fuzz_once() {
+    for seed in 0..2000 {
+        fuzz_decode_adversarial_once(seed);
+    }
+}
+
+#[test]
+#[ignore]
+fn decode_adversarial_fuzz_forever() {
+    for seed in 0.. {
+        if seed % 1000 == 0 { println!("seed {seed}"); }
+        fuzz_decode_adversarial_once(seed);
+    }
+}
@@ -170,24 +170,20 @@ pub fn decode_leb_u64(buf: &[u8]) -> Result<(u64, usize), ParseError> {
 
 pub fn decode_leb_u32(buf: &[u8]) -> Result<(u32, usize), ParseError> {
     let (val, bytes_consumed) = decode_leb_u64(buf)?;
-    if val >= u32::MAX as u64 {
+    // Overlong encodings (with extra 0x80 bytes) are invalid too.
+    if val >= u32::MAX as u64 || bytes_consumed > 5 {
         // varint is not a u32!
         return Err(ParseError::InvalidVarInt);
     }
-    debug_assert!(bytes_consumed <= 5);
     Ok((val as u32, bytes_consumed))
 }
 
 pub fn decode_leb_usize(buf: &[u8]) -> Result<(usize, usize), ParseError> {
-    if size_of::<usize>() <= size_of::<u32>() {
-        let (val, count) = decode_leb_u32(buf)?;
-        Ok((val as usize, count))
-    } else if size_of::<usize>() == size_of::<u64>() {
-        let (val, count) = decode_leb_u64(buf)?;
-        Ok((val as usize, count))
-    } else {
-        panic!("usize larger than u64 not supported");
-    }
+    // Encoded data is the same on every platform. On 32 bit platforms (like wasm32), documents
+    // from 64 bit peers can contain values which don't fit in a usize.
+    let (val, count) = decode_leb_u64(buf)?;
+    let val = usize::try_from(val).map_err(|_| ParseError::ValueTooLarge)?;
+    Ok((val, count))
 }
 
 #[cfg(test)]
@@ -0,0 +1,102 @@
+//! Loading oplogs from memory mapped files.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use memmap2::Mmap;
+use crate::encoding::parseerror::ParseError;
+use crate::list::encoding::{DecodeOptions, SharedBytes};
+use crate::list::ListOpLog;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LoadFileError {
+    IO(io::Error),
+    ParseError(ParseError),
+}
+
+impl From<io::Error> for LoadFileError {
+    fn from(err: io::Error) -> Self {
+        LoadFileError::IO(err)
+    }
+}
+
+impl From<ParseError> for LoadFileError {
+    fn from(err: ParseError) -> Self {
+        LoadFileError::ParseError(err)
+    }
+}
+
+impl ListOpLog {
+    /// Load an oplog from a file by memory mapping it.
+    ///
+    /// Inserted and deleted content is loaded lazily (see [`DecodeOptions::lazy_content`]). Its
+    /// checked but not copied while the file is loaded - each chunk of content is read from the
+    /// mapped file the first time its needed. The OS is free to drop the file's pages from memory, and they're
+    /// paged back in when the content is next read. This makes opening documents with a lot of
+    /// history much faster, and makes a big difference to their memory footprint.
+    ///
+    /// For this to work, the file must have been saved with content compression disabled.
+    /// Compressed content is read and copied into memory as usual. Note the file's checksum (if
+    /// any) is still checked, which reads the whole file once. Set
+    /// [`DecodeOptions::ignore_crc`] with [`load_from_file_mmap_opts`](Self::load_from_file_mmap_opts)
+    /// to skip it.
+    ///
+    /// # Panics
+    ///
+    /// Content isn't checked until its read, so reading content from a corrupt file panics.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated (by this process or any other) while the oplog,
+    /// or anything cloned from it, is alive. Modifying a memory mapped file is undefined
+    /// behaviour.
+    pub unsafe fn load_from_file_mmap<P: AsRef<Path>>(path: P) -> Result<Self, LoadFileError> {
+        Self::load_from_file_mmap_opts(path, DecodeOptions::default())
+    }
+
+    /// Load an oplog from a memory mapped file with the given options. Content is always loaded
+    /// lazily. See [`load_from_file_mmap`](Self::load_from_file_mmap).
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified while the oplog is alive. See
+    /// [`load_from_file_mmap`](Self::load_from_file_mmap).
+    pub unsafe fn load_from_file_mmap_opts<P: AsRef<Path>>(path: P, opts: DecodeOptions) -> Result<Self, LoadFileError> {
+        let file = File::open(path)?;
+        // Safety: The caller promises the file won't be modified.
+        let map = unsafe { Mmap::map(&file)? };
+        let data: SharedBytes = Arc::new(map);
+        Ok(Self::load_from_shared_opts(data, DecodeOptions { lazy_content: true, ..opts })?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::ListCRDT;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn load_mmap() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hello from a memory mapped file 🗺️");
+        doc.delete(seph, 6..11);
+        doc.insert(seph, 6, "inside");
+
+        let path = std::env::temp_dir().join(format!("dt_mmap_test_{}.dt", std::process::id()));
+        std::fs::write(&path, doc.oplog.encode(&EncodeOptions::full().compress_content(false).store_deleted_content(true))).unwrap();
+
+        // Safety: Nothing else touches the file.
+        let oplog = unsafe { ListOpLog::load_from_file_mmap(&path) }.unwrap();
+        // Nothing has been read yet.
+        assert_eq!(oplog.mem_usage_breakdown().content, 0);
+        assert_eq!(oplog.checkout_tip().content(), doc.branch.content());
+        assert_eq!(oplog, doc.oplog);
+
+        drop(oplog);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -15,15 +15,37 @@ pub mod save_transformed;
 pub(crate) mod leb;
 pub(crate) mod txn_trace;
 mod encode_options;
+mod cipher;
+mod cdc;
+mod version_summary;
+mod describe;
+pub mod format;
+#[cfg(feature = "mmap")]
+mod mmap;
 
-use rle::MergableSpan;
+use rle::{HasLength, MergableSpan};
 use crate::encoding::varint::*;
+use crate::{DTRange, LV};
+use crate::rle::{KVPair, RleVec};
 use num_enum::TryFromPrimitive;
 pub use encode_options::{EncodeOptions, EncodeOptionsBuilder, ENCODE_FULL, ENCODE_PATCH};
+pub use decode_oplog::{DecodeOptions, IngestSummary};
+pub use encode_oplog::MissingDependencies;
+pub use crate::encoding::parseerror::{DecodeLimit, ParseError};
+pub use cipher::ChunkCipher;
+pub use cdc::{ManifestChunk, read_content_manifest};
+pub use version_summary::read_start_summary;
+pub use describe::PatchSummary;
+pub(crate) use describe::{read_patch_header, read_patch_ops};
+pub use crate::list::content_buffer::SharedBytes;
+#[cfg(feature = "mmap")]
+pub use mmap::LoadFileError;
 
 const MAGIC_BYTES: [u8; 8] = *b"DMNDTYPS";
 
-const PROTOCOL_VERSION: usize = 0;
+/// Version 1 added skipping unknown chunks, and lots of new chunk types. See [`format::CHUNKS`]
+/// for the version each chunk type was added in.
+const PROTOCOL_VERSION: usize = 1;
 
 // #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 #[derive(Debug, PartialEq, Eq, Copy, Clone, TryFromPrimitive)]
@@ -31,18 +53,29 @@ const PROTOCOL_VERSION: usize = 0;
 enum ListChunkType {
     /// Packed bytes storing any data compressed in later parts of the file.
     CompressedFieldsLZ4 = 5,
+    CompressedFieldsZstd = 6,
+    // Chunk types 5-9 are reserved for compressed fields in different formats.
 
     /// FileInfo contains optional UserData and AgentNames.
     FileInfo = 1,
     DocId = 2,
+    /// The name of each agent in the file. Since protocol version 1, each name is followed by the
+    /// agent's info.
     AgentNames = 3,
     UserData = 4,
+    /// The documents this document was forked from.
+    ForkPoints = 15,
+    /// The name of the custom [`TieBreak`](crate::list::TieBreak) policy used to order concurrent
+    /// inserts. Critical, since readers which ignore it would merge the document differently.
+    InsertTieBreak = 0x81,
 
     /// The StartBranch chunk describes the state of the document before included patches have been
     /// applied.
     StartBranch = 10,
     ExperimentalEndBranch = 11,
     Version = 12,
+    /// A [`VersionSummary`](crate::causalgraph::summary::VersionSummary) of the start version.
+    VersionSummary = 36,
     /// StartBranch content is optional.
     Content = 13,
     ContentCompressed = 14, // Might make more sense to have a generic compression tag for chunks.
@@ -55,15 +88,64 @@ enum ListChunkType {
     PatchContent = 24,
     /// ContentKnown is a RLE expressing which ranges of patches have known content
     ContentIsKnown = 25,
+    /// The boundaries of atomic transactions, as (gap since last transaction, length) pairs.
+    Transactions = 26,
+    /// Timestamps and metadata for runs of operations, in file order.
+    OpMetadata = 29,
 
     /// A chunk specifying which operations are cancelled when the data is transformed
     TransformedCancelsOps = 27,
     /// A chunk specifying the position deltas for operations when transformed in the stored order
     TransformedPositions = 28,
 
+    /// Signatures over ranges of operations in the file.
+    Signatures = 30,
+    /// Named versions of the document.
+    Checkpoints = 31,
+    /// Named branches, and their versions.
+    NamedBranches = 32,
+    /// The content-defined chunks of the patches, and their hashes.
+    ContentManifest = 33,
+    /// The hash of the version at the end of the file. See `ListOpLog::hash_for_version`.
+    VersionHash = 34,
+    /// The IDs of operations pruned from a shallow clone.
+    PrunedHistory = 35,
+    /// The CRC of the chunk immediately before this one. See `EncodeOptions::chunk_crcs`.
+    ChunkCrc = 37,
+
+    /// Wraps a chunk whose payload has been encrypted. Contains the inner chunk type, then the
+    /// ciphertext.
+    Encrypted = 90,
+
     Crc = 100,
 }
 
+/// Chunk types with this bit set are critical. Readers which don't understand a critical chunk
+/// must fail to load the file. Unknown chunks without this bit are skipped (since protocol
+/// version 1), so new optional chunks can be added without breaking older readers.
+///
+/// InsertTieBreak is currently the only critical chunk type.
+const CRITICAL_CHUNK_BIT: u32 = 0x80;
+
+/// Map a range through a version map (eg from local versions to versions in a file). Returns None
+/// unless the whole range is mapped to a single contiguous range.
+fn map_contiguous_range(map: &RleVec<KVPair<DTRange>>, range: DTRange) -> Option<DTRange> {
+    let mut result: Option<DTRange> = None;
+    let mut v = range.start;
+    while v < range.end {
+        let (KVPair(_, mapped), offset) = map.find_with_offset(v)?;
+        let len = ((mapped.len() - offset) as LV).min(range.end - v);
+        let start = mapped.start + offset as LV;
+        match result.as_mut() {
+            None => { result = Some((start..start + len).into()); }
+            Some(r) if r.end == start => { r.end += len; }
+            Some(_) => { return None; }
+        }
+        v += len;
+    }
+    result
+}
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone, TryFromPrimitive)]
 #[repr(u32)]
 enum DataType {
@@ -73,9 +155,41 @@ enum DataType {
     PlainText = 4,
 }
 
+/// The compression algorithm used to compress content in encoded files.
 #[derive(Debug, PartialEq, Eq, Copy, Clone, TryFromPrimitive)]
 #[repr(u32)]
-enum CompressionFormat {
-    // Just for future proofing, ya know?
+#[non_exhaustive]
+pub enum CompressionFormat {
+    /// LZ4 compression. Very fast. Needs the `lz4` feature (enabled by default).
     LZ4 = 1,
+    /// Zstandard compression. Slower than LZ4 but compresses better. Needs the `zstd` feature.
+    Zstd = 2,
+}
+
+impl CompressionFormat {
+    /// Returns true if support for this format is compiled in.
+    pub fn is_supported(self) -> bool {
+        match self {
+            CompressionFormat::LZ4 => cfg!(feature = "lz4"),
+            CompressionFormat::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    fn chunk_type(self) -> ListChunkType {
+        match self {
+            CompressionFormat::LZ4 => ListChunkType::CompressedFieldsLZ4,
+            CompressionFormat::Zstd => ListChunkType::CompressedFieldsZstd,
+        }
+    }
+
+    /// Chunk types 5-9 are reserved for compressed fields. Returns None for other chunk types, and
+    /// Some(None) for compressed chunks in a format we don't know about.
+    fn from_chunk_type(chunk_type: u32) -> Option<Option<Self>> {
+        match chunk_type {
+            5 => Some(Some(CompressionFormat::LZ4)),
+            6 => Some(Some(CompressionFormat::Zstd)),
+            7..=9 => Some(None),
+            _ => None,
+        }
+    }
 }
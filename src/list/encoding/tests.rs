@@ -1,8 +1,12 @@
 use lz4_flex::compress;
 use crate::encoding::parseerror::ParseError;
 use crate::list::{ListCRDT, ListOpLog};
+use crate::list::operation::{ListOpKind, TextOperation};
 use crate::list::encoding::decode_oplog::{dbg_print_chunks_in, DecodeOptions};
 use crate::frontier::local_frontier_eq;
+use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionOwned;
+use crate::list::encoding::encode_tools::{push_leb_u32, push_leb_usize};
+use crate::lv_to_usize;
 use super::*;
 
 fn simple_doc() -> ListCRDT {
@@ -135,6 +139,309 @@ fn with_deleted_content() {
     check_encode_decode_matches(&doc.oplog);
 }
 
+/// Returns a document with long inserts, and a copy of its oplog part way through.
+fn long_doc() -> (ListCRDT, ListOpLog) {
+    let mut doc = ListCRDT::new();
+    doc.get_or_create_agent_id("seph");
+    doc.insert(0, 0, &"the quick brown fox ".repeat(10));
+    let base = doc.oplog.clone();
+    doc.insert(0, 0, &"jumps over the lazy dog ".repeat(10));
+    doc.delete(0, 10..100);
+    (doc, base)
+}
+
+/// The compressed fields chunk (if any) comes directly after the magic bytes and protocol version.
+fn compressed_chunk_type(data: &[u8]) -> Option<u32> {
+    let t = data[MAGIC_BYTES.len() + 1] as u32;
+    CompressionFormat::from_chunk_type(t).map(|_| t)
+}
+
+fn check_patch_round_trips(opts: &EncodeOptions) -> Vec<u8> {
+    let (doc, base) = long_doc();
+    let data = opts.encode_from(&doc.oplog, base.local_frontier_ref());
+    let mut oplog = base.clone();
+    oplog.decode_and_add(&data).unwrap();
+    assert_eq!(oplog, doc.oplog);
+    data
+}
+
+#[test]
+fn per_chunk_compression() {
+    for (branch, patch) in [(false, false), (true, false), (false, true), (true, true)] {
+        let data = check_patch_round_trips(&EncodeOptions::patch()
+            .store_start_branch_content(true)
+            .store_deleted_content(true)
+            .compress_branch_content(branch)
+            .compress_patch_content(patch));
+
+        let expect_compressed = (branch || patch) && cfg!(feature = "lz4");
+        assert_eq!(compressed_chunk_type(&data).is_some(), expect_compressed);
+    }
+}
+
+#[test]
+fn decode_limits() {
+    let (doc, _) = long_doc();
+    let data = doc.oplog.encode(&EncodeOptions::full().store_deleted_content(true));
+    let len = lv_to_usize(doc.oplog.len());
+    let load = |opts: DecodeOptions| ListOpLog::load_from_opts(&data, opts);
+
+    assert_eq!(load(DecodeOptions { max_ops: Some(len - 1), ..Default::default() }),
+               Err(ParseError::LimitExceeded { limit: DecodeLimit::Ops, max: len - 1 }));
+    assert_eq!(load(DecodeOptions { max_agents: Some(0), ..Default::default() }),
+               Err(ParseError::LimitExceeded { limit: DecodeLimit::Agents, max: 0 }));
+    assert_eq!(load(DecodeOptions { max_content_bytes: Some(10), ..Default::default() }),
+               Err(ParseError::LimitExceeded { limit: DecodeLimit::ContentBytes, max: 10 }));
+    let oplog = load(DecodeOptions::default().with_limits(len, 1000, 1)).unwrap();
+    assert_eq!(oplog, doc.oplog);
+
+    // Limits apply to the data being merged, not the whole document.
+    let mut partial = ListOpLog::load_from(&doc.oplog.encode_from(&EncodeOptions::patch(), &[])).unwrap();
+    let mut remote = partial.clone();
+    let seph = remote.get_or_create_agent_id("seph");
+    remote.add_insert(seph, 0, "x");
+    let patch = remote.encode_from(&EncodeOptions::patch(), partial.local_frontier_ref());
+    let before = partial.clone();
+    assert!(partial.decode_and_add_opts(&patch, DecodeOptions::default().with_limits(1, 0, 1)).is_err());
+    assert_eq!(partial, before);
+    partial.decode_and_add_opts(&patch, DecodeOptions::default().with_limits(1, 1, 1)).unwrap();
+}
+
+#[test]
+fn ingest_and_validate() {
+    let mut source = ListOpLog::new();
+    let seph = source.get_or_create_agent_id("seph");
+    let mike = source.get_or_create_agent_id("mike");
+    source.add_insert(seph, 0, "hi");
+    source.add_insert_at(mike, &[], 0, "yo");
+
+    let mut relay = ListOpLog::new();
+    let data = source.encode(&EncodeOptions::patch());
+    let summary = relay.ingest_and_validate(&data).unwrap();
+    assert_eq!(summary.new_ops, (0..4).into());
+    assert_eq!(summary.agents, ["mike", "seph"]);
+    assert_eq!(summary.version.as_ref(), source.local_frontier_ref());
+    assert_eq!(relay, source);
+
+    // Ingesting the same data again adds nothing.
+    let summary = relay.ingest_and_validate(&data).unwrap();
+    assert!(summary.new_ops.is_empty());
+    assert!(summary.agents.is_empty());
+
+    // Data must have a checksum. The CRC chunk is the last 6 bytes.
+    let mut other = ListOpLog::new();
+    let no_crc = &data[..data.len() - 6];
+    assert_eq!(other.ingest_and_validate(no_crc), Err(ParseError::MissingChunk(ListChunkType::Crc as u32)));
+    assert_eq!(other, ListOpLog::new());
+    other.decode_and_add(no_crc).unwrap();
+
+    // Sequence numbers can't have gaps.
+    let mut gappy = source.clone();
+    let v = gappy.local_frontier();
+    let next_seq = gappy.cg.agent_assignment.client_data[seph as usize].get_next_seq();
+    gappy.add_operations_remote(seph, v.as_ref(), 10, &[TextOperation::new_insert(0, "!")]);
+    let before = relay.clone();
+    let patch = gappy.encode_from(&EncodeOptions::patch(), relay.local_frontier_ref());
+    assert_eq!(relay.ingest_and_validate(&patch), Err(ParseError::SeqGap));
+    assert_eq!(relay, before);
+    relay.decode_and_add(&patch).unwrap();
+
+    // Filling the gap later is fine.
+    let mut filled = gappy.clone();
+    filled.add_operations_remote(seph, v.as_ref(), next_seq, &[TextOperation::new_insert(0, &"x".repeat(10 - lv_to_usize(next_seq)))]);
+    let patch = filled.encode_from(&EncodeOptions::patch(), relay.local_frontier_ref());
+    relay.ingest_and_validate(&patch).unwrap();
+}
+
+#[test]
+fn check_positions() {
+    let mut source = ListOpLog::new();
+    let seph = source.get_or_create_agent_id("seph");
+    source.add_insert(seph, 0, "hello");
+    source.add_delete_without_content(seph, 1..3);
+    let base = source.local_frontier();
+    let mut dest = source.clone();
+
+    let opts = DecodeOptions { check_positions: true, ..Default::default() };
+    ListOpLog::load_from_opts(&source.encode(&EncodeOptions::full()), opts.clone()).unwrap();
+
+    // Concurrent operations are checked against the document at their own parents.
+    let mut valid = source.clone();
+    let mike = valid.get_or_create_agent_id("mike");
+    valid.add_operations_at(mike, &[4], &[TextOperation::new_delete(0..5)]);
+    valid.add_insert(seph, 0, "!");
+    let patch = valid.encode_from(&EncodeOptions::patch(), base.as_ref());
+    dest.clone().decode_and_add_opts(&patch, opts.clone()).unwrap();
+
+    // The document only has 3 characters at this version.
+    let mut invalid = source.clone();
+    let mike = invalid.get_or_create_agent_id("mike");
+    invalid.add_insert_at(mike, base.as_ref(), 1, "ok");
+    invalid.add_delete_without_content(mike, 4..6);
+    let patch = invalid.encode_from(&EncodeOptions::patch(), base.as_ref());
+    let err = dest.decode_and_add_opts(&patch, opts).unwrap_err();
+    assert_eq!(err, ParseError::InvalidPosition { offset: 2 });
+    assert_eq!(dest.len(), source.len());
+    // The rejected agent isn't kept.
+    assert_eq!(dest.get_agent_id("mike"), None);
+
+    // Without the check the data is accepted.
+    dest.decode_and_add(&patch).unwrap();
+}
+
+#[test]
+fn unknown_compression_format() {
+    let (doc, _) = long_doc();
+    let mut data = doc.oplog.encode(&EncodeOptions::full().compress_content(true));
+    if compressed_chunk_type(&data).is_none() { return; } // Compression isn't enabled.
+
+    // Pretend the content was compressed using some compression format from the future.
+    data[MAGIC_BYTES.len() + 1] = 9;
+    let opts = DecodeOptions { ignore_crc: true, ..Default::default() };
+    assert_eq!(ListOpLog::load_from_opts(&data, opts.clone()), Err(ParseError::UnknownCompressionFormat));
+
+    // The operations can still be loaded, without their content.
+    let oplog = ListOpLog::load_from_opts(&data, DecodeOptions {
+        allow_missing_content: true,
+        ..opts
+    }).unwrap();
+    assert_eq!(oplog.local_frontier(), doc.oplog.local_frontier());
+    assert_eq!(oplog.operations.num_entries(), doc.oplog.operations.num_entries());
+    assert!(oplog.operations.iter().all(|op| op.1.content_pos.is_none()));
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_round_trip() {
+    let data = check_patch_round_trips(&EncodeOptions::patch()
+        .store_start_branch_content(true)
+        .store_deleted_content(true)
+        .compression_format(CompressionFormat::Zstd));
+    assert_eq!(compressed_chunk_type(&data), Some(ListChunkType::CompressedFieldsZstd as u32));
+
+    let (doc, _) = long_doc();
+    let data = doc.oplog.encode(&EncodeOptions::full()
+        .store_deleted_content(true)
+        .compression_format(CompressionFormat::Zstd));
+    assert_eq!(ListOpLog::load_from(&data).unwrap(), doc.oplog);
+}
+
+#[cfg(feature = "lv_u64")]
+#[test]
+fn large_seqs_round_trip() {
+    use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
+
+    // Sequence numbers past u32::MAX work even on 32 bit platforms.
+    let seq = u32::MAX as LV + 10;
+    let mut oplog = ListOpLog::new();
+    let seph = oplog.get_or_create_agent_id("seph");
+    let v = oplog.add_operations_remote(seph, &[], seq, &[TextOperation::new_insert(0, "hi")]);
+    let v2 = oplog.add_insert_at(seph, &[v.last()], 2, "!");
+    assert_eq!(oplog.cg.agent_assignment.local_to_remote_version(v2), RemoteVersion("seph", seq + 2));
+
+    let data = oplog.encode(&EncodeOptions::full());
+    let loaded = ListOpLog::load_from(&data).unwrap();
+    assert_eq!(loaded, oplog);
+    assert_eq!(loaded.checkout_tip().content(), "hi!");
+
+    let mut other = ListOpLog::new();
+    let mike = other.get_or_create_agent_id("mike");
+    other.add_insert(mike, 0, "yo ");
+    other.decode_and_add(&data).unwrap();
+    assert_eq!(other.cg.agent_assignment.remote_to_local_version(RemoteVersion("seph", seq + 2)), 5);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_length_mismatch() {
+    use crate::list::encoding::decode_oplog::decompress_chunk;
+    use crate::list::encoding::decode_tools::BufReader;
+
+    let compressed = zstd::bulk::compress(b"hello there", 3).unwrap();
+    for len in [5, 11, 20] {
+        let mut chunk = vec![];
+        push_leb_usize(&mut chunk, len);
+        chunk.extend_from_slice(&compressed);
+        let result = decompress_chunk(CompressionFormat::Zstd, BufReader(&chunk), None);
+        if len == 11 {
+            assert_eq!(result.unwrap(), b"hello there");
+        } else {
+            // The stored length doesn't match, so the data is rejected rather than truncated.
+            assert_eq!(result, Err(ParseError::ZstdDecompressionError));
+        }
+    }
+}
+
+#[test]
+fn load_from_shared_borrows_content() {
+    let mut doc = ListCRDT::new();
+    doc.get_or_create_agent_id("seph");
+    doc.insert(0, 0, "hello there");
+    doc.delete(0, 1..3);
+    doc.insert(0, 5, " everyone");
+
+    let data = doc.oplog.encode(&EncodeOptions::full()
+        .store_deleted_content(true)
+        .compress_content(false));
+    let shared: SharedBytes = std::sync::Arc::new(data);
+
+    let oplog = ListOpLog::load_from_shared(shared.clone()).unwrap();
+    assert_eq!(oplog, doc.oplog);
+    // The content is borrowed from the shared buffer.
+    assert_eq!(oplog.mem_usage_breakdown().content, 0);
+    assert_eq!(oplog.checkout_tip().content(), doc.branch.content());
+
+    // Merging into an existing oplog works too, and new local edits are stored as normal.
+    let mut oplog2 = ListOpLog::new();
+    oplog2.decode_and_add_shared(&shared).unwrap();
+    let seph = oplog2.get_or_create_agent_id("seph");
+    oplog2.add_insert(seph, 0, "oh ");
+    assert!(oplog2.mem_usage_breakdown().content > 0);
+    assert_eq!(oplog2.checkout_tip().content().to_string(), format!("oh {}", doc.branch.content()));
+}
+
+#[test]
+fn lazy_content() {
+    let mut doc = ListCRDT::new();
+    doc.get_or_create_agent_id("seph");
+    doc.insert(0, 0, "hello there 💃");
+    doc.delete(0, 1..3);
+    doc.insert(0, 5, " everyone");
+    let data = doc.oplog.encode(&EncodeOptions::full()
+        .store_deleted_content(true)
+        .compress_content(false));
+
+    let opts = DecodeOptions { lazy_content: true, ..Default::default() };
+    let oplog = ListOpLog::load_from_shared_opts(std::sync::Arc::new(data), opts).unwrap();
+    assert_eq!(oplog.checkout_tip().content(), doc.branch.content());
+    assert_eq!(oplog, doc.oplog);
+    let mut copy = oplog.clone();
+    copy.add_insert(0, 0, "🐱");
+    assert_eq!(copy.checkout_tip().content().to_string(), format!("🐱{}", doc.branch.content()));
+    check_encode_decode_matches(&copy);
+}
+
+#[test]
+fn lazy_content_is_checked_when_loaded() {
+    let mut doc = ListCRDT::new();
+    doc.get_or_create_agent_id("seph");
+    doc.insert(0, 0, "hello there");
+    let data = doc.oplog.encode(&EncodeOptions::full().compress_content(false));
+    let pos = data.windows(5).position(|w| w == b"hello").unwrap();
+    let opts = DecodeOptions { lazy_content: true, ignore_crc: true, ..Default::default() };
+
+    let mut bad_utf8 = data.clone();
+    bad_utf8[pos] = 0xff;
+    let result = ListOpLog::load_from_shared_opts(std::sync::Arc::new(bad_utf8), opts.clone());
+    assert_eq!(result.unwrap_err(), ParseError::InvalidUTF8);
+
+    // "he" -> "é" is valid UTF-8, but one character short.
+    let mut bad_len = data;
+    bad_len[pos..pos + 2].copy_from_slice("é".as_bytes());
+    let result = ListOpLog::load_from_shared_opts(std::sync::Arc::new(bad_len), opts);
+    assert_eq!(result.unwrap_err(), ParseError::InvalidLength);
+}
+
 #[test]
 fn encode_reordered() {
     let mut oplog = ListOpLog::new();
@@ -198,6 +505,20 @@ fn check_unroll_works(dest: &ListOpLog, src: &ListOpLog) {
         let result = actual_output.decode_and_add_opts(&corrupted, DecodeOptions {
             ignore_crc: false,
             verbose: true,
+            allow_missing_content: false,
+            cipher: None,
+            verify_signatures: None,
+            require_doc_id: false,
+            max_ops: None,
+            max_content_bytes: None,
+            max_agents: None,
+            require_crc: false,
+            require_contiguous_seqs: false,
+            check_positions: false,
+            trust_transformed_positions: false,
+            #[cfg(feature = "dag_hash")]
+            verify_version_hash: false,
+            lazy_content: false,
         });
 
         if let Err(_err) = result {
@@ -387,4 +708,245 @@ fn compat_simple_doc() {
         let bytes2_compressed_full = &[68, 77, 78, 68, 84, 89, 80, 83, 0, 5, 11, 9, 144, 104, 105, 32, 116, 104, 101, 114, 101, 109, 1, 7, 3, 5, 4, 115, 101, 112, 104, 10, 0, 20, 24, 24, 8, 0, 14, 2, 4, 9, 25, 1, 19, 21, 2, 2, 13, 22, 4, 65, 79, 11, 0, 23, 2, 13, 1, 100, 4, 128, 32, 8, 191];
         assert_eq!(ListOpLog::load_from(bytes2_compressed_full).unwrap(), doc.oplog);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn omit_content() {
+    let mut doc = ListCRDT::new();
+    let seph = doc.get_or_create_agent_id("seph");
+    doc.insert(seph, 0, "hello world");
+    doc.delete(seph, 0..1);
+    doc.insert(seph, 0, "J");
+    let omit = [(3..7).into(), (11..12).into()];
+
+    let data = doc.oplog.encode(&EncodeOptions::full().store_deleted_content(true).omit_content_for(&omit));
+    let loaded = ListOpLog::load_from(&data).unwrap();
+    assert_eq!(loaded.ranges_missing_content(), [(3..7).into()]);
+    assert_eq!(loaded.checkout_tip().content(), "Jel\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}orld");
+    let del = loaded.iter_ops().find(|op| op.kind == ListOpKind::Del).unwrap();
+    assert!(del.content.is_none());
+    assert!(doc.oplog.ranges_missing_content().is_empty());
+
+    // Content the receiver already has isn't affected.
+    let mut other = doc.oplog.clone();
+    other.decode_and_add(&data).unwrap();
+    assert!(other.ranges_missing_content().is_empty());
+    assert_eq!(other, doc.oplog);
+
+    // The placeholders are merged like any other content.
+    let mut loaded = loaded;
+    let mike = loaded.get_or_create_agent_id("mike");
+    loaded.add_insert_at(mike, &[10], 10, "!");
+    doc.oplog.decode_and_add(&loaded.encode_from(&EncodeOptions::patch(), &[11])).unwrap();
+    assert_eq!(doc.oplog.checkout_tip().content(), "Jello worl!d");
+    assert_eq!(loaded.checkout_tip().content(), "Jel\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}orl!d");
+}
+
+#[test]
+fn encode_to_writer() {
+    for seed in 0..10 {
+        let oplog = crate::list::gen_random::gen_oplog(seed, 30, false, true);
+        for opts in [EncodeOptions::default(), EncodeOptions::patch().store_xf(true), EncodeOptions::full().compress_content(false)] {
+            let mut out = vec![];
+            oplog.encode_to(&mut out, &opts).unwrap();
+            assert_eq!(out, oplog.encode(&opts));
+
+            let v = oplog.cg.graph.find_dominators(&[oplog.len() / 2]);
+            let mut out = vec![];
+            oplog.encode_from_to(&mut out, &opts, v.as_ref()).unwrap();
+            assert_eq!(out, oplog.encode_from(&opts, v.as_ref()));
+        }
+    }
+}
+
+#[test]
+fn content_defined_chunks() {
+    let mut doc = ListCRDT::new();
+    let seph = doc.get_or_create_agent_id("seph");
+    let add_lines = |doc: &mut ListCRDT, range: std::ops::Range<usize>| {
+        for i in range {
+            let pos = if i % 10 == 0 { doc.len() / 2 } else { doc.len() };
+            doc.insert(seph, pos, &format!("Line {} of the document, hash {}\n", i, i.wrapping_mul(2654435761) % 100000));
+        }
+    };
+
+    add_lines(&mut doc, 0..3000);
+    let opts = EncodeOptions::default().content_defined_chunks(true);
+    let data1 = doc.oplog.encode(&opts);
+    add_lines(&mut doc, 3000..3200);
+    let data2 = doc.oplog.encode(&opts);
+
+    let chunks1 = read_content_manifest(&data1).unwrap().unwrap();
+    let chunks2 = read_content_manifest(&data2).unwrap().unwrap();
+    assert!(chunks1.len() > 10);
+
+    for (data, chunks) in [(&data1, &chunks1), (&data2, &chunks2)] {
+        for (a, b) in chunks.iter().zip(chunks.iter().skip(1)) {
+            assert_eq!(a.range.end, b.range.start);
+        }
+        for c in chunks.iter() {
+            assert_eq!(c.hash, cdc::CHUNK_HASH.checksum(&data[c.range.clone()]));
+        }
+        ListOpLog::load_from(data).unwrap().dbg_check(true);
+    }
+
+    // Most of the first save should be reused by the second.
+    let shared = chunks1.iter().filter(|c| chunks2.iter().any(|c2| c2.hash == c.hash)).count();
+    assert!(shared * 10 >= chunks1.len() * 8, "Only {shared} of {} chunks shared", chunks1.len());
+
+    assert_eq!(ListOpLog::load_from(&data2).unwrap(), doc.oplog);
+    assert_eq!(read_content_manifest(&doc.oplog.encode(&EncodeOptions::default())).unwrap(), None);
+}
+
+#[test]
+fn start_version_summaries() {
+    let mut server = ListOpLog::new();
+    let seph = server.get_or_create_agent_id("seph");
+    server.add_insert(seph, 0, "hello");
+
+    let mut client = server.clone();
+    let base = client.cg.version.clone();
+    let mike = client.get_or_create_agent_id("mike");
+    client.add_insert(mike, 5, " world");
+    let alice = server.get_or_create_agent_id("alice");
+    server.add_insert(alice, 0, ">> ");
+    server.add_insert(seph, 8, "!");
+
+    let opts = EncodeOptions::patch().store_start_summary(true);
+    let patch = client.encode_from(&opts, base.as_ref());
+    let summary = read_start_summary(&patch).unwrap().unwrap();
+    assert_eq!(summary, client.cg.summarize_version(base.as_ref()));
+    assert_eq!(client.cg.summarize_version(client.cg.version.as_ref()), client.summarize_versions());
+
+    // The summary replaces the frontier.
+    let layout = format::read_layout(&patch).unwrap();
+    assert!(layout.chunks.iter().any(|c| c.id == ListChunkType::VersionSummary as u32));
+    assert!(layout.chunks.iter().all(|c| c.id != ListChunkType::Version as u32));
+    assert_eq!(ListOpLog::describe_patch(&patch).unwrap().base_version, [RemoteVersionOwned("seph".into(), 4)]);
+    assert_eq!(ListOpLog::new().decode_and_add(&patch).unwrap_err(), ParseError::BaseVersionUnknown);
+
+    // The server uses the summary to work out what the client is missing.
+    server.decode_and_add(&patch).unwrap();
+    let (known, unknown) = server.intersect_summary(&summary);
+    assert_eq!(unknown, None);
+    assert!(local_frontier_eq(known.as_ref(), base.as_ref()));
+    client.decode_and_add(&server.encode_from(&EncodeOptions::patch(), known.as_ref())).unwrap();
+    assert_eq!(client.checkout_tip().content(), ">> hello world!");
+
+    // Operations the server doesn't know about are returned.
+    let (known, unknown) = ListOpLog::new().intersect_summary(&summary);
+    assert!(known.is_root());
+    assert_eq!(unknown, Some(summary));
+
+    assert_eq!(read_start_summary(&client.encode_from(&EncodeOptions::patch(), base.as_ref())), Ok(None));
+    assert_eq!(read_start_summary(&client.encode(&opts)), Ok(None));
+}
+
+#[test]
+fn describe_patches() {
+    let mut oplog = ListOpLog::new();
+    let seph = oplog.get_or_create_agent_id("seph");
+    let mike = oplog.get_or_create_agent_id("mike");
+    oplog.add_insert(seph, 0, "hello");
+    let v = oplog.cg.version.clone();
+    oplog.add_insert(mike, 5, " wörld");
+    oplog.add_delete_without_content(seph, 0..1);
+    oplog.add_insert(seph, 0, "H");
+
+    let patch = oplog.encode_from(&EncodeOptions::patch().store_deleted_content(true), v.as_ref());
+    let summary = ListOpLog::describe_patch(&patch).unwrap();
+    assert_eq!(summary.base_version.as_slice(), &[RemoteVersionOwned("seph".into(), 4)]);
+    assert_eq!(summary.heads.as_slice(), &[RemoteVersionOwned("seph".into(), 6)]);
+    assert_eq!(summary.agents, [("mike".into(), 6), ("seph".into(), 2)]);
+    assert_eq!((summary.inserted, summary.deleted, summary.num_ops()), (7, 1, 8));
+    // The content of the delete isn't known.
+    assert_eq!(summary.content_bytes, " wörld".len() + 1);
+
+    let summary = ListOpLog::describe_patch(&oplog.encode(&EncodeOptions::default())).unwrap();
+    assert!(summary.base_version.is_empty());
+    assert_eq!(summary.num_ops(), 13);
+    assert!(ListOpLog::describe_patch(&patch[..patch.len() - 1]).is_err());
+
+    // Concurrent changes leave the patch with multiple heads.
+    oplog.set_doc_id(Some("doc"));
+    oplog.add_insert_at(mike, v.as_ref(), 0, "a");
+    let summary = ListOpLog::describe_patch(&oplog.encode_from(&EncodeOptions::patch(), v.as_ref())).unwrap();
+    assert_eq!(summary.doc_id.as_deref(), Some("doc"));
+    assert_eq!(summary.heads.as_slice(), &[RemoteVersionOwned("seph".into(), 6), RemoteVersionOwned("mike".into(), 6)]);
+    assert_eq!(summary.num_ops(), 9);
+}
+
+#[test]
+fn chunk_crcs() {
+    let doc = simple_doc();
+    let data = doc.oplog.encode(&EncodeOptions::default().chunk_crcs(true));
+    assert!(data.len() > doc.oplog.encode(&EncodeOptions::default()).len());
+    let oplog = ListOpLog::load_from(&data).unwrap();
+    assert!(oplog.eq_ignoring_agent_order(&doc.oplog));
+
+    // Corrupting a chunk is caught by the chunk's CRC, even when the file's CRC is ignored.
+    let opts = DecodeOptions { ignore_crc: true, ..Default::default() };
+    let mut corrupt = data.clone();
+    corrupt[data.len() / 2] ^= 0xff;
+    assert_eq!(ListOpLog::load_from_opts(&corrupt, opts).unwrap_err(), ParseError::ChecksumFailed);
+}
+
+#[test]
+fn unknown_chunks_are_skipped() {
+    let doc = simple_doc();
+    let data = doc.oplog.encode(&EncodeOptions::default());
+
+    // Insert a chunk of an unknown type just before the CRC, and recompute the CRC.
+    let with_chunk = |chunk_type: u32| -> Vec<u8> {
+        let crc_pos = data.len() - 6;
+        assert_eq!(data[crc_pos], ListChunkType::Crc as u8);
+        let mut result = data[..crc_pos].to_vec();
+        push_leb_u32(&mut result, chunk_type);
+        push_leb_usize(&mut result, 3);
+        result.extend_from_slice(b"abc");
+        let crc = crate::encoding::tools::calc_checksum(&result);
+        push_leb_u32(&mut result, ListChunkType::Crc as u32);
+        push_leb_usize(&mut result, 4);
+        result.extend_from_slice(&crc.to_le_bytes());
+        result
+    };
+
+    let oplog = ListOpLog::load_from(&with_chunk(60)).unwrap();
+    assert!(oplog.eq_ignoring_agent_order(&doc.oplog));
+    assert_eq!(ListOpLog::load_from(&with_chunk(60 | 0x80)).unwrap_err(), ParseError::UnknownChunk);
+}
+
+#[test]
+fn insert_tie_break_is_stored() {
+    use crate::list::TieBreak;
+    let reverse = TieBreak::Custom { name: "reverse", cmp: |a, b| b.cmp(a) };
+
+    let mut oplog = ListOpLog::new();
+    oplog.set_insert_tie_break(reverse);
+    let seph = oplog.get_or_create_agent_id("seph");
+    oplog.add_insert(seph, 0, "hi");
+    let data = oplog.encode(&EncodeOptions::default());
+
+    // The file can only be loaded into oplogs which use the same policy.
+    assert_eq!(ListOpLog::load_from(&data).unwrap_err(), ParseError::TieBreakMismatch);
+    let mut other = ListOpLog::new();
+    other.set_insert_tie_break(TieBreak::Custom { name: "other", cmp: |a, b| b.cmp(a) });
+    assert_eq!(other.decode_and_add(&data).unwrap_err(), ParseError::TieBreakMismatch);
+
+    let mut copy = ListOpLog::new();
+    copy.set_insert_tie_break(reverse);
+    copy.decode_and_add(&data).unwrap();
+    assert_eq!(copy, oplog);
+
+    // And files without a custom policy can't be loaded into an oplog with one.
+    let plain = simple_doc().oplog.encode(&EncodeOptions::default());
+    assert_eq!(copy.decode_and_add(&plain).unwrap_err(), ParseError::TieBreakMismatch);
+}
+
+#[test]
+#[should_panic(expected = "need protocol version 1")]
+fn insert_tie_break_needs_v1() {
+    let mut oplog = ListOpLog::new();
+    oplog.set_insert_tie_break(crate::list::TieBreak::Custom { name: "reverse", cmp: |a, b| b.cmp(a) });
+    oplog.encode(&EncodeOptions::default().protocol_version(0));
+}
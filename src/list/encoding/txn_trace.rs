@@ -31,7 +31,7 @@ use smallvec::{SmallVec, smallvec};
 use rle::{HasLength, SplitableSpan};
 use crate::causalgraph::graph::Graph;
 use crate::dtrange::DTRange;
-use crate::{Frontier, LV};
+use crate::{Frontier, lv_to_usize, LV};
 
 #[derive(Debug)]
 // struct VisitEntry<'a> {
@@ -135,7 +135,7 @@ impl<'a> SpanningTreeWalker<'a> {
                 let txn = &graph.entries[i];
                 debug_assert!(span_remaining.start >= txn.span.start && span_remaining.start < txn.span.end);
 
-                let offset = LV::min(span_remaining.len(), txn.span.end - span_remaining.start);
+                let offset = usize::min(span_remaining.len(), lv_to_usize(txn.span.end - span_remaining.start));
                 let span = span_remaining.truncate_keeping_right(offset);
 
                 // dbg!(span_remaining.start);
@@ -557,7 +557,7 @@ mod test {
         let mut clears = 0;
         let mut ff_len = 0;
         let mut apply_len = 0;
-        for a in plan.0.iter() {
+        for a in plan.actions.iter() {
             match a {
                 M1PlanAction::Retreat(span) | M1PlanAction::Advance(span) => {
                     cost_estimate += o.estimate_cost(*span);
@@ -574,7 +574,7 @@ mod test {
                 M1PlanAction::BeginOutput => {}
             }
         }
-        println!("plan length {} (vs graph len {})", plan.0.len(), cg.graph.entries.0.len());
+        println!("plan length {} (vs graph len {})", plan.actions.len(), cg.graph.entries.0.len());
         println!("New cost estimate {cost_estimate}. Clears: {clears}");
         println!("ff_len {ff_len} / apply_len {apply_len}");
         plan.dbg_print();
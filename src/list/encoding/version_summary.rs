@@ -0,0 +1,161 @@
+//! Version summaries in the start branch.
+//!
+//! A patch's start version is usually stored as a frontier - a handful of (agent, seq) pairs.
+//! That's compact, but the reader can only make sense of it if it already has those operations. A
+//! server deciding which changes to send back to a peer often doesn't.
+//!
+//! With [`EncodeOptions::store_start_summary`](super::EncodeOptions::store_start_summary), the
+//! encoder stores a [`VersionSummary`] of the start version in the StartBranch chunk instead of
+//! the frontier. This names the run-length encoded ranges of sequence numbers the sender has from
+//! each agent. It can be read with [`read_start_summary`], and compared against an oplog with
+//! [`ListOpLog::intersect_summary`](crate::list::ListOpLog::intersect_summary).
+
+use rle::HasLength;
+use smallvec::SmallVec;
+use crate::causalgraph::summary::{VersionSummary, VSEntry};
+use crate::encoding::parseerror::ParseError;
+use crate::list::encoding::{ListChunkType, format};
+use crate::list::encoding::decode_tools::BufReader;
+use crate::list::encoding::encode_tools::{push_leb_lv, push_leb_str, push_leb_usize};
+use crate::list::{is_squash_agent, ListOpLog};
+use crate::{Frontier, LV};
+
+/// Entries are stored as the agent's name, the number of ranges, then (gap since the end of the
+/// previous range, length) pairs.
+pub(super) fn write_summary(dest: &mut Vec<u8>, summary: &VersionSummary) {
+    push_leb_usize(dest, summary.entries().len());
+    for entry in summary.entries() {
+        push_leb_str(dest, &entry.name);
+        push_leb_usize(dest, entry.seq_ranges.len());
+        let mut last = 0;
+        for range in entry.seq_ranges.iter() {
+            push_leb_lv(dest, range.start - last);
+            push_leb_usize(dest, range.len());
+            last = range.end;
+        }
+    }
+}
+
+pub(super) fn read_summary(mut reader: BufReader) -> Result<VersionSummary, ParseError> {
+    let num_entries = reader.next_usize()?;
+    // Each entry takes at least 2 bytes.
+    if num_entries > reader.0.len() { return Err(ParseError::InvalidLength); }
+
+    let mut entries = Vec::with_capacity(num_entries);
+    for _ in 0..num_entries {
+        let name = reader.next_str()?;
+        let num_ranges = reader.next_usize()?;
+        if num_ranges == 0 || num_ranges > reader.0.len() { return Err(ParseError::InvalidLength); }
+
+        let mut seq_ranges = SmallVec::with_capacity(num_ranges);
+        let mut last: LV = 0;
+        for _ in 0..num_ranges {
+            let start = last.checked_add(reader.next_lv()?).ok_or(ParseError::ValueTooLarge)?;
+            let len = reader.next_lv()?;
+            // Adjacent ranges would have been merged.
+            if len == 0 || (start == last && !seq_ranges.is_empty()) {
+                return Err(ParseError::GenericInvalidData);
+            }
+            last = start.checked_add(len).ok_or(ParseError::ValueTooLarge)?;
+            seq_ranges.push((start..last).into());
+        }
+        entries.push(VSEntry { name: name.into(), seq_ranges });
+    }
+    reader.expect_empty()?;
+    Ok(entries.into())
+}
+
+/// Find the start version named by a summary. Like frontiers, the summary must only name
+/// operations we know about - except for operations in a snapshot, which are assumed to be the
+/// `base_hint` version.
+pub(super) fn summary_to_version(oplog: &ListOpLog, summary: &VersionSummary, base_hint: Option<&[LV]>) -> Result<Frontier, ParseError> {
+    let (version, unknown) = oplog.cg.intersect_with_summary(summary, &[]);
+    let Some(unknown) = unknown else { return Ok(version); };
+
+    let mut used_hint = false;
+    for VSEntry { name, seq_ranges } in unknown.entries() {
+        match base_hint {
+            Some(_) if is_squash_agent(name) => used_hint = true,
+            _ => return Err(if oplog.is_pruned(name, seq_ranges[0].start) {
+                ParseError::HistoryPruned
+            } else {
+                ParseError::BaseVersionUnknown
+            }),
+        }
+    }
+    Ok(match base_hint {
+        Some(hint) if used_hint => oplog.cg.graph.find_dominators_2(version.as_ref(), hint),
+        _ => version,
+    })
+}
+
+/// Read the summary of a file's start version, if the file has one. Files only have a summary if
+/// they were written with
+/// [`EncodeOptions::store_start_summary`](crate::list::encoding::EncodeOptions::store_start_summary).
+/// The summary names the ranges of sequence numbers the sender had from each agent, so it can be
+/// compared against an oplog (with
+/// [`ListOpLog::intersect_summary`](crate::list::ListOpLog::intersect_summary)) even if the
+/// reader doesn't have those operations.
+///
+/// This doesn't decode the rest of the file. Returns [`ParseError::CipherNeeded`] if the start
+/// branch is encrypted.
+pub fn read_start_summary(data: &[u8]) -> Result<Option<VersionSummary>, ParseError> {
+    let mut reader = BufReader(data);
+    reader.read_magic()?;
+    if !format::is_supported_version(reader.next_usize()?) {
+        return Err(ParseError::UnsupportedProtocolVersion);
+    }
+
+    let mut reader = reader.chunks();
+    while !reader.is_empty() {
+        let (chunk_type, mut chunk) = reader.next_chunk_untyped()?;
+        if chunk_type == ListChunkType::StartBranch as u32 {
+            return chunk.chunks().read_chunk_if_eq(ListChunkType::VersionSummary)?
+                .map(read_summary)
+                .transpose();
+        } else if chunk_type == ListChunkType::Encrypted as u32
+            && chunk.next_u32()? == ListChunkType::StartBranch as u32 {
+            return Err(ParseError::CipherNeeded);
+        }
+    }
+    Err(ParseError::MissingChunk(ListChunkType::StartBranch as u32))
+}
+
+#[cfg(test)]
+mod test {
+    use smallvec::smallvec;
+    use crate::causalgraph::summary::{VersionSummary, VSEntry};
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::encoding::decode_tools::BufReader;
+    use crate::list::{ListOpLog, SQUASH_AGENT};
+    use super::{read_summary, summary_to_version, write_summary};
+
+    fn with_entry(summary: &VersionSummary, name: &str, len: u32) -> VersionSummary {
+        let mut entries = summary.entries().to_vec();
+        entries.push(VSEntry { name: name.into(), seq_ranges: smallvec![(0..len as _).into()] });
+        entries.into()
+    }
+
+    #[test]
+    fn summary_with_unknown_agents() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi");
+        let summary = oplog.cg.agent_assignment.summarize_versions();
+
+        let mut bytes = vec![];
+        write_summary(&mut bytes, &summary);
+        assert_eq!(read_summary(BufReader(&bytes)).unwrap(), summary);
+        assert_eq!(summary_to_version(&oplog, &summary, None).unwrap(), oplog.local_frontier());
+
+        // Operations from agents we've never heard of can't be found.
+        let unknown = with_entry(&summary, "mike", 3);
+        assert_eq!(summary_to_version(&oplog, &unknown, None), Err(ParseError::BaseVersionUnknown));
+        assert_eq!(summary_to_version(&oplog, &unknown, Some(&[])), Err(ParseError::BaseVersionUnknown));
+
+        // Unless they're in a snapshot, and we were told where the snapshot is.
+        let squashed = with_entry(&summary, &format!("{SQUASH_AGENT}abc"), 10);
+        assert_eq!(summary_to_version(&oplog, &squashed, None), Err(ParseError::BaseVersionUnknown));
+        assert_eq!(summary_to_version(&oplog, &squashed, Some(&[0])).unwrap(), oplog.local_frontier());
+    }
+}
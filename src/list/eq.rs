@@ -11,6 +11,7 @@ use rle::{HasLength, SplitableSpan};
 use rle::zip::rle_zip3;
 use crate::{AgentId, Frontier, LV};
 use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
 use crate::frontier::sort_frontier;
 use crate::causalgraph::graph::GraphEntrySimple;
 use crate::rle::KVPair;
@@ -21,6 +22,27 @@ const VERBOSE: bool = true;
 impl PartialEq<Self> for ListOpLog {
     fn eq(&self, other: &Self) -> bool {
         if self.doc_id != other.doc_id { return false; }
+        self.history_eq(other, VERBOSE, true)
+    }
+}
+
+impl Eq for ListOpLog {}
+
+impl ListOpLog {
+    /// Check if two oplogs contain the same operations, with the same history. This is designed
+    /// for tests and health checks which need to make sure replicas have converged.
+    ///
+    /// Unlike comparing encoded files, this doesn't depend on the order in which each replica
+    /// received the operations, or on the local IDs assigned to each agent. Unlike `==`, deleted
+    /// content is ignored (since its usually not sent between peers), and so is the document ID.
+    ///
+    /// This is O(n) with the number of operations.
+    pub fn eq_ignoring_agent_order(&self, other: &Self) -> bool {
+        self.history_eq(other, false, false)
+    }
+
+    fn history_eq(&self, other: &Self, verbose: bool, compare_deleted_content: bool) -> bool {
+        if self.len() != other.len() { return false; }
 
         // This implementation is based on the equivalent version in the original diamond types
         // implementation.
@@ -52,7 +74,7 @@ impl PartialEq<Self> for ListOpLog {
                     AgentId::MAX // Just using this as a placeholder. Could use None but its awkward.
                 } else {
                     // Agent missing.
-                    if VERBOSE {
+                    if verbose {
                         println!("Oplog does not match because agent ID is missing");
                     }
                     return false;
@@ -73,12 +95,12 @@ impl PartialEq<Self> for ListOpLog {
             let other_time = map_lv_to_other(*t);
             if let Some(other_time) = other_time {
                 if !other.cg.version.0.contains(&other_time) {
-                    if VERBOSE { println!("Frontier is not contained by other frontier"); }
+                    if verbose { println!("Frontier is not contained by other frontier"); }
                     return false;
                 }
             } else {
                 // The time is unknown.
-                if VERBOSE { println!("Frontier is not known in other doc"); }
+                if verbose { println!("Frontier is not known in other doc"); }
                 return false;
             }
         }
@@ -124,11 +146,11 @@ impl PartialEq<Self> for ListOpLog {
                 if offset > 0 { other_id.truncate_keeping_right(offset); }
 
                 if agent_a_to_b[crdt_id.agent as usize] != other_id.agent {
-                    if VERBOSE { println!("Ops do not match because agents differ"); }
+                    if verbose { println!("Ops do not match because agents differ"); }
                     return false;
                 }
                 if crdt_id.seq_range.start != other_id.seq_range.start {
-                    if VERBOSE { println!("Ops do not match because CRDT sequence numbers differ"); }
+                    if verbose { println!("Ops do not match because CRDT sequence numbers differ"); }
                     return false;
                 }
 
@@ -143,8 +165,13 @@ impl PartialEq<Self> for ListOpLog {
                     Some(op.truncate(len_here))
                 } else { None };
 
+                if !compare_deleted_content && op.kind == ListOpKind::Del {
+                    op.content = None;
+                    other_op.content = None;
+                }
+
                 if op != other_op {
-                    if VERBOSE { println!("Ops do not match at {}:\n{:?}\n{:?}", txn.span.start, op, other_op); }
+                    if verbose { println!("Ops do not match at {}:\n{:?}\n{:?}", txn.span.start, op, other_op); }
                     return false;
                 }
 
@@ -163,7 +190,7 @@ impl PartialEq<Self> for ListOpLog {
                 };
 
                 let mut mapped_txn = GraphEntrySimple {
-                    span: (mapped_start..mapped_start + len_here).into(),
+                    span: (mapped_start..mapped_start + len_here as LV).into(),
                     // .unwrap() should be safe here because we've already walked past this item's
                     // parents.
                     parents: Frontier(txn.parents.iter().map(|t| map_lv_to_other(*t).unwrap()).collect())
@@ -172,14 +199,14 @@ impl PartialEq<Self> for ListOpLog {
                 sort_frontier(&mut mapped_txn.parents.0);
 
                 if other_txn != mapped_txn {
-                    if VERBOSE { println!("Txns do not match {:?} (was {:?}) != {:?}", mapped_txn, txn, other_txn); }
+                    if verbose { println!("Txns do not match {:?} (was {:?}) != {:?}", mapped_txn, txn, other_txn); }
                     return false;
                 }
 
                 if let Some(rem) = remainder {
                     op = rem;
                 } else { break; }
-                crdt_id.seq_range.start += len_here;
+                crdt_id.seq_range.start += len_here as LV;
                 txn.truncate_keeping_right(len_here);
             }
         }
@@ -188,12 +215,12 @@ impl PartialEq<Self> for ListOpLog {
     }
 }
 
-impl Eq for ListOpLog {}
-
 
 #[cfg(test)]
 mod test {
     use crate::list::ListOpLog;
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::gen_random::gen_oplog;
 
     fn is_eq(a: &ListOpLog, b: &ListOpLog) -> bool {
         let a_eq_b = a.eq(b);
@@ -235,4 +262,23 @@ mod test {
         assert!(is_eq(&a, &c));
         assert!(is_eq(&b, &c));
     }
+
+    #[test]
+    fn replicas_converge() {
+        for seed in 0..10 {
+            let a = gen_oplog(seed, 30, false, true);
+            // Deleted content isn't stored by default.
+            let b = ListOpLog::load_from(&a.encode(&EncodeOptions::default().sort_operations(true))).unwrap();
+            assert!(a.eq_ignoring_agent_order(&b));
+            assert!(b.eq_ignoring_agent_order(&a));
+            assert_eq!(a.checkout_tip().content_hash(), b.checkout_tip().content_hash());
+
+            let mut c = b.clone();
+            let agent = c.get_or_create_agent_id("extra");
+            c.add_insert(agent, 0, "x");
+            assert!(!a.eq_ignoring_agent_order(&c));
+            assert!(!c.eq_ignoring_agent_order(&a));
+            assert_ne!(a.checkout_tip().content_hash(), c.checkout_tip().content_hash());
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,156 @@
+//! Change events for branches.
+//!
+//! UI layers need to know exactly what changed in a document whenever remote changes are merged
+//! in, so they can update their view. Once enabled with
+//! [`enable_events`](ListBranch::enable_events), the branch records every change made to its
+//! content. The events are drained with [`take_events`](ListBranch::take_events).
+
+use std::fmt::{Debug, Formatter};
+use std::ops::Range;
+use smartstring::alias::String as SmartString;
+use crate::{AgentId, LV};
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::ListOpKind;
+
+/// A single change to a branch's content.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BranchEvent {
+    /// The range of the document which was inserted or deleted. Positions are in unicode
+    /// characters, and take into account all the events before this one.
+    pub range: Range<usize>,
+    /// The inserted text. This is empty for deletes.
+    pub inserted: SmartString,
+    /// The number of deleted characters. This is 0 for inserts.
+    pub deleted_len: usize,
+    /// The agent which made the change.
+    pub agent: Option<AgentId>,
+    /// The version of the (first item of the) operation which made the change.
+    ///
+    /// This and `agent` are None for changes which don't correspond to an operation - which
+    /// happens when a branch is moved backwards in time, or when IME composition text is updated.
+    pub version: Option<LV>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RawEvent {
+    kind: ListOpKind,
+    range: Range<usize>,
+    inserted: Option<SmartString>,
+    version: Option<LV>,
+}
+
+/// The branch's buffered events, if enabled. Like the rewind cache, this isn't part of the
+/// branch's value, so its ignored when comparing branches.
+#[derive(Clone, Default)]
+pub(crate) struct EventBuffer(pub(crate) Option<Vec<RawEvent>>);
+
+impl PartialEq for EventBuffer {
+    fn eq(&self, _other: &Self) -> bool { true }
+}
+
+impl Eq for EventBuffer {}
+
+impl Debug for EventBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("EventBuffer")
+            .field(&self.0.as_ref().map(|e| e.len()))
+            .finish()
+    }
+}
+
+impl EventBuffer {
+    pub(crate) fn push_insert(&mut self, pos: usize, len: usize, content: &str, version: Option<LV>) {
+        if let Some(events) = self.0.as_mut() {
+            events.push(RawEvent {
+                kind: ListOpKind::Ins,
+                range: pos..pos + len,
+                inserted: Some(content.into()),
+                version,
+            });
+        }
+    }
+
+    pub(crate) fn push_delete(&mut self, range: Range<usize>, version: Option<LV>) {
+        if let Some(events) = self.0.as_mut() {
+            events.push(RawEvent { kind: ListOpKind::Del, range, inserted: None, version });
+        }
+    }
+}
+
+impl ListBranch {
+    /// Start recording every change made to the branch's content, including local edits, merges
+    /// and rewinds.
+    pub fn enable_events(&mut self) {
+        self.events.0.get_or_insert_with(Vec::new);
+    }
+
+    /// Stop recording changes, and discard any events which haven't been taken.
+    pub fn disable_events(&mut self) {
+        self.events.0 = None;
+    }
+
+    /// Take all the events recorded since the last call. The events must be processed in order.
+    ///
+    /// The oplog is used to look up the agent for each change. It must be the oplog which the
+    /// branch has been used with.
+    pub fn take_events(&mut self, oplog: &ListOpLog) -> Vec<BranchEvent> {
+        let Some(events) = self.events.0.as_mut() else { return vec![]; };
+        std::mem::take(events).into_iter().map(|e| {
+            let len = e.range.len();
+            BranchEvent {
+                range: e.range,
+                inserted: e.inserted.unwrap_or_default(),
+                deleted_len: if e.kind == ListOpKind::Del { len } else { 0 },
+                agent: e.version.map(|v| oplog.lv_to_agent_version(v).0),
+                version: e.version,
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListCRDT;
+    use super::BranchEvent;
+
+    #[test]
+    fn events_report_merged_changes() {
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, "hello");
+
+        let mut b = ListCRDT::new();
+        let mike = b.get_or_create_agent_id("mike");
+        b.merge_data_and_ff(&a.oplog.encode(&Default::default())).unwrap();
+        b.branch.enable_events();
+
+        // Local edits are reported.
+        b.insert(mike, 5, "!");
+        // And so are merged changes.
+        a.delete_without_content(seph, 0..1);
+        b.merge_data_and_ff(&a.oplog.encode(&Default::default())).unwrap();
+
+        let events = b.branch.take_events(&b.oplog);
+        let seph_b = b.oplog.get_or_create_agent_id("seph");
+        assert_eq!(events, vec![
+            BranchEvent { range: 5..6, inserted: "!".into(), deleted_len: 0, agent: Some(mike), version: Some(5) },
+            BranchEvent { range: 0..1, inserted: "".into(), deleted_len: 1, agent: Some(seph_b), version: Some(6) },
+        ]);
+        assert!(b.branch.take_events(&b.oplog).is_empty());
+
+        // Rewinding is reported without an agent. Replaying the events gets us from the old
+        // content to the new content.
+        assert_eq!(b.branch.content().to_string(), "ello!");
+        b.branch.rewind_to(&b.oplog, &[4]);
+        let events = b.branch.take_events(&b.oplog);
+        assert!(!events.is_empty());
+        assert!(events.iter().all(|e| e.agent.is_none() && e.version.is_none()));
+
+        let mut s: Vec<char> = "ello!".chars().collect();
+        for e in events {
+            if e.deleted_len > 0 { s.drain(e.range); }
+            else { s.splice(e.range.start..e.range.start, e.inserted.chars()); }
+        }
+        assert_eq!(s.into_iter().collect::<String>(), "hello");
+    }
+}
@@ -0,0 +1,174 @@
+//! This module lets you strip out all the edits made by some set of agents (eg, a vandal) from a
+//! document. The remaining operations are rebased as if the removed operations never happened.
+//!
+//! The approach is pretty simple: we replay the whole document into a tracker to figure out the
+//! final order of every item ever inserted. Then we walk the kept operations in order, tracking
+//! which items would be visible without the removed operations, and use that to figure out where
+//! each kept operation lands.
+
+use rle::{AppendRle, HasLength};
+use crate::{AgentId, DTRange, LV};
+use crate::list::ListOpLog;
+use crate::list::operation::{ListOpKind, TextOperation};
+use crate::listmerge::rewind::RewindTracker;
+use crate::rle::KVPair;
+
+/// A simple fenwick tree used to count visible items before some point in the document.
+struct VisibleCount(Vec<usize>);
+
+impl VisibleCount {
+    fn new(len: usize) -> Self { Self(vec![0; len + 1]) }
+
+    fn add(&mut self, idx: usize, val: isize) {
+        let mut i = idx + 1;
+        while i < self.0.len() {
+            self.0[i] = self.0[i].wrapping_add_signed(val);
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Count of visible items before idx.
+    fn prefix(&self, idx: usize) -> usize {
+        let mut sum = 0;
+        let mut i = idx;
+        while i > 0 {
+            sum += self.0[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+
+    fn is_visible(&self, idx: usize) -> bool {
+        self.prefix(idx + 1) - self.prefix(idx) == 1
+    }
+}
+
+impl ListOpLog {
+    /// Create a new oplog containing only the operations made by agents *not* in `agents`. The
+    /// kept operations are rebased so that it looks like the removed operations never happened:
+    ///
+    /// - Text inserted by a removed agent never appears
+    /// - Deletes made by a removed agent never happen
+    /// - Deletes of text inserted by a removed agent are dropped.
+    ///
+    /// The resulting oplog has a linear history, and it has the same set of agents (with the same
+    /// agent IDs) as this oplog. But it is a new document. The resulting operations have different
+    /// versions from the operations here, so the two oplogs must never be merged together.
+    pub fn without_agents(&self, agents: &[AgentId]) -> ListOpLog {
+        let mut result = ListOpLog::new();
+        for agent in 0..self.num_agents() {
+            result.get_or_create_agent_id(self.get_agent_name(agent));
+        }
+
+        let mut tracker = RewindTracker::new();
+        tracker.move_to(self, self.cg.version.as_ref());
+
+        let order = tracker.item_order();
+        let mut visible = VisibleCount::new(order.len());
+
+        for KVPair(span_start, span) in self.cg.agent_assignment.client_with_lv.iter() {
+            if agents.contains(&span.agent) { continue; }
+
+            let range: DTRange = (*span_start..*span_start + span.len() as LV).into();
+            let mut ops: Vec<TextOperation> = vec![];
+
+            for lv in range.iter() {
+                // This is pretty inefficient, but it keeps the content handling simple.
+                let KVPair(_, op) = self.operations.iter_range_ctx(lv..lv + 1, &self.operation_ctx)
+                    .next().unwrap();
+                let content = op.get_content(&self.operation_ctx);
+
+                match op.kind {
+                    ListOpKind::Ins => {
+                        let o = order.order_of(lv);
+                        let pos = visible.prefix(o);
+                        visible.add(o, 1);
+                        ops.push_rle(match content {
+                            Some(c) => TextOperation::new_insert(pos, c),
+                            None => TextOperation { loc: (pos as LV..(pos + 1) as LV).into(), kind: ListOpKind::Ins, content: None },
+                        });
+                    }
+                    ListOpKind::Del => {
+                        let o = order.order_of(tracker.deleted_item(lv));
+                        if !visible.is_visible(o) {
+                            // Either the item was inserted by a removed agent, or its already
+                            // been deleted.
+                            continue;
+                        }
+                        let pos = visible.prefix(o);
+                        visible.add(o, -1);
+                        ops.push_rle(match content {
+                            Some(c) => TextOperation::new_delete_with_content(pos, c.into()),
+                            None => TextOperation::new_delete(pos..pos + 1),
+                        });
+                    }
+                }
+            }
+
+            if !ops.is_empty() {
+                result.add_operations_local(span.agent, &ops);
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn strip_vandal() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let vandal = oplog.get_or_create_agent_id("vandal");
+
+        oplog.add_insert(seph, 0, "hello");
+        oplog.add_insert(vandal, 2, "XXX"); // heXXXllo
+        oplog.add_delete_without_content(vandal, 0..1); // eXXXllo
+        oplog.add_delete_without_content(seph, 2..3); // eXXllo
+        oplog.add_insert(seph, 6, " world"); // eXXllo world
+        assert_eq!(oplog.checkout_tip().content().to_string(), "eXXllo world");
+
+        let clean = oplog.without_agents(&[vandal]);
+        assert_eq!(clean.checkout_tip().content().to_string(), "hello world");
+        assert_eq!(clean.get_agent_name(vandal), "vandal");
+
+        let only_vandal = oplog.without_agents(&[seph]);
+        assert_eq!(only_vandal.checkout_tip().content().to_string(), "XXX");
+
+        let all = oplog.without_agents(&[]);
+        assert_eq!(all.checkout_tip().content().to_string(), "eXXllo world");
+    }
+
+    #[test]
+    fn without_no_agents_matches_tip() {
+        use rand::prelude::*;
+        use crate::list::ListCRDT;
+        use crate::list::old_fuzzer_tools::old_make_random_change;
+        use crate::list_fuzzer_tools::choose_2;
+
+        for seed in 0..10 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut docs = [ListCRDT::new(), ListCRDT::new(), ListCRDT::new()];
+            for doc in docs.iter_mut() {
+                for a in 0..3 {
+                    doc.get_or_create_agent_id(format!("agent {a}").as_str());
+                }
+            }
+
+            for _ in 0..30 {
+                let idx = rng.gen_range(0..docs.len());
+                old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, false);
+
+                let (_a_idx, a, _b_idx, b) = choose_2(&mut docs, &mut rng);
+                a.oplog.add_missing_operations_from(&b.oplog);
+            }
+
+            let oplog = &docs[0].oplog;
+            assert_eq!(oplog.without_agents(&[]).checkout_tip().content(),
+                       oplog.checkout_tip().content());
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Causally stable versions and garbage collection.
+//!
+//! A version is *causally stable* once every replica of the document has seen it. After that, no
+//! replica can make a new change which is concurrent with anything in that version. So data which
+//! is only needed to merge concurrent changes can be dropped:
+//!
+//! - [`prune_deleted_content`](ListOpLog::prune_deleted_content) drops the content of deletes, but
+//!   keeps every operation.
+//! - [`collect_garbage`](ListOpLog::collect_garbage) drops the history in the stable version,
+//!   along with the tombstones of every item deleted in it. Only the document's content at the
+//!   stable version is kept.
+//!
+//! Replicas report what they've seen using [`VersionSummary`]s (see
+//! [`summarize_versions`](ListOpLog::summarize_versions)). The application decides which replicas
+//! are registered - a replica which is missing from the list won't hold back garbage collection.
+
+use std::mem::take;
+use rle::{HasLength, SplitableSpanCtx};
+use crate::{AgentId, DTRange, Frontier, lv_to_usize, LV};
+use crate::causalgraph::summary::VersionSummary;
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::rle::KVPair;
+
+impl ListOpLog {
+    /// Summarize the versions known to this oplog, to send to other replicas.
+    pub fn summarize_versions(&self) -> VersionSummary {
+        self.cg.agent_assignment.summarize_versions()
+    }
+
+    /// Compare a summary from another replica against this oplog. Returns the version containing
+    /// every operation named in the summary which we know about, and a summary of the operations
+    /// we don't have (if any).
+    ///
+    /// To catch up a peer, send it the patch [`encode_from`](ListOpLog::encode_from) the known
+    /// version.
+    pub fn intersect_summary(&self, summary: &VersionSummary) -> (Frontier, Option<VersionSummary>) {
+        self.cg.intersect_with_summary(summary, &[])
+    }
+
+    /// Find a version which is known to this oplog and to every replica in `summaries`.
+    ///
+    /// The result is always a single point in history (the latest common ancestor), so nothing
+    /// outside it is concurrent with anything inside it. It may be a little older than the true
+    /// intersection of everyone's versions.
+    pub fn causally_stable_version(&self, summaries: &[VersionSummary]) -> Frontier {
+        let mut stable = self.cg.version.clone();
+        for summary in summaries {
+            if stable.is_root() { break; }
+            let (known, _) = self.cg.intersect_with_summary(summary, &[]);
+            stable = self.cg.graph.find_conflicting(stable.as_ref(), known.as_ref(), |_, _| {});
+        }
+        stable
+    }
+
+    /// Remove every agent which hasn't made any operations in this oplog - for example, agents
+    /// whose operations were all removed by [`squash_before`](ListOpLog::squash_before). Their
+    /// stored [agent info](ListOpLog::agent_info) is dropped too. (Unused agents are never written
+    /// to encoded files either way.)
+    ///
+    /// The remaining agents are renumbered, keeping their relative order. This invalidates any
+    /// [`AgentId`]s held by the application - the returned map translates each old ID to its new
+    /// ID, or None if the agent was removed.
+    pub fn gc_agents(&mut self) -> Vec<Option<AgentId>> {
+        let map = self.cg.agent_assignment.remove_unused_agents();
+        let aa = &self.cg.agent_assignment;
+        self.agent_info.retain(|name, _| aa.get_agent_id(name).is_some());
+        map
+    }
+
+    /// Drop the stored content of all deletes in `version`. This content isn't needed to merge
+    /// changes, but its stored (when known) so old versions can be reconstructed exactly and
+    /// deletes can be undone. Once `version` is causally stable (see
+    /// [`causally_stable_version`](ListOpLog::causally_stable_version)), the application may decide
+    /// it doesn't need that any more.
+    ///
+    /// Returns the number of characters of deleted content which were dropped.
+    pub fn prune_deleted_content(&mut self, version: &[LV]) -> usize {
+        // Spans which are not in version, in ascending order.
+        let (_, unstable) = self.cg.graph.diff(version, self.cg.version.as_ref());
+        if unstable.len() == 1 && unstable[0] == (0..self.len()).into() { return 0; }
+
+        self.drop_content(&unstable, |in_unstable, kind| !in_unstable && kind == ListOpKind::Del)
+    }
+
+    /// Rewrite the operations, dropping the content of each operation for which
+    /// `should_drop(in_ranges, kind)` returns true. `ranges` must be sorted by their start.
+    /// Operations are split where they enter or leave a range.
+    ///
+    /// Returns the number of characters of content which were dropped.
+    pub(crate) fn drop_content<F>(&mut self, ranges: &[DTRange], should_drop: F) -> usize
+        where F: Fn(bool, ListOpKind) -> bool
+    {
+        let old_ops = take(&mut self.operations);
+        let old_ctx = take(&mut self.operation_ctx);
+        let mut ranges = ranges.iter().copied().peekable();
+        let mut dropped = 0;
+
+        for KVPair(mut lv, mut op) in old_ops.0.into_iter() {
+            loop {
+                while ranges.peek().is_some_and(|r| r.end <= lv) { ranges.next(); }
+
+                let (in_ranges, boundary) = match ranges.peek() {
+                    Some(r) if r.start <= lv => (true, r.end),
+                    Some(r) => (false, r.start),
+                    None => (false, LV::MAX),
+                };
+                let rest = (lv + op.len() as LV > boundary)
+                    .then(|| op.truncate_ctx(lv_to_usize(boundary - lv), &old_ctx));
+
+                let content = if should_drop(in_ranges, op.kind) {
+                    if op.content_pos.is_some() { dropped += op.len(); }
+                    None
+                } else {
+                    op.get_content(&old_ctx)
+                };
+                self.push_op_internal(lv, op.loc, op.kind, content);
+
+                lv += op.len() as LV;
+                match rest {
+                    Some(rest) => op = rest,
+                    None => break,
+                }
+            }
+        }
+
+        dropped
+    }
+
+    /// Collect the tombstones and history in `version`, which should be causally stable. (See
+    /// [`causally_stable_version`](ListOpLog::causally_stable_version)).
+    ///
+    /// This returns a [shallow clone](crate::list::ListOpLog::shallow_clone) of the oplog, where
+    /// the operations in `version` are replaced with a single insert of the document's content.
+    /// Items deleted in the replaced history are dropped entirely. The last operations in
+    /// `version` (and everything after it) are kept, so the clone can still merge changes from any
+    /// replica which has seen `version` - but changes which depend on the collected history are
+    /// rejected with
+    /// [`ParseError::HistoryPruned`](crate::encoding::parseerror::ParseError::HistoryPruned).
+    ///
+    /// Returns None if some operation outside `version` is concurrent with it.
+    pub fn collect_garbage(&self, version: &[LV]) -> Option<ListOpLog> {
+        // The operations in version must be exactly 0..k, and everything after must depend on
+        // all of them.
+        let k = version.iter().max().map_or(0, |v| v + 1);
+        let (_, rest) = self.cg.graph.diff(version, self.cg.version.as_ref());
+        if rest.iter().any(|r| r.start < k) || self.latest_clean_split(k) != k { return None; }
+
+        // Changes made on top of version name its last operations as their parents, so those are
+        // kept.
+        Some(self.shallow_clone_at(self.latest_clean_split(k.saturating_sub(1))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rle::HasLength;
+    use crate::list::{AgentInfo, ListCRDT, ListOpLog};
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::operation::ListOpKind;
+
+    fn deleted_content(oplog: &ListOpLog) -> Vec<Option<String>> {
+        oplog.iter_ops()
+            .filter(|op| op.kind == ListOpKind::Del)
+            .map(|op| op.content.map(|c| c.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn prune_stable_deletes() {
+        let opts = EncodeOptions::full().store_deleted_content(true);
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, "hello world");
+        a.delete(seph, 0..6);
+
+        // b has seen everything so far.
+        let mut b = ListCRDT::load_from(&a.oplog.encode(&opts)).unwrap();
+        let mike = b.get_or_create_agent_id("mike");
+        let stable_v = a.oplog.local_frontier();
+
+        // Concurrent changes.
+        a.delete(seph, 0..1);
+        b.insert(mike, 5, "!");
+        assert_eq!(a.oplog.causally_stable_version(&[b.oplog.summarize_versions()]), stable_v);
+        assert_eq!(a.oplog.causally_stable_version(&[]), a.oplog.local_frontier());
+
+        b.merge_data_and_ff(&a.oplog.encode(&opts)).unwrap();
+        assert_eq!(b.oplog.causally_stable_version(&[a.oplog.summarize_versions()]), stable_v);
+
+        let before = b.oplog.checkout_tip().content().to_string();
+        assert_eq!(deleted_content(&b.oplog), [Some("hello ".into()), Some("w".into())]);
+        assert_eq!(b.oplog.prune_deleted_content(stable_v.as_ref()), 6);
+        b.oplog.dbg_check(true);
+        assert_eq!(deleted_content(&b.oplog), [None, Some("w".into())]);
+        assert_eq!(b.oplog.checkout_tip().content(), before.as_str());
+        assert_eq!(b.oplog.prune_deleted_content(stable_v.as_ref()), 0);
+
+        // Pruned oplogs can still sync.
+        a.merge_data_and_ff(&b.oplog.encode(&opts)).unwrap();
+        assert_eq!(a.branch.content(), b.branch.content());
+    }
+
+    #[test]
+    fn collect_stable_garbage() {
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, "hello world");
+        a.delete(seph, 0..6);
+        let stable_v = a.oplog.local_frontier();
+
+        let mut b = ListCRDT::load_from(&a.oplog.encode(&EncodeOptions::default())).unwrap();
+        let mike = b.get_or_create_agent_id("mike");
+        a.insert(seph, 0, ">");
+        b.insert(mike, 5, "!");
+        b.merge_data_and_ff(&a.oplog.encode(&EncodeOptions::default())).unwrap();
+
+        // Versions which don't split the history can't be collected.
+        assert!(b.oplog.collect_garbage(&[a.oplog.len() - 1]).is_none());
+
+        let collected = b.oplog.collect_garbage(stable_v.as_ref()).unwrap();
+        collected.dbg_check(true);
+        assert!(collected.is_shallow());
+        assert_eq!(collected.checkout_tip().content(), b.branch.content());
+        // The tombstones for "hello" are gone. Only the last deleted character (at the tip of the
+        // stable version) is kept.
+        assert!(collected.len() < b.oplog.len());
+        assert_eq!(collected.iter_ops().filter(|op| op.kind == ListOpKind::Del).map(|op| op.len()).sum::<usize>(), 1);
+
+        // Changes after the stable version can still be merged.
+        let mut collected = ListCRDT { branch: collected.checkout_tip(), oplog: collected };
+        a.insert(seph, 0, "?");
+        collected.merge_data_and_ff(&a.oplog.encode_from(&EncodeOptions::patch(), stable_v.as_ref())).unwrap();
+        a.merge_data_and_ff(&b.oplog.encode(&EncodeOptions::default())).unwrap();
+        assert_eq!(collected.branch.content(), a.branch.content());
+    }
+
+    #[test]
+    fn gc_unused_agents() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let info = AgentInfo { display_name: Some("Mike".into()), ..Default::default() };
+        oplog.get_or_create_agent_with_info("mike", info);
+        let alice = oplog.get_or_create_agent_id("alice");
+        oplog.add_insert(seph, 0, "hi");
+        oplog.add_insert(alice, 2, "!!");
+        let data = oplog.encode(&EncodeOptions::default());
+
+        assert_eq!(oplog.gc_agents(), [Some(0), None, Some(1)]);
+        oplog.dbg_check(true);
+        assert_eq!(oplog.get_agent_id("mike"), None);
+        assert_eq!(oplog.get_agent_name(1), "alice");
+        assert_eq!(oplog.iter_agent_info().count(), 0);
+        assert_eq!(ListOpLog::load_from(&data).unwrap(), oplog);
+
+        oplog.add_insert(1, 0, ">> ");
+        assert_eq!(oplog.checkout_tip().content(), ">> hi!!");
+        assert_eq!(oplog.gc_agents(), [Some(0), Some(1)]);
+    }
+}
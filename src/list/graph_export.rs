@@ -0,0 +1,248 @@
+//! Exporting a document's history graph, for tools which visualize document history.
+//!
+//! The graph has a node for each run of operations made by one agent. Nodes are labelled with the
+//! agent, the agent's sequence numbers and the local versions of the run. Edges point from each
+//! node to its parents. Graphs can be exported in graphviz's DOT format, or as JSON.
+//!
+//! (The `dot_export` feature is different - it contains tools for debugging the merge algorithm
+//! itself.)
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use rle::HasLength;
+use crate::{DTRange, Frontier, LV};
+use crate::list::ListOpLog;
+use crate::list::operation::{ListOpKind, TextOperation};
+
+/// The output format of [`ListOpLog::export_graph`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GraphFormat {
+    /// Graphviz DOT. Render with `dot -Tsvg`.
+    Dot,
+    /// JSON, in the form `{"nodes": [{"id", "agent", "seq", "span", "parents", "ops"?}]}`. Spans
+    /// are `[start, end)` pairs. Parents are node IDs (an empty list means the root).
+    Json,
+}
+
+/// Options for [`ListOpLog::export_graph`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GraphExportOptions {
+    /// Include a short summary of each node's operations (eg `Ins 4 'hi'`).
+    pub include_ops: bool,
+    /// The maximum number of operations summarized in each node.
+    pub max_ops_per_node: usize,
+    /// Inserted content longer than this is truncated in summaries.
+    pub max_content_len: usize,
+}
+
+impl Default for GraphExportOptions {
+    fn default() -> Self {
+        Self {
+            include_ops: false,
+            max_ops_per_node: 5,
+            max_content_len: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GraphNode<'a> {
+    agent: &'a str,
+    seq: DTRange,
+    span: DTRange,
+    parents: Frontier,
+    ops: Vec<String>,
+}
+
+impl GraphNode<'_> {
+    /// Nodes are named by their last version, since that's what child nodes name as a parent.
+    fn id(&self) -> LV {
+        self.span.last()
+    }
+}
+
+fn escape_json(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn escape_html(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+}
+
+impl ListOpLog {
+    fn summarize_op(&self, op: &TextOperation, opts: &GraphExportOptions) -> String {
+        match op.kind {
+            ListOpKind::Ins => {
+                let content = op.inserted_text();
+                let content = content.as_deref().unwrap_or("?");
+                let mut s: String = content.chars().take(opts.max_content_len).collect();
+                if content.chars().count() > opts.max_content_len { s.push('…'); }
+                format!("Ins {} '{}'", op.start(), s)
+            }
+            ListOpKind::Del => format!("Del {}..{}", op.start(), op.end()),
+        }
+    }
+
+    fn graph_nodes(&self, opts: &GraphExportOptions) -> Vec<GraphNode<'_>> {
+        // Nodes are split so every parent is the last version in some node.
+        let split_after: BTreeSet<LV> = self.iter_history()
+            .flat_map(|e| e.parents.0.into_iter())
+            .collect();
+
+        let mut nodes = vec![];
+        for entry in self.iter_history() {
+            let mut parents = entry.parents;
+            let mut lv = entry.span.start;
+            for span in self.iter_remote_mappings_range(entry.span) {
+                let mut seq = span.1.start;
+                let agent_end = lv + span.len() as LV;
+                while lv < agent_end {
+                    let end = split_after.range(lv..agent_end).next()
+                        .map_or(agent_end, |&p| p + 1);
+                    let len = end - lv;
+                    let span_here: DTRange = (lv..end).into();
+
+                    let ops = if opts.include_ops {
+                        let mut ops = vec![];
+                        for (op, content) in self.iter_range_simple(span_here) {
+                            if ops.len() >= opts.max_ops_per_node {
+                                ops.push("…".to_string());
+                                break;
+                            }
+                            let op: TextOperation = (op.1, content).into();
+                            ops.push(self.summarize_op(&op, opts));
+                        }
+                        ops
+                    } else { vec![] };
+
+                    nodes.push(GraphNode {
+                        agent: span.0,
+                        seq: (seq..seq + len).into(),
+                        span: span_here,
+                        parents: std::mem::replace(&mut parents, Frontier::new_1(end - 1)),
+                        ops,
+                    });
+                    seq += len;
+                    lv = end;
+                }
+            }
+        }
+        nodes
+    }
+
+    /// Export the oplog's history as a graph. See [`GraphFormat`] for the available formats.
+    pub fn export_graph(&self, format: GraphFormat, opts: &GraphExportOptions) -> String {
+        let nodes = self.graph_nodes(opts);
+        let mut out = String::new();
+
+        match format {
+            GraphFormat::Dot => {
+                out.push_str("strict digraph {\n");
+                out.push_str("\trankdir=\"BT\"\n");
+                out.push_str("\tnode [shape=box]\n");
+                out.push_str("\tedge [dir=back]\n");
+                out.push_str("\tROOT [shape=point]\n");
+
+                for node in &nodes {
+                    let mut label = String::new();
+                    write!(label, "<b>").unwrap();
+                    escape_html(&mut label, node.agent);
+                    write!(label, "</b> {}..{}<br/>LV {}..{}", node.seq.start, node.seq.end, node.span.start, node.span.end).unwrap();
+                    for op in &node.ops {
+                        label.push_str("<br align=\"left\"/>");
+                        escape_html(&mut label, op);
+                    }
+                    writeln!(out, "\t{} [label=<{}>]", node.id(), label).unwrap();
+
+                    if node.parents.is_root() {
+                        writeln!(out, "\t{} -> ROOT", node.id()).unwrap();
+                    }
+                    for p in node.parents.iter() {
+                        writeln!(out, "\t{} -> {}", node.id(), p).unwrap();
+                    }
+                }
+                out.push_str("}\n");
+            }
+
+            GraphFormat::Json => {
+                out.push_str("{\"nodes\":[");
+                for (i, node) in nodes.iter().enumerate() {
+                    if i > 0 { out.push(','); }
+                    write!(out, "{{\"id\":{},\"agent\":", node.id()).unwrap();
+                    escape_json(&mut out, node.agent);
+                    write!(out, ",\"seq\":[{},{}],\"span\":[{},{}],\"parents\":[", node.seq.start, node.seq.end, node.span.start, node.span.end).unwrap();
+                    for (j, p) in node.parents.iter().enumerate() {
+                        if j > 0 { out.push(','); }
+                        write!(out, "{p}").unwrap();
+                    }
+                    out.push(']');
+                    if opts.include_ops {
+                        out.push_str(",\"ops\":[");
+                        for (j, op) in node.ops.iter().enumerate() {
+                            if j > 0 { out.push(','); }
+                            escape_json(&mut out, op);
+                        }
+                        out.push(']');
+                    }
+                    out.push('}');
+                }
+                out.push_str("]}");
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+    use super::{GraphExportOptions, GraphFormat};
+
+    #[test]
+    fn export_graph() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hi \"there\"");
+        oplog.add_insert_at(mike, &[1], 2, "!");
+        oplog.add_delete_at(seph, &[3, 10], 0..2);
+
+        let opts = GraphExportOptions { include_ops: true, ..Default::default() };
+        let json = oplog.export_graph(GraphFormat::Json, &opts);
+        assert_eq!(json, concat!(
+            r#"{"nodes":["#,
+            r#"{"id":1,"agent":"seph","seq":[0,2],"span":[0,2],"parents":[],"ops":["Ins 0 'hi'"]},"#,
+            r#"{"id":3,"agent":"seph","seq":[2,4],"span":[2,4],"parents":[1],"ops":["Ins 2 ' \"'"]},"#,
+            r#"{"id":9,"agent":"seph","seq":[4,10],"span":[4,10],"parents":[3],"ops":["Ins 4 'there\"'"]},"#,
+            r#"{"id":10,"agent":"mike","seq":[0,1],"span":[10,11],"parents":[1],"ops":["Ins 2 '!'"]},"#,
+            r#"{"id":12,"agent":"seph","seq":[10,12],"span":[11,13],"parents":[3,10],"ops":["Del 0..2"]}"#,
+            r#"]}"#,
+        ));
+
+        let dot = oplog.export_graph(GraphFormat::Dot, &GraphExportOptions::default());
+        assert!(dot.starts_with("strict digraph {"));
+        assert!(dot.contains("\t12 -> 3\n\t12 -> 10\n"));
+        assert!(dot.contains("<b>mike</b> 0..1<br/>LV 10..11"));
+        assert!(!dot.contains("Ins"));
+    }
+}
@@ -0,0 +1,152 @@
+//! Finding the versions in a document's history where the document had some specific content.
+//!
+//! This is useful for lining up diamond types history with snapshots stored elsewhere (eg backups,
+//! or git commits). The caller hashes the snapshot with [`content_hash`], and we walk through the
+//! document's history looking for versions with a matching hash.
+//!
+//! The hash is a polynomial hash over the document's characters. Rather than rehashing the whole
+//! document at every version, we keep a segment tree over every item ever inserted (in document
+//! order), with deleted and not-yet-inserted items contributing nothing. Each operation only
+//! updates a single leaf, so the hash of the whole document can be kept up to date in O(log n).
+
+use rle::HasLength;
+use crate::{Frontier, LV};
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::listmerge::rewind::RewindTracker;
+use crate::rle::KVPair;
+
+const MODULUS: u64 = (1 << 61) - 1;
+const BASE: u64 = 1_000_003;
+
+fn mul_mod(a: u64, b: u64) -> u64 {
+    ((a as u128 * b as u128) % MODULUS as u128) as u64
+}
+
+fn char_val(c: char) -> u64 {
+    // +1 so NUL characters still change the hash.
+    c as u64 + 1
+}
+
+/// Hash a string the same way [`find_versions_matching`](ListOpLog::find_versions_matching) hashes
+/// the document content. This is not a cryptographic hash.
+pub fn content_hash(content: &str) -> u64 {
+    content_hash_chars(content.chars())
+}
+
+pub(crate) fn content_hash_chars<I: Iterator<Item = char>>(chars: I) -> u64 {
+    chars.fold(0, |h, c| (mul_mod(h, BASE) + char_val(c)) % MODULUS)
+}
+
+/// Segment tree storing (number of visible chars, hash) for every range of items.
+struct HashTree {
+    /// Number of leaves. Always a power of 2.
+    size: usize,
+    nodes: Vec<(usize, u64)>,
+    /// BASE^i for each i up to the number of items.
+    pow: Vec<u64>,
+}
+
+impl HashTree {
+    fn new(num_items: usize) -> Self {
+        let size = num_items.next_power_of_two();
+        let mut pow = Vec::with_capacity(num_items + 1);
+        pow.push(1);
+        for i in 0..num_items {
+            pow.push(mul_mod(pow[i], BASE));
+        }
+        Self { size, nodes: vec![(0, 0); size * 2], pow }
+    }
+
+    fn set(&mut self, idx: usize, val: Option<u64>) {
+        let mut i = idx + self.size;
+        self.nodes[i] = val.map_or((0, 0), |v| (1, v));
+        while i > 1 {
+            i /= 2;
+            let (l_len, l_hash) = self.nodes[i * 2];
+            let (r_len, r_hash) = self.nodes[i * 2 + 1];
+            self.nodes[i] = (l_len + r_len, (mul_mod(l_hash, self.pow[r_len]) + r_hash) % MODULUS);
+        }
+    }
+
+    fn hash(&self) -> u64 {
+        self.nodes[1].1
+    }
+}
+
+impl ListOpLog {
+    /// Walk through the document's history and find every version where the hash of the document
+    /// content (calculated with [`content_hash`]) is `hash`.
+    ///
+    /// Only the versions made up of the first N operations (in local version order) are checked.
+    /// When the history is linear, that's every version the document has ever had. Concurrent
+    /// edits will only be checked in the order in which they were merged locally.
+    ///
+    /// Inserts without known content are hashed as if they were filled with placeholder
+    /// characters, so versions containing them will generally never match.
+    pub fn find_versions_matching(&self, hash: u64) -> Vec<Frontier> {
+        let mut result = vec![];
+        if hash == content_hash("") {
+            result.push(Frontier::root());
+        }
+
+        let mut tracker = RewindTracker::new();
+        tracker.move_to(self, self.cg.version.as_ref());
+        let order = tracker.item_order();
+        let mut tree = HashTree::new(order.len());
+
+        let mut frontier = Frontier::root();
+        for (KVPair(start, op), content) in self.iter_fast() {
+            let mut chars = content.map(|c| c.chars());
+
+            for lv in start..start + op.len() as LV {
+                match op.kind {
+                    ListOpKind::Ins => {
+                        // Content for inserts is always stored in order.
+                        let c = chars.as_mut().and_then(|c| c.next());
+                        tree.set(order.order_of(lv), Some(c.map_or(0, char_val)));
+                    }
+                    ListOpKind::Del => {
+                        tree.set(order.order_of(tracker.deleted_item(lv)), None);
+                    }
+                }
+
+                frontier.advance(&self.cg.graph, (lv..lv + 1).into());
+                if tree.hash() == hash {
+                    result.push(frontier.clone());
+                }
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Frontier;
+    use crate::list::ListOpLog;
+    use super::content_hash;
+
+    #[test]
+    fn find_matching_versions() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hello");
+        let v1 = oplog.add_delete_without_content(seph, 4..5); // hell
+        oplog.add_insert(seph, 4, "o world"); // hello world
+        let v2 = oplog.add_delete_without_content(seph, 5..11); // hello
+
+        assert_eq!(oplog.find_versions_matching(content_hash("hell")), vec![
+            Frontier::new_1(3), // Typing "hell"
+            Frontier::new_1(v1),
+        ]);
+        assert_eq!(oplog.find_versions_matching(content_hash("hello")), vec![
+            Frontier::new_1(4),
+            Frontier::new_1(6),
+            Frontier::new_1(v2),
+        ]);
+        assert_eq!(oplog.find_versions_matching(content_hash("")), vec![Frontier::root()]);
+        assert!(oplog.find_versions_matching(content_hash("nope")).is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+//! Converting between character positions and (line, column) positions.
+//!
+//! Editors (and the language server protocol) generally name positions by line and column. Branches
+//! can optionally keep an index of where each line starts, which is updated as the branch's content
+//! changes. This makes the conversion O(log n), and saves editor integrations from maintaining
+//! their own line index which needs to be kept in sync through merges.
+
+use std::fmt::{Debug, Formatter};
+use rle::HasLength;
+use std::ops::Range;
+use jumprope::JumpRope;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::ListOpKind;
+use crate::list::redact::placeholder_str;
+use crate::listmerge::merge::reverse_str;
+use crate::LV;
+use crate::unicount::count_chars;
+
+/// A position in a document, named by line and column. Both are zero-based, and columns are
+/// counted in unicode characters.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LineCol {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// A change to a document, expressed in lines and columns. The text between `start` and `end` is
+/// replaced by `text`. (This is the same shape as an LSP `TextEdit`).
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LineColEdit {
+    pub start: LineCol,
+    pub end: LineCol,
+    pub text: String,
+}
+
+/// The character position of every newline in the document. Sorted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LineIndex(Vec<usize>);
+
+impl LineIndex {
+    fn from_rope(rope: &JumpRope) -> Self {
+        Self(rope.chars().enumerate()
+            .filter(|(_, c)| *c == '\n')
+            .map(|(i, _)| i)
+            .collect())
+    }
+
+    pub(crate) fn insert(&mut self, pos: usize, content: &str) {
+        let idx = self.0.partition_point(|p| *p < pos);
+        let len = count_chars(content);
+        for p in &mut self.0[idx..] { *p += len; }
+
+        if content.contains('\n') {
+            self.0.splice(idx..idx, content.chars().enumerate()
+                .filter(|(_, c)| *c == '\n')
+                .map(|(i, _)| pos + i));
+        }
+    }
+
+    pub(crate) fn remove(&mut self, range: Range<usize>) {
+        let start = self.0.partition_point(|p| *p < range.start);
+        let end = self.0.partition_point(|p| *p < range.end);
+        self.0.drain(start..end);
+        for p in &mut self.0[start..] { *p -= range.len(); }
+    }
+
+    fn line_col(&self, pos: usize) -> LineCol {
+        let line = self.0.partition_point(|p| *p < pos);
+        LineCol { line, col: pos - self.line_start(line) }
+    }
+
+    fn line_start(&self, line: usize) -> usize {
+        if line == 0 { 0 } else { self.0[line - 1] + 1 }
+    }
+
+    /// doc_len is needed to find the end of the last line.
+    fn pos(&self, line: usize, col: usize, doc_len: usize) -> usize {
+        assert!(line <= self.0.len(), "Line out of bounds");
+        let line_end = self.0.get(line).copied().unwrap_or(doc_len);
+        (self.line_start(line) + col).min(line_end)
+    }
+}
+
+/// The branch's line index, if it has been enabled. This is just a cache of information in the
+/// branch's content, so its ignored when comparing branches.
+#[derive(Clone, Default)]
+pub(crate) struct LineCache(pub(crate) Option<Box<LineIndex>>);
+
+impl PartialEq for LineCache {
+    fn eq(&self, _other: &Self) -> bool { true }
+}
+
+impl Eq for LineCache {}
+
+impl Debug for LineCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("LineCache")
+            .field(&self.0.is_some())
+            .finish()
+    }
+}
+
+impl ListBranch {
+    /// Start keeping an index of where each line starts in the document. The index is updated
+    /// with every change to the branch, which makes
+    /// [`pos_to_line_col`](ListBranch::pos_to_line_col) and
+    /// [`line_col_to_pos`](ListBranch::line_col_to_pos) O(log n) instead of O(n).
+    ///
+    /// Updating the index is O(number of lines) per edit, so this is best used for documents
+    /// which are open in an editor.
+    pub fn enable_line_index(&mut self) {
+        if self.lines.0.is_none() {
+            self.lines.0 = Some(Box::new(LineIndex::from_rope(&self.content.borrow())));
+        }
+    }
+
+    pub fn disable_line_index(&mut self) {
+        self.lines.0 = None;
+    }
+
+    fn with_line_index<R, F: FnOnce(&LineIndex) -> R>(&self, f: F) -> R {
+        match self.lines.0.as_ref() {
+            Some(index) => f(index),
+            None => f(&LineIndex::from_rope(&self.content.borrow())),
+        }
+    }
+
+    /// Convert a character position in the document into a line and column.
+    pub fn pos_to_line_col(&self, pos: usize) -> LineCol {
+        assert!(pos <= self.len());
+        self.with_line_index(|index| index.line_col(pos))
+    }
+
+    /// Convert a line and column into a character position in the document. Columns past the end
+    /// of the line are clamped to the end of the line.
+    ///
+    /// Panics if the line is past the end of the document.
+    pub fn line_col_to_pos(&self, line: usize, col: usize) -> usize {
+        let len = self.len();
+        self.with_line_index(|index| index.pos(line, col, len))
+    }
+
+    /// Merge changes from the oplog into the branch, like [`merge`](ListBranch::merge). This also
+    /// returns the changes made to the branch, expressed as line / column edits. The edits apply
+    /// sequentially - each edit's positions take into account the edits before it.
+    ///
+    /// This is slower than a normal merge, so its best used when merging small remote changes into
+    /// a document which is open in an editor.
+    pub fn merge_line_col(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) -> Vec<LineColEdit> {
+        let merge_frontier = oplog.expand_to_transactions(merge_frontier);
+        let merge_frontier = merge_frontier.as_ref();
+
+        // Line / column conversions are much faster with the index.
+        let had_index = self.lines.0.is_some();
+        self.enable_line_index();
+        self.lift_composition();
+        self.bind_anchors(oplog);
+
+        let ops: Vec<_> = oplog.iter_xf_operations_from(self.version.as_ref(), merge_frontier)
+            .filter_map(|(range, op)| Some((range.start, op?)))
+            .collect();
+
+        let mut edits = Vec::with_capacity(ops.len());
+        for (lv, op) in ops {
+            let span = op.loc.span;
+            let start = self.pos_to_line_col(op.start());
+            match op.kind {
+                ListOpKind::Ins => {
+                    let text = match op.content_as_str() {
+                        Some(content) if op.loc.fwd => content.into(),
+                        Some(content) => reverse_str(content).to_string(),
+                        None => placeholder_str(op.len()),
+                    };
+                    self.content_insert(op.start(), &text, op.len(), Some(lv));
+                    edits.push(LineColEdit { start, end: start, text });
+                }
+                ListOpKind::Del => {
+                    let end = self.pos_to_line_col(op.end());
+                    self.content_remove(span.into(), Some(lv));
+                    edits.push(LineColEdit { start, end, text: String::new() });
+                }
+            }
+
+            if let Some(c) = self.composition.as_mut() {
+                c.transform(op.kind, span);
+            }
+        }
+
+        self.version = oplog.cg.graph.find_dominators_2(self.version.as_ref(), merge_frontier);
+        self.fix_stale_anchors(oplog);
+        self.restore_composition();
+        if !had_index { self.disable_line_index(); }
+        edits
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListCRDT};
+    use super::{LineCol, LineColEdit};
+
+    fn lc(line: usize, col: usize) -> LineCol { LineCol { line, col } }
+
+    #[test]
+    fn line_col_conversion() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "ab\ncde\n\nf");
+
+        for indexed in [false, true] {
+            if indexed { doc.branch.enable_line_index(); }
+            let b = &doc.branch;
+            assert_eq!(b.pos_to_line_col(0), lc(0, 0));
+            assert_eq!(b.pos_to_line_col(2), lc(0, 2));
+            assert_eq!(b.pos_to_line_col(3), lc(1, 0));
+            assert_eq!(b.pos_to_line_col(7), lc(2, 0));
+            assert_eq!(b.pos_to_line_col(9), lc(3, 1));
+
+            assert_eq!(b.line_col_to_pos(1, 2), 5);
+            assert_eq!(b.line_col_to_pos(1, 100), 6);
+            assert_eq!(b.line_col_to_pos(3, 0), 8);
+        }
+
+        // The index is kept up to date through local edits.
+        doc.insert(seph, 1, "x\ny");
+        doc.delete_without_content(seph, 5..7); // Deletes "\nc"
+        assert_eq!(doc.branch.content().to_string(), "ax\nybde\n\nf");
+        for pos in 0..=doc.len() {
+            let expected = ListBranch::new_at_tip(&doc.oplog).pos_to_line_col(pos);
+            assert_eq!(doc.branch.pos_to_line_col(pos), expected);
+        }
+    }
+
+    #[test]
+    fn merge_line_col_edits() {
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, "hello\nworld\n");
+
+        let mut b = ListCRDT::new();
+        b.merge_data_and_ff(&a.oplog.encode(&Default::default())).unwrap();
+        b.branch.enable_line_index();
+
+        a.insert(seph, 6, "big ");
+        a.delete_without_content(seph, 0..6);
+
+        let edits = b.branch.merge_line_col(&a.oplog, a.oplog.local_frontier_ref());
+        assert_eq!(edits, vec![
+            LineColEdit { start: lc(1, 0), end: lc(1, 0), text: "big ".into() },
+            LineColEdit { start: lc(0, 0), end: lc(1, 0), text: "".into() },
+        ]);
+        assert_eq!(b.branch.content().to_string(), "big world\n");
+        assert_eq!(b.branch.pos_to_line_col(10), lc(1, 0));
+    }
+}
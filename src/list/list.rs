@@ -1,7 +1,7 @@
 use std::ops::Range;
 use humansize::{BINARY, format_size};
-use crate::list::{ListBranch, ListCRDT, ListOpLog};
-use crate::{AgentId, Frontier, LV};
+use crate::list::{AddPatchesError, ListBranch, ListCRDT, ListOpLog};
+use crate::{AgentId, Frontier, lv_to_usize, LV};
 use rle::HasLength;
 use crate::list::operation::ListOpKind::{Del, Ins};
 use crate::list::operation::{ListOpKind, TextOperation};
@@ -45,29 +45,31 @@ fn insert_history_local(oplog: &mut ListOpLog, frontier: &mut Frontier, range: D
 ///
 /// (I low key hate the duplicated code though.)
 pub(crate) fn apply_local_operations(oplog: &mut ListOpLog, branch: &mut ListBranch, agent: AgentId, local_ops: &[TextOperation]) -> LV {
+    debug_assert!(branch.composition.is_none(), "Local edits made during a composition session");
+    branch.bind_anchors(oplog);
     let first_time = oplog.len();
     let mut next_time = first_time;
 
     // for LocalOp { pos, ins_content, del_span } in local_ops {
     for c in local_ops {
-        let pos = c.loc.span.start;
+        let pos = lv_to_usize(c.loc.span.start);
         let len = c.len();
 
         match c.kind {
             Ins => {
                 // assert!(c.);
                 // let new_content = consume_chars(&mut content, len);
-                branch.content.insert(pos, c.content.as_ref().unwrap());
+                branch.content_insert(pos, c.content.as_ref().unwrap(), len, Some(next_time));
             }
 
             Del => {
-                branch.content.remove(pos..pos + len);
+                branch.content_remove(pos..pos + len, Some(next_time));
             }
         }
 
         // oplog.operations.push(KVPair(next_time, c.clone()));
         oplog.push_op_internal(next_time, c.loc, c.kind, c.content_as_str());
-        next_time += len;
+        next_time += len as LV;
     }
 
     let span = DTRange {
@@ -81,6 +83,7 @@ pub(crate) fn apply_local_operations(oplog: &mut ListOpLog, branch: &mut ListBra
 
     // replace_frontier_with(&mut oplog.version, next_time - 1);
     insert_history_local(oplog, &mut branch.version, span);
+    branch.fix_stale_anchors(oplog);
 
     next_time - 1
 }
@@ -92,11 +95,12 @@ fn internal_do_insert(oplog: &mut ListOpLog, branch: &mut ListBranch, agent: Age
 
     let len = count_chars(content);
 
-    branch.content.insert(pos, content);
+    branch.bind_anchors(oplog);
+    branch.content_insert(pos, content, len, Some(start));
 
-    oplog.push_op_internal(start, (pos..pos + len).into(), ListOpKind::Ins, Some(content));
+    oplog.push_op_internal(start, (pos as LV..(pos + len) as LV).into(), ListOpKind::Ins, Some(content));
 
-    let end = start + len;
+    let end = start + len as LV;
     let time_span = DTRange {
         start,
         end
@@ -110,17 +114,19 @@ fn internal_do_insert(oplog: &mut ListOpLog, branch: &mut ListBranch, agent: Age
     debug_assert_eq!(oplog.cg.version, branch.version);
     oplog.cg.version.replace_with_1(end - 1);
     insert_history_local(oplog, &mut branch.version, time_span);
+    branch.fix_stale_anchors(oplog);
     end - 1
 }
 
 fn internal_do_delete(oplog: &mut ListOpLog, branch: &mut ListBranch, agent: AgentId, pos: DTRange) -> LV {
     let start = oplog.len();
 
-    branch.content.remove(pos.into());
+    branch.bind_anchors(oplog);
+    branch.content_remove(pos.into(), Some(start));
 
     oplog.push_op_internal(start, pos.into(), ListOpKind::Del, None);
 
-    let end = start + pos.len();
+    let end = start + pos.len() as LV;
     let time_span = DTRange {
         start,
         end
@@ -132,6 +138,7 @@ fn internal_do_delete(oplog: &mut ListOpLog, branch: &mut ListBranch, agent: Age
     oplog.cg.version.replace_with_1(end - 1);
     // oplog.advance_frontier(&branch.version, time_span);
     insert_history_local(oplog, &mut branch.version, time_span);
+    branch.fix_stale_anchors(oplog);
     end - 1
 }
 
@@ -149,6 +156,17 @@ impl ListCRDT {
         }
     }
 
+    /// Create a document containing `content`, inserted by the named agent. This is the fastest
+    /// way to import a large file.
+    pub fn from_str(agent_name: &str, content: &str) -> Self {
+        let mut doc = Self::new();
+        let agent = doc.get_or_create_agent_id(agent_name);
+        if !content.is_empty() {
+            internal_do_insert(&mut doc.oplog, &mut doc.branch, agent, 0, content);
+        }
+        doc
+    }
+
     pub fn load_from(bytes: &[u8]) -> Result<Self, ParseError> {
         let oplog = ListOpLog::load_from(bytes)?;
         let branch = oplog.checkout_tip();
@@ -163,6 +181,17 @@ impl ListCRDT {
         Ok(v)
     }
 
+    /// Add a batch of patches to the oplog, then merge them all into the branch at once. This is
+    /// much faster than merging each patch separately when the patches have concurrent changes.
+    /// See [`ListOpLog::add_patches`].
+    ///
+    /// If an error is returned, the branch is left unchanged.
+    pub fn merge_patches<'a, I: IntoIterator<Item=&'a [u8]>>(&mut self, patches: I) -> Result<Frontier, AddPatchesError> {
+        let v = self.oplog.add_patches(patches)?;
+        self.branch.merge(&self.oplog, self.oplog.cg.version.as_ref());
+        Ok(v)
+    }
+
     pub fn len(&self) -> usize {
         self.branch.len()
     }
@@ -191,7 +220,7 @@ impl ListCRDT {
 
     pub fn delete_without_content(&mut self, agent: AgentId, loc: Range<usize>) -> LV {
         // self.branch.delete_without_content(&mut self.oplog, agent, loc)
-        internal_do_delete(&mut self.oplog, &mut self.branch, agent, loc.into())
+        internal_do_delete(&mut self.oplog, &mut self.branch, agent, (loc.start as LV..loc.end as LV).into())
     }
 
     pub fn delete(&mut self, agent: AgentId, range: Range<usize>) -> LV {
@@ -203,6 +232,11 @@ impl ListCRDT {
         self.branch.delete_at_wchar(&mut self.oplog, agent, wchar_range)
     }
 
+    /// See [`ListBranch::apply_local_patch`].
+    pub fn apply_local_patch(&mut self, agent: AgentId, patch: &[(Range<usize>, &str)]) -> Option<LV> {
+        self.branch.apply_local_patch(&mut self.oplog, agent, patch)
+    }
+
     pub fn print_stats(&self, detailed: bool) {
         println!("Document of length {}", self.branch.len());
 
@@ -244,4 +278,28 @@ mod tests {
 
         doc.oplog.dbg_print_all();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bulk_import() {
+        let content = "hello wörld\n".repeat(10000);
+        let doc = ListCRDT::from_str("seph", &content);
+        assert_eq!(doc.branch.content, content.as_str());
+        assert_eq!(doc.oplog.operations.0.len(), 1);
+        doc.dbg_check(true);
+
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert_bulk(seph, 0, &content);
+        let v = oplog.add_insert_bulk(seph, 5, "!!");
+        assert_eq!(v, count_chars(&content) as LV + 1);
+        oplog.dbg_check(true);
+
+        let mut expected = ListOpLog::new();
+        expected.get_or_create_agent_id("seph");
+        expected.add_insert(seph, 0, &content);
+        expected.add_insert(seph, 5, "!!");
+        assert_eq!(oplog, expected);
+
+        assert!(ListCRDT::from_str("seph", "").is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+//! Memory usage reporting, and limiting how much inserted / deleted content is kept in memory.
+
+use std::fs::File;
+use std::mem::size_of;
+use std::sync::Arc;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::causalgraph::agent_assignment::ClientData;
+use crate::list::ListOpLog;
+use crate::list::content_buffer::SpillFile;
+use crate::rle::rle_vec::{RleStats, RleVecStats};
+
+/// A breakdown of the memory used by an oplog, in bytes. Sizes are based on allocated capacity,
+/// so they include any slack space in the oplog's vectors.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MemUsage {
+    /// Per-agent data: agent names and the mapping from sequence numbers to local versions.
+    pub client_data: usize,
+    /// The mapping from local versions to (agent, seq) pairs.
+    pub agent_assignment: usize,
+    /// The operations themselves (not including their content).
+    pub operations: usize,
+    /// Inserted and deleted content kept in memory.
+    pub content: usize,
+    /// Content which has been spilled to disk. This isn't counted in the total.
+    pub spilled_content: usize,
+    /// The causal graph (parents information).
+    pub history: usize,
+    /// Transaction boundaries.
+    pub transactions: usize,
+}
+
+impl MemUsage {
+    /// The total number of bytes of memory used.
+    pub fn total(&self) -> usize {
+        self.client_data + self.agent_assignment + self.operations + self.content
+            + self.history + self.transactions
+    }
+}
+
+fn rle_size(stats: RleStats) -> usize {
+    stats.capacity * stats.entry_byte_size
+}
+
+impl ListOpLog {
+    /// Returns how much memory is used by each part of the oplog.
+    pub fn mem_usage_breakdown(&self) -> MemUsage {
+        let aa = &self.cg.agent_assignment;
+        let client_data = aa.client_data.capacity() * size_of::<ClientData>()
+            + aa.client_data.iter().map(|c| {
+                let name = if c.name.is_inline() { 0 } else { c.name.len() };
+                name + rle_size(c.lv_for_seq.get_stats())
+            }).sum::<usize>();
+
+        let ctx = &self.operation_ctx;
+        MemUsage {
+            client_data,
+            agent_assignment: rle_size(aa.client_with_lv.get_stats()),
+            operations: rle_size(self.operations.get_stats()),
+            content: ctx.ins_content.mem_size() + ctx.del_content.mem_size(),
+            spilled_content: ctx.ins_content.spilled_bytes() + ctx.del_content.spilled_bytes(),
+            history: rle_size(self.cg.graph.entries.get_stats())
+                + self.cg.graph.root_child_indexes.capacity() * size_of::<usize>(),
+            transactions: self.transactions.capacity() * size_of::<crate::DTRange>(),
+        }
+    }
+
+    /// Free any unused space in the oplog's internal vectors. Vectors grow in steps as operations
+    /// are added, so this can noticeably reduce the memory used by small documents which are kept
+    /// around (eg lots of collaborative form fields). Adding more operations afterwards is fine,
+    /// but will grow the vectors again.
+    pub fn shrink_to_fit(&mut self) {
+        let aa = &mut self.cg.agent_assignment;
+        for c in aa.client_data.iter_mut() {
+            c.lv_for_seq.0.shrink_to_fit();
+        }
+        aa.client_data.shrink_to_fit();
+        aa.client_with_lv.0.shrink_to_fit();
+        self.cg.graph.entries.0.shrink_to_fit();
+        self.cg.graph.root_child_indexes.shrink_to_fit();
+
+        self.operations.0.shrink_to_fit();
+        self.operation_ctx.ins_content.shrink_to_fit();
+        self.operation_ctx.del_content.shrink_to_fit();
+        self.fork_points.shrink_to_fit();
+        self.transactions.shrink_to_fit();
+        self.signatures.shrink_to_fit();
+        self.op_metadata.shrink_to_fit();
+        self.checkpoints.shrink_to_fit();
+    }
+
+    /// Limit the amount of inserted and deleted content kept in memory to (about) `limit` bytes.
+    /// When the limit is exceeded, older content is written out to `file` and read back from the
+    /// file when its needed. Content is read back in chunks, and chunks which have been read back
+    /// stay in memory until the limit is next enforced - so memory usage can temporarily exceed
+    /// the limit while merging old history.
+    ///
+    /// The file should be a new, empty temporary file, opened for reading and writing. It must
+    /// not be modified by anything else while the oplog is in use. If the file can't be read
+    /// when spilled content is needed, the content is replaced with placeholder characters. See
+    /// [`take_content_spill_error`](Self::take_content_spill_error).
+    pub fn set_content_memory_limit(&mut self, limit: usize, file: File) -> std::io::Result<()> {
+        self.operation_ctx.spill.0 = Some((Arc::new(SpillFile::new(file)), limit));
+        self.operation_ctx.enforce_memory_limit()
+    }
+
+    /// Returns (and clears) the error from the last failed attempt to read spilled content back
+    /// from disk.
+    ///
+    /// Spilled content is read back when its needed, which is usually somewhere that can't return
+    /// an error (like checking out a branch). So if the file can't be read, the content is replaced
+    /// with placeholder characters and the error is kept here. Check this after reading old
+    /// content if you need to know it was intact.
+    pub fn take_content_spill_error(&self) -> Option<std::io::Error> {
+        self.operation_ctx.ins_content.take_spill_error()
+            .or_else(|| self.operation_ctx.del_content.take_spill_error())
+    }
+
+    /// Stop spilling new content to disk. Content which has already been spilled stays in the
+    /// file.
+    pub fn clear_content_memory_limit(&mut self) {
+        self.operation_ctx.spill.0 = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListOpLog, PLACEHOLDER_CHAR};
+    use crate::lv_to_usize;
+
+    #[test]
+    fn small_documents() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        for c in "hello world".chars() {
+            oplog.add_insert(seph, lv_to_usize(oplog.len()), &c.to_string());
+        }
+        oplog.add_delete_without_content(seph, 0..6);
+        let usage = oplog.mem_usage_breakdown();
+        assert!(usage.content < 100);
+
+        let expected = oplog.clone();
+        oplog.shrink_to_fit();
+        assert_eq!(oplog, expected);
+        assert!(oplog.mem_usage_breakdown().total() <= usage.total());
+        assert_eq!(oplog.mem_usage_breakdown().content, 11);
+
+        oplog.add_insert(seph, 5, "!");
+        assert_eq!(oplog.checkout_tip().content().to_string(), "world!");
+    }
+
+    #[test]
+    fn spill_content() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let usage = oplog.mem_usage_breakdown();
+        assert_eq!(usage.content, 0);
+
+        let path = std::env::temp_dir().join(format!("dt_spill_test_{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true).write(true).create(true).truncate(true)
+            .open(&path).unwrap();
+        oplog.set_content_memory_limit(100_000, file).unwrap();
+
+        let chunk = "abcdefghij".repeat(5000);
+        for _ in 0..10 {
+            oplog.add_insert(seph, 0, &chunk);
+        }
+
+        let usage = oplog.mem_usage_breakdown();
+        assert!(usage.content <= 200_000);
+        assert!(usage.spilled_content > 0);
+        assert!(usage.operations > 0 && usage.history > 0 && usage.client_data > 0);
+        assert!(usage.total() >= usage.content);
+
+        // Spilled content is read back when its needed.
+        let expected = chunk.repeat(10);
+        assert_eq!(oplog.checkout_tip().content().to_string(), expected);
+        assert!(oplog.take_content_spill_error().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn spill_read_error() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+
+        let path = std::env::temp_dir().join(format!("dt_spill_error_test_{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true).write(true).create(true).truncate(true)
+            .open(&path).unwrap();
+        let other = file.try_clone().unwrap();
+        oplog.set_content_memory_limit(0, file).unwrap();
+
+        let chunk = "héllo wörld ☃ 🌍".repeat(5000);
+        oplog.add_insert(seph, 0, &chunk);
+        oplog.add_insert(seph, 0, &chunk);
+        assert!(oplog.mem_usage_breakdown().spilled_content > 0);
+
+        // If the file is truncated, spilled content can't be read back. The document still has the
+        // right length, and the error is reported.
+        other.set_len(0).unwrap();
+        let content = oplog.checkout_tip().content().to_string();
+        assert_eq!(content.chars().count(), chunk.chars().count() * 2);
+        assert_eq!(content.len(), chunk.len() * 2);
+        assert!(content.contains(PLACEHOLDER_CHAR));
+        assert!(oplog.take_content_spill_error().is_some());
+        assert!(oplog.take_content_spill_error().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,13 +1,16 @@
 use rle::HasLength;
 
-use crate::{DTRange, LV};
+use crate::{DTRange, Frontier, LV};
 use crate::frontier::FrontierRef;
 use crate::list::{ListBranch, ListOpLog};
+use crate::list::redact::placeholder_str;
 use crate::list::op_metrics::ListOpMetrics;
 use crate::list::operation::{ListOpKind, TextOperation};
 use crate::listmerge::merge::{reverse_str, TransformedOpsIterRaw, TransformedResultRaw, TransformedSimpleOp, TransformedSimpleOpsIter};
 use crate::listmerge::merge::TransformedResult::{BaseMoved, DeleteAlreadyHappened};
-use crate::listmerge::plan::M1PlanAction;
+use crate::listmerge::merge_context::PendingMergeContext;
+use crate::listmerge::plan::{M1Plan, M1PlanAction};
+use crate::listmerge::rewind::RewindTracker;
 use crate::rle::KVPair;
 
 impl ListOpLog {
@@ -15,6 +18,16 @@ impl ListOpLog {
         self.cg.graph.make_m1_plan(Some(&self.operations), &[], self.cg.version.as_ref(), false);
     }
 
+    /// Make a plan for merging the changes in `merge_frontier` into a branch at version `from`.
+    /// The plan can be inspected, then run with [`ListBranch::merge_with_plan`]. If the frontier
+    /// only contains part of a transaction, the plan includes the rest of the transaction.
+    pub fn make_merge_plan(&self, from: &[LV], merge_frontier: &[LV]) -> M1Plan {
+        let merge_frontier = self.expand_to_transactions(merge_frontier);
+        let mut plan = self.cg.graph.make_m1_plan(Some(&self.operations), from, merge_frontier.as_ref(), true).0;
+        plan.made_for = Some((from.into(), merge_frontier));
+        plan
+    }
+
     pub(crate) fn get_xf_operations_full(&self, from: FrontierRef, merging: FrontierRef) -> TransformedOpsIterRaw {
         TransformedOpsIterRaw::new(&self.cg.graph, &self.cg.agent_assignment,
                                 &self.operation_ctx, &self.operations,
@@ -37,7 +50,7 @@ impl ListOpLog {
                     let content = op.get_content(&self.operation_ctx);
                     let len = op.len();
                     let text_op: TextOperation = (op, content).into();
-                    ((start..start + len).into(), Some(text_op))
+                    ((start..start + len as LV).into(), Some(text_op))
                 }
                 TransformedSimpleOp::DeleteAlreadyHappened(range) => (range, None)
             }
@@ -54,6 +67,21 @@ impl ListOpLog {
         self.iter_xf_operations_from(&[], self.cg.version.as_ref())
     }
 
+    /// Get a list of operations which, when applied in order, would convert the document at
+    /// version `a` into the document at version `b`.
+    ///
+    /// Unlike [`iter_xf_operations_from`](ListOpLog::iter_xf_operations_from), `b` doesn't need to
+    /// contain `a`. If the versions are concurrent (or `b` is earlier than `a`), the result will
+    /// undo any changes in `a` that aren't in `b`.
+    ///
+    /// This currently needs to replay the document's history up to both versions, so it is about
+    /// as expensive as checking out the document from scratch.
+    pub fn diff_versions(&self, a: &[LV], b: &[LV]) -> Vec<TextOperation> {
+        let a = self.cg.graph.find_dominators(a);
+        let b = self.cg.graph.find_dominators(b);
+        RewindTracker::new().diff(self, a.as_ref(), b.as_ref())
+    }
+
     #[cfg(feature = "merge_conflict_checks")]
     pub fn has_conflicts_when_merging(&self) -> bool {
         let mut iter = TransformedOpsIterRaw::new(&self.cg.graph, &self.cg.agent_assignment,
@@ -79,7 +107,7 @@ impl ListOpLog {
                     let content = op.get_content(&self.operation_ctx);
                     let len = op.len();
                     let text_op: TextOperation = (op, content).into();
-                    ((start..start + len).into(), Some(text_op))
+                    ((start..start + len as LV).into(), Some(text_op))
                 }
                 TransformedSimpleOp::DeleteAlreadyHappened(range) => (range, None)
             }
@@ -113,7 +141,7 @@ impl ListOpLog {
         let mut clears = 0;
         let mut ff = 0;
 
-        for a in &plan.0 {
+        for a in &plan.actions {
             match a {
                 M1PlanAction::Apply(span) => { normal_advances += span.len(); }
                 M1PlanAction::Clear => { clears += 1; }
@@ -129,53 +157,480 @@ impl ListOpLog {
 
 impl ListBranch {
     #[inline(always)]
-    fn apply_op_at(&mut self, oplog: &ListOpLog, op: ListOpMetrics) {
+    pub(super) fn apply_op_at(&mut self, oplog: &ListOpLog, lv: LV, op: ListOpMetrics) {
+        if let Some(c) = self.composition.as_mut() {
+            c.transform(op.kind, op.loc.span);
+        }
+
         // let xf_pos = op.loc.span.start;
         match op.kind {
             ListOpKind::Ins => {
-                let content = oplog.operation_ctx.get_str(ListOpKind::Ins, op.content_pos.unwrap());
+                let Some(content_pos) = op.content_pos else {
+                    // The content isn't known (eg, it was redacted). Insert placeholders instead.
+                    self.content_insert(op.start(), &placeholder_str(op.len()), op.len(), Some(lv));
+                    return;
+                };
+                let content = oplog.operation_ctx.get_str(ListOpKind::Ins, content_pos);
                 // assert!(pos <= self.content.len_chars());
                 if op.loc.fwd {
-                    self.content.insert(op.loc.span.start, content);
+                    self.content_insert(op.start(), content, op.len(), Some(lv));
                 } else {
                     // We need to insert the content in reverse order.
                     let c = reverse_str(content);
-                    self.content.insert(op.loc.span.start, &c);
+                    self.content_insert(op.start(), &c, op.len(), Some(lv));
                 }
             }
             ListOpKind::Del => {
-                self.content.remove(op.loc.span.into());
+                self.content_remove(op.loc.span.into(), Some(lv));
             }
         }
     }
 
+    /// Merge the changes in `merge_frontier` from the oplog into the branch. If the frontier only
+    /// contains part of a transaction, the rest of the transaction is merged too.
     pub fn merge(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) {
-        // let mut iter = oplog.get_xf_operations_full_raw(self.version.as_ref(), merge_frontier).merge_spans();
-        let iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
-        // println!("merge '{}' at {:?} + {:?}", self.content.to_string(), self.version, merge_frontier);
-
-        for xf in iter {
-            // dbg!(&xf);
-            // dbg!(_lv, &origin_op, &xf);
-            match xf {
-                TransformedResultRaw::Apply { xf_pos, op: KVPair(_, mut op) } => {
-                    // dbg!(&op);
-                    op.transpose_to(xf_pos);
-                    self.apply_op_at(oplog, op);
+        self.start_merge(oplog, merge_frontier).finish();
+    }
+
+    /// Merge the changes in `merge_frontier` into the branch by running a plan from
+    /// [`ListOpLog::make_merge_plan`]. The plan must have been made for this merge - from the
+    /// branch's current version, to the same `merge_frontier`. The result is the same as calling
+    /// [`merge`](ListBranch::merge).
+    ///
+    /// This doesn't use the branch's merge context.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the plan was made from a different version or for a different frontier.
+    pub fn merge_with_plan(&mut self, oplog: &ListOpLog, plan: M1Plan, merge_frontier: &[LV]) {
+        let merge_frontier = oplog.expand_to_transactions(merge_frontier);
+        let (from, planned_frontier) = plan.made_for.as_ref()
+            .expect("Plan was not made by make_merge_plan");
+        assert_eq!(from, &self.version, "Plan was made from a different version");
+        assert_eq!(planned_frontier, &merge_frontier, "Plan was made for a different frontier");
+
+        let iter = TransformedOpsIterRaw::from_plan(&oplog.cg.agent_assignment, &oplog.operation_ctx,
+                                                    &oplog.operations, plan);
+        self.lift_composition();
+        self.bind_anchors(oplog);
+
+        let final_version = oplog.cg.graph.find_dominators_2(self.version.as_ref(), merge_frontier.as_ref());
+        MergeTask {
+            branch: self,
+            oplog,
+            iter,
+            pending_context: None,
+            ff: Default::default(),
+            final_version,
+            done: false,
+        }.finish();
+    }
+
+    /// Keep the tracker used by each merge, and reuse it for the next merge. This makes merging
+    /// small batches of remote changes which are concurrent with local edits O(new operations),
+    /// rather than rebuilding the tracker over the whole conflict zone each time.
+    ///
+    /// The tracker uses memory proportional to the history it spans, and it grows with each merge
+    /// until a merge comes in which can't reuse it. Trackers past a size limit aren't kept. The
+    /// tracker is also dropped if the branch is merged with a different oplog - one with a
+    /// different doc ID, or fewer operations than the oplog the tracker was built from.
+    pub fn enable_merge_context(&mut self) {
+        self.merge_context.set_enabled(true);
+    }
+
+    /// Stop keeping the merge tracker, and free its memory.
+    pub fn disable_merge_context(&mut self) {
+        self.merge_context.set_enabled(false);
+    }
+
+    /// Start merging the changes in `merge_frontier` into the branch, without doing any of the
+    /// work yet. The merge is performed incrementally by calling [`MergeTask::step`].
+    ///
+    /// This is useful when merging a lot of changes (eg, months of divergent history) from a UI
+    /// thread or an async task, since the work can be interleaved with other work.
+    ///
+    /// While the merge is in progress, the branch's content is in an intermediate state and its
+    /// version is not updated. So the task borrows the branch until it's dropped. If the task is
+    /// dropped before it's done, the rest of the merge is run when it's dropped.
+    pub fn start_merge<'a>(&'a mut self, oplog: &'a ListOpLog, merge_frontier: &[LV]) -> MergeTask<'a> {
+        let merge_frontier = oplog.expand_to_transactions(merge_frontier);
+        let merge_frontier = merge_frontier.as_ref();
+        let (iter, pending_context) = self.merge_context.start(oplog, self.version.as_ref(), merge_frontier);
+        self.lift_composition();
+        self.bind_anchors(oplog);
+
+        let final_version = oplog.cg.graph.find_dominators_2(self.version.as_ref(), merge_frontier);
+        MergeTask {
+            branch: self,
+            oplog,
+            iter,
+            pending_context,
+            ff: Default::default(),
+            final_version,
+            done: false,
+        }
+    }
+}
+
+/// An in-progress incremental merge. Created by [`ListBranch::start_merge`].
+///
+/// Dropping an unfinished task runs the rest of the merge, so the branch is never left half merged.
+pub struct MergeTask<'a> {
+    branch: &'a mut ListBranch,
+    oplog: &'a ListOpLog,
+    iter: TransformedOpsIterRaw<'a>,
+    /// Set if the branch keeps the merge's tracker once the merge is done.
+    pending_context: Option<PendingMergeContext>,
+    /// The remainder of a fast forward range which hasn't been applied yet.
+    ff: DTRange,
+    final_version: Frontier,
+    done: bool,
+}
+
+impl<'a> MergeTask<'a> {
+    /// Merge (about) `max_ops` more operations into the branch. Each operation is counted by the
+    /// number of items it inserts or deletes. Returns true once the merge is complete.
+    pub fn step(&mut self, max_ops: usize) -> bool {
+        let branch = &mut *self.branch;
+        let mut budget = max_ops;
+        while !self.done && budget > 0 {
+            if !self.ff.is_empty() {
+                // Fast forward ranges can be huge, so they're split up.
+                let end = self.ff.end.min(self.ff.start.saturating_add(budget as LV));
+                let range: DTRange = (self.ff.start..end).into();
+                for KVPair(lv, op) in self.oplog.operations.iter_range_ctx(range, &self.oplog.operation_ctx) {
+                    branch.apply_op_at(self.oplog, lv, op);
                 }
+                budget -= range.len();
+                self.ff.start = end;
+                continue;
+            }
 
-                TransformedResultRaw::FF(range) => {
+            match self.iter.next() {
+                Some(TransformedResultRaw::Apply { xf_pos, op: KVPair(lv, mut op) }) => {
+                    budget = budget.saturating_sub(op.len());
+                    op.transpose_to(xf_pos);
+                    branch.apply_op_at(self.oplog, lv, op);
+                }
+                Some(TransformedResultRaw::FF(range)) => {
                     // Activate *SUPER FAST MODE*.
-                    for KVPair(_, op) in oplog.operations.iter_range_ctx(range, &oplog.operation_ctx) {
-                        // dbg!(&op);
-                        self.apply_op_at(oplog, op);
+                    self.ff = range;
+                }
+                Some(TransformedResultRaw::DeleteAlreadyHappened(range)) => {
+                    // Discard.
+                    budget = budget.saturating_sub(range.len());
+                }
+                None => {
+                    if let Some(pending) = self.pending_context.take() {
+                        branch.merge_context.finish(&mut self.iter, pending, self.final_version.clone());
                     }
+                    branch.version = std::mem::take(&mut self.final_version);
+                    branch.fix_stale_anchors(self.oplog);
+                    branch.restore_composition();
+                    self.done = true;
                 }
+            }
+        }
+        self.done
+    }
+
+    /// Returns true if the merge has finished.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Run the rest of the merge to completion.
+    pub fn finish(mut self) {
+        while !self.step(usize::MAX) {}
+    }
+}
 
-                TransformedResultRaw::DeleteAlreadyHappened(_) => {} // Discard.
+impl Drop for MergeTask<'_> {
+    fn drop(&mut self) {
+        // If we're unwinding, the branch is probably broken anyway. Don't risk a double panic.
+        if !self.done && !std::thread::panicking() {
+            while !self.step(usize::MAX) {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jumprope::JumpRope;
+    use rand::prelude::*;
+    use std::cmp::Ordering;
+    use crate::list::{ListBranch, ListCRDT, ListOpLog, TieBreak};
+    use crate::list::old_fuzzer_tools::old_make_random_change;
+    use crate::list::operation::{ListOpKind, TextOperation};
+    use crate::list_fuzzer_tools::choose_2;
+    use crate::listmerge::merge::reverse_str;
+    use rle::HasLength;
+    use crate::listmerge::plan::M1PlanAction;
+
+    fn apply(rope: &mut JumpRope, ops: &[TextOperation]) {
+        for op in ops {
+            match op.kind {
+                ListOpKind::Ins => {
+                    let content = op.content_as_str().unwrap();
+                    if op.loc.fwd {
+                        rope.insert(op.start(), content);
+                    } else {
+                        rope.insert(op.start(), &reverse_str(content));
+                    }
+                }
+                ListOpKind::Del => rope.remove(op.loc.span.into()),
             }
         }
-        
-        self.version = oplog.cg.graph.find_dominators_2(self.version.as_ref(), merge_frontier);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn diff_versions_fuzz() {
+        for seed in 0..20 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut docs = [ListCRDT::new(), ListCRDT::new(), ListCRDT::new()];
+            for doc in docs.iter_mut() {
+                for a in 0..3 {
+                    doc.get_or_create_agent_id(format!("agent {a}").as_str());
+                }
+            }
+
+            for _ in 0..20 {
+                let idx = rng.gen_range(0..docs.len());
+                old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, false);
+
+                let (_a_idx, a, _b_idx, b) = choose_2(&mut docs, &mut rng);
+                a.oplog.add_missing_operations_from(&b.oplog);
+            }
+
+            let oplog = &docs[0].oplog;
+            for _ in 0..10 {
+                let a = oplog.parents_at_version(rng.gen_range(0..oplog.len()));
+                let b = oplog.parents_at_version(rng.gen_range(0..oplog.len()));
+
+                let ops = oplog.diff_versions(a.as_ref(), b.as_ref());
+                let mut rope = oplog.checkout(a.as_ref()).into_inner();
+                apply(&mut rope, &ops);
+                assert_eq!(rope, oplog.checkout(b.as_ref()).into_inner());
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_merge() {
+        let mut rng = SmallRng::seed_from_u64(321);
+        let mut docs = [ListCRDT::new(), ListCRDT::new()];
+        for doc in docs.iter_mut() {
+            for a in 0..2 {
+                doc.get_or_create_agent_id(format!("agent {a}").as_str());
+            }
+        }
+
+        for _ in 0..50 {
+            let idx = rng.gen_range(0..docs.len());
+            old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, false);
+        }
+        let [a, b] = &mut docs;
+        a.oplog.add_missing_operations_from(&b.oplog);
+
+        let mut branch = a.oplog.checkout(&[]);
+        let mut task = branch.start_merge(&a.oplog, a.oplog.local_frontier_ref());
+        let mut steps = 0;
+        while !task.step(3) { steps += 1; }
+        assert!(steps > 1);
+        assert!(task.is_done());
+        drop(task);
+
+        let expected = a.oplog.checkout_tip();
+        assert_eq!(branch.content(), expected.content());
+        assert_eq!(branch.local_frontier_ref(), expected.local_frontier_ref());
+
+        // Dropping a task partway through finishes the merge.
+        let mut branch = a.oplog.checkout(&[]);
+        let mut task = branch.start_merge(&a.oplog, a.oplog.local_frontier_ref());
+        assert!(!task.step(3));
+        drop(task);
+        assert_eq!(branch.content(), expected.content());
+        assert_eq!(branch.local_frontier_ref(), expected.local_frontier_ref());
+    }
+
+    #[test]
+    fn merge_plans() {
+        let mut rng = SmallRng::seed_from_u64(123);
+        let mut docs = [ListCRDT::new(), ListCRDT::new()];
+        for doc in docs.iter_mut() {
+            for a in 0..2 {
+                doc.get_or_create_agent_id(format!("agent {a}").as_str());
+            }
+        }
+
+        for _ in 0..30 {
+            let idx = rng.gen_range(0..docs.len());
+            old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, false);
+        }
+        let [a, b] = &mut docs;
+        a.oplog.add_missing_operations_from(&b.oplog);
+
+        let tip = a.oplog.cg.version.clone();
+        let plan = a.oplog.make_merge_plan(a.branch.version.as_ref(), tip.as_ref());
+        assert!(!plan.is_empty());
+        assert!(plan.actions().contains(&M1PlanAction::BeginOutput));
+        // The merge has to replay at least the new operations.
+        let new_ops: usize = a.oplog.cg.graph.diff(a.branch.version.as_ref(), tip.as_ref()).1
+            .iter().map(|r| r.len()).sum();
+        assert!(new_ops > 0 && plan.estimated_ops() >= new_ops);
+
+        let mut branch = a.branch.clone();
+        branch.merge_with_plan(&a.oplog, plan, tip.as_ref());
+        let expected = a.oplog.checkout_tip();
+        assert_eq!(branch.content(), expected.content());
+        assert_eq!(branch.local_frontier_ref(), expected.local_frontier_ref());
+
+        // Nothing left to merge.
+        assert!(a.oplog.make_merge_plan(tip.as_ref(), tip.as_ref()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "different version")]
+    fn stale_merge_plans_panic() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.oplog.add_insert(seph, 0, "hi");
+        let plan = doc.oplog.make_merge_plan(&[], doc.oplog.cg.version.as_ref());
+
+        let tip = doc.oplog.cg.version.clone();
+        doc.branch.merge(&doc.oplog, tip.as_ref());
+        doc.branch.merge_with_plan(&doc.oplog, plan, tip.as_ref());
+    }
+
+    #[test]
+    fn insert_tie_break_policies() {
+        let make = |policy: TieBreak| {
+            let mut oplog = ListOpLog::new();
+            oplog.set_insert_tie_break(policy);
+            let zed = oplog.get_or_create_agent_id("zed");
+            let amy = oplog.get_or_create_agent_id("amy");
+            oplog.add_insert_at(amy, &[], 0, "a");
+            oplog.add_insert_at(zed, &[], 0, "z");
+            oplog.checkout_tip().content().to_string()
+        };
+
+        assert_eq!(make(TieBreak::AgentName), "az");
+        assert_eq!(make(TieBreak::Custom { name: "reverse", cmp: |a, b| b.cmp(a) }), "za");
+        // Agents the comparator can't tell apart are ordered by name.
+        assert_eq!(make(TieBreak::Custom { name: "equal", cmp: |_, _| Ordering::Equal }), "az");
+    }
+
+    #[test]
+    fn merge_context_matches_checkout() {
+        for seed in 0..30 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut docs = [ListCRDT::new(), ListCRDT::new(), ListCRDT::new()];
+            for doc in docs.iter_mut() {
+                for a in 0..3 {
+                    doc.get_or_create_agent_id(format!("agent {a}").as_str());
+                }
+            }
+
+            let mut branch = ListBranch::new();
+            branch.enable_merge_context();
+            let mut reused = 0;
+
+            for _ in 0..40 {
+                let idx = rng.gen_range(0..docs.len());
+                old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, false);
+
+                let (_a_idx, a, _b_idx, b) = choose_2(&mut docs, &mut rng);
+                a.oplog.add_missing_operations_from(&b.oplog);
+
+                // docs[0]'s oplog only grows, so the branch can track it.
+                let oplog = &docs[0].oplog;
+                if !oplog.is_empty() && rng.gen_bool(0.1) {
+                    let v = oplog.parents_at_version(rng.gen_range(0..oplog.len()));
+                    branch.rewind_to(oplog, v.as_ref());
+                }
+                if branch.merge_context.is_cached() { reused += 1; }
+                branch.merge(oplog, oplog.local_frontier_ref());
+                assert!(branch.merge_context.is_cached());
+
+                let expected = oplog.checkout_tip();
+                assert_eq!(branch.content(), expected.content());
+                assert_eq!(branch.local_frontier_ref(), expected.local_frontier_ref());
+            }
+            assert!(reused > 0);
+        }
+    }
+
+    #[test]
+    fn merge_context_with_local_edits() {
+        let mut rng = SmallRng::seed_from_u64(10);
+        let mut local = ListCRDT::new();
+        let mut remote = ListCRDT::new();
+        local.get_or_create_agent_id("seph");
+        remote.get_or_create_agent_id("mike");
+        local.branch.enable_merge_context();
+
+        for _ in 0..100 {
+            if rng.gen_bool(0.5) {
+                old_make_random_change(&mut local, None, 0, &mut rng, false);
+            } else {
+                old_make_random_change(&mut remote, None, 0, &mut rng, false);
+            }
+
+            if rng.gen_bool(0.3) {
+                remote.oplog.add_missing_operations_from(&local.oplog);
+                remote.branch.merge(&remote.oplog, remote.oplog.local_frontier_ref());
+                local.oplog.add_missing_operations_from(&remote.oplog);
+                local.branch.merge(&local.oplog, local.oplog.local_frontier_ref());
+                assert_eq!(local.branch, local.oplog.checkout_tip());
+            }
+        }
+
+        local.branch.disable_merge_context();
+        assert!(!local.branch.merge_context.is_cached());
+    }
+
+    #[test]
+    fn merge_context_checks_oplog() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.oplog.set_doc_id(Some("a"));
+        doc.oplog.add_insert(seph, 0, "hi");
+        let shorter = doc.oplog.clone();
+        doc.oplog.add_insert_at(seph, &[], 0, "yo");
+
+        let mut branch = ListBranch::new();
+        branch.enable_merge_context();
+        branch.merge(&doc.oplog, doc.oplog.local_frontier_ref());
+        assert!(branch.merge_context.is_cached_for(&doc.oplog));
+
+        // The tracker can't be used with a different document, or an oplog missing operations.
+        let mut renamed = doc.oplog.clone();
+        renamed.set_doc_id(Some("b"));
+        assert!(!branch.merge_context.is_cached_for(&renamed));
+        assert!(!branch.merge_context.is_cached_for(&shorter));
+
+        // But it can be used once the oplog grows.
+        doc.oplog.add_insert(seph, 0, "x");
+        assert!(branch.merge_context.is_cached_for(&doc.oplog));
+        branch.merge(&doc.oplog, doc.oplog.local_frontier_ref());
+        assert_eq!(branch.content(), doc.oplog.checkout_tip().content());
+    }
+
+    #[test]
+    fn big_merge_trackers_are_dropped() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let mike = doc.get_or_create_agent_id("mike");
+        for i in 0..50 {
+            doc.oplog.add_insert_at(seph, &[], 0, &i.to_string());
+        }
+        doc.oplog.add_insert_at(mike, &[], 0, "x");
+
+        let mut branch = ListBranch::new();
+        branch.enable_merge_context();
+        branch.merge_context.max_leaves = Some(1);
+        branch.merge(&doc.oplog, doc.oplog.local_frontier_ref());
+        assert!(!branch.merge_context.is_cached());
+        assert_eq!(branch.content(), doc.oplog.checkout_tip().content());
+    }
+}
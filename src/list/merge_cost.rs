@@ -0,0 +1,122 @@
+//! Estimating how expensive a merge will be before doing it.
+//!
+//! Merging concurrent changes means replaying the part of the history where the branches
+//! diverged (the *conflict window*). Usually this is tiny, but merging a long-lived fork can take
+//! a while. Interactive applications can check the cost first, and move expensive merges to a
+//! background thread.
+
+use rle::HasLength;
+use crate::LV;
+use crate::causalgraph::graph::conflict_subgraph::ConflictSubgraph;
+use crate::list::{ListOpLog, M1PlanAction};
+use crate::listmerge::plan::M1EntryState;
+
+/// The result of [`ListOpLog::estimate_merge_cost`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct MergeCost {
+    /// The number of operations being merged in.
+    pub new_ops: usize,
+
+    /// The number of operations in the part of the history the merge needs to walk through. This
+    /// includes the new operations, and every operation since the branches diverged.
+    pub conflict_window: usize,
+
+    /// The number of times the merge needs to retreat and advance through operations its already
+    /// seen. Lots of these mean the history has a lot of interleaved concurrent edits.
+    pub retreats: usize,
+    pub advances: usize,
+
+    /// An estimate of the total number of operations the merge will process. See
+    /// [`M1Plan::estimated_ops`](crate::list::M1Plan::estimated_ops).
+    pub estimated_ops: usize,
+}
+
+impl ListOpLog {
+    /// Estimate the cost of merging the changes in `merge_frontier` into a branch at version
+    /// `from`. This only looks at the causal graph, so its much cheaper than the merge itself.
+    ///
+    /// If `max_window` is set and the conflict window is bigger than that, this gives up early
+    /// and returns None.
+    pub fn estimate_merge_cost(&self, from: &[LV], merge_frontier: &[LV], max_window: Option<usize>) -> Option<MergeCost> {
+        let graph = &self.cg.graph;
+        let merge_frontier = self.expand_to_transactions(merge_frontier);
+        let merge_frontier = merge_frontier.as_ref();
+        if graph.frontier_contains_frontier(from, merge_frontier) {
+            return Some(MergeCost::default());
+        }
+
+        let sg: ConflictSubgraph<M1EntryState> = graph.make_conflict_graph_between_max(from, merge_frontier, max_window)?;
+        let conflict_window: usize = sg.entries.iter().map(|e| e.span.len()).sum();
+
+        let (plan, _) = sg.make_m1_plan(Some(&self.operations), true);
+        let mut cost = MergeCost {
+            new_ops: graph.diff(from, merge_frontier).1.iter().map(|r| r.len()).sum(),
+            conflict_window,
+            estimated_ops: plan.estimated_ops(),
+            ..Default::default()
+        };
+        for a in plan.actions() {
+            match a {
+                M1PlanAction::Retreat(_) => cost.retreats += 1,
+                M1PlanAction::Advance(_) => cost.advances += 1,
+                _ => {}
+            }
+        }
+        Some(cost)
+    }
+
+    /// Find the size of the conflict window for merging `merge_frontier` into a branch at version
+    /// `from`. (See [`MergeCost::conflict_window`]). This is cheaper than
+    /// [`estimate_merge_cost`](ListOpLog::estimate_merge_cost), since it doesn't plan the merge.
+    ///
+    /// If `max_window` is set and the conflict window is bigger than that, this gives up early
+    /// and returns None.
+    pub fn merge_conflict_window(&self, from: &[LV], merge_frontier: &[LV], max_window: Option<usize>) -> Option<usize> {
+        let graph = &self.cg.graph;
+        let merge_frontier = self.expand_to_transactions(merge_frontier);
+        let merge_frontier = merge_frontier.as_ref();
+        if graph.frontier_contains_frontier(from, merge_frontier) { return Some(0); }
+
+        let sg: ConflictSubgraph = graph.make_conflict_graph_between_max(from, merge_frontier, max_window)?;
+        Some(sg.entries.iter().map(|e| e.span.len()).sum())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+    use super::MergeCost;
+
+    #[test]
+    fn merge_costs() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hello"); // 0..5
+        let base = oplog.cg.version.clone();
+        oplog.add_insert(seph, 5, " world"); // 5..11
+        let a = oplog.cg.version.clone();
+        oplog.add_insert_at(mike, base.as_ref(), 0, "oh "); // 11..14
+        let tip = oplog.cg.version.clone();
+
+        assert_eq!(oplog.estimate_merge_cost(tip.as_ref(), a.as_ref(), None), Some(MergeCost::default()));
+
+        // Fast forwarding doesn't need to walk through any concurrent operations.
+        let ff = oplog.estimate_merge_cost(base.as_ref(), a.as_ref(), None).unwrap();
+        assert_eq!(ff.new_ops, 6);
+        assert_eq!(ff.retreats, 0);
+
+        let cost = oplog.estimate_merge_cost(a.as_ref(), tip.as_ref(), None).unwrap();
+        assert_eq!(cost.new_ops, 3);
+        assert_eq!(cost.conflict_window, 9);
+        assert!(cost.retreats > 0);
+        assert!(cost.estimated_ops >= 9);
+
+        assert_eq!(oplog.estimate_merge_cost(a.as_ref(), tip.as_ref(), Some(5)), None);
+        assert_eq!(oplog.estimate_merge_cost(a.as_ref(), tip.as_ref(), Some(9)), Some(cost));
+
+        assert_eq!(oplog.merge_conflict_window(tip.as_ref(), a.as_ref(), None), Some(0));
+        assert_eq!(oplog.merge_conflict_window(a.as_ref(), tip.as_ref(), None), Some(9));
+        assert_eq!(oplog.merge_conflict_window(a.as_ref(), tip.as_ref(), Some(5)), None);
+    }
+}
@@ -0,0 +1,205 @@
+//! Merge dry runs. This runs the merge algorithm without modifying anything, and reports where the
+//! merged changes conflict with each other. Text CRDTs always merge cleanly - but applications
+//! might still want to tell users when someone else was editing the same text they were.
+
+use rle::{AppendRle, HasLength};
+use crate::{DTRange, LV};
+use crate::causalgraph::graph::Graph;
+use crate::list::{ListBranch, ListOpLog};
+use crate::listmerge::merge::{TransformedOpsIterRaw, TransformedResultRaw};
+use crate::rev_range::RangeRev;
+
+/// The result of [`ListBranch::merge_dry_run`]. All ranges are local versions.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct MergeReport {
+    /// Pairs of inserts which were made concurrently at the same location in the document. Their
+    /// relative order in the merged document is picked based on the agents' names.
+    pub concurrent_inserts: Vec<(DTRange, DTRange)>,
+
+    /// (insert, delete) pairs where the insert was made next to some text, and that text was
+    /// concurrently deleted.
+    pub edited_and_deleted: Vec<(DTRange, DTRange)>,
+
+    /// Deletes of text which had already been concurrently deleted.
+    pub deleted_twice: Vec<DTRange>,
+}
+
+impl MergeReport {
+    pub fn has_conflicts(&self) -> bool {
+        !self.concurrent_inserts.is_empty()
+            || !self.edited_and_deleted.is_empty()
+            || !self.deleted_twice.is_empty()
+    }
+}
+
+impl ListOpLog {
+    /// Find the conflicts which would happen merging `merging` into a document at version `from`.
+    /// Only conflicts involving at least one change which isn't in `from` are reported.
+    pub fn merge_report(&self, from: &[LV], merging: &[LV]) -> MergeReport {
+        let graph = &self.cg.graph;
+        let merging = self.expand_to_transactions(merging);
+        let mut iter = TransformedOpsIterRaw::new(graph, &self.cg.agent_assignment,
+                                                  &self.operation_ctx, &self.operations,
+                                                  from, merging.as_ref());
+        iter.log_conflicts();
+
+        let mut report = MergeReport::default();
+        for result in &mut iter {
+            if let TransformedResultRaw::DeleteAlreadyHappened(range) = result {
+                report.deleted_twice.push_rle(range);
+            }
+        }
+        let log = iter.take_conflict_log();
+
+        let is_new = |v: LV| !graph.frontier_contains_version(from, v);
+
+        report.concurrent_inserts = log.concurrent_inserts.into_iter()
+            .filter(|(a, b)| is_new(a.start) || is_new(b.start))
+            .collect();
+        report.concurrent_inserts.dedup();
+
+        let mut runs: Vec<(DTRange, DTRange)> = vec![];
+        for (ins, del) in log.neighbour_deletes {
+            for_each_conflict(graph, from, ins, del, |ins, del| {
+                if del.fwd {
+                    runs.push((ins, del.span));
+                } else {
+                    // The deletes go backwards, so each pair is its own run.
+                    runs.extend((ins.start..ins.end).zip((del.span.start..del.span.end).rev())
+                        .map(|(i, d)| ((i..i + 1).into(), (d..d + 1).into())));
+                }
+            });
+        }
+        runs.sort_unstable_by_key(|(i, d)| (i.start, d.start));
+        for (ins, del) in runs {
+            match report.edited_and_deleted.last_mut() {
+                Some((i, d)) if i.end == ins.start && d.end == del.start => {
+                    i.end = ins.end;
+                    d.end = del.end;
+                }
+                _ => report.edited_and_deleted.push((ins, del)),
+            }
+        }
+
+        report
+    }
+}
+
+/// Split a run of (insert, delete) pairs from the conflict log into pieces which are either all
+/// conflicts or not, and call `f` with the conflicting pieces. A pair conflicts if the insert and
+/// delete are concurrent, and at least one of them isn't in `from`.
+///
+/// Within a single graph entry on each side, each of those checks changes at most once along the
+/// run. So the pieces are found with a binary search rather than by checking every pair.
+fn for_each_conflict<F: FnMut(DTRange, RangeRev)>(graph: &Graph, from: &[LV], ins: DTRange, del: RangeRev, mut f: F) {
+    let del_at = |k: LV| if del.fwd { del.span.start + k } else { del.span.end - 1 - k };
+    let state = |k: LV| {
+        let (i, d) = (ins.start + k, del_at(k));
+        (
+            !graph.frontier_contains_version(from, i),
+            !graph.frontier_contains_version(from, d),
+            graph.version_cmp(i, d),
+        )
+    };
+
+    let len = ins.len() as LV;
+    let mut start = 0;
+    while start < len {
+        let (i, d) = (ins.start + start, del_at(start));
+        let ins_entry = graph.entries.find_packed(i).span;
+        let del_entry = graph.entries.find_packed(d).span;
+        let entry_end = start + (ins_entry.end - i).min(if del.fwd {
+            del_entry.end - d
+        } else {
+            d + 1 - del_entry.start
+        });
+
+        // Find the first pair where the state changes.
+        let s = state(start);
+        let (mut lo, mut hi) = (start + 1, entry_end.min(len));
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if state(mid) == s { lo = mid + 1; } else { hi = mid; }
+        }
+
+        let (ins_new, del_new, cmp) = s;
+        if (ins_new || del_new) && cmp.is_none() {
+            let span = if del.fwd {
+                (del.span.start + start..del.span.start + lo).into()
+            } else {
+                (del.span.end - lo..del.span.end - start).into()
+            };
+            f((ins.start + start..ins.start + lo).into(), RangeRev { span, fwd: del.fwd });
+        }
+        start = lo;
+    }
+}
+
+impl ListBranch {
+    /// Find where merging `merge_frontier` into this branch would cause conflicts, without
+    /// modifying the branch. See [`MergeReport`].
+    ///
+    /// This does about as much work as actually merging the changes.
+    pub fn merge_dry_run(&self, oplog: &ListOpLog, merge_frontier: &[LV]) -> MergeReport {
+        oplog.merge_report(self.version.as_ref(), merge_frontier)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListOpLog};
+    use crate::list::gen_random::gen_oplog;
+    use super::MergeReport;
+
+    #[test]
+    fn dry_run_reports_conflicts() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "abc"); // 0..3
+
+        // Concurrent inserts at the same location.
+        oplog.add_insert_at(seph, &[2], 1, "X"); // 3
+        oplog.add_insert_at(mike, &[2], 1, "Y"); // 4
+        // Mike types after b, while seph deletes it.
+        oplog.add_insert_at(mike, &[4], 3, "Z"); // 5
+        oplog.add_delete_at(seph, &[3], 2..3); // 6
+        // Both delete c.
+        oplog.add_delete_at(seph, &[6], 2..3); // 7
+        oplog.add_delete_at(mike, &[5], 4..5); // 8
+
+        let mut branch = ListBranch::new();
+        branch.merge(&oplog, &[7]);
+        let before = branch.clone();
+        let report = branch.merge_dry_run(&oplog, oplog.local_frontier_ref());
+        assert_eq!(branch, before);
+
+        assert_eq!(report.concurrent_inserts, [((4..5).into(), (3..4).into())]);
+        assert_eq!(report.edited_and_deleted, [
+            ((4..5).into(), (6..7).into()), // Y was inserted before b.
+            ((5..6).into(), (6..7).into()),
+            ((5..6).into(), (7..8).into()), // Z was inserted before c.
+        ]);
+        assert_eq!(report.deleted_twice, [(8..9).into()]);
+        assert!(report.has_conflicts());
+
+        // Changes made after the branch's version don't conflict.
+        let mut branch = ListBranch::new();
+        branch.merge(&oplog, &[1]);
+        assert_eq!(branch.merge_dry_run(&oplog, &[2]), MergeReport::default());
+        // Nor does merging a version the branch already has.
+        branch.merge(&oplog, oplog.local_frontier_ref());
+        assert!(!branch.merge_dry_run(&oplog, oplog.local_frontier_ref()).has_conflicts());
+    }
+
+    #[test]
+    fn dry_run_random_oplogs() {
+        for seed in 0..20 {
+            let oplog = gen_oplog(seed, 30, false, true);
+            let report = ListBranch::new().merge_dry_run(&oplog, oplog.local_frontier_ref());
+            for (a, b) in report.concurrent_inserts {
+                assert_eq!(oplog.cg.graph.version_cmp(a.start, b.start), None);
+            }
+        }
+    }
+}
@@ -2,15 +2,21 @@
 //!
 //! Some code in here will be moved out when diamond types supports more data structures.
 //!
-//! Currently this code only supports lists of unicode characters (text documents). Support for
-//! more data types will be added over time.
+//! Most of this code is for lists of unicode characters (text documents). Lists of other values
+//! are supported via [`ValueList`].
 
 use smartstring::alias::String as SmartString;
 
 use crate::list::operation::ListOpKind;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
-use crate::{CausalGraph, Frontier};
+use crate::{CausalGraph, DTRange, Frontier};
 use crate::rle::{KVPair, RleVec};
+use crate::listmerge::rewind::RewindCache;
+use crate::listmerge::merge_context::MergeContext;
+use crate::list::composition::Composition;
+use crate::list::line_index::LineCache;
+use crate::list::events::EventBuffer;
+use crate::list::anchors::AnchorSet;
 
 pub mod operation;
 mod list;
@@ -24,21 +30,108 @@ pub mod encoding;
 pub mod op_metrics;
 mod eq;
 mod oplog_merge;
+mod attribution;
+mod conflict_estimate;
+mod coalesce;
+mod filter_agents;
+mod composition;
+mod squash;
+mod history_hash;
+mod value_list;
+mod line_index;
+mod transactions;
+mod events;
+pub mod adapters;
+mod presence;
+mod mem_usage;
+mod signatures;
+mod doc_id;
+mod snapshot;
+mod shared;
+mod text_sink;
+mod graph_export;
+mod gc;
+mod merge_report;
+mod op_metadata;
+mod checkpoints;
+mod named_branches;
+mod transformed_positions;
+mod thin_client;
+mod redact;
+mod analytics;
+mod agent_info;
+mod agent_seq;
+mod pending;
+mod op_stream;
+mod shallow;
+mod rebase;
+mod position_map;
+mod anchors;
+mod canonical;
+mod text_diff;
+mod merge_cost;
+mod subhistory;
+mod collab_string;
+mod compact;
 
-#[cfg(any(test, feature = "gen_test_data"))]
-mod old_fuzzer_tools;
+#[cfg(any(test, feature = "testing"))]
+pub(crate) mod old_fuzzer_tools;
 #[cfg(test)]
 mod oplog_merge_fuzzer;
 
 pub(crate) mod buffered_iter;
+pub(crate) mod content_buffer;
 mod stochastic_summary;
 mod merge;
+#[cfg(feature = "rayon")]
+mod par_merge;
+#[cfg(feature = "dag_hash")]
+mod dag_hash;
 
-#[cfg(feature = "gen_test_data")]
-mod gen_random;
+#[cfg(any(test, feature = "testing"))]
+pub(crate) mod gen_random;
 #[cfg(feature = "gen_test_data")]
 pub use gen_random::gen_oplog;
 
+pub use conflict_estimate::ConflictEstimate;
+pub use coalesce::OpCoalescer;
+pub use squash::SQUASH_AGENT;
+pub(crate) use squash::is_squash_agent;
+pub use history_hash::content_hash;
+pub use value_list::{MoveError, ValueList, ValueListPatch};
+pub use line_index::{LineCol, LineColEdit};
+pub use branch::BranchChars;
+pub use events::BranchEvent;
+pub use presence::{Presence, PresenceSet};
+pub use merge::MergeTask;
+pub use mem_usage::MemUsage;
+pub use signatures::{OpSigner, SignatureVerifier, SignedRange, SignError};
+pub use doc_id::{ForkPoint, random_doc_id};
+pub use snapshot::{CowOpLog, ReadOnlyOpLog};
+pub use shared::SharedListCRDT;
+pub use text_sink::{SinkBranch, TextSink};
+pub use graph_export::{GraphExportOptions, GraphFormat};
+pub use merge_report::MergeReport;
+pub use op_metadata::{OpMetadata, MAX_OP_METADATA_LEN};
+pub use checkpoints::Checkpoint;
+pub use thin_client::{ThinClient, ThinClientError};
+pub use redact::PLACEHOLDER_CHAR;
+pub use analytics::{AgentStats, GrowthSample, HistoryStats};
+pub use agent_info::AgentInfo;
+pub use agent_seq::{SeqCollision, SeqReservation};
+pub use pending::{AddPatchesError, DEFAULT_MAX_PENDING_BYTES, DEFAULT_MAX_PENDING_PATCHES, PendingPatch};
+pub use op_stream::OpStream;
+pub use position_map::PositionMap;
+pub use anchors::{AnchorBias, AnchorId, SavedAnchor};
+pub use text_diff::diff_lines;
+pub use crate::listmerge::plan::{M1Plan, M1PlanAction};
+pub use merge_cost::MergeCost;
+pub use collab_string::CollabString;
+pub use compact::{CompactListOpLog, DEFAULT_MAX_COMPACT_BYTES};
+#[cfg(feature = "dag_hash")]
+pub use dag_hash::VersionHash;
+pub use crate::causalgraph::agent_assignment::TieBreak;
+
 // TODO!
 // trait InlineReplace<T> {
 //     fn insert(pos: usize, vals: &[T]);
@@ -78,6 +171,27 @@ pub struct ListBranch {
 
     /// The document's content.
     content: jumprope::JumpRopeBuf,
+
+    /// Lazily created when the branch is moved backwards in time via
+    /// [`rewind_to`](ListBranch::rewind_to).
+    rewind: RewindCache,
+
+    /// The tracker from the last merge, if enabled. See
+    /// [`enable_merge_context`](ListBranch::enable_merge_context).
+    merge_context: MergeContext,
+
+    /// The active IME composition session, if any. See
+    /// [`begin_composition`](ListBranch::begin_composition).
+    composition: Option<Box<Composition>>,
+
+    /// The line index, if enabled. See [`enable_line_index`](ListBranch::enable_line_index).
+    lines: LineCache,
+
+    /// Buffered change events, if enabled. See [`enable_events`](ListBranch::enable_events).
+    events: EventBuffer,
+
+    /// See [`add_anchor`](ListBranch::add_anchor).
+    anchors: AnchorSet,
 }
 
 /// An OpLog is a collection of Diamond Types operations, stored in a super fancy compact way. Each
@@ -113,6 +227,9 @@ pub struct ListOpLog {
     /// Optional - only used if you set it.
     doc_id: Option<SmartString>,
 
+    /// The documents this document was forked from. See [`fork_document`](ListOpLog::fork_document).
+    pub(crate) fork_points: Vec<doc_id::ForkPoint>,
+
     pub cg: CausalGraph,
 
     /// This contains all content ever inserted into the document, in time order (not document
@@ -121,6 +238,40 @@ pub struct ListOpLog {
     // TODO: Replace me with a compact form of this data.
     pub(crate) operations: RleVec<KVPair<ListOpMetrics>>,
 
+    /// Atomic transactions. Sorted and non-overlapping. See
+    /// [`add_transaction`](ListOpLog::add_transaction).
+    pub(crate) transactions: Vec<DTRange>,
+
+    /// Signatures over ranges of operations. See [`sign_operations`](ListOpLog::sign_operations).
+    pub(crate) signatures: Vec<signatures::SignedRange>,
+
+    /// Timestamps and other metadata for runs of operations. Sorted and non-overlapping. See
+    /// [`set_op_metadata`](ListOpLog::set_op_metadata).
+    pub(crate) op_metadata: Vec<(DTRange, OpMetadata)>,
+
+    /// Named versions. See [`add_checkpoint`](ListOpLog::add_checkpoint).
+    pub(crate) checkpoints: Vec<Checkpoint>,
+
+    /// Named versions which can be moved. See [`create_branch`](ListOpLog::create_branch).
+    pub(crate) named_branches: named_branches::NamedBranches,
+
+    /// Metadata about agents, by agent name. See [`set_agent_info`](ListOpLog::set_agent_info).
+    pub(crate) agent_info: agent_info::AgentInfoMap,
+
+    /// Transformed positions loaded from a file, used to speed up checkouts. See the
+    /// transformed_positions module.
+    pub(crate) transformed_positions: Option<transformed_positions::TransformedPositions>,
+
+    /// Patches waiting for their parents to arrive. See [`apply_or_queue`](ListOpLog::apply_or_queue).
+    pub(crate) pending: pending::PendingQueue,
+
+    /// The IDs of operations removed by [`shallow_clone`](ListOpLog::shallow_clone).
+    pub(crate) pruned: shallow::PrunedHistory,
+
+    /// Cached operation hashes. See [`hash_for_version`](ListOpLog::hash_for_version).
+    #[cfg(feature = "dag_hash")]
+    pub(crate) hash_cache: dag_hash::HashCache,
+
     // /// This is the LocalVersion for the entire oplog. So, if you merged every change we store into
     // /// a branch, this is the version of that branch.
     // ///
@@ -0,0 +1,163 @@
+//! Named branches.
+//!
+//! The oplog can store a small table of named versions, like branches in git. Unlike
+//! [checkpoints](crate::list::Checkpoint), named branches can be moved. This is useful for things
+//! like draft / published workflows, where the published version of a document lags behind the
+//! latest edits.
+//!
+//! Named branches are stored with the oplog and sent to other peers. Each branch has an edit
+//! count, which goes up every time the branch is created, moved or deleted. When a peer receives a
+//! branch it already has, the entry with the higher edit count wins (last writer wins). Deleted
+//! branches are kept as tombstones, so deletes are synced too. Concurrent changes with the same
+//! edit count are resolved the same way on every peer - deletes win, then the later version. (See
+//! [`version_sort_key`](ListOpLog::version_sort_key)).
+
+use std::collections::BTreeMap;
+use smartstring::alias::String as SmartString;
+use crate::{Frontier, LV};
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::oplog::VersionSortKey;
+
+/// A named branch, or the tombstone of a deleted one.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct NamedBranch {
+    /// The number of times the branch has been changed. See the module documentation.
+    pub(crate) edit: usize,
+    /// The branch's version, or None if the branch was deleted.
+    pub(crate) version: Option<Frontier>,
+}
+
+pub(crate) type NamedBranches = BTreeMap<SmartString, NamedBranch>;
+
+impl ListOpLog {
+    fn check_known_version(&self, version: &[LV]) -> Frontier {
+        assert!(version.iter().all(|v| *v < self.len()), "Unknown version");
+        self.cg.graph.find_dominators(version)
+    }
+
+    /// Set a branch's version (or delete it), bumping its edit count.
+    fn set_branch(&mut self, name: &str, version: Option<Frontier>) {
+        let edit = self.named_branches.get(name).map_or(0, |b| b.edit + 1);
+        self.named_branches.insert(name.into(), NamedBranch { edit, version });
+    }
+
+    /// Create a new named branch at `version`. Returns false (and does nothing) if a branch with
+    /// this name already exists.
+    ///
+    /// Panics if the version contains unknown operations.
+    pub fn create_branch(&mut self, name: &str, version: &[LV]) -> bool {
+        let version = self.check_known_version(version);
+        if self.branch_version(name).is_some() { return false; }
+        self.set_branch(name, Some(version));
+        true
+    }
+
+    /// Move a named branch to a different version. Returns false if the branch doesn't exist.
+    ///
+    /// Panics if the version contains unknown operations.
+    pub fn move_branch(&mut self, name: &str, version: &[LV]) -> bool {
+        let version = self.check_known_version(version);
+        if self.branch_version(name).is_none() { return false; }
+        self.set_branch(name, Some(version));
+        true
+    }
+
+    /// Delete a named branch. Returns the branch's version, if it existed.
+    pub fn delete_branch(&mut self, name: &str) -> Option<Frontier> {
+        let version = self.branch_version(name)?.clone();
+        self.set_branch(name, None);
+        Some(version)
+    }
+
+    /// Get the version of a named branch.
+    pub fn branch_version(&self, name: &str) -> Option<&Frontier> {
+        self.named_branches.get(name)?.version.as_ref()
+    }
+
+    /// Iterate through all named branches, sorted by name.
+    pub fn iter_branches(&self) -> impl Iterator<Item = (&str, &Frontier)> + '_ {
+        self.named_branches.iter()
+            .filter_map(|(name, b)| Some((name.as_str(), b.version.as_ref()?)))
+    }
+
+    /// Check out the document at a named branch's version.
+    pub fn checkout_branch(&self, name: &str) -> Option<ListBranch> {
+        self.branch_version(name).map(|v| self.checkout(v.as_ref()))
+    }
+
+    /// The order of concurrent changes to a branch. This is the same on every peer.
+    fn branch_order(&self, branch: &NamedBranch) -> (usize, bool, VersionSortKey<'_>) {
+        let version = branch.version.as_ref().map_or(&[][..], |v| v.as_ref());
+        (branch.edit, branch.version.is_none(), self.version_sort_key(version))
+    }
+
+    /// Add a branch received from a remote peer. If we already have a branch with the same name,
+    /// the most recent change wins. See the module documentation.
+    pub(crate) fn merge_named_branch(&mut self, name: SmartString, branch: NamedBranch) {
+        if let Some(existing) = self.named_branches.get(&name) {
+            if self.branch_order(existing) >= self.branch_order(&branch) { return; }
+        }
+        self.named_branches.insert(name, branch);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn named_branches() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi");
+        assert!(oplog.create_branch("published", &[1]));
+        assert!(oplog.create_branch("draft", &[1]));
+        assert!(!oplog.create_branch("draft", &[]));
+        oplog.add_insert(seph, 2, " there");
+        assert!(oplog.move_branch("draft", &[7]));
+        assert!(!oplog.move_branch("nope", &[7]));
+
+        assert_eq!(oplog.checkout_branch("published").unwrap().content(), "hi");
+        assert_eq!(oplog.checkout_branch("draft").unwrap().content(), "hi there");
+        assert!(oplog.checkout_branch("nope").is_none());
+        assert_eq!(oplog.iter_branches().map(|(n, _)| n).collect::<Vec<_>>(), ["draft", "published"]);
+
+        let data = oplog.encode(&EncodeOptions::default());
+        let mut remote = ListOpLog::load_from(&data).unwrap();
+        assert_eq!(remote.iter_branches().collect::<Vec<_>>(), oplog.iter_branches().collect::<Vec<_>>());
+
+        // The most recent change wins.
+        let mike = remote.get_or_create_agent_id("mike");
+        remote.add_insert_at(mike, &[1], 2, "!");
+        remote.move_branch("published", &[8]);
+        assert_eq!(oplog.delete_branch("draft").unwrap().as_ref(), &[7]);
+        oplog.decode_and_add(&remote.encode_from(&EncodeOptions::patch(), &[7])).unwrap();
+        assert_eq!(oplog.branch_version("published").unwrap().as_ref(), &[8]);
+        assert!(oplog.branch_version("draft").is_none());
+        assert!(!oplog.move_branch("draft", &[1]));
+
+        // Deletes are synced.
+        remote.decode_and_add(&oplog.encode_from(&EncodeOptions::patch(), &[7, 8])).unwrap();
+        assert!(remote.branch_version("draft").is_none());
+        assert_eq!(remote.iter_branches().collect::<Vec<_>>(), oplog.iter_branches().collect::<Vec<_>>());
+
+        // Moves backwards aren't undone by merging.
+        remote.move_branch("published", &[7]);
+        oplog.decode_and_add(&remote.encode_from(&EncodeOptions::patch(), &[7, 8])).unwrap();
+        assert_eq!(oplog.branch_version("published").unwrap().as_ref(), &[7]);
+
+        // Concurrent changes converge.
+        assert!(oplog.create_branch("draft", &[1]));
+        assert!(remote.create_branch("draft", &[8]));
+        oplog.move_branch("published", &[1]);
+        remote.delete_branch("published");
+        let a = oplog.encode_from(&EncodeOptions::patch(), &[7, 8]);
+        let b = remote.encode_from(&EncodeOptions::patch(), &[7, 8]);
+        oplog.decode_and_add(&b).unwrap();
+        remote.decode_and_add(&a).unwrap();
+        assert_eq!(remote.iter_branches().collect::<Vec<_>>(), oplog.iter_branches().collect::<Vec<_>>());
+        assert!(oplog.branch_version("published").is_none());
+        assert_eq!(oplog.branch_version("draft").unwrap().as_ref(), &[8]);
+    }
+}
@@ -42,7 +42,6 @@ pub(crate) fn old_make_random_change_raw(oplog: &mut ListOpLog, branch: &ListBra
         let pos = rng.gen_range(0..doc_len);
         // println!("range {}", u32::min(10, doc_len - pos));
         let span = rng.gen_range(1..=usize::min(10, doc_len - pos));
-        // dbg!(&state.marker_tree, pos, len);
         // Sometimes deletes happen backwards - ie, via hitting backspace a bunch of times.
         let fwd = span == 1 || rng.gen_bool(0.5);
 
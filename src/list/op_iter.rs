@@ -3,7 +3,7 @@ use smallvec::SmallVec;
 use rle::{AppendRle, HasLength, MergableSpan, SplitableSpan, SplitableSpanCtx, SplitableSpanHelpers};
 use rle::zip::rle_zip3;
 
-use crate::{Frontier, LV};
+use crate::{Frontier, lv_to_usize, LV};
 use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionSpan;
 use crate::causalgraph::agent_span::AgentSpan;
 use crate::causalgraph::graph::GraphEntrySimple;
@@ -53,12 +53,12 @@ impl<'a> Iterator for OpMetricsIter<'a> {
         let KVPair(mut start, mut c) = self.list[self.idx].clone();
         if start >= self.range.end { return None; }
 
-        if start + c.len() > self.range.end {
-            c.truncate_ctx(self.range.end - start, self.ctx);
+        if start + c.len() as LV > self.range.end {
+            c.truncate_ctx(lv_to_usize(self.range.end - start), self.ctx);
         }
 
         if start < self.range.start {
-            c.truncate_keeping_right_ctx(self.range.start - start, self.ctx);
+            c.truncate_keeping_right_ctx(lv_to_usize(self.range.start - start), self.ctx);
             start = self.range.start;
         }
 
@@ -209,7 +209,7 @@ impl SplitableSpanHelpers for FullEntry {
 
         let mut result = Self {
             span: self.span.truncate_h(at),
-            parents: Frontier::new_1(self.span.start + at - 1),
+            parents: Frontier::new_1(self.span.start + at as LV - 1),
             agent_span: self.agent_span.truncate_h(at),
             ops: Default::default(),
         };
@@ -327,8 +327,9 @@ mod test {
         }));
 
         let ctx = ListOperationCtx {
-            ins_content: "0123456789".to_string().into_bytes(),
-            del_content: "".to_string().into_bytes()
+            ins_content: "0123456789".into(),
+            del_content: "".into(),
+            ..Default::default()
         };
 
         assert_eq!(OpMetricsIter::new(&ops, &ctx, (0..30).into()).collect::<Vec<_>>(), ops.0.as_slice());
@@ -0,0 +1,186 @@
+//! Timestamps and other metadata attached to operations.
+//!
+//! Diamond types doesn't need to know when operations were made - but audit logs and history UIs
+//! do. Applications can attach a wall clock time and a few bytes of their own data (like a device
+//! ID) to runs of operations. Metadata is stored alongside the operations when the oplog is encoded
+//! (unless [`EncodeOptions::store_op_metadata`](crate::list::encoding::EncodeOptions::store_op_metadata)
+//! is turned off).
+//!
+//! Metadata is informational only. Its not part of the operations themselves, and clocks on
+//! different devices disagree - so don't use timestamps to order anything.
+
+use std::ops::Range;
+use rle::{AppendRle, HasLength};
+use crate::{DTRange, LV};
+use crate::list::ListOpLog;
+
+/// The maximum size of [`OpMetadata::data`].
+pub const MAX_OP_METADATA_LEN: usize = 256;
+
+/// Metadata attached to a run of operations.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct OpMetadata {
+    /// When the operations were made, in milliseconds since the unix epoch.
+    pub timestamp: Option<u64>,
+    /// Any other (small) application data, like the ID of the device which made the changes. Must
+    /// be at most [`MAX_OP_METADATA_LEN`] bytes.
+    pub data: Vec<u8>,
+}
+
+impl OpMetadata {
+    pub fn at_time(timestamp: u64) -> Self {
+        Self { timestamp: Some(timestamp), data: vec![] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timestamp.is_none() && self.data.is_empty()
+    }
+}
+
+impl ListOpLog {
+    /// Attach metadata to the operations in `range`, replacing any metadata they had before.
+    /// Setting empty metadata removes it.
+    ///
+    /// Panics if the range contains unknown operations or the metadata's data is too big.
+    pub fn set_op_metadata(&mut self, range: DTRange, meta: OpMetadata) {
+        assert!(range.end <= self.len(), "Cannot set metadata of unknown operations");
+        assert!(meta.data.len() <= MAX_OP_METADATA_LEN, "Operation metadata is too big");
+        if range.is_empty() { return; }
+
+        // Entries in start_idx..end_idx overlap the range.
+        let start_idx = self.op_metadata.partition_point(|(r, _)| r.end <= range.start);
+        let end_idx = self.op_metadata.partition_point(|(r, _)| r.start < range.end);
+
+        let mut replacement = Vec::with_capacity(3);
+        if start_idx < end_idx {
+            let (r, m) = &self.op_metadata[start_idx];
+            if r.start < range.start {
+                replacement.push(((r.start..range.start).into(), m.clone()));
+            }
+        }
+        if !meta.is_empty() {
+            replacement.push((range, meta));
+        }
+        if start_idx < end_idx {
+            let (r, m) = &self.op_metadata[end_idx - 1];
+            if r.end > range.end {
+                replacement.push(((range.end..r.end).into(), m.clone()));
+            }
+        }
+
+        let replaced_len = replacement.len();
+        self.op_metadata.splice(start_idx..end_idx, replacement);
+
+        // Join runs with the same metadata.
+        let last = (start_idx + replaced_len).min(self.op_metadata.len().saturating_sub(1));
+        for i in (start_idx.max(1)..=last).rev() {
+            let (a, a_meta) = &self.op_metadata[i - 1];
+            let (b, b_meta) = &self.op_metadata[i];
+            if a.end == b.start && a_meta == b_meta {
+                self.op_metadata[i - 1].0.end = self.op_metadata[i].0.end;
+                self.op_metadata.remove(i);
+            }
+        }
+    }
+
+    /// Get the metadata attached to an operation.
+    pub fn op_metadata(&self, v: LV) -> Option<&OpMetadata> {
+        let idx = self.op_metadata.partition_point(|(r, _)| r.end <= v);
+        self.op_metadata.get(idx)
+            .filter(|(r, _)| r.start <= v)
+            .map(|(_, m)| m)
+    }
+
+    /// Iterate through all runs of operations which have metadata, in order.
+    pub fn iter_op_metadata(&self) -> impl Iterator<Item = (DTRange, &OpMetadata)> + '_ {
+        self.op_metadata.iter().map(|(r, m)| (*r, m))
+    }
+
+    /// Find the operations with a timestamp in the window (in milliseconds since the unix epoch).
+    /// Operations without timestamps are never included.
+    pub fn ops_in_time_window(&self, window: Range<u64>) -> Vec<DTRange> {
+        let mut result = vec![];
+        for (r, m) in &self.op_metadata {
+            if m.timestamp.is_some_and(|t| window.contains(&t)) {
+                result.push_rle(*r);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::DTRange;
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::ListOpLog;
+    use super::OpMetadata;
+
+    #[test]
+    fn set_and_query_metadata() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hello world");
+        let r = |a, b| -> DTRange { (a..b).into() };
+
+        oplog.set_op_metadata(r(0, 5), OpMetadata::at_time(1000));
+        oplog.set_op_metadata(r(5, 11), OpMetadata { timestamp: Some(2000), data: b"phone".to_vec() });
+        oplog.set_op_metadata(r(3, 7), OpMetadata::at_time(1000));
+        assert_eq!(oplog.iter_op_metadata().map(|(r, _)| r).collect::<Vec<_>>(), [r(0, 7), r(7, 11)]);
+        assert_eq!(oplog.op_metadata(6), Some(&OpMetadata::at_time(1000)));
+        assert_eq!(oplog.op_metadata(8).unwrap().data, b"phone");
+
+        assert_eq!(oplog.ops_in_time_window(0..1500), [r(0, 7)]);
+        assert_eq!(oplog.ops_in_time_window(0..5000), [r(0, 11)]);
+        assert!(oplog.ops_in_time_window(3000..5000).is_empty());
+
+        oplog.set_op_metadata(r(2, 9), OpMetadata::default());
+        assert_eq!(oplog.iter_op_metadata().map(|(r, _)| r).collect::<Vec<_>>(), [r(0, 2), r(9, 11)]);
+        assert_eq!(oplog.op_metadata(5), None);
+    }
+
+    #[test]
+    fn metadata_round_trips() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "abc");
+        oplog.add_insert_at(mike, &[], 0, "xyz");
+        oplog.add_delete_at(seph, &[2, 5], 0..2);
+        oplog.set_op_metadata((0..3).into(), OpMetadata::at_time(1_700_000_000_000));
+        oplog.set_op_metadata((3..6).into(), OpMetadata { timestamp: Some(1_600_000_000_000), data: vec![1, 2, 3] });
+        oplog.set_op_metadata((6..8).into(), OpMetadata { timestamp: None, data: vec![4] });
+
+        let data = oplog.encode(&EncodeOptions::default());
+        let result = ListOpLog::load_from(&data).unwrap();
+        assert_eq!(result.iter_op_metadata().collect::<Vec<_>>(), oplog.iter_op_metadata().collect::<Vec<_>>());
+
+        // Patches carry metadata too, but it can be stripped.
+        let mut partial = ListOpLog::load_from(&oplog.encode_from(&EncodeOptions::patch(), &[])).unwrap();
+        assert_eq!(partial.op_metadata(4), oplog.op_metadata(4));
+        let stripped = oplog.encode(&EncodeOptions::default().store_op_metadata(false));
+        assert_eq!(ListOpLog::load_from(&stripped).unwrap().iter_op_metadata().count(), 0);
+
+        // Metadata for operations we already know isn't changed.
+        partial.set_op_metadata((0..8).into(), OpMetadata::at_time(5));
+        partial.decode_and_add(&data).unwrap();
+        assert_eq!(partial.op_metadata(7), Some(&OpMetadata::at_time(5)));
+    }
+
+    #[test]
+    fn roll_back_trims_joined_metadata() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "abc");
+        oplog.set_op_metadata((0..3).into(), OpMetadata::at_time(1000));
+
+        let checkpoint = oplog.checkpoint();
+        oplog.add_insert(seph, 3, "def");
+        oplog.set_op_metadata((3..6).into(), OpMetadata::at_time(1000));
+        assert_eq!(oplog.iter_op_metadata().count(), 1);
+
+        oplog.roll_back(checkpoint);
+        assert_eq!(oplog.op_metadata(2), Some(&OpMetadata::at_time(1000)));
+        assert_eq!(oplog.iter_op_metadata().map(|(r, _)| r).collect::<Vec<_>>(), vec![(0..3).into()]);
+    }
+}
@@ -3,9 +3,11 @@ use rle::{HasLength, MergableSpan, SplitableSpan, SplitableSpanCtx};
 use crate::list::operation::{ListOpKind, TextOperation};
 use crate::list::operation::ListOpKind::*;
 use crate::list::switch;
+use crate::list::content_buffer::{ContentBuffer, ContentSpill, SharedBytes};
 use crate::dtrange::DTRange;
 use crate::rev_range::RangeRev;
 use crate::unicount::chars_to_bytes;
+use crate::{lv_to_usize, LV};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
@@ -44,12 +46,12 @@ pub(crate) struct ListOpMetrics {
 impl ListOpMetrics {
     #[inline]
     pub fn start(&self) -> usize {
-        self.loc.span.start
+        lv_to_usize(self.loc.span.start)
     }
 
     #[inline]
     pub fn end(&self) -> usize {
-        self.loc.span.end
+        lv_to_usize(self.loc.span.end)
     }
 
     pub(crate) fn get_content<'a>(&self, ctx: &'a ListOperationCtx) -> Option<&'a str> {
@@ -67,8 +69,8 @@ impl ListOpMetrics {
     pub fn transpose_to(&mut self, xf_pos: usize) {
         let len = self.loc.len();
 
-        self.loc.span.start = xf_pos;
-        self.loc.span.end = xf_pos + len;
+        self.loc.span.start = xf_pos as LV;
+        self.loc.span.end = (xf_pos + len) as LV;
     }
 }
 
@@ -81,8 +83,10 @@ impl HasLength for ListOpMetrics {
 #[derive(Clone, Eq, PartialEq, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) struct ListOperationCtx {
-    pub(crate) ins_content: Vec<u8>,
-    pub(crate) del_content: Vec<u8>,
+    pub(crate) ins_content: ContentBuffer,
+    pub(crate) del_content: ContentBuffer,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) spill: ContentSpill,
 }
 
 // Not using the derived Debug so we can from_utf8 the internal content.
@@ -91,8 +95,8 @@ impl Debug for ListOperationCtx {
         f.debug_struct("ListOperationCtx")
             // We should be able to use from_utf8_unchecked here but its Debug, and I'd rather be
             // safe than sorry.
-            .field("ins_content", &std::str::from_utf8(&self.ins_content).unwrap())
-            .field("del_content", &std::str::from_utf8(&self.del_content).unwrap())
+            .field("ins_content", &self.ins_content)
+            .field("del_content", &self.del_content)
             .finish()
     }
 }
@@ -100,14 +104,15 @@ impl Debug for ListOperationCtx {
 impl ListOperationCtx {
     pub fn new() -> Self {
         Self {
-            ins_content: Vec::new(),
-            del_content: Vec::new()
+            ins_content: ContentBuffer::new(),
+            del_content: ContentBuffer::new(),
+            spill: ContentSpill::default(),
         }
     }
 
     #[inline]
     pub(crate) fn get_str(&self, kind: ListOpKind, range: DTRange) -> &str {
-        unsafe { std::str::from_utf8_unchecked(&self.switch(kind)[range.start..range.end]) }
+        unsafe { std::str::from_utf8_unchecked(self.switch(kind).get(range)) }
     }
 
     // pub(crate) fn switch_str(&self, kind: InsDelTag) -> &str {
@@ -115,21 +120,40 @@ impl ListOperationCtx {
     //     // switch(tag, self.ins_content.as_str(), self.del_content.as_str())
     // }
 
-    pub(crate) fn switch(&self, kind: ListOpKind) -> &[u8] {
+    pub(crate) fn switch(&self, kind: ListOpKind) -> &ContentBuffer {
         switch(kind, &self.ins_content, &self.del_content)
     }
 
-    pub(crate) fn switch_mut(&mut self, kind: ListOpKind) -> &mut Vec<u8> {
+    pub(crate) fn switch_mut(&mut self, kind: ListOpKind) -> &mut ContentBuffer {
         switch(kind, &mut self.ins_content, &mut self.del_content)
     }
 
     pub(crate) fn push_str(&mut self, kind: ListOpKind, s: &str) -> DTRange {
-        let storage = self.switch_mut(kind);
-        let start = storage.len();
-        storage.extend_from_slice(s.as_bytes());
-        let end = storage.len();
+        let buf = self.switch_mut(kind);
+        let num_chunks = buf.num_chunks();
+        let result = buf.push_str(s);
+
+        // The memory limit only needs to be checked when a new chunk has been allocated.
+        if buf.num_chunks() != num_chunks && self.spill.0.is_some() {
+            // If the file can't be written to, content just stays in memory.
+            let _ = self.enforce_memory_limit();
+        }
+        result
+    }
+
+    pub(crate) fn push_shared(&mut self, kind: ListOpKind, data: &SharedBytes, range: std::ops::Range<usize>) -> DTRange {
+        self.switch_mut(kind).push_shared(data, range)
+    }
+
+    pub(crate) fn push_lazy(&mut self, kind: ListOpKind, data: &SharedBytes, range: std::ops::Range<usize>, chars: usize) -> DTRange {
+        self.switch_mut(kind).push_lazy(data, range, chars)
+    }
 
-        (start..end).into()
+    /// Spill content to disk until the memory limit (if any) is met.
+    pub(crate) fn enforce_memory_limit(&mut self) -> std::io::Result<()> {
+        let Some((file, limit)) = self.spill.0.as_ref() else { return Ok(()); };
+        self.ins_content.spill(file, limit.saturating_sub(self.del_content.mem_size()))?;
+        self.del_content.spill(file, limit.saturating_sub(self.ins_content.mem_size()))
     }
 }
 
@@ -140,7 +164,7 @@ impl SplitableSpanCtx for ListOpMetrics {
     // reference to the contained data.
     #[inline]
     fn truncate_ctx(&mut self, at: usize, ctx: &Self::Ctx) -> Self {
-        debug_assert!(self.loc.span.start + at <= self.loc.span.end);
+        debug_assert!(at <= self.loc.len());
 
         // Note we can't use self.span.truncate() because it assumes the span is absolute, but
         // actually how the span splits depends on the tag (and some other stuff).
@@ -192,7 +216,8 @@ impl RangeRev {
     // In godbolt these variants all look pretty similar.
     #[inline]
     pub(crate) fn truncate_tagged_span(&mut self, tag: ListOpKind, at: usize) -> RangeRev {
-        let len = self.len();
+        let len = self.len() as LV;
+        let at = at as LV;
 
         let start2 = if self.fwd && tag == Ins {
             self.span.start + at
@@ -206,7 +231,7 @@ impl RangeRev {
         self.span.end = self.span.start + at;
 
         RangeRev {
-            span: DTRange { start: start2, end: start2 + len - at },
+            span: DTRange { start: start2, end: start2 + (len - at) },
             fwd: self.fwd
         }
     }
@@ -227,6 +252,7 @@ impl RangeRev {
     #[inline]
     #[allow(unused)] // FOR NOW...
     pub(crate) fn split_op_span(range: RangeRev, tag: ListOpKind, at: usize) -> (DTRange, DTRange) {
+        let at = at as LV;
         let (start1, start2) = match (range.fwd, tag) {
             (true, Ins) => (range.span.start, range.span.start + at),
             (false, Del) => (range.span.end - at, range.span.start),
@@ -235,7 +261,7 @@ impl RangeRev {
 
         (
             DTRange { start: start1, end: start1 + at },
-            DTRange { start: start2, end: start2 + range.len() - at },
+            DTRange { start: start2, end: start2 + range.len() as LV - at },
         )
     }
 
@@ -276,7 +302,7 @@ impl RangeRev {
         if tag == Del && !self.fwd {
             self.span.start = other.span.start;
         } else {
-            self.span.end += other.span.len();
+            self.span.end += other.span.len() as LV;
         }
     }
 }
@@ -309,6 +335,7 @@ mod test {
     use crate::list::operation::ListOpKind;
     use crate::dtrange::DTRange;
     use crate::rev_range::RangeRev;
+    use crate::LV;
 
     #[test]
     fn internal_op_splitable() {
@@ -317,18 +344,20 @@ mod test {
             kind: ListOpKind::Ins,
             content_pos: Some((0..10).into()),
         }, &ListOperationCtx {
-            ins_content: "0123456789".as_bytes().to_owned(),
-            del_content: "".as_bytes().to_owned()
+            ins_content: "0123456789".into(),
+            del_content: "".into(),
+            ..Default::default()
         });
 
         let s2 = "↯1↯3↯5↯7↯9";
         test_splitable_methods_valid_ctx(ListOpMetrics {
             loc: (10..20).into(),
             kind: ListOpKind::Ins,
-            content_pos: Some((0..s2.len()).into()),
+            content_pos: Some((0..s2.len() as LV).into()),
         }, &ListOperationCtx {
-            ins_content: s2.as_bytes().to_owned(), // too easy? Maybe..
-            del_content: "".as_bytes().to_owned()
+            ins_content: s2.into(), // too easy? Maybe..
+            del_content: "".into(),
+            ..Default::default()
         });
 
         // I can't test the other splitablespan variants like this because they don't support
@@ -346,8 +375,9 @@ mod test {
 
         // let rem = op.truncate(2, "abcde");
         let rem = op.truncate_ctx(2, &ListOperationCtx {
-            ins_content: "".as_bytes().to_owned(),
-            del_content: "abcde".as_bytes().to_owned()
+            ins_content: "".into(),
+            del_content: "abcde".into(),
+            ..Default::default()
         });
 
         assert_eq!(op, ListOpMetrics {
@@ -369,14 +399,15 @@ mod test {
     fn split_around_unicode() {
         // The ¥ symbol is a 2-byte encoding. And ↯ is 3 bytes.
         let ctx = ListOperationCtx {
-            ins_content: "¥123↯".as_bytes().to_owned(),
-            del_content: "¥123↯".as_bytes().to_owned()
+            ins_content: "¥123↯".into(),
+            del_content: "¥123↯".into(),
+            ..Default::default()
         };
 
         let op = ListOpMetrics {
             loc: (10..15).into(),
             kind: ListOpKind::Ins,
-            content_pos: Some((0..ctx.ins_content.len()).into())
+            content_pos: Some((0..ctx.ins_content.end() as LV).into())
         };
 
         let (a, b) = op.split_ctx(1, &ctx);
@@ -388,7 +419,7 @@ mod test {
         assert_eq!(b, ListOpMetrics {
             loc: (11..15).into(),
             kind: ListOpKind::Ins,
-            content_pos: Some((2..ctx.ins_content.len()).into())
+            content_pos: Some((2..ctx.ins_content.end() as LV).into())
         });
     }
 
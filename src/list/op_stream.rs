@@ -0,0 +1,130 @@
+//! Polling for new operations.
+//!
+//! An [`OpStream`] remembers which operations it has already returned. Each time its polled it
+//! returns any operations which have been added to the oplog since the last poll - whether they
+//! were made locally or merged in from remote peers. This is useful for feeding a broadcast loop,
+//! or for keeping an editor up to date.
+//!
+//! The stream doesn't borrow the oplog, so the oplog can be modified freely between polls.
+
+use rle::HasLength;
+use crate::{DTRange, Frontier, LV};
+use crate::list::ListOpLog;
+use crate::list::encoding::EncodeOptions;
+use crate::list::operation::TextOperation;
+
+/// A cursor over the operations in an oplog. Created with [`ListOpLog::ops_since`]. Each poll
+/// returns the operations added to the oplog since the last poll, whether they were made locally
+/// or merged in from peers. The stream doesn't borrow the oplog, so the oplog can be modified
+/// between polls.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OpStream {
+    version: Frontier,
+}
+
+impl OpStream {
+    /// The version of the oplog as of the last poll. Every operation in this version has been
+    /// returned (or was already known when the stream was created).
+    pub fn version(&self) -> &[LV] {
+        self.version.as_ref()
+    }
+
+    /// Are there any operations in the oplog which haven't been returned yet?
+    pub fn has_changes(&self, oplog: &ListOpLog) -> bool {
+        self.version != oplog.cg.version
+    }
+
+    /// Get the operations added since the last poll, as they were originally made, along with
+    /// their local versions.
+    pub fn poll(&mut self, oplog: &ListOpLog) -> Vec<(DTRange, TextOperation)> {
+        let mut result = vec![];
+        for range in oplog.cg.diff_since(self.version.as_ref()) {
+            let mut lv = range.start;
+            for op in oplog.iter_ops_range(range) {
+                let len = op.len() as LV;
+                result.push(((lv..lv + len).into(), op));
+                lv += len;
+            }
+        }
+        self.version = oplog.cg.version.clone();
+        result
+    }
+
+    /// Get the operations added since the last poll, transformed so they can be applied in order
+    /// to a document at the stream's previous version.
+    pub fn poll_xf(&mut self, oplog: &ListOpLog) -> Vec<TextOperation> {
+        let result = oplog.iter_xf_operations_from(self.version.as_ref(), oplog.cg.version.as_ref())
+            .filter_map(|(_, op)| op)
+            .collect();
+        self.version = oplog.cg.version.clone();
+        result
+    }
+
+    /// Encode the operations added since the last poll as a patch, for sending to remote peers.
+    /// Returns None if there are no new operations.
+    pub fn poll_encoded(&mut self, oplog: &ListOpLog, opts: &EncodeOptions) -> Option<Vec<u8>> {
+        if !self.has_changes(oplog) { return None; }
+        let data = oplog.encode_from(opts, self.version.as_ref());
+        self.version = oplog.cg.version.clone();
+        Some(data)
+    }
+}
+
+impl ListOpLog {
+    /// Create a stream of the operations added to the oplog after the named version. See
+    /// [`OpStream`].
+    pub fn ops_since(&self, version: &[LV]) -> OpStream {
+        OpStream { version: Frontier::from(version) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jumprope::JumpRope;
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::operation::{ListOpKind, TextOperation};
+
+    #[test]
+    fn poll_for_changes() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hi");
+
+        let mut raw = doc.oplog.ops_since(doc.oplog.local_frontier_ref());
+        let mut xf = raw.clone();
+        let mut encoded = raw.clone();
+        let mut mirror = JumpRope::from(doc.branch.content().to_string());
+        let mut peer = doc.oplog.clone();
+        assert!(raw.poll(&doc.oplog).is_empty());
+        assert_eq!(encoded.poll_encoded(&doc.oplog, &EncodeOptions::patch()), None);
+
+        doc.insert(seph, 2, " there");
+        doc.delete(seph, 0..1);
+        let ops = raw.poll(&doc.oplog);
+        assert_eq!(ops.len(), 2);
+        assert_eq!(ops[0], ((2..8).into(), TextOperation::new_insert(2, " there")));
+        assert!(raw.poll(&doc.oplog).is_empty());
+        assert!(!raw.has_changes(&doc.oplog));
+
+        // Concurrent changes from a remote peer.
+        let mut remote = ListOpLog::new();
+        remote.decode_and_add(&doc.oplog.encode_from(&EncodeOptions::patch(), &[])).unwrap();
+        let mike = remote.get_or_create_agent_id("mike");
+        remote.add_insert_at(mike, &[1], 0, "oh ");
+        doc.merge_data_and_ff(&remote.encode(&EncodeOptions::patch())).unwrap();
+        assert_eq!(raw.poll(&doc.oplog).len(), 1);
+
+        for op in xf.poll_xf(&doc.oplog) {
+            match op.kind {
+                ListOpKind::Ins => mirror.insert(op.start(), op.content_as_str().unwrap()),
+                ListOpKind::Del => mirror.remove(op.range().into()),
+            }
+        }
+        assert_eq!(mirror.to_string(), doc.branch.content().to_string());
+
+        peer.decode_and_add(&encoded.poll_encoded(&doc.oplog, &EncodeOptions::patch()).unwrap()).unwrap();
+        assert_eq!(peer.checkout_tip().content(), doc.branch.content());
+        assert_eq!(encoded.version(), doc.oplog.local_frontier_ref());
+    }
+}
@@ -12,6 +12,7 @@ use ListOpKind::*;
 use crate::unicount::{chars_to_bytes, count_chars};
 use crate::list::op_metrics::ListOpMetrics;
 use crate::dtrange::DTRange;
+use crate::{lv_to_usize, LV};
 use crate::rev_range::RangeRev;
 
 #[cfg(feature = "serde")]
@@ -112,11 +113,11 @@ impl FlattenSerializable for TextOperation {
 impl TextOperation {
     pub fn new_insert(pos: usize, content: &str) -> Self {
         let len = count_chars(content);
-        TextOperation { loc: (pos..pos+len).into(), kind: Ins, content: Some(content.into()) }
+        TextOperation { loc: (pos as LV..(pos + len) as LV).into(), kind: Ins, content: Some(content.into()) }
     }
 
     pub fn new_delete(loc: Range<usize>) -> Self {
-        Self::new_delete_dt(loc.into())
+        Self::new_delete_dt((loc.start as LV..loc.end as LV).into())
     }
 
     fn new_delete_dt(loc: RangeRev) -> Self {
@@ -125,7 +126,7 @@ impl TextOperation {
 
     pub fn new_delete_with_content_range(loc: Range<usize>, content: SmartString) -> Self {
         debug_assert_eq!(count_chars(&content), loc.len());
-        TextOperation { loc: loc.into(), kind: Del, content: Some(content) }
+        TextOperation { loc: (loc.start as LV..loc.end as LV).into(), kind: Del, content: Some(content) }
     }
 
     pub fn new_delete_with_content(pos: usize, content: SmartString) -> Self {
@@ -139,12 +140,12 @@ impl TextOperation {
 
     #[inline]
     pub fn start(&self) -> usize {
-        self.loc.span.start
+        lv_to_usize(self.loc.span.start)
     }
 
     #[inline]
     pub fn end(&self) -> usize {
-        self.loc.span.end
+        lv_to_usize(self.loc.span.end)
     }
 
     pub fn content_as_str(&self) -> Option<&str> {
@@ -1,20 +1,25 @@
+use std::cmp::Ordering;
 use std::ops::Range;
 use rle::{HasLength, SplitableSpan};
-use crate::{AgentId, Frontier, LV};
+use crate::{AgentId, Frontier, FrontierError, lv_to_usize, LV};
 use crate::list::{ListBranch, ListOpLog};
 use crate::causalgraph::graph::GraphEntrySimple;
+use crate::causalgraph::entry::CGEntry;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::list::operation::{TextOperation, ListOpKind};
-use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontier, RemoteVersionSpan};
+use crate::causalgraph::agent_assignment::remote_ids::{parse_remote_frontier, remote_frontier_to_string, RemoteFrontier, RemoteFrontierOwned, RemoteVersion, RemoteVersionSpan, VersionConversionError, VersionParseError};
+use crate::causalgraph::agent_assignment::AgentAssignment;
 use crate::dtrange::DTRange;
 use crate::causalgraph::agent_span::*;
+use crate::causalgraph::agent_assignment::TieBreak;
 use crate::rev_range::RangeRev;
 use crate::rle::KVPair;
 use crate::unicount::{chars_to_bytes, count_chars};
+use crate::list::content_buffer::SharedBytes;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use crate::rle::rle_vec::RleStats;
+use crate::rle::rle_vec::{RleStats, RleVecStats};
 
 impl Default for ListOpLog {
     fn default() -> Self {
@@ -22,13 +27,37 @@ impl Default for ListOpLog {
     }
 }
 
+fn sorted_local_versions(aa: &AgentAssignment, version: &[RemoteVersion]) -> Result<Vec<LV>, VersionConversionError> {
+    let mut result = version.iter()
+        .map(|rv| aa.try_remote_to_local_version(*rv))
+        .collect::<Result<Vec<LV>, _>>()?;
+    result.sort_unstable();
+    result.dedup();
+    Ok(result)
+}
+
+/// See [`ListOpLog::version_sort_key`].
+pub(crate) type VersionSortKey<'a> = (usize, Vec<(&'a str, LV)>);
+
 impl ListOpLog {
     pub fn new() -> Self {
         Self {
             doc_id: None,
+            fork_points: vec![],
             cg: Default::default(),
             operation_ctx: ListOperationCtx::new(),
             operations: Default::default(),
+            transactions: vec![],
+            signatures: vec![],
+            op_metadata: vec![],
+            checkpoints: vec![],
+            named_branches: Default::default(),
+            agent_info: Default::default(),
+            transformed_positions: None,
+            pending: Default::default(),
+            pruned: Default::default(),
+            #[cfg(feature = "dag_hash")]
+            hash_cache: Default::default(),
             // inserted_content: "".to_string(),
         }
     }
@@ -39,8 +68,17 @@ impl ListOpLog {
         branch
     }
 
+    /// Create a branch at some historical version, in preparation for moving it around in time.
+    ///
+    /// This is equivalent to [`checkout`](ListOpLog::checkout). The returned branch can be
+    /// efficiently moved to other versions using [`rewind_to`](ListBranch::rewind_to).
+    pub fn checkout_at(&self, version: &[LV]) -> ListBranch {
+        self.checkout(version)
+    }
+
     pub fn checkout_tip(&self) -> ListBranch {
-        let mut branch = ListBranch::new();
+        // If the file we loaded stored transformed positions, we can skip most of the merge work.
+        let mut branch = self.checkout_transformed_positions().unwrap_or_default();
         branch.merge(self, self.cg.version.as_ref());
         branch
     }
@@ -57,6 +95,20 @@ impl ListOpLog {
         self.cg.agent_assignment.get_agent_name(agent)
     }
 
+    /// Set how concurrent inserts at the same location are ordered. See [`TieBreak`].
+    ///
+    /// This should be set before any changes are merged, and every replica must use the same
+    /// policy. Custom policies are named in encoded files, and loading a file written with a
+    /// different policy fails with
+    /// [`ParseError::TieBreakMismatch`](crate::encoding::parseerror::ParseError::TieBreakMismatch).
+    pub fn set_insert_tie_break(&mut self, policy: TieBreak) {
+        self.cg.agent_assignment.insert_tie_break = policy;
+    }
+
+    pub fn insert_tie_break(&self) -> TieBreak {
+        self.cg.agent_assignment.insert_tie_break
+    }
+
     pub fn num_agents(&self) -> AgentId {
         self.cg.num_agents()
     }
@@ -99,7 +151,7 @@ impl ListOpLog {
     // }
 
     /// Get the number of operations
-    pub fn len(&self) -> usize {
+    pub fn len(&self) -> LV {
         self.cg.len()
     }
 
@@ -123,7 +175,7 @@ impl ListOpLog {
         let client_data = &mut self.cg.agent_assignment.client_data[agent as usize];
 
         // let next_seq = client_data.get_next_seq();
-        let timespan = (start..start + span.len()).into();
+        let timespan = (start..start + span.len() as LV).into();
 
         // // Could just optimize .insert() to efficiently handle both of these cases.
         // if next_seq <= seq_range.start {
@@ -145,13 +197,14 @@ impl ListOpLog {
         debug_assert_eq!(span.start, self.cg.len_assignment());
 
         let client_data = &mut self.cg.agent_assignment.client_data[agent as usize];
+        client_data.check_local_seqs(span.len());
 
         let next_seq = client_data.get_next_seq();
         client_data.lv_for_seq.push(KVPair(next_seq, span));
 
         self.cg.agent_assignment.client_with_lv.push(KVPair(span.start, AgentSpan {
             agent,
-            seq_range: DTRange { start: next_seq, end: next_seq + span.len() },
+            seq_range: DTRange { start: next_seq, end: next_seq + span.len() as LV },
         }));
     }
 
@@ -182,6 +235,25 @@ impl ListOpLog {
         }));
     }
 
+    /// Like [`push_op_internal`](ListOpLog::push_op_internal), but the operation's content is
+    /// referenced from a shared buffer rather than copied.
+    pub(crate) fn push_op_internal_shared(&mut self, next_time: LV, loc: RangeRev, kind: ListOpKind, data: &SharedBytes, content: Range<usize>) {
+        let content_pos = Some(self.operation_ctx.push_shared(kind, data, content));
+        self.operations.push(KVPair(next_time, ListOpMetrics {
+            loc,
+            kind,
+            content_pos
+        }));
+    }
+
+    pub(crate) fn push_op_internal_lazy(&mut self, next_time: LV, loc: RangeRev, kind: ListOpKind, content_pos: DTRange) {
+        self.operations.push(KVPair(next_time, ListOpMetrics {
+            loc,
+            kind,
+            content_pos: Some(content_pos)
+        }));
+    }
+
     /// Push new operations to the opset. Operation parents specified by parents parameter.
     ///
     /// Returns the single item version after merging. (The resulting LocalVersion after calling
@@ -196,22 +268,22 @@ impl ListOpLog {
             // let content = if op.content_known { Some(op.content.as_str()) } else { None };
             // let content = op.content.map(|c| c.as_str());
             self.push_op_internal(next_time, op.loc, op.kind, op.content_as_str());
-            next_time += len;
+            next_time += len as LV;
         }
 
-        self.cg.assign_local_op(agent, next_time - first_time);
+        self.cg.assign_local_op(agent, lv_to_usize(next_time - first_time));
         // self.assign_internal(agent, parents, DTRange { start: first_time, end: next_time });
         next_time - 1
     }
 
-    pub fn add_operations_remote(&mut self, agent: AgentId, parents: &[LV], start_seq: usize, ops: &[TextOperation]) -> DTRange {
+    pub fn add_operations_remote(&mut self, agent: AgentId, parents: &[LV], start_seq: LV, ops: &[TextOperation]) -> DTRange {
         // This is a bit complex because we could locally store some or all of the incoming operations.
         // First figure out the length of the new operations.
         let len: usize = ops.iter().map(|op| op.len()).sum();
 
         let new_lv_range = self.cg.merge_and_assign(parents, AgentSpan {
             agent,
-            seq_range: (start_seq..start_seq + len).into()
+            seq_range: (start_seq..start_seq + len as LV).into()
         });
 
         // new_lv_range might be shorter than len.
@@ -238,11 +310,11 @@ impl ListOpLog {
 
                 self.push_op_internal(next_time, loc, op.kind, content);
 
-                next_time += len - skip;
+                next_time += (len - skip) as LV;
                 skip = 0;
             } else {
                 self.push_op_internal(next_time, op.loc, op.kind, op.content_as_str());
-                next_time += len;
+                next_time += len as LV;
             }
         }
 
@@ -263,7 +335,7 @@ impl ListOpLog {
             // let content = if op.content_known { Some(op.content.as_str()) } else { None };
             // let content = op.content.map(|c| c.as_str());
             self.push_op_internal(next_time, op.loc, op.kind, op.content_as_str());
-            next_time += len;
+            next_time += len as LV;
         }
 
         self.cg.assign_span(agent, parents, DTRange { start: first_time, end: next_time });
@@ -277,9 +349,9 @@ impl ListOpLog {
         // self.add_operations_at(agent, parents, &[Operation::new_insert(pos, ins_content)])
         let len = count_chars(ins_content);
         let start = self.len();
-        let end = start + len;
+        let end = start + len as LV;
 
-        self.push_op_internal(start, (pos..pos+len).into(), ListOpKind::Ins, Some(ins_content));
+        self.push_op_internal(start, (pos as LV..(pos + len) as LV).into(), ListOpKind::Ins, Some(ins_content));
         self.cg.assign_span(agent, parents, DTRange { start, end });
         end - 1
     }
@@ -292,9 +364,9 @@ impl ListOpLog {
         // Equivalent to:
         // self.push_at(agent, parents, &[Operation::new_delete(pos, len)])
         let start_time = self.len();
-        let end_time = start_time + loc.len();
+        let end_time = start_time + loc.len() as LV;
 
-        self.push_op_internal(start_time, loc.into(), ListOpKind::Del, None);
+        self.push_op_internal(start_time, (loc.start as LV..loc.end as LV).into(), ListOpKind::Del, None);
         self.cg.assign_span(agent, parents, DTRange { start: start_time, end: end_time });
         end_time - 1
     }
@@ -324,6 +396,21 @@ impl ListOpLog {
         self.add_operations(agent, &[TextOperation::new_insert(pos, ins_content)])
     }
 
+    /// Insert a large block of text, like the initial content of an imported file. This does the
+    /// same thing as [`add_insert`](OpLog::add_insert), but the content is copied straight into
+    /// the oplog's content buffer instead of going through a [`TextOperation`]. The text is stored
+    /// as a single run of operations.
+    ///
+    /// Panics if the content is empty.
+    pub fn add_insert_bulk(&mut self, agent: AgentId, pos: usize, content: &str) -> LV {
+        assert!(!content.is_empty(), "Cannot insert empty content");
+        let start = self.len();
+        let len = count_chars(content);
+        self.push_op_internal(start, (pos as LV..(pos + len) as LV).into(), ListOpKind::Ins, Some(content));
+        self.cg.assign_local_op(agent, len);
+        start + len as LV - 1
+    }
+
     /// Add a local delete operation to the oplog. This variant of the method allows a user to pass
     /// the content of the delete into the oplog. This can be useful for undos and things like that
     /// but it is NOT CHECKED. If you don't have access to the deleted content, use
@@ -346,11 +433,36 @@ impl ListOpLog {
         self.add_operations(agent, &[TextOperation::new_delete(loc)])
     }
 
-    /// Iterate through history entries
+    /// Iterate through history entries, in local version order. This order is always a
+    /// topological sort of the history: every entry comes after all of its parents.
     pub fn iter_history(&self) -> impl Iterator<Item = GraphEntrySimple> + '_ {
         self.cg.graph.iter()
     }
 
+    /// Iterate through the history like [`iter_history`](Self::iter_history), but also yield the
+    /// agent which made each run of operations and their sequence numbers. Each entry is a run of
+    /// operations by a single agent, where every operation (after the first) has the previous
+    /// operation as its only parent.
+    ///
+    /// Entries are yielded in local version order, so parents always come before their children.
+    pub fn iter_history_entries(&self) -> impl Iterator<Item = CGEntry> + '_ {
+        self.cg.iter()
+    }
+
+    /// The same as [`iter_history_entries`](Self::iter_history_entries), in reverse. Children
+    /// always come before their parents.
+    pub fn iter_history_entries_rev(&self) -> impl Iterator<Item = CGEntry> + '_ {
+        let mut v = self.len();
+        std::iter::from_fn(move || {
+            if v == 0 { return None; }
+            let graph_start = self.cg.graph.entries.find_packed(v - 1).span.start;
+            let KVPair(agent_start, _) = self.cg.agent_assignment.client_with_lv.find_packed(v - 1);
+            let range: DTRange = ((*agent_start).max(graph_start)..v).into();
+            v = range.start;
+            Some(self.cg.simple_entry_at(range))
+        })
+    }
+
     pub fn iter_history_range(&self, range: DTRange) -> impl Iterator<Item = GraphEntrySimple> + '_ {
         self.cg.graph.iter_range(range)
     }
@@ -374,6 +486,40 @@ impl ListOpLog {
         self.cg.agent_assignment.local_to_remote_frontier(self.cg.version.as_ref())
     }
 
+    /// The oplog's current version in its compact string form (eg `"seph:42+mike:17"`). This is
+    /// stable across peers, so it can be stored in URLs and databases.
+    pub fn remote_frontier_string(&self) -> String {
+        self.frontier_to_remote_string(self.cg.version.as_ref())
+    }
+
+    /// Convert a local version to its compact string form.
+    pub fn frontier_to_remote_string(&self, frontier: &[LV]) -> String {
+        remote_frontier_to_string(self.cg.agent_assignment.local_to_remote_frontier(frontier))
+    }
+
+    /// A key for sorting versions, which is the same on every peer. Versions are sorted by the
+    /// number of operations in their history (so versions sort after the versions they contain),
+    /// then by their remote IDs.
+    pub(crate) fn version_sort_key(&self, frontier: &[LV]) -> VersionSortKey<'_> {
+        let (_, history) = self.cg.graph.diff(&[], frontier);
+        let mut ids: Vec<_> = self.cg.agent_assignment.local_to_remote_frontier(frontier).into_iter()
+            .map(|rv| (rv.0, rv.1))
+            .collect();
+        ids.sort_unstable();
+        (history.iter().map(|r| r.len()).sum(), ids)
+    }
+
+    /// Parse a version from its string form (see
+    /// [`remote_frontier_string`](ListOpLog::remote_frontier_string)). Fails if the version isn't
+    /// known to this oplog.
+    pub fn frontier_from_remote_string(&self, s: &str) -> Result<Frontier, VersionParseError> {
+        let remote = parse_remote_frontier(s)?;
+        let mut frontier = self.cg.agent_assignment.try_remote_to_local_frontier(remote.iter())?;
+        // The string might not be in canonical form.
+        frontier = self.cg.graph.find_dominators(frontier.as_ref());
+        Ok(frontier)
+    }
+
     // pub(crate) fn content_str(&self, tag: InsDelTag) -> &str {
     //     switch(tag, &self.ins_content, &self.del_content)
     // }
@@ -430,6 +576,84 @@ impl ListOpLog {
         self.cg.graph.find_dominators_2(a, b)
     }
 
+    /// Check if `version` contains the operation `lv`. The empty version (ROOT) contains nothing.
+    pub fn version_contains(&self, version: &[LV], lv: LV) -> bool {
+        self.cg.graph.frontier_contains_version(version, lv)
+    }
+
+    /// Take the intersection of two versions. This names the set of operations contained in both
+    /// `a` and `b`. If the versions are concurrent, this is where they diverged.
+    pub fn version_intersection(&self, a: &[LV], b: &[LV]) -> Frontier {
+        self.cg.graph.version_intersection(a, b)
+    }
+
+    /// Compare two versions. Returns `Some(Greater)` if `a` contains every operation in `b` (and
+    /// more), `Some(Equal)` if they name the same operations, and None if the versions are
+    /// concurrent.
+    pub fn compare_versions(&self, a: &[LV], b: &[LV]) -> Option<Ordering> {
+        self.cg.graph.partial_cmp_versions(a, b)
+    }
+
+    /// Check that `version` is a valid frontier in this oplog, and convert it to a [`Frontier`].
+    /// See [`Frontier::validate`].
+    pub fn validate_frontier(&self, version: &[LV]) -> Result<Frontier, FrontierError> {
+        Frontier::try_from_versions(&self.cg.graph, version)
+    }
+
+    /// Convert a list of remote versions (in any order) into the local frontier which contains
+    /// all of them.
+    ///
+    /// Returns an error if any of the named operations are unknown.
+    pub fn remote_to_frontier(&self, version: &[RemoteVersion]) -> Result<Frontier, VersionConversionError> {
+        let aa = &self.cg.agent_assignment;
+        Ok(self.cg.graph.find_dominators(&sorted_local_versions(aa, version)?))
+    }
+
+    /// Check out a branch at `version`, returning an error instead of misbehaving if the version
+    /// isn't a valid frontier.
+    pub fn try_checkout(&self, version: &[LV]) -> Result<ListBranch, FrontierError> {
+        let version = self.validate_frontier(version)?;
+        Ok(self.checkout(version.as_ref()))
+    }
+
+    /// Check if a remote version contains the operation with the remote ID `id`.
+    ///
+    /// Returns an error if any of the named operations are unknown.
+    pub fn remote_version_contains(&self, version: &[RemoteVersion], id: RemoteVersion) -> Result<bool, VersionConversionError> {
+        let aa = &self.cg.agent_assignment;
+        let version = self.cg.graph.find_dominators(&sorted_local_versions(aa, version)?);
+        Ok(self.version_contains(version.as_ref(), aa.try_remote_to_local_version(id)?))
+    }
+
+    /// Take the union of two remote versions. See [`version_union`](Self::version_union).
+    ///
+    /// Returns an error if any of the named operations are unknown.
+    pub fn remote_version_union(&self, a: &[RemoteVersion], b: &[RemoteVersion]) -> Result<RemoteFrontierOwned, VersionConversionError> {
+        let aa = &self.cg.agent_assignment;
+        let v = self.version_union(&sorted_local_versions(aa, a)?, &sorted_local_versions(aa, b)?);
+        Ok(aa.local_to_remote_frontier_owned(v.as_ref()))
+    }
+
+    /// Take the intersection of two remote versions. See
+    /// [`version_intersection`](Self::version_intersection).
+    ///
+    /// Returns an error if any of the named operations are unknown.
+    pub fn remote_version_intersection(&self, a: &[RemoteVersion], b: &[RemoteVersion]) -> Result<RemoteFrontierOwned, VersionConversionError> {
+        let aa = &self.cg.agent_assignment;
+        let graph = &self.cg.graph;
+        let a = graph.find_dominators(&sorted_local_versions(aa, a)?);
+        let b = graph.find_dominators(&sorted_local_versions(aa, b)?);
+        Ok(aa.local_to_remote_frontier_owned(self.version_intersection(a.as_ref(), b.as_ref()).as_ref()))
+    }
+
+    /// Compare two remote versions. See [`compare_versions`](Self::compare_versions).
+    ///
+    /// Returns an error if any of the named operations are unknown.
+    pub fn compare_remote_versions(&self, a: &[RemoteVersion], b: &[RemoteVersion]) -> Result<Option<Ordering>, VersionConversionError> {
+        let aa = &self.cg.agent_assignment;
+        Ok(self.compare_versions(&sorted_local_versions(aa, a)?, &sorted_local_versions(aa, b)?))
+    }
+
     pub fn parents_at_version(&self, lv: LV) -> Frontier {
         self.cg.graph.parents_at_version(lv)
     }
@@ -506,8 +730,8 @@ impl ListOpLog {
         println!("del: singles {d_1}, fwd {d_n}, rev {d_r}, count {d_count}, keystrokes {d_k}");
         println!("Total keystrokes: {}", i_k + d_k);
 
-        println!("Insert content length {}", self.operation_ctx.ins_content.len());
-        println!("Delete content length {}", self.operation_ctx.del_content.len());
+        println!("Insert content length {}", self.operation_ctx.ins_content.num_bytes());
+        println!("Delete content length {}", self.operation_ctx.del_content.num_bytes());
 
         self.cg.agent_assignment.client_with_lv.print_stats("Client LV map", detailed);
         println!("number of agents: {}", self.cg.agent_assignment.client_data.len());
@@ -547,7 +771,7 @@ impl ListOpLog {
             num_delete_keystrokes: d_k,
             total_keystrokes: i_k + d_k,
 
-            ins_content_len_utf8: self.operation_ctx.ins_content.len(),
+            ins_content_len_utf8: self.operation_ctx.ins_content.num_bytes(),
             final_doc_len_chars: resulting_content.len_chars(),
             final_doc_len_utf8: resulting_content.len_bytes(),
 
@@ -559,4 +783,75 @@ impl ListOpLog {
         }
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering;
+    use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersion, RemoteVersionOwned, VersionConversionError};
+    use rle::HasLength;
+    use crate::causalgraph::entry::CGEntry;
+    use crate::list::gen_random::gen_oplog;
+    use crate::{FrontierError, LV};
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn version_arithmetic() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "abc"); // 0..3
+        let a = oplog.add_insert(seph, 3, "d"); // 3
+        let b = oplog.add_insert_at(mike, &[2], 0, "x"); // 4
+
+        assert!(oplog.version_contains(&[a], 2));
+        assert!(!oplog.version_contains(&[a], b));
+        assert!(!oplog.version_contains(&[], 0));
+        assert_eq!(oplog.version_union(&[a], &[b]).as_ref(), &[a, b]);
+        assert_eq!(oplog.version_intersection(&[a], &[b]).as_ref(), &[2]);
+        assert_eq!(oplog.compare_versions(&[a], &[b]), None);
+        assert_eq!(oplog.compare_versions(&[a, b], &[b]), Some(Ordering::Greater));
+        assert_eq!(oplog.compare_versions(&[1], &[a]), Some(Ordering::Less));
+
+        let rv_a = [RemoteVersion("seph", 3)];
+        let rv_b = [RemoteVersion("mike", 0)];
+        assert_eq!(oplog.remote_version_contains(&rv_a, RemoteVersion("seph", 0)), Ok(true));
+        assert_eq!(oplog.remote_version_contains(&rv_a, rv_b[0]), Ok(false));
+        assert_eq!(oplog.compare_remote_versions(&rv_a, &rv_b), Ok(None));
+        assert_eq!(oplog.remote_version_union(&rv_a, &rv_b).unwrap(),
+            [RemoteVersionOwned("seph".into(), 3), RemoteVersionOwned("mike".into(), 0)]);
+        assert_eq!(oplog.remote_version_union(&rv_a, &[RemoteVersion("seph", 1)]).unwrap(), [rv_a[0].into()]);
+        assert_eq!(oplog.remote_version_intersection(&rv_a, &rv_b).unwrap(), [RemoteVersionOwned("seph".into(), 2)]);
+        assert_eq!(oplog.remote_version_intersection(&rv_a, &[]).unwrap(), []);
+        assert_eq!(oplog.remote_version_union(&rv_a, &[RemoteVersion("fred", 0)]), Err(VersionConversionError::UnknownAgent));
+        assert_eq!(oplog.compare_remote_versions(&[RemoteVersion("fred", 0)], &rv_b), Err(VersionConversionError::UnknownAgent));
+
+        assert_eq!(oplog.validate_frontier(&[a, b]).unwrap().as_ref(), &[a, b]);
+        assert_eq!(oplog.validate_frontier(&[b, a]), Err(FrontierError::Unsorted));
+        assert_eq!(oplog.validate_frontier(&[1, a]), Err(FrontierError::Dominated(1)));
+        assert!(oplog.try_checkout(&[10]).is_err());
+        assert_eq!(oplog.try_checkout(&[a]).unwrap().content().to_string(), "abcd");
+        assert_eq!(oplog.remote_to_frontier(&[rv_b[0], rv_a[0], RemoteVersion("seph", 1)]).unwrap().as_ref(), &[a, b]);
+        assert_eq!(oplog.remote_to_frontier(&[rv_b[0], rv_b[0]]).unwrap().as_ref(), &[b]);
+        assert_eq!(oplog.compare_remote_versions(&[rv_a[0], rv_a[0]], &rv_a), Ok(Some(Ordering::Equal)));
+        assert_eq!(oplog.remote_to_frontier(&[rv_a[0], rv_b[0], rv_a[0]]).unwrap().as_ref(), &[a, b]);
+    }
+
+    #[test]
+    fn history_entries() {
+        let oplog = gen_oplog(123, 50, false, true);
+        let entries: Vec<CGEntry> = oplog.iter_history_entries().collect();
+        let mut rev: Vec<CGEntry> = oplog.iter_history_entries_rev().collect();
+        rev.reverse();
+        assert_eq!(entries, rev);
+
+        let mut next = 0;
+        for e in entries {
+            assert_eq!(e.start, next);
+            assert!(e.parents.iter().all(|p| *p < e.start));
+            let span = oplog.cg.agent_assignment.local_span_to_agent_span((e.start..e.start + e.span.len() as LV).into());
+            assert_eq!(span, e.span);
+            next += e.span.len() as LV;
+        }
+        assert_eq!(next, oplog.len());
+    }
+}
@@ -0,0 +1,136 @@
+//! Parallel merging, using rayon.
+//!
+//! The merge plan for a large merge (or a checkout from scratch) is usually made up of several
+//! independent segments. Every time the history passes through a critical version (a version
+//! which everything before it precedes, and everything after it follows), the merge tracker is
+//! cleared. Operations after the clear are transformed without reference to anything which came
+//! before it.
+//!
+//! So we split the plan at each clear, transform each segment on a separate thread and then apply
+//! the transformed operations to the branch in order. This gives a big speedup for long histories
+//! with lots of short-lived concurrent branches - like repository imports.
+
+use rayon::prelude::*;
+use crate::list::{ListBranch, ListOpLog};
+use crate::listmerge::merge::{TransformedOpsIterRaw, TransformedResultRaw};
+use crate::listmerge::plan::{M1Plan, M1PlanAction};
+use crate::LV;
+use crate::rle::KVPair;
+
+/// Split a plan into segments which can be transformed independently.
+fn split_plan(plan: M1Plan) -> Vec<M1Plan> {
+    let mut result = vec![];
+    let mut current = vec![];
+    let mut applying = false;
+
+    for action in plan.actions {
+        match action {
+            M1PlanAction::Clear => {
+                // Segments which don't output anything can be skipped entirely.
+                if applying { result.push(M1Plan::new(std::mem::take(&mut current))); }
+                current.clear();
+                if applying { current.push(M1PlanAction::BeginOutput); }
+            }
+            M1PlanAction::BeginOutput => {
+                applying = true;
+                current.push(action);
+            }
+            _ => current.push(action),
+        }
+    }
+
+    if applying { result.push(M1Plan::new(current)); }
+    result
+}
+
+impl ListBranch {
+    /// Merge the changes in `merge_frontier` into the branch, like [`merge`](ListBranch::merge).
+    /// Independent parts of the merge are transformed in parallel on rayon's thread pool.
+    ///
+    /// This uses more memory than a normal merge, since transformed operations are buffered before
+    /// they're applied. Its only faster for large merges.
+    pub fn par_merge(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) {
+        let merge_frontier = oplog.expand_to_transactions(merge_frontier);
+        let merge_frontier = merge_frontier.as_ref();
+        let (plan, _common) = oplog.cg.graph.make_m1_plan(Some(&oplog.operations), self.version.as_ref(), merge_frontier, true);
+
+        let segments: Vec<Vec<TransformedResultRaw>> = split_plan(plan)
+            .into_par_iter()
+            .map(|plan| {
+                TransformedOpsIterRaw::from_plan(&oplog.cg.agent_assignment, &oplog.operation_ctx, &oplog.operations, plan)
+                    .collect()
+            })
+            .collect();
+
+        self.lift_composition();
+        self.bind_anchors(oplog);
+        for xf in segments.into_iter().flatten() {
+            match xf {
+                TransformedResultRaw::Apply { xf_pos, op: KVPair(lv, mut op) } => {
+                    op.transpose_to(xf_pos);
+                    self.apply_op_at(oplog, lv, op);
+                }
+                TransformedResultRaw::FF(range) => {
+                    for KVPair(lv, op) in oplog.operations.iter_range_ctx(range, &oplog.operation_ctx) {
+                        self.apply_op_at(oplog, lv, op);
+                    }
+                }
+                TransformedResultRaw::DeleteAlreadyHappened(_) => {} // Discard.
+            }
+        }
+
+        self.version = oplog.cg.graph.find_dominators_2(self.version.as_ref(), merge_frontier);
+        self.fix_stale_anchors(oplog);
+        self.restore_composition();
+    }
+}
+
+impl ListOpLog {
+    /// Check out the current version of the document, transforming independent parts of the
+    /// history in parallel. See [`ListBranch::par_merge`].
+    pub fn par_checkout_tip(&self) -> ListBranch {
+        let mut branch = ListBranch::new();
+        branch.par_merge(self, self.cg.version.as_ref());
+        branch
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+    use crate::list::ListCRDT;
+    use crate::list::old_fuzzer_tools::old_make_random_change;
+    use super::split_plan;
+
+    #[test]
+    fn par_merge_matches_merge() {
+        let mut rng = SmallRng::seed_from_u64(10);
+        let mut docs = [ListCRDT::new(), ListCRDT::new()];
+        for doc in docs.iter_mut() {
+            for a in 0..2 {
+                doc.get_or_create_agent_id(format!("agent {a}").as_str());
+            }
+        }
+
+        // Lots of short concurrent branches, with syncs in between.
+        for _ in 0..20 {
+            for _ in 0..5 {
+                let idx = rng.gen_range(0..docs.len());
+                old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, false);
+            }
+            let [a, b] = &mut docs;
+            a.oplog.add_missing_operations_from(&b.oplog);
+            b.oplog.add_missing_operations_from(&a.oplog);
+            a.branch.merge(&a.oplog, a.oplog.local_frontier_ref());
+            b.branch.merge(&b.oplog, b.oplog.local_frontier_ref());
+        }
+
+        let oplog = &docs[0].oplog;
+        let (plan, _) = oplog.cg.graph.make_m1_plan(Some(&oplog.operations), &[], oplog.local_frontier_ref(), true);
+        assert!(split_plan(plan).len() > 1);
+
+        let branch = oplog.par_checkout_tip();
+        assert_eq!(branch.content(), docs[0].branch.content());
+        assert_eq!(branch.local_frontier_ref(), oplog.local_frontier_ref());
+    }
+}
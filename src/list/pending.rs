@@ -0,0 +1,476 @@
+//! A queue for patches which arrive before their parents.
+//!
+//! Network transports don't always deliver patches in order. Normally
+//! [`decode_and_add`](ListOpLog::decode_and_add) fails if a patch depends on operations we don't
+//! have yet. [`apply_or_queue`](ListOpLog::apply_or_queue) instead stores the patch in the oplog,
+//! and applies it automatically once the missing operations arrive.
+//!
+//! Each queued patch waits for one missing operation from its base version. Patches are indexed by
+//! that operation, so adding operations only retries the patches which were waiting for them.
+//!
+//! The queue is limited to [`DEFAULT_MAX_PENDING_PATCHES`] patches and
+//! [`DEFAULT_MAX_PENDING_BYTES`] bytes by default. When its full the oldest patches are discarded.
+//! Use [`set_pending_limits`](ListOpLog::set_pending_limits) to change the limits.
+//!
+//! The queue is only kept in memory. Its not saved with the document or copied when oplogs are
+//! merged.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use smartstring::alias::String as SmartString;
+use crate::causalgraph::agent_assignment::AgentAssignment;
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersionOwned, RemoteVersionSpanOwned};
+use crate::encoding::parseerror::ParseError;
+use crate::{DTRange, Frontier, LV};
+use crate::list::ListOpLog;
+use crate::list::encoding::read_patch_header;
+
+/// The default maximum number of queued patches. See [`ListOpLog::set_pending_limits`].
+pub const DEFAULT_MAX_PENDING_PATCHES: usize = 1000;
+/// The default maximum total size of queued patches, in bytes. See
+/// [`ListOpLog::set_pending_limits`].
+pub const DEFAULT_MAX_PENDING_BYTES: usize = 16 * 1024 * 1024;
+
+/// A patch waiting for its parents to arrive. See [`ListOpLog::pending_patches`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PendingPatch {
+    /// The encoded patch.
+    pub data: Vec<u8>,
+    /// The error from the last attempt to apply the patch.
+    pub error: ParseError,
+    /// The number of times we've tried to apply the patch.
+    pub attempts: usize,
+    /// The operation in the patch's base version we're waiting for.
+    pub waiting_for: RemoteVersionOwned,
+}
+
+/// The error returned by [`ListOpLog::add_patches`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AddPatchesError {
+    /// Why the patch couldn't be added.
+    pub error: ParseError,
+    /// The combined version of the patches added before the error. Their operations stay in the
+    /// oplog.
+    pub added: Frontier,
+}
+
+impl Display for AddPatchesError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Could not add patches: {}", self.error)
+    }
+}
+
+impl Error for AddPatchesError {}
+
+impl From<AddPatchesError> for ParseError {
+    fn from(err: AddPatchesError) -> Self {
+        err.error
+    }
+}
+
+/// The queued patches, oldest first, indexed by the operations they're waiting for.
+#[derive(Debug, Clone)]
+pub(crate) struct PendingQueue {
+    /// Patches by ID. IDs are assigned in order, so iterating gives the oldest patches first.
+    patches: BTreeMap<usize, PendingPatch>,
+    /// Agent name -> seq -> the IDs of the patches waiting for that operation.
+    index: BTreeMap<SmartString, BTreeMap<LV, Vec<usize>>>,
+    next_id: usize,
+    bytes: usize,
+    max_patches: usize,
+    max_bytes: usize,
+}
+
+impl Default for PendingQueue {
+    fn default() -> Self {
+        Self {
+            patches: BTreeMap::new(),
+            index: BTreeMap::new(),
+            next_id: 0,
+            bytes: 0,
+            max_patches: DEFAULT_MAX_PENDING_PATCHES,
+            max_bytes: DEFAULT_MAX_PENDING_BYTES,
+        }
+    }
+}
+
+impl PendingQueue {
+    fn insert(&mut self, id: usize, patch: PendingPatch) {
+        let RemoteVersionOwned(name, seq) = &patch.waiting_for;
+        self.index.entry(name.clone()).or_default().entry(*seq).or_default().push(id);
+        self.bytes += patch.data.len();
+        self.patches.insert(id, patch);
+    }
+
+    fn remove(&mut self, id: usize) -> Option<PendingPatch> {
+        let patch = self.patches.remove(&id)?;
+        self.bytes -= patch.data.len();
+
+        let RemoteVersionOwned(name, seq) = &patch.waiting_for;
+        let by_seq = self.index.get_mut(name).unwrap();
+        let ids = by_seq.get_mut(seq).unwrap();
+        ids.retain(|&i| i != id);
+        if ids.is_empty() {
+            by_seq.remove(seq);
+            if by_seq.is_empty() { self.index.remove(name); }
+        }
+        Some(patch)
+    }
+
+    /// Discard the oldest patches until there's room for a patch of the given size. Returns
+    /// false if the patch is too big to ever fit.
+    fn make_room(&mut self, bytes: usize) -> bool {
+        if self.max_patches == 0 || bytes > self.max_bytes { return false; }
+        while self.patches.len() >= self.max_patches || self.bytes + bytes > self.max_bytes {
+            let oldest = *self.patches.keys().next().unwrap();
+            self.remove(oldest);
+        }
+        true
+    }
+
+    /// Discard the oldest patches until the queue is within both of its limits.
+    fn enforce_limits(&mut self) {
+        while self.patches.len() > self.max_patches || self.bytes > self.max_bytes {
+            let oldest = *self.patches.keys().next().unwrap();
+            self.remove(oldest);
+        }
+    }
+
+    /// Add a new patch to the queue, discarding older patches if the queue is full. Returns false
+    /// if the patch was too big to queue.
+    fn push(&mut self, patch: PendingPatch) -> bool {
+        if !self.make_room(patch.data.len()) { return false; }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.insert(id, patch);
+        true
+    }
+
+    /// The IDs of the patches waiting for the operations in `range`.
+    fn waiting_on(&self, aa: &AgentAssignment, range: DTRange) -> Vec<usize> {
+        let mut result = vec![];
+        if self.patches.is_empty() { return result; }
+        for span in aa.iter_remote_mappings_range(range) {
+            if let Some(by_seq) = self.index.get(span.0) {
+                for ids in by_seq.range(span.1.start..span.1.end).map(|(_, ids)| ids) {
+                    result.extend_from_slice(ids);
+                }
+            }
+        }
+        result
+    }
+
+    fn retain<F: FnMut(&PendingPatch) -> bool>(&mut self, mut f: F) {
+        let discard: Vec<usize> = self.patches.iter()
+            .filter(|(_, p)| !f(p))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in discard { self.remove(id); }
+    }
+}
+
+impl ListOpLog {
+    /// The first operation in the patch's base version which we don't have yet.
+    fn missing_parent(&self, data: &[u8]) -> Option<RemoteVersionOwned> {
+        read_patch_header(data).ok()?.base_version.into_iter()
+            .find(|rv| self.cg.agent_assignment.try_remote_to_local_version(rv.into()).is_err())
+    }
+
+    /// Add the operations in a patch to the oplog. If the patch's base version contains operations
+    /// we don't have yet, its queued and applied once they arrive. Any queued patches which can now
+    /// be applied are applied too.
+    ///
+    /// Each queued patch waits for the first operation in its base version which we don't have.
+    /// The queue is only kept in memory, and its size is limited - see
+    /// [`set_pending_limits`](ListOpLog::set_pending_limits).
+    ///
+    /// Returns the number of patches applied (including queued patches). Returns 0 if the patch
+    /// was queued. Other errors are returned as normal, and the patch is discarded. Patches too big
+    /// to fit in the queue return [`ParseError::BaseVersionUnknown`].
+    pub fn apply_or_queue(&mut self, data: &[u8]) -> Result<usize, ParseError> {
+        let len = self.len();
+        match self.decode_and_add(data) {
+            Ok(_) => {
+                let ready = self.pending.waiting_on(&self.cg.agent_assignment, (len..self.len()).into());
+                Ok(1 + self.apply_ready(ready.into_iter().collect()))
+            }
+            Err(error @ ParseError::BaseVersionUnknown) => {
+                let waiting_for = self.missing_parent(data).ok_or(error)?;
+                let patch = PendingPatch { data: data.to_vec(), error, attempts: 1, waiting_for };
+                if self.pending.push(patch) { Ok(0) } else { Err(error) }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Apply queued patches whose missing operations have been added to the oplog some other way
+    /// (eg with [`decode_and_add`](ListOpLog::decode_and_add)). Returns the number of patches
+    /// applied.
+    ///
+    /// This is called automatically by [`apply_or_queue`](ListOpLog::apply_or_queue). Only
+    /// patches whose missing operations have arrived are decoded. Queued patches which fail for any
+    /// other reason than missing parents are discarded.
+    pub fn apply_pending(&mut self) -> usize {
+        let ready = self.pending.patches.iter()
+            .filter(|(_, p)| self.cg.agent_assignment.try_remote_to_local_version((&p.waiting_for).into()).is_ok())
+            .map(|(&id, _)| id)
+            .collect();
+        self.apply_ready(ready)
+    }
+
+    /// Apply the queued patches in `ready`, and any patches waiting for the operations they add.
+    fn apply_ready(&mut self, mut ready: BTreeSet<usize>) -> usize {
+        let mut applied = 0;
+        while let Some(id) = ready.pop_first() {
+            let Some(mut patch) = self.pending.remove(id) else { continue; };
+            let len = self.len();
+            match self.decode_and_add(&patch.data) {
+                Ok(_) => {
+                    applied += 1;
+                    ready.extend(self.pending.waiting_on(&self.cg.agent_assignment, (len..self.len()).into()));
+                }
+                Err(error @ ParseError::BaseVersionUnknown) => {
+                    // The patch is waiting on another operation from its base version.
+                    if let Some(waiting_for) = self.missing_parent(&patch.data) {
+                        patch.error = error;
+                        patch.attempts += 1;
+                        patch.waiting_for = waiting_for;
+                        self.pending.insert(id, patch);
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        applied
+    }
+
+    /// Add the operations from a batch of patches (eg a backlog of changes made while offline).
+    /// The patches can be in any order. Unlike [`apply_or_queue`](ListOpLog::apply_or_queue),
+    /// nothing is queued.
+    ///
+    /// The base version and operation IDs of every patch are read first, and the patches are
+    /// sorted so each patch is added after the patches containing its base version. Each patch is
+    /// then decoded once.
+    ///
+    /// Returns the combined version of all the patches. If any patch is invalid or depends on
+    /// operations which aren't in the oplog or the batch, the error is returned along with the
+    /// version of the patches added before it. Operations from those patches stay in the oplog.
+    ///
+    /// This only adds operations to the oplog. Use
+    /// [`ListCRDT::merge_patches`](crate::list::ListCRDT::merge_patches) to also merge them into a
+    /// branch in one pass.
+    pub fn add_patches<'a, I: IntoIterator<Item=&'a [u8]>>(&mut self, patches: I) -> Result<Frontier, AddPatchesError> {
+        let patches: Vec<&[u8]> = patches.into_iter().collect();
+        let headers = patches.iter()
+            .map(|data| read_patch_header(data))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| AddPatchesError { error, added: Frontier::root() })?;
+
+        // (agent name, first seq) -> (end seq, patch). If patches overlap the first one is used.
+        let mut contains = BTreeMap::new();
+        for (i, header) in headers.iter().enumerate() {
+            for RemoteVersionSpanOwned(name, seqs) in header.ids.iter() {
+                contains.entry((name.as_str(), seqs.start)).or_insert((seqs.end, i));
+            }
+        }
+        let find_patch = |name: &str, seq: LV| -> Option<usize> {
+            contains.range(..=(name, seq)).rev()
+                .take_while(|((n, _), _)| *n == name)
+                .find(|(_, (end, _))| seq < *end)
+                .map(|(_, (_, i))| *i)
+        };
+
+        // Topologically sort the patches, keeping the passed order where we can.
+        // Patches with missing parents are never ready, so they (and the patches which depend on
+        // them) are added last.
+        let mut num_deps = vec![0; patches.len()];
+        let mut dependents = vec![vec![]; patches.len()];
+        for (i, header) in headers.iter().enumerate() {
+            let mut deps = vec![];
+            for rv in header.base_version.iter() {
+                if self.cg.agent_assignment.try_remote_to_local_version(rv.into()).is_ok() { continue; }
+                match find_patch(&rv.0, rv.1) {
+                    Some(p) if p != i => deps.push(p),
+                    _ => num_deps[i] += 1,
+                }
+            }
+            deps.sort_unstable();
+            deps.dedup();
+            num_deps[i] += deps.len();
+            for p in deps { dependents[p].push(i); }
+        }
+
+        let mut ready: BTreeSet<usize> = (0..patches.len()).filter(|&i| num_deps[i] == 0).collect();
+        let mut order = Vec::with_capacity(patches.len());
+        while let Some(i) = ready.pop_first() {
+            order.push(i);
+            for &d in dependents[i].iter() {
+                num_deps[d] -= 1;
+                if num_deps[d] == 0 { ready.insert(d); }
+            }
+        }
+        // Adding the remaining patches will fail, and return the error.
+        if order.len() < patches.len() {
+            order.extend((0..patches.len()).filter(|&i| num_deps[i] > 0));
+        }
+
+        let mut version = Frontier::root();
+        for i in order {
+            match self.decode_and_add(patches[i]) {
+                Ok(v) => version.merge_union(v.as_ref(), &self.cg.graph),
+                Err(error) => return Err(AddPatchesError { error, added: version }),
+            }
+        }
+        Ok(version)
+    }
+
+    /// The patches waiting for their parents to arrive, oldest first.
+    pub fn pending_patches(&self) -> impl Iterator<Item = &PendingPatch> + '_ {
+        self.pending.patches.values()
+    }
+
+    /// Discard queued patches which don't match the predicate. Returns the number of patches
+    /// discarded.
+    pub fn retain_pending<F: FnMut(&PendingPatch) -> bool>(&mut self, f: F) -> usize {
+        let len = self.pending.patches.len();
+        self.pending.retain(f);
+        len - self.pending.patches.len()
+    }
+
+    /// Discard the oldest queued patches, so at most `max_patches` are left. Returns the
+    /// number of patches discarded.
+    pub fn expire_pending(&mut self, max_patches: usize) -> usize {
+        let expired = self.pending.patches.len().saturating_sub(max_patches);
+        let ids: Vec<usize> = self.pending.patches.keys().take(expired).copied().collect();
+        for id in ids { self.pending.remove(id); }
+        expired
+    }
+
+    /// Set the maximum number of queued patches, and their maximum total size in bytes. When a
+    /// new patch doesn't fit, the oldest patches are discarded to make room. The defaults are
+    /// [`DEFAULT_MAX_PENDING_PATCHES`] and [`DEFAULT_MAX_PENDING_BYTES`].
+    ///
+    /// Queued patches over the new limits are discarded straight away, oldest first.
+    pub fn set_pending_limits(&mut self, max_patches: usize, max_bytes: usize) {
+        self.pending.max_patches = max_patches;
+        self.pending.max_bytes = max_bytes;
+        self.pending.enforce_limits();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Frontier;
+    use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersionOwned, RemoteVersionSpanOwned};
+    use crate::list::{AddPatchesError, ListCRDT, ListOpLog};
+    use crate::list::encoding::{EncodeOptions, ParseError};
+
+    #[test]
+    fn out_of_order_patches() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut patches = vec![];
+        for (i, s) in ["a", "b", "c", "d"].iter().enumerate() {
+            let v = oplog.cg.version.clone();
+            oplog.add_insert(seph, i, s);
+            patches.push(oplog.encode_from(&EncodeOptions::patch(), v.as_ref()));
+        }
+
+        let mut dest = ListOpLog::new();
+        assert_eq!(dest.apply_or_queue(&patches[2]), Ok(0));
+        assert_eq!(dest.apply_or_queue(&patches[3]), Ok(0));
+        assert_eq!(dest.apply_or_queue(&patches[1]), Ok(0));
+        assert_eq!(dest.pending_patches().count(), 3);
+        let first = dest.pending_patches().next().unwrap();
+        assert_eq!(first.error, ParseError::BaseVersionUnknown);
+        assert_eq!(first.waiting_for, RemoteVersionOwned("seph".into(), 1));
+        assert_eq!(dest.apply_or_queue(&patches[0]), Ok(4));
+        assert_eq!(dest.pending_patches().count(), 0);
+        assert_eq!(dest.checkout_tip().content(), "abcd");
+
+        // Invalid data is still an error.
+        assert_eq!(dest.apply_or_queue(b"hello there"), Err(ParseError::InvalidMagic));
+
+        let mut dest = ListOpLog::new();
+        dest.apply_or_queue(&patches[3]).unwrap();
+        dest.apply_or_queue(&patches[2]).unwrap();
+        dest.apply_or_queue(&patches[1]).unwrap();
+        assert_eq!(dest.expire_pending(2), 1);
+        assert_eq!(dest.retain_pending(|p| p.data != patches[1]), 1);
+        assert_eq!(dest.apply_or_queue(&patches[0]), Ok(1));
+        // patches[2] is still waiting for patches[1], so it wasn't tried again.
+        assert_eq!(dest.pending_patches().count(), 1);
+        assert_eq!(dest.pending_patches().next().unwrap().attempts, 1);
+
+        // Operations added some other way are picked up by apply_pending.
+        dest.decode_and_add(&patches[1]).unwrap();
+        assert_eq!(dest.apply_pending(), 1);
+        assert_eq!(dest.checkout_tip().content(), "abc");
+    }
+
+    #[test]
+    fn pending_limits() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "a");
+        let mut patches = vec![];
+        for i in 1..5 {
+            let v = oplog.cg.version.clone();
+            oplog.add_insert(seph, i, "b");
+            patches.push(oplog.encode_from(&EncodeOptions::patch(), v.as_ref()));
+        }
+
+        let mut dest = ListOpLog::new();
+        dest.set_pending_limits(2, usize::MAX);
+        for p in &patches { assert_eq!(dest.apply_or_queue(p), Ok(0)); }
+        // The oldest patches were discarded.
+        let queued: Vec<_> = dest.pending_patches().map(|p| p.data.clone()).collect();
+        assert_eq!(queued, &patches[2..]);
+
+        // Lowering either limit discards patches straight away.
+        dest.set_pending_limits(1, usize::MAX);
+        assert_eq!(dest.pending_patches().count(), 1);
+        dest.set_pending_limits(2, usize::MAX);
+        assert_eq!(dest.apply_or_queue(&patches[2]), Ok(0));
+        assert_eq!(dest.pending_patches().count(), 2);
+        dest.set_pending_limits(2, patches[3].len());
+        assert_eq!(dest.pending_patches().count(), 1);
+        dest.set_pending_limits(2, 1);
+        assert_eq!(dest.pending_patches().count(), 0);
+        assert_eq!(dest.apply_or_queue(&patches[0]), Err(ParseError::BaseVersionUnknown));
+
+    }
+
+    #[test]
+    fn add_patches() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let mut patches = vec![];
+        for (i, s) in ["a", "b", "c", "d"].iter().enumerate() {
+            let v = doc.oplog.cg.version.clone();
+            doc.insert(seph, i, s);
+            patches.push(doc.oplog.encode_from(&EncodeOptions::patch(), v.as_ref()));
+        }
+
+        let mut dest = ListCRDT::new();
+        let order = [&patches[3], &patches[1], &patches[0], &patches[2], &patches[1]];
+        let v = dest.merge_patches(order.iter().map(|p| p.as_slice())).unwrap();
+        assert_eq!(v, doc.oplog.cg.version);
+        assert_eq!(dest.branch.content(), "abcd");
+        assert_eq!(dest.oplog.pending_patches().count(), 0);
+
+        // Patches whose parents never arrive are an error.
+        let mut dest = ListOpLog::new();
+        let result = dest.add_patches([patches[2].as_slice(), patches[0].as_slice()]);
+        let err = result.unwrap_err();
+        assert_eq!(err.error, ParseError::BaseVersionUnknown);
+        assert_eq!(dest.checkout_tip().content(), "a");
+        assert_eq!(err.added, dest.cg.version);
+        // Nothing is added if a patch can't be read.
+        let err = dest.add_patches([patches[1].as_slice(), b"hello there".as_slice()]).unwrap_err();
+        assert_eq!(err, AddPatchesError { error: ParseError::InvalidMagic, added: Frontier::root() });
+        assert_eq!(dest.checkout_tip().content(), "a");
+        assert_eq!(dest.add_patches([]), Ok(Frontier::root()));
+    }
+}
@@ -0,0 +1,231 @@
+//! Mapping between positions and items at historical versions.
+//!
+//! Positions in a document only make sense at some version. A [`PositionMap`] records which item
+//! (named by the LV of the operation which inserted it) is at each position at a version, so
+//! positions stored against old versions can be reinterpreted. To move a single position between
+//! versions, use [`ListOpLog::translate_position`].
+
+use rle::HasLength;
+use crate::{DTRange, Frontier, lv_to_usize, LV};
+use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
+use crate::list::{ListBranch, ListOpLog};
+use crate::listmerge::rewind::RewindTracker;
+
+/// The visible items in a document at some version, in document order. Created with
+/// [`PositionMap::new_at_version`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PositionMap {
+    version: Frontier,
+    /// (position of the first item, LV range). In document order.
+    visible: Vec<(usize, DTRange)>,
+    /// (LV range, position of the first item). Sorted by LV.
+    by_lv: Vec<(DTRange, usize)>,
+    len: usize,
+}
+
+impl PositionMap {
+    /// Build a position map for the document at the named version. This replays the document's
+    /// history, so its about as expensive as a checkout.
+    pub fn new_at_version(oplog: &ListOpLog, version: &[LV]) -> Self {
+        let version = oplog.cg.graph.find_dominators(version);
+        let mut tracker = RewindTracker::new();
+        tracker.move_to(oplog, version.as_ref());
+
+        let mut visible = vec![];
+        let mut len = 0;
+        for range in tracker.iter_visible() {
+            visible.push((len, range));
+            len += range.len();
+        }
+        let mut by_lv: Vec<_> = visible.iter().map(|(pos, range)| (*range, *pos)).collect();
+        by_lv.sort_unstable_by_key(|(range, _)| range.start);
+
+        Self { version, visible, by_lv, len }
+    }
+
+    pub fn version(&self) -> &[LV] {
+        self.version.as_ref()
+    }
+
+    /// The length of the document at this version, in unicode characters.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The LV of the insert which created the character at `pos`.
+    pub fn id_at(&self, pos: usize) -> Option<LV> {
+        let idx = self.visible.partition_point(|(start, range)| start + range.len() <= pos);
+        self.visible.get(idx).map(|(start, range)| range.start + (pos - start) as LV)
+    }
+
+    /// The position of the character inserted at `lv`. Returns None if the character isn't
+    /// visible at this version (it was deleted, or it hadn't been inserted yet).
+    pub fn position_of(&self, lv: LV) -> Option<usize> {
+        let idx = self.by_lv.partition_point(|(range, _)| range.end <= lv);
+        self.by_lv.get(idx)
+            .filter(|(range, _)| range.contains(lv))
+            .map(|(range, pos)| pos + lv_to_usize(lv - range.start))
+    }
+}
+
+impl ListBranch {
+    /// Get the ID of the character at `pos` in the branch, as an (agent name, seq) pair. Unlike
+    /// local versions, IDs are the same on every replica of the document - so they can be used to
+    /// refer to characters in a way which survives concurrent edits. See
+    /// [`ListOpLog::position_of_id`].
+    ///
+    /// The oplog must be the one this branch was checked out from. This needs to replay the
+    /// document's history - when looking up lots of characters, use a [`PositionMap`] instead.
+    pub fn id_at<'a>(&self, oplog: &'a ListOpLog, pos: usize) -> Option<RemoteVersion<'a>> {
+        PositionMap::new_at_version(oplog, self.version.as_ref())
+            .id_at(pos)
+            .map(|lv| oplog.cg.agent_assignment.local_to_remote_version(lv))
+    }
+}
+
+impl ListOpLog {
+    /// Find the position of the character with the given ID in the document at `version`.
+    /// Returns None if the ID is unknown, doesn't name an insert, or the character isn't visible
+    /// at that version.
+    pub fn position_of_id(&self, id: RemoteVersion, version: &[LV]) -> Option<usize> {
+        let lv = self.cg.agent_assignment.try_remote_to_local_version(id).ok()?;
+        PositionMap::new_at_version(self, version).position_of(lv)
+    }
+
+    /// Translate a position in the document at `from_version` to the corresponding position at
+    /// `to_version`. The versions don't need to be related - `to_version` can be older than, or
+    /// concurrent with `from_version`.
+    ///
+    /// Positions stick to the character before them. If that character has been deleted, the
+    /// position moves to just after the closest earlier character which is still visible.
+    pub fn translate_position(&self, pos: usize, from_version: &[LV], to_version: &[LV]) -> usize {
+        let mut tracker = RewindTracker::new();
+        tracker.move_to(self, self.cg.graph.find_dominators_2(from_version, to_version).as_ref());
+
+        // Find the number of items (visible or not) in document order up to the position.
+        tracker.move_to(self, from_version);
+        let mut remaining = pos;
+        let mut order = 0;
+        if remaining > 0 {
+            for (range, visible) in tracker.iter_items() {
+                if visible {
+                    if remaining <= range.len() {
+                        order += remaining;
+                        remaining = 0;
+                        break;
+                    }
+                    remaining -= range.len();
+                }
+                order += range.len();
+            }
+        }
+        assert_eq!(remaining, 0, "Position is past the end of the document");
+
+        // Then count the visible items before that point at the target version.
+        tracker.move_to(self, to_version);
+        let mut result = 0;
+        for (range, visible) in tracker.iter_items() {
+            if order == 0 { break; }
+            let len = range.len().min(order);
+            if visible { result += len; }
+            order -= len;
+        }
+        result
+    }
+
+    /// The position at `version` just before the character inserted at `item` (or just after it,
+    /// if `after` is set), and whether the character is visible at `version`. If the character
+    /// isn't visible, this is where it would be. Returns None if `item` doesn't name an insert.
+    pub(crate) fn item_boundary(&self, item: LV, version: &[LV], after: bool) -> Option<(usize, bool)> {
+        if item >= self.len() { return None; }
+        let mut tracker = RewindTracker::new();
+        tracker.move_to(self, self.cg.graph.find_dominators_2(version, &[item]).as_ref());
+        tracker.move_to(self, version);
+
+        let mut result = 0;
+        for (range, visible) in tracker.iter_items() {
+            if range.contains(item) {
+                let offset = lv_to_usize(item - range.start) + (after as usize);
+                return Some((result + if visible { offset } else { 0 }, visible));
+            }
+            if visible { result += range.len(); }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::LV;
+    use super::PositionMap;
+
+    #[test]
+    fn position_maps() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hello"); // 0..5
+        let v1 = oplog.cg.version.clone();
+        oplog.add_insert(seph, 5, " world"); // 5..11
+        oplog.add_insert(seph, 0, "oh "); // 11..14
+        oplog.add_delete_without_content(seph, 5..7); // 14..16, deletes "ll".
+        let v2 = oplog.cg.version.clone();
+        oplog.add_insert_at(mike, v1.as_ref(), 2, "XX"); // 16..18, concurrent.
+        assert_eq!(oplog.checkout_tip().content(), "oh heXXo world");
+
+        let map = PositionMap::new_at_version(&oplog, v2.as_ref());
+        assert_eq!(map.len(), 12);
+        assert_eq!(map.id_at(0), Some(11));
+        assert_eq!(map.id_at(3), Some(0));
+        assert_eq!(map.id_at(5), Some(4));
+        assert_eq!(map.id_at(12), None);
+        assert_eq!(map.position_of(4), Some(5));
+        assert_eq!(map.position_of(2), None); // Deleted.
+        assert_eq!(map.position_of(16), None); // Not in this version.
+
+        let tip = oplog.cg.version.clone();
+        let t = |pos, from: &[LV], to: &[LV]| oplog.translate_position(pos, from, to);
+        assert_eq!(t(0, v1.as_ref(), v2.as_ref()), 0);
+        assert_eq!(t(1, v1.as_ref(), v2.as_ref()), 4);
+        // The character before the position was deleted.
+        assert_eq!(t(3, v1.as_ref(), v2.as_ref()), 5);
+        assert_eq!(t(5, v1.as_ref(), v2.as_ref()), 6);
+        assert_eq!(t(5, v1.as_ref(), tip.as_ref()), 8);
+        // Backwards in time, and to a concurrent version.
+        assert_eq!(t(8, tip.as_ref(), v1.as_ref()), 5);
+        assert_eq!(t(4, &[17], v2.as_ref()), 5);
+        assert_eq!(t(2, v2.as_ref(), &[17]), 0);
+    }
+
+    #[test]
+    fn ids_are_stable_across_replicas() {
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, "hello");
+        let id = a.branch.id_at(&a.oplog, 1).unwrap();
+        assert_eq!(id, RemoteVersion("seph", 1));
+        assert_eq!(a.branch.id_at(&a.oplog, 5), None);
+
+        // Another replica has different agent IDs, and concurrent changes.
+        let mut b = ListCRDT::new();
+        let mike = b.get_or_create_agent_id("mike");
+        b.insert(mike, 0, "oh ");
+        b.merge_data_and_ff(&a.oplog.encode(&Default::default())).unwrap();
+        let pos = b.oplog.position_of_id(id, b.oplog.cg.version.as_ref()).unwrap();
+        assert_eq!(b.branch.content().borrow().slice_chars(pos..pos + 1).collect::<String>(), "e");
+
+        b.delete(mike, pos..pos + 1);
+        let version = b.oplog.cg.version.clone();
+        assert_eq!(b.oplog.position_of_id(id, version.as_ref()), None);
+        assert_eq!(b.oplog.position_of_id(RemoteVersion("seph", 100), version.as_ref()), None);
+        assert_eq!(b.oplog.position_of_id(RemoteVersion("fred", 0), version.as_ref()), None);
+        // Deletes aren't characters.
+        assert_eq!(b.oplog.position_of_id(RemoteVersion("mike", 3), version.as_ref()), None);
+    }
+}
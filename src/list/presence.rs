@@ -0,0 +1,238 @@
+//! Presence (awareness) information.
+//!
+//! Collaborative editors usually show where each other user's cursor is. This information is
+//! ephemeral - it isn't part of the document, it isn't stored in the oplog and it isn't saved to
+//! disk. But positions in a document only make sense at some version, so each [`Presence`] names
+//! the version its positions refer to. When the document advances, positions are transformed
+//! through the intervening changes.
+//!
+//! Presence updates have their own compact binary encoding, which is much smaller than a
+//! document patch and can be sent over an unreliable channel. Each update carries a counter, so
+//! updates which arrive out of order can be discarded by a [`PresenceSet`].
+
+use std::collections::BTreeMap;
+use smartstring::alias::String as SmartString;
+use crate::{Frontier, LV};
+use crate::encoding::bufparser::BufParser;
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::tools::push_str;
+use crate::encoding::varint::{push_lv, push_usize};
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+
+const PRESENCE_FORMAT_VERSION: usize = 0;
+
+/// The cursor / selection of a single user, along with any other ephemeral data the application
+/// wants to attach (eg, the user's name and colour).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Presence {
+    /// The name of the agent this presence belongs to.
+    pub agent: SmartString,
+    /// Incremented by the agent with every update. Used to discard stale updates.
+    pub clock: usize,
+    /// The version of the document which `anchor` and `head` refer to.
+    pub version: Frontier,
+    /// The fixed end of the selection. This is the same as `head` if nothing is selected.
+    pub anchor: usize,
+    /// The moving end of the selection (where the cursor is drawn).
+    pub head: usize,
+    /// Arbitrary application data.
+    pub data: Vec<u8>,
+}
+
+/// Transform a position in the document through a (transformed) operation.
+fn transform_pos(pos: usize, kind: ListOpKind, start: usize, len: usize) -> usize {
+    match kind {
+        ListOpKind::Ins => if start <= pos { pos + len } else { pos },
+        ListOpKind::Del => {
+            if start + len <= pos { pos - len }
+            else if start < pos { start }
+            else { pos }
+        }
+    }
+}
+
+impl Presence {
+    /// Create a cursor (with no selection) for the named agent.
+    pub fn new_cursor(agent: &str, version: &[LV], pos: usize) -> Self {
+        Self {
+            agent: agent.into(),
+            clock: 0,
+            version: Frontier::from_unsorted(version),
+            anchor: pos,
+            head: pos,
+            data: vec![],
+        }
+    }
+
+    /// Returns (anchor, head) transformed to refer to the document at `version`.
+    ///
+    /// If `version` doesn't contain this presence's version, the positions refer to the merge of
+    /// both versions.
+    pub fn positions_at(&self, oplog: &ListOpLog, version: &[LV]) -> (usize, usize) {
+        let mut anchor = self.anchor;
+        let mut head = self.head;
+        for (_, op) in oplog.iter_xf_operations_from(self.version.as_ref(), version) {
+            let Some(op) = op else { continue; };
+            anchor = transform_pos(anchor, op.kind, op.start(), op.end() - op.start());
+            head = transform_pos(head, op.kind, op.start(), op.end() - op.start());
+        }
+        (anchor, head)
+    }
+
+    /// Move this presence forward to `version`, transforming its positions.
+    pub fn transform_to(&mut self, oplog: &ListOpLog, version: &[LV]) {
+        (self.anchor, self.head) = self.positions_at(oplog, version);
+        self.version = oplog.cg.graph.find_dominators_2(self.version.as_ref(), version);
+    }
+
+    /// Encode this presence into a compact binary update. The oplog is used to name the version
+    /// in a way other peers understand.
+    pub fn encode(&self, oplog: &ListOpLog) -> Vec<u8> {
+        let mut result = vec![];
+        push_usize(&mut result, PRESENCE_FORMAT_VERSION);
+        push_str(&mut result, &self.agent);
+        push_usize(&mut result, self.clock);
+
+        let remote_version = oplog.cg.agent_assignment.local_to_remote_frontier(self.version.as_ref());
+        push_usize(&mut result, remote_version.len());
+        for rv in remote_version.iter() {
+            push_str(&mut result, rv.0);
+            push_lv(&mut result, rv.1);
+        }
+
+        push_usize(&mut result, self.anchor);
+        push_usize(&mut result, self.head);
+        push_usize(&mut result, self.data.len());
+        result.extend_from_slice(&self.data);
+        result
+    }
+
+    /// Decode a presence update created by [`encode`](Presence::encode).
+    ///
+    /// The update's version must be known to the oplog. If it isn't, this returns
+    /// [`ParseError::InvalidRemoteID`] - the update should be retried once the corresponding
+    /// operations have been merged in.
+    pub fn decode(oplog: &ListOpLog, data: &[u8]) -> Result<Self, ParseError> {
+        let mut reader = BufParser(data);
+        if reader.next_usize()? != PRESENCE_FORMAT_VERSION {
+            return Err(ParseError::UnsupportedProtocolVersion);
+        }
+
+        let agent = reader.next_str()?.into();
+        let clock = reader.next_usize()?;
+
+        let num_versions = reader.next_usize()?;
+        let mut version = Frontier::root();
+        for _ in 0..num_versions {
+            let name = reader.next_str()?;
+            let seq = reader.next_lv()?;
+            let v = oplog.cg.agent_assignment.try_remote_to_local_version((name, seq).into())
+                .map_err(ParseError::InvalidRemoteID)?;
+            version.merge_union(&[v], &oplog.cg.graph);
+        }
+
+        let anchor = reader.next_usize()?;
+        let head = reader.next_usize()?;
+        let data_len = reader.next_usize()?;
+        let data = reader.next_n_bytes(data_len)?.to_vec();
+        reader.expect_empty()?;
+
+        Ok(Self { agent, clock, version, anchor, head, data })
+    }
+}
+
+/// The latest presence of every remote agent.
+#[derive(Debug, Clone, Default)]
+pub struct PresenceSet {
+    peers: BTreeMap<SmartString, Presence>,
+}
+
+impl PresenceSet {
+    pub fn new() -> Self { Self::default() }
+
+    /// Store an update. Returns false (and ignores the update) if we already have a newer update
+    /// from the same agent.
+    pub fn apply(&mut self, presence: Presence) -> bool {
+        if let Some(existing) = self.peers.get(&presence.agent) {
+            if existing.clock >= presence.clock { return false; }
+        }
+        self.peers.insert(presence.agent.clone(), presence);
+        true
+    }
+
+    /// Decode and store an update. Returns the same as [`apply`](PresenceSet::apply).
+    pub fn apply_encoded(&mut self, oplog: &ListOpLog, data: &[u8]) -> Result<bool, ParseError> {
+        Ok(self.apply(Presence::decode(oplog, data)?))
+    }
+
+    /// Remove an agent (eg when they disconnect).
+    pub fn remove(&mut self, agent: &str) -> Option<Presence> {
+        self.peers.remove(agent)
+    }
+
+    pub fn get(&self, agent: &str) -> Option<&Presence> {
+        self.peers.get(agent)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Presence> + '_ {
+        self.peers.values()
+    }
+
+    pub fn len(&self) -> usize { self.peers.len() }
+
+    pub fn is_empty(&self) -> bool { self.peers.is_empty() }
+
+    /// Move every stored presence forward to `version`. Call this after merging changes into the
+    /// document, so positions are cheap to read while rendering.
+    pub fn transform_to(&mut self, oplog: &ListOpLog, version: &[LV]) {
+        for p in self.peers.values_mut() {
+            p.transform_to(oplog, version);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::ListCRDT;
+    use super::{Presence, PresenceSet};
+
+    #[test]
+    fn presence_transforms_and_round_trips() {
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, "hello world");
+
+        let mut b = ListCRDT::new();
+        b.merge_data_and_ff(&a.oplog.encode(&Default::default())).unwrap();
+
+        // Seph selects "world", and sends their presence to b.
+        let mut p = Presence::new_cursor("seph", a.oplog.local_frontier_ref(), 11);
+        p.anchor = 6;
+        p.data = b"blue".to_vec();
+        let mut set = PresenceSet::new();
+        assert_eq!(set.apply_encoded(&b.oplog, &p.encode(&a.oplog)), Ok(true));
+        assert_eq!(set.get("seph"), Some(&p));
+
+        // Stale updates are ignored.
+        assert_eq!(set.apply_encoded(&b.oplog, &p.encode(&a.oplog)), Ok(false));
+
+        // b edits the document. The selection moves with the text.
+        let mike = b.get_or_create_agent_id("mike");
+        b.insert(mike, 0, "oh ");
+        b.delete_without_content(mike, 9..11); // "hello world" -> "oh hello rld"
+        let received = set.get("seph").unwrap();
+        assert_eq!(received.positions_at(&b.oplog, b.oplog.local_frontier_ref()), (9, 12));
+
+        set.transform_to(&b.oplog, b.oplog.local_frontier_ref());
+        let received = set.get("seph").unwrap();
+        assert_eq!((received.anchor, received.head), (9, 12));
+        assert_eq!(received.version, b.oplog.local_frontier());
+
+        // Updates referring to unknown versions can't be decoded yet.
+        let mut p2 = Presence::new_cursor("mike", b.oplog.local_frontier_ref(), 0);
+        p2.clock = 1;
+        assert!(matches!(Presence::decode(&a.oplog, &p2.encode(&b.oplog)), Err(ParseError::InvalidRemoteID(_))));
+    }
+}
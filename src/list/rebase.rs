@@ -0,0 +1,125 @@
+//! Rebasing patches from shallow clones.
+//!
+//! A patch can only be merged if we have every operation it depends on. That isn't always true
+//! when peers have pruned their history - for example, edits made on a
+//! [shallow clone](ListOpLog::shallow_clone) which kept no history depend on the clone's snapshot
+//! operation, which no other peer has.
+//!
+//! A peer with the full history can still accept those edits using
+//! [`rebase_foreign_patch`](ListOpLog::rebase_foreign_patch), given the version the patch's author
+//! started from. The edits are transformed against everything which has happened since then, and
+//! returned as operations which apply to the current document. The caller then adds them as new
+//! local operations (using its own agent ID) - so they get new IDs, and the original operations
+//! are never merged.
+
+use std::mem::take;
+use crate::LV;
+use crate::encoding::parseerror::ParseError;
+use crate::list::ListOpLog;
+use crate::list::encoding::DecodeOptions;
+use crate::list::operation::TextOperation;
+
+impl ListOpLog {
+    /// Transform the operations in a patch so they can be applied to the document at the oplog's
+    /// current version. This lets a peer with the full history accept edits which depend on
+    /// history it doesn't have - like edits made on a [shallow clone](ListOpLog::shallow_clone).
+    ///
+    /// Snapshot operations the patch depends on (made by
+    /// [`squash_before`](ListOpLog::squash_before) when its author made a shallow clone) are
+    /// assumed to be `base_version_hint` - which should be the version (in this oplog) the patch's
+    /// author had when they made their changes. If the patch only depends on known operations, the
+    /// hint isn't used. Returns an error if the patch depends on any other unknown operations.
+    ///
+    /// Operations in the patch which are already in this oplog are skipped. Transformed positions
+    /// stored in the patch are relative to the author's history, so they're never used.
+    ///
+    /// The patch is merged into the oplog to transform it, then removed again. The oplog is left
+    /// unchanged. Add the returned operations as new local operations (using your own agent ID),
+    /// so they get new IDs.
+    pub fn rebase_foreign_patch(&mut self, patch: &[u8], base_version_hint: &[LV]) -> Result<Vec<TextOperation>, ParseError> {
+        let hint = self.cg.graph.find_dominators(base_version_hint);
+        let from = self.cg.version.clone();
+        let checkpoint = self.checkpoint();
+
+        // Rolling back only removes operations. The rest of the patch's metadata is kept aside.
+        let checkpoints = take(&mut self.checkpoints);
+        let named_branches = take(&mut self.named_branches);
+        let agent_info = take(&mut self.agent_info);
+        let was_shallow = self.is_shallow();
+
+        let opts = DecodeOptions { trust_transformed_positions: false, ..Default::default() };
+        let result = self.decode_and_add_internal(patch, opts, None, Some(hint.as_ref()))
+            .map(|_| {
+                self.iter_xf_operations_from(from.as_ref(), self.cg.version.as_ref())
+                    .filter_map(|(_, op)| op)
+                    .collect()
+            });
+
+        self.roll_back(checkpoint);
+        self.checkpoints = checkpoints;
+        self.named_branches = named_branches;
+        self.agent_info = agent_info;
+        if !was_shallow { self.pruned = Default::default(); }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::encoding::{EncodeOptions, ParseError};
+
+    #[test]
+    fn rebase_offline_edits() {
+        let mut server = ListCRDT::new();
+        let seph = server.get_or_create_agent_id("seph");
+        server.insert(seph, 0, "hello world");
+        let base = server.oplog.cg.version.clone();
+
+        // The client keeps no history, then makes some changes offline.
+        let mut client = server.oplog.shallow_clone(0);
+        let mike = client.get_or_create_agent_id("mike");
+        let client_v = client.cg.version.clone();
+        client.add_insert(mike, 5, ",");
+        client.add_delete_without_content(mike, 7..12);
+        client.add_insert(mike, 7, "there");
+        let patch = client.encode_from(&EncodeOptions::patch(), client_v.as_ref());
+        let xf_patch = client.encode_from(&EncodeOptions::patch().store_xf(true), client_v.as_ref());
+
+        // Meanwhile, the server has moved on.
+        server.insert(seph, 0, ">> ");
+        server.insert(seph, 14, "!");
+
+        assert_eq!(server.oplog.clone().decode_and_add(&patch), Err(ParseError::BaseVersionUnknown));
+        let len = server.oplog.len();
+        let ops = server.oplog.rebase_foreign_patch(&patch, base.as_ref()).unwrap();
+        assert_eq!(server.oplog.rebase_foreign_patch(&xf_patch, base.as_ref()).unwrap(), ops);
+
+        // The oplog isn't changed, and the rebased operations are added under the server's own
+        // agent.
+        assert_eq!(server.oplog.len(), len);
+        assert!(server.oplog.get_agent_id("mike").is_none());
+        let rebased = server.get_or_create_agent_id("rebased");
+        server.apply_local_operations(rebased, &ops);
+        assert_eq!(server.branch.content().to_string(), ">> hello, there!");
+
+        // Patches which don't need the hint are merged normally.
+        let mut peer = ListOpLog::new();
+        peer.decode_and_add(&server.oplog.encode(&EncodeOptions::patch())).unwrap();
+        let v = peer.cg.version.clone();
+        let alice = peer.get_or_create_agent_id("alice");
+        peer.add_insert(alice, 0, "$");
+        let patch = peer.encode_from(&EncodeOptions::patch(), v.as_ref());
+        let ops = server.oplog.rebase_foreign_patch(&patch, &[]).unwrap();
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].content_as_str(), Some("$"));
+
+        // But patches which depend on other operations we don't have are rejected.
+        let v = peer.cg.version.clone();
+        peer.add_insert(alice, 0, "$");
+        let patch = peer.encode_from(&EncodeOptions::patch(), v.as_ref());
+        let len = server.oplog.len();
+        assert_eq!(server.oplog.rebase_foreign_patch(&patch, base.as_ref()), Err(ParseError::BaseVersionUnknown));
+        assert_eq!(server.oplog.len(), len);
+    }
+}
@@ -0,0 +1,115 @@
+//! Redacting content.
+//!
+//! Sometimes inserted text has to be erased from a document's history (for example, to comply with
+//! a request to erase someone's personal data). Simply deleting the text isn't enough, since the
+//! oplog keeps the content of every insert forever.
+//!
+//! Redacting an operation removes its content from the oplog but keeps the operation itself, so the
+//! causal graph and the position of every item are unchanged. The content of redacted inserts is
+//! unknown - it isn't encoded, and it shows up as [`PLACEHOLDER_CHAR`] when checking out the
+//! document. Merging still works, since merging only needs the length of each insert.
+
+use rle::HasLength;
+use crate::{AgentId, DTRange, LV};
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::rle::KVPair;
+
+/// Inserted text whose content is unknown (eg, because it was redacted) is replaced by this
+/// character when checking out a document.
+pub const PLACEHOLDER_CHAR: char = '\u{FFFD}';
+
+/// A string of `len` placeholder characters, to stand in for unknown content.
+pub(crate) fn placeholder_str(len: usize) -> String {
+    std::iter::repeat(PLACEHOLDER_CHAR).take(len).collect()
+}
+
+impl ListOpLog {
+    /// Remove the content of all operations in `ranges`. Redacted inserts keep their length, but
+    /// their content becomes unknown. Redacted deletes forget which characters they deleted.
+    ///
+    /// Note this only redacts the named operations. Content of redacted inserts may still be stored
+    /// with later deletes made by other agents. (See
+    /// [`prune_deleted_content`](ListOpLog::prune_deleted_content)). Peers which already have the
+    /// operations will also keep their copy of the content.
+    ///
+    /// Returns the number of characters of content which were removed.
+    ///
+    /// Panics if the ranges contain unknown operations.
+    pub fn redact_content(&mut self, ranges: &[DTRange]) -> usize {
+        let mut ranges = ranges.iter().copied().filter(|r| !r.is_empty()).collect::<Vec<_>>();
+        assert!(ranges.iter().all(|r| r.end <= self.len()), "Cannot redact unknown operations");
+        if ranges.is_empty() { return 0; }
+        ranges.sort_unstable_by_key(|r| r.start);
+
+        let redacted = self.drop_content(&ranges, |in_ranges, _| in_ranges);
+
+        #[cfg(feature = "dag_hash")]
+        self.hash_cache.clear();
+        redacted
+    }
+
+    /// Find the inserts whose content isn't known. This happens when inserts are redacted, or
+    /// loaded from a file which omitted their content (see
+    /// [`EncodeOptions::omit_content_for`](crate::list::encoding::EncodeOptions::omit_content_for)).
+    ///
+    /// Deletes aren't included, since their content is usually not stored anyway.
+    pub fn ranges_missing_content(&self) -> Vec<DTRange> {
+        let mut result: Vec<DTRange> = vec![];
+        for KVPair(lv, op) in self.operations.iter() {
+            if op.kind != ListOpKind::Ins || op.content_pos.is_some() { continue; }
+            let range: DTRange = (*lv..*lv + op.len() as LV).into();
+            match result.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => result.push(range),
+            }
+        }
+        result
+    }
+
+    /// Remove the content of every operation made by `agent`. See
+    /// [`redact_content`](ListOpLog::redact_content).
+    pub fn redact_agent_content(&mut self, agent: AgentId) -> usize {
+        let ranges = self.cg.agent_assignment.client_data[agent as usize].lv_for_seq
+            .iter()
+            .map(|KVPair(_, range)| *range)
+            .collect::<Vec<_>>();
+        self.redact_content(&ranges)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::encoding::EncodeOptions;
+    use super::PLACEHOLDER_CHAR;
+
+    #[test]
+    fn redact_agent_content() {
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        let mike = a.get_or_create_agent_id("mike");
+        a.insert(seph, 0, "hi ");
+        a.insert(mike, 3, "secret");
+        a.insert(seph, 9, "!");
+        a.delete(seph, 0..1);
+        let b = ListCRDT::load_from(&a.oplog.encode(&EncodeOptions::default())).unwrap();
+
+        assert_eq!(a.oplog.redact_agent_content(mike), 6);
+        a.oplog.dbg_check(true);
+        assert_eq!(a.oplog.redact_agent_content(mike), 0);
+        assert_eq!(a.oplog.ranges_missing_content(), [(3..9).into()]);
+        let redacted = format!("i {}!", PLACEHOLDER_CHAR.to_string().repeat(6));
+        assert_eq!(a.oplog.checkout_tip().content(), redacted.as_str());
+
+        // Redacted oplogs can still be encoded and merged with concurrent changes.
+        let data = a.oplog.encode(&EncodeOptions::default());
+        let mut c = ListOpLog::load_from(&data).unwrap();
+        let zed = c.get_or_create_agent_id("zed");
+        c.add_insert_at(zed, &[9], 4, "ABC");
+        let mut merged = b.oplog.clone();
+        merged.decode_and_add(&c.encode_from(&EncodeOptions::patch(), b.oplog.local_frontier_ref())).unwrap();
+        assert_eq!(merged.checkout_tip().content(), "i sABCecret!");
+        assert_eq!(c.checkout_tip().content().len_chars(), 12);
+    }
+}
@@ -0,0 +1,176 @@
+//! Shallow clones.
+//!
+//! Clients often only need a document's current content and its recent history. A shallow clone
+//! (see [`shallow_clone`](ListOpLog::shallow_clone)) replaces all the history before some version
+//! with a single insert of the document's content at that version (like
+//! [`squash_before`](ListOpLog::squash_before)), and remembers the IDs of the operations which
+//! were pruned.
+//!
+//! The shallow oplog can merge changes from peers as normal, so long as the changes only depend on
+//! operations it still has. Data which depends on pruned operations is rejected with
+//! [`ParseError::HistoryPruned`], so the client knows to ask a peer with the full history instead.
+//!
+//! The pruned IDs are saved with the document. But they're only loaded into empty oplogs - merging
+//! a shallow document's changes into a full document doesn't make it shallow.
+
+use std::collections::BTreeMap;
+use smartstring::alias::String as SmartString;
+use rle::{AppendRle, HasLength};
+use crate::{AgentId, DTRange, lv_to_usize, LV};
+use crate::encoding::parseerror::ParseError;
+use crate::list::ListOpLog;
+
+/// The sequence numbers of each agent's pruned operations, by agent name.
+pub(crate) type PrunedHistory = BTreeMap<SmartString, Vec<DTRange>>;
+
+impl ListOpLog {
+    /// Find the largest `k <= max` where the history can be cleanly split into `0..k` and
+    /// everything after, so every operation after the split comes after every operation before it.
+    pub(crate) fn latest_clean_split(&self, max: LV) -> LV {
+        let mut result = 0;
+        for (span, linear_end) in self.cg.graph.linear_history() {
+            if span.start >= max { break; }
+            let k = linear_end.min(max);
+            if k > span.start { result = k; }
+        }
+        result
+    }
+
+    /// Create a shallow clone of this oplog, which keeps (at least) the last `keep_ops`
+    /// operations. All the history before that is replaced with a snapshot of the document.
+    ///
+    /// The clone can merge changes from peers as normal, so long as they only depend on operations
+    /// it still has. Data which depends on pruned operations is rejected with
+    /// [`ParseError::HistoryPruned`].
+    ///
+    /// More operations are kept if the history was concurrent at the requested point. Changes made
+    /// on the clone always depend on the kept operations (never the snapshot), so they can be
+    /// merged back into the full document. To make sure of this, `keep_ops` should be at least 1.
+    pub fn shallow_clone(&self, keep_ops: usize) -> ListOpLog {
+        self.shallow_clone_at(self.latest_clean_split(self.len().saturating_sub(keep_ops as LV)))
+    }
+
+    /// Make a shallow clone with the operations in `0..k` pruned. The history must split cleanly
+    /// at `k`. (See [`latest_clean_split`](ListOpLog::latest_clean_split)).
+    pub(crate) fn shallow_clone_at(&self, k: LV) -> ListOpLog {
+        let mut result = if k == 0 {
+            self.clone()
+        } else {
+            let mut result = self.squash_before(&[k - 1]).unwrap();
+            result.pruned = self.pruned.clone();
+            result
+        };
+
+        for span in self.iter_agent_mappings_range((0..k).into()) {
+            let name = self.get_agent_name(span.agent);
+            result.pruned.entry(name.into()).or_default().push_rle(span.seq_range);
+        }
+        for ranges in result.pruned.values_mut() {
+            ranges.sort_unstable_by_key(|r| r.start);
+        }
+        result
+    }
+
+    /// Create a shallow clone of this oplog which keeps every operation made at or after
+    /// `timestamp`, based on the timestamps set with
+    /// [`set_op_metadata`](ListOpLog::set_op_metadata). Operations without timestamps are kept
+    /// if they come after the first operation with a recent enough timestamp.
+    pub fn shallow_clone_since(&self, timestamp: u64) -> ListOpLog {
+        let first_kept = self.op_metadata.iter()
+            .find(|(_, m)| m.timestamp.is_some_and(|t| t >= timestamp))
+            .map_or(self.len(), |(range, _)| range.start);
+        self.shallow_clone(lv_to_usize(self.len() - first_kept))
+    }
+
+    /// Has any history been pruned from this oplog? (Ie, is this a shallow clone?)
+    pub fn is_shallow(&self) -> bool {
+        !self.pruned.is_empty()
+    }
+
+    /// Check if the named operation was pruned from this oplog when it was shallow cloned.
+    pub fn is_pruned(&self, agent_name: &str, seq: LV) -> bool {
+        self.pruned.get(agent_name).is_some_and(|ranges| {
+            ranges.iter().any(|r| r.contains(seq))
+        })
+    }
+
+    /// The error for data naming an operation we don't have.
+    pub(crate) fn unknown_id_error(&self, agent: AgentId, seq: LV, otherwise: ParseError) -> ParseError {
+        if self.is_pruned(self.get_agent_name(agent), seq) {
+            ParseError::HistoryPruned
+        } else { otherwise }
+    }
+
+    pub(crate) fn set_pruned_history(&mut self, pruned: PrunedHistory) {
+        debug_assert!(self.pruned.is_empty());
+        self.pruned = pruned;
+    }
+
+    pub(crate) fn iter_pruned(&self) -> impl Iterator<Item = (&str, &[DTRange])> + '_ {
+        self.pruned.iter().map(|(name, ranges)| (name.as_str(), ranges.as_slice()))
+    }
+
+    #[allow(unused)]
+    pub(crate) fn pruned_len(&self) -> usize {
+        self.pruned.values().flatten().map(|r| r.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListOpLog, OpMetadata};
+    use crate::lv_to_usize;
+    use crate::list::encoding::{EncodeOptions, ParseError};
+
+    #[test]
+    fn shallow_clones() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hello");
+        let old = oplog.cg.version.clone();
+        oplog.add_insert_at(seph, &[4], 5, " world"); // 5..11
+        oplog.add_insert_at(mike, &[4], 0, "oh "); // 11..14, concurrent.
+        oplog.add_delete_at(seph, &[10, 13], 0..3); // 14..17
+        oplog.add_insert(mike, 0, "!"); // 17
+
+        // We can't split inside the concurrent region, so more operations are kept.
+        let shallow = oplog.shallow_clone(5);
+        assert!(shallow.is_shallow());
+        assert!(shallow.is_pruned("seph", 4));
+        assert!(!shallow.is_pruned("seph", 5));
+        assert_eq!(shallow.pruned_len(), 5);
+        assert_eq!(shallow.checkout_tip().content(), oplog.checkout_tip().content());
+
+        let data = shallow.encode(&EncodeOptions::default());
+        let mut loaded = ListOpLog::load_from(&data).unwrap();
+        assert!(loaded.is_pruned("seph", 4));
+        assert_eq!(loaded.checkout_tip().content(), oplog.checkout_tip().content());
+
+        // Changes based on kept operations merge in both directions.
+        let mut full = oplog.clone();
+        let seph2 = full.get_or_create_agent_id("seph");
+        let v = full.cg.version.clone();
+        full.add_insert(seph2, 0, "1");
+        loaded.decode_and_add(&full.encode_from(&EncodeOptions::patch(), v.as_ref())).unwrap();
+        let mike2 = loaded.get_agent_id("mike").unwrap();
+        let v = loaded.cg.version.clone();
+        loaded.add_insert(mike2, 0, "2");
+        full.decode_and_add(&loaded.encode_from(&EncodeOptions::patch(), v.as_ref())).unwrap();
+        assert!(!full.is_shallow());
+        assert_eq!(full.checkout_tip().content(), loaded.checkout_tip().content());
+
+        // But changes based on pruned operations are rejected.
+        let mut stale = oplog.clone();
+        stale.add_insert_at(seph, old.as_ref(), 0, "x");
+        let patch = stale.encode_from(&EncodeOptions::patch(), old.as_ref());
+        assert_eq!(loaded.decode_and_add(&patch), Err(ParseError::HistoryPruned));
+
+        let all = oplog.shallow_clone(lv_to_usize(oplog.len()));
+        assert!(!all.is_shallow());
+        assert_eq!(all.len(), oplog.len());
+
+        oplog.set_op_metadata((17..18).into(), OpMetadata::at_time(1000));
+        assert_eq!(oplog.shallow_clone_since(1000).pruned_len(), 17);
+    }
+}
@@ -0,0 +1,197 @@
+//! A thread-safe document handle.
+//!
+//! [`SharedListCRDT`] wraps a document's oplog and branch with locks, so it can be shared between
+//! threads (eg in an `Arc`). The concurrency contract is:
+//!
+//! - Edits are serialized. Only one writer can modify the document at a time.
+//! - Readers take a [`ReadOnlyOpLog`] snapshot, which only holds a lock long enough to clone an
+//!   `Arc`. Readers can then check out branches, encode patches and so on without blocking the
+//!   writer (and without being blocked by it).
+//! - Each snapshot sees a consistent version of the document. Reads are versioned - they're
+//!   either at the snapshot's current version or at a named version in the past.
+//!
+//! Edits write lock the oplog while operations are added to it. Merging the new operations into
+//! the document's branch (the slow part of merging remote changes) only needs a read lock, so
+//! readers don't wait for it.
+//!
+//! If the document is edited while snapshots are alive, the writer copies the oplog before
+//! editing it (copy-on-write). So drop snapshots when you're done with them.
+
+use std::ops::Range;
+use std::sync::{Mutex, RwLock};
+use crate::{AgentId, Frontier, LV};
+use crate::encoding::parseerror::ParseError;
+use crate::list::{CowOpLog, ListBranch, ListCRDT, ListOpLog, ReadOnlyOpLog};
+
+/// A list CRDT which can be shared between threads (eg in an `Arc`).
+///
+/// Edits are serialized, so only one writer modifies the document at a time. Readers take a
+/// [`ReadOnlyOpLog`] snapshot with [`snapshot`](SharedListCRDT::snapshot), which sees a consistent
+/// version of the document and doesn't block (or get blocked by) the writer. If the document is
+/// edited while snapshots are alive, the oplog is copied first - so drop snapshots when you're
+/// done with them.
+#[derive(Debug, Default)]
+pub struct SharedListCRDT {
+    // Lock order: branch, then oplog.
+    branch: Mutex<ListBranch>,
+    oplog: RwLock<CowOpLog>,
+}
+
+impl From<ListCRDT> for SharedListCRDT {
+    fn from(doc: ListCRDT) -> Self {
+        Self {
+            branch: Mutex::new(doc.branch),
+            oplog: RwLock::new(doc.oplog.into()),
+        }
+    }
+}
+
+impl SharedListCRDT {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_from(bytes: &[u8]) -> Result<Self, ParseError> {
+        Ok(ListCRDT::load_from(bytes)?.into())
+    }
+
+    pub fn into_inner(self) -> ListCRDT {
+        ListCRDT {
+            branch: self.branch.into_inner().unwrap(),
+            oplog: self.oplog.into_inner().unwrap().into_oplog(),
+        }
+    }
+
+    /// Take a read-only snapshot of the document's oplog. This is cheap, and the snapshot can be
+    /// sent to other threads.
+    pub fn snapshot(&self) -> ReadOnlyOpLog {
+        self.oplog.read().unwrap().fork_read_only()
+    }
+
+    /// The current version of the document.
+    pub fn version(&self) -> Frontier {
+        self.oplog.read().unwrap().local_frontier()
+    }
+
+    /// The current content of the document.
+    pub fn content(&self) -> String {
+        self.branch.lock().unwrap().content().to_string()
+    }
+
+    /// Check out the document at some version, without blocking writers.
+    pub fn checkout_at(&self, version: &[LV]) -> ListBranch {
+        self.snapshot().checkout_at(version)
+    }
+
+    /// Modify the document's oplog. The branch is then merged up to the oplog's new version.
+    ///
+    /// The oplog is write locked while `f` runs, so keep `f` short. The branch is merged
+    /// afterwards with only a read lock held. Other writers (and calls to
+    /// [`content`](Self::content)) wait for the merge to finish, but snapshot reads don't.
+    pub fn edit<R>(&self, f: impl FnOnce(&mut ListOpLog) -> R) -> R {
+        let mut branch = self.branch.lock().unwrap();
+        let result = f(self.oplog.write().unwrap().make_mut());
+        // Writers are serialized by the branch lock, so the oplog can't change before the merge.
+        let oplog = self.oplog.read().unwrap();
+        branch.merge(&oplog, oplog.local_frontier_ref());
+        result
+    }
+
+    /// Make a small edit to the oplog and branch in place. The oplog is write locked for the
+    /// duration of the call.
+    fn edit_in_place<R>(&self, f: impl FnOnce(&mut ListOpLog, &mut ListBranch) -> R) -> R {
+        let mut branch = self.branch.lock().unwrap();
+        let mut oplog = self.oplog.write().unwrap();
+        f(oplog.make_mut(), &mut branch)
+    }
+
+    pub fn get_or_create_agent_id(&self, name: &str) -> AgentId {
+        self.edit_in_place(|oplog, _| oplog.get_or_create_agent_id(name))
+    }
+
+    pub fn insert(&self, agent: AgentId, pos: usize, ins_content: &str) -> LV {
+        self.edit_in_place(|oplog, branch| branch.insert(oplog, agent, pos, ins_content))
+    }
+
+    pub fn delete(&self, agent: AgentId, range: Range<usize>) -> LV {
+        self.edit_in_place(|oplog, branch| branch.delete(oplog, agent, range))
+    }
+
+    /// Merge remote changes into the document. See [`ListCRDT::merge_data_and_ff`].
+    pub fn merge_data_and_ff(&self, bytes: &[u8]) -> Result<Frontier, ParseError> {
+        self.edit(|oplog| oplog.decode_and_add(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+    use crate::list::encoding::EncodeOptions;
+    use crate::lv_to_usize;
+    use super::SharedListCRDT;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn concurrent_readers_and_writer() {
+        assert_send_sync::<SharedListCRDT>();
+
+        let doc = Arc::new(SharedListCRDT::new());
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hi");
+        let v1 = doc.version();
+
+        let writer = {
+            let doc = doc.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    doc.insert(seph, i % 3, "x");
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let doc = doc.clone();
+            let v1 = v1.clone();
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    // Each snapshot is internally consistent. (There are no deletes yet, so the
+                    // document length matches the number of operations.)
+                    let snapshot = doc.snapshot();
+                    let tip = snapshot.checkout_tip();
+                    assert_eq!(tip.len(), lv_to_usize(snapshot.len()));
+                    assert_eq!(doc.checkout_at(v1.as_ref()).content(), "hi");
+                }
+            })
+        }).collect();
+
+        writer.join().unwrap();
+        for r in readers { r.join().unwrap(); }
+
+        assert_eq!(doc.content().len(), 102);
+        let data = doc.snapshot().encode(&EncodeOptions::default());
+
+        let other = SharedListCRDT::load_from(&data).unwrap();
+        let mike = other.get_or_create_agent_id("mike");
+        other.delete(mike, 0..2);
+        doc.merge_data_and_ff(&other.snapshot().encode(&EncodeOptions::default())).unwrap();
+        assert_eq!(doc.content().len(), 100);
+
+        let doc = Arc::try_unwrap(doc).unwrap().into_inner();
+        assert_eq!(doc.oplog.checkout_tip().content(), doc.branch.content());
+    }
+
+    #[test]
+    fn snapshots_keep_their_version() {
+        let doc = SharedListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hi");
+
+        let before = doc.snapshot();
+        doc.edit(|oplog| { oplog.add_insert(seph, 2, " there"); });
+        assert_eq!(before.checkout_tip().content(), "hi");
+        assert_eq!(doc.snapshot().checkout_tip().content(), "hi there");
+        assert_eq!(doc.content(), "hi there");
+    }
+}
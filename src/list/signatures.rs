@@ -0,0 +1,338 @@
+//! Signed operations.
+//!
+//! Each agent can sign the operations it authored with its own key. Signatures are stored in the
+//! oplog, saved with the document and sent along with patches - so a document can be relayed
+//! through an untrusted server, and the receiver can still check who wrote what.
+//!
+//! A signature covers a contiguous range of an agent's sequence numbers. The signed message is a
+//! canonical description of the operations in that range (their positions, inserted content and
+//! parents), which doesn't depend on how the operations happen to be stored locally. Deleted
+//! content isn't signed, since its usually not sent over the network.
+//!
+//! Signatures stored in the oplog aren't trusted by themselves - a document loaded without a
+//! verifier could contain anything. Decoding with a verifier checks every signature it relies on,
+//! including ones which were stored earlier.
+//!
+//! Diamond types doesn't ship any cryptography itself. Implement [`OpSigner`] and
+//! [`SignatureVerifier`] using your preferred library (eg ed25519).
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use rle::{HasLength, SplitableSpanCtx};
+use smartstring::alias::String as SmartString;
+use crate::{AgentId, DTRange, LV};
+use crate::encoding::tools::push_str;
+use crate::encoding::varint::{push_lv, push_usize};
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::rle::KVPair;
+
+/// Prefix for signed messages, so signatures can't be confused with anything else signed with the
+/// same key.
+const SIGNATURE_DOMAIN: &[u8] = b"diamond-types list ops v0";
+
+/// Signs operations on behalf of a single agent.
+pub trait OpSigner {
+    fn public_key(&self) -> Vec<u8>;
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+}
+
+/// Checks signatures on decode.
+pub trait SignatureVerifier: Debug + Send + Sync {
+    /// Returns true if `signature` is a valid signature of `message` using `public_key`, and the
+    /// key is trusted to sign operations for the named agent.
+    fn verify(&self, agent: &str, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// A signature over a range of operations authored by one agent.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SignedRange {
+    pub agent: SmartString,
+    pub seq_range: DTRange,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl ListOpLog {
+    /// Returns the message signed for the named agent's operations in `seq_range`, or None if some
+    /// of those operations (or their inserted content) are missing.
+    pub(crate) fn signed_message(&self, agent: &str, seq_range: DTRange) -> Option<Vec<u8>> {
+        let aa = &self.cg.agent_assignment;
+        let agent_id = self.get_agent_id(agent)?;
+
+        let mut message = SIGNATURE_DOMAIN.to_vec();
+        push_str(&mut message, agent);
+        push_lv(&mut message, seq_range.start);
+        push_lv(&mut message, seq_range.end);
+
+        let mut seq = seq_range.start;
+        while seq < seq_range.end {
+            let lv_range = aa.client_data[agent_id as usize].lv_for_seq.find_with_offset(seq)
+                .map(|(KVPair(s, lvs), offset)| {
+                    let offset = offset as LV;
+                    let len = (lvs.len() as LV - offset).min(seq_range.end - seq);
+                    debug_assert!(*s + offset == seq);
+                    DTRange::from(lvs.start + offset..lvs.start + offset + len)
+                })?;
+
+            let chunk_end = seq + lv_range.len() as LV;
+            for (KVPair(mut lv, mut op), _) in self.iter_range_simple(lv_range) {
+                // Split the operation into individual items, so the message doesn't depend on how
+                // operations were run-length encoded.
+                loop {
+                    let rest = (op.len() > 1).then(|| op.truncate_ctx(1, &self.operation_ctx));
+
+                    push_usize(&mut message, op.kind as usize);
+                    push_usize(&mut message, op.start());
+                    if op.kind == ListOpKind::Ins {
+                        let content = op.content_pos?;
+                        push_str(&mut message, self.operation_ctx.get_str(op.kind, content));
+                    }
+
+                    let parents = self.cg.graph.parents_at_version(lv);
+                    let prev = (seq > 0).then(|| (agent_id, seq - 1));
+                    if parents.len() == 1 && Some(aa.local_to_agent_version(parents[0])) == prev {
+                        push_usize(&mut message, 0);
+                    } else {
+                        let mut remote: Vec<_> = aa.local_to_remote_frontier(parents.as_ref())
+                            .into_iter().collect();
+                        remote.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+                        push_usize(&mut message, remote.len() + 1);
+                        for rv in remote {
+                            push_str(&mut message, rv.0);
+                            push_lv(&mut message, rv.1);
+                        }
+                    }
+
+                    lv += 1;
+                    seq += 1;
+                    match rest {
+                        Some(rest) => op = rest,
+                        None => break,
+                    }
+                }
+            }
+            if seq != chunk_end { return None; }
+        }
+
+        Some(message)
+    }
+
+    /// Sign all of the agent's operations which haven't been signed yet. Returns the newly signed
+    /// range of sequence numbers, or None if there was nothing to sign.
+    ///
+    /// Signatures cover inserted content, so this fails with [`SignError::ContentUnknown`] if the
+    /// content of any of the operations isn't known.
+    ///
+    /// Signing is O(n) with the number of operations being signed.
+    pub fn sign_operations(&mut self, agent: AgentId, signer: &dyn OpSigner) -> Result<Option<DTRange>, SignError> {
+        let name = self.get_agent_name(agent);
+        let start = self.signatures.iter()
+            .filter(|s| s.agent == name)
+            .map(|s| s.seq_range.end)
+            .max().unwrap_or(0);
+        let end = self.cg.agent_assignment.client_data[agent as usize].get_next_seq();
+        if start >= end { return Ok(None); }
+
+        let seq_range: DTRange = (start..end).into();
+        let message = self.signed_message(name, seq_range)
+            .ok_or(SignError::ContentUnknown)?;
+        let signed = SignedRange {
+            agent: name.into(),
+            seq_range,
+            public_key: signer.public_key(),
+            signature: signer.sign(&message),
+        };
+        self.signatures.push(signed);
+        Ok(Some(seq_range))
+    }
+
+    /// All the signatures known to this oplog. These haven't necessarily been checked - use
+    /// [`verify_signature`](ListOpLog::verify_signature).
+    pub fn signatures(&self) -> &[SignedRange] {
+        &self.signatures
+    }
+
+    /// Check a signature against the operations stored in this oplog.
+    ///
+    /// If we know the agent's public key (from its [`AgentInfo`](crate::list::AgentInfo)), the
+    /// signature must use that key.
+    pub fn verify_signature(&self, signed: &SignedRange, verifier: &dyn SignatureVerifier) -> bool {
+        let known_key = self.agent_info.get(&signed.agent).and_then(|(_, info)| info.public_key.as_ref());
+        if known_key.is_some_and(|key| *key != signed.public_key) { return false; }
+
+        self.signed_message(&signed.agent, signed.seq_range).is_some_and(|message| {
+            verifier.verify(&signed.agent, &signed.public_key, &message, &signed.signature)
+        })
+    }
+
+    /// Check the signature at `idx` in `self.signatures`. `verified` caches the result of each
+    /// check, so a signature is only verified once no matter how many ranges it covers.
+    pub(crate) fn signature_is_valid(&self, idx: usize, verifier: &dyn SignatureVerifier, verified: &mut Vec<Option<bool>>) -> bool {
+        if verified.len() < self.signatures.len() { verified.resize(self.signatures.len(), None); }
+        *verified[idx].get_or_insert_with(|| self.verify_signature(&self.signatures[idx], verifier))
+    }
+
+    /// Returns true if every operation in `range` is covered by a valid signature we have. See
+    /// [`signature_is_valid`](ListOpLog::signature_is_valid) for `verified`.
+    pub(crate) fn range_is_signed(&self, range: DTRange, verifier: &dyn SignatureVerifier, verified: &mut Vec<Option<bool>>) -> bool {
+        self.iter_agent_mappings_range(range).all(|span| {
+            let name = self.get_agent_name(span.agent);
+            let mut seq = span.seq_range.start;
+            while seq < span.seq_range.end {
+                let Some(idx) = (0..self.signatures.len())
+                    .filter(|&i| {
+                        let s = &self.signatures[i];
+                        s.agent == name && s.seq_range.start <= seq && seq < s.seq_range.end
+                    })
+                    .find(|&i| self.signature_is_valid(i, verifier, verified))
+                    else { return false; };
+                seq = self.signatures[idx].seq_range.end;
+            }
+            true
+        })
+    }
+
+    /// Add a signature (if we don't already have it). Returns its index in `self.signatures`.
+    pub(crate) fn add_signature(&mut self, signed: SignedRange) -> usize {
+        self.signatures.iter().position(|s| *s == signed).unwrap_or_else(|| {
+            self.signatures.push(signed);
+            self.signatures.len() - 1
+        })
+    }
+}
+
+/// The error returned by [`ListOpLog::sign_operations`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SignError {
+    /// Some of the operations to sign are inserts whose content isn't known.
+    ContentUnknown,
+}
+
+impl Display for SignError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SignError {:?}", self)
+    }
+}
+
+impl Error for SignError {}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::encoding::{DecodeOptions, EncodeOptions};
+    use crate::list::{AgentInfo, ListCRDT, ListOpLog};
+    use super::{OpSigner, SignatureVerifier, SignError};
+
+    /// A toy signature scheme. The "signature" is a checksum of the key and message.
+    #[derive(Debug)]
+    struct ToyKey(Vec<u8>);
+
+    fn toy_sign(key: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut data = key.to_vec();
+        data.extend_from_slice(message);
+        crate::encoding::tools::calc_checksum(&data).to_le_bytes().to_vec()
+    }
+
+    impl OpSigner for ToyKey {
+        fn public_key(&self) -> Vec<u8> { self.0.clone() }
+        fn sign(&self, message: &[u8]) -> Vec<u8> { toy_sign(&self.0, message) }
+    }
+
+    /// Trusts key "seph" for agent seph, and key "mike" for agent mike.
+    #[derive(Debug)]
+    struct ToyVerifier;
+
+    impl SignatureVerifier for ToyVerifier {
+        fn verify(&self, agent: &str, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+            agent.as_bytes() == public_key && toy_sign(public_key, message) == signature
+        }
+    }
+
+    fn verify_opts() -> DecodeOptions {
+        DecodeOptions { verify_signatures: Some(Arc::new(ToyVerifier)), ..Default::default() }
+    }
+
+    #[test]
+    fn signed_ops_round_trip() {
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, "hello");
+        a.delete(seph, 1..3);
+        assert_eq!(a.oplog.sign_operations(seph, &ToyKey(b"seph".to_vec())), Ok(Some((0..7).into())));
+        assert_eq!(a.oplog.sign_operations(seph, &ToyKey(b"seph".to_vec())), Ok(None));
+
+        let data = a.oplog.encode(&EncodeOptions::default());
+        let mut b = ListOpLog::load_from_opts(&data, verify_opts()).unwrap();
+        assert_eq!(b.signatures(), a.oplog.signatures());
+        assert!(b.verify_signature(&b.signatures()[0], &ToyVerifier));
+
+        // Concurrent edits from mike. Patches relayed from b to a carry mike's signature.
+        let mike = b.get_or_create_agent_id("mike");
+        let v = b.local_frontier();
+        b.add_insert_at(mike, &[], 0, "yo ");
+        b.add_insert_at(mike, v.as_ref(), 2, "x");
+        b.sign_operations(mike, &ToyKey(b"mike".to_vec())).unwrap();
+        let patch = b.encode_from(&EncodeOptions::patch(), a.oplog.local_frontier_ref());
+        a.oplog.decode_and_add_opts(&patch, verify_opts()).unwrap();
+        assert_eq!(a.oplog.signatures().len(), 2);
+
+        // Unsigned operations are rejected, and the oplog is left unchanged.
+        b.add_insert(mike, 0, "z");
+        let patch = b.encode_from(&EncodeOptions::patch(), a.oplog.local_frontier_ref());
+        let before = a.oplog.clone();
+        assert_eq!(a.oplog.decode_and_add_opts(&patch, verify_opts()), Err(ParseError::MissingSignature));
+        assert_eq!(a.oplog, before);
+
+        // As are operations signed with the wrong key.
+        b.sign_operations(mike, &ToyKey(b"seph".to_vec())).unwrap();
+        let patch = b.encode_from(&EncodeOptions::patch(), a.oplog.local_frontier_ref());
+        assert_eq!(a.oplog.decode_and_add_opts(&patch, verify_opts()), Err(ParseError::InvalidSignature));
+
+        // Signatures are only checked when asked.
+        a.oplog.decode_and_add(&patch).unwrap();
+        assert_eq!(a.oplog.local_frontier(), b.local_frontier());
+        assert_eq!(a.oplog.signatures(), b.signatures());
+    }
+
+    #[test]
+    fn stored_signatures_are_checked() {
+        let mut a = ListCRDT::new();
+        let mike = a.get_or_create_agent_id("mike");
+        a.insert(mike, 0, "hi");
+        let unsigned = a.oplog.encode(&EncodeOptions::default());
+        a.oplog.sign_operations(mike, &ToyKey(b"seph".to_vec())).unwrap();
+        let forged = a.oplog.encode(&EncodeOptions::default());
+
+        // A forged signature loaded without verification doesn't count as a signature later.
+        let mut b = ListOpLog::load_from(&forged).unwrap();
+        assert_eq!(b.decode_and_add_opts(&unsigned, verify_opts()), Err(ParseError::MissingSignature));
+
+        // Signatures must use the key in the agent's info, if we know it.
+        let mut a = ListCRDT::new();
+        let mike = a.get_or_create_agent_id("mike");
+        a.insert(mike, 0, "hi");
+        a.oplog.sign_operations(mike, &ToyKey(b"mike".to_vec())).unwrap();
+        let signed = a.oplog.encode(&EncodeOptions::default());
+        assert!(ListOpLog::load_from_opts(&signed, verify_opts()).is_ok());
+
+        let mut b = ListOpLog::new();
+        b.get_or_create_agent_with_info("mike", AgentInfo { public_key: Some(b"other".to_vec()), ..Default::default() });
+        assert_eq!(b.decode_and_add_opts(&signed, verify_opts()), Err(ParseError::InvalidSignature));
+    }
+
+    #[test]
+    fn unknown_content_cant_be_signed() {
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, "hi");
+        let data = a.oplog.encode(&EncodeOptions::default().store_inserted_content(false));
+
+        let opts = DecodeOptions { allow_missing_content: true, ..Default::default() };
+        let mut b = ListOpLog::load_from_opts(&data, opts).unwrap();
+        let seph = b.get_agent_id("seph").unwrap();
+        assert_eq!(b.sign_operations(seph, &ToyKey(b"seph".to_vec())), Err(SignError::ContentUnknown));
+        assert!(b.signatures().is_empty());
+    }
+}
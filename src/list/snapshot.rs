@@ -0,0 +1,145 @@
+//! Read-only oplog snapshots for concurrent readers.
+//!
+//! A [`CowOpLog`] keeps its oplog behind an [`Arc`]. Taking a snapshot just bumps the reference
+//! count, and the snapshot can be sent to other threads to answer `checkout_at` / `encode_from`
+//! style requests while the writer keeps appending. The oplog is only copied if the writer edits
+//! it while snapshots are still alive (copy-on-write).
+//!
+//! Copies are cheaper than they look. Inserted and deleted content is stored in immutable chunks
+//! which are shared (via [`Arc`]) between the copy and the snapshots, so only the operation and
+//! history metadata (and the content chunk being appended to) are duplicated.
+
+use std::ops::Deref;
+use std::sync::Arc;
+use crate::list::ListOpLog;
+
+/// An immutable snapshot of an oplog. Snapshots are cheap to clone, and can be shared between
+/// threads.
+#[derive(Debug, Clone, Default)]
+pub struct ReadOnlyOpLog(Arc<ListOpLog>);
+
+impl Deref for ReadOnlyOpLog {
+    type Target = ListOpLog;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<ListOpLog> for ReadOnlyOpLog {
+    fn from(oplog: ListOpLog) -> Self {
+        Self(Arc::new(oplog))
+    }
+}
+
+impl ReadOnlyOpLog {
+    /// Get an editable copy of the oplog. This only copies the oplog if other snapshots of it are
+    /// still alive.
+    pub fn into_oplog(self) -> ListOpLog {
+        Arc::try_unwrap(self.0).unwrap_or_else(|arc| (*arc).clone())
+    }
+}
+
+/// An oplog which can hand out read-only snapshots of itself in O(1).
+///
+/// Edits go through [`make_mut`](CowOpLog::make_mut). If any snapshots are alive at that point,
+/// the oplog is copied first (and the snapshots keep the old version). The copy shares its full
+/// content chunks with the snapshots.
+#[derive(Debug, Clone, Default)]
+pub struct CowOpLog(Arc<ListOpLog>);
+
+impl Deref for CowOpLog {
+    type Target = ListOpLog;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<ListOpLog> for CowOpLog {
+    fn from(oplog: ListOpLog) -> Self {
+        Self(Arc::new(oplog))
+    }
+}
+
+impl CowOpLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a read-only snapshot of the oplog's current state.
+    pub fn fork_read_only(&self) -> ReadOnlyOpLog {
+        ReadOnlyOpLog(self.0.clone())
+    }
+
+    /// Get mutable access to the oplog, copying it first if it's shared with any snapshots.
+    pub fn make_mut(&mut self) -> &mut ListOpLog {
+        Arc::make_mut(&mut self.0)
+    }
+
+    pub fn into_oplog(self) -> ListOpLog {
+        ReadOnlyOpLog(self.0).into_oplog()
+    }
+}
+
+impl ListOpLog {
+    /// Take a read-only snapshot of this oplog, which can be shared between threads.
+    ///
+    /// This copies the oplog. To take many snapshots of an oplog which is being edited, store it
+    /// in a [`CowOpLog`] instead - which only copies the oplog when its edited while snapshots are
+    /// alive.
+    pub fn fork_read_only(&self) -> ReadOnlyOpLog {
+        self.clone().into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+    use crate::list::encoding::EncodeOptions;
+    use super::{CowOpLog, ReadOnlyOpLog};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn snapshots_are_copy_on_write() {
+        assert_send_sync::<ReadOnlyOpLog>();
+
+        let mut oplog = CowOpLog::new();
+        let seph = oplog.make_mut().get_or_create_agent_id("seph");
+        oplog.make_mut().add_insert(seph, 0, "hi there");
+
+        // Taking a snapshot doesn't copy anything.
+        let snapshot = oplog.fork_read_only();
+        assert!(Arc::ptr_eq(&snapshot.0, &oplog.0));
+
+        // Snapshots can be read from other threads while the writer keeps going.
+        let readers: Vec<_> = (0..4).map(|_| {
+            let snapshot = snapshot.clone();
+            thread::spawn(move || {
+                let data = snapshot.encode(&EncodeOptions::default());
+                (snapshot.checkout_tip().content().to_string(), data.len())
+            })
+        }).collect();
+
+        oplog.make_mut().add_insert(seph, 0, "oh ");
+        assert!(!Arc::ptr_eq(&snapshot.0, &oplog.0));
+
+        for r in readers {
+            assert_eq!(r.join().unwrap().0, "hi there");
+        }
+        assert_eq!(snapshot.checkout_tip().content(), "hi there");
+        assert_eq!(oplog.checkout_tip().content(), "oh hi there");
+
+        // Once the snapshots are gone, edits don't copy.
+        drop(snapshot);
+        let ptr = Arc::as_ptr(&oplog.0);
+        oplog.make_mut().add_insert(seph, 0, "!");
+        assert_eq!(Arc::as_ptr(&oplog.0), ptr);
+
+        let copy = oplog.fork_read_only().into_oplog();
+        assert_eq!(copy, *oplog);
+        assert_eq!(copy.fork_read_only().len(), copy.len());
+    }
+}
@@ -0,0 +1,217 @@
+//! History compaction. Long lived documents accumulate huge histories, most of which nobody will
+//! ever branch from. This module replaces all the history before some version with a single
+//! synthetic insert of the document's content at that version.
+
+use rle::HasLength;
+use sha2::{Digest, Sha256};
+use crate::{DTRange, Frontier, LV};
+use crate::causalgraph::graph::GraphEntrySimple;
+use crate::encoding::parseerror::ParseError;
+use crate::list::ListOpLog;
+use crate::list::operation::TextOperation;
+use crate::rle::KVPair;
+
+/// The prefix of the names of the agents which own the synthetic snapshot operations created by
+/// [`squash_before`](ListOpLog::squash_before).
+pub const SQUASH_AGENT: &str = "__squashed";
+
+/// Is this agent the owner of a snapshot operation created by squashing?
+pub(crate) fn is_squash_agent(name: &str) -> bool {
+    name.starts_with(SQUASH_AGENT)
+}
+
+/// The name of the agent which owns the snapshot of the named version. The name contains the
+/// first 128 bits of the version's SHA-256 hash (in hex), so it still fits in an agent name.
+fn squash_agent_name(version_name: &str) -> String {
+    let hash = Sha256::digest(version_name.as_bytes());
+    let mut name = format!("{SQUASH_AGENT}-");
+    for b in &hash[..16] {
+        name.push_str(&format!("{b:02x}"));
+    }
+    name
+}
+
+impl ListOpLog {
+    /// Create a copy of this oplog with all the operations in `version` replaced with a single
+    /// insert of the document content at that version. Any content which was inserted and later
+    /// deleted before `version` is dropped entirely.
+    ///
+    /// The snapshot insert is attributed to an agent named [`SQUASH_AGENT`] followed by a SHA-256
+    /// hash of `version`. Squashing is deterministic, so peers which squash at the same version end
+    /// up with the same snapshot operation, and snapshots of different versions never share an
+    /// agent ID. Operations after `version` keep their agent IDs and sequence numbers. They can
+    /// still be concurrent with one another - but they must all come after `version`.
+    ///
+    /// Returns None if any operation not in `version` is concurrent with it. (Ie, if `version`
+    /// doesn't cleanly split the history in two).
+    ///
+    /// Note the resulting oplog doesn't know about any of the squashed operations. Changes from
+    /// peers which still reference them can't be merged. Merging with an oplog squashed at a
+    /// different version (or one which isn't squashed at all) fails with
+    /// [`ParseError::SquashBaseMismatch`], since both snapshots would end up in the document.
+    pub fn squash_before(&self, version: &[LV]) -> Option<ListOpLog> {
+        let graph = &self.cg.graph;
+        let version = graph.find_dominators(version);
+
+        // Everything after version must come after everything in version. Because LVs are
+        // assigned in causal order, version must contain exactly the LVs 0..k.
+        let (_, after) = graph.diff_rev(version.as_ref(), self.cg.version.as_ref());
+        let after: DTRange = match after.as_slice() {
+            [] => (self.len()..self.len()).into(),
+            [r] if r.end == self.len() => *r,
+            _ => return None,
+        };
+        let k = after.start;
+
+        let mut result = ListOpLog::new();
+        result.doc_id = self.doc_id.clone();
+
+        let content = self.checkout(version.as_ref()).content.to_string();
+        let n = if content.is_empty() { 0 } else {
+            let name = squash_agent_name(&self.frontier_to_remote_string(version.as_ref()));
+            let agent = result.get_or_create_agent_id(&name);
+            result.add_operations_remote(agent, &[], 0, &[TextOperation::new_insert(0, &content)]).len() as LV
+        };
+        let snapshot: Frontier = if n == 0 { Frontier::root() } else { Frontier::new_1(n - 1) };
+
+        if after.is_empty() { return Some(result); }
+
+        let map_lv = |lv: LV| -> LV { lv - k + n };
+
+        // This is pretty similar to add_missing_operations_from.
+        let mut t = n;
+        for (KVPair(_, op), content) in self.iter_range_simple(after) {
+            result.push_op_internal(t, op.loc, op.kind, content);
+            t += op.len() as LV;
+        }
+
+        t = n;
+        for mut span in self.iter_agent_mappings_range(after) {
+            span.agent = result.get_or_create_agent_id(self.get_agent_name(span.agent));
+            result.assign_time_to_crdt_span(t, span);
+            t += span.len() as LV;
+        }
+
+        for hist_entry in graph.entries.iter_range_map(after, |e| GraphEntrySimple::from(e)) {
+            let old_only = hist_entry.parents.iter().all(|p| *p < k);
+            if old_only && graph.find_dominators(hist_entry.parents.as_ref()) != version {
+                // This operation is concurrent with version.
+                return None;
+            }
+
+            let parents: Frontier = if old_only {
+                snapshot.clone()
+            } else {
+                let mapped: Vec<LV> = hist_entry.parents.iter()
+                    .map(|p| if *p < k { n.wrapping_sub(1) } else { map_lv(*p) })
+                    .filter(|p| *p != LV::MAX)
+                    .collect();
+                result.cg.graph.find_dominators(&mapped)
+            };
+
+            let span: DTRange = (map_lv(hist_entry.span.start)..map_lv(hist_entry.span.end)).into();
+            result.cg.graph.push(parents.as_ref(), span);
+            result.cg.version.advance_by_known_run(parents.as_ref(), span);
+        }
+
+        Some(result)
+    }
+
+    /// The name of the agent which owns this oplog's snapshot, if it was made by
+    /// [`squash_before`](ListOpLog::squash_before). The snapshot is always the first operation.
+    pub(crate) fn squash_base(&self) -> Option<&str> {
+        if self.is_empty() { return None; }
+        let name = self.get_agent_name(self.lv_to_agent_version(0).0);
+        if is_squash_agent(name) { Some(name) } else { None }
+    }
+
+    /// Check the other oplog was squashed at the same version as this oplog, so they can be merged.
+    pub(crate) fn check_squash_base(&self, other: &ListOpLog) -> Result<(), ParseError> {
+        if !self.is_empty() && !other.is_empty() && self.squash_base() != other.squash_base() {
+            Err(ParseError::SquashBaseMismatch)
+        } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::{EncodeOptions, ParseError};
+    use crate::list::ListOpLog;
+    use crate::lv_to_usize;
+    use super::SQUASH_AGENT;
+
+    #[test]
+    fn squash_keeps_later_changes() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hello there world");
+        oplog.add_delete_without_content(mike, 5..11);
+        let v = oplog.add_insert(seph, 0, ">> ");
+
+        // Concurrent changes after v.
+        let ins_h = oplog.add_insert_at(seph, &[v], 3, "H");
+        oplog.add_delete_at(seph, &[ins_h], 4..5);
+        oplog.add_insert_at(mike, &[v], 14, "!");
+
+        let squashed = oplog.squash_before(&[v]).unwrap();
+        squashed.dbg_check(true);
+        assert_eq!(squashed.checkout_tip().content(), oplog.checkout_tip().content());
+        assert_eq!(lv_to_usize(squashed.len()), ">> hello world".len() + 3);
+        assert!(squashed.get_agent_name(0).starts_with(SQUASH_AGENT));
+
+        // Later operations keep their IDs.
+        let mut a = squashed.remote_frontier();
+        let mut b = oplog.remote_frontier();
+        a.sort_by_key(|rv| rv.0);
+        b.sort_by_key(|rv| rv.0);
+        assert_eq!(a, b);
+
+        // Squashing everything leaves just the content.
+        let all = oplog.squash_before(oplog.local_frontier_ref()).unwrap();
+        assert_eq!(lv_to_usize(all.len()), all.checkout_tip().len());
+        assert_eq!(all.checkout_tip().content(), oplog.checkout_tip().content());
+
+        // But we can't squash before a version which is concurrent with other changes.
+        assert!(oplog.squash_before(&[ins_h]).is_none());
+    }
+
+    #[test]
+    fn merge_squashed_at_different_versions() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let v1 = oplog.add_insert(seph, 0, "hi");
+        let v2 = oplog.add_insert(seph, 2, " there");
+
+        let mut a = oplog.squash_before(&[v1]).unwrap();
+        let mut b = oplog.squash_before(&[v2]).unwrap();
+        assert_ne!(a.get_agent_name(0), b.get_agent_name(0));
+        assert_eq!(a.get_agent_name(0), oplog.squash_before(&[v1]).unwrap().get_agent_name(0));
+
+        let mike = a.get_or_create_agent_id("mike");
+        a.add_insert(mike, 0, "a ");
+        let kaarina = b.get_or_create_agent_id("kaarina");
+        b.add_insert(kaarina, 0, "b ");
+
+        // The snapshots would both end up in the document, so the merge is rejected.
+        let b_content = b.checkout_tip().content().to_string();
+        assert_eq!(a.clone().merge_oplog(&b), Err(ParseError::SquashBaseMismatch));
+        assert_eq!(b.clone().merge_oplog(&a), Err(ParseError::SquashBaseMismatch));
+        assert_eq!(oplog.clone().merge_oplog(&a), Err(ParseError::SquashBaseMismatch));
+        assert_eq!(a.clone().merge_oplog(&oplog), Err(ParseError::SquashBaseMismatch));
+
+        let a_data = a.encode(&EncodeOptions::default());
+        assert_eq!(b.decode_and_add(&a_data), Err(ParseError::SquashBaseMismatch));
+        assert_eq!(b.checkout_tip().content(), b_content);
+        assert_eq!(oplog.clone().decode_and_add(&a_data), Err(ParseError::SquashBaseMismatch));
+        let full_data = oplog.encode(&EncodeOptions::default());
+        assert_eq!(a.clone().decode_and_add(&full_data), Err(ParseError::SquashBaseMismatch));
+
+        // But oplogs squashed at the same version merge normally.
+        let mut c = oplog.squash_before(&[v1]).unwrap();
+        c.decode_and_add(&a_data).unwrap();
+        c.merge_oplog(&a).unwrap();
+        assert_eq!(c.checkout_tip().content(), a.checkout_tip().content());
+        ListOpLog::new().merge_oplog(&a).unwrap();
+    }
+}
@@ -67,7 +67,7 @@ impl ListOpLog {
             let mut t_inv = 1f32;
             while t_inv < time_len as f32 {
                 dbg!(t_inv);
-                push_time(time_len - (t_inv as usize));
+                push_time(time_len - (t_inv as LV));
                 t_inv *= factor;
             }
         }
@@ -0,0 +1,158 @@
+//! Extracting the history of a document up to some version.
+//!
+//! This is useful for sharing a snapshot of a document (eg with an external reviewer) without
+//! sharing any of the edits made since.
+
+use rle::HasLength;
+use crate::{DTRange, Frontier, LV};
+use crate::causalgraph::agent_span::AgentSpan;
+use crate::list::ListOpLog;
+use crate::list::named_branches::NamedBranch;
+use crate::rle::{KVPair, RleVec};
+
+impl ListOpLog {
+    /// Make a standalone oplog containing exactly the operations in the history of `version`,
+    /// including their content. Later operations are left out entirely. Local versions are
+    /// renumbered, but every operation keeps its agent name and sequence number.
+    ///
+    /// Agent info is only copied for agents with operations in the extracted history. Checkpoints,
+    /// named branches and transactions are only kept if they're entirely within the extracted
+    /// history. Operation metadata is kept for the extracted operations. Signatures are not copied.
+    ///
+    /// The document's ID, fork points and pruned history are copied as they are. Fork points and
+    /// pruned history name operations from other documents and from before the oplog's history,
+    /// so they aren't filtered. The names of kept checkpoints and named branches are shared too.
+    ///
+    /// Panics if the version contains unknown operations.
+    pub fn extract_subhistory(&self, version: &[LV]) -> ListOpLog {
+        assert!(version.iter().all(|v| *v < self.len()), "Version contains unknown operations");
+        let version = self.cg.graph.find_dominators(version);
+
+        let mut result = ListOpLog::new();
+        result.doc_id = self.doc_id.clone();
+        result.fork_points = self.fork_points.clone();
+        result.pruned = self.pruned.clone();
+        result.cg.agent_assignment.insert_tie_break = self.cg.agent_assignment.insert_tie_break;
+
+        // The history of a version always contains all the parents of its operations. So copying
+        // the operations in order means every parent has already been copied.
+        let kept = self.cg.graph.diff(&[], version.as_ref()).1;
+        let mut map = RleVec::<KVPair<DTRange>>::new();
+        let map_lv = |map: &RleVec<KVPair<DTRange>>, lv: LV| -> LV {
+            let (KVPair(_, mapped), offset) = map.find_with_offset(lv).unwrap();
+            mapped.start + offset as LV
+        };
+
+        for range in kept.iter() {
+            for e in self.cg.iter_range(*range) {
+                let parents = Frontier::from_unsorted_iter(e.parents.iter().map(|p| map_lv(&map, *p)));
+                let agent = result.get_or_create_agent_id(self.get_agent_name(e.span.agent));
+                let span: DTRange = (e.start..e.start + e.span.len() as LV).into();
+                let start = result.len();
+                result.cg.merge_and_assign(parents.as_ref(), AgentSpan { agent, seq_range: e.span.seq_range });
+
+                let mut t = start;
+                for (KVPair(_, op), content) in self.iter_range_simple(span) {
+                    result.push_op_internal(t, op.loc, op.kind, content);
+                    t += op.len() as LV;
+                }
+                map.insert(KVPair(span.start, (start..t).into()));
+            }
+        }
+
+        // Agents who only made later operations are left out entirely.
+        result.agent_info = self.agent_info.iter()
+            .filter(|(name, _)| result.cg.agent_assignment.get_agent_id(name).is_some())
+            .map(|(name, info)| (name.clone(), info.clone()))
+            .collect();
+
+        // Map a range of operations, if its entirely within the extracted history.
+        let map_range = |r: DTRange| -> Option<DTRange> {
+            kept.iter().any(|k| k.start <= r.start && r.end <= k.end).then(|| {
+                let start = map_lv(&map, r.start);
+                (start..start + r.len() as LV).into()
+            })
+        };
+
+        for txn in self.transactions.iter() {
+            if let Some(r) = map_range(*txn) { result.transactions.push(r); }
+        }
+        for (r, meta) in self.op_metadata.iter() {
+            for k in kept.iter() {
+                let start = r.start.max(k.start);
+                let end = r.end.min(k.end);
+                if start < end {
+                    result.set_op_metadata(map_range((start..end).into()).unwrap(), meta.clone());
+                }
+            }
+        }
+
+        let contains = |v: &Frontier| self.cg.graph.frontier_contains_frontier(version.as_ref(), v.as_ref());
+        let map_version = |v: &Frontier| Frontier::from_unsorted_iter(v.iter().map(|v| map_lv(&map, *v)));
+        result.checkpoints = self.checkpoints.iter().filter(|c| contains(&c.version)).map(|c| {
+            let mut c = c.clone();
+            c.version = map_version(&c.version);
+            c
+        }).collect();
+        result.named_branches = self.named_branches.iter()
+            .filter_map(|(name, b)| {
+                let v = b.version.as_ref().filter(|v| contains(v))?;
+                Some((name.clone(), NamedBranch { edit: b.edit, version: Some(map_version(v)) }))
+            })
+            .collect();
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{AgentInfo, ListCRDT, ListOpLog};
+    use crate::list::encoding::EncodeOptions;
+
+    #[test]
+    fn extract_subhistory() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let mike = doc.oplog.get_or_create_agent_with_info("mike", AgentInfo {
+            display_name: Some("Mike".into()),
+            ..Default::default()
+        });
+        doc.insert(seph, 0, "hello world");
+        let base = doc.oplog.cg.version.clone();
+        doc.delete(seph, 0..6);
+        doc.oplog.add_insert_at(mike, base.as_ref(), 11, "!");
+        doc.oplog.add_checkpoint(base.as_ref(), "draft", "");
+        doc.oplog.add_checkpoint(doc.oplog.cg.version.clone().as_ref(), "final", "");
+
+        // Everything except seph's delete.
+        let v = doc.oplog.cg.version.as_ref().iter().copied().filter(|v| *v >= 17).collect::<Vec<_>>();
+        let sub = doc.oplog.extract_subhistory(&v);
+        sub.dbg_check(true);
+        assert_eq!(sub.len(), 12);
+        assert_eq!(sub.checkout_tip().content().to_string(), "hello world!");
+        assert_eq!(sub.checkpoints().len(), 1);
+        assert_eq!(sub.checkpoints()[0].name, "draft");
+
+        // The extracted history is a prefix of the original, so it merges back cleanly.
+        let mut copy = sub.clone();
+        copy.add_missing_operations_from(&doc.oplog);
+        assert_eq!(copy.checkout_tip().content(), doc.oplog.checkout_tip().content());
+
+        let data = sub.encode(&EncodeOptions::full());
+        let loaded = ListOpLog::load_from(&data).unwrap();
+        assert_eq!(loaded.checkout_tip().content().to_string(), "hello world!");
+
+        assert!(sub.agent_info(sub.get_agent_id("mike").unwrap()).is_some());
+
+        // Agent info isn't shared for agents whose edits are all left out.
+        let sub = doc.oplog.extract_subhistory(base.as_ref());
+        assert_eq!(sub.get_agent_id("mike"), None);
+        assert_eq!(sub.iter_agent_info().count(), 0);
+
+        assert_eq!(doc.oplog.extract_subhistory(&[]).len(), 0);
+        let all = doc.oplog.extract_subhistory(doc.oplog.cg.version.as_ref());
+        assert_eq!(all.checkout_tip().content(), doc.oplog.checkout_tip().content());
+        assert_eq!(all.checkpoints().len(), 2);
+    }
+}
@@ -0,0 +1,254 @@
+//! Importing changes made to a document's text outside of diamond types.
+//!
+//! When a document is synced with a file on disk (or any other system which only stores the
+//! current text), we only find out what the new content is - not the edits which were made. To
+//! merge nicely with concurrent changes, the new content should be imported as the smallest set
+//! of edits which turns the old text into the new text.
+//!
+//! [`diff_lines`] finds those edits with a line based diff (Myers' algorithm), then trims the
+//! unchanged characters from the start and end of each changed block of lines.
+//! [`ListBranch::sync_text`] applies them to a branch as a single transaction.
+
+use std::ops::Range;
+use crate::list::{ListBranch, ListCRDT, ListOpLog};
+use crate::{AgentId, LV};
+use crate::unicount::count_chars;
+
+/// Diffing two very different files takes O(n * d) time. If more lines than this have changed,
+/// we give up and replace all the changed lines in one edit.
+const MAX_LINE_EDITS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Edit { Keep, Del, Ins }
+
+/// Myers' diff algorithm. Returns the edits needed to turn `a` into `b`, or None if that takes
+/// more than `max_d` insertions and deletions.
+fn myers_edits<T: Eq>(a: &[T], b: &[T], max_d: usize) -> Option<Vec<Edit>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max_d = max_d.min(a.len() + b.len()) as isize;
+    let offset = max_d + 1;
+    // The furthest x position reached on each diagonal k = x - y.
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // A copy of the relevant part of v at the start of each round.
+    let mut trace: Vec<Vec<isize>> = vec![];
+
+    'outer: {
+        for d in 0..=max_d {
+            trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+            for k in (-d..=d).step_by(2) {
+                let idx = (offset + k) as usize;
+                let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                    v[idx + 1]
+                } else {
+                    v[idx - 1] + 1
+                };
+                let mut y = x - k;
+                while x < n && y < m && a[x as usize] == b[y as usize] {
+                    x += 1;
+                    y += 1;
+                }
+                v[idx] = x;
+                if x >= n && y >= m { break 'outer; }
+            }
+        }
+        return None;
+    }
+
+    // Walk backwards through the rounds to find the path we took.
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, round) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| round[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == prev_x { Edit::Ins } else { Edit::Del });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    Some(edits)
+}
+
+/// The changed blocks of items between a and b, as (range in a, range in b) pairs.
+fn diff_blocks<T: Eq>(a: &[T], b: &[T]) -> Vec<(Range<usize>, Range<usize>)> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    if a_mid.is_empty() && b_mid.is_empty() { return vec![]; }
+
+    let Some(edits) = myers_edits(a_mid, b_mid, MAX_LINE_EDITS) else {
+        return vec![(prefix..a.len() - suffix, prefix..b.len() - suffix)];
+    };
+
+    let mut result: Vec<(Range<usize>, Range<usize>)> = vec![];
+    let (mut i, mut j) = (prefix, prefix);
+    let mut in_block = false;
+    for e in edits {
+        if e != Edit::Keep && !in_block {
+            result.push((i..i, j..j));
+        }
+        in_block = e != Edit::Keep;
+        match e {
+            Edit::Keep => { i += 1; j += 1; }
+            Edit::Del => { i += 1; result.last_mut().unwrap().0.end = i; }
+            Edit::Ins => { j += 1; result.last_mut().unwrap().1.end = j; }
+        }
+    }
+    result
+}
+
+fn common_prefix_bytes(a: &str, b: &str) -> usize {
+    a.chars().zip(b.chars()).take_while(|(x, y)| x == y).map(|(c, _)| c.len_utf8()).sum()
+}
+
+fn common_suffix_bytes(a: &str, b: &str) -> usize {
+    a.chars().rev().zip(b.chars().rev()).take_while(|(x, y)| x == y).map(|(c, _)| c.len_utf8()).sum()
+}
+
+/// Find a small set of edits which turn `old` into `new`. Each edit replaces a range of
+/// characters in `old` with some text from `new`. The edits are in order and don't overlap, and
+/// can be passed straight to [`ListBranch::apply_local_patch`].
+///
+/// The diff is line based, so its fast even for large files. But if more than a thousand lines
+/// have changed, all the changed lines are replaced in a single edit.
+pub fn diff_lines<'a>(old: &str, new: &'a str) -> Vec<(Range<usize>, &'a str)> {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+
+    // The byte and char offsets of the start of each line (and the end of the last line).
+    let offsets = |lines: &[&str]| -> Vec<(usize, usize)> {
+        let mut pos = (0, 0);
+        let mut result = Vec::with_capacity(lines.len() + 1);
+        result.push(pos);
+        for line in lines {
+            pos = (pos.0 + line.len(), pos.1 + count_chars(line));
+            result.push(pos);
+        }
+        result
+    };
+    let old_offsets = offsets(&old_lines);
+    let new_offsets = offsets(&new_lines);
+
+    diff_blocks(&old_lines, &new_lines).into_iter().filter_map(|(a, b)| {
+        let (old_start, old_start_char) = old_offsets[a.start];
+        let old_text = &old[old_start..old_offsets[a.end].0];
+        let new_text = &new[new_offsets[b.start].0..new_offsets[b.end].0];
+
+        let prefix = common_prefix_bytes(old_text, new_text);
+        let suffix = common_suffix_bytes(&old_text[prefix..], &new_text[prefix..]);
+        let deleted = &old_text[prefix..old_text.len() - suffix];
+        let inserted = &new_text[prefix..new_text.len() - suffix];
+
+        let start = old_start_char + count_chars(&old_text[..prefix]);
+        let range = start..start + count_chars(deleted);
+        (!range.is_empty() || !inserted.is_empty()).then_some((range, inserted))
+    }).collect()
+}
+
+impl ListBranch {
+    /// Change the branch's content to `text`, by applying the edits found by [`diff_lines`] as a
+    /// single transaction from `agent`. This is useful for importing changes made to a file on
+    /// disk (or anywhere else which only stores the current text). Importing the smallest set of
+    /// edits means the change merges cleanly with concurrent edits.
+    ///
+    /// The branch must be at the oplog's current version. Returns the version of the last
+    /// operation, or None if the text is unchanged.
+    pub fn sync_text(&mut self, oplog: &mut ListOpLog, agent: AgentId, text: &str) -> Option<LV> {
+        let old = self.content.to_string();
+        let edits = diff_lines(&old, text);
+        self.apply_local_patch(oplog, agent, &edits)
+    }
+}
+
+impl ListCRDT {
+    /// See [`ListBranch::sync_text`].
+    pub fn sync_text(&mut self, agent: AgentId, text: &str) -> Option<LV> {
+        self.branch.sync_text(&mut self.oplog, agent, text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+    use crate::list::ListCRDT;
+    use super::*;
+
+    fn apply(old: &str, edits: &[(Range<usize>, &str)]) -> String {
+        let chars: Vec<char> = old.chars().collect();
+        let mut result = String::new();
+        let mut pos = 0;
+        for (range, text) in edits {
+            result.extend(&chars[pos..range.start]);
+            result.push_str(text);
+            pos = range.end;
+        }
+        result.extend(&chars[pos..]);
+        result
+    }
+
+    #[test]
+    fn small_diffs() {
+        assert_eq!(diff_lines("", ""), []);
+        assert_eq!(diff_lines("a\nb\n", "a\nb\n"), []);
+        assert_eq!(diff_lines("", "hi"), [(0..0, "hi")]);
+        assert_eq!(diff_lines("one\ntwo\nthree\n", "one\nthree\n"), [(4..8, "")]);
+        assert_eq!(diff_lines("one\ntwo\nthree\n", "one\ntwö!\nthree\n"), [(6..7, "ö!")]);
+        assert_eq!(diff_lines("a\nb\nc\nd\n", "a\nx\nc\nd\ny\n"), [(2..3, "x"), (8..8, "y\n")]);
+        // No trailing newline.
+        assert_eq!(diff_lines("a\nb", "a\nbc"), [(3..3, "c")]);
+
+        // Very different files are replaced in one go.
+        let old: String = (0..2000).map(|i| format!("{i}\n")).collect();
+        let new: String = (0..2000).map(|i| format!("{}\n", i * 7)).collect();
+        let edits = diff_lines(&old, &new);
+        assert_eq!(edits.len(), 1);
+        assert_eq!(apply(&old, &edits), new);
+    }
+
+    #[test]
+    fn random_diffs() {
+        let mut rng = SmallRng::seed_from_u64(21);
+        let lines = ["hello\n", "wörld\n", "\n", "fn main() {\n", "}\n", "x"];
+        let gen = |rng: &mut SmallRng| -> String {
+            (0..rng.gen_range(0..20)).map(|_| *lines.choose(rng).unwrap()).collect()
+        };
+
+        for _ in 0..300 {
+            let old = gen(&mut rng);
+            let new = gen(&mut rng);
+            let edits = diff_lines(&old, &new);
+            assert_eq!(apply(&old, &edits), new);
+            assert!(edits.windows(2).all(|w| w[0].0.end < w[1].0.start));
+        }
+    }
+
+    #[test]
+    fn sync_text() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "one\ntwo\nthree\n");
+        let mut remote = doc.clone();
+        let mike = remote.get_or_create_agent_id("mike");
+
+        // Someone edits the file on disk, and another peer makes a concurrent change.
+        assert_eq!(doc.sync_text(seph, "one\ntwo\nthree\n"), None);
+        doc.sync_text(seph, "zero\none\nthree\n").unwrap();
+        assert_eq!(doc.branch.content().to_string(), "zero\none\nthree\n");
+        remote.insert(mike, 7, "!");
+
+        doc.oplog.add_missing_operations_from(&remote.oplog);
+        doc.branch.merge(&doc.oplog, doc.oplog.cg.version.as_ref());
+        assert_eq!(doc.branch.content().to_string(), "zero\none\n!three\n");
+        doc.dbg_check(true);
+    }
+}
@@ -0,0 +1,188 @@
+//! Keeping external text buffers in sync with a document.
+//!
+//! Editors usually have their own text storage (a piece table, a rope, a browser textarea). A
+//! [`ListBranch`] keeps its own copy of the document in a [`JumpRope`], which doubles the memory
+//! needed for big documents. Instead, implement [`TextSink`] for your buffer and use a
+//! [`SinkBranch`]. It only stores a version - merged and local changes are written straight
+//! through to the sink.
+
+use std::ops::Range;
+use rle::HasLength;
+use jumprope::{JumpRope, JumpRopeBuf};
+use crate::{AgentId, Frontier, LV};
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::{ListOpKind, TextOperation};
+use crate::list::redact::placeholder_str;
+use crate::unicount::chars_to_bytes;
+
+/// A text buffer which can be edited by diamond types. All positions are in unicode characters.
+pub trait TextSink {
+    fn insert(&mut self, pos: usize, content: &str);
+    fn remove(&mut self, range: Range<usize>);
+}
+
+impl TextSink for JumpRope {
+    fn insert(&mut self, pos: usize, content: &str) {
+        JumpRope::insert(self, pos, content);
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        JumpRope::remove(self, range);
+    }
+}
+
+impl TextSink for JumpRopeBuf {
+    fn insert(&mut self, pos: usize, content: &str) {
+        JumpRopeBuf::insert(self, pos, content);
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        JumpRopeBuf::remove(self, range);
+    }
+}
+
+/// Strings are O(n) to edit. This is mostly useful for small documents and testing.
+impl TextSink for String {
+    fn insert(&mut self, pos: usize, content: &str) {
+        let byte_pos = chars_to_bytes(self, pos);
+        self.insert_str(byte_pos, content);
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        let start = chars_to_bytes(self, range.start);
+        let end = start + chars_to_bytes(&self[start..], range.len());
+        self.replace_range(start..end, "");
+    }
+}
+
+/// Apply a (transformed) operation to a sink.
+fn apply_to_sink(sink: &mut dyn TextSink, op: &TextOperation) {
+    match op.kind {
+        ListOpKind::Ins => {
+            match op.inserted_text() {
+                Some(content) => sink.insert(op.start(), &content),
+                None => sink.insert(op.start(), &placeholder_str(op.len())),
+            }
+        }
+        ListOpKind::Del => sink.remove(op.loc.span.into()),
+    }
+}
+
+impl ListOpLog {
+    /// Write the changes needed to bring a document at version `from` up to date with `merging`
+    /// into `sink`. The sink must contain the document's content at version `from`.
+    ///
+    /// Returns the sink's new version.
+    pub fn merge_into_sink(&self, sink: &mut dyn TextSink, from: &[LV], merging: &[LV]) -> Frontier {
+        let merging = self.expand_to_transactions(merging);
+        for (_, op) in self.iter_xf_operations_from(from, merging.as_ref()) {
+            if let Some(op) = op {
+                apply_to_sink(sink, &op);
+            }
+        }
+        self.cg.graph.find_dominators_2(from, merging.as_ref())
+    }
+}
+
+/// A branch which writes its content to an external [`TextSink`] instead of storing it.
+///
+/// This works like a [`ListBranch`], except deleting content requires reading it - so deletes made
+/// through a `SinkBranch` don't store the deleted text in the oplog.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SinkBranch<S: TextSink> {
+    version: Frontier,
+    sink: S,
+}
+
+impl<S: TextSink> SinkBranch<S> {
+    /// Create a new branch at the start of time. The sink should be empty.
+    pub fn new(sink: S) -> Self {
+        Self { version: Frontier::root(), sink }
+    }
+
+    /// Create a branch wrapping a sink which already contains the document's content at `version`.
+    pub fn new_at_local_version(sink: S, version: &[LV]) -> Self {
+        Self { version: version.into(), sink }
+    }
+
+    pub fn local_frontier_ref(&self) -> &[LV] { self.version.as_ref() }
+
+    pub fn local_frontier(&self) -> Frontier { self.version.clone() }
+
+    pub fn sink(&self) -> &S { &self.sink }
+
+    pub fn into_sink(self) -> S { self.sink }
+
+    /// Merge the changes in `merge_frontier` from the oplog into the sink.
+    pub fn merge(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) {
+        self.version = oplog.merge_into_sink(&mut self.sink, self.version.as_ref(), merge_frontier);
+    }
+
+    /// Add local operations to the oplog, and apply them to the sink. The operations are made at
+    /// the branch's current version.
+    pub fn apply_local_operations(&mut self, oplog: &mut ListOpLog, agent: AgentId, ops: &[TextOperation]) -> LV {
+        let v = oplog.add_operations_at(agent, self.version.as_ref(), ops);
+        for op in ops {
+            apply_to_sink(&mut self.sink, op);
+        }
+        self.version.replace_with_1(v);
+        v
+    }
+
+    pub fn insert(&mut self, oplog: &mut ListOpLog, agent: AgentId, pos: usize, ins_content: &str) -> LV {
+        self.apply_local_operations(oplog, agent, &[TextOperation::new_insert(pos, ins_content)])
+    }
+
+    pub fn delete_without_content(&mut self, oplog: &mut ListOpLog, agent: AgentId, loc: Range<usize>) -> LV {
+        self.apply_local_operations(oplog, agent, &[TextOperation::new_delete(loc)])
+    }
+}
+
+impl ListBranch {
+    /// Merge changes into the branch, and write the same changes through to `sink`. The sink must
+    /// contain the branch's content before the merge.
+    pub fn merge_with_sink(&mut self, oplog: &ListOpLog, merge_frontier: &[LV], sink: &mut dyn TextSink) {
+        let from = self.version.clone();
+        self.merge(oplog, merge_frontier);
+        oplog.merge_into_sink(sink, from.as_ref(), self.version.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jumprope::JumpRope;
+    use crate::list::{ListCRDT, ListOpLog};
+    use super::SinkBranch;
+
+    #[test]
+    fn sinks_track_merges() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+
+        let mut a = SinkBranch::new(String::new());
+        a.insert(&mut oplog, seph, 0, "hi there");
+        let mut b = SinkBranch::new(JumpRope::new());
+        b.merge(&oplog, oplog.local_frontier_ref());
+        assert_eq!(b.sink().to_string(), "hi there");
+
+        // Concurrent edits.
+        a.delete_without_content(&mut oplog, seph, 0..3);
+        a.insert(&mut oplog, seph, 0, "😃 ");
+        b.insert(&mut oplog, mike, 8, "!");
+        a.merge(&oplog, oplog.local_frontier_ref());
+        b.merge(&oplog, oplog.local_frontier_ref());
+
+        assert_eq!(a.sink(), "😃 there!");
+        assert_eq!(b.sink().to_string(), "😃 there!");
+        assert_eq!(a.local_frontier(), oplog.local_frontier());
+        assert_eq!(oplog.checkout_tip().content(), "😃 there!");
+
+        // Regular branches can drive a sink too.
+        let mut doc = ListCRDT::new();
+        let mut text = String::new();
+        doc.branch.merge_with_sink(&oplog, oplog.local_frontier_ref(), &mut text);
+        assert_eq!(text, "😃 there!");
+        assert_eq!(doc.branch.content(), "😃 there!");
+    }
+}
@@ -0,0 +1,260 @@
+//! Thin clients.
+//!
+//! Merging remote changes into a document normally needs the full oplog, because the merge looks
+//! up old operations to figure out how concurrent changes interact. For memory constrained
+//! applications (like relays which keep a live copy of each document), [`ThinClient`] applies
+//! remote operations one at a time as they arrive, directly to a branch. It only keeps the causal
+//! graph and a tracker of where each item is - the content of the operations is never stored.
+//!
+//! The tracker stores runs of items rather than their content, so its much smaller than an oplog
+//! for most documents. But it still grows with the document's history. Once every peer has seen
+//! all the operations applied so far, call [`ThinClient::set_stable_version`] to throw the
+//! tracker's items away.
+
+use std::fmt::{Display, Formatter};
+use rle::{HasLength, SplitableSpan};
+use crate::{CausalGraph, DTRange, Frontier, lv_to_usize, LV};
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontier, RemoteVersion, VersionConversionError};
+use crate::causalgraph::agent_span::AgentSpan;
+use crate::list::ListBranch;
+use crate::list::op_metrics::ListOpMetrics;
+use crate::list::operation::{ListOpKind, TextOperation};
+use crate::listmerge::merge::reverse_str;
+use crate::listmerge::op_stream::StreamTracker;
+use crate::unicount::{chars_to_bytes, count_chars};
+
+/// The error returned when a [`ThinClient`] can't apply an operation.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ThinClientError {
+    /// The operation's parents (or the stable version) name operations which haven't been applied.
+    UnknownVersion(VersionConversionError),
+    /// The operation is an insert with unknown content. Thin clients can't apply these, since
+    /// they don't store content.
+    MissingContent,
+    /// The insert's content doesn't have the same length as the operation.
+    InvalidContent,
+    /// The operation's parents don't include the stable version, so the operation may depend on
+    /// items which have been pruned from the tracker. See [`ThinClient::set_stable_version`].
+    BeforeStableVersion,
+}
+
+impl Display for ThinClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ThinClientError {:?}", self)
+    }
+}
+
+impl std::error::Error for ThinClientError {}
+
+impl From<VersionConversionError> for ThinClientError {
+    fn from(err: VersionConversionError) -> Self {
+        ThinClientError::UnknownVersion(err)
+    }
+}
+
+/// A document which applies remote operations directly to a branch, without storing them in an
+/// oplog. This is useful for memory constrained applications, like relays which keep a live copy
+/// of each document.
+///
+/// Thin clients only keep the causal graph and a tracker of where each item is. The tracker
+/// grows with the document's history, so once every peer has seen all the operations applied so
+/// far, call [`set_stable_version`](ThinClient::set_stable_version) to throw its items away.
+#[derive(Debug, Clone)]
+pub struct ThinClient {
+    cg: CausalGraph,
+    branch: ListBranch,
+    tracker: StreamTracker,
+    /// Every operation applied from now on must have this version in its history.
+    stable: Frontier,
+}
+
+impl Default for ThinClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThinClient {
+    /// Create a thin client for a new (empty) document.
+    pub fn new() -> Self {
+        Self {
+            cg: CausalGraph::new(),
+            branch: ListBranch::new(),
+            tracker: StreamTracker::new(),
+            stable: Frontier::root(),
+        }
+    }
+
+    /// The document, at the version of all the operations applied so far.
+    pub fn branch(&self) -> &ListBranch { &self.branch }
+
+    pub fn cg(&self) -> &CausalGraph { &self.cg }
+
+    pub fn local_frontier_ref(&self) -> &[LV] { self.cg.version.as_ref() }
+
+    pub fn remote_frontier(&self) -> RemoteFrontier<'_> {
+        self.cg.remote_frontier()
+    }
+
+    /// Apply an operation made by `agent`, with the sequence number `seq`, at the version
+    /// `parents`. The operation's parents must have already been applied. Operations (or parts of
+    /// operations) which have already been applied are ignored.
+    ///
+    /// Returns the local versions assigned to the new part of the operation.
+    ///
+    /// Like operations added to an oplog with
+    /// [`add_operations_remote`](crate::list::ListOpLog::add_operations_remote), the operation's
+    /// position is trusted to be valid at its parents. If the operation can't be applied, an error
+    /// is returned and the client isn't changed.
+    pub fn apply_remote_op(&mut self, agent: &str, seq: LV, parents: &[RemoteVersion], op: &TextOperation) -> Result<DTRange, ThinClientError> {
+        let parents = self.cg.agent_assignment.try_remote_to_local_frontier(parents.iter().copied())?;
+        let len = op.len();
+        let content = match op.kind {
+            ListOpKind::Ins => {
+                let content = op.content_as_str().ok_or(ThinClientError::MissingContent)?;
+                if count_chars(content) != len { return Err(ThinClientError::InvalidContent); }
+                Some(content)
+            }
+            ListOpKind::Del => None,
+        };
+
+        // Operations we already have are ignored. (If we have the end of an operation, we have
+        // all of it.)
+        let aa = &self.cg.agent_assignment;
+        let known = len == 0 || aa.get_agent_id(agent)
+            .is_some_and(|a| aa.client_data[a as usize].try_seq_to_lv(seq + len as LV - 1).is_some());
+        if known { return Ok((self.cg.len()..self.cg.len()).into()); }
+        if !self.cg.graph.frontier_contains_frontier(parents.as_ref(), self.stable.as_ref()) {
+            return Err(ThinClientError::BeforeStableVersion);
+        }
+
+        let agent = self.cg.get_or_create_agent_id(agent);
+        let lv_range = self.cg.merge_and_assign(parents.as_ref(), AgentSpan {
+            agent,
+            seq_range: (seq..seq + len as LV).into(),
+        });
+        if lv_range.is_empty() { return Ok(lv_range); }
+
+        // Skip the part of the operation we already have.
+        let skip = len - lv_range.len();
+        let mut metrics = ListOpMetrics { loc: op.loc, kind: op.kind, content_pos: None };
+        if skip > 0 { metrics.loc.truncate_keeping_right(skip); }
+
+        let branch = &mut self.branch;
+        self.tracker.integrate(&self.cg, lv_range.start, metrics, |v, op| {
+            match op.kind {
+                ListOpKind::Ins => {
+                    let content = content.unwrap();
+                    let offset = skip + lv_to_usize(v - lv_range.start);
+                    let start = chars_to_bytes(content, offset);
+                    let end = start + chars_to_bytes(&content[start..], op.len());
+                    let content = &content[start..end];
+                    if op.loc.fwd {
+                        branch.content_insert(op.start(), content, op.len(), Some(v));
+                    } else {
+                        branch.content_insert(op.start(), &reverse_str(content), op.len(), Some(v));
+                    }
+                }
+                ListOpKind::Del => {
+                    branch.content_remove(op.loc.span.into(), Some(v));
+                }
+            }
+        });
+
+        self.branch.version = self.cg.version.clone();
+        Ok(lv_range)
+    }
+
+    /// Tell the client that every operation applied from now on will have `version` in its
+    /// history. (For example, because every peer has acknowledged it.)
+    ///
+    /// If the stable version includes every operation applied so far, the tracker's items are
+    /// thrown away. Later operations which don't have the stable version in their history are
+    /// rejected with [`ThinClientError::BeforeStableVersion`].
+    pub fn set_stable_version(&mut self, version: &[RemoteVersion]) -> Result<(), ThinClientError> {
+        let version = self.cg.agent_assignment.try_remote_to_local_frontier(version.iter().copied())?;
+        let mut stable = self.stable.clone();
+        stable.merge_union(version.as_ref(), &self.cg.graph);
+        if stable == self.cg.version {
+            self.tracker.clear(stable.clone());
+        }
+        self.stable = stable;
+        Ok(())
+    }
+
+    /// Consume the thin client, returning the branch.
+    pub fn into_branch(self) -> ListBranch {
+        self.branch
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::gen_random::gen_oplog;
+    use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersion, VersionConversionError};
+    use crate::list::operation::TextOperation;
+    use super::{ThinClient, ThinClientError};
+
+    #[test]
+    fn thin_client_matches_checkout() {
+        for seed in 0..20 {
+            let oplog = gen_oplog(seed, 40, false, true);
+            let mut client = ThinClient::new();
+
+            for (op, hist, rv) in oplog.iter_full() {
+                let parents = oplog.cg.agent_assignment.local_to_remote_frontier(hist.parents.as_ref());
+                let range = client.apply_remote_op(rv.0, rv.1.start, &parents, &op).unwrap();
+                assert_eq!(range, hist.span);
+
+                // Duplicates are ignored.
+                let range = client.apply_remote_op(rv.0, rv.1.start, &parents, &op).unwrap();
+                assert!(range.is_empty());
+            }
+
+            let expected = oplog.checkout_tip();
+            assert_eq!(client.branch().content(), expected.content());
+            assert_eq!(client.local_frontier_ref(), oplog.local_frontier_ref());
+        }
+
+        let mut client = ThinClient::new();
+        assert_eq!(client.apply_remote_op("seph", 0, &[("mike", 3).into()], &TextOperation::new_delete(0..1)),
+                   Err(ThinClientError::UnknownVersion(VersionConversionError::UnknownAgent)));
+    }
+
+    #[test]
+    fn bad_inserts_are_errors() {
+        let mut client = ThinClient::new();
+        let mut op = TextOperation::new_insert(0, "hi");
+        op.content = None;
+        assert_eq!(client.apply_remote_op("seph", 0, &[], &op), Err(ThinClientError::MissingContent));
+
+        let mut op = TextOperation::new_insert(0, "hi");
+        op.loc.span.end += 1;
+        assert_eq!(client.apply_remote_op("seph", 0, &[], &op), Err(ThinClientError::InvalidContent));
+
+        // The client isn't changed.
+        assert!(client.cg().is_empty());
+        assert!(client.apply_remote_op("seph", 0, &[], &TextOperation::new_insert(0, "hi")).is_ok());
+        assert_eq!(client.branch().content(), "hi");
+    }
+
+    #[test]
+    fn stable_versions_prune_the_tracker() {
+        let mut client = ThinClient::new();
+        client.apply_remote_op("seph", 0, &[], &TextOperation::new_insert(0, "hello")).unwrap();
+        client.apply_remote_op("mike", 0, &[], &TextOperation::new_insert(0, "yo ")).unwrap();
+        let version: [RemoteVersion; 2] = [("seph", 4).into(), ("mike", 2).into()];
+        client.set_stable_version(&version).unwrap();
+
+        // Operations after the stable version still merge correctly.
+        client.apply_remote_op("seph", 5, &version, &TextOperation::new_insert(8, "!")).unwrap();
+        client.apply_remote_op("mike", 3, &version, &TextOperation::new_delete(0..3)).unwrap();
+        assert_eq!(client.branch().content(), "hello!");
+
+        // But operations which don't know about the stable version are rejected.
+        let op = TextOperation::new_insert(0, "x");
+        assert_eq!(client.apply_remote_op("amy", 0, &[("seph", 4).into()], &op), Err(ThinClientError::BeforeStableVersion));
+        // Unless we already have them.
+        assert!(client.apply_remote_op("seph", 0, &[], &TextOperation::new_insert(0, "hello")).unwrap().is_empty());
+    }
+}
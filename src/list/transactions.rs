@@ -0,0 +1,204 @@
+//! Atomic transactions.
+//!
+//! A transaction groups a run of operations which should never be seen partially applied. (Eg, the
+//! delete and insert from a paste replacing a selection.) The operations in a transaction must be
+//! a linear run of consecutive local versions from a single agent.
+//!
+//! Transactions don't change how operations are stored or transformed. Instead, when a branch
+//! merges (or rewinds to) a version which includes only part of a transaction, the rest of the
+//! transaction is merged too. Transaction boundaries are stored alongside the operations when the
+//! oplog is encoded.
+
+use rle::HasLength;
+use crate::{AgentId, DTRange, Frontier, LV};
+use crate::list::{ListCRDT, ListOpLog};
+
+impl ListOpLog {
+    /// Mark the operations in `range` as a single atomic transaction. Marking the same range twice
+    /// does nothing.
+    ///
+    /// Panics if the range overlaps an existing transaction, or if the operations aren't a linear
+    /// run of operations from a single agent.
+    pub fn add_transaction(&mut self, range: DTRange) {
+        assert!(self.try_add_transaction(range), "Invalid transaction");
+    }
+
+    /// Returns false if the transaction is invalid.
+    pub(crate) fn try_add_transaction(&mut self, range: DTRange) -> bool {
+        if range.is_empty() || range.end > self.len() || !self.is_valid_transaction(range) {
+            return false;
+        }
+
+        let idx = self.transactions.partition_point(|t| t.end <= range.start);
+        if let Some(existing) = self.transactions.get(idx) {
+            if *existing == range { return true; }
+            if existing.start < range.end { return false; }
+        }
+        self.transactions.insert(idx, range);
+        true
+    }
+
+    fn is_valid_transaction(&self, range: DTRange) -> bool {
+        let linear = self.cg.graph.iter_range(range)
+            .all(|e| e.span.start == range.start || e.parents.as_ref() == [e.span.start - 1]);
+
+        let mut agents = self.cg.agent_assignment.client_with_lv.iter_range(range).map(|e| e.1.agent);
+        let first = agents.next();
+        linear && agents.all(|a| Some(a) == first)
+    }
+
+    /// Returns the transaction containing `v`, if any.
+    pub fn transaction_containing(&self, v: LV) -> Option<DTRange> {
+        let idx = self.transactions.partition_point(|t| t.end <= v);
+        self.transactions.get(idx).copied().filter(|t| t.start <= v)
+    }
+
+    /// Iterate through the oplog's history in atomic units. Each transaction is returned as a
+    /// single range. Operations which aren't part of any transaction are returned one operation
+    /// at a time.
+    pub fn iter_transactions(&self) -> impl Iterator<Item = DTRange> + '_ {
+        let mut next = 0;
+        std::iter::from_fn(move || {
+            if next >= self.len() { return None; }
+
+            let range = if let Some(txn) = self.transaction_containing(next) {
+                txn
+            } else {
+                let (op, offset) = self.operations.find_packed_with_offset(next);
+                let op_end = next + (op.len() - offset) as LV;
+                let idx = self.transactions.partition_point(|t| t.start <= next);
+                let next_txn = self.transactions.get(idx).map_or(LV::MAX, |t| t.start);
+                (next..op_end.min(next_txn)).into()
+            };
+
+            next = range.end;
+            Some(range)
+        })
+    }
+
+    /// Expand `frontier` to include the rest of any transaction it only partially contains.
+    pub(crate) fn expand_to_transactions(&self, frontier: &[LV]) -> Frontier {
+        let mut result = Frontier::from_unsorted(frontier);
+        if self.transactions.is_empty() { return result; }
+
+        for v in frontier {
+            if let Some(txn) = self.transaction_containing(*v) {
+                if *v + 1 < txn.end {
+                    result.merge_union(&[txn.last()], &self.cg.graph);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl ListCRDT {
+    /// Run `f`, and group all the local changes it makes into a single atomic transaction.
+    ///
+    /// A transaction can only contain changes from one agent. If `f` makes changes as more than
+    /// one agent (or merges in other changes), each linear run of changes from a single agent
+    /// becomes its own transaction.
+    pub fn transaction<R, F: FnOnce(&mut Self) -> R>(&mut self, f: F) -> R {
+        let start = self.oplog.len();
+        let result = f(self);
+        let end = self.oplog.len();
+
+        let mut runs: Vec<(DTRange, AgentId)> = vec![];
+        for e in self.oplog.cg.iter_range((start..end).into()) {
+            let span: DTRange = (e.start..e.start + e.span.len() as LV).into();
+            match runs.last_mut() {
+                Some((r, agent)) if *agent == e.span.agent && e.parents.as_ref() == [r.last()] => {
+                    r.end = span.end;
+                }
+                _ => runs.push((span, e.span.agent)),
+            }
+        }
+        for (r, _) in runs {
+            self.oplog.add_transaction(r);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::encoding::{ENCODE_FULL, EncodeOptions, ParseError};
+
+    #[test]
+    fn transactions_merge_atomically() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hello world");
+        let v = doc.oplog.local_frontier();
+
+        // Replace "world" with "there".
+        doc.transaction(|doc| {
+            doc.delete(seph, 6..11);
+            doc.insert(seph, 6, "there");
+        });
+        assert_eq!(doc.oplog.transaction_containing(12), Some((11..21).into()));
+        assert_eq!(doc.oplog.transaction_containing(3), None);
+
+        // Merging only part of the transaction merges the whole thing.
+        let mut branch = doc.oplog.checkout(v.as_ref());
+        branch.merge(&doc.oplog, &[13]);
+        assert_eq!(branch.content().to_string(), "hello there");
+        assert_eq!(branch.local_frontier_ref(), &[20]);
+
+        assert_eq!(doc.oplog.iter_transactions().collect::<Vec<_>>(), vec![
+            (0..11).into(), (11..21).into()
+        ]);
+
+        // Transaction boundaries survive encoding.
+        let data = doc.oplog.encode(&ENCODE_FULL);
+        let oplog2 = ListOpLog::load_from(&data).unwrap();
+        assert_eq!(oplog2.transaction_containing(15), Some((11..21).into()));
+
+        // And decoding the same data again doesn't duplicate them.
+        let mut oplog3 = oplog2.clone();
+        oplog3.decode_and_add(&data).unwrap();
+        assert_eq!(oplog3.transactions, oplog2.transactions);
+    }
+
+    #[test]
+    fn transactions_with_multiple_agents() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let mike = doc.get_or_create_agent_id("mike");
+        doc.transaction(|doc| {
+            doc.insert(seph, 0, "ab");
+            doc.insert(mike, 2, "cd");
+            doc.insert(seph, 4, "e");
+        });
+        assert_eq!(doc.oplog.iter_transactions().collect::<Vec<_>>(), vec![
+            (0..2).into(), (2..4).into(), (4..5).into()
+        ]);
+    }
+
+    #[test]
+    fn transactions_rolled_back_with_failed_decode() {
+        let mut a = ListCRDT::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, "ab");
+        let mut b = a.clone();
+        b.transaction(|b| b.insert(seph, 2, "xyz"));
+
+        let mut data = b.oplog.encode_from(&EncodeOptions::patch(), a.oplog.local_frontier_ref());
+        *data.last_mut().unwrap() ^= 1; // Corrupt the checksum.
+        assert_eq!(a.oplog.decode_and_add(&data), Err(ParseError::ChecksumFailed));
+        assert!(a.oplog.transactions.is_empty());
+        a.insert(seph, 2, "c");
+        assert_eq!(a.oplog.transaction_containing(2), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn transactions_must_be_linear() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "a");
+        oplog.add_insert_at(seph, &[], 0, "b");
+        oplog.add_transaction((0..2).into());
+    }
+}
@@ -0,0 +1,107 @@
+//! Transformed positions stored in encoded files.
+//!
+//! Checking out a document normally runs the merge algorithm over every concurrent region of its
+//! history. When an oplog is encoded with
+//! [`EncodeOptions::store_xf`](crate::list::encoding::EncodeOptions::store_xf), the operations are
+//! sorted and the file also stores where each operation lands when the operations are applied in
+//! that order. Loading that file gives us a list of already-transformed operations, so
+//! [`checkout_tip`](ListOpLog::checkout_tip) is just a sequential replay.
+//!
+//! The stored positions cover the operations which were loaded with the file. Operations added
+//! later are merged as usual on top of the replayed document.
+//!
+//! Checking the positions would mean redoing the merge, so they're only loaded when
+//! [`DecodeOptions::trust_transformed_positions`](crate::list::encoding::DecodeOptions::trust_transformed_positions)
+//! is set. Files from anywhere else are checked out by merging, as usual.
+
+use rle::HasLength;
+use crate::{DTRange, Frontier};
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::ListOpKind;
+use crate::rle::KVPair;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TransformedPositions {
+    /// The version containing all the operations in `runs`.
+    pub(crate) version: Frontier,
+    /// Runs of operations, starting at 0. Each run is either moved by some amount, or cancelled
+    /// (`None`), which happens when a delete was concurrently deleted by another operation.
+    pub(crate) runs: Vec<(DTRange, Option<isize>)>,
+}
+
+impl ListOpLog {
+    /// Returns true if this oplog was loaded with stored transformed positions, so checking out the
+    /// document doesn't need to merge concurrent changes.
+    pub fn has_transformed_positions(&self) -> bool {
+        self.transformed_positions.is_some()
+    }
+
+    /// Replay the stored transformed positions into a new branch. Returns None if there aren't any
+    /// stored positions, or they're invalid.
+    pub(crate) fn checkout_transformed_positions(&self) -> Option<ListBranch> {
+        let xf = self.transformed_positions.as_ref()?;
+        let mut branch = ListBranch::new();
+
+        for &(range, moved_by) in &xf.runs {
+            let Some(moved_by) = moved_by else { continue; };
+            for KVPair(lv, mut op) in self.operations.iter_range_ctx(range, &self.operation_ctx) {
+                if moved_by != 0 {
+                    let pos = (op.start() as isize).checked_add(moved_by)?;
+                    op.transpose_to(usize::try_from(pos).ok()?);
+                }
+
+                let valid = match op.kind {
+                    ListOpKind::Ins => op.start() <= branch.len(),
+                    ListOpKind::Del => op.end() <= branch.len(),
+                };
+                if !valid { return None; }
+                branch.apply_op_at(self, lv, op);
+            }
+        }
+
+        branch.version = xf.version.clone();
+        Some(branch)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::{DecodeOptions, EncodeOptions};
+    use crate::list::gen_random::gen_oplog;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn checkout_with_stored_positions() {
+        for seed in 0..20 {
+            let oplog = gen_oplog(seed, 40, false, true);
+            let expected = oplog.checkout_tip();
+
+            let data = oplog.encode(&EncodeOptions::full().store_xf(true));
+            // Stored positions are ignored unless they're trusted.
+            assert!(!ListOpLog::load_from(&data).unwrap().has_transformed_positions());
+            let opts = DecodeOptions { trust_transformed_positions: true, ..Default::default() };
+            let mut loaded = ListOpLog::load_from_opts(&data, opts.clone()).unwrap();
+            assert!(loaded.has_transformed_positions());
+            assert_eq!(loaded.checkout_transformed_positions().unwrap().content(), expected.content());
+            assert_eq!(loaded.checkout_tip(), loaded.checkout(loaded.local_frontier_ref()));
+            assert_eq!(loaded.checkout_tip().content(), expected.content());
+
+            // Changes made after loading are merged on top.
+            let agent = loaded.get_or_create_agent_id("zed");
+            loaded.add_insert_at(agent, &[], 0, "xyz");
+            assert_eq!(loaded.checkout_tip(), loaded.checkout(loaded.local_frontier_ref()));
+
+            // Merging into a document which already has operations doesn't use stored positions.
+            let mut other = ListOpLog::new();
+            let agent = other.get_or_create_agent_id("someone");
+            other.add_insert(agent, 0, "hi");
+            other.decode_and_add_opts(&data, opts.clone()).unwrap();
+            assert!(!other.has_transformed_positions());
+        }
+
+        // Files without stored positions.
+        let oplog = gen_oplog(1, 10, false, true);
+        let loaded = ListOpLog::load_from(&oplog.encode(&EncodeOptions::full())).unwrap();
+        assert!(!loaded.has_transformed_positions());
+    }
+}
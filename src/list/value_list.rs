@@ -0,0 +1,658 @@
+//! A list CRDT storing arbitrary values instead of characters.
+//!
+//! This reuses all the list merging code from text documents. The list's operations are stored in a
+//! normal [`ListOpLog`] with no inserted content. The inserted values are stored separately, keyed
+//! by the local version of the insert which created them. When operations are transformed (eg when
+//! merging concurrent changes), we just look up the values for the inserted versions.
+//!
+//! ## Moves
+//!
+//! Moving an item inserts a new *slot* for the item at the destination. The item's old slot is
+//! left in place, and we remember which original item each slot stands in for. Each item's
+//! position acts as a last-writer-wins register over its slots:
+//!
+//! - An item is only shown in its winning slot. A slot wins if none of the item's other slots were
+//!   created causally after it. Concurrent moves are tie-broken by agent, so if an item is moved to
+//!   multiple places concurrently, it only shows up at one of the destinations.
+//! - Deleting any of an item's slots deletes the item. So if an item is deleted concurrently with
+//!   being moved, the delete wins.
+//!
+//! To merge incrementally we also remember every deleted item, since a slot can be deleted by
+//! several concurrent operations and the transformed operations only name it once.
+//!
+//! Moves are built out of normal inserts, so there's no move operation in [`ListOpKind`] and the
+//! oplog's encoding and merging code don't know about them. The move information is sent
+//! alongside the operations in each [`ValueListPatch`]. This also means text documents
+//! ([`ListCRDT`](crate::list::ListCRDT)) don't support moves - they can only be moved with a delete
+//! and an insert.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use rle::HasLength;
+use crate::{AgentId, DTRange, Frontier, LV};
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersion, RemoteVersionOwned};
+use crate::encoding::parseerror::ParseError;
+use crate::list::encoding::EncodeOptions;
+use crate::list::ListOpLog;
+use crate::list::operation::{ListOpKind, TextOperation};
+use crate::listmerge::rewind::RewindTracker;
+use crate::rle::KVPair;
+
+#[derive(Debug, Clone)]
+struct Item<T> {
+    lv: LV,
+    /// Only missing if a peer sent us an insert without its values.
+    value: Option<T>,
+    /// Hidden items are still in the oplog's view of the list, but they've lost a concurrent move
+    /// or been deleted. They're skipped when counting positions.
+    hidden: bool,
+}
+
+#[derive(Debug, Clone)]
+struct MoveInfo {
+    /// The first version of the insert which created the new slots for the items.
+    ins_start: LV,
+    /// The original item for each moved item.
+    originals: Vec<LV>,
+}
+
+/// A list of values of type T, along with the list's editing history.
+///
+/// Like [`ListCRDT`](crate::list::ListCRDT), this pairs an oplog with the current state of the
+/// list. Local edits are applied to both, and remote changes are merged in with
+/// [`merge_patch`](ValueList::merge_patch).
+#[derive(Debug, Clone)]
+pub struct ValueList<T> {
+    oplog: ListOpLog,
+
+    /// (version, value) pairs for every inserted item. Sorted by version. Items inserted by moves
+    /// aren't in here - they use the value of the original item.
+    values: Vec<(LV, T)>,
+
+    /// Every move operation. Sorted by the version of the insert.
+    moves: Vec<MoveInfo>,
+
+    /// (slot, original item) for every slot inserted by a move. Sorted by slot.
+    moved: Vec<(LV, LV)>,
+
+    /// The original items which have been deleted (via any of their slots).
+    deleted: BTreeSet<LV>,
+
+    /// The list's content at `oplog.cg.version`. This contains every slot which is visible in the
+    /// underlying oplog, so indexes in here line up with positions in the oplog's operations.
+    content: Vec<Item<T>>,
+}
+
+/// A set of changes to a [`ValueList`], which can be sent to remote peers.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ValueListPatch<T> {
+    /// The list's operations, encoded with the normal binary encoding.
+    ops: Vec<u8>,
+    /// Runs of values, named by the remote version of the first inserted item in each run.
+    values: Vec<(RemoteVersionOwned, Vec<T>)>,
+    /// (first inserted slot, original items) for each move.
+    moves: Vec<(RemoteVersionOwned, Vec<RemoteVersionOwned>)>,
+}
+
+/// The reason [`ValueList::move_items`] couldn't move the requested items.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MoveError {
+    /// The range of items to move is empty.
+    EmptyRange,
+    /// The range of items or the destination is past the end of the list.
+    OutOfBounds,
+    /// The destination is inside the range of items being moved.
+    IntoItself,
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MoveError {:?}", self)
+    }
+}
+
+impl Error for MoveError {}
+
+impl<T: Clone> Default for ValueList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> ValueList<T> {
+    pub fn new() -> Self {
+        Self {
+            oplog: ListOpLog::new(),
+            values: vec![],
+            moves: vec![],
+            moved: vec![],
+            deleted: BTreeSet::new(),
+            content: vec![],
+        }
+    }
+
+    pub fn oplog(&self) -> &ListOpLog {
+        &self.oplog
+    }
+
+    pub fn get_or_create_agent_id(&mut self, name: &str) -> AgentId {
+        self.oplog.get_or_create_agent_id(name)
+    }
+
+    /// Iterate through the values currently in the list.
+    ///
+    /// Items whose values we never received (which only happens when a peer sends a malformed
+    /// patch) are skipped. They still take up a position in the list.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.content.iter().filter(|item| !item.hidden).filter_map(|item| item.value.as_ref())
+    }
+
+    /// Get the value at position `pos`.
+    pub fn get(&self, pos: usize) -> Option<&T> {
+        self.content.iter().filter(|item| !item.hidden).nth(pos)?.value.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.content.iter().filter(|item| !item.hidden).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The indexes in content of the visible items in `range`.
+    fn raw_indexes(&self, range: Range<usize>) -> Vec<usize> {
+        let result: Vec<usize> = self.content.iter().enumerate()
+            .filter(|(_, item)| !item.hidden)
+            .skip(range.start)
+            .take(range.len())
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(result.len(), range.len(), "Range out of bounds");
+        result
+    }
+
+    /// The index in content to insert at, to put new items at position `pos`.
+    fn raw_insert_pos(&self, pos: usize) -> usize {
+        if pos == 0 { 0 } else { self.raw_indexes(pos - 1..pos)[0] + 1 }
+    }
+
+    /// Insert `items` at position `pos` in the list.
+    pub fn insert(&mut self, agent: AgentId, pos: usize, items: &[T]) -> LV {
+        let pos = self.raw_insert_pos(pos);
+        let start = self.oplog.len();
+        let v = self.add_insert_op(agent, pos, items.len());
+
+        self.values.extend(items.iter().cloned().enumerate().map(|(i, item)| (start + i as LV, item)));
+        self.content.splice(pos..pos, items.iter().enumerate().map(|(i, item)| {
+            Item { lv: start + i as LV, value: Some(item.clone()), hidden: false }
+        }));
+        v
+    }
+
+    fn add_insert_op(&mut self, agent: AgentId, pos: usize, len: usize) -> LV {
+        self.oplog.add_operations(agent, &[TextOperation {
+            loc: (pos as LV..(pos + len) as LV).into(),
+            kind: ListOpKind::Ins,
+            content: None,
+        }])
+    }
+
+    /// Delete the items in `range`, which must not be empty.
+    pub fn delete(&mut self, agent: AgentId, range: Range<usize>) -> LV {
+        assert!(!range.is_empty());
+        self.delete_raw(agent, &self.raw_indexes(range));
+        self.oplog.len() - 1
+    }
+
+    /// Delete the items at the named indexes in content. Hidden items are skipped over, so this
+    /// might need multiple delete operations.
+    fn delete_raw(&mut self, agent: AgentId, indexes: &[usize]) {
+        let mut end = indexes.len();
+        while end > 0 {
+            // Find the run of consecutive indexes ending at end. Runs are deleted back to front so
+            // the earlier indexes stay valid.
+            let mut start = end - 1;
+            while start > 0 && indexes[start - 1] + 1 == indexes[start] { start -= 1; }
+            let range = indexes[start]..indexes[end - 1] + 1;
+            self.oplog.add_delete_without_content(agent, range.clone());
+            let removed: Vec<LV> = self.content.drain(range).map(|item| item.lv).collect();
+            for lv in removed {
+                self.deleted.insert(self.original_of(lv));
+            }
+            end = start;
+        }
+    }
+
+    /// Move the items in `from` so they start at position `to` (in the list as it is before the
+    /// move). `to` can't be inside `from`. Returns an error (and leaves the list unchanged) if
+    /// the range is empty, or the range or destination are out of bounds.
+    ///
+    /// Moved items keep their identity. When concurrent moves are merged, each item ends up at
+    /// the destination of the move which was made last, and deleting an item wins over
+    /// concurrently moving it.
+    pub fn move_items(&mut self, agent: AgentId, from: Range<usize>, to: usize) -> Result<LV, MoveError> {
+        if from.is_empty() { return Err(MoveError::EmptyRange); }
+        let list_len = self.len();
+        if from.end > list_len || to > list_len { return Err(MoveError::OutOfBounds); }
+        if to > from.start && to < from.end { return Err(MoveError::IntoItself); }
+        let len = from.len();
+
+        let indexes = self.raw_indexes(from);
+        let originals = indexes.iter()
+            .map(|i| self.original_of(self.content[*i].lv))
+            .collect();
+        let values: Vec<_> = indexes.iter().map(|i| self.content[*i].value.clone()).collect();
+
+        let pos = self.raw_insert_pos(to);
+        // The old slots stay in the list. The new slots are causally after them, so they win.
+        for i in indexes.iter() {
+            self.content[*i].hidden = true;
+        }
+
+        let ins_start = self.oplog.len();
+        let v = self.add_insert_op(agent, pos, len);
+        self.content.splice(pos..pos, values.into_iter().enumerate().map(|(i, value)| {
+            Item { lv: ins_start + i as LV, value, hidden: false }
+        }));
+
+        self.push_move(MoveInfo { ins_start, originals });
+        Ok(v)
+    }
+
+    fn push_move(&mut self, m: MoveInfo) {
+        let Err(idx) = self.moves.binary_search_by_key(&m.ins_start, |m| m.ins_start) else { return; };
+        for (i, orig) in m.originals.iter().enumerate() {
+            let item = m.ins_start + i as LV;
+            if let Err(idx) = self.moved.binary_search_by_key(&item, |(item, _)| *item) {
+                self.moved.insert(idx, (item, *orig));
+            }
+        }
+        self.moves.insert(idx, m);
+    }
+
+    /// Slots inserted by moves stand in for the item which was moved.
+    fn original_of(&self, item: LV) -> LV {
+        match self.moved.binary_search_by_key(&item, |(item, _)| *item) {
+            Ok(idx) => self.moved[idx].1,
+            Err(_) => item,
+        }
+    }
+
+    fn value_of(&self, item: LV) -> Option<T> {
+        let orig = self.original_of(item);
+        self.values.binary_search_by_key(&orig, |(v, _)| *v).ok()
+            .map(|idx| self.values[idx].1.clone())
+    }
+
+    /// Of the slots standing in for the same original item, which one was written last? The
+    /// winner doesn't depend on the order of `slots`.
+    fn last_moved(&self, slots: &[LV]) -> LV {
+        let graph = &self.oplog.cg.graph;
+        let aa = &self.oplog.cg.agent_assignment;
+        slots.iter().copied()
+            .filter(|a| !slots.iter().any(|b| graph.version_cmp(*a, *b) == Some(Ordering::Less)))
+            .max_by(|a, b| {
+                aa.tie_break_agent_versions(aa.local_to_agent_version(*a), aa.local_to_agent_version(*b))
+            })
+            .unwrap()
+    }
+
+    /// Apply the transformed operations between the two versions to `content`. Deleted items are
+    /// added to `deleted`.
+    fn apply_xf(&self, content: &mut Vec<Item<T>>, deleted: &mut BTreeSet<LV>, from: &[LV], merging: &[LV]) {
+        for (range, op) in self.oplog.iter_xf_operations_from(from, merging) {
+            let Some(op) = op else { continue; };
+            match op.kind {
+                ListOpKind::Ins => {
+                    let mut items: Vec<_> = range.iter().map(|lv| {
+                        Item { lv, value: self.value_of(lv), hidden: false }
+                    }).collect();
+                    if !op.loc.fwd { items.reverse(); }
+                    let pos = op.start();
+                    content.splice(pos..pos, items);
+                }
+                ListOpKind::Del => {
+                    for item in content.drain(op.start()..op.end()) {
+                        deleted.insert(self.original_of(item.lv));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hide every slot which isn't its item's winning slot, and the slots of deleted items.
+    fn update_hidden(&self, content: &mut [Item<T>], deleted: &BTreeSet<LV>) {
+        let mut slots: BTreeMap<LV, Vec<LV>> = BTreeMap::new();
+        for item in content.iter() {
+            slots.entry(self.original_of(item.lv)).or_default().push(item.lv);
+        }
+        let winners: BTreeMap<LV, LV> = slots.iter()
+            .map(|(orig, slots)| (*orig, self.last_moved(slots)))
+            .collect();
+
+        for item in content.iter_mut() {
+            let orig = self.original_of(item.lv);
+            item.hidden = deleted.contains(&orig) || winners[&orig] != item.lv;
+        }
+    }
+
+    /// Figure out the list's content at some version by replaying the entire history.
+    fn content_at(&self, version: &[LV]) -> Vec<Item<T>> {
+        let mut content = vec![];
+        if self.moves.is_empty() {
+            self.apply_xf(&mut content, &mut BTreeSet::new(), &[], version);
+            return content;
+        }
+
+        let mut tracker = RewindTracker::new();
+        tracker.move_to(&self.oplog, version);
+
+        // Deleting any slot deletes the item, even if it was concurrently moved.
+        let mut deleted = BTreeSet::new();
+        for range in self.oplog.cg.graph.diff_rev(&[], version).1 {
+            for (KVPair(start, op), _) in self.oplog.iter_range_simple(range) {
+                if op.kind != ListOpKind::Del { continue; }
+                for v in start..start + op.len() as LV {
+                    deleted.insert(self.original_of(tracker.deleted_item(v)));
+                }
+            }
+        }
+
+        content.extend(tracker.iter_visible().flat_map(|r| r.iter()).map(|lv| {
+            Item { lv, value: self.value_of(lv), hidden: false }
+        }));
+        self.update_hidden(&mut content, &deleted);
+        content
+    }
+
+    /// Get the list's content at some version.
+    pub fn checkout_at(&self, version: &[LV]) -> Vec<T> {
+        self.content_at(version).into_iter()
+            .filter(|item| !item.hidden)
+            .filter_map(|item| item.value)
+            .collect()
+    }
+
+    /// Get all the changes since the named version, for sending to a remote peer.
+    pub fn ops_since(&self, from: &[LV]) -> ValueListPatch<T> {
+        let aa = &self.oplog.cg.agent_assignment;
+        let mut values = vec![];
+        let mut moves = vec![];
+        for range in self.oplog.cg.diff_since(from) {
+            let start = self.values.partition_point(|(v, _)| *v < range.start);
+            let end = self.values.partition_point(|(v, _)| *v < range.end);
+            let known = &self.values[start..end];
+
+            let mut i = 0;
+            while i < known.len() {
+                // Find the run of consecutive versions starting at i.
+                let start = known[i].0;
+                let mut end = i + 1;
+                while end < known.len() && known[end].0 == start + (end - i) as LV { end += 1; }
+
+                // And split it up by agent.
+                let run: DTRange = (start..start + (end - i) as LV).into();
+                for span in self.oplog.iter_remote_mappings_range(run) {
+                    let items = known[i..i + span.len()].iter().map(|(_, t)| t.clone()).collect();
+                    values.push((RemoteVersionOwned(span.0.into(), span.1.start), items));
+                    i += span.len();
+                }
+            }
+
+            let start = self.moves.partition_point(|m| m.ins_start < range.start);
+            for m in self.moves[start..].iter().take_while(|m| m.ins_start < range.end) {
+                let originals = m.originals.iter()
+                    .map(|orig| aa.local_to_remote_version(*orig).into())
+                    .collect();
+                moves.push((aa.local_to_remote_version(m.ins_start).into(), originals));
+            }
+        }
+
+        ValueListPatch {
+            ops: EncodeOptions::patch()
+                .store_inserted_content(false)
+                .encode_from(&self.oplog, from),
+            values,
+            moves,
+        }
+    }
+
+    /// Find the run of `len` inserted items starting at `rv`.
+    fn insert_run(&self, rv: &RemoteVersionOwned, len: usize) -> Result<LV, ParseError> {
+        let aa = &self.oplog.cg.agent_assignment;
+        let to_lv = |rv: RemoteVersion| aa.try_remote_to_local_version(rv)
+            .map_err(ParseError::InvalidRemoteID);
+
+        if len == 0 { return Err(ParseError::InvalidLength); }
+        let start = to_lv(rv.into())?;
+        let len = len as LV;
+        let last_seq = rv.1.checked_add(len - 1).ok_or(ParseError::InvalidLength)?;
+        if to_lv(RemoteVersion(&rv.0, last_seq))? != start + len - 1 {
+            return Err(ParseError::InvalidLength);
+        }
+        let all_inserts = self.oplog.iter_range_simple((start..start + len).into())
+            .all(|(KVPair(_, op), _)| op.kind == ListOpKind::Ins);
+        if !all_inserts { return Err(ParseError::GenericInvalidData); }
+        Ok(start)
+    }
+
+    /// Read the values and moves in a patch whose operations have already been merged.
+    #[allow(clippy::type_complexity)]
+    fn read_patch(&self, patch: &ValueListPatch<T>) -> Result<(Vec<(LV, T)>, Vec<MoveInfo>), ParseError> {
+        let mut values = vec![];
+        for (rv, items) in &patch.values {
+            let start = self.insert_run(rv, items.len())?;
+            values.extend(items.iter().cloned().enumerate().map(|(i, item)| (start + i as LV, item)));
+        }
+
+        let mut moves = vec![];
+        for (rv, originals) in &patch.moves {
+            let ins_start = self.insert_run(rv, originals.len())?;
+            let originals = originals.iter()
+                .map(|orig| self.insert_run(orig, 1))
+                .collect::<Result<Vec<_>, _>>()?;
+            moves.push(MoveInfo { ins_start, originals });
+        }
+        Ok((values, moves))
+    }
+
+    /// Merge changes from a remote peer into this list. Returns the new version of the list.
+    ///
+    /// If the patch is invalid, the list is left unchanged.
+    pub fn merge_patch(&mut self, patch: &ValueListPatch<T>) -> Result<Frontier, ParseError> {
+        let old_version = self.oplog.cg.version.clone();
+        let checkpoint = self.oplog.checkpoint();
+        self.oplog.decode_and_add(&patch.ops)?;
+
+        let (values, moves) = match self.read_patch(patch) {
+            Ok(result) => result,
+            Err(e) => {
+                self.oplog.roll_back(checkpoint);
+                return Err(e);
+            }
+        };
+
+        for (v, item) in values {
+            if let Err(idx) = self.values.binary_search_by_key(&v, |(v, _)| *v) {
+                self.values.insert(idx, (v, item));
+            }
+        }
+        for m in moves {
+            self.push_move(m);
+        }
+
+        let mut content = std::mem::take(&mut self.content);
+        let mut deleted = std::mem::take(&mut self.deleted);
+        self.apply_xf(&mut content, &mut deleted, old_version.as_ref(), self.oplog.cg.version.as_ref());
+        if !self.moves.is_empty() {
+            self.update_hidden(&mut content, &deleted);
+        }
+        self.content = content;
+        self.deleted = deleted;
+        Ok(self.oplog.cg.version.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::LV;
+    use super::{MoveError, ValueList};
+
+    fn items(list: &ValueList<u32>) -> Vec<u32> {
+        list.iter().copied().collect()
+    }
+
+    fn sync(a: &mut ValueList<u32>, b: &mut ValueList<u32>) {
+        let from_a = a.ops_since(&[]);
+        a.merge_patch(&b.ops_since(&[])).unwrap();
+        b.merge_patch(&from_a).unwrap();
+        assert_eq!(items(a), items(b));
+        assert_eq!(a.checkout_at(a.oplog().local_frontier_ref()), items(a));
+        a.oplog().dbg_check(true);
+    }
+
+    #[test]
+    fn concurrent_edits() {
+        let mut a: ValueList<u32> = ValueList::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, &[1, 2, 3, 4]);
+
+        let mut b: ValueList<u32> = ValueList::new();
+        b.merge_patch(&a.ops_since(&[])).unwrap();
+        assert_eq!(items(&b), &[1, 2, 3, 4]);
+        let mike = b.get_or_create_agent_id("mike");
+
+        // Concurrent changes.
+        let v = a.oplog().local_frontier();
+        a.insert(seph, 0, &[10, 11]);
+        a.delete(seph, 3..4); // Delete 2.
+        b.delete(mike, 0..1);
+        b.insert(mike, 2, &[20]);
+
+        let from_a = a.ops_since(v.as_ref());
+        a.merge_patch(&b.ops_since(v.as_ref())).unwrap();
+        b.merge_patch(&from_a).unwrap();
+        assert_eq!(items(&a), items(&b));
+        assert_eq!(items(&a), &[10, 11, 3, 20, 4]);
+        assert_eq!(a.checkout_at(a.oplog().local_frontier_ref()), items(&a));
+        assert_eq!(a.checkout_at(v.as_ref()), vec![1, 2, 3, 4]);
+
+        // Merging the same changes again does nothing.
+        a.merge_patch(&b.ops_since(&[])).unwrap();
+        assert_eq!(items(&a), items(&b));
+        a.oplog().dbg_check(true);
+    }
+
+    #[test]
+    fn concurrent_moves() {
+        let mut a: ValueList<u32> = ValueList::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, &[1, 2, 3, 4, 5]);
+        let mut b = a.clone();
+        let mike = b.get_or_create_agent_id("mike");
+
+        // Both peers move 1. It should only end up in one place.
+        a.move_items(seph, 0..1, 3).unwrap(); // 2 3 1 4 5
+        b.move_items(mike, 0..1, 5).unwrap(); // 2 3 4 5 1
+        // And concurrently edit near the moved items.
+        a.insert(seph, 5, &[6]);
+        b.delete(mike, 2..3); // Delete 4.
+        sync(&mut a, &mut b);
+        let result = items(&a);
+        assert_eq!(result.iter().filter(|i| **i == 1).count(), 1);
+        assert!(result == [2, 3, 1, 5, 6] || result == [2, 3, 5, 6, 1], "{:?}", result);
+
+        // Moving an item concurrently with deleting it removes the item.
+        let pos = result.iter().position(|i| *i == 3).unwrap();
+        a.move_items(seph, pos..pos + 1, 0).unwrap();
+        b.delete(mike, pos..pos + 1);
+        sync(&mut a, &mut b);
+        assert!(!items(&a).contains(&3));
+
+        // Moves of moved items keep working.
+        let len = a.len();
+        a.move_items(seph, 0..2, len).unwrap();
+        b.merge_patch(&a.ops_since(&[])).unwrap();
+        assert_eq!(items(&a), items(&b));
+        assert_eq!(b.checkout_at(b.oplog().local_frontier_ref()), items(&b));
+    }
+
+    #[test]
+    fn moves_are_last_writer_wins() {
+        let mut a: ValueList<u32> = ValueList::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, &[1, 2, 3]);
+        let mut b = a.clone();
+        let mike = b.get_or_create_agent_id("mike");
+        let mut c = a.clone();
+
+        a.move_items(seph, 0..1, 3).unwrap(); // 2 3 1
+        b.move_items(mike, 0..1, 2).unwrap(); // 2 1 3
+        // Moving doesn't delete anything in the underlying list.
+        assert_eq!(b.oplog().checkout_tip().len(), 4);
+
+        // Merging in either order picks the same winner.
+        c.merge_patch(&b.ops_since(&[])).unwrap();
+        c.merge_patch(&a.ops_since(&[])).unwrap();
+        sync(&mut a, &mut b);
+        assert_eq!(items(&a), items(&c));
+
+        // Bad moves are rejected without changing the list.
+        let len = a.oplog().len();
+        assert_eq!(a.move_items(seph, 1..1, 0), Err(MoveError::EmptyRange));
+        assert_eq!(a.move_items(seph, 2..4, 0), Err(MoveError::OutOfBounds));
+        assert_eq!(a.move_items(seph, 0..1, 4), Err(MoveError::OutOfBounds));
+        assert_eq!(a.move_items(seph, 0..2, 1), Err(MoveError::IntoItself));
+        assert_eq!(a.oplog().len(), len);
+
+        // Whoever lost can move the item again, and that move wins everywhere.
+        let loser = if items(&a) == [2, 3, 1] { &mut b } else { &mut a };
+        let agent = loser.get_or_create_agent_id("mike");
+        let pos = items(loser).iter().position(|i| *i == 1).unwrap();
+        loser.move_items(agent, pos..pos + 1, 0).unwrap();
+        assert_eq!(items(loser), &[1, 2, 3]);
+        sync(&mut a, &mut b);
+        c.merge_patch(&a.ops_since(&[])).unwrap();
+        assert_eq!(items(&a), &[1, 2, 3]);
+        assert_eq!(items(&c), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn invalid_patches_are_rolled_back() {
+        let mut a: ValueList<u32> = ValueList::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.insert(seph, 0, &[1, 2, 3]);
+        a.move_items(seph, 0..1, 3).unwrap();
+        let patch = a.ops_since(&[]);
+
+        let mut b: ValueList<u32> = ValueList::new();
+        let mike = b.get_or_create_agent_id("mike");
+        b.insert(mike, 0, &[10]);
+        let len = b.oplog().len();
+
+        let mut bad = patch.clone();
+        bad.values[0].0.1 = LV::MAX;
+        assert!(b.merge_patch(&bad).is_err());
+        let mut bad = patch.clone();
+        bad.moves[0].1[0].0 = "nobody".into();
+        assert!(b.merge_patch(&bad).is_err());
+        let mut bad = patch.clone();
+        bad.values[0].1.extend([4, 5]);
+        assert!(b.merge_patch(&bad).is_err());
+
+        assert_eq!(b.oplog().len(), len);
+        assert_eq!(b.oplog().cg.agent_assignment.client_data.len(), 1);
+        assert_eq!(items(&b), &[10]);
+        b.oplog().dbg_check(true);
+
+        b.merge_patch(&patch).unwrap();
+        assert_eq!(items(&b), &[10, 2, 3, 1]);
+    }
+}
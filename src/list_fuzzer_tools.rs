@@ -26,7 +26,7 @@ const UCHARS: [char; 23] = [
     '𐆐', '𐆔', '𐆘', '𐆚', // Ancient roman symbols (U+10190 – U+101CF)
 ];
 
-pub(crate) fn random_str(len: usize, rng: &mut SmallRng, use_unicode: bool) -> String {
+pub fn random_str(len: usize, rng: &mut SmallRng, use_unicode: bool) -> String {
     let mut str = String::new();
     let alphabet: Vec<char> = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_".chars().collect();
 
@@ -73,7 +73,6 @@ pub(crate) fn make_random_change(oplog: &mut SimpleOpLog, branch: &SimpleBranch,
         let pos = rng.gen_range(0..doc_len);
         // println!("range {}", u32::min(10, doc_len - pos));
         let span = rng.gen_range(1..=usize::min(10, doc_len - pos));
-        // dbg!(&state.marker_tree, pos, len);
         // Sometimes deletes happen backwards - ie, via hitting backspace a bunch of times.
         let fwd = span == 1 || rng.gen_bool(0.5);
 
@@ -114,7 +113,7 @@ pub(crate) fn make_random_change(oplog: &mut SimpleOpLog, branch: &SimpleBranch,
 //     // doc.check(false);
 // }
 
-pub(crate) fn choose_2<'a, T>(arr: &'a mut [T], rng: &mut SmallRng) -> (usize, &'a mut T, usize, &'a mut T) {
+pub fn choose_2<'a, T>(arr: &'a mut [T], rng: &mut SmallRng) -> (usize, &'a mut T, usize, &'a mut T) {
     loop {
         // Then merge 2 branches at random
         let a_idx = rng.gen_range(0..arr.len());
@@ -145,7 +144,7 @@ impl Drop for Seed {
     }
 }
 
-pub(crate) fn fuzz_multithreaded<F: Fn(u64) + Send + Sync + Copy + Clone + 'static>(num_iter: u64, f: F) {
+pub fn fuzz_multithreaded<F: Fn(u64) + Send + Sync + Copy + Clone + 'static>(num_iter: u64, f: F) {
     let num_threads: usize = std::thread::available_parallelism().unwrap().into();
     let mut threads = vec![];
     let is_error = Arc::new(AtomicBool::new(false));
@@ -1,16 +1,21 @@
 use std::mem::replace;
 
-use rle::{HasLength, RleDRun, SplitableSpan};
+use jumprope::JumpRopeBuf;
+use rle::{AppendRle, HasLength, RleDRun, SplitableSpan};
 
 use crate::dtrange::DTRange;
-use crate::list::operation::ListOpKind;
+use crate::list::op_iter::OpMetricsIter;
+use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
+use crate::list::operation::{ListOpKind, TextOperation};
 use crate::list::operation::ListOpKind::{Del, Ins};
 use crate::listmerge::M2Tracker;
 use crate::listmerge::markers::Marker;
-use crate::listmerge::merge::notify_for;
-use crate::LV;
+use crate::listmerge::merge::{notify_for, reverse_str};
+use crate::listmerge::yjsspan::{SpanState, INSERTED};
+use crate::{lv_to_usize, LV};
 use crate::ost::LeafIdx;
 use crate::rev_range::RangeRev;
+use crate::rle::{KVPair, RleVec};
 
 #[derive(Debug, Eq, PartialEq)]
 pub(super) struct QueryResult {
@@ -20,6 +25,70 @@ pub(super) struct QueryResult {
     leaf_idx: LeafIdx,
 }
 
+/// A document which is kept in sync with the visible items in a tracker as it is retreated and
+/// advanced. This only makes sense when the tracker contains the entire history of the document -
+/// since otherwise there's no way to know what content the "underwater" items hold.
+pub(super) struct ContentSync<'a> {
+    /// Document content to keep in sync with the tracker.
+    pub(super) content: Option<&'a mut JumpRopeBuf>,
+    /// If present, the equivalent text operations are appended here.
+    pub(super) out: Option<&'a mut Vec<TextOperation>>,
+    pub(super) ctx: &'a ListOperationCtx,
+    pub(super) ops: &'a RleVec<KVPair<ListOpMetrics>>,
+}
+
+impl<'a> ContentSync<'a> {
+    /// Get the content of the items named by `id`, if it is known.
+    fn item_content(&self, id: DTRange) -> Option<String> {
+        // The item IDs name the insert operations which created them. Items in the range tree
+        // are always in document order, so we can just concatenate the content of each op.
+        let mut result = String::new();
+        let mut iter = OpMetricsIter::new(self.ops, self.ctx, id);
+        while let Some(pair) = iter.next() {
+            debug_assert_eq!(pair.1.kind, ListOpKind::Ins);
+            let s = iter.get_content(&pair)?;
+            if pair.1.loc.fwd {
+                result.push_str(s);
+            } else {
+                result.push_str(&reverse_str(s));
+            }
+        }
+        Some(result)
+    }
+
+    /// Update the content after the items in `id` (at content position `pos`) changed state.
+    fn update(&mut self, pos: usize, id: DTRange, old_state: SpanState, incr: i32) {
+        let was_visible = old_state == INSERTED;
+        let now_visible = old_state.0.wrapping_add_signed(incr) == INSERTED.0;
+
+        if was_visible && !now_visible {
+            if let Some(content) = self.content.as_deref_mut() {
+                content.remove(pos..pos + id.len());
+            }
+            if self.out.is_some() {
+                let op = match self.item_content(id) {
+                    Some(s) => TextOperation::new_delete_with_content(pos, s.into()),
+                    None => TextOperation::new_delete(pos..pos + id.len()),
+                };
+                self.out.as_deref_mut().unwrap().push_rle(op);
+            }
+        } else if !was_visible && now_visible {
+            let s = self.item_content(id);
+            if let Some(content) = self.content.as_deref_mut() {
+                let s = s.as_ref().expect("Cannot restore inserted content which is not known");
+                content.insert(pos, s);
+            }
+            if let Some(out) = self.out.as_deref_mut() {
+                out.push_rle(TextOperation {
+                    loc: (pos as LV..(pos + id.len()) as LV).into(),
+                    kind: Ins,
+                    content: s.map(|s| s.into()),
+                });
+            }
+        }
+    }
+}
+
 impl M2Tracker {
     /// Returns what happened here, target range, offset into range and a cursor into the range
     /// tree.
@@ -28,13 +97,13 @@ impl M2Tracker {
     ///
     /// Returns (ins / del, target, offset into target, rev, range_tree cursor).
     fn index_query(&self, lv: LV) -> QueryResult {
-        debug_assert_ne!(lv, usize::MAX);
+        debug_assert_ne!(lv, LV::MAX);
 
         let RleDRun {
             start, end, val: marker
         } = self.index.get_entry(lv);
 
-        let offset = lv - start;
+        let offset = lv_to_usize(lv - start);
         let len = end - start;
 
         match marker {
@@ -68,7 +137,13 @@ impl M2Tracker {
         }
     }
 
-    pub(super) fn adv_retreat_range(&mut self, mut range: DTRange, incr: i32) {
+    pub(super) fn adv_retreat_range(&mut self, range: DTRange, incr: i32) {
+        self.adv_retreat_range_sync(range, incr, None);
+    }
+
+    /// Retreat or advance the tracker. If `sync` is passed, the document content is updated to
+    /// reflect any items which become visible or invisible as a result.
+    pub(super) fn adv_retreat_range_sync(&mut self, mut range: DTRange, incr: i32, mut sync: Option<&mut ContentSync>) {
         // This method handles both advancing and retreating. In either case, because of the way
         // SpanState is designed, we need to either increment or decrement the state of every
         // visited item in the LV range.
@@ -94,17 +169,25 @@ impl M2Tracker {
                 let (e, _offset) = cursor.0.get_item(&self.range_tree);
                 // let chunk_start = last_lv - offset;
                 let start = range.start.max(e.id.start);
-                cursor.0.offset = start - e.id.start;
-                let max_len = range.end - start;
+                let old_state = e.current_state;
+                cursor.0.offset = lv_to_usize(start - e.id.start);
+                let max_len = lv_to_usize(range.end - start);
+                let pos = sync.is_some().then(|| cursor.0.calc_pos(&self.range_tree).cur);
 
-                range.start += self.range_tree.mutate_entry(
+                let (len, id) = self.range_tree.mutate_entry(
                     &mut cursor,
                     max_len,
                     &mut notify_for(&mut self.index),
                     |e| {
                         e.current_state.0 = e.current_state.0.wrapping_add_signed(incr);
+                        e.id
                     }
-                ).0;
+                );
+                range.start += len as LV;
+
+                if let Some(sync) = sync.as_deref_mut() {
+                    sync.update(pos.unwrap(), id, old_state, incr);
+                }
 
                 self.range_tree.emplace_cursor_unknown(cursor);
 
@@ -133,14 +216,23 @@ impl M2Tracker {
                         x => x,
                     };
                     let (mut cursor, _pos) = self.range_tree.mut_cursor_before_item(target_range.start, leaf_idx);
-                    target_range.start += self.range_tree.mutate_entry(
+                    let old_state = cursor.0.get_item(&self.range_tree).0.current_state;
+                    let pos = sync.is_some().then(|| cursor.0.calc_pos(&self.range_tree).cur);
+
+                    let (len, id) = self.range_tree.mutate_entry(
                         &mut cursor,
                         target_range.len(),
                         &mut notify_for(&mut self.index),
                         |e| {
                             e.current_state.0 = e.current_state.0.wrapping_add_signed(incr);
+                            e.id
                         }
-                    ).0;
+                    );
+                    target_range.start += len as LV;
+
+                    if let Some(sync) = sync.as_deref_mut() {
+                        sync.update(pos.unwrap(), id, old_state, incr);
+                    }
 
                     self.range_tree.emplace_cursor_unknown(cursor);
                 }
@@ -33,13 +33,13 @@ impl DelRange {
         if self.fwd {
             // Simple case.
             DTRange {
-                start: self.target + offset_start,
-                end: self.target + offset_end
+                start: self.target + offset_start as LV,
+                end: self.target + offset_end as LV
             }
         } else {
             DTRange {
-                start: self.target - offset_end,
-                end: self.target - offset_start
+                start: self.target - offset_end as LV,
+                end: self.target - offset_start as LV
             }
         }
     }
@@ -75,7 +75,7 @@ impl IndexContent for Marker {
                 // let other_len_1 = other_len == 1;
 
                 // Can we append forwards?
-                if a.fwd && b.fwd && a.target + offset == b.target { return true; }
+                if a.fwd && b.fwd && a.target + offset as LV == b.target { return true; }
 
                 // Can we append backwards? This is horrible. If we're going to, first figure out
                 // the expected resulting a.target value. IF we can append backward, they will
@@ -87,9 +87,9 @@ impl IndexContent for Marker {
                 } else { None };
 
                 let b_start = if !b.fwd {
-                    Some(b.target + offset)
+                    Some(b.target + offset as LV)
                 } else if other_len == 1 {
-                    Some(b.target + 1 + offset)
+                    Some(b.target + 1 + offset as LV)
                 } else { None };
 
                 if let (Some(a_start), Some(b_start)) = (a_start, b_start) {
@@ -109,8 +109,8 @@ impl IndexContent for Marker {
     fn at_offset(&self, offset: usize) -> Self {
         match self {
             Self::InsPtr(_) => *self,
-            Self::Del(DelRange {target: lv, fwd: true}) => Self::Del(DelRange::new(*lv + offset, true)),
-            Self::Del(DelRange {target: lv, fwd: false}) => Self::Del(DelRange::new(*lv - offset, false)),
+            Self::Del(DelRange {target: lv, fwd: true}) => Self::Del(DelRange::new(*lv + offset as LV, true)),
+            Self::Del(DelRange {target: lv, fwd: false}) => Self::Del(DelRange::new(*lv - offset as LV, false)),
         }
     }
 
@@ -127,30 +127,3 @@ impl IndexContent for Marker {
         } else { false }
     }
 }
-
-#[cfg(test)]
-mod tests {
-    // #[test]
-    // fn marker_split_merge() {
-    //     test_splitable_methods_valid(MarkerEntry {
-    //         len: 10,
-    //         inner: InsPtr(NonNull::dangling())
-    //     });
-    //
-    //     test_splitable_methods_valid(MarkerEntry {
-    //         len: 10,
-    //         inner: DelTarget(RangeRev {
-    //             span: (0..10).into(),
-    //             fwd: true,
-    //         })
-    //     });
-    //
-    //     test_splitable_methods_valid(MarkerEntry {
-    //         len: 10,
-    //         inner: DelTarget(RangeRev {
-    //             span: (0..10).into(),
-    //             fwd: false,
-    //         })
-    //     });
-    // }
-}
\ No newline at end of file
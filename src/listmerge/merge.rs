@@ -3,6 +3,7 @@
 #![allow(clippy::needless_option_as_deref)]
 
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 
 use jumprope::JumpRopeBuf;
 use smartstring::alias::String as SmartString;
@@ -10,7 +11,7 @@ use smartstring::alias::String as SmartString;
 use rle::{AppendRle, HasLength, MergableSpan, MergeableIterator, Searchable, SplitableSpanCtx, Trim, TrimCtx};
 use rle::intersect::rle_intersect_rev;
 
-use crate::{AgentId, CausalGraph, Frontier, LV};
+use crate::{AgentId, CausalGraph, Frontier, lv_to_usize, LV};
 use crate::causalgraph::agent_assignment::AgentAssignment;
 use crate::causalgraph::graph::Graph;
 use crate::dtrange::{DTRange, UNDERWATER_START};
@@ -20,7 +21,7 @@ use crate::list::ListOpLog;
 use crate::list::op_iter::OpMetricsIter;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::list::operation::{ListOpKind, TextOperation};
-use crate::listmerge::{Index, M2Tracker};
+use crate::listmerge::{ConflictLog, Index, M2Tracker};
 #[cfg(feature = "dot_export")]
 use crate::listmerge::dot::DotColor::*;
 use crate::listmerge::markers::{DelRange, Marker};
@@ -68,6 +69,8 @@ impl M2Tracker {
 
             #[cfg(feature = "merge_conflict_checks")]
             concurrent_inserts_collide: false,
+
+            conflict_log: None,
         };
 
         // The list is initially populated with a dummy "underwater" item, which corresponds to
@@ -109,8 +112,69 @@ impl M2Tracker {
         }
     }
 
+    /// Log every insert whose neighbour (origin_left or origin_right) has been deleted. This is
+    /// only used for merge dry runs, and it must be called before the tracker is cleared.
+    fn log_neighbour_deletes(&mut self) {
+        let Some(log) = self.conflict_log.as_mut() else { return; };
+
+        let mut items: Vec<CRDTSpan> = self.range_tree.iter()
+            .filter(|e| !e.is_underwater())
+            .collect();
+        items.sort_unstable_by_key(|e| e.id.start);
+
+        // Maps from each origin to the items inserted next to it. Items in the range tree are split
+        // up arbitrarily, and each piece of a run has the same origin_right. Only the first piece
+        // is interesting. Items inserted right after the previous item in their run are found
+        // below instead.
+        let mut next_to: BTreeMap<LV, Vec<LV>> = BTreeMap::new();
+        for e in &items {
+            if e.id.start == 0 || e.origin_left != e.id.start - 1 {
+                next_to.entry(e.origin_left).or_default().push(e.id.start);
+                next_to.entry(e.origin_right).or_default().push(e.id.start);
+            }
+        }
+
+        for entry in self.index.iter() {
+            let Marker::Del(target) = entry.val else { continue; };
+            let span = target.range(0, entry.len());
+            // The delete of each target item.
+            let del_lv = |t: LV| if target.fwd {
+                entry.start as LV + (t - span.start)
+            } else {
+                entry.start as LV + (span.end - 1 - t)
+            };
+
+            for (&t, ins) in next_to.range(span.start..span.end) {
+                let del = del_lv(t);
+                log.neighbour_deletes.extend(ins.iter().map(|&i| (
+                    (i..i + 1).into(), RangeRev { span: (del..del + 1).into(), fwd: true }
+                )));
+            }
+
+            // Any item in span.start+1..span.end+1 which directly follows the previous item in its
+            // run was inserted after a deleted item.
+            let first = items.partition_point(|e| e.id.end <= span.start + 1);
+            for e in &items[first..] {
+                if e.id.start > span.end { break; }
+                let mut start = e.id.start.max(span.start + 1);
+                if start == e.id.start && (start == 0 || e.origin_left != start - 1) {
+                    start += 1;
+                }
+                let end = e.id.end.min(span.end + 1);
+                if start >= end { continue; }
+
+                let dels: DTRange = if target.fwd {
+                    (del_lv(start - 1)..del_lv(end - 2) + 1).into()
+                } else {
+                    (del_lv(end - 2)..del_lv(start - 1) + 1).into()
+                };
+                log.neighbour_deletes.push(((start..end).into(), RangeRev { span: dels, fwd: target.fwd }));
+            }
+        }
+    }
+
     fn get_cursor_before(&self, lv: LV) -> ContentCursor {
-        if lv == usize::MAX {
+        if lv == LV::MAX {
             // This never happens due to dummy data at the end of the list - which means we always
             // insert before some actual value. If we did, the right thing to do in this case would
             // be to return a cursor at the end of the range tree, but I don't have code for that
@@ -125,12 +189,10 @@ impl M2Tracker {
     }
 
     fn get_cursor_after(&self, lv: LV, stick_end: bool) -> ContentCursor {
-        if lv == usize::MAX {
+        if lv == LV::MAX {
             self.range_tree.cursor_at_start_nothing_emplaced()
         } else {
             let leaf_idx = self.marker_at(lv);
-            // let marker: NonNull<NodeLeaf<YjsSpan, ContentIndex>> = self.markers.at(order as usize).unwrap();
-            // self.content_tree.
             let mut cursor = self.range_tree.cursor_before_item(lv, leaf_idx);
             // The cursor points to parent. This is safe because of guarantees provided by
             // cursor_before_item.
@@ -188,6 +250,9 @@ impl M2Tracker {
                 //println!("Concurrent changes {:?} vs {:?}", item.id, other_entry.id);
                 self.concurrent_inserts_collide = true;
             }
+            if let Some(log) = self.conflict_log.as_mut() {
+                log.concurrent_inserts.push((item.id, other_entry.id));
+            }
 
             // This code could be better optimized, but its already O(n * log n), and its extremely
             // rare that you actually get concurrent inserts at the same location in the document
@@ -202,16 +267,14 @@ impl M2Tracker {
                 Ordering::Greater => {} // Bottom row. Continue.
                 Ordering::Equal => {
                     if item.origin_right == other_entry.origin_right {
-                        // Origin_right matches. Items are concurrent. Order by agent names.
-                        let my_name = aa.get_agent_name(agent);
-
+                        // Origin_right matches. Items are concurrent. Order by agent (names,
+                        // by default).
                         let (other_agent, other_seq) = aa.local_to_agent_version(other_lv);
-                        let other_name = aa.get_agent_name(other_agent);
-                        // eprintln!("concurrent insert at the same place {} ({}) vs {} ({})", item.id.start, my_name, other_lv, other_name);
+                        // eprintln!("concurrent insert at the same place {} ({}) vs {} ({})", item.id.start, agent, other_lv, other_agent);
 
                         // It's possible for a user to conflict with themselves if they commit to
                         // multiple branches. In this case, sort by seq number.
-                        let ins_here = match my_name.cmp(other_name) {
+                        let ins_here = match aa.insert_tie_break_agents(agent, other_agent) {
                             Ordering::Less => true,
                             Ordering::Equal => {
                                 // We can't compare versions here because sequence numbers could be
@@ -414,11 +477,11 @@ impl M2Tracker {
                 // UNDERWATER_START = 4611686018427387903
 
                 let (origin_left, end_pos, mut new_cursor) = if op.start() == 0 {
-                    (usize::MAX, 0, self.range_tree.mut_cursor_at_start())
+                    (LV::MAX, 0, self.range_tree.mut_cursor_at_start())
                 } else {
                     let (mut end_pos, mut cursor) = self.range_tree.mut_cursor_before_cur_pos(op.start() - 1);
                     let (e, offset) = cursor.0.get_item(&self.range_tree);
-                    let origin_left = e.id.start + offset;
+                    let origin_left = e.id.start + offset as LV;
                     end_pos += e.takes_up_space::<false>() as usize;
                     // if CHECK_TREES { assert_eq!(origin_left, origin_left_2); }
                     cursor.0.inc_offset(&self.range_tree);
@@ -429,12 +492,12 @@ impl M2Tracker {
                 debug_assert_eq!(new_cursor.0.get_pos(&self.range_tree), cursor_pos);
 
                 // Origin_right should be the next item which isn't in the NotInsertedYet state.
-                // If we reach the end of the document before that happens, use usize::MAX.
+                // If we reach the end of the document before that happens, use LV::MAX.
 
                 let origin_right = if !new_cursor.roll_next_item(&mut self.range_tree) {
                     // Because the list has underwater elements, this never happens in practice.
                     // unreachable!() would be equally valid here.
-                    usize::MAX
+                    LV::MAX
                 } else {
                     let mut c2 = new_cursor.0.clone();
 
@@ -483,7 +546,7 @@ impl M2Tracker {
                 } else {
                     // We're moving backwards. We need to delete as many items as we can before the
                     // end of the op.
-                    let last_pos = op.loc.span.last();
+                    let last_pos = lv_to_usize(op.loc.span.last());
                     // Find the last entry
                     let (end_pos, mut cursor) = self.range_tree.mut_cursor_before_cur_pos(last_pos);
                     // let mut cursor_pos = LenPair::new(last_pos, end_pos);
@@ -544,7 +607,7 @@ impl M2Tracker {
 
                 let lv_start = op_pair.0;
 
-                self.index.set_range((lv_start..lv_start + len).into(), Marker::Del(DelRange {
+                self.index.set_range((lv_start..lv_start + len as LV).into(), Marker::Del(DelRange {
                     target: if fwd { target.start } else { target.end },
                     fwd,
                 }).into());
@@ -662,6 +725,29 @@ impl<'a> TransformedOpsIterRaw<'a> {
         }
     }
 
+    /// Run a plan on top of an existing tracker. The plan must pick up from wherever the tracker
+    /// was left.
+    pub(super) fn from_plan_with_tracker(aa: &'a AgentAssignment, op_ctx: &'a ListOperationCtx,
+                                         ops: &'a RleVec<KVPair<ListOpMetrics>>,
+                                         plan: M1Plan, tracker: M2Tracker) -> Self {
+        Self {
+            aa,
+            op_ctx,
+            ops,
+            plan,
+            op_iter: None,
+            tracker,
+            plan_idx: 0,
+            applying: false,
+        }
+    }
+
+    /// Take the tracker out of a finished iterator.
+    pub(super) fn take_tracker(&mut self) -> M2Tracker {
+        debug_assert_eq!(self.plan_idx, self.plan.actions.len());
+        std::mem::replace(&mut self.tracker, M2Tracker::new())
+    }
+
     pub(crate) fn new(subgraph: &'a Graph, aa: &'a AgentAssignment, op_ctx: &'a ListOperationCtx,
                       ops: &'a RleVec<KVPair<ListOpMetrics>>,
                       from_frontier: &[LV], merge_frontier: &[LV]) -> Self {
@@ -692,6 +778,17 @@ impl<'a> TransformedOpsIterRaw<'a> {
         (remainder, result)
     }
 
+    /// Record conflicts while traversing. This must be called before iterating.
+    pub(crate) fn log_conflicts(&mut self) {
+        self.tracker.conflict_log = Some(ConflictLog::default());
+    }
+
+    /// Take the conflicts found while traversing. See [`log_conflicts`](Self::log_conflicts).
+    pub(crate) fn take_conflict_log(&mut self) -> ConflictLog {
+        self.tracker.log_neighbour_deletes();
+        self.tracker.conflict_log.take().unwrap_or_default()
+    }
+
     /// Returns if concurrent inserts ever collided at the same location while traversing.
     #[cfg(feature = "merge_conflict_checks")]
     pub(crate) fn concurrent_inserts_collided(&self) -> bool {
@@ -717,8 +814,8 @@ impl<'a> Iterator for TransformedOpsIterRaw<'a> {
             } else { self.op_iter = None; }
         }
 
-        while self.plan_idx < self.plan.0.len() {
-            let action = &self.plan.0[self.plan_idx];
+        while self.plan_idx < self.plan.actions.len() {
+            let action = &self.plan.actions[self.plan_idx];
             self.plan_idx += 1;
 
             match action {
@@ -750,6 +847,7 @@ impl<'a> Iterator for TransformedOpsIterRaw<'a> {
                     return Some(TransformedResultRaw::FF(*span));
                 }
                 M1PlanAction::Clear => {
+                    self.tracker.log_neighbour_deletes();
                     self.tracker.clear();
                 }
                 M1PlanAction::BeginOutput => {
@@ -776,7 +874,7 @@ pub(crate) enum TransformedSimpleOp {
 /// TODO: Name me.
 pub(crate) struct TransformedSimpleOpsIter<'a> {
     inner: TransformedOpsIterRaw<'a>,
-    ff_iter: Option<(std::slice::Iter<'a, KVPair<ListOpMetrics>>, usize)>,
+    ff_iter: Option<(std::slice::Iter<'a, KVPair<ListOpMetrics>>, LV)>,
 }
 
 impl<'a> From<TransformedOpsIterRaw<'a>> for TransformedSimpleOpsIter<'a> {
@@ -797,7 +895,7 @@ impl<'a> Iterator for TransformedSimpleOpsIter<'a> {
                 if item.0 < *end {
                     let item_end = item.end();
                     if item_end > *end {
-                        item.truncate_ctx(*end - item.0, self.inner.op_ctx);
+                        item.truncate_ctx(lv_to_usize(*end - item.0), self.inner.op_ctx);
                     }
                     return Some(Apply(item));
                 }
@@ -819,7 +917,7 @@ impl<'a> Iterator for TransformedSimpleOpsIter<'a> {
                 let start_idx = self.inner.ops.find_next_index(range.start);
                 let mut first = self.inner.ops[start_idx].clone();
                 if first.0 < range.start {
-                    first.truncate_keeping_right_ctx(range.start - first.0, self.inner.op_ctx);
+                    first.truncate_keeping_right_ctx(lv_to_usize(range.start - first.0), self.inner.op_ctx);
                 }
 
                 self.ff_iter = Some((self.inner.ops.0[start_idx+1..].iter(), range.end));
@@ -993,6 +1091,16 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn merge_state_is_send() {
+        // The merge state is stored in plain vectors (no pointers), so it can be moved between
+        // threads.
+        fn assert_send<T: Send>() {}
+        assert_send::<M2Tracker>();
+        assert_send::<ContentTree<CRDTSpan>>();
+        assert_send::<IndexTree<Marker>>();
+    }
+
     #[test]
     fn test_ff() {
         let mut list = SimpleOpLog::new();
@@ -1071,7 +1179,7 @@ mod test {
     }
 
     fn items(tracker: &M2Tracker, filter_underwater: usize) -> Vec<CRDTSpan> {
-        let trim_from = UNDERWATER_START + filter_underwater;
+        let trim_from = UNDERWATER_START + filter_underwater as LV;
 
         tracker.range_tree
             .iter()
@@ -1084,7 +1192,7 @@ mod test {
 
                 if i.id.end > trim_from {
                     assert_eq!(i.current_state, INSERTED);
-                    i.truncate(i.id.end - trim_from);
+                    i.truncate(lv_to_usize(i.id.end - trim_from));
                 }
 
                 Some(i)
@@ -0,0 +1,326 @@
+//! Branches can optionally keep the tracker used by their last merge. Normally every merge builds
+//! a new tracker from scratch over the whole conflict zone between the branch and the merged
+//! changes. When a branch is repeatedly merging small batches of remote operations which are
+//! concurrent with local edits, that conflict zone (and the work to rebuild it) keeps growing.
+//!
+//! With the merge context enabled, the tracker is kept after each merge along with the version it
+//! covers. The next merge only walks the operations the tracker hasn't seen yet, so merging is
+//! O(new operations) instead of O(conflict zone).
+//!
+//! The tracker can only integrate operations which happened after the point where it was last
+//! cleared (its *base* version). Merging anything older falls back to a normal merge, which
+//! replaces the cached tracker.
+//!
+//! The cached tracker is also thrown away if the branch is merged with a different oplog (going by
+//! its doc ID and length), or if the tracker grows past [`MAX_CACHED_TRACKER_LEAVES`].
+
+use std::fmt::{Debug, Formatter};
+use smartstring::alias::String as SmartString;
+use crate::{DTRange, Frontier, LV};
+use crate::causalgraph::graph::Graph;
+use crate::list::encoding::txn_trace::SpanningTreeWalker;
+use crate::list::ListOpLog;
+use crate::listmerge::M2Tracker;
+use crate::listmerge::merge::TransformedOpsIterRaw;
+use crate::listmerge::plan::{M1Plan, M1PlanAction};
+
+/// Trackers bigger than this (in content tree leaves) aren't kept after a merge.
+const MAX_CACHED_TRACKER_LEAVES: usize = 1 << 14;
+
+/// Identifies the oplog a tracker was built from. Oplogs only grow, so the tracker can be used
+/// with any oplog with the same doc ID which is at least as long.
+#[derive(Debug, Clone)]
+struct OpLogIdentity {
+    doc_id: Option<SmartString>,
+    len: LV,
+}
+
+impl OpLogIdentity {
+    fn new(oplog: &ListOpLog) -> Self {
+        Self { doc_id: oplog.doc_id().map(SmartString::from), len: oplog.len() }
+    }
+
+    fn matches(&self, oplog: &ListOpLog) -> bool {
+        self.doc_id.as_deref() == oplog.doc_id() && self.len <= oplog.len()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedTracker {
+    tracker: M2Tracker,
+
+    /// The oplog the tracker was built from.
+    oplog: OpLogIdentity,
+
+    /// The version the state of the items in the tracker reflects.
+    tracker_version: Frontier,
+
+    /// Every operation in this version has been integrated into the tracker. This is the version
+    /// of the branch after the merge which built the tracker.
+    version: Frontier,
+
+    /// The tracker doesn't know about any operations in this version. (They're underwater.)
+    base: Frontier,
+}
+
+/// The merge context of a branch. See [`ListBranch::enable_merge_context`](crate::list::ListBranch::enable_merge_context).
+///
+/// Like the rewind cache, this has no bearing on the branch's content, so its ignored when
+/// comparing branches.
+#[derive(Clone, Default)]
+pub(crate) struct MergeContext {
+    enabled: bool,
+    cached: Option<Box<CachedTracker>>,
+    /// Set in tests to override [`MAX_CACHED_TRACKER_LEAVES`].
+    #[cfg(test)]
+    pub(crate) max_leaves: Option<usize>,
+}
+
+impl PartialEq for MergeContext {
+    fn eq(&self, _other: &Self) -> bool { true }
+}
+
+impl Eq for MergeContext {}
+
+impl Debug for MergeContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MergeContext")
+            .field("enabled", &self.enabled)
+            .field("cached", &self.cached.is_some())
+            .finish()
+    }
+}
+
+/// What to cache once a merge has finished.
+#[derive(Debug)]
+pub(crate) struct PendingMergeContext {
+    oplog: OpLogIdentity,
+    tracker_version: Frontier,
+    base: Frontier,
+}
+
+impl MergeContext {
+    pub(crate) fn is_cached(&self) -> bool {
+        self.cached.is_some()
+    }
+
+    /// Is there a cached tracker which can be used with this oplog?
+    pub(crate) fn is_cached_for(&self, oplog: &ListOpLog) -> bool {
+        self.cached.as_ref().is_some_and(|c| c.oplog.matches(oplog))
+    }
+
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled { self.cached = None; }
+    }
+
+    /// Make the iterator to merge `merge_frontier` into a branch at version `from`. If the merge
+    /// context is enabled, the cached tracker is reused when possible, and the returned pending
+    /// state should be passed to [`finish`](Self::finish) once the iterator has been consumed.
+    ///
+    /// The cached tracker is taken out of the context while the merge is in progress.
+    pub(crate) fn start<'a>(&mut self, oplog: &'a ListOpLog, from: &[LV], merge_frontier: &[LV]) -> (TransformedOpsIterRaw<'a>, Option<PendingMergeContext>) {
+        let aa = &oplog.cg.agent_assignment;
+        let graph = &oplog.cg.graph;
+
+        if !self.enabled {
+            return (oplog.get_xf_operations_full(from, merge_frontier), None);
+        }
+
+        let identity = OpLogIdentity::new(oplog);
+        let usable = self.is_cached_for(oplog);
+        if let Some(cached) = self.cached.take().filter(|_| usable) {
+            if let Some((plan, tracker_version)) = cached.plan_merge(graph, from, merge_frontier) {
+                let iter = TransformedOpsIterRaw::from_plan_with_tracker(aa, &oplog.operation_ctx,
+                                                                         &oplog.operations, plan, cached.tracker);
+                return (iter, Some(PendingMergeContext { oplog: identity, tracker_version, base: cached.base }));
+            }
+        }
+
+        let (plan, common) = graph.make_m1_plan(Some(&oplog.operations), from, merge_frontier, true);
+        let (tracker_version, base) = plan_end_state(&plan, graph, common);
+        let iter = TransformedOpsIterRaw::from_plan(aa, &oplog.operation_ctx, &oplog.operations, plan);
+        (iter, Some(PendingMergeContext { oplog: identity, tracker_version, base }))
+    }
+
+    /// Store the tracker from a finished merge. `version` is the branch's new version.
+    pub(crate) fn finish(&mut self, iter: &mut TransformedOpsIterRaw, pending: PendingMergeContext, version: Frontier) {
+        if !self.enabled { return; }
+        let tracker = iter.take_tracker();
+
+        #[cfg(test)]
+        let max_leaves = self.max_leaves.unwrap_or(MAX_CACHED_TRACKER_LEAVES);
+        #[cfg(not(test))]
+        let max_leaves = MAX_CACHED_TRACKER_LEAVES;
+        if tracker.range_tree.num_leaves() > max_leaves { return; }
+
+        self.cached = Some(Box::new(CachedTracker {
+            tracker,
+            oplog: pending.oplog,
+            tracker_version: pending.tracker_version,
+            version,
+            base: pending.base,
+        }));
+    }
+}
+
+impl CachedTracker {
+    /// Plan a merge from `from` to `merge_frontier` which continues on from the tracker's current
+    /// state. Returns None if the tracker can't be used for this merge.
+    fn plan_merge(&self, graph: &Graph, from: &[LV], merge_frontier: &[LV]) -> Option<(M1Plan, Frontier)> {
+        // The branch must have everything the tracker knows about. (It might have more - like
+        // local edits made since the last merge.)
+        if !graph.frontier_contains_frontier(from, self.version.as_ref()) { return None; }
+
+        let mut actions = vec![];
+
+        // First catch the tracker up to the branch's version, then add the new operations.
+        let (_, known_rev) = graph.diff_rev(self.version.as_ref(), from);
+        let tracker_version = self.walk(graph, &known_rev, self.tracker_version.clone(), &mut actions)?;
+
+        actions.push(M1PlanAction::BeginOutput);
+        let (_, new_rev) = graph.diff_rev(from, merge_frontier);
+        let tracker_version = self.walk(graph, &new_rev, tracker_version, &mut actions)?;
+
+        Some((M1Plan::new(actions), tracker_version))
+    }
+
+    fn walk(&self, graph: &Graph, rev_spans: &[DTRange], start_at: Frontier, actions: &mut Vec<M1PlanAction>) -> Option<Frontier> {
+        let mut walker = SpanningTreeWalker::new(graph, rev_spans, start_at);
+
+        for walk in &mut walker {
+            if !self.is_after_base(graph, walk.parents.as_ref()) { return None; }
+
+            actions.extend(walk.retreat.into_iter().map(M1PlanAction::Retreat));
+            actions.extend(walk.advance_rev.into_iter().rev().map(M1PlanAction::Advance));
+            actions.push(M1PlanAction::Apply(walk.consume));
+        }
+
+        Some(walker.into_frontier())
+    }
+
+    /// Check that an operation with the named parents happened after the tracker's base version.
+    fn is_after_base(&self, graph: &Graph, parents: &[LV]) -> bool {
+        // Everything in the tracker happened after the base, so its enough to have any parent which
+        // isn't in the base version. This is also the cheap case, since those parents are usually
+        // recent.
+        parents.iter().any(|p| !graph.frontier_contains_version(self.base.as_ref(), *p))
+            || graph.frontier_contains_frontier(parents, self.base.as_ref())
+    }
+}
+
+/// Figure out the state of the tracker after running a plan: (the version the tracker's items
+/// reflect, the version it was last cleared at).
+fn plan_end_state(plan: &M1Plan, graph: &Graph, common: Frontier) -> (Frontier, Frontier) {
+    let mut current = common.clone();
+    let mut max = common.clone();
+    let mut base = common;
+
+    for action in &plan.actions {
+        match action {
+            M1PlanAction::Retreat(span) => current.retreat(graph, *span),
+            M1PlanAction::Advance(span) => current.advance(graph, *span),
+            M1PlanAction::Apply(span) => {
+                current.advance(graph, *span);
+                max.advance(graph, *span);
+            }
+            M1PlanAction::FF(span) => {
+                // Fast forwarded operations aren't added to the tracker. They end up underwater.
+                current.replace_with_1(span.last());
+                max.replace_with_1(span.last());
+                base.replace_with_1(span.last());
+            }
+            M1PlanAction::Clear => {
+                current = max.clone();
+                base = max.clone();
+            }
+            M1PlanAction::BeginOutput => {}
+        }
+    }
+
+    (current, base)
+}
+
+#[cfg(test)]
+mod test {
+    use jumprope::JumpRopeBuf;
+    use rand::prelude::*;
+    use crate::Frontier;
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::old_fuzzer_tools::old_make_random_change;
+    use crate::list::op_metrics::ListOpMetrics;
+    use crate::list::operation::ListOpKind;
+    use crate::list_fuzzer_tools::choose_2;
+    use crate::listmerge::merge::{reverse_str, TransformedResultRaw};
+    use crate::rle::KVPair;
+    use super::MergeContext;
+
+    fn apply_op(oplog: &ListOpLog, content: &mut JumpRopeBuf, op: ListOpMetrics) {
+        match op.kind {
+            ListOpKind::Ins => {
+                let s = oplog.operation_ctx.get_str(ListOpKind::Ins, op.content_pos.unwrap());
+                if op.loc.fwd { content.insert(op.start(), s); }
+                else { content.insert(op.start(), &reverse_str(s)); }
+            }
+            ListOpKind::Del => content.remove(op.loc.span.into()),
+        }
+    }
+
+    /// Merge into `content` (at `version`) with the merge context, like a branch does.
+    fn merge(ctx: &mut MergeContext, oplog: &ListOpLog, content: &mut JumpRopeBuf, version: &mut Frontier) {
+        let merge_frontier = oplog.local_frontier_ref();
+        let (mut iter, pending) = ctx.start(oplog, version.as_ref(), merge_frontier);
+        for xf in &mut iter {
+            match xf {
+                TransformedResultRaw::Apply { xf_pos, op: KVPair(_, mut op) } => {
+                    op.transpose_to(xf_pos);
+                    apply_op(oplog, content, op);
+                }
+                TransformedResultRaw::FF(range) => {
+                    for KVPair(_, op) in oplog.operations.iter_range_ctx(range, &oplog.operation_ctx) {
+                        apply_op(oplog, content, op);
+                    }
+                }
+                TransformedResultRaw::DeleteAlreadyHappened(_) => {}
+            }
+        }
+        *version = oplog.cg.graph.find_dominators_2(version.as_ref(), merge_frontier);
+        ctx.finish(&mut iter, pending.unwrap(), version.clone());
+    }
+
+    #[test]
+    fn reused_tracker_matches_fresh_merge() {
+        for seed in 0..20 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut docs = [ListCRDT::new(), ListCRDT::new(), ListCRDT::new()];
+            for doc in docs.iter_mut() {
+                for a in 0..3 {
+                    doc.get_or_create_agent_id(format!("agent {a}").as_str());
+                }
+            }
+
+            let mut ctx = MergeContext::default();
+            ctx.set_enabled(true);
+            let mut content = JumpRopeBuf::new();
+            let mut version = Frontier::root();
+            let mut reused = 0;
+
+            for _ in 0..40 {
+                let idx = rng.gen_range(0..docs.len());
+                old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, false);
+                let (_, a, _, b) = choose_2(&mut docs, &mut rng);
+                a.oplog.add_missing_operations_from(&b.oplog);
+
+                let oplog = &docs[0].oplog;
+                if ctx.is_cached_for(oplog) { reused += 1; }
+                merge(&mut ctx, oplog, &mut content, &mut version);
+                assert!(ctx.is_cached());
+
+                let fresh = oplog.checkout_tip();
+                assert_eq!(content, *fresh.content());
+                assert_eq!(version.as_ref(), fresh.local_frontier_ref());
+            }
+            assert!(reused > 0);
+        }
+    }
+}
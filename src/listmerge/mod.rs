@@ -10,22 +10,27 @@
 //! entries as we go). Or we could figure it out by walking the txns forwards and backwards through
 //! time.
 
+use crate::DTRange;
 use crate::listmerge::markers::Marker;
 use crate::listmerge::yjsspan::CRDTSpan;
 use crate::ost::content_tree::ContentTree;
 use crate::ost::IndexTree;
+use crate::rev_range::RangeRev;
 
 pub(crate) mod yjsspan;
 pub(crate) mod merge;
 pub(crate) mod markers;
 mod advance_retreat;
+pub(crate) mod rewind;
+pub(crate) mod merge_context;
+pub(crate) mod op_stream;
 // pub(crate) mod txn_trace;
 #[cfg(test)]
 pub mod fuzzer;
 #[cfg(feature = "dot_export")]
 mod dot;
 
-#[cfg(any(test, feature = "gen_test_data"))]
+#[cfg(any(test, feature = "testing"))]
 pub(crate) mod simple_oplog;
 pub(crate) mod plan;
 
@@ -33,7 +38,7 @@ pub(crate) mod xf_old;
 
 type Index = IndexTree<Marker>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct M2Tracker {
     /// The index is used for 2 things:
     ///
@@ -45,4 +50,18 @@ struct M2Tracker {
 
     #[cfg(feature = "merge_conflict_checks")]
     concurrent_inserts_collide: bool,
+
+    /// Conflicts found while merging. This is only collected for merge dry runs.
+    conflict_log: Option<ConflictLog>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConflictLog {
+    /// (item, other item) for each pair of inserts which were integrated at the same location.
+    pub(crate) concurrent_inserts: Vec<(DTRange, DTRange)>,
+
+    /// (inserts, deletes) for runs of inserts whose neighbours were deleted. Each insert pairs
+    /// with the delete at the same offset, counting from the end of the deletes if they're
+    /// reversed. The inserts and deletes might not be concurrent - thats checked using the graph.
+    pub(crate) neighbour_deletes: Vec<(DTRange, RangeRev)>,
 }
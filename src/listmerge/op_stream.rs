@@ -0,0 +1,136 @@
+//! A tracker for transforming a stream of operations as they arrive, one at a time. Unlike the
+//! merge tracker, this never needs to look up old operations - so the operations themselves don't
+//! need to be stored anywhere. See [`ThinClient`](crate::list::ThinClient).
+
+use rle::{HasLength, TrimCtx};
+use crate::{Frontier, LV};
+use crate::causalgraph::CausalGraph;
+use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
+use crate::listmerge::M2Tracker;
+use crate::listmerge::merge::TransformedResult::BaseMoved;
+use crate::rle::{KVPair, RleSpanHelpers};
+
+#[derive(Debug, Clone)]
+pub(crate) struct StreamTracker {
+    tracker: M2Tracker,
+
+    /// The version which the state of the items in the tracker currently reflects.
+    version: Frontier,
+
+    /// Always empty. The tracker only needs this to split operations, and the operations we
+    /// integrate never reference any content.
+    ctx: ListOperationCtx,
+}
+
+impl StreamTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            tracker: M2Tracker::new(),
+            version: Frontier::root(),
+            ctx: ListOperationCtx::new(),
+        }
+    }
+
+    /// Throw away the tracker's items. Only operations which have `version` in their history can
+    /// be integrated afterwards.
+    pub(crate) fn clear(&mut self, version: Frontier) {
+        self.tracker.clear();
+        self.version = version;
+    }
+
+    /// Integrate the operation at `lv`, which must have just been added to the causal graph.
+    /// The operation must not reference any content.
+    ///
+    /// `apply` is called with each part of the operation which changes the document at the
+    /// tracker's maximum version, transformed to apply there.
+    pub(crate) fn integrate<F: FnMut(LV, ListOpMetrics)>(&mut self, cg: &CausalGraph, lv: LV, op: ListOpMetrics, mut apply: F) {
+        debug_assert!(op.content_pos.is_none());
+        let graph = &cg.graph;
+        let aa = &cg.agent_assignment;
+        let parents = graph.parents_at_version(lv);
+
+        let (only_tracker, only_parents) = graph.diff_rev(self.version.as_ref(), parents.as_ref());
+        for range in only_tracker {
+            self.tracker.retreat_by_range(range);
+        }
+        for range in only_parents.into_iter().rev() {
+            self.tracker.advance_by_range(range);
+        }
+
+        let mut pair = KVPair(lv, op);
+        let agent = aa.local_span_to_agent_span(pair.span()).agent;
+        debug_assert!(aa.local_span_to_agent_span(pair.span()).len() >= pair.len());
+        let last = pair.span().last();
+
+        loop {
+            let (len, xf) = self.tracker.apply(aa, &self.ctx, &pair, usize::MAX, agent);
+            let rest = pair.trim_ctx(len, &self.ctx);
+
+            if let BaseMoved(pos) = xf {
+                let KVPair(v, mut op) = pair;
+                op.transpose_to(pos);
+                apply(v, op);
+            }
+
+            match rest {
+                Some(rest) => pair = rest,
+                None => break,
+            }
+        }
+
+        self.version.replace_with_1(last);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jumprope::JumpRope;
+    use rle::HasLength;
+    use crate::list::gen_random::gen_oplog;
+    use crate::list::ListOpLog;
+    use crate::list::op_metrics::ListOpMetrics;
+    use crate::list::operation::ListOpKind;
+    use crate::listmerge::merge::reverse_str;
+    use crate::{lv_to_usize, DTRange};
+    use super::StreamTracker;
+
+    /// Stream the operations in `range` from the oplog through the tracker into `content`.
+    fn stream(tracker: &mut StreamTracker, oplog: &ListOpLog, range: DTRange, content: &mut JumpRope) {
+        for (op, hist, _) in oplog.iter_full_range(range) {
+            let text = op.content_as_str();
+            let metrics = ListOpMetrics { loc: op.loc, kind: op.kind, content_pos: None };
+            tracker.integrate(&oplog.cg, hist.span.start, metrics, |v, op| match op.kind {
+                ListOpKind::Ins => {
+                    let offset = lv_to_usize(v - hist.span.start);
+                    let s: String = text.unwrap().chars().skip(offset).take(op.len()).collect();
+                    if op.loc.fwd { content.insert(op.start(), &s); }
+                    else { content.insert(op.start(), &reverse_str(&s)); }
+                }
+                ListOpKind::Del => content.remove(op.loc.span.into()),
+            });
+        }
+    }
+
+    #[test]
+    fn stream_matches_fresh_merge() {
+        for seed in 0..20 {
+            let mut oplog = gen_oplog(seed, 40, true, true);
+            let mut tracker = StreamTracker::new();
+            let mut content = JumpRope::new();
+            stream(&mut tracker, &oplog, (0..oplog.len()).into(), &mut content);
+            assert_eq!(content, *oplog.checkout_tip().content().borrow());
+
+            // Once everything has been streamed, the tracker can be cleared and reused for
+            // operations on top of the current version.
+            let tip = oplog.local_frontier();
+            tracker.clear(tip.clone());
+            let start = oplog.len();
+            let seph = oplog.get_or_create_agent_id("seph");
+            let mike = oplog.get_or_create_agent_id("mike");
+            oplog.add_insert_at(seph, tip.as_ref(), 0, "abc");
+            oplog.add_insert_at(mike, tip.as_ref(), 0, "xyz");
+            stream(&mut tracker, &oplog, (start..oplog.len()).into(), &mut content);
+            assert_eq!(content, *oplog.checkout_tip().content().borrow());
+        }
+    }
+}
@@ -15,13 +15,24 @@ use crate::list::ListOpLog;
 use crate::list::op_metrics::ListOpMetrics;
 use crate::rle::{KVPair, RleSpanHelpers, RleVec};
 
+/// A step in an [`M1Plan`].
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum M1PlanAction {
+    /// Deactivate operations which have already been applied, moving the tracker back in time.
+    /// The range is walked backwards.
     Retreat(DTRange),
+    /// Reactivate operations which were deactivated by an earlier retreat.
     Advance(DTRange),
+    /// Throw out the tracker's state. Everything applied so far is treated as a single version.
     Clear,
+    /// Apply new operations to the tracker (and after [`BeginOutput`](M1PlanAction::BeginOutput),
+    /// to the document).
     Apply(DTRange),
+    /// Apply operations directly to the document, without transforming them. This is used when
+    /// nothing is concurrent with the operations.
     FF(DTRange),
+    /// Operations from here on are being merged into the document. Everything before this was
+    /// already in the document, and only rebuilds the tracker's state.
     BeginOutput,
 }
 
@@ -49,8 +60,19 @@ impl MergableSpan for M1PlanAction {
     }
 }
 
+/// A plan for merging changes into a branch. Merging walks a tracker through the conflicting
+/// part of the document's history, and the plan lists the steps to take.
+///
+/// Plans are made with [`ListOpLog::make_merge_plan`] and run with
+/// [`ListBranch::merge_with_plan`](crate::list::ListBranch::merge_with_plan). They're mostly
+/// useful for profiling merges of pathological histories.
 #[derive(Debug, Clone)]
-pub struct M1Plan(pub Vec<M1PlanAction>);
+pub struct M1Plan {
+    pub(crate) actions: Vec<M1PlanAction>,
+    /// The branch version and the merge frontier the plan was made for. This is only set for plans
+    /// made by [`ListOpLog::make_merge_plan`], and checked when the plan is run.
+    pub(crate) made_for: Option<(Frontier, Frontier)>,
+}
 
 type Metrics = RleVec<KVPair<ListOpMetrics>>;
 
@@ -371,7 +393,7 @@ impl ConflictSubgraph<M1EntryState> {
     pub(crate) fn make_m1_plan(mut self, metrics: Option<&Metrics>, allow_ff: bool) -> (M1Plan, Frontier) {
         let mut actions = vec![];
         if self.entries.is_empty() {
-            return (M1Plan(actions), self.base_version);
+            return (M1Plan::new(actions), self.base_version);
         }
 
         let mut nonempty_spans_remaining = self.entries.iter()
@@ -384,7 +406,7 @@ impl ConflictSubgraph<M1EntryState> {
 
         if nonempty_spans_remaining == a_spans_remaining {
             // There's no spans with B. Just bail - no plan needed in this case.
-            return (M1Plan(actions), self.base_version);
+            return (M1Plan::new(actions), self.base_version);
         }
 
 
@@ -645,7 +667,7 @@ impl ConflictSubgraph<M1EntryState> {
             }
         }
 
-        (M1Plan(actions), self.base_version)
+        (M1Plan::new(actions), self.base_version)
     }
 }
 
@@ -653,7 +675,7 @@ impl Graph {
     pub(crate) fn make_m1_plan(&self, metrics: Option<&Metrics>, a: &[LV], b: &[LV], allow_ff: bool) -> (M1Plan, Frontier) {
         if self.frontier_contains_frontier(a, b) {
             // Nothing to merge. Do nothing.
-            return (M1Plan(vec![]), a.into());
+            return (M1Plan::new(vec![]), a.into());
         }
 
         let sg = self.make_conflict_graph_between(a, b);
@@ -663,27 +685,51 @@ impl Graph {
 }
 
 impl M1Plan {
+    pub(crate) fn new(actions: Vec<M1PlanAction>) -> Self {
+        Self { actions, made_for: None }
+    }
+
+    pub fn actions(&self) -> &[M1PlanAction] {
+        &self.actions
+    }
+
+    /// Returns true if the plan does nothing. (The branch already has the merged changes).
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// An estimate of the work needed to run the plan. This is the total number of operations
+    /// in every step of the plan - including retreats and advances, which revisit operations
+    /// that have already been applied.
+    pub fn estimated_ops(&self) -> usize {
+        self.actions.iter().map(|a| match a {
+            M1PlanAction::Retreat(span) | M1PlanAction::Advance(span)
+                | M1PlanAction::Apply(span) | M1PlanAction::FF(span) => span.len(),
+            M1PlanAction::Clear | M1PlanAction::BeginOutput => 0,
+        }).sum()
+    }
+
     pub(crate) fn dbg_check(&self, common_ancestor: &[LV], a: &[LV], b: &[LV], graph: &Graph) {
-        if self.0.is_empty() {
+        if self.actions.is_empty() {
             // It would be better to make this stricter, and require an empty plan if a contains b.
             assert!(graph.frontier_contains_frontier(a, b));
             return;
         }
         // if graph.frontier_contains_frontier(a, b) {
         //     // We shouldn't do anything in this case.
-        //     assert!(self.0.is_empty());
+        //     assert!(self.actions.is_empty());
         //     return;
         // }
 
         // dbg!(self, a, b);
-        assert!(self.0.iter().filter(|&&a| a == M1PlanAction::BeginOutput).count() <= 1);
+        assert!(self.actions.iter().filter(|&&a| a == M1PlanAction::BeginOutput).count() <= 1);
 
         let mut current: Frontier = common_ancestor.into();
         let mut max: Frontier = common_ancestor.into();
         let mut cleared_version: Frontier = common_ancestor.into();
         let mut started_output = false;
 
-        for (_i, action) in self.0.iter().enumerate() {
+        for (_i, action) in self.actions.iter().enumerate() {
             // println!("{_i}: {:?}", action);
             match action {
                 M1PlanAction::BeginOutput => {
@@ -776,9 +822,10 @@ impl M1Plan {
         assert_eq!(max, final_version);
     }
 
-    pub(crate) fn dbg_print(&self) {
+    /// Print the plan's actions to stdout.
+    pub fn dbg_print(&self) {
         let mut i = 0;
-        for a in self.0.iter() {
+        for a in self.actions.iter() {
             match a {
                 M1PlanAction::Retreat(span) => {
                     println!("{i}: --- deactivate {:?}", span);
@@ -913,5 +960,5 @@ fn lite_bench() {
     //     oplog.checkout_tip_2(plan.clone(), common.as_ref());
     // }
 
-    dbg!(plan.0.len());
+    dbg!(plan.actions.len());
 }
@@ -0,0 +1,199 @@
+//! This file contains a tracker which spans the entire history of a document. Unlike the tracker
+//! used for merging (which only covers the conflict zone), this tracker knows about every item
+//! ever inserted into the document. That makes it possible to move the document content both
+//! forwards *and backwards* in time by simply retreating and advancing the tracker.
+//!
+//! Building the tracker is about as expensive as replaying the whole document, but once it exists
+//! moving between nearby versions is very cheap. This is useful for things like history sliders.
+
+use std::fmt::{Debug, Formatter};
+use std::mem::take;
+use jumprope::JumpRopeBuf;
+use rle::HasLength;
+use crate::{DTRange, Frontier, lv_to_usize, LV};
+use crate::dtrange::UNDERWATER_START;
+use crate::list::ListOpLog;
+use crate::list::operation::TextOperation;
+use crate::listmerge::advance_retreat::ContentSync;
+use crate::listmerge::M2Tracker;
+use crate::listmerge::markers::Marker;
+use crate::listmerge::yjsspan::INSERTED;
+
+#[derive(Debug, Clone)]
+pub(crate) struct RewindTracker {
+    tracker: M2Tracker,
+
+    /// Every operation within this version has been integrated into the tracker.
+    covered: Frontier,
+
+    /// The version which the state of the items in the tracker currently reflects.
+    version: Frontier,
+}
+
+impl RewindTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            tracker: M2Tracker::new(),
+            covered: Frontier::root(),
+            version: Frontier::root(),
+        }
+    }
+
+    /// Move the passed document content from `content_version` to `target`.
+    ///
+    /// The tracker must always be used with the same oplog, though the oplog may have had new
+    /// operations added since the last call.
+    /// If `out` is passed, the changes made to the content are also appended there as text
+    /// operations.
+    pub(crate) fn move_content(&mut self, oplog: &ListOpLog, content: &mut JumpRopeBuf, content_version: &[LV], target: &[LV], out: Option<&mut Vec<TextOperation>>) {
+        // First integrate any operations the tracker hasn't seen yet. This doesn't touch the
+        // content, so we don't care which version the tracker ends up at.
+        let needed = oplog.cg.graph.find_dominators_2(content_version, target);
+        self.integrate(oplog, needed.as_ref());
+
+        // Then bring the tracker in line with the content we've been passed, and finally move
+        // both to the target version together.
+        self.move_tracker(oplog, content_version, None);
+        self.move_tracker(oplog, target, Some(&mut ContentSync {
+            content: Some(content),
+            out,
+            ctx: &oplog.operation_ctx,
+            ops: &oplog.operations,
+        }));
+    }
+
+    /// Generate a list of operations which would convert a document at version `from` into the
+    /// document at version `to`. The operations apply sequentially.
+    pub(crate) fn diff(&mut self, oplog: &ListOpLog, from: &[LV], to: &[LV]) -> Vec<TextOperation> {
+        let needed = oplog.cg.graph.find_dominators_2(from, to);
+        self.integrate(oplog, needed.as_ref());
+
+        let mut result = vec![];
+        self.move_tracker(oplog, from, None);
+        self.move_tracker(oplog, to, Some(&mut ContentSync {
+            content: None,
+            out: Some(&mut result),
+            ctx: &oplog.operation_ctx,
+            ops: &oplog.operations,
+        }));
+        result
+    }
+
+    /// Move the tracker (but no content) to the named version.
+    pub(crate) fn move_to(&mut self, oplog: &ListOpLog, target: &[LV]) {
+        self.integrate(oplog, target);
+        self.move_tracker(oplog, target, None);
+    }
+
+    /// Iterate through the LV ranges of all the items visible at the tracker's current version,
+    /// in document order.
+    pub(crate) fn iter_visible(&self) -> impl Iterator<Item = DTRange> + '_ {
+        self.tracker.range_tree.iter()
+            .filter(|e| e.current_state == INSERTED && e.id.start < UNDERWATER_START)
+            .map(|e| e.id)
+    }
+
+    /// Iterate through the LV ranges of every item the tracker knows about (whether or not its
+    /// currently visible), in document order.
+    pub(crate) fn iter_all(&self) -> impl Iterator<Item = DTRange> + '_ {
+        self.tracker.range_tree.iter()
+            .filter(|e| e.id.start < UNDERWATER_START)
+            .map(|e| e.id)
+    }
+
+    /// Iterate through every item the tracker knows about in document order, along with whether
+    /// its visible at the tracker's current version.
+    pub(crate) fn iter_items(&self) -> impl Iterator<Item = (DTRange, bool)> + '_ {
+        self.tracker.range_tree.iter()
+            .filter(|e| e.id.start < UNDERWATER_START)
+            .map(|e| (e.id, e.current_state == INSERTED))
+    }
+
+    /// Figure out where every item the tracker knows about sits in the document order.
+    pub(crate) fn item_order(&self) -> ItemOrder {
+        let mut spans: Vec<(DTRange, usize)> = vec![];
+        let mut len = 0;
+        for range in self.iter_all() {
+            spans.push((range, len));
+            len += range.len();
+        }
+        spans.sort_unstable_by_key(|(r, _)| r.start);
+        ItemOrder { spans, len }
+    }
+
+    /// Get the LV of the item deleted by the delete operation at `lv`. The operation must have
+    /// been integrated into the tracker.
+    pub(crate) fn deleted_item(&self, lv: LV) -> LV {
+        let entry = self.tracker.index.get_entry(lv);
+        match entry.val {
+            Marker::Del(del) => del.range(lv_to_usize(lv - entry.start), lv_to_usize(lv - entry.start) + 1).start,
+            Marker::InsPtr(_) => panic!("Operation at {lv} is not a delete"),
+        }
+    }
+
+    fn integrate(&mut self, oplog: &ListOpLog, needed: &[LV]) {
+        let graph = &oplog.cg.graph;
+        let (_, new_ops) = graph.diff_rev(self.covered.as_ref(), needed);
+        if !new_ops.is_empty() {
+            self.version = self.tracker.walk(graph, &oplog.cg.agent_assignment,
+                                             &oplog.operation_ctx, &oplog.operations,
+                                             take(&mut self.version), &new_ops, None);
+            self.covered = graph.find_dominators_2(self.covered.as_ref(), needed);
+        }
+    }
+
+    fn move_tracker(&mut self, oplog: &ListOpLog, target: &[LV], mut sync: Option<&mut ContentSync>) {
+        let (only_here, only_target) = oplog.cg.graph.diff_rev(self.version.as_ref(), target);
+
+        // Spans are in descending order. Retreat the most recent changes first, then advance in
+        // causal order.
+        for range in only_here {
+            self.tracker.adv_retreat_range_sync(range, -1, sync.as_deref_mut());
+        }
+        for range in only_target.into_iter().rev() {
+            self.tracker.adv_retreat_range_sync(range, 1, sync.as_deref_mut());
+        }
+
+        self.version = target.into();
+    }
+}
+
+/// A map from each item's LV to its index in the document, counting deleted items.
+pub(crate) struct ItemOrder {
+    /// (lv range, index of first item). Sorted by LV.
+    spans: Vec<(DTRange, usize)>,
+    len: usize,
+}
+
+impl ItemOrder {
+    /// The total number of items (visible or not).
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn order_of(&self, lv: LV) -> usize {
+        let idx = self.spans.partition_point(|(r, _)| r.end <= lv);
+        let (r, base) = self.spans[idx];
+        debug_assert!(r.contains(lv));
+        base + lv_to_usize(lv - r.start)
+    }
+}
+
+/// Branches lazily create a rewind tracker the first time they're moved backwards in time. The
+/// cache has no bearing on the branch's content, so it's ignored when branches are compared.
+#[derive(Clone, Default)]
+pub(crate) struct RewindCache(pub(crate) Option<Box<RewindTracker>>);
+
+impl PartialEq for RewindCache {
+    fn eq(&self, _other: &Self) -> bool { true }
+}
+
+impl Eq for RewindCache {}
+
+impl Debug for RewindCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RewindCache")
+            .field(&self.0.is_some())
+            .finish()
+    }
+}
@@ -2,7 +2,7 @@ use smallvec::{SmallVec, smallvec};
 
 use rle::{AppendRle, HasLength, Trim, TrimCtx};
 
-use crate::{DTRange, Frontier, LV};
+use crate::{DTRange, Frontier, lv_to_usize, LV};
 use crate::causalgraph::agent_assignment::AgentAssignment;
 use crate::causalgraph::graph::Graph;
 use crate::causalgraph::graph::tools::DiffFlag;
@@ -151,7 +151,7 @@ impl<'a> Iterator for TransformedOpsIterOld<'a> {
             if can_ff {
                 let mut span = self.new_ops.pop().unwrap();
 
-                let remainder = span.trim(txn.span.end - span.start);
+                let remainder = span.trim(lv_to_usize(txn.span.end - span.start));
 
                 debug_assert!(!span.is_empty());
 
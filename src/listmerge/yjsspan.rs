@@ -116,16 +116,17 @@ impl Debug for CRDTSpan {
 }
 
 impl CRDTSpan {
-    pub fn origin_left_at_offset(&self, offset: LV) -> LV {
+    pub fn origin_left_at_offset(&self, offset: usize) -> LV {
         if offset == 0 { self.origin_left }
-        else { self.id.start + offset - 1 }
+        else { self.id.start + offset as LV - 1 }
     }
 
     pub fn new_underwater() -> Self {
         CRDTSpan {
-            id: DTRange::new(UNDERWATER_START, UNDERWATER_START * 2 - 1),
-            origin_left: usize::MAX,
-            origin_right: usize::MAX,
+            // The span's length is a document length, so it needs to fit in a usize.
+            id: DTRange::new(UNDERWATER_START, UNDERWATER_START + (usize::MAX / 4) as LV - 1),
+            origin_left: LV::MAX,
+            origin_right: LV::MAX,
             current_state: INSERTED, // Underwater items are never in the NotInsertedYet state.
             end_state_ever_deleted: false,
         }
@@ -171,7 +172,7 @@ impl SplitableSpanHelpers for CRDTSpan {
         // let at_signed = offset as i32 * self.len.signum();
         CRDTSpan {
             id: self.id.truncate(offset),
-            origin_left: self.id.start + offset - 1,
+            origin_left: self.id.start + offset as LV - 1,
             origin_right: self.origin_right,
             current_state: self.current_state,
             end_state_ever_deleted: self.end_state_ever_deleted,
@@ -212,7 +213,7 @@ impl Searchable for CRDTSpan {
     }
 
     fn at_offset(&self, offset: usize) -> Self::Item {
-        self.id.start + offset
+        self.id.start + offset as LV
     }
 }
 
@@ -313,7 +314,7 @@ mod tests {
         test_splitable_methods_valid(CRDTSpan {
             id: (10..15).into(),
             origin_left: 20,
-            origin_right: usize::MAX,
+            origin_right: LV::MAX,
             current_state: INSERTED,
             end_state_ever_deleted: false
         });
@@ -9,7 +9,7 @@ use serde::{Serialize, Serializer};
 
 use rle::{HasLength, SplitableSpanCtx};
 use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
-use crate::{AgentId, CRDTKind, CreateValue, DTRange, DTValue, OpLog, LV, LVKey, RegisterInfo, RegisterValue, ROOT_CRDT_ID, SerializedOps, ValPair};
+use crate::{AgentId, CRDTKind, CreateValue, DTRange, DTValue, OpLog, lv_to_usize, LV, LVKey, Primitive, RegisterInfo, RegisterValue, ROOT_CRDT_ID, SerializedOps, ValPair};
 use crate::encoding::bufparser::BufParser;
 use crate::encoding::cg_entry::{read_cg_entry_into_cg, write_cg_entry_iter};
 use crate::encoding::map::{ReadMap, WriteMap};
@@ -115,6 +115,57 @@ impl OpLog {
         }
         assert_eq!(self.text_index.len(), expected_idx_count);
 
+        // Custom operations
+        let mut expected_idx_count = 0;
+        for (crdt, info) in self.customs.iter() {
+            assert_eq!(*item_type.get(crdt).unwrap(), CRDTKind::Custom);
+            assert!(is_sorted_iter_uniq(info.ops.iter().map(|(v, _)| *v)));
+            for (v, _) in info.ops.iter() {
+                assert!(*v < cg_len);
+                assert_eq!(self.custom_index.get(v), Some(crdt));
+                expected_idx_count += 1;
+            }
+        }
+        assert_eq!(self.custom_index.len(), expected_idx_count);
+
+        // Counters
+        let mut expected_idx_count = 0;
+        for (crdt, info) in self.counters.iter() {
+            assert_eq!(*item_type.get(crdt).unwrap(), CRDTKind::Counter);
+            assert!(is_sorted_iter_uniq(info.ops.iter().map(|(v, _)| *v)));
+            let mut value = 0i64;
+            for (v, n) in info.ops.iter() {
+                assert!(*v < cg_len);
+                assert_eq!(self.counter_index.get(v), Some(crdt));
+                value = value.wrapping_add(*n);
+                expected_idx_count += 1;
+            }
+            assert_eq!(info.value, value);
+        }
+        assert_eq!(self.counter_index.len(), expected_idx_count);
+
+        // Registers
+        let mut expected_idx_count = 0;
+        for (crdt, info) in self.registers.iter() {
+            assert_eq!(*item_type.get(crdt).unwrap(), CRDTKind::Register);
+            assert!(is_sorted_slice::<true, _>(&info.supremum));
+            assert!(is_sorted_iter_uniq(info.ops.iter().map(|(v, _)| *v)));
+            for (v, val) in info.ops.iter() {
+                assert!(*v < cg_len);
+                assert!(matches!(val, CreateValue::Primitive(_)));
+                assert_eq!(self.register_index.get(v), Some(crdt));
+                expected_idx_count += 1;
+            }
+
+            if deep {
+                let all_versions = info.ops.iter().map(|(v, _)| *v).collect::<Vec<_>>();
+                let dominators = self.cg.graph.find_dominators(&all_versions);
+                let sup_versions = info.supremum.iter().map(|idx| info.ops[*idx].0).collect::<Vec<_>>();
+                assert_eq!(dominators.as_ref(), &sup_versions);
+            }
+        }
+        assert_eq!(self.register_index.len(), expected_idx_count);
+
         if deep {
             // Find all the CRDTs which have been created then later overwritten or deleted.
             let mut deleted_crdts = BTreeSet::new();
@@ -199,11 +250,19 @@ impl OpLog {
     fn create_child_crdt(&mut self, v: LV, kind: CRDTKind) {
         match kind {
             CRDTKind::Map => {}
-            CRDTKind::Register => {}
+            CRDTKind::Register => {
+                self.registers.entry(v).or_default();
+            }
             CRDTKind::Collection => {}
             CRDTKind::Text => {
                 self.texts.entry(v).or_default();
             }
+            CRDTKind::Custom => {
+                self.customs.entry(v).or_default();
+            }
+            CRDTKind::Counter => {
+                self.counters.entry(v).or_default();
+            }
         }
     }
 
@@ -379,7 +438,7 @@ impl OpLog {
         }
     }
 
-    fn resolve_mv(&self, reg: &RegisterInfo) -> RegisterValue {
+    pub(crate) fn resolve_mv(&self, reg: &RegisterInfo) -> RegisterValue {
         let (active_idx, _) = self.tie_break_mv(reg);
 
         let (v, value) = &reg.ops[active_idx];
@@ -411,6 +470,8 @@ impl OpLog {
                     match kind {
                         CRDTKind::Map => DTValue::Map(self.checkout_map(child_crdt)),
                         CRDTKind::Text => DTValue::Text(self.checkout_text(child_crdt).to_string()),
+                        CRDTKind::Counter => DTValue::Primitive(Primitive::I64(self.checkout_counter(child_crdt).unwrap())),
+                        CRDTKind::Register => DTValue::Primitive(self.checkout_register(child_crdt).unwrap()),
                         _ => unimplemented!(),
                         // CRDTKind::Register => {}
                         // CRDTKind::Collection => {}
@@ -476,9 +537,14 @@ impl OpLog {
         }
     }
 
-    fn remote_to_crdt_name(&self, crdt_rv: RemoteVersion) -> LVKey {
-        if crdt_rv.0 == "ROOT" { ROOT_CRDT_ID }
-        else { self.cg.agent_assignment.remote_to_local_version(crdt_rv) }
+    fn remote_to_crdt_name(&self, crdt_rv: RemoteVersion) -> Result<LVKey, ParseError> {
+        if crdt_rv.0 == "ROOT" { Ok(ROOT_CRDT_ID) }
+        else { self.remote_to_lv(crdt_rv) }
+    }
+
+    fn remote_to_lv(&self, rv: RemoteVersion) -> Result<LV, ParseError> {
+        self.cg.agent_assignment.try_remote_to_local_version(rv)
+            .map_err(ParseError::InvalidRemoteID)
     }
 
     // pub fn xf_text_changes_since(&self, text_item: LVKey, since_frontier: &[LV]) {
@@ -496,6 +562,9 @@ impl OpLog {
         let mut cg_changes = Vec::new();
         let mut text_crdts_to_send = BTreeSet::new();
         let mut map_crdts_to_send = BTreeSet::new();
+        let mut custom_ops = Vec::new();
+        let mut counter_ops = Vec::new();
+        let mut register_ops = Vec::new();
         for range_rev in diff_rev.iter() {
             let iter = self.cg.iter_range(*range_rev);
             write_cg_entry_iter(&mut cg_changes, iter, &mut write_map, &self.cg);
@@ -510,6 +579,34 @@ impl OpLog {
             }
         }
 
+        // Serialize custom, counter and register operations. These are sent in order.
+        for range in diff_rev.iter().rev() {
+            for (v, crdt) in self.custom_index.range(*range) {
+                // The index should always agree with the stored operations (see dbg_check).
+                let Some((kind, op)) = self.custom_op_at(*crdt, *v) else {
+                    debug_assert!(false, "Custom op index out of sync");
+                    continue;
+                };
+                let rv = self.cg.agent_assignment.local_to_remote_version(*v);
+                custom_ops.push((self.crdt_name_to_remote(*crdt), rv, kind, op.to_vec()));
+            }
+
+            for (v, crdt) in self.counter_index.range(*range) {
+                let info = &self.counters[crdt];
+                let (_, n) = info.ops[info.ops.binary_search_by_key(v, |(v, _)| *v).unwrap()];
+                let rv = self.cg.agent_assignment.local_to_remote_version(*v);
+                counter_ops.push((self.crdt_name_to_remote(*crdt), rv, n));
+            }
+
+            for (v, crdt) in self.register_index.range(*range) {
+                let info = &self.registers[crdt];
+                let (_, val) = &info.ops[info.ops.binary_search_by_key(v, |(v, _)| *v).unwrap()];
+                let CreateValue::Primitive(val) = val else { unreachable!() };
+                let rv = self.cg.agent_assignment.local_to_remote_version(*v);
+                register_ops.push((self.crdt_name_to_remote(*crdt), rv, val.clone()));
+            }
+        }
+
         // Serialize map operations
         let mut map_ops = Vec::new();
         for (crdt, key) in map_crdts_to_send {
@@ -562,6 +659,9 @@ impl OpLog {
             map_ops,
             text_ops,
             text_context,
+            custom_ops,
+            counter_ops,
+            register_ops,
         }
     }
 
@@ -585,31 +685,58 @@ impl OpLog {
         if new_range.is_empty() { return Ok(new_range); }
 
         for (crdt_r_name, rv, key, val) in changes.map_ops {
-            let lv = self.cg.agent_assignment.remote_to_local_version(rv);
+            let lv = self.remote_to_lv(rv)?;
             if new_range.contains(lv) {
-                let crdt_id = self.remote_to_crdt_name(crdt_r_name);
+                let crdt_id = self.remote_to_crdt_name(crdt_r_name)?;
                 // dbg!(crdt_id, lv, key, val);
                 self.remote_map_set(crdt_id, lv, key, val);
             }
         }
 
         for (crdt_r_name, rv, mut op_metrics) in changes.text_ops {
-            let lv = self.cg.agent_assignment.remote_to_local_version(rv);
-            let mut v_range: DTRange = (lv..lv + op_metrics.len()).into();
+            let lv = self.remote_to_lv(rv)?;
+            let mut v_range: DTRange = (lv..lv + op_metrics.len() as LV).into();
 
             if v_range.end <= new_range.start { continue; }
             else if v_range.start < new_range.start {
                 // Trim the new operation.
-                op_metrics.truncate_keeping_right_ctx(new_range.start - v_range.start, &changes.text_context);
+                op_metrics.truncate_keeping_right_ctx(lv_to_usize(new_range.start - v_range.start), &changes.text_context);
                 v_range.start = new_range.start;
             }
 
-            let crdt_id = self.remote_to_crdt_name(crdt_r_name);
+            let crdt_id = self.remote_to_crdt_name(crdt_r_name)?;
+            if !self.texts.contains_key(&crdt_id) { return Err(ParseError::GenericInvalidData); }
 
             let op = op_metrics.to_operation(&changes.text_context);
             self.remote_text_op(crdt_id, v_range, op);
         }
 
+        for (crdt_r_name, rv, kind, op) in changes.custom_ops {
+            let lv = self.remote_to_lv(rv)?;
+            if new_range.contains(lv) {
+                let crdt_id = self.remote_to_crdt_name(crdt_r_name)?;
+                self.push_custom_op(crdt_id, lv, kind, op)?;
+            }
+        }
+
+        for (crdt_r_name, rv, n) in changes.counter_ops {
+            let lv = self.remote_to_lv(rv)?;
+            if new_range.contains(lv) {
+                let crdt_id = self.remote_to_crdt_name(crdt_r_name)?;
+                if !self.counters.contains_key(&crdt_id) { return Err(ParseError::GenericInvalidData); }
+                self.push_counter_op(crdt_id, lv, n);
+            }
+        }
+
+        for (crdt_r_name, rv, val) in changes.register_ops {
+            let lv = self.remote_to_lv(rv)?;
+            if new_range.contains(lv) {
+                let crdt_id = self.remote_to_crdt_name(crdt_r_name)?;
+                if !self.registers.contains_key(&crdt_id) { return Err(ParseError::GenericInvalidData); }
+                self.push_register_op(crdt_id, lv, val);
+            }
+        }
+
         Ok(new_range)
     }
 
@@ -69,6 +69,19 @@ pub(crate) struct ContentTree<V: Content> {
 
     /// There is a cached cursor currently at some content position, with a held delta update.
     cursor: Option<(Option<LenPair>, DeltaCursor)>,
+
+    /// Incremented every time items in the tree are moved around. Cursors remember the generation
+    /// they were created in, so stale cursors can be detected. (A stale cursor's indexes might
+    /// point to the wrong item, or no item at all.)
+    generation: u32,
+
+    /// Leaves which have been merged into their neighbours are put in a free list (linked through
+    /// next_leaf) so they can be reused by split_leaf.
+    free_leaf_pool_head: LeafIdx,
+
+    /// Internal nodes which have been merged into their neighbours are put in a free list too
+    /// (linked through parent), so they can be reused by split_node.
+    free_node_pool_head: NodeIdx,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -81,6 +94,9 @@ pub(crate) struct ContentCursor {
 
     /// Offset into the item.
     pub offset: usize,
+
+    /// The tree's generation when this cursor was made. See [`ContentTree::check_cursor`].
+    pub generation: u32,
 }
 
 // Wouldn't need this impl if LeafIdx defaulted to 0...
@@ -90,6 +106,7 @@ impl Default for ContentCursor {
             leaf_idx: LeafIdx(0),
             elem_idx: 0,
             offset: 0,
+            generation: 0,
         }
     }
 }
@@ -97,6 +114,27 @@ impl Default for ContentCursor {
 #[derive(Debug, Clone)]
 pub struct DeltaCursor(pub ContentCursor, pub LenUpdate);
 
+/// A cursor which is safe to hold while the tree is modified.
+///
+/// A [`ContentCursor`] stores raw indexes into the tree, which go stale when the tree is modified
+/// through some other cursor. A PosCursor also remembers its position in the current content, so
+/// when it goes stale it's found again from the root of the tree instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PosCursor {
+    pos: usize,
+    cursor: Option<ContentCursor>,
+}
+
+impl PosCursor {
+    /// Make a cursor before the content at pos. It's resolved lazily, the first time it's used.
+    pub fn new(pos: usize) -> Self {
+        Self { pos, cursor: None }
+    }
+
+    /// The cursor's position in the current content.
+    pub fn pos(&self) -> usize { self.pos }
+}
+
 const NODE_SPLIT_POINT: usize = NODE_CHILDREN / 2;
 // const LEAF_CHILDREN: usize = LEAF_SIZE - 1;
 const LEAF_SPLIT_POINT: usize = LEAF_CHILDREN / 2;
@@ -119,9 +157,11 @@ pub struct ContentNode {
     /// Children have an index of usize::MAX if the slot is unused.
     child_indexes: [usize; NODE_CHILDREN],
 
-    /// Child entries point to either another node or a leaf. We disambiguate using the height.
-    /// The named LV is the first LV of the child data.
-    child_width: [LenPair; NODE_CHILDREN],
+    /// The width of each child. The current and end lengths are stored in separate arrays (rather
+    /// than an array of LenPairs) so finding a position only scans the array it needs, and the
+    /// scans are simple enough to be vectorized.
+    child_cur: [usize; NODE_CHILDREN],
+    child_end: [usize; NODE_CHILDREN],
     parent: NodeIdx,
 }
 
@@ -157,6 +197,39 @@ impl<V: Content> ContentLeaf<V> {
 }
 
 impl ContentNode {
+    fn new(parent: NodeIdx) -> Self {
+        Self {
+            child_indexes: [usize::MAX; NODE_CHILDREN],
+            child_cur: [0; NODE_CHILDREN],
+            child_end: [0; NODE_CHILDREN],
+            parent,
+        }
+    }
+
+    #[inline]
+    fn width(&self, i: usize) -> LenPair {
+        LenPair::new(self.child_cur[i], self.child_end[i])
+    }
+
+    #[inline]
+    fn set_width(&mut self, i: usize, width: LenPair) {
+        self.child_cur[i] = width.cur;
+        self.child_end[i] = width.end;
+    }
+
+    #[inline]
+    fn update_width(&mut self, i: usize, delta: LenUpdate) {
+        self.child_cur[i] = self.child_cur[i].wrapping_add_signed(delta.cur);
+        self.child_end[i] = self.child_end[i].wrapping_add_signed(delta.end);
+    }
+
+    /// Remove the child at slot i, sliding back later children.
+    fn remove_child(&mut self, i: usize) {
+        remove_from_array_fill(&mut self.child_indexes, i..i + 1, usize::MAX);
+        remove_from_array_fill(&mut self.child_cur, i..i + 1, 0);
+        remove_from_array_fill(&mut self.child_end, i..i + 1, 0);
+    }
+
     fn is_full(&self) -> bool {
         *self.child_indexes.last().unwrap() != usize::MAX
     }
@@ -218,6 +291,7 @@ impl ContentCursor {
     ///
     /// Returns false if there is no next item.
     pub(crate) fn roll_next_item<V: Content>(&mut self, tree: &ContentTree<V>) -> (bool, Option<LeafIdx>) {
+        tree.check_cursor(self);
         let leaf = &tree[self.leaf_idx];
         if self.offset < leaf.children[self.elem_idx].len() { return (true, None); }
 
@@ -263,6 +337,7 @@ impl ContentCursor {
     // }
 
     pub fn get_item<'a, V: Content>(&self, tree: &'a ContentTree<V>) -> (&'a V, usize) {
+        tree.check_cursor(self);
         let leaf = &tree[self.leaf_idx];
         (&leaf.children[self.elem_idx], self.offset)
     }
@@ -274,6 +349,15 @@ impl ContentCursor {
     /// of later items. The cursor itself is (just) early enough to be unaffected.
     pub(crate) fn get_pos<V: Content>(&self, tree: &ContentTree<V>) -> LenPair {
         assert!(cfg!(debug_assertions), "get_pos should never be called in release mode");
+        self.calc_pos(tree)
+    }
+
+    /// Calculate the cursor's position by walking up the tree. This is O(log n) - which is fine
+    /// for occasional use, but it shouldn't be used in the hot path of a merge.
+    ///
+    /// The tree must not have any other cursor with an outstanding delta.
+    pub(crate) fn calc_pos<V: Content>(&self, tree: &ContentTree<V>) -> LenPair {
+        tree.check_cursor(self);
         let mut result = LenPair::default();
 
         let leaf = &tree[self.leaf_idx];
@@ -293,7 +377,7 @@ impl ContentCursor {
 
             for i in 0..node.child_indexes.len() {
                 if node.child_indexes[i] == last_child { break; }
-                result += node.child_width[i];
+                result += node.width(i);
             }
             last_child = p.0;
             p = node.parent;
@@ -375,9 +459,54 @@ impl<V: Content> ContentTree<V> {
             root: 0,
             cursor: Default::default(),
             total_len: Default::default(),
+            generation: 0,
+            free_leaf_pool_head: LeafIdx::default(),
+            free_node_pool_head: NodeIdx::default(),
+        }
+    }
+
+    /// Check that the cursor was made from the tree's current generation. Cursors go stale when
+    /// the tree is modified through some other cursor.
+    ///
+    /// Using a stale cursor is a bug, and this panics in debug mode. In release mode we don't pay
+    /// for the check. (The indexes are bounds checked, so this isn't memory unsafe.) Code which
+    /// needs to hold a cursor across edits should use a [`PosCursor`], which finds its position
+    /// again when it goes stale.
+    #[inline]
+    pub(crate) fn check_cursor(&self, cursor: &ContentCursor) {
+        debug_assert_eq!(cursor.generation, self.generation, "Stale content tree cursor");
+    }
+
+    /// Returns true if the cursor is still valid (it was made from the tree's current generation).
+    pub(crate) fn cursor_is_current(&self, cursor: &ContentCursor) -> bool {
+        cursor.generation == self.generation
+    }
+
+    /// Take the cached cursor, if there is one. If the cached cursor has gone stale, its discarded
+    /// and the caller will find its position again from the root of the tree.
+    fn take_cached_cursor(&mut self) -> Option<(Option<LenPair>, DeltaCursor)> {
+        let (pos, cursor) = self.cursor.take()?;
+        if self.cursor_is_current(&cursor.0) { return Some((pos, cursor)); }
+
+        // The cached cursor is flushed before the tree is modified (see flush_cached_cursor), so
+        // a stale cursor shouldn't have a delta. And we can't flush it anyway - the leaf it
+        // points to might have been recycled.
+        None
+    }
+
+    /// Flush and discard the cached cursor. This is called before the tree is modified, while the
+    /// cached cursor's leaf index is still valid.
+    #[inline]
+    fn flush_cached_cursor(&mut self) {
+        if let Some((_, cursor)) = self.cursor.take() {
+            cursor.flush(self);
         }
     }
 
+    fn next_generation(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
     pub fn clear(&mut self) {
         self.leaves.clear();
         self.nodes.clear();
@@ -385,6 +514,9 @@ impl<V: Content> ContentTree<V> {
         self.root = 0;
         self.cursor = Default::default();
         self.total_len = Default::default();
+        self.free_leaf_pool_head = LeafIdx::default();
+        self.free_node_pool_head = NodeIdx::default();
+        self.next_generation();
 
         self.leaves.push(initial_root_leaf());
     }
@@ -396,6 +528,7 @@ impl<V: Content> ContentTree<V> {
         debug_assert!(self.cursor.is_none());
 
         self.total_len = item.content_len_pair();
+        self.next_generation();
         notify(item, LeafIdx(0));
         self.leaves[0].children[0] = item;
     }
@@ -403,21 +536,17 @@ impl<V: Content> ContentTree<V> {
     // fn create_new_root_node(&mut self, child_a: usize, child_b: usize, split_point: LenPair) -> NodeIdx {
     fn create_new_root_node(&mut self, child_a: usize, child_b: usize, b_size: LenPair) -> NodeIdx {
         self.height += 1;
-        let mut new_root = ContentNode {
-            child_indexes: [usize::MAX; NODE_CHILDREN],
-            child_width: [Default::default(); NODE_CHILDREN],
-            parent: Default::default(),
-        };
+        let mut new_root = ContentNode::new(Default::default());
 
         new_root.child_indexes[0] = child_a;
         new_root.child_indexes[1] = child_b;
-        new_root.child_width[0] = self.total_len - b_size;
-        new_root.child_width[1] = b_size;
+        new_root.set_width(0, self.total_len - b_size);
+        new_root.set_width(1, b_size);
 
-        let new_idx = self.nodes.len();
+        let new_idx = self.alloc_node_idx();
         // println!("Setting root to {new_idx}");
         self.root = new_idx;
-        self.nodes.push(new_root);
+        self.put_node(new_idx, new_root);
         NodeIdx(new_idx)
     }
 
@@ -442,6 +571,18 @@ impl<V: Content> ContentTree<V> {
     /// The cursor ends up right after the modified item.
     pub(crate) fn mutate_entry<N, MapFn, R>(&mut self, dc: &mut DeltaCursor, replace_max: usize, notify: &mut N, map_fn: MapFn) -> (usize, R)
     where N: FnMut(V, LeafIdx), MapFn: FnOnce(&mut V) -> R
+    {
+        self.check_cursor(&dc.0);
+        self.flush_cached_cursor();
+        let result = self.mutate_entry_internal(dc, replace_max, notify, map_fn);
+        // The entry may have been split, or merged with its neighbours.
+        self.next_generation();
+        dc.0.generation = self.generation;
+        result
+    }
+
+    fn mutate_entry_internal<N, MapFn, R>(&mut self, dc: &mut DeltaCursor, replace_max: usize, notify: &mut N, map_fn: MapFn) -> (usize, R)
+    where N: FnMut(V, LeafIdx), MapFn: FnOnce(&mut V) -> R
     {
         if !dc.roll_next_item(self) { panic!("Cannot mutate at end of data structure") }
         let DeltaCursor(cursor, delta) = dc;
@@ -473,6 +614,10 @@ impl<V: Content> ContentTree<V> {
             if elem_idx2 > scan_start {
                 leaf.children[cursor.elem_idx] = entry;
                 remove_from_array_fill(&mut leaf.children, scan_start..elem_idx2, V::none());
+
+                // The leaf has shrunk. If its now mostly empty, try and merge it with a neighbour
+                // so long editing sessions don't leave the tree full of sparse leaves.
+                self.try_merge_leaf(cursor, notify);
             }
             
             return (entry_len, r);
@@ -525,7 +670,19 @@ impl<V: Content> ContentTree<V> {
         (len, r)
     }
 
-    pub fn insert<N>(&mut self, item: V, DeltaCursor(cursor, delta): &mut DeltaCursor, notify_here: bool, notify: &mut N)
+    /// Insert an item at the cursor. The cursor ends up right after the inserted item. Any other
+    /// cursors into the tree become stale.
+    pub fn insert<N>(&mut self, item: V, dc: &mut DeltaCursor, notify_here: bool, notify: &mut N)
+        where N: FnMut(V, LeafIdx)
+    {
+        self.check_cursor(&dc.0);
+        self.flush_cached_cursor();
+        self.insert_internal(item, dc, notify_here, notify);
+        self.next_generation();
+        dc.0.generation = self.generation;
+    }
+
+    fn insert_internal<N>(&mut self, item: V, DeltaCursor(cursor, delta): &mut DeltaCursor, notify_here: bool, notify: &mut N)
         where N: FnMut(V, LeafIdx)
     {
         debug_assert!(item.exists());
@@ -668,9 +825,9 @@ impl<V: Content> ContentTree<V> {
         while !idx.is_root() {
             let n = &mut self.nodes[idx.0];
             let pos = n.idx_of_child(child);
-            debug_assert!(pos < n.child_width.len());
+            debug_assert!(pos < NODE_CHILDREN);
 
-            n.child_width[pos % n.child_width.len()].update_by(delta);
+            n.update_width(pos % NODE_CHILDREN, delta);
 
             child = idx.0;
             idx = n.parent;
@@ -725,26 +882,28 @@ impl<V: Content> ContentTree<V> {
     /// performance.
     fn split_node(&mut self, old_idx: NodeIdx, children_are_leaves: bool) -> NodeIdx {
         // Split a full internal node into 2 nodes.
-        let new_node_idx = self.nodes.len();
+        let new_node_idx = self.alloc_node_idx();
         // println!("split node -> {new_node_idx}");
         let old_node = &mut self.nodes[old_idx.0];
         // The old leaf must be full before we split it.
         debug_assert!(old_node.is_full());
 
-        let split_size: LenPair = old_node.child_width[NODE_SPLIT_POINT..].iter().copied().sum();
+        let split_size = LenPair::new(
+            old_node.child_cur[NODE_SPLIT_POINT..].iter().sum(),
+            old_node.child_end[NODE_SPLIT_POINT..].iter().sum(),
+        );
 
         // eprintln!("split node {:?} -> {:?} + {:?} (leaves: {children_are_leaves})", old_idx, old_idx, new_node_idx);
         // eprintln!("split start {:?} / {:?}", &old_node.children[..NODE_SPLIT_POINT], &old_node.children[NODE_SPLIT_POINT..]);
 
-        let mut new_node = ContentNode {
-            child_indexes: [usize::MAX; NODE_CHILDREN],
-            child_width: [LenPair::default(); NODE_CHILDREN],
-            parent: NodeIdx(usize::MAX), // Overwritten below.
-        };
+        let mut new_node = ContentNode::new(NodeIdx(usize::MAX)); // Parent overwritten below.
 
         new_node.child_indexes[0..NODE_SPLIT_POINT].copy_from_slice(&old_node.child_indexes[NODE_SPLIT_POINT..]);
-        new_node.child_width[0..NODE_SPLIT_POINT].copy_from_slice(&old_node.child_width[NODE_SPLIT_POINT..]);
+        new_node.child_cur[0..NODE_SPLIT_POINT].copy_from_slice(&old_node.child_cur[NODE_SPLIT_POINT..]);
+        new_node.child_end[0..NODE_SPLIT_POINT].copy_from_slice(&old_node.child_end[NODE_SPLIT_POINT..]);
         old_node.child_indexes[NODE_SPLIT_POINT..].fill(usize::MAX);
+        old_node.child_cur[NODE_SPLIT_POINT..].fill(0);
+        old_node.child_end[NODE_SPLIT_POINT..].fill(0);
 
         if children_are_leaves {
             for idx in &new_node.child_indexes[..NODE_SPLIT_POINT] {
@@ -756,14 +915,13 @@ impl<V: Content> ContentTree<V> {
             }
         }
 
-        debug_assert_eq!(new_node_idx, self.nodes.len());
         // let split_point_lv = new_node.children[0].0;
-        self.nodes.push(new_node);
+        self.put_node(new_node_idx, new_node);
 
         // It would be much nicer to do this above earlier - and in earlier versions I did.
         // The problem is that both create_new_root_node and insert_into_node can insert new items
         // into self.nodes. If that happens, the new node index we're expecting to use is used by
-        // another node. Hence, we need to call self.nodes.push() before calling any other function
+        // another node. Hence, we need to call self.put_node() before calling any other function
         // which modifies the node list.
         let old_node = &self.nodes[old_idx.0];
         if old_idx.0 == self.root {
@@ -787,7 +945,7 @@ impl<V: Content> ContentTree<V> {
         let mut child_pos = node.child_indexes
             .iter()
             .position(|idx| { *idx == child_idx })
-            .unwrap() % node.child_width.len();
+            .unwrap() % NODE_CHILDREN;
 
         if node.is_full() {
             let new_node = self.split_node(node_idx, children_are_leaves);
@@ -802,9 +960,9 @@ impl<V: Content> ContentTree<V> {
             node = &mut self[node_idx];
         }
 
-        node.child_width[child_pos] -= stolen_len;
+        node.set_width(child_pos, node.width(child_pos) - stolen_len);
 
-        let insert_pos = (child_pos + 1) % node.child_width.len();
+        let insert_pos = (child_pos + 1) % NODE_CHILDREN;
 
         // dbg!(&node);
         // println!("insert_into_node n={:?} after_child {after_child} pos {insert_pos}, new_child {:?}", node_idx, new_child);
@@ -815,8 +973,9 @@ impl<V: Content> ContentTree<V> {
         node.child_indexes.copy_within(insert_pos..NODE_CHILDREN - 1, insert_pos + 1);
         node.child_indexes[insert_pos] = new_child_idx;
 
-        node.child_width.copy_within(insert_pos..NODE_CHILDREN - 1, insert_pos + 1);
-        node.child_width[insert_pos] = stolen_len;
+        node.child_cur.copy_within(insert_pos..NODE_CHILDREN - 1, insert_pos + 1);
+        node.child_end.copy_within(insert_pos..NODE_CHILDREN - 1, insert_pos + 1);
+        node.set_width(insert_pos, stolen_len);
 
         node_idx
     }
@@ -828,9 +987,8 @@ impl<V: Content> ContentTree<V> {
         // The result is two nodes - old_leaf with items 0..N/2 and new_leaf with items N/2..N.
 
         let old_height = self.height;
-        // TODO: This doesn't currently use the pool of leaves that we have so carefully prepared.
 
-        let new_leaf_idx = self.leaves.len(); // Weird instruction order for borrowck.
+        let new_leaf_idx = self.alloc_leaf_idx(); // Weird instruction order for borrowck.
         let mut old_leaf = &mut self.leaves[old_idx.0];
         // debug_assert!(old_leaf.is_full());
         debug_assert!(!old_leaf.has_space(2));
@@ -886,12 +1044,191 @@ impl<V: Content> ContentTree<V> {
         // old_leaf.upper_bound = split_lv;
         old_leaf.next_leaf = LeafIdx(new_leaf_idx);
 
-        self.leaves.push(new_leaf);
-        debug_assert_eq!(self.leaves.len() - 1, new_leaf_idx);
+        if new_leaf_idx == self.leaves.len() {
+            self.leaves.push(new_leaf);
+        } else {
+            self.leaves[new_leaf_idx] = new_leaf;
+        }
 
         LeafIdx(new_leaf_idx)
     }
 
+    /// Get the index of an unused leaf - either from the free pool, or at the end of the leaves
+    /// list. Leaves at the end of the list must be pushed by the caller.
+    fn alloc_leaf_idx(&mut self) -> usize {
+        let idx = self.free_leaf_pool_head;
+        if idx.exists() {
+            self.free_leaf_pool_head = self.leaves[idx.0].next_leaf;
+            idx.0
+        } else {
+            self.leaves.len()
+        }
+    }
+
+    /// Get the index of an unused internal node - either from the free pool, or at the end of the
+    /// nodes list. The node must be stored with put_node before any other nodes are allocated.
+    fn alloc_node_idx(&mut self) -> usize {
+        let idx = self.free_node_pool_head;
+        if !idx.is_root() {
+            self.free_node_pool_head = self.nodes[idx.0].parent;
+            idx.0
+        } else {
+            self.nodes.len()
+        }
+    }
+
+    fn put_node(&mut self, idx: usize, node: ContentNode) {
+        if idx == self.nodes.len() {
+            self.nodes.push(node);
+        } else {
+            self.nodes[idx] = node;
+        }
+    }
+
+    /// The cursor's leaf has shrunk. If it and one of its neighbours (with the same parent) are
+    /// both mostly empty, merge them together and put the emptied leaf in the free pool.
+    ///
+    /// The cursor is moved if its items are moved. Its delta (if any) stays correct, since the
+    /// merged leaves share a parent.
+    fn try_merge_leaf<N>(&mut self, cursor: &mut ContentCursor, notify: &mut N)
+        where N: FnMut(V, LeafIdx)
+    {
+        if self.height == 0 { return; }
+
+        let leaf_idx = cursor.leaf_idx;
+        let parent = self.leaves[leaf_idx.0].parent;
+        let node = &self.nodes[parent.0];
+        let i = node.idx_of_child(leaf_idx.0);
+
+        let count = |idx: usize| self.leaves[idx].children.iter().take_while(|c| c.exists()).count();
+        let a = count(leaf_idx.0);
+        // Leave some slack so we don't immediately split the leaf again.
+        let fits = |b: usize| a + b <= LEAF_CHILDREN / 2;
+
+        if i + 1 < NODE_CHILDREN && node.child_indexes[i + 1] != usize::MAX
+            && fits(count(node.child_indexes[i + 1]))
+        {
+            self.merge_leaves(parent, i, notify);
+        } else if i > 0 && fits(count(node.child_indexes[i - 1])) {
+            let prev_idx = LeafIdx(node.child_indexes[i - 1]);
+            let prev_count = count(prev_idx.0);
+            self.merge_leaves(parent, i - 1, notify);
+            cursor.leaf_idx = prev_idx;
+            cursor.elem_idx += prev_count;
+        } else { return; }
+
+        // The parent lost a child, so it might need merging too.
+        self.try_merge_node(parent, true);
+    }
+
+    /// A child has been removed from the node. If its children and the children of one of its
+    /// neighbours (with the same parent) fit in a single node with some room to spare, merge them
+    /// together and put the emptied node in the free pool. This continues up the tree. If the root is left with a single child, that child
+    /// becomes the new root.
+    ///
+    /// Any cursor's delta stays correct, since merged nodes share a parent.
+    fn try_merge_node(&mut self, node_idx: NodeIdx, children_are_leaves: bool) {
+        let count = |node: &ContentNode| node.child_indexes.iter().take_while(|i| **i != usize::MAX).count();
+        let node = &self.nodes[node_idx.0];
+        let a = count(node);
+        let parent = node.parent;
+
+        if parent.is_root() {
+            if a == 1 { self.remove_root(children_are_leaves); }
+            return;
+        }
+
+        let p = &self.nodes[parent.0];
+        let i = p.idx_of_child(node_idx.0);
+        let fits = |b: usize| a + b <= NODE_CHILDREN * 3 / 4;
+
+        if i + 1 < NODE_CHILDREN && p.child_indexes[i + 1] != usize::MAX
+            && fits(count(&self.nodes[p.child_indexes[i + 1]]))
+        {
+            self.merge_nodes(parent, i, children_are_leaves);
+        } else if i > 0 && fits(count(&self.nodes[p.child_indexes[i - 1]])) {
+            self.merge_nodes(parent, i - 1, children_are_leaves);
+        } else { return; }
+
+        self.try_merge_node(parent, false);
+    }
+
+    /// Move the children of the node at child i + 1 of parent to the end of the node at child i.
+    fn merge_nodes(&mut self, parent: NodeIdx, i: usize, children_are_leaves: bool) {
+        let p = &mut self.nodes[parent.0];
+        let left_idx = p.child_indexes[i];
+        let right_idx = p.child_indexes[i + 1];
+        p.set_width(i, p.width(i) + p.width(i + 1));
+        p.remove_child(i + 1);
+
+        let right = replace(&mut self.nodes[right_idx], ContentNode::new(self.free_node_pool_head));
+        self.free_node_pool_head = NodeIdx(right_idx);
+
+        let left = &mut self.nodes[left_idx];
+        let a = left.child_indexes.iter().take_while(|i| **i != usize::MAX).count();
+        let b = right.child_indexes.iter().take_while(|i| **i != usize::MAX).count();
+        left.child_indexes[a..a + b].copy_from_slice(&right.child_indexes[..b]);
+        left.child_cur[a..a + b].copy_from_slice(&right.child_cur[..b]);
+        left.child_end[a..a + b].copy_from_slice(&right.child_end[..b]);
+
+        for &child in &right.child_indexes[..b] {
+            if children_are_leaves {
+                self.leaves[child].parent = NodeIdx(left_idx);
+            } else {
+                self.nodes[child].parent = NodeIdx(left_idx);
+            }
+        }
+    }
+
+    /// The root node has a single child. Make that child the root, and shrink the tree's height.
+    fn remove_root(&mut self, children_are_leaves: bool) {
+        let old_root = self.root;
+        let child = self.nodes[old_root].child_indexes[0];
+        self.height -= 1;
+        self.root = child;
+
+        if children_are_leaves {
+            // We're back to a single leaf. Its always the first leaf, and every node is unused.
+            debug_assert_eq!(self.height, 0);
+            debug_assert_eq!(child, 0);
+            self.leaves[child].parent = NodeIdx::default();
+            self.nodes.clear();
+            self.free_node_pool_head = NodeIdx::default();
+        } else {
+            self.nodes[child].parent = NodeIdx::default();
+            self.nodes[old_root] = ContentNode::new(self.free_node_pool_head);
+            self.free_node_pool_head = NodeIdx(old_root);
+        }
+    }
+
+    /// Move the items in the leaf at child i + 1 of the node to the end of the leaf at child i.
+    fn merge_leaves<N>(&mut self, parent: NodeIdx, i: usize, notify: &mut N)
+        where N: FnMut(V, LeafIdx)
+    {
+        let node = &mut self.nodes[parent.0];
+        let left_idx = LeafIdx(node.child_indexes[i]);
+        let right_idx = LeafIdx(node.child_indexes[i + 1]);
+        node.set_width(i, node.width(i) + node.width(i + 1));
+        node.remove_child(i + 1);
+
+        let right = replace(&mut self.leaves[right_idx.0], ContentLeaf {
+            children: [V::none(); LEAF_CHILDREN],
+            next_leaf: self.free_leaf_pool_head,
+            parent: NodeIdx::default(),
+        });
+        self.free_leaf_pool_head = right_idx;
+
+        let left = &mut self.leaves[left_idx.0];
+        debug_assert_eq!(left.next_leaf, right_idx);
+        let a = left.children.iter().take_while(|c| c.exists()).count();
+        let b = right.children.iter().take_while(|c| c.exists()).count();
+        left.children[a..a + b].copy_from_slice(&right.children[..b]);
+        left.next_leaf = right.next_leaf;
+        for item in &right.children[..b] {
+            notify(*item, left_idx);
+        }
+    }
+
     // /// This function blindly assumes the item is definitely in the recursive children.
     // ///
     // /// Returns (child index, len_remaining).
@@ -908,17 +1245,18 @@ impl<V: Content> ContentTree<V> {
     ///
     /// Returns (child index, relative end pos of the index, len remaining).
     fn find_cur_pos_in_node(node: &ContentNode, mut at_cur_pos: usize) -> (usize, usize, usize) {
-        let mut end_pos_offset = 0;
-        for i in 0..NODE_CHILDREN {
-            let width = node.child_width[i];
-            // if at_cur_pos <= width.cur {
-            if at_cur_pos < width.cur {
-                return (node.child_indexes[i], end_pos_offset, at_cur_pos);
-            }
-            at_cur_pos -= width.cur;
-            end_pos_offset += width.end;
+        // First find the child by scanning the current widths. The end position is summed
+        // separately afterwards, which keeps this loop tight.
+        let mut i = 0;
+        loop {
+            let width = node.child_cur[i];
+            if at_cur_pos < width { break; }
+            at_cur_pos -= width;
+            i += 1;
+            if i >= NODE_CHILDREN { panic!("Position not in node"); }
         }
-        panic!("Position not in node");
+        let end_pos_offset = node.child_end[..i].iter().sum();
+        (node.child_indexes[i], end_pos_offset, at_cur_pos)
     }
 
     // /// Returns (index, offset).
@@ -1048,12 +1386,12 @@ impl<V: Content> ContentTree<V> {
 
         // This is always valid because there is always at least 1 leaf item, and its always
         // the first item in the tree.
-        ContentCursor::default().into()
+        ContentCursor { generation: self.generation, ..Default::default() }
     }
 
     pub fn cursor_at_start_nothing_emplaced(&self) -> ContentCursor {
         debug_assert!(self.cursor.is_none());
-        ContentCursor::default().into()
+        ContentCursor { generation: self.generation, ..Default::default() }
     }
 
     pub fn mut_cursor_at_start(&mut self) -> DeltaCursor {
@@ -1070,7 +1408,7 @@ impl<V: Content> ContentTree<V> {
     /// We never "stick end" - ie, the cursor is moved to the start of the next item with actual
     /// content.
     pub fn mut_cursor_before_cur_pos(&mut self, content_pos: usize) -> (usize, DeltaCursor) {
-        if let Some((pos, mut cursor)) = self.cursor.take() {
+        if let Some((pos, mut cursor)) = self.take_cached_cursor() {
             if let Some(mut pos) = pos {
                 if pos.cur == content_pos {
                     pos.end += self.slide_cursor_to_next_content(&mut cursor.0, &mut cursor.1);
@@ -1109,6 +1447,7 @@ impl<V: Content> ContentTree<V> {
                 leaf_idx: LeafIdx(idx),
                 elem_idx,
                 offset,
+                generation: self.generation,
             }, Default::default())
         )
     }
@@ -1119,6 +1458,7 @@ impl<V: Content> ContentTree<V> {
 
     pub(crate) fn emplace_cursor(&mut self, pos: LenPair, cursor: DeltaCursor) {
         assert!(self.cursor.is_none());
+        self.check_cursor(&cursor.0);
         self.cursor = Some((Some(pos), cursor));
 
         if cfg!(debug_assertions) {
@@ -1129,6 +1469,7 @@ impl<V: Content> ContentTree<V> {
 
     pub(crate) fn emplace_cursor_unknown(&mut self, cursor: DeltaCursor) {
         assert!(self.cursor.is_none());
+        self.check_cursor(&cursor.0);
         self.cursor = Some((None, cursor));
     }
 
@@ -1149,13 +1490,13 @@ impl<V: Content> ContentTree<V> {
 
         assert_ne!(elem_idx, usize::MAX, "Could not find element in leaf");
 
-        ContentCursor { leaf_idx, elem_idx, offset }
+        ContentCursor { leaf_idx, elem_idx, offset, generation: self.generation }
     }
 
     pub(crate) fn try_find_item(&mut self, id: V::Item) -> Option<DeltaCursor>
         where V: Searchable
     {
-        if let Some((_pos, cursor)) = self.cursor.take() {
+        if let Some((_pos, cursor)) = self.take_cached_cursor() {
             let leaf = &self[cursor.0.leaf_idx];
 
             for (elem_idx, e) in leaf.children.iter().enumerate() {
@@ -1166,6 +1507,7 @@ impl<V: Content> ContentTree<V> {
                             leaf_idx: cursor.0.leaf_idx,
                             elem_idx,
                             offset,
+                            generation: self.generation,
                         },
                         cursor.1
                     ));
@@ -1180,7 +1522,7 @@ impl<V: Content> ContentTree<V> {
     pub(crate) fn mut_cursor_before_item(&mut self, id: V::Item, leaf_idx: LeafIdx) -> (DeltaCursor, Option<LenPair>)
         where V: Searchable
     {
-        if let Some((mut pos, mut cursor)) = self.cursor.take() {
+        if let Some((mut pos, mut cursor)) = self.take_cached_cursor() {
             let (item, cur_offset) = cursor.0.get_item(self);
             if let Some(actual_offset) = item.get_offset(id) {
                 // The cursor already points to the item.
@@ -1244,6 +1586,78 @@ impl<V: Content> ContentTree<V> {
         (DeltaCursor(self.cursor_before_item(id, leaf_idx), LenUpdate::default()), None)
     }
 
+    /// Find the raw cursor for a [`PosCursor`]. If the tree has been modified since the cursor was
+    /// last used, its position is found again from the root.
+    ///
+    /// Returns None if the cursor is at (or past) the end of the current content.
+    fn resolve_pos_cursor(&mut self, c: &mut PosCursor) -> Option<ContentCursor> {
+        if let Some(cursor) = c.cursor {
+            if self.cursor_is_current(&cursor) { return Some(cursor); }
+        }
+
+        if c.pos >= self.total_len().cur { return None; }
+        let (_, dc) = self.mut_cursor_before_cur_pos(c.pos);
+        let cursor = dc.0;
+        dc.flush(self);
+        c.cursor = Some(cursor);
+        Some(cursor)
+    }
+
+    /// Get the item at the cursor, and the cursor's offset into it. Returns None at the end of the
+    /// content.
+    pub fn pos_cursor_get(&mut self, c: &mut PosCursor) -> Option<(V, usize)> {
+        let cursor = self.resolve_pos_cursor(c)?;
+        let (item, offset) = cursor.get_item(self);
+        Some((*item, offset))
+    }
+
+    /// Read the rest of the item at the cursor, and move the cursor to the next content. Items
+    /// which don't take up space in the current content are skipped. Returns None at the end of
+    /// the content.
+    pub fn pos_cursor_next(&mut self, c: &mut PosCursor) -> Option<V> {
+        let mut cursor = self.resolve_pos_cursor(c)?;
+        let (item, offset) = cursor.get_item(self);
+        let mut item = *item;
+        cursor.offset = item.len();
+        if offset > 0 { item.truncate_keeping_right(offset); }
+
+        c.pos += item.len();
+        c.cursor = if c.pos < self.total_len().cur {
+            self.slide_cursor_to_next_content(&mut cursor, &mut ());
+            Some(cursor)
+        } else { None };
+        Some(item)
+    }
+
+    /// Insert an item at the cursor. The cursor ends up before the content after the inserted
+    /// item. Raw cursors into the tree become stale, but other PosCursors are found again by
+    /// position when they're next used.
+    ///
+    /// Panics if the cursor is past the end of the content.
+    pub fn pos_cursor_insert<N>(&mut self, c: &mut PosCursor, item: V, notify: &mut N)
+        where N: FnMut(V, LeafIdx)
+    {
+        let len = self.total_len().cur;
+        assert!(c.pos <= len, "Cursor is past the end of the content");
+
+        let cursor = if c.pos < len {
+            self.resolve_pos_cursor(c).unwrap()
+        } else if len == 0 {
+            self.cursor_at_start()
+        } else {
+            // Insert right after the last content item.
+            let mut cursor = self.resolve_pos_cursor(&mut PosCursor::new(len - 1)).unwrap();
+            cursor.offset += 1;
+            cursor
+        };
+
+        let mut dc = DeltaCursor(cursor, Default::default());
+        self.insert(item, &mut dc, true, notify);
+        dc.flush(self);
+        c.pos += item.content_len_cur();
+        c.cursor = None;
+    }
+
     fn first_leaf(&self) -> LeafIdx {
         if cfg!(debug_assertions) {
             // dbg!(&self);
@@ -1294,6 +1708,11 @@ impl<V: Content> ContentTree<V> {
         self.iter().merge_spans()
     }
 
+    /// The number of leaves allocated by the tree. This is a cheap proxy for its memory usage.
+    pub(crate) fn num_leaves(&self) -> usize {
+        self.leaves.len()
+    }
+
     pub fn count_entries(&self) -> usize {
         let mut count = 0;
         for (_idx, children) in self.iter_leaves() {
@@ -1355,7 +1774,7 @@ impl<V: Content> ContentTree<V> {
                     assert!(replace(&mut delta, d).is_none());
                 }
 
-                let mut expect_child_size = node.child_width[i];
+                let mut expect_child_size = node.width(i);
                 expect_child_size.update_by(d.unwrap_or_default()); // The stored child width is wrong by d.
                 assert_eq!(actual_child_size, expect_child_size);
 
@@ -1367,6 +1786,14 @@ impl<V: Content> ContentTree<V> {
         }
     }
 
+    fn dbg_count_nodes(&self, idx: usize, height: usize) -> usize {
+        if height == self.height { return 0; }
+        1 + self.nodes[idx].child_indexes.iter()
+            .take_while(|i| **i != usize::MAX)
+            .map(|i| self.dbg_count_nodes(*i, height + 1))
+            .sum::<usize>()
+    }
+
     fn dbg_check_walk(&self) {
         let (actual_len, last_next_ptr, delta) = self.dbg_check_walk_internal(self.root, 0, LeafIdx(0), NodeIdx(usize::MAX));
         // dbg!(actual_len, delta, self.total_len);
@@ -1420,15 +1847,24 @@ impl<V: Content> ContentTree<V> {
             }
             leaf_idx = leaf.next_leaf;
         }
-        assert_eq!(leaves_visited, self.leaves.len());
 
-        // let mut leaf_pool_size = 0;
-        // let mut i = self.free_leaf_pool_head;
-        // while i.0 != usize::MAX {
-        //     leaf_pool_size += 1;
-        //     i = self.leaves[i.0].next_leaf;
-        // }
-        // assert_eq!(leaves_visited + leaf_pool_size, self.leaves.len());
+        let mut leaf_pool_size = 0;
+        let mut i = self.free_leaf_pool_head;
+        while i.0 != usize::MAX {
+            assert!(!self.leaves[i.0].children[0].exists());
+            leaf_pool_size += 1;
+            i = self.leaves[i.0].next_leaf;
+        }
+        assert_eq!(leaves_visited + leaf_pool_size, self.leaves.len());
+
+        let mut node_pool_size = 0;
+        let mut i = self.free_node_pool_head;
+        while !i.is_root() {
+            assert_eq!(self.nodes[i.0].child_indexes[0], usize::MAX);
+            node_pool_size += 1;
+            i = self.nodes[i.0].parent;
+        }
+        assert_eq!(self.dbg_count_nodes(self.root, 0) + node_pool_size, self.nodes.len());
         //
         // if self.height == 0 {
         //     assert!(self.root < self.leaves.len());
@@ -1504,6 +1940,55 @@ impl<'a, V: Content> Iterator for ContentLeafIter<'a, V> {
 }
 
 
+/// A content tree full of small items, for benchmarking how quickly cursors are made at different
+/// positions. This is the hot path when merging concurrent changes.
+#[cfg(feature = "expose_benchmarking")]
+pub struct DbgBenchCursors {
+    tree: ContentTree<crate::listmerge::yjsspan::CRDTSpan>,
+    positions: Vec<usize>,
+}
+
+#[cfg(feature = "expose_benchmarking")]
+impl DbgBenchCursors {
+    pub fn new(num_items: usize) -> Self {
+        use crate::listmerge::yjsspan::{CRDTSpan, DELETED_ONCE, INSERTED};
+        use crate::LV;
+
+        let mut tree = ContentTree::new();
+        let mut cursor = tree.mut_cursor_at_start();
+        for i in 0..num_items {
+            // The gaps between the IDs stop the items from being merged together.
+            let start = (i * 8) as LV;
+            let item = CRDTSpan {
+                id: (start..start + (1 + i % 4) as LV).into(),
+                origin_left: LV::MAX,
+                origin_right: LV::MAX,
+                // Deleted items have no current length, so they're skipped over by the search.
+                current_state: if i % 3 == 0 { DELETED_ONCE } else { INSERTED },
+                end_state_ever_deleted: false,
+            };
+            tree.insert(item, &mut cursor, false, &mut |_, _| {});
+        }
+        cursor.flush(&mut tree);
+
+        let len = tree.total_len().cur;
+        let positions = (0..1000).map(|i| i * 7919 % len).collect();
+        Self { tree, positions }
+    }
+
+    /// Make a cursor at each of the benchmark's 1000 positions. Returns the sum of their end
+    /// positions.
+    pub fn run(&mut self) -> usize {
+        let mut result = 0;
+        for &pos in &self.positions {
+            let (end_pos, cursor) = self.tree.mut_cursor_before_cur_pos(pos);
+            cursor.flush(&mut self.tree);
+            result += end_pos;
+        }
+        result
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::fmt::Debug;
@@ -1518,9 +2003,10 @@ mod test {
     use rle::{HasLength, HasRleKey, MergableSpan, SplitableSpan, SplitableSpanHelpers};
 
     use crate::list_fuzzer_tools::fuzz_multithreaded;
+    use crate::LV;
     use crate::ost::{LeafIdx, LenPair};
 
-    use super::{Content, ContentTree, DeltaCursor};
+    use super::{Content, ContentTree, DeltaCursor, PosCursor};
 
     /// This is a simple span object for testing.
     #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -1584,8 +2070,8 @@ mod test {
     }
 
     impl HasRleKey for TestRange {
-        fn rle_key(&self) -> usize {
-            self.id as usize
+        fn rle_key(&self) -> LV {
+            self.id as LV
         }
     }
 
@@ -1653,6 +2139,165 @@ mod test {
         ].into_iter()));
     }
 
+    #[test]
+    fn cursor_generations() {
+        let mut tree: ContentTree<TestRange> = ContentTree::new();
+        let mut cursor = tree.mut_cursor_at_start();
+        tree.insert(TestRange { id: 0, len: 10, is_activated: true, exists: true }, &mut cursor, true, &mut null_notify);
+        cursor.flush(&mut tree);
+
+        let a = tree.mut_cursor_before_cur_pos(5).1.0;
+        let mut b = tree.mut_cursor_before_cur_pos(2).1;
+        assert!(tree.cursor_is_current(&a) && tree.cursor_is_current(&b.0));
+
+        // Inserting through b splits the item a points into. b is updated, but a is now stale.
+        tree.insert(TestRange { id: 100, len: 1, is_activated: true, exists: true }, &mut b, true, &mut null_notify);
+        assert!(tree.cursor_is_current(&b.0));
+        assert!(!tree.cursor_is_current(&a));
+        assert_eq!(b.0.get_item(&tree).0.id, 100);
+        b.flush(&mut tree);
+        tree.dbg_check();
+
+        if cfg!(debug_assertions) {
+            let result = std::panic::catch_unwind(|| { a.get_item(&tree); });
+            assert!(result.is_err());
+        }
+
+        // A stale cached cursor is thrown away, and the position is found from the root instead.
+        tree.cursor = Some((None, DeltaCursor(a, Default::default())));
+        let (_, c) = tree.mut_cursor_before_cur_pos(5);
+        assert!(tree.cursor_is_current(&c.0));
+        let (item, offset) = c.0.get_item(&tree);
+        assert_eq!((item.id, offset), (2, 2));
+        assert!(tree.cursor.is_none());
+
+        // A cached cursor's delta is flushed before the tree is modified through another cursor.
+        let (_, mut c) = tree.mut_cursor_before_cur_pos(8);
+        tree.insert(TestRange { id: 200, len: 3, is_activated: true, exists: true }, &mut c, true, &mut null_notify);
+        tree.emplace_cursor_unknown(c);
+        let (_, mut d) = tree.mut_cursor_before_cur_pos(1);
+        // d doesn't come from the cache, because the cached cursor isn't at a known position.
+        tree.insert(TestRange { id: 300, len: 1, is_activated: true, exists: true }, &mut d, true, &mut null_notify);
+        d.flush(&mut tree);
+        tree.dbg_check();
+        assert_eq!(tree.total_len().cur, 15);
+    }
+
+    #[test]
+    fn pos_cursors_survive_edits() {
+        let mut tree: ContentTree<TestRange> = ContentTree::new();
+        let mut a = PosCursor::new(0);
+        tree.pos_cursor_insert(&mut a, TestRange { id: 0, len: 10, is_activated: true, exists: true }, &mut null_notify);
+        assert_eq!(a.pos(), 10);
+        // Appending at the end of the content.
+        tree.pos_cursor_insert(&mut a, TestRange { id: 50, len: 2, is_activated: true, exists: true }, &mut null_notify);
+        tree.dbg_check();
+
+        let mut b = PosCursor::new(5);
+        let mut c = PosCursor::new(7);
+        assert_eq!(tree.pos_cursor_get(&mut b).map(|(e, offset)| (e.id, offset)), Some((0, 5)));
+        assert_eq!(tree.pos_cursor_get(&mut c).map(|(e, offset)| (e.id, offset)), Some((0, 7)));
+
+        // Inserting through b leaves c stale. Instead of panicking, its found again at the same
+        // position (which now points to different content).
+        tree.pos_cursor_insert(&mut b, TestRange { id: 100, len: 1, is_activated: true, exists: true }, &mut null_notify);
+        tree.pos_cursor_insert(&mut b, TestRange { id: 200, len: 4, is_activated: false, exists: true }, &mut null_notify);
+        tree.dbg_check();
+        assert_eq!(b.pos(), 6);
+        assert_eq!(tree.pos_cursor_get(&mut c).map(|(e, offset)| (e.id, offset)), Some((5, 1)));
+
+        // Iterating skips items which aren't in the current content.
+        let mut iter = PosCursor::new(4);
+        let mut items = vec![];
+        while let Some(e) = tree.pos_cursor_next(&mut iter) {
+            items.push((e.id, e.len));
+        }
+        assert_eq!(items, [(4, 1), (100, 1), (5, 5), (50, 2)]);
+        assert_eq!(iter.pos(), 13);
+        assert_eq!(tree.pos_cursor_get(&mut iter), None);
+    }
+
+    #[test]
+    fn merged_leaves_are_reused() {
+        let mut tree: ContentTree<TestRange> = ContentTree::new();
+        let mut cursor = tree.mut_cursor_at_start();
+        let mut id = 0;
+        let mut insert_items = |tree: &mut ContentTree<TestRange>, cursor: &mut DeltaCursor, n: u32| {
+            for i in 0..n {
+                // Alternating items can't be merged together.
+                tree.insert(TestRange { id, len: 1, is_activated: i % 2 == 0, exists: true }, cursor, true, &mut null_notify);
+                id += 1;
+            }
+        };
+        insert_items(&mut tree, &mut cursor, 200);
+        cursor.flush(&mut tree);
+        tree.dbg_check();
+        let leaves_before = tree.iter_leaves().count();
+
+        // Activating everything lets items merge together, so leaves end up mostly empty. Items
+        // are only merged with the items after them, so this takes 2 passes.
+        for _ in 0..2 {
+            let mut cursor = tree.mut_cursor_at_start();
+            while cursor.roll_next_item(&mut tree) {
+                tree.mutate_entry(&mut cursor, usize::MAX, &mut null_notify, |e| e.is_activated = true);
+            }
+            cursor.flush(&mut tree);
+            tree.dbg_check();
+        }
+        assert!(tree.iter_leaves().count() < leaves_before);
+        assert!(tree.free_leaf_pool_head.exists());
+
+        // New leaves come from the pool before the leaves list grows again.
+        let mut cursor = tree.mut_cursor_at_start();
+        insert_items(&mut tree, &mut cursor, 400);
+        cursor.flush(&mut tree);
+        tree.dbg_check();
+        assert!(!tree.free_leaf_pool_head.exists());
+        assert_eq!(tree.iter_leaves().count(), tree.leaves.len());
+    }
+
+    #[test]
+    fn merged_nodes_are_reused() {
+        let mut tree: ContentTree<TestRange> = ContentTree::new();
+        let mut id = 0;
+        let mut first_peak = None;
+
+        for _cycle in 0..5 {
+            // Alternating items can't be merged together, so this builds a tree with a few levels
+            // of internal nodes.
+            let mut cursor = tree.mut_cursor_at_start();
+            for i in 0..3000 {
+                tree.insert(TestRange { id, len: 1, is_activated: i % 2 == 0, exists: true }, &mut cursor, true, &mut null_notify);
+                id += 1;
+            }
+            cursor.flush(&mut tree);
+            tree.dbg_check();
+            assert!(tree.height >= 2);
+
+            // Without recycling, each cycle would add as many nodes as the first one did. A few
+            // items are left over each cycle (items in different leaves aren't merged), so allow
+            // a little growth.
+            let first_peak = *first_peak.get_or_insert(tree.nodes.len());
+            assert!(tree.nodes.len() <= first_peak * 11 / 10, "{} nodes, first peak {first_peak}", tree.nodes.len());
+
+            // Activating everything merges the items, then the leaves, then the internal nodes.
+            // Items are only merged with their neighbours in the same leaf, so this takes a few
+            // passes.
+            let mut entries = usize::MAX;
+            loop {
+                let mut cursor = tree.mut_cursor_at_start();
+                while cursor.roll_next_item(&mut tree) {
+                    tree.mutate_entry(&mut cursor, usize::MAX, &mut null_notify, |e| e.is_activated = true);
+                }
+                cursor.flush(&mut tree);
+                tree.dbg_check();
+                if tree.count_entries() == entries { break; }
+                entries = tree.count_entries();
+            }
+            assert!(tree.count_entries() < 20);
+        }
+    }
+
     #[test]
     fn replace_item() {
         let mut tree: ContentTree<TestRange> = ContentTree::new();
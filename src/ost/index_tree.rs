@@ -10,7 +10,7 @@ use std::fmt::Debug;
 use std::mem;
 use std::ops::{Index, IndexMut, Range};
 use rle::{HasLength, RleDRun};
-use crate::{DTRange, LV};
+use crate::{DTRange, lv_to_usize, LV};
 use crate::ost::{LEAF_CHILDREN, LeafIdx, NODE_CHILDREN, NodeIdx, remove_from_array, remove_from_array_fill};
 
 #[derive(Debug, Clone)]
@@ -75,7 +75,7 @@ fn initial_root_leaf<V: Default + Copy>() -> IndexLeaf<V> {
     // The tree is initialized with V::Default covering the entire range. This means we don't need
     // to have any special handling for the size of the tree. Set operations "carve out" their
     // specified value.
-    let mut bounds = [usize::MAX; LEAF_CHILDREN];
+    let mut bounds = [LV::MAX; LEAF_CHILDREN];
     bounds[0] = 0;
 
     IndexLeaf {
@@ -87,14 +87,14 @@ fn initial_root_leaf<V: Default + Copy>() -> IndexLeaf<V> {
     }
 }
 
-const EMPTY_NODE_CHILD: NodeChild = (usize::MAX, usize::MAX);
+const EMPTY_NODE_CHILD: NodeChild = (LV::MAX, usize::MAX);
 
 impl<V: Copy> IndexLeaf<V> {
     #[inline(always)]
     fn has_space(&self, space_wanted: usize) -> bool {
         if space_wanted == 0 { return true; }
         debug_assert!(space_wanted < LEAF_CHILDREN);
-        self.bounds[LEAF_CHILDREN - space_wanted] == usize::MAX
+        self.bounds[LEAF_CHILDREN - space_wanted] == LV::MAX
 
         // We could alternately write this:
         // self.bounds[LEAF_CHILDREN.wrapping_sub(space_wanted) % LEAF_CHILDREN] == usize::MAX
@@ -104,7 +104,7 @@ impl<V: Copy> IndexLeaf<V> {
     fn is_last(&self) -> bool { !self.next_leaf.exists() }
 
     fn remove_children(&mut self, del_range: Range<usize>) {
-        remove_from_array_fill(&mut self.bounds, del_range.clone(), usize::MAX);
+        remove_from_array_fill(&mut self.bounds, del_range.clone(), LV::MAX);
         remove_from_array(&mut self.children, del_range.clone());
     }
 }
@@ -171,14 +171,14 @@ pub trait IndexContent: Debug + Copy {
 
 fn split_rle<V: IndexContent>(val: RleDRun<V>, offset: usize) -> (RleDRun<V>, RleDRun<V>) {
     debug_assert!(offset > 0);
-    debug_assert!(offset < (val.end - val.start));
+    debug_assert!(offset < val.len());
 
     (RleDRun {
         start: val.start,
-        end: val.start + offset,
+        end: val.start + offset as LV,
         val: val.val,
     }, RleDRun {
-        start: val.start + offset,
+        start: val.start + offset as LV,
         end: val.end,
         val: val.val.at_offset(offset),
     })
@@ -205,7 +205,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
         self.leaves.push(initial_root_leaf());
     }
 
-    fn create_new_root_node(&mut self, lower_bound: usize, child_a: usize, split_point: LV, child_b: usize) -> NodeIdx {
+    fn create_new_root_node(&mut self, lower_bound: LV, child_a: usize, split_point: LV, child_b: usize) -> NodeIdx {
         self.height += 1;
         let mut new_root = IndexNode {
             children: [EMPTY_NODE_CHILD; NODE_CHILDREN],
@@ -362,7 +362,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
         // debug_assert!(old_leaf.data.last().unwrap().is_some());
 
         let mut new_leaf = IndexLeaf {
-            bounds: [usize::MAX; LEAF_CHILDREN],
+            bounds: [LV::MAX; LEAF_CHILDREN],
             children: [V::default(); LEAF_CHILDREN],
             // upper_bound: old_leaf.upper_bound,
             next_leaf: old_leaf.next_leaf,
@@ -376,7 +376,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
 
         // The old leaf's new bound is the first copied item's position.
         // old_leaf.upper_bound = split_lv;
-        old_leaf.bounds[LEAF_SPLIT_POINT..].fill(usize::MAX);
+        old_leaf.bounds[LEAF_SPLIT_POINT..].fill(LV::MAX);
 
         // Ignore any danging children in release mode. They don't matter.
         if cfg!(debug_assertions) {
@@ -443,7 +443,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
         // There are much faster ways to write this using SIMD.
         leaf.bounds[1..].iter()
             // We're looking for the first item past the needle.
-            .position(|bound| *bound == usize::MAX || needle < *bound)
+            .position(|bound| *bound == LV::MAX || needle < *bound)
             .unwrap_or(LEAF_CHILDREN - 1)
     }
 
@@ -456,7 +456,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
     #[inline]
     fn leaf_upper_bound_2(leaves: &Vec<IndexLeaf<V>>, leaf: &IndexLeaf<V>) -> LV {
         if leaf.is_last() {
-            usize::MAX
+            LV::MAX
         } else {
             leaves[leaf.next_leaf.0].bounds[0]
         }
@@ -468,7 +468,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
         let lower_bound = leaf.bounds[cursor.elem_idx];
 
         let next = cursor.elem_idx + 1;
-        let upper_bound = if next < LEAF_CHILDREN && leaf.bounds[next] != usize::MAX {
+        let upper_bound = if next < LEAF_CHILDREN && leaf.bounds[next] != LV::MAX {
             leaf.bounds[next]
         } else {
             self.leaf_upper_bound(leaf)
@@ -485,7 +485,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
     fn cursor_to_next(&self, cursor: &mut IndexCursor) {
         let leaf = &self.leaves[cursor.leaf_idx.0];
         let next_idx = cursor.elem_idx + 1;
-        if next_idx >= LEAF_CHILDREN || leaf.bounds[next_idx] == usize::MAX {
+        if next_idx >= LEAF_CHILDREN || leaf.bounds[next_idx] == LV::MAX {
             cursor.elem_idx = 0;
             cursor.leaf_idx = leaf.next_leaf;
         } else {
@@ -495,7 +495,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
 
     /// Generate a cursor which points at the specified LV.
     fn cursor_at(&self, lv: LV) -> IndexCursor {
-        debug_assert!(lv < usize::MAX);
+        debug_assert!(lv < LV::MAX);
 
         let (cursor_lv, cursor) = self.cursor.get();
         if cursor_lv == lv {
@@ -587,7 +587,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
         let lower_bound = leaf.bounds[cursor.elem_idx];
 
         let next_elem = cursor.elem_idx + 1;
-        let upper_bound = if next_elem >= LEAF_CHILDREN || leaf.bounds[next_elem] == usize::MAX {
+        let upper_bound = if next_elem >= LEAF_CHILDREN || leaf.bounds[next_elem] == LV::MAX {
             self.leaf_upper_bound(leaf)
         } else {
             leaf.bounds[next_elem]
@@ -644,14 +644,14 @@ impl<V: Default + IndexContent> IndexTree<V> {
             // Check the bounds
             let mut prev = leaf.bounds[0];
             for &b in &leaf.bounds[1..elem_idx] {
-                if b != usize::MAX {
+                if b != LV::MAX {
                     assert!(b > prev, "Bounds does not monotonically increase b={:?}", &leaf.bounds);
                 }
                 prev = b;
             }
         }
 
-        if elem_idx >= LEAF_CHILDREN || leaf.bounds[elem_idx] == usize::MAX {
+        if elem_idx >= LEAF_CHILDREN || leaf.bounds[elem_idx] == LV::MAX {
             // The cat is already out of the bag. Continue trimming after this leaf.
             return end > leaf_upper_bound;
         }
@@ -679,7 +679,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
 
             // Ugh this is so gross. If we hit the last in-use item, the bound is
             // leaf_upper_bound and stop after this one.
-            if b == usize::MAX {
+            if b == LV::MAX {
                 b = leaf_upper_bound;
             }
 
@@ -691,7 +691,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
                     // Trim the current item and stop here.
                     // let b = b.min(leaf_upper_bound);
                     debug_assert!(leaf.bounds[del_to] < end);
-                    leaf.children[del_to] = leaf.children[del_to].at_offset(end - leaf.bounds[del_to]);
+                    leaf.children[del_to] = leaf.children[del_to].at_offset(lv_to_usize(end - leaf.bounds[del_to]));
                     leaf.bounds[del_to] = end;
                     // stop_here = true;
                     break;
@@ -708,12 +708,12 @@ impl<V: Default + IndexContent> IndexTree<V> {
             }
 
             // Bleh!
-            if next < LEAF_CHILDREN && leaf.bounds[next] == usize::MAX { break; }
+            if next < LEAF_CHILDREN && leaf.bounds[next] == LV::MAX { break; }
         }
 
-        if del_to >= LEAF_CHILDREN || leaf.bounds[del_to] == usize::MAX {
+        if del_to >= LEAF_CHILDREN || leaf.bounds[del_to] == LV::MAX {
             // Delete the rest of this leaf and bubble up.
-            leaf.bounds[elem_idx..].fill(usize::MAX);
+            leaf.bounds[elem_idx..].fill(LV::MAX);
             end > leaf_upper_bound
         } else {
             let trimmed_items = del_to - elem_idx;
@@ -728,7 +728,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
         }
     }
 
-    fn upper_bound_scan(&self, mut idx: usize, mut height: usize) -> usize {
+    fn upper_bound_scan(&self, mut idx: usize, mut height: usize) -> LV {
         while height > 0 {
             // Descend to the last child of this item.
             let node = &self.nodes[idx];
@@ -780,7 +780,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
         if keep_elem_idx >= 1 {
             leaf.remove_children(0..keep_elem_idx);
         }
-        leaf.children[0] = leaf.children[0].at_offset(end - leaf.bounds[0]);
+        leaf.children[0] = leaf.children[0].at_offset(lv_to_usize(end - leaf.bounds[0]));
         leaf.bounds[0] = end;
 
         if cfg!(debug_assertions) {
@@ -936,7 +936,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
         // let mut leaf = &mut self.leaves[leaf_idx.0];
         let (mut leaf, mut leaf_upper_bound) = self.get_leaf_and_bound(leaf_idx);
 
-        debug_assert!(leaf.bounds[elem_idx] != usize::MAX);
+        debug_assert!(leaf.bounds[elem_idx] != LV::MAX);
         debug_assert!(start >= leaf.bounds[0] || leaf_idx.0 == 0);
         debug_assert!(start < leaf_upper_bound);
         // debug_assert!(elem_idx == LEAF_CHILDREN - 1 || start < leaf.bounds[elem_idx + 1]);
@@ -953,7 +953,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
             // perf.
             let prev_idx = elem_idx - 1;
             let prev_start = leaf.bounds[prev_idx];
-            if leaf.children[prev_idx].try_append(cur_start - prev_start, &data, end - start) {
+            if leaf.children[prev_idx].try_append(lv_to_usize(cur_start - prev_start), &data, lv_to_usize(end - start)) {
                 // Ok!
                 self.extend_upper_range(leaf_idx, elem_idx, end);
 
@@ -961,7 +961,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
                 // cursor always points to the *next* element, we'll roll the cursor forward in this
                 // case here.
                 let leaf = &self.leaves[leaf_idx.0];
-                if leaf.bounds[elem_idx] == usize::MAX {
+                if leaf.bounds[elem_idx] == LV::MAX {
                     // println!("A1");
                     return (IndexCursor { leaf_idx: leaf.next_leaf, elem_idx: 0}, false);
                 } else {
@@ -979,13 +979,13 @@ impl<V: Default + IndexContent> IndexTree<V> {
         } else {
             // This is pretty gnarly.
             let b = leaf.bounds[elem_idx + 1];
-            if b == usize::MAX { leaf_upper_bound } else { b }
+            if b == LV::MAX { leaf_upper_bound } else { b }
         };
 
         // If we can append the item to the current item, do that.
         if cur_start < start {
             let mut d = leaf.children[elem_idx];
-            if d.try_append(start - cur_start, &data, end - start) {
+            if d.try_append(lv_to_usize(start - cur_start), &data, lv_to_usize(end - start)) {
                 data = d;
                 start = cur_start;
             }
@@ -995,7 +995,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
 
         if end < cur_end {
             // Try to append the end of the current element.
-            if data.try_append(end - start, &leaf.children[elem_idx].at_offset(end - cur_start), cur_end - end) {
+            if data.try_append(lv_to_usize(end - start), &leaf.children[elem_idx].at_offset(lv_to_usize(end - cur_start)), lv_to_usize(cur_end - end)) {
                 // Nice. We'll handle this in the special case below.
                 end = cur_end;
                 end_is_end = false;
@@ -1020,7 +1020,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
                     leaf.children[elem_idx + 1] = data;
                     leaf.bounds[elem_idx + 2] = end;
                     // This will be a no-op for many types of data because of the memcpy.
-                    leaf.children[elem_idx + 2] = leaf.children[elem_idx].at_offset(end - cur_start);
+                    leaf.children[elem_idx + 2] = leaf.children[elem_idx].at_offset(lv_to_usize(end - cur_start));
 
                     // We modified elem_idx +1 and +2, so we can't have modified index 0. No parent update.
                     // println!("b");
@@ -1041,7 +1041,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
                     assert!(elem_idx + 1 < LEAF_CHILDREN);
                     leaf.children[elem_idx] = data;
                     leaf.bounds[elem_idx + 1] = end;
-                    leaf.children[elem_idx + 1] = leaf.children[elem_idx + 1].at_offset(end - start);
+                    leaf.children[elem_idx + 1] = leaf.children[elem_idx + 1].at_offset(lv_to_usize(end - start));
 
                     // Since start == lower bound, the parents won't need updating.
                     // println!("c");
@@ -1087,7 +1087,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
 
             // Alternately, we could just use make_space_in_leaf here - though it would need to be
             // adjusted to allow the elem_idx to be = LEAF_CHILDREN.
-            if elem_idx >= LEAF_CHILDREN || leaf.bounds[elem_idx] == usize::MAX {
+            if elem_idx >= LEAF_CHILDREN || leaf.bounds[elem_idx] == LV::MAX {
                 // This is the end of the leaf node.
                 // leaf.upper_bound = start;
 
@@ -1128,7 +1128,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
                 leaf_upper_bound
             } else {
                 let b = leaf.bounds[elem_idx + 1];
-                if b == usize::MAX { leaf_upper_bound } else { b }
+                if b == LV::MAX { leaf_upper_bound } else { b }
             };
 
             leaf.bounds[elem_idx] = start;
@@ -1143,7 +1143,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
 
             if end < cur_end {
                 // Try to prepend the new item to the start of the existing item.
-                if data.try_append(cur_start - start, &leaf.children[elem_idx], cur_end - cur_start) {
+                if data.try_append(lv_to_usize(cur_start - start), &leaf.children[elem_idx], lv_to_usize(cur_end - cur_start)) {
                     // Ok!
                     leaf.children[elem_idx] = data;
                     // println!("e");
@@ -1153,7 +1153,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
                     leaf = &mut self.leaves[leaf_idx.0];
                     leaf.children[elem_idx] = data;
                     leaf.bounds[elem_idx + 1] = end;
-                    leaf.children[elem_idx + 1] = leaf.children[elem_idx + 1].at_offset(end - cur_start);
+                    leaf.children[elem_idx + 1] = leaf.children[elem_idx + 1].at_offset(lv_to_usize(end - cur_start));
                     // println!("f");
                     return (IndexCursor { leaf_idx, elem_idx }, end_is_end);
                 }
@@ -1238,11 +1238,11 @@ impl<V: Default + IndexContent> IndexTree<V> {
             }
 
             let mut finished = false;
-            let mut prev_start = usize::MAX;
+            let mut prev_start = LV::MAX;
             for &(start, child_idx) in &node.children {
                 if child_idx == usize::MAX { finished = true; }
                 else {
-                    assert!(prev_start == usize::MAX || prev_start < start, "prev_start {prev_start} / start {start}");
+                    assert!(prev_start == LV::MAX || prev_start < start, "prev_start {prev_start} / start {start}");
                     prev_start = start;
 
                     assert_eq!(finished, false);
@@ -1260,7 +1260,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
             assert_eq!(leaf.parent, expect_parent);
 
             // We check that the first child is in use below.
-            if leaf.bounds[0] != usize::MAX {
+            if leaf.bounds[0] != LV::MAX {
                 if let Some(expect_start) = expect_start {
                     assert_eq!(leaf.bounds[0], expect_start);
                 }
@@ -1291,18 +1291,18 @@ impl<V: Default + IndexContent> IndexTree<V> {
 
             if leaf_idx == self.first_leaf() {
                 // First leaf. This can be empty - but only if the whole data structure is empty.
-                if leaf.bounds[0] == usize::MAX {
+                if leaf.bounds[0] == LV::MAX {
                     assert!(!leaf.next_leaf.exists());
                 }
             } else {
-                assert_ne!(leaf.bounds[0], usize::MAX, "Only the first leaf can be empty");
+                assert_ne!(leaf.bounds[0], LV::MAX, "Only the first leaf can be empty");
             }
 
             // Make sure the bounds are all sorted.
             let mut prev = leaf.bounds[0];
             let mut finished = false;
             for &b in &leaf.bounds[1..] {
-                if b == usize::MAX {
+                if b == LV::MAX {
                     finished = true;
                 } else {
                     assert!(b > prev, "Bounds does not monotonically increase b={:?}", &leaf.bounds);
@@ -1366,13 +1366,13 @@ impl<V: Default + IndexContent> IndexTree<V> {
 
                 // Trim the start of actual_next
                 if actual.start < expect.start {
-                    (_, actual) = split_rle(actual, expect.start - actual.start);
+                    (_, actual) = split_rle(actual, lv_to_usize(expect.start - actual.start));
                 } else if expect.start < actual.start {
                     panic!("Missing element");
                 }
 
                 assert_eq!(actual.start, expect.start);
-                let r = DTRange { start: actual.start, end: actual.start + usize::min(actual.len(), expect.len()) };
+                let r = actual.start..actual.start + usize::min(actual.len(), expect.len()) as LV;
                 assert!(expect.val.eq(&actual.val, usize::min(actual.len(), expect.len())),
                         "at {:?}: expect {:?} != actual {:?} (len={})", r, &expect.val, &actual.val, usize::min(actual.len(), expect.len()));
                 // assert_eq!(expect.val, actual.val, "{:?}", &tree_iter);
@@ -1387,7 +1387,7 @@ impl<V: Default + IndexContent> IndexTree<V> {
                 } else {
                     // actual.end < expect.end
                     // Keep the rest of expect for the next iteration.
-                    (_, expect) = split_rle(expect, actual.end - expect.start);
+                    (_, expect) = split_rle(expect, lv_to_usize(actual.end - expect.start));
                     debug_assert_eq!(expect.start, actual.end);
                     // And continue with this expected item.
                 }
@@ -1429,7 +1429,7 @@ impl<'a, V: Copy> Iterator for IndexTreeIter<'a, V> {
 
         let data = leaf.children[self.elem_idx].clone();
         let start = leaf.bounds[self.elem_idx];
-        if start == usize::MAX {
+        if start == LV::MAX {
             // This will happen when the tree is empty.
             debug_assert_eq!(self.elem_idx, 0);
             debug_assert_eq!(self.leaf_idx.0, 0);
@@ -1439,12 +1439,12 @@ impl<'a, V: Copy> Iterator for IndexTreeIter<'a, V> {
         self.elem_idx += 1;
 
         let end = 'block: {
-            if self.elem_idx >= LEAF_CHILDREN || leaf.bounds[self.elem_idx] == usize::MAX {
+            if self.elem_idx >= LEAF_CHILDREN || leaf.bounds[self.elem_idx] == LV::MAX {
                 // Try to move to the next leaf.
                 self.leaf_idx = leaf.next_leaf;
                 // if self.leaf_idx.0 == usize::MAX {
                 if self.leaf_idx.0 >= self.tree.leaves.len() {
-                    break 'block usize::MAX;
+                    break 'block LV::MAX;
                 }
                 self.elem_idx = 0;
 
@@ -1610,7 +1610,7 @@ mod test {
             eprintln!("i: {i}");
             let mut tree = IndexTree::new();
             for base in 0..i {
-                tree.set_range((base*3..base*3+2).into(), X(base + 100));
+                tree.set_range((base*3..base*3+2).into(), X(lv_to_usize(base) + 100));
             }
             // dbg!(tree.iter().collect::<Vec<_>>());
 
@@ -1624,7 +1624,7 @@ mod test {
             tree.dbg_check_eq(&[
                 RleDRun::new(0..1, X(100)),
                 RleDRun::new(1..ceil, X(99)),
-                RleDRun::new(ceil..ceil+1, X(i - 1 + 100 + 1)),
+                RleDRun::new(ceil..ceil+1, X(lv_to_usize(i) - 1 + 100 + 1)),
             ]);
         }
     }
@@ -1667,14 +1667,14 @@ mod test {
 
             let mut val = RleDRun {
                 val: *val,
-                start,
-                end: start + 1,
+                start: start as LV,
+                end: start as LV + 1,
             };
 
             loop {
                 let Some((_, peek_next)) = self.0.clone().next() else { return Some(val); };
 
-                if val.val.try_append(val.end - val.start, peek_next, 1) {
+                if val.val.try_append(val.len(), peek_next, 1) {
                     // Great! Consume from the actual iterator and keep going.
                     self.0.next();
                 } else {
@@ -1716,7 +1716,7 @@ mod test {
             //     dbg!(val, start, len);
             //     dbg!(tree.iter().collect::<Vec<_>>());
             // }
-            tree.set_range((start..start+len).into(), X(val));
+            tree.set_range((start as LV..(start+len) as LV).into(), X(val));
             // dbg!(&tree);
             tree.dbg_check();
 
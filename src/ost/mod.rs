@@ -1,5 +1,5 @@
-//! This module exists as a future planned replacement for the content-tree crate. It has a few
-//! advantages:
+//! The b-trees used while merging. These replaced the content-tree crate (and the older
+//! pointer-based marker / range trees). They have a few advantages:
 //!
 //! - I have two separate data structures, one for the index and one for content. Content-tree uses
 //!   the same b-tree data structure for both
@@ -0,0 +1,121 @@
+//! Standalone registers storing a single primitive value. Concurrent writes are all kept (like a
+//! multi-value register), and one of them is deterministically picked as the winner so the register
+//! can also be read like a last-writer-wins register.
+//!
+//! Registers are created by setting a map key to `CreateValue::NewCRDT(CRDTKind::Register)`.
+
+use std::cmp::Ordering;
+use crate::{AgentId, CreateValue, LV, LVKey, OpLog, Primitive, RegisterInfo, RegisterValue};
+
+fn expect_primitive(val: RegisterValue) -> Primitive {
+    match val {
+        RegisterValue::Primitive(p) => p,
+        RegisterValue::OwnedCRDT(..) => unreachable!("Registers only store primitive values"),
+    }
+}
+
+impl OpLog {
+    /// Set the value of the register `crdt`.
+    ///
+    /// Panics if the register doesn't exist.
+    pub fn local_register_set(&mut self, agent: AgentId, crdt: LVKey, value: Primitive) -> LV {
+        assert!(self.registers.contains_key(&crdt), "Missing register");
+        let v = self.cg.assign_local_op(agent, 1).start;
+        self.push_register_op(crdt, v, value);
+        v
+    }
+
+    /// This function requires that the lv has already been added to the causal graph.
+    pub(crate) fn push_register_op(&mut self, crdt: LVKey, v: LV, value: Primitive) {
+        let info = self.registers.get_mut(&crdt).expect("Missing register");
+        let Err(new_idx) = info.ops.binary_search_by_key(&v, |(v, _)| *v) else { return; };
+
+        info.ops.insert(new_idx, (v, CreateValue::Primitive(value)));
+        for s in info.supremum.iter_mut() {
+            if *s >= new_idx { *s += 1; }
+        }
+
+        // Operations can arrive out of order, so the new value isn't necessarily the latest.
+        let mut dominated = false;
+        info.supremum.retain(|s| {
+            match self.cg.graph.version_cmp(info.ops[*s].0, v) {
+                Some(Ordering::Less) => false,
+                Some(_) => { dominated = true; true }
+                None => true,
+            }
+        });
+        if !dominated {
+            let idx = info.supremum.partition_point(|s| *s < new_idx);
+            info.supremum.insert(idx, new_idx);
+        }
+
+        self.register_index.insert(v, crdt);
+    }
+
+    fn register_values(&self, info: &RegisterInfo) -> Vec<Primitive> {
+        if info.supremum.is_empty() { return vec![Primitive::Nil]; }
+
+        let (active_idx, other_idxes) = self.tie_break_mv(info);
+        let mut result = vec![expect_primitive((&info.ops[active_idx]).into())];
+        if let Some(iter) = other_idxes {
+            result.extend(iter.map(|idx| expect_primitive((&info.ops[idx]).into())));
+        }
+        result
+    }
+
+    /// Get the current value of a register. If there are conflicting concurrent values, the same
+    /// winner is picked on every peer. Registers which have never been set contain
+    /// [`Primitive::Nil`].
+    ///
+    /// Returns None if the register doesn't exist.
+    pub fn checkout_register(&self, crdt: LVKey) -> Option<Primitive> {
+        let info = self.registers.get(&crdt)?;
+        Some(self.register_values(info).swap_remove(0))
+    }
+
+    /// Get all of the register's concurrent values. The first value is the winner returned by
+    /// [`checkout_register`](OpLog::checkout_register).
+    pub fn checkout_register_conflicts(&self, crdt: LVKey) -> Option<Vec<Primitive>> {
+        let info = self.registers.get(&crdt)?;
+        Some(self.register_values(info))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CRDTKind, CreateValue, OpLog, Primitive, ROOT_CRDT_ID};
+
+    #[test]
+    fn concurrent_sets() {
+        let mut a = OpLog::new();
+        let seph = a.cg.get_or_create_agent_id("seph");
+        let title = a.local_map_set(seph, ROOT_CRDT_ID, "title", CreateValue::NewCRDT(CRDTKind::Register));
+        assert_eq!(a.checkout_register(title), Some(Primitive::Nil));
+        a.local_register_set(seph, title, Primitive::Str("Untitled".into()));
+
+        let mut b = OpLog::new();
+        b.merge_ops(a.ops_since(&[])).unwrap();
+        let mike = b.cg.get_or_create_agent_id("mike");
+        let title_b = b.crdt_at_path(&["title"]).1;
+        assert_eq!(b.checkout_register(title_b), Some(Primitive::Str("Untitled".into())));
+
+        a.local_register_set(seph, title, Primitive::Str("Seph's doc".into()));
+        b.local_register_set(mike, title_b, Primitive::Str("Mike's doc".into()));
+
+        b.merge_ops(a.ops_since(&[])).unwrap();
+        a.merge_ops(b.ops_since(&[])).unwrap();
+        a.dbg_check(true);
+        b.dbg_check(true);
+
+        let values = a.checkout_register_conflicts(title).unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(b.checkout_register_conflicts(title_b).unwrap(), values);
+        assert_eq!(a.checkout_register(title), Some(values[0].clone()));
+
+        // Setting the value again resolves the conflict.
+        a.local_register_set(seph, title, Primitive::I64(123));
+        b.merge_ops(a.ops_since(&[])).unwrap();
+        assert_eq!(b.checkout_register_conflicts(title_b), Some(vec![Primitive::I64(123)]));
+        b.dbg_check(true);
+    }
+}
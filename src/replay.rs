@@ -0,0 +1,161 @@
+//! Loading and replaying editing traces.
+//!
+//! Editing traces record every keystroke made while writing a real document. Diamond types is
+//! benchmarked using the traces from [automerge-perf](https://github.com/automerge/automerge-perf/)
+//! and [editing-traces](https://github.com/josephg/editing-traces) (see `benchmark_data/`). This
+//! module loads traces in that JSON format (optionally gzipped), and replays them through the
+//! public API - so you can benchmark your own storage or network code with realistic data.
+//!
+//! Only sequential (single user) traces are supported.
+//!
+//! Enable the `replay` feature to use this module.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use crate::{AgentId, DTRange};
+use crate::encoding::parseerror::ParseError;
+use crate::list::ListCRDT;
+
+/// A single edit: (position, number of deleted characters, inserted content). Positions are in
+/// unicode characters.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct TracePatch(pub usize, pub usize, pub String);
+
+/// A set of edits which were made together.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct TraceTxn {
+    pub patches: Vec<TracePatch>,
+}
+
+/// A sequential editing trace.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct EditTrace {
+    #[serde(rename = "startContent")]
+    pub start_content: String,
+    #[serde(rename = "endContent")]
+    pub end_content: String,
+    pub txns: Vec<TraceTxn>,
+}
+
+impl EditTrace {
+    /// Parse a trace from JSON. The data may be gzipped.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            let mut json = Vec::new();
+            GzDecoder::new(data).read_to_end(&mut json)?;
+            Ok(serde_json::from_slice(&json)?)
+        } else {
+            Ok(serde_json::from_slice(data)?)
+        }
+    }
+
+    /// Load a trace from a `.json` or `.json.gz` file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Self::from_bytes(&data)
+    }
+
+    /// The number of patches in the trace.
+    pub fn len(&self) -> usize {
+        self.txns.iter().map(|txn| txn.patches.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txns.iter().all(|txn| txn.patches.is_empty())
+    }
+
+    /// The number of inserted and deleted characters.
+    pub fn len_keystrokes(&self) -> usize {
+        self.txns.iter()
+            .flat_map(|txn| txn.patches.iter())
+            .map(|TracePatch(_, del, ins)| *del + ins.chars().count())
+            .sum()
+    }
+
+    /// Replay the trace into a document, as local edits from `agent`.
+    ///
+    /// After each transaction, `f` is called with the document and the range of versions the
+    /// transaction added. This can be used to save or send each change as its made.
+    ///
+    /// Returns [`ParseError::InvalidLength`] if a patch edits past the end of the document. Any
+    /// transactions before the bad patch will have been replayed.
+    pub fn replay_with<F: FnMut(&mut ListCRDT, DTRange)>(&self, doc: &mut ListCRDT, agent: AgentId, mut f: F) -> Result<(), ParseError> {
+        if !self.start_content.is_empty() {
+            let start = doc.oplog.len();
+            doc.insert(agent, 0, &self.start_content);
+            f(doc, (start..doc.oplog.len()).into());
+        }
+
+        for txn in &self.txns {
+            let start = doc.oplog.len();
+            for TracePatch(pos, del, ins) in &txn.patches {
+                if *pos > doc.len() || *del > doc.len() - *pos {
+                    return Err(ParseError::InvalidLength);
+                }
+                if *del > 0 {
+                    doc.delete_without_content(agent, *pos..*pos + *del);
+                }
+                if !ins.is_empty() {
+                    doc.insert(agent, *pos, ins);
+                }
+            }
+            if doc.oplog.len() > start {
+                f(doc, (start..doc.oplog.len()).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Replay the trace into a new document.
+    pub fn replay(&self) -> Result<ListCRDT, ParseError> {
+        let mut doc = ListCRDT::new();
+        let agent = doc.get_or_create_agent_id("trace");
+        self.replay_with(&mut doc, agent, |_, _| {})?;
+        Ok(doc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::ListCRDT;
+    use super::EditTrace;
+
+    #[test]
+    fn replay_dataset() {
+        let trace = EditTrace::load("benchmark_data/sveltecomponent.json.gz").unwrap();
+        assert!(!trace.is_empty());
+        let doc = trace.replay().unwrap();
+        assert_eq!(doc.branch.content(), trace.end_content.as_str());
+
+        // Changes can be sent to a remote peer as they're made.
+        let mut local = ListCRDT::new();
+        let mut remote = ListCRDT::new();
+        let agent = local.get_or_create_agent_id("seph");
+        let short = EditTrace::from_bytes(br#"{"startContent": "", "endContent": "hi!",
+            "txns": [{"patches": [[0, 0, "hey"]]}, {"patches": [[1, 2, "i!"]]}]}"#).unwrap();
+        short.replay_with(&mut local, agent, |doc, range| {
+            let patch = doc.oplog.encode_from(&EncodeOptions::patch(), doc.oplog.parents_at_version(range.start).as_ref());
+            remote.merge_data_and_ff(&patch).unwrap();
+        }).unwrap();
+        assert_eq!(remote.branch.content(), "hi!");
+        assert_eq!(short.len_keystrokes(), 7);
+    }
+
+    #[test]
+    fn malformed_trace() {
+        for json in [
+            br#"{"startContent": "", "endContent": "", "txns": [{"patches": [[1, 0, "a"]]}]}"#.as_slice(),
+            br#"{"startContent": "ab", "endContent": "", "txns": [{"patches": [[1, 2, ""]]}]}"#,
+            br#"{"startContent": "ab", "endContent": "", "txns": [{"patches": [[1, 18446744073709551615, ""]]}]}"#,
+        ] {
+            let trace = EditTrace::from_bytes(json).unwrap();
+            assert_eq!(trace.replay().unwrap_err(), ParseError::InvalidLength);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! A repository holding many named text documents.
+//!
+//! Every document in the repo shares the same causal graph and agent table, so an agent which
+//! edits a hundred documents is only stored once, and the whole repository can be synced or saved
+//! with a single set of changes. Internally each document is a text CRDT stored in the root map of
+//! an [`OpLog`], keyed by the document's name.
+
+use jumprope::JumpRopeBuf;
+use smallvec::SmallVec;
+use rle::HasLength;
+use crate::{AgentId, CRDTKind, CreateValue, DTRange, Frontier, LV, LVKey, OpLog, Primitive, RegisterValue, ROOT_CRDT_ID, SerializedOps, SerializedOpsOwned};
+use crate::causalgraph::agent_span::AgentSpan;
+use crate::encoding::bufparser::BufParser;
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::varint::push_usize;
+use crate::list::{ListOpLog, PLACEHOLDER_CHAR};
+use crate::list::operation::{ListOpKind, TextOperation};
+
+/// The name given to documents loaded by [`Repo::load_auto`] which don't have a document ID.
+pub const IMPORTED_DOC_NAME: &str = "main";
+
+/// The agent which creates documents loaded by [`Repo::load_auto`].
+pub const IMPORT_AGENT_NAME: &str = "import";
+
+/// Repositories saved with [`Repo::encode`] start with these bytes.
+const REPO_MAGIC_BYTES: [u8; 8] = *b"DMNDTREP";
+const REPO_VERSION: usize = 0;
+
+#[derive(Debug, Clone, Default)]
+pub struct Repo {
+    oplog: OpLog,
+
+    /// The version of the repository which has already been saved. Everything after this will be
+    /// returned by the next call to [`save_missing`](Repo::save_missing).
+    saved_version: Frontier,
+}
+
+impl Repo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a repository from data previously returned by [`save_missing`](Repo::save_missing).
+    /// Saved changes should be merged in the order they were saved.
+    pub fn load<'a>(saved: impl IntoIterator<Item = SerializedOps<'a>>) -> Result<Self, ParseError> {
+        let mut repo = Self::new();
+        for ops in saved {
+            repo.oplog.merge_ops(ops)?;
+        }
+        repo.saved_version = repo.oplog.cg.version.clone();
+        Ok(repo)
+    }
+
+    /// Encode the whole repository. The result can be loaded with [`load_auto`](Repo::load_auto).
+    /// See [`SerializedOps::encode`] for the errors this can return.
+    pub fn encode(&self) -> Result<Vec<u8>, ParseError> {
+        let mut result = REPO_MAGIC_BYTES.to_vec();
+        push_usize(&mut result, REPO_VERSION);
+        result.extend_from_slice(&self.oplog.ops_since(&[]).encode()?);
+        Ok(result)
+    }
+
+    /// Load a repository from bytes in any supported format. The format is detected from the
+    /// start of the data:
+    ///
+    /// - Repositories saved with [`encode`](Repo::encode) are loaded directly.
+    /// - Documents saved in the original list format (see [`list::encoding`](crate::list::encoding))
+    ///   are upgraded in memory using [`import_list`](Repo::import_list). The document is named by
+    ///   its document ID, or [`IMPORTED_DOC_NAME`] if it doesn't have one.
+    pub fn load_auto(data: &[u8]) -> Result<Self, ParseError> {
+        let mut repo = Self::new();
+        if let Some(rest) = data.strip_prefix(&REPO_MAGIC_BYTES) {
+            let mut buf = BufParser(rest);
+            if buf.next_usize()? != REPO_VERSION {
+                return Err(ParseError::UnsupportedProtocolVersion);
+            }
+            repo.oplog.merge_ops(SerializedOps::decode(buf.0)?)?;
+        } else {
+            let list = ListOpLog::load_from(data)?;
+            let agent = repo.get_or_create_agent_id(IMPORT_AGENT_NAME);
+            repo.import_list(agent, list.doc_id().unwrap_or(IMPORTED_DOC_NAME), &list)?;
+        }
+        repo.saved_version = repo.oplog.cg.version.clone();
+        Ok(repo)
+    }
+
+    /// Add a document from a list oplog to the repository, with the given name. The document is
+    /// created by `agent`, then every operation in the list oplog is added with its original ID
+    /// and parents. Operations at the root of the list's history are made children of the
+    /// document's creation.
+    ///
+    /// Every document in a repository shares the same agents, so the list's operations can't
+    /// reuse the ID (agent name and sequence number) of an operation already in the repository.
+    /// If they do, [`ParseError::DuplicateId`] is returned and the repository is left unchanged.
+    ///
+    /// Inserts with unknown content are filled in with [`PLACEHOLDER_CHAR`]s.
+    pub fn import_list(&mut self, agent: AgentId, name: &str, list: &ListOpLog) -> Result<LVKey, ParseError> {
+        for span in list.iter_agent_mappings() {
+            let name = list.get_agent_name(span.agent);
+            if let Some(seq) = self.first_known_seq(name, span.seq_range) {
+                let agent = self.oplog.cg.agent_assignment.get_agent_id(name).unwrap();
+                return Err(ParseError::DuplicateId { agent, seq });
+            }
+        }
+
+        let doc = self.create_doc(agent, name);
+        let base = self.oplog.cg.len();
+        let map_lv = |v: LV| v + base;
+
+        for entry in list.cg.graph.iter() {
+            let mut parents: SmallVec<LV, 2> = entry.parents.iter().copied().map(map_lv).collect();
+            if parents.is_empty() { parents.push(doc); }
+
+            for span in list.iter_agent_mappings_range(entry.span) {
+                let agent = self.oplog.cg.get_or_create_agent_id(list.get_agent_name(span.agent));
+                let range = self.oplog.cg.merge_and_assign(&parents, AgentSpan { agent, seq_range: span.seq_range });
+                debug_assert_eq!(range.len(), span.len());
+                parents = smallvec::smallvec![range.last()];
+            }
+        }
+
+        let mut v = 0;
+        for mut op in list.iter_ops_range((0..list.len()).into()) {
+            let len = op.len();
+            if op.kind == ListOpKind::Ins && op.content.is_none() {
+                op.content = Some(std::iter::repeat(PLACEHOLDER_CHAR).take(len).collect());
+            }
+            self.oplog.remote_text_op(doc, (map_lv(v)..map_lv(v + len as LV)).into(), op);
+            v += len as LV;
+        }
+        Ok(doc)
+    }
+
+    /// The first sequence number in `seqs` which the named agent has already used, if any.
+    fn first_known_seq(&self, name: &str, seqs: DTRange) -> Option<LV> {
+        let agent = self.oplog.cg.agent_assignment.get_agent_id(name)?;
+        let client_data = &self.oplog.cg.agent_assignment.client_data[agent as usize];
+        match client_data.lv_for_seq.find_sparse(seqs.start).0 {
+            Ok(_) => Some(seqs.start),
+            Err(gap) => (gap.end < seqs.end).then_some(gap.end),
+        }
+    }
+
+    pub fn oplog(&self) -> &OpLog {
+        &self.oplog
+    }
+
+    pub fn get_or_create_agent_id(&mut self, name: &str) -> AgentId {
+        self.oplog.cg.get_or_create_agent_id(name)
+    }
+
+    /// Create a new, empty document with the given name. If a document already exists with this
+    /// name, it is replaced.
+    pub fn create_doc(&mut self, agent: AgentId, name: &str) -> LVKey {
+        self.oplog.local_map_set(agent, ROOT_CRDT_ID, name, CreateValue::NewCRDT(CRDTKind::Text))
+    }
+
+    /// Look up the document with the given name.
+    pub fn open_doc(&self, name: &str) -> Option<LVKey> {
+        let reg = self.oplog.map_keys.get(&(ROOT_CRDT_ID, name.into()))?;
+        match self.oplog.resolve_mv(reg) {
+            RegisterValue::OwnedCRDT(CRDTKind::Text, doc) => Some(doc),
+            _ => None,
+        }
+    }
+
+    /// Delete the named document. Returns false if there was no document with that name.
+    pub fn delete_doc(&mut self, agent: AgentId, name: &str) -> bool {
+        if self.open_doc(name).is_none() { return false; }
+        self.oplog.local_map_set(agent, ROOT_CRDT_ID, name, CreateValue::Primitive(Primitive::Nil));
+        true
+    }
+
+    /// Iterate through the names of all the documents in the repository, in sorted order.
+    pub fn doc_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.oplog.map_keys.range((ROOT_CRDT_ID, "".into())..)
+            .filter(|(_, reg)| matches!(
+                self.oplog.resolve_mv(reg),
+                RegisterValue::OwnedCRDT(CRDTKind::Text, _)
+            ))
+            .map(|((_, name), _)| name.as_str())
+    }
+
+    /// Apply a local edit to a document.
+    pub fn edit(&mut self, agent: AgentId, doc: LVKey, op: TextOperation) -> DTRange {
+        self.oplog.local_text_op(agent, doc, op)
+    }
+
+    pub fn checkout_doc(&self, doc: LVKey) -> JumpRopeBuf {
+        self.oplog.checkout_text(doc)
+    }
+
+    /// Merge changes from another replica of this repository.
+    pub fn merge_ops(&mut self, ops: SerializedOps) -> Result<DTRange, ParseError> {
+        self.oplog.merge_ops(ops)
+    }
+
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.saved_version != self.oplog.cg.version
+    }
+
+    /// Get all the changes to every document in the repository which haven't been saved yet, and
+    /// mark them as saved. The returned changes should be appended to the repository's storage.
+    pub fn save_missing(&mut self) -> SerializedOpsOwned {
+        let since = std::mem::replace(&mut self.saved_version, self.oplog.cg.version.clone());
+        self.oplog.ops_since(since.as_ref()).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::gen_random::gen_oplog;
+    use crate::list::ListOpLog;
+    use crate::list::operation::TextOperation;
+    use super::*;
+
+    #[test]
+    fn save_and_load_docs() {
+        let mut repo = Repo::new();
+        let seph = repo.get_or_create_agent_id("seph");
+        let readme = repo.create_doc(seph, "README");
+        repo.edit(seph, readme, TextOperation::new_insert(0, "hi there"));
+        let notes = repo.create_doc(seph, "notes");
+        repo.edit(seph, notes, TextOperation::new_insert(0, "todo"));
+
+        assert!(repo.has_unsaved_changes());
+        let first = repo.save_missing();
+        assert!(!repo.has_unsaved_changes());
+
+        let mut loaded = Repo::load([first.as_ops()]).unwrap();
+        assert_eq!(loaded.doc_names().collect::<Vec<_>>(), vec!["README", "notes"]);
+        let readme_2 = loaded.open_doc("README").unwrap();
+        assert_eq!(loaded.checkout_doc(readme_2).to_string(), "hi there");
+        assert_eq!(loaded.open_doc("nope"), None);
+        assert!(!loaded.has_unsaved_changes());
+
+        // Further changes are saved incrementally.
+        assert!(repo.delete_doc(seph, "notes"));
+        assert!(!repo.delete_doc(seph, "notes"));
+        repo.edit(seph, readme, TextOperation::new_delete(0..3));
+        loaded.merge_ops(repo.save_missing().as_ops()).unwrap();
+
+        assert_eq!(loaded.doc_names().collect::<Vec<_>>(), vec!["README"]);
+        assert_eq!(loaded.checkout_doc(readme_2).to_string(), "there");
+        assert_eq!(loaded.oplog().cg, repo.oplog().cg);
+    }
+
+    #[test]
+    fn upgrade_list_documents() {
+        for seed in 0..10 {
+            let list = gen_oplog(seed, 30, true, true);
+            let data = list.encode(&EncodeOptions::default().store_deleted_content(true));
+            let repo = Repo::load_auto(&data).unwrap();
+            assert!(!repo.has_unsaved_changes());
+            let doc = repo.open_doc(IMPORTED_DOC_NAME).unwrap();
+            assert_eq!(repo.checkout_doc(doc).to_string(), list.checkout_tip().content().to_string());
+            assert_eq!(repo.oplog().cg.len(), list.len() + 1);
+        }
+
+        let mut list = ListOpLog::new();
+        list.set_doc_id(Some("imported"));
+        let seph = list.get_or_create_agent_id("seph");
+        list.add_insert(seph, 0, "hi");
+        let data = list.encode(&EncodeOptions::default().omit_content_for(&[(1..2).into()]));
+        let mut repo = Repo::load_auto(&data).unwrap();
+        let doc = repo.open_doc(list.doc_id().unwrap()).unwrap();
+        assert_eq!(repo.checkout_doc(doc).to_string(), format!("h{PLACEHOLDER_CHAR}"));
+
+        // The imported document can be edited as normal.
+        let seph = repo.get_or_create_agent_id("seph");
+        repo.edit(seph, doc, TextOperation::new_insert(0, "!"));
+        assert_eq!(repo.checkout_doc(doc).to_string(), format!("!h{PLACEHOLDER_CHAR}"));
+
+        assert_eq!(Repo::load_auto(b"not a document").unwrap_err(), ParseError::InvalidMagic);
+
+        // Repositories saved in the new format are detected too.
+        let loaded = Repo::load_auto(&repo.encode().unwrap()).unwrap();
+        assert_eq!(loaded.oplog().cg, repo.oplog().cg);
+        assert!(!loaded.has_unsaved_changes());
+        let doc = loaded.open_doc(list.doc_id().unwrap()).unwrap();
+        assert_eq!(loaded.checkout_doc(doc).to_string(), format!("!h{PLACEHOLDER_CHAR}"));
+    }
+
+    #[test]
+    fn import_reused_ids() {
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.add_insert(seph, 0, "hi");
+        let mut b = ListOpLog::new();
+        let seph = b.get_or_create_agent_id("seph");
+        b.add_insert(seph, 0, "yo there");
+
+        let mut repo = Repo::new();
+        let import = repo.get_or_create_agent_id(IMPORT_AGENT_NAME);
+        repo.import_list(import, "a", &a).unwrap();
+        let cg = repo.oplog().cg.clone();
+        let seph = repo.oplog().cg.agent_assignment.get_agent_id("seph").unwrap();
+        assert_eq!(repo.import_list(import, "b", &b), Err(ParseError::DuplicateId { agent: seph, seq: 0 }));
+        assert_eq!(repo.oplog().cg, cg);
+        assert_eq!(repo.doc_names().collect::<Vec<_>>(), vec!["a"]);
+    }
+}
@@ -1,6 +1,7 @@
 use std::ops::Range;
 use rle::{HasLength, MergableSpan, SplitableSpan, SplitableSpanHelpers};
 use crate::dtrange::DTRange;
+use crate::LV;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize};
@@ -39,11 +40,11 @@ impl RangeRev {
     // }
 
     #[allow(unused)]
-    pub fn lv_at_offset(&self, offset: usize) -> usize {
+    pub fn lv_at_offset(&self, offset: usize) -> LV {
         if self.fwd {
-            self.span.start + offset
+            self.span.start + offset as LV
         } else {
-            self.span.end - offset - 1
+            self.span.end - offset as LV - 1
         }
     }
 
@@ -56,8 +57,8 @@ impl RangeRev {
     /// This is useful because reversed ranges are weird.
     pub fn range(&self, offset_start: usize, offset_end: usize) -> DTRange {
         debug_assert!(offset_start <= offset_end);
-        debug_assert!(self.span.start + offset_start <= self.span.end);
-        debug_assert!(self.span.start + offset_end <= self.span.end);
+        debug_assert!(offset_end <= self.span.len());
+        let (offset_start, offset_end) = (offset_start as LV, offset_end as LV);
 
         if self.fwd {
             // Simple case.
@@ -82,15 +83,15 @@ impl From<DTRange> for RangeRev {
         }
     }
 }
-impl From<Range<usize>> for RangeRev {
-    fn from(range: Range<usize>) -> Self {
+impl From<Range<LV>> for RangeRev {
+    fn from(range: Range<LV>) -> Self {
         RangeRev {
             span: range.into(),
             fwd: true,
         }
     }
 }
-impl From<RangeRev> for Range<usize> {
+impl From<RangeRev> for Range<LV> {
     fn from(range: RangeRev) -> Self {
         range.span.into()
     }
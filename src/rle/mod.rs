@@ -2,24 +2,26 @@ use std::fmt::{Debug, Formatter};
 
 use rle::{HasRleKey, HasLength, MergableSpan, Searchable, SplitableSpan, SplitableSpanCtx};
 pub use rle_vec::RleVec;
+pub(crate) use rle_vec::RleVecStats;
 use crate::dtrange::{debug_lv_raw, DTRange};
+use crate::{lv_to_usize, LV};
 
 pub mod rle_vec;
 // pub mod rle_packed_vec;
 // pub mod rle_packed_vec2;
 
 pub trait RleSpanHelpers: HasRleKey + HasLength {
-    fn end(&self) -> usize {
-        self.rle_key() + self.len()
+    fn end(&self) -> LV {
+        self.rle_key() + self.len() as LV
     }
 
-    fn last(&self) -> usize {
+    fn last(&self) -> LV {
         self.end() - 1
     }
 
     fn span(&self) -> DTRange {
         let start = self.rle_key();
-        DTRange { start, end: start + self.len() }
+        DTRange { start, end: start + self.len() as LV }
     }
 }
 
@@ -27,30 +29,30 @@ impl<V: HasRleKey + HasLength> RleSpanHelpers for V {}
 
 pub trait RleKeyedAndSplitable: HasRleKey + SplitableSpanCtx {
     #[inline(always)]
-    fn truncate_from_ctx(&mut self, at: usize, ctx: &Self::Ctx) -> Self {
-        self.truncate_ctx(at - self.rle_key(), ctx)
+    fn truncate_from_ctx(&mut self, at: LV, ctx: &Self::Ctx) -> Self {
+        self.truncate_ctx(lv_to_usize(at - self.rle_key()), ctx)
     }
 
     #[inline(always)]
-    fn truncate_keeping_right_from_ctx(&mut self, at: usize, ctx: &Self::Ctx) -> Self {
-        self.truncate_keeping_right_ctx(at - self.rle_key(), ctx)
+    fn truncate_keeping_right_from_ctx(&mut self, at: LV, ctx: &Self::Ctx) -> Self {
+        self.truncate_keeping_right_ctx(lv_to_usize(at - self.rle_key()), ctx)
     }
 
     #[inline(always)]
-    fn truncate_from(&mut self, at: usize) -> Self where Self: SplitableSpan {
-        self.truncate(at - self.rle_key())
+    fn truncate_from(&mut self, at: LV) -> Self where Self: SplitableSpan {
+        self.truncate(lv_to_usize(at - self.rle_key()))
     }
 
     #[inline(always)]
-    fn truncate_keeping_right_from(&mut self, at: usize) -> Self where Self: SplitableSpan {
-        self.truncate_keeping_right(at - self.rle_key())
+    fn truncate_keeping_right_from(&mut self, at: LV) -> Self where Self: SplitableSpan {
+        self.truncate_keeping_right(lv_to_usize(at - self.rle_key()))
     }
 }
 
 impl<V: HasRleKey + SplitableSpanCtx> RleKeyedAndSplitable for V {}
 
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub struct KVPair<V>(pub usize, pub V);
+pub struct KVPair<V>(pub LV, pub V);
 
 impl<V: Debug> Debug for KVPair<V> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -63,7 +65,7 @@ impl<V: Debug> Debug for KVPair<V> {
 
 
 impl<V> HasRleKey for KVPair<V> {
-    fn rle_key(&self) -> usize {
+    fn rle_key(&self) -> LV {
         self.0
     }
 }
@@ -74,7 +76,7 @@ impl<V: HasLength> HasLength for KVPair<V> {
 
 impl<V: HasLength> KVPair<V> {
     pub fn range(&self) -> DTRange {
-        DTRange::new(self.0, self.0 + self.len())
+        DTRange::new(self.0, self.0 + self.len() as LV)
     }
 }
 
@@ -87,14 +89,14 @@ impl<V: SplitableSpanCtx> SplitableSpanCtx for KVPair<V> {
         // debug_assert!(at <= self.len());
 
         let remainder = self.1.truncate_ctx(at, ctx);
-        KVPair(self.0 + at, remainder)
+        KVPair(self.0 + at as LV, remainder)
     }
 
     #[inline]
     fn truncate_keeping_right_ctx(&mut self, at: usize, ctx: &Self::Ctx) -> Self {
         // debug_assert!(at <= self.len());
         let old_key = self.0;
-        self.0 += at;
+        self.0 += at as LV;
         let trimmed = self.1.truncate_keeping_right_ctx(at, ctx);
         KVPair(old_key, trimmed)
     }
@@ -135,12 +137,12 @@ pub fn try_trim<V>(mut x: V, target_span: DTRange) -> Option<V>
     let x_span = x.span();
     if x_span.start < target_span.start {
         if x_span.end <= target_span.start { return None; }
-        x.truncate_keeping_right(target_span.start - x_span.start);
+        x.truncate_keeping_right(lv_to_usize(target_span.start - x_span.start));
     }
 
     if x_span.end > target_span.end {
         if x_span.start >= target_span.end { return None; }
-        x.truncate(target_span.end - x_span.start);
+        x.truncate(lv_to_usize(target_span.end - x_span.start));
     }
 
     Some(x)
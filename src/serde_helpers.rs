@@ -8,6 +8,7 @@ use serde::ser::SerializeStruct;
 use crate::rev_range::RangeRev;
 use smartstring::alias::String as SmartString;
 use crate::dtrange::DTRange;
+use crate::LV;
 
 pub(crate) trait FlattenSerializable {
     fn struct_name() -> &'static str;
@@ -119,7 +120,7 @@ impl FlattenSerializable for RangeRev {
 /// This is used to flatten `[agent, seq]` into a tuple for serde serialization.
 #[derive(Clone, Debug, Eq, PartialEq)]
 #[derive(Serialize, Deserialize)]
-pub(crate) struct DTRangeTuple(usize, usize); // from, to.
+pub(crate) struct DTRangeTuple(LV, LV); // from, to.
 
 impl From<DTRangeTuple> for DTRange {
     fn from(f: DTRangeTuple) -> Self {
@@ -38,6 +38,10 @@ impl Branch {
             CRDTKind::Text => {
                 SimpleVal::Text(self.texts.get(&key).unwrap().to_string())
             }
+            CRDTKind::Custom | CRDTKind::Counter => {
+                // Branches don't store custom CRDTs or counters.
+                SimpleVal::Primitive(Primitive::Nil)
+            }
         }
     }
 
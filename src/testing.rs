@@ -0,0 +1,75 @@
+//! Deterministic random document generators, for property testing code built on diamond types.
+//!
+//! These are the same generators diamond types uses to fuzz itself. Everything here is seeded, so a
+//! failing seed can be replayed exactly. Enable the `testing` feature to use this module.
+//!
+//! ```
+//! use diamond_types::list::ListCRDT;
+//! use diamond_types::testing::{make_random_change, seeded_rng};
+//!
+//! let mut doc = ListCRDT::new();
+//! let agent = doc.get_or_create_agent_id("seph");
+//! let mut rng = seeded_rng(123);
+//! for _ in 0..100 {
+//!     make_random_change(&mut doc, agent, &mut rng, true);
+//! }
+//! assert_eq!(doc.oplog.checkout_tip().content(), doc.branch.content());
+//! ```
+
+use rand::SeedableRng;
+use crate::{AgentId, LV};
+use crate::list::{ListBranch, ListCRDT, ListOpLog};
+use crate::list::old_fuzzer_tools::{old_make_random_change, old_make_random_change_raw};
+
+pub use rand::rngs::SmallRng;
+pub use crate::list_fuzzer_tools::{choose_2, fuzz_multithreaded, random_str};
+pub use crate::causalgraph::graph::random_graphs::with_random_cgs;
+pub use crate::list::gen_random::gen_oplog;
+
+/// Make a random number generator from a seed. The same seed always generates the same documents.
+pub fn seeded_rng(seed: u64) -> SmallRng {
+    SmallRng::seed_from_u64(seed)
+}
+
+/// Make a random local edit (an insert or delete) to the document.
+pub fn make_random_change(doc: &mut ListCRDT, agent: AgentId, rng: &mut SmallRng, use_unicode: bool) {
+    old_make_random_change(doc, None, agent, rng, use_unicode);
+}
+
+/// Add a random edit to the oplog, made at the branch's version. The branch isn't modified.
+/// Returns the version of the new edit.
+///
+/// This is useful for simulating a set of peers which each have their own branch.
+pub fn make_random_change_at(oplog: &mut ListOpLog, branch: &ListBranch, agent: AgentId, rng: &mut SmallRng, use_unicode: bool) -> LV {
+    old_make_random_change_raw(oplog, branch, None, agent, rng, use_unicode)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListCRDT;
+    use super::*;
+
+    #[test]
+    fn generators_are_deterministic() {
+        let gen = |seed| {
+            let mut doc = ListCRDT::new();
+            let agent = doc.get_or_create_agent_id("seph");
+            let mut rng = seeded_rng(seed);
+            for _ in 0..50 {
+                make_random_change(&mut doc, agent, &mut rng, true);
+            }
+            doc.branch.content().to_string()
+        };
+        assert_eq!(gen(10), gen(10));
+        assert_ne!(gen(10), gen(11));
+
+        assert_eq!(gen_oplog(123, 10, true, true), gen_oplog(123, 10, true, true));
+
+        let mut count = 0;
+        with_random_cgs(1, (1, 10), |_, cg, _| {
+            cg.dbg_check(true);
+            count += 1;
+        });
+        assert_eq!(count, 10);
+    }
+}
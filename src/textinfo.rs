@@ -24,7 +24,7 @@ impl TextInfo {
         OpMetricsIter::new(&self.ops, &self.ctx, range)
     }
     pub fn iter_metrics(&self) -> OpMetricsIter {
-        OpMetricsIter::new(&self.ops, &self.ctx, (0..self.ops.end()).into())
+        OpMetricsIter::new(&self.ops, &self.ctx, (0..self.ops.end() as LV).into())
     }
 
     pub fn iter_fast(&self) -> OpMetricsWithContent {
@@ -78,11 +78,11 @@ impl TextInfo {
                 let content = self.ctx.get_str(ListOpKind::Ins, op.content_pos.unwrap());
                 // assert!(pos <= self.content.len_chars());
                 if op.loc.fwd {
-                    dest.insert(op.loc.span.start, content);
+                    dest.insert(op.start(), content);
                 } else {
                     // We need to insert the content in reverse order.
                     let c = reverse_str(content);
-                    dest.insert(op.loc.span.start, &c);
+                    dest.insert(op.start(), &c);
                 }
             }
             ListOpKind::Del => {
@@ -33,6 +33,99 @@ pub fn count_chars(s: &str) -> usize {
     str_indices::chars::count(s)
 }
 
+/// Finds the boundaries between grapheme clusters (user-perceived characters) in a stream of
+/// characters.
+///
+/// This is a simplified version of the rules in
+/// [UAX #29](https://unicode.org/reports/tr29/), without pulling in the unicode property tables.
+/// It handles CRLF, combining marks, variation selectors, emoji modifiers and tags, ZWJ emoji
+/// sequences and regional indicator (flag) pairs, which covers the clusters people actually type.
+/// Hangul syllables and spacing marks (eg in Devanagari) are split into separate clusters.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GraphemeSegmenter {
+    prev: Option<char>,
+    /// The number of consecutive regional indicators ending at prev.
+    ri_run: usize,
+}
+
+const ZWJ: char = '\u{200d}';
+
+fn is_extend(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036f // Combining diacritical marks
+        | 0x1ab0..=0x1aff | 0x1dc0..=0x1dff | 0x20d0..=0x20ff | 0xfe20..=0xfe2f
+        | 0x200c | 0x200d // ZWNJ and ZWJ
+        | 0xfe00..=0xfe0f | 0xe0100..=0xe01ef // Variation selectors
+        | 0x1f3fb..=0x1f3ff // Emoji skin tone modifiers
+        | 0xe0020..=0xe007f // Tags (used in subdivision flags)
+    )
+}
+
+fn is_pictographic(c: char) -> bool {
+    matches!(c as u32, 0x2300..=0x23ff | 0x2600..=0x27bf | 0x2b00..=0x2bff | 0x1f000..=0x1faff)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1f1e6..=0x1f1ff)
+}
+
+impl GraphemeSegmenter {
+    /// Add the next character. Returns true if there's a grapheme boundary before c.
+    pub(crate) fn push(&mut self, c: char) -> bool {
+        let boundary = match self.prev {
+            None => true,
+            Some('\r') if c == '\n' => false,
+            Some(prev) if prev.is_control() || c.is_control() => true,
+            _ if is_extend(c) => false,
+            Some(ZWJ) if is_pictographic(c) => false,
+            Some(prev) if is_regional_indicator(prev) && is_regional_indicator(c) => self.ri_run % 2 == 0,
+            _ => true,
+        };
+
+        self.ri_run = if is_regional_indicator(c) { self.ri_run + 1 } else { 0 };
+        self.prev = Some(c);
+        boundary
+    }
+}
+
+/// Convert a grapheme cluster offset into a char offset. Panics if the offset is past the end of
+/// the characters.
+pub(crate) fn graphemes_to_chars<I: Iterator<Item=char>>(chars: I, grapheme_pos: usize) -> usize {
+    let mut seg = GraphemeSegmenter::default();
+    let mut graphemes = 0;
+    let mut len = 0;
+    for c in chars {
+        if seg.push(c) {
+            if graphemes == grapheme_pos { return len; }
+            graphemes += 1;
+        }
+        len += 1;
+    }
+    assert_eq!(graphemes, grapheme_pos, "Position out of bounds");
+    len
+}
+
+/// Convert a char offset into a grapheme cluster offset. Positions inside a grapheme cluster are
+/// rounded down to the start of the cluster.
+pub(crate) fn chars_to_graphemes<I: Iterator<Item=char>>(chars: I, char_pos: usize) -> usize {
+    let mut seg = GraphemeSegmenter::default();
+    let mut graphemes = 0;
+    let mut len = 0;
+    for c in chars.take(char_pos + 1) {
+        if seg.push(c) && len > 0 { graphemes += 1; }
+        len += 1;
+    }
+    assert!(len >= char_pos, "Position out of bounds");
+    // The end of the string is also the end of the last cluster.
+    if len == char_pos && len > 0 { graphemes += 1; }
+    graphemes
+}
+
+pub(crate) fn count_graphemes<I: Iterator<Item=char>>(chars: I) -> usize {
+    let mut seg = GraphemeSegmenter::default();
+    chars.filter(|c| seg.push(*c)).count()
+}
+
 #[cfg(test)]
 mod test {
     use crate::unicount::*;
@@ -100,5 +193,26 @@ mod test {
         assert_eq!(split_at_char("日本語", 2), ("日本", "語"));
         assert_eq!(split_at_char("日本語", 3), ("日本語", ""));
     }
-}
 
+    #[test]
+    fn grapheme_positions() {
+        // e + combining acute, a flag (2 regional indicators), CRLF, and a ZWJ family emoji.
+        let s = "ae\u{301}\u{1f1f3}\u{1f1ff}\r\n\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}b";
+        assert_eq!(count_graphemes(s.chars()), 6);
+
+        let expected_chars = [0, 1, 3, 5, 7, 12, 13];
+        for (g, c) in expected_chars.iter().enumerate() {
+            assert_eq!(graphemes_to_chars(s.chars(), g), *c);
+            assert_eq!(chars_to_graphemes(s.chars(), *c), g);
+        }
+
+        // Positions inside a cluster are rounded down.
+        assert_eq!(chars_to_graphemes(s.chars(), 2), 1);
+        assert_eq!(chars_to_graphemes(s.chars(), 9), 4);
+
+        // 3 regional indicators are a flag followed by a lone indicator.
+        assert_eq!(count_graphemes("\u{1f1f3}\u{1f1ff}\u{1f1f3}".chars()), 2);
+        assert_eq!(count_graphemes("".chars()), 0);
+        assert_eq!(chars_to_graphemes("".chars(), 0), 0);
+    }
+}